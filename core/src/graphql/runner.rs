@@ -4,6 +4,7 @@ use std::str::FromStr;
 use std::time::{Duration, Instant};
 
 use graph::prelude::{GraphQlRunner as GraphQlRunnerTrait, *};
+use graph::util::tracing;
 use graph_graphql::prelude::*;
 
 use lazy_static::lazy_static;
@@ -12,6 +13,7 @@ use lazy_static::lazy_static;
 pub struct GraphQlRunner<S> {
     logger: Logger,
     store: Arc<S>,
+    query_stats: Arc<QueryStats>,
 }
 
 lazy_static! {
@@ -32,8 +34,16 @@ where
         GraphQlRunner {
             logger: logger.new(o!("component" => "GraphQlRunner")),
             store,
+            query_stats: Arc::new(QueryStats::default()),
         }
     }
+
+    /// Records the statistics of the queries this runner runs in
+    /// `query_stats` instead of in statistics of its own.
+    pub fn with_query_stats(mut self, query_stats: Arc<QueryStats>) -> Self {
+        self.query_stats = query_stats;
+        self
+    }
 }
 
 impl<S> GraphQlRunnerTrait for GraphQlRunner<S>
@@ -41,14 +51,27 @@ where
     S: Store,
 {
     fn run_query(&self, query: Query) -> QueryResultFuture {
-        let result = execute_query(
-            &query,
-            QueryExecutionOptions {
-                logger: self.logger.clone(),
-                resolver: StoreResolver::new(&self.logger, self.store.clone()),
-                deadline: GRAPHQL_QUERY_TIMEOUT.map(|t| Instant::now() + t),
-            },
-        );
+        let (result, slow_query) = self.query_stats.measure(&query, || {
+            tracing::traced("execute_query", |_| {
+                execute_query(
+                    &query,
+                    QueryExecutionOptions {
+                        logger: self.logger.clone(),
+                        resolver: StoreResolver::new(&self.logger, self.store.clone())
+                            .at_block(query.block),
+                        deadline: GRAPHQL_QUERY_TIMEOUT.map(|t| Instant::now() + t),
+                    },
+                )
+            })
+        });
+        if let Some(slow_query) = slow_query {
+            warn!(self.logger, "Slow query";
+                  "subgraph_id" => slow_query.subgraph_id.to_string(),
+                  "query" => &slow_query.query,
+                  "variables" => slow_query.variables.as_ref().map_or("{}", |v| v.as_str()),
+                  "time" => format!("{}ms", slow_query.duration.as_millis()),
+                  "store_time" => format!("{}ms", slow_query.store_time.as_millis()));
+        }
         Box::new(future::ok(result))
     }
 
@@ -5,6 +5,8 @@ extern crate graph_graphql;
 #[cfg(test)]
 extern crate graph_mock;
 extern crate graph_runtime_wasm;
+extern crate graphql_parser;
+extern crate inflector;
 extern crate itertools;
 extern crate reqwest;
 extern crate serde;
@@ -21,4 +23,8 @@ mod subgraph;
 pub use crate::graphql::GraphQlRunner;
 pub use crate::log::elastic::{elastic_logger, ElasticDrainConfig, ElasticLoggingConfig};
 pub use crate::log::split::split_logger;
-pub use crate::subgraph::{SubgraphAssignmentProvider, SubgraphInstanceManager, SubgraphRegistrar};
+pub use crate::subgraph::{
+    load_dynamic_data_sources, reassign_subgraph, remove_subgraph, replay_subgraph,
+    unassign_subgraph, ForkStore, ReplayStore, SubgraphAssignmentProvider, SubgraphInstanceManager,
+    SubgraphRegistrar,
+};
@@ -2,6 +2,7 @@ use chrono::prelude::{SecondsFormat, Utc};
 use itertools;
 use reqwest;
 use serde::ser::Serializer as SerdeSerializer;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fmt::Write;
 use std::sync::Mutex;
@@ -61,6 +62,9 @@ struct ElasticLog {
     #[serde(serialize_with = "serialize_log_level")]
     level: Level,
     meta: ElasticLogMeta,
+    /// The key/value arguments of the log message and the logger, so that
+    /// logs can be searched by them, e.g. by `handler` or `block_number`.
+    arguments: BTreeMap<String, String>,
 }
 
 /// A super-simple slog Serializer for concatenating key/value arguments.
@@ -125,6 +129,11 @@ pub struct ElasticDrainConfig {
 ///       "column": 9
 ///     },
 ///     "text": "Chain head pointer, number: 6661038, hash: 0xf089c457700a57798ced06bd3f18eef53bb8b46510bcefaf13615a8a26e4424a, component: BlockStream",
+///     "arguments": {
+///       "component": "BlockStream",
+///       "hash": "0xf089c457700a57798ced06bd3f18eef53bb8b46510bcefaf13615a8a26e4424a",
+///       "number": "6661038"
+///     },
 ///     "id": "Qmb31zcpzqga7ERaUTp83gVdYcuBasz4rXUHFufikFTJGU-2018-11-08T00:54:52.589258000Z"
 ///   }
 /// }
@@ -255,12 +264,15 @@ impl Drain for ElasticDrain {
         let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true);
         let id = format!("{}-{}", self.config.subgraph_id, timestamp);
 
+        let mut arguments = BTreeMap::new();
+
         // Serialize logger arguments
         let mut serializer = SimpleKVSerializer::new();
         record
             .kv()
             .serialize(record, &mut serializer)
             .expect("failed to serializer logger arguments");
+        arguments.extend(serializer.kvs.iter().cloned());
         let (n_logger_kvs, logger_kvs) = serializer.finish();
 
         // Serialize log message arguments
@@ -268,6 +280,11 @@ impl Drain for ElasticDrain {
         values
             .serialize(record, &mut serializer)
             .expect("failed to serialize log message arguments");
+        for (key, value) in serializer.kvs.iter() {
+            arguments
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
         let (n_value_kvs, value_kvs) = serializer.finish();
 
         let mut text = format!("{}", record.msg());
@@ -290,6 +307,7 @@ impl Drain for ElasticDrain {
                 line: record.line() as i64,
                 column: record.column() as i64,
             },
+            arguments,
         };
 
         // Push the log into the queue
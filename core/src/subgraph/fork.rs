@@ -0,0 +1,1027 @@
+use graph::data::store::scalar::Bytes;
+use graph::data::store::{BIG_DECIMAL_SCALAR, BIG_INT_SCALAR, BYTES_SCALAR};
+use graph::data::subgraph::schema::{SubgraphManifestEntity, TypedEntity, SUBGRAPHS_ID};
+use graph::prelude::*;
+use graph::web3::types::H256;
+use graph_graphql::schema::ast as sast;
+use graphql_parser::schema::{Field, Type};
+use inflector::Inflector;
+use reqwest;
+use std::cmp::{self, Ordering};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// A store that serves the entities that the subgraphs it is used for
+/// don't have yet from the deployments with the same IDs on another Graph
+/// Node, so that a mapping failure can be reproduced without indexing the
+/// subgraph from the start.
+///
+/// Entities are queried from the GraphQL API of the other node at
+/// `<fork base>/<deployment ID>`, as of the block the local deployment was
+/// at when it first read from the fork. They are kept in memory rather than
+/// written to the underlying store; once a deployment writes an entity, the
+/// underlying store has the final say about it, until the block of that
+/// first write is reverted. This requires that the writes and reverts of
+/// the deployments go through this store. Without a fork base, the store is
+/// a plain wrapper around the underlying store.
+pub struct ForkStore<S> {
+    logger: Logger,
+    store: Arc<S>,
+    fork_base: Option<reqwest::Url>,
+    client: reqwest::Client,
+    /// The block at which each deployment reads the fork.
+    fork_blocks: Mutex<HashMap<SubgraphDeploymentId, u64>>,
+    /// Entities read from the fork, `None` if the fork doesn't have them.
+    fetched: Mutex<BTreeMap<EntityKey, Option<Entity>>>,
+    /// Entities the deployments wrote, with the number of the block of the
+    /// first write; `0` for writes outside of blocks.
+    written: Mutex<BTreeMap<EntityKey, u64>>,
+}
+
+impl<S> ForkStore<S>
+where
+    S: Store + SubgraphDeploymentStore,
+{
+    pub fn new(logger: &Logger, store: Arc<S>, fork_base: Option<reqwest::Url>) -> Self {
+        ForkStore {
+            logger: logger.new(o!("component" => "ForkStore")),
+            store,
+            fork_base,
+            client: reqwest::Client::new(),
+            fork_blocks: Mutex::new(HashMap::new()),
+            fetched: Mutex::new(BTreeMap::new()),
+            written: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the fork base if the entities of `subgraph_id` are forked.
+    fn fork_base_for(&self, subgraph_id: &SubgraphDeploymentId) -> Option<&reqwest::Url> {
+        match &self.fork_base {
+            Some(fork_base) if *subgraph_id != *SUBGRAPHS_ID => Some(fork_base),
+            _ => None,
+        }
+    }
+
+    /// Runs `query` against the deployment `subgraph_id` on the fork base
+    /// and returns the `data` of the result.
+    fn query(
+        &self,
+        fork_base: &reqwest::Url,
+        subgraph_id: &SubgraphDeploymentId,
+        query: String,
+    ) -> Result<serde_json::Value, Error> {
+        let url = format!(
+            "{}/{}",
+            fork_base.as_str().trim_end_matches('/'),
+            subgraph_id
+        );
+        let mut result: serde_json::Value = self
+            .client
+            .post(&url)
+            .json(&json!({ "query": query }))
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|mut response| response.json())
+            .map_err(|e| format_err!("failed to query the fork at {}: {}", url, e))?;
+
+        match result.get("errors") {
+            Some(errors) if !errors.is_null() => {
+                bail!("the fork at {} returned errors: {}", url, errors)
+            }
+            _ => Ok(result["data"].take()),
+        }
+    }
+
+    /// Returns the block at which the deployment reads the fork: the block
+    /// the deployment was at when it first read from the fork. Reading all
+    /// entities at the same block keeps them consistent with each other,
+    /// even while the fork keeps indexing.
+    fn fork_block(
+        &self,
+        fork_base: &reqwest::Url,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<u64, Error> {
+        if let Some(block_number) = self.fork_blocks.lock().unwrap().get(subgraph_id) {
+            return Ok(*block_number);
+        }
+
+        // A deployment that hasn't reached the first block of its data
+        // sources has the same entities as at the block before it
+        let block_number = cmp::max(
+            self.store.block_ptr(subgraph_id.clone())?.number,
+            self.start_block(subgraph_id)?.saturating_sub(1),
+        );
+        let data = self.query(
+            fork_base,
+            subgraph_id,
+            "{ _meta { block { number } } }".into(),
+        )?;
+        let fork_block_number = data["_meta"]["block"]["number"]
+            .as_u64()
+            .ok_or_else(|| format_err!("the fork did not return its latest block"))?;
+        if fork_block_number < block_number {
+            bail!(
+                "the fork has only indexed up to block {}, but the deployment is at block {}",
+                fork_block_number,
+                block_number
+            );
+        }
+        info!(
+            self.logger,
+            "Forking subgraph deployment";
+            "subgraph_id" => subgraph_id.to_string(),
+            "fork_base" => fork_base.as_str(),
+            "block_number" => block_number,
+        );
+        self.fork_blocks
+            .lock()
+            .unwrap()
+            .insert(subgraph_id.clone(), block_number);
+        Ok(block_number)
+    }
+
+    /// Returns the earliest start block of the data sources of the
+    /// deployment, which a local copy of a manifest may have moved to the
+    /// block of a failure.
+    fn start_block(&self, subgraph_id: &SubgraphDeploymentId) -> Result<u64, Error> {
+        let manifest = self
+            .store
+            .get(SubgraphManifestEntity::key(SubgraphManifestEntity::id(
+                subgraph_id,
+            )))?;
+        let data_sources = match manifest.and_then(|manifest| manifest.get("dataSources").cloned())
+        {
+            Some(Value::List(data_sources)) => data_sources,
+            _ => return Ok(0),
+        };
+
+        let mut start_block = None;
+        for data_source in data_sources {
+            let source = match data_source {
+                Value::String(data_source) => self.store.get(EntityKey {
+                    subgraph_id: SUBGRAPHS_ID.clone(),
+                    entity_type: "EthereumContractSource".to_owned(),
+                    entity_id: format!("{}-source", data_source),
+                })?,
+                _ => None,
+            };
+            if let Some(Value::BigInt(block)) =
+                source.and_then(|source| source.get("startBlock").cloned())
+            {
+                start_block = Some(cmp::min(
+                    start_block.unwrap_or(u64::max_value()),
+                    block.to_u64(),
+                ));
+            }
+        }
+        Ok(start_block.unwrap_or(0))
+    }
+
+    /// Returns the fields of `entity_type` that are stored with its
+    /// entities and the selection that queries them.
+    fn selection(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_type: &str,
+    ) -> Result<(Vec<Field>, String), Error> {
+        let schema = self.store.subgraph_schema(subgraph_id)?;
+        let object_type = sast::get_object_type_definitions(&schema.document)
+            .into_iter()
+            .find(|object_type| object_type.name == entity_type)
+            .ok_or_else(|| format_err!("unknown entity type `{}`", entity_type))?;
+
+        // Derived fields are not stored with the entity, and references
+        // are stored as the IDs of the referenced entities
+        let fields = object_type
+            .fields
+            .iter()
+            .filter(|field| {
+                !field
+                    .directives
+                    .iter()
+                    .any(|directive| directive.name == "derivedFrom")
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let selection = fields
+            .iter()
+            .map(|field| {
+                if sast::get_referenced_entity_type(&schema.document, field).is_some() {
+                    format!("{} {{ id }}", field.name)
+                } else {
+                    field.name.clone()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        Ok((fields, selection))
+    }
+
+    /// Fetches the entity `key` from the fork base.
+    fn fetch(&self, fork_base: &reqwest::Url, key: &EntityKey) -> Result<Option<Entity>, Error> {
+        let block_number = self.fork_block(fork_base, &key.subgraph_id)?;
+        let (fields, selection) = self.selection(&key.subgraph_id, &key.entity_type)?;
+        let query = format!(
+            "{{ entity: {}(id: {}, block: {{ number: {} }}) {{ {} }} }}",
+            key.entity_type.to_camel_case(),
+            serde_json::to_string(&key.entity_id)?,
+            block_number,
+            selection
+        );
+
+        let data = self.query(fork_base, &key.subgraph_id, query)?;
+        match &data["entity"] {
+            serde_json::Value::Null => Ok(None),
+            value => entity_from_json(&fields, value).map(Some),
+        }
+    }
+
+    /// Fetches the first `count` entities that match `query` from the fork
+    /// base, ignoring the range of the query. Each entity has its type in
+    /// `__typename`, like the entities `find` returns.
+    fn fetch_all(
+        &self,
+        fork_base: &reqwest::Url,
+        query: &EntityQuery,
+        count: Option<u32>,
+    ) -> Result<Vec<Entity>, Error> {
+        if query.order_by_child.is_some() {
+            bail!("the fork cannot be queried in the order of referenced entities");
+        }
+        let block_number = self.fork_block(fork_base, &query.subgraph_id)?;
+        let mut arguments = vec![];
+        if let Some(filter) = &query.filter {
+            filter_arguments(filter, &mut arguments)?;
+        }
+        let mut order = String::new();
+        if let Some((attribute, _)) = &query.order_by {
+            order = format!(", orderBy: {}", attribute);
+            if let Some(EntityOrder::Descending) = query.order_direction {
+                order.push_str(", orderDirection: desc");
+            }
+        }
+
+        let mut entities = vec![];
+        for entity_type in query.entity_types.iter() {
+            let (fields, selection) = self.selection(&query.subgraph_id, entity_type)?;
+            let mut skip = 0;
+            loop {
+                let first = count.map_or(FORK_PAGE_SIZE, |count| {
+                    cmp::min(FORK_PAGE_SIZE, count.saturating_sub(skip))
+                });
+                if first == 0 {
+                    break;
+                }
+                let graphql = format!(
+                    "{{ entities: {}(where: {{ {} }}, first: {}, skip: {}{}, \
+                     block: {{ number: {} }}) {{ {} }} }}",
+                    entity_type.to_plural().to_camel_case(),
+                    arguments.join(", "),
+                    first,
+                    skip,
+                    order,
+                    block_number,
+                    selection
+                );
+                let data = self.query(fork_base, &query.subgraph_id, graphql)?;
+                let page = match &data["entities"] {
+                    serde_json::Value::Array(page) => page,
+                    value => bail!("the fork returned invalid entities: {}", value),
+                };
+                for value in page {
+                    let mut entity = entity_from_json(&fields, value)?;
+                    entity.set("__typename", entity_type.as_str());
+                    entities.push(entity);
+                }
+                if (page.len() as u32) < first {
+                    break;
+                }
+                skip += first;
+            }
+        }
+        Ok(entities)
+    }
+
+    /// Returns the entity `key` as the fork has it, fetching it only the
+    /// first time it is needed.
+    fn forked(&self, fork_base: &reqwest::Url, key: &EntityKey) -> Result<Option<Entity>, Error> {
+        if let Some(entity) = self.fetched.lock().unwrap().get(key) {
+            return Ok(entity.clone());
+        }
+        let entity = self.fetch(fork_base, key)?;
+        debug!(
+            self.logger,
+            "Fetched entity from the fork";
+            "subgraph_id" => key.subgraph_id.to_string(),
+            "entity_type" => &key.entity_type,
+            "entity_id" => &key.entity_id,
+            "found" => entity.is_some(),
+        );
+        self.fetched
+            .lock()
+            .unwrap()
+            .insert(key.clone(), entity.clone());
+        Ok(entity)
+    }
+
+    /// Merges the entities that the fork has into the entities that
+    /// `operations` set for the first time, so that the underlying store
+    /// gets all of their attributes.
+    fn merge_forked(
+        &self,
+        operations: Vec<EntityOperation>,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        operations
+            .into_iter()
+            .map(|operation| match operation {
+                EntityOperation::Set { key, data } => {
+                    let fork_base = match self.fork_base_for(&key.subgraph_id) {
+                        Some(fork_base) => fork_base,
+                        None => return Ok(EntityOperation::Set { key, data }),
+                    };
+                    if self.written.lock().unwrap().contains_key(&key)
+                        || self
+                            .store
+                            .get(key.clone())
+                            .map_err(|e| StoreError::Unknown(e.into()))?
+                            .is_some()
+                    {
+                        return Ok(EntityOperation::Set { key, data });
+                    }
+                    match self.forked(fork_base, &key)? {
+                        Some(mut entity) => {
+                            entity.merge(data);
+                            Ok(EntityOperation::Set { key, data: entity })
+                        }
+                        None => Ok(EntityOperation::Set { key, data }),
+                    }
+                }
+                operation => Ok(operation),
+            })
+            .collect()
+    }
+
+    /// Records that the entities `keys` were written in block `block_number`.
+    fn record_writes(&self, keys: Vec<EntityKey>, block_number: u64) {
+        if self.fork_base.is_none() {
+            return;
+        }
+        let mut fetched = self.fetched.lock().unwrap();
+        let mut written = self.written.lock().unwrap();
+        for key in keys {
+            fetched.remove(&key);
+            written.entry(key).or_insert(block_number);
+        }
+    }
+
+    /// Forgets the entities of `subgraph_id` that were first written after
+    /// block `block_number`, so that they are read from the fork again.
+    fn forget_writes(&self, subgraph_id: &SubgraphDeploymentId, block_number: u64) {
+        let mut written = self.written.lock().unwrap();
+        let reverted = written
+            .iter()
+            .filter(|(key, written_at)| {
+                key.subgraph_id == *subgraph_id && **written_at > block_number
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in reverted {
+            written.remove(&key);
+        }
+    }
+}
+
+/// Number of entities that are queried from the fork at once.
+const FORK_PAGE_SIZE: u32 = 100;
+
+/// The entities that `operations` write.
+fn written_keys(operations: &[EntityOperation]) -> Vec<EntityKey> {
+    operations
+        .iter()
+        .filter_map(|operation| match operation {
+            EntityOperation::Set { key, .. }
+            | EntityOperation::Update { key, .. }
+            | EntityOperation::Remove { key } => Some(key.clone()),
+            EntityOperation::AbortUnless { .. } => None,
+        })
+        .collect()
+}
+
+/// Converts an entity in a query result with the `fields` of its type.
+fn entity_from_json(fields: &[Field], value: &serde_json::Value) -> Result<Entity, Error> {
+    let attributes = match value {
+        serde_json::Value::Object(attributes) => attributes,
+        value => bail!("the fork returned an invalid entity: {}", value),
+    };
+    let mut entity = Entity::new();
+    for field in fields {
+        let value = attributes
+            .get(&field.name)
+            .unwrap_or(&serde_json::Value::Null);
+        entity.set(field.name.clone(), entity_value(&field.field_type, value)?);
+    }
+    Ok(entity)
+}
+
+/// Adds the arguments of the `where` filter of a collection query that
+/// correspond to `filter`.
+fn filter_arguments(filter: &EntityFilter, arguments: &mut Vec<String>) -> Result<(), Error> {
+    use self::EntityFilter::*;
+
+    let (attribute, suffix, value) = match filter {
+        And(filters) => {
+            for filter in filters {
+                filter_arguments(filter, arguments)?;
+            }
+            return Ok(());
+        }
+        Equal(attribute, value) => (attribute, "", graphql_value(value)?),
+        Not(attribute, value) => (attribute, "_not", graphql_value(value)?),
+        GreaterThan(attribute, value) => (attribute, "_gt", graphql_value(value)?),
+        LessThan(attribute, value) => (attribute, "_lt", graphql_value(value)?),
+        GreaterOrEqual(attribute, value) => (attribute, "_gte", graphql_value(value)?),
+        LessOrEqual(attribute, value) => (attribute, "_lte", graphql_value(value)?),
+        In(attribute, values) => (attribute, "_in", graphql_list(values)?),
+        NotIn(attribute, values) => (attribute, "_not_in", graphql_list(values)?),
+        Contains(attribute, value) => (attribute, "_contains", graphql_value(value)?),
+        NotContains(attribute, value) => (attribute, "_not_contains", graphql_value(value)?),
+        StartsWith(attribute, value) => (attribute, "_starts_with", graphql_value(value)?),
+        NotStartsWith(attribute, value) => (attribute, "_not_starts_with", graphql_value(value)?),
+        EndsWith(attribute, value) => (attribute, "_ends_with", graphql_value(value)?),
+        NotEndsWith(attribute, value) => (attribute, "_not_ends_with", graphql_value(value)?),
+        ContainsNoCase(attribute, value) => (attribute, "_contains_nocase", graphql_value(value)?),
+        NotContainsNoCase(attribute, value) => {
+            (attribute, "_not_contains_nocase", graphql_value(value)?)
+        }
+        StartsWithNoCase(attribute, value) => {
+            (attribute, "_starts_with_nocase", graphql_value(value)?)
+        }
+        NotStartsWithNoCase(attribute, value) => {
+            (attribute, "_not_starts_with_nocase", graphql_value(value)?)
+        }
+        EndsWithNoCase(attribute, value) => (attribute, "_ends_with_nocase", graphql_value(value)?),
+        NotEndsWithNoCase(attribute, value) => {
+            (attribute, "_not_ends_with_nocase", graphql_value(value)?)
+        }
+        Or(_) | Child(..) => bail!("the fork cannot be queried with filter {:?}", filter),
+    };
+    arguments.push(format!("{}{}: {}", attribute, suffix, value));
+    Ok(())
+}
+
+/// Formats `value` as a GraphQL input value.
+fn graphql_value(value: &Value) -> Result<String, Error> {
+    Ok(match value {
+        Value::String(s) => serde_json::to_string(s)?,
+        Value::Int(i) => i.to_string(),
+        Value::BigDecimal(d) => serde_json::to_string(&d.to_string())?,
+        Value::Bool(b) => b.to_string(),
+        Value::List(values) => graphql_list(values)?,
+        Value::Null => "null".to_owned(),
+        Value::Bytes(bytes) => serde_json::to_string(&bytes.to_string())?,
+        Value::BigInt(i) => serde_json::to_string(&i.to_string())?,
+    })
+}
+
+fn graphql_list(values: &[Value]) -> Result<String, Error> {
+    Ok(format!(
+        "[{}]",
+        values
+            .iter()
+            .map(graphql_value)
+            .collect::<Result<Vec<_>, _>>()?
+            .join(", ")
+    ))
+}
+
+/// Compares two values of an attribute for ordering entities; values of
+/// different types compare as equal, except that `null` comes first.
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Int(a), Value::Int(b)) => a.cmp(b),
+        (Value::BigInt(a), Value::BigInt(b)) => a.cmp(b),
+        (Value::BigDecimal(a), Value::BigDecimal(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Bytes(a), Value::Bytes(b)) => a.as_slice().cmp(b.as_slice()),
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Less,
+        (_, Value::Null) => Ordering::Greater,
+        _ => Ordering::Equal,
+    }
+}
+
+/// Converts the JSON value of a field of type `field_type` in a query
+/// result to the value of the entity attribute.
+fn entity_value(field_type: &Type, value: &serde_json::Value) -> Result<Value, Error> {
+    use serde_json::Value as JsonValue;
+
+    Ok(match (value, field_type) {
+        (JsonValue::Null, _) => Value::Null,
+        (value, Type::NonNullType(inner)) => entity_value(inner, value)?,
+        (JsonValue::Array(values), Type::ListType(inner)) => Value::List(
+            values
+                .iter()
+                .map(|value| entity_value(inner, value))
+                .collect::<Result<_, _>>()?,
+        ),
+        (JsonValue::Object(object), Type::NamedType(_)) => match object.get("id") {
+            Some(JsonValue::String(id)) => Value::String(id.clone()),
+            _ => bail!("the fork returned a reference without ID: {}", value),
+        },
+        (JsonValue::String(s), Type::NamedType(name)) => match name.as_str() {
+            BYTES_SCALAR => Value::Bytes(Bytes::from_str(s)?),
+            BIG_INT_SCALAR => Value::BigInt(BigInt::from_str(s)?),
+            BIG_DECIMAL_SCALAR => Value::BigDecimal(BigDecimal::from_str(s)?),
+            _ => Value::String(s.clone()),
+        },
+        (JsonValue::Number(number), Type::NamedType(_)) => Value::Int(
+            number
+                .as_i64()
+                .filter(|number| *number >= i32::min_value() as i64)
+                .filter(|number| *number <= i32::max_value() as i64)
+                .ok_or_else(|| format_err!("the fork returned an invalid Int: {}", number))?
+                as i32,
+        ),
+        (JsonValue::Bool(b), Type::NamedType(_)) => Value::Bool(*b),
+        _ => bail!(
+            "the fork returned `{}` for a field of type `{}`",
+            value,
+            field_type
+        ),
+    })
+}
+
+impl<S> Store for ForkStore<S>
+where
+    S: Store + SubgraphDeploymentStore,
+{
+    fn block_ptr(&self, subgraph_id: SubgraphDeploymentId) -> Result<EthereumBlockPointer, Error> {
+        self.store.block_ptr(subgraph_id)
+    }
+
+    fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        let fork_base = match self.fork_base_for(&key.subgraph_id) {
+            Some(fork_base) => fork_base,
+            None => return self.store.get(key),
+        };
+        if let Some(entity) = self.store.get(key.clone())? {
+            return Ok(Some(entity));
+        }
+
+        // The deployment removed entities that it wrote and no longer has
+        if self.written.lock().unwrap().contains_key(&key) {
+            return Ok(None);
+        }
+        self.forked(fork_base, &key)
+            .map_err(QueryExecutionError::StoreError)
+    }
+
+    fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        let fork_base = match self.fork_base_for(&query.subgraph_id) {
+            Some(fork_base) => fork_base,
+            None => return self.store.find(query),
+        };
+
+        // Both the store and the fork are asked for all entities up to the
+        // end of the range, since either may have the ones that come first
+        let count = query.range.first.map(|first| first + query.range.skip);
+        let mut local_query = query.clone();
+        local_query.range = EntityRange {
+            first: count,
+            skip: 0,
+        };
+        let mut entities = self.store.find(local_query)?;
+        let forked = self
+            .fetch_all(fork_base, &query, count)
+            .map_err(QueryExecutionError::StoreError)?;
+
+        // Entities the deployment wrote are only taken from the store
+        let key = |entity: &Entity| EntityKey {
+            subgraph_id: query.subgraph_id.clone(),
+            entity_type: match entity.get("__typename") {
+                Some(Value::String(entity_type)) => entity_type.clone(),
+                _ => query.entity_types[0].clone(),
+            },
+            entity_id: entity.id().unwrap_or_default(),
+        };
+        let written = self.written.lock().unwrap();
+        let local_keys = entities.iter().map(key).collect::<BTreeSet<_>>();
+        entities.extend(forked.into_iter().filter(|entity| {
+            let key = key(entity);
+            !written.contains_key(&key) && !local_keys.contains(&key)
+        }));
+
+        let order_by = query
+            .order_by
+            .as_ref()
+            .map_or("id", |(attribute, _)| attribute.as_str());
+        entities.sort_by(|a, b| {
+            let ordering = compare_values(
+                a.get(order_by).unwrap_or(&Value::Null),
+                b.get(order_by).unwrap_or(&Value::Null),
+            );
+            match query.order_direction {
+                Some(EntityOrder::Descending) => ordering.reverse(),
+                _ => ordering,
+            }
+        });
+        Ok(entities
+            .into_iter()
+            .skip(query.range.skip as usize)
+            .take(
+                query
+                    .range
+                    .first
+                    .map_or(usize::max_value(), |first| first as usize),
+            )
+            .collect())
+    }
+
+    fn find_one(&self, mut query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
+        if self.fork_base_for(&query.subgraph_id).is_none() {
+            return self.store.find_one(query);
+        }
+        query.range = EntityRange::first(1);
+        Ok(self.find(query)?.pop())
+    }
+
+    fn snapshot_at_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_number: u64,
+    ) -> Result<Box<EntitySnapshot>, QueryExecutionError> {
+        match self.fork_base_for(subgraph_id) {
+            Some(_) => Err(QueryExecutionError::StoreError(format_err!(
+                "queries at a block are not supported for forked subgraphs"
+            ))),
+            None => self.store.snapshot_at_block(subgraph_id, block_number),
+        }
+    }
+
+    fn set_block_ptr_with_no_changes(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        block_ptr_from: EthereumBlockPointer,
+        block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        self.store
+            .set_block_ptr_with_no_changes(subgraph_id, block_ptr_from, block_ptr_to)
+    }
+
+    fn transact_block_operations(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        block_ptr_from: EthereumBlockPointer,
+        block_ptr_to: EthereumBlockPointer,
+        operations: Vec<EntityOperation>,
+    ) -> Result<(), StoreError> {
+        let keys = written_keys(&operations);
+        let operations = self.merge_forked(operations)?;
+        self.store.transact_block_operations(
+            subgraph_id,
+            block_ptr_from,
+            block_ptr_to,
+            operations,
+        )?;
+        self.record_writes(keys, block_ptr_to.number);
+        Ok(())
+    }
+
+    fn apply_entity_operations(
+        &self,
+        operations: Vec<EntityOperation>,
+        event_source: EventSource,
+    ) -> Result<(), StoreError> {
+        let keys = written_keys(&operations);
+        let operations = self.merge_forked(operations)?;
+        self.store
+            .apply_entity_operations(operations, event_source)?;
+        self.record_writes(keys, 0);
+        Ok(())
+    }
+
+    fn build_entity_attribute_indexes(
+        &self,
+        indexes: Vec<AttributeIndexDefinition>,
+    ) -> Result<(), SubgraphAssignmentProviderError> {
+        self.store.build_entity_attribute_indexes(indexes)
+    }
+
+    fn revert_block_operations(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        block_ptr_from: EthereumBlockPointer,
+        block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        self.store
+            .revert_block_operations(subgraph_id.clone(), block_ptr_from, block_ptr_to)?;
+        self.forget_writes(&subgraph_id, block_ptr_to.number);
+        Ok(())
+    }
+
+    fn revert_block_range(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        block_ptrs: Vec<EthereumBlockPointer>,
+        operations: Vec<EntityOperation>,
+    ) -> Result<(), StoreError> {
+        let block_ptr_to = block_ptrs.last().cloned();
+        self.store
+            .revert_block_range(subgraph_id.clone(), block_ptrs, operations)?;
+        if let Some(block_ptr_to) = block_ptr_to {
+            self.forget_writes(&subgraph_id, block_ptr_to.number);
+        }
+        Ok(())
+    }
+
+    fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
+        self.store.subscribe(entities)
+    }
+
+    fn count_entities(&self, subgraph: SubgraphDeploymentId) -> Result<u64, Error> {
+        self.store.count_entities(subgraph)
+    }
+
+    fn block_timestamp(&self, block_hash: H256) -> Result<Option<u64>, Error> {
+        self.store.block_timestamp(block_hash)
+    }
+
+    fn background_jobs(&self) -> Result<Vec<BackgroundJob>, Error> {
+        self.store.background_jobs()
+    }
+
+    fn blocks_with_entity_changes(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: &[String],
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<EthereumBlockPointer>, Error> {
+        self.store
+            .blocks_with_entity_changes(subgraph_id, entity_types, from, to)
+    }
+
+    fn entity_changes_in_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: &[String],
+        block_hash: H256,
+    ) -> Result<Vec<EntityChangeTrigger>, Error> {
+        self.store
+            .entity_changes_in_block(subgraph_id, entity_types, block_hash)
+    }
+}
+
+impl<S> SubgraphDeploymentStore for ForkStore<S>
+where
+    S: Store + SubgraphDeploymentStore,
+{
+    fn subgraph_schema(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Arc<Schema>, Error> {
+        self.store.subgraph_schema(subgraph_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::data::subgraph::schema::SubgraphDeploymentEntity;
+    use graph_mock::MockStore;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const SCHEMA: &str = "type User @entity { id: ID!, name: String!, age: Int }";
+
+    /// Serves the GraphQL API of a fork at block 10 from a thread, with the
+    /// users `forked` and `removed`, and returns its URL and the queries it
+    /// received.
+    fn fork() -> (reqwest::Url, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let queries = Arc::new(Mutex::new(vec![]));
+        let received = queries.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    let line = line.to_lowercase();
+                    if line.starts_with("content-length:") {
+                        content_length = line["content-length:".len()..].trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                let query = body["query"].as_str().unwrap().to_owned();
+
+                let data = if query.contains("_meta") {
+                    json!({ "_meta": { "block": { "number": 10 } } })
+                } else if query.contains("entities:") {
+                    json!({ "entities": [
+                        { "id": "forked", "name": "Forked", "age": 30 },
+                        { "id": "removed", "name": "Removed", "age": 40 },
+                    ] })
+                } else if query.contains("\"forked\"") {
+                    json!({ "entity": { "id": "forked", "name": "Forked", "age": 30 } })
+                } else if query.contains("\"removed\"") {
+                    json!({ "entity": { "id": "removed", "name": "Removed", "age": 40 } })
+                } else {
+                    json!({ "entity": null })
+                };
+                received.lock().unwrap().push(query);
+
+                let body = json!({ "data": data }).to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        (url.parse().unwrap(), queries)
+    }
+
+    /// Returns a store that forks the deployment `forked` from a fork
+    /// served by `fork`, with the deployment at block 5.
+    fn fork_store() -> (
+        ForkStore<MockStore>,
+        SubgraphDeploymentId,
+        Arc<Mutex<Vec<String>>>,
+    ) {
+        let subgraph_id = SubgraphDeploymentId::new("forked").unwrap();
+        let schema = Schema::parse(SCHEMA, subgraph_id.clone()).unwrap();
+        let store = Arc::new(MockStore::new(vec![(subgraph_id.clone(), schema)]));
+        let mut deployment = Entity::new();
+        deployment.set("latestEthereumBlockHash", format!("{:x}", H256::zero()));
+        deployment.set("latestEthereumBlockNumber", Value::BigInt(BigInt::from(5)));
+        store
+            .apply_entity_operations(
+                vec![EntityOperation::Set {
+                    key: SubgraphDeploymentEntity::key(subgraph_id.clone()),
+                    data: deployment,
+                }],
+                EventSource::None,
+            )
+            .unwrap();
+
+        let (url, queries) = fork();
+        let logger = Logger::root(slog::Discard, o!());
+        let store = ForkStore::new(&logger, store, Some(url));
+        (store, subgraph_id, queries)
+    }
+
+    fn user_key(subgraph_id: &SubgraphDeploymentId, id: &str) -> EntityKey {
+        EntityKey {
+            subgraph_id: subgraph_id.clone(),
+            entity_type: "User".to_owned(),
+            entity_id: id.to_owned(),
+        }
+    }
+
+    fn user(id: &str, name: &str, age: Option<i32>) -> Entity {
+        let mut entity = Entity::new();
+        entity.set("id", id);
+        entity.set("name", name);
+        entity.set("age", age.map_or(Value::Null, Value::from));
+        entity
+    }
+
+    #[test]
+    fn entity_values_are_converted_by_field_type() {
+        let named = |name: &str| Type::NamedType(name.to_owned());
+        let non_null = |ty: Type| Type::NonNullType(Box::new(ty));
+        let list = |ty: Type| Type::ListType(Box::new(ty));
+
+        assert_eq!(
+            entity_value(&non_null(named("BigInt")), &json!("12345678901234567890")).unwrap(),
+            Value::BigInt(BigInt::from_str("12345678901234567890").unwrap())
+        );
+        assert_eq!(
+            entity_value(&named("Bytes"), &json!("0xff00")).unwrap(),
+            Value::Bytes(Bytes::from_str("0xff00").unwrap())
+        );
+        assert_eq!(
+            entity_value(&named("Int"), &json!(-7)).unwrap(),
+            Value::Int(-7)
+        );
+        assert_eq!(
+            entity_value(&named("Int"), &serde_json::Value::Null).unwrap(),
+            Value::Null
+        );
+        assert_eq!(
+            entity_value(
+                &non_null(list(non_null(named("User")))),
+                &json!([{ "id": "1" }, { "id": "2" }])
+            )
+            .unwrap(),
+            Value::List(vec![Value::from("1"), Value::from("2")])
+        );
+        assert!(entity_value(&named("Int"), &json!(1u64 << 40)).is_err());
+        assert!(entity_value(&named("Boolean"), &json!([true])).is_err());
+    }
+
+    #[test]
+    fn entities_are_read_from_the_fork_once_at_the_block_of_the_deployment() {
+        let (store, subgraph_id, queries) = fork_store();
+        let key = user_key(&subgraph_id, "forked");
+
+        let expected = user("forked", "Forked", Some(30));
+        assert_eq!(store.get(key.clone()).unwrap(), Some(expected.clone()));
+        assert_eq!(store.get(key.clone()).unwrap(), Some(expected));
+        assert_eq!(store.get(user_key(&subgraph_id, "missing")).unwrap(), None);
+
+        let queries = queries.lock().unwrap();
+        assert_eq!(queries.len(), 3);
+        assert!(queries[0].contains("_meta"));
+        assert!(queries[1].contains("block: { number: 5 }"));
+        assert!(queries[2].contains("block: { number: 5 }"));
+
+        // Nothing is written to the store until the deployment writes
+        assert_eq!(store.store.get(key).unwrap(), None);
+    }
+
+    #[test]
+    fn writes_merge_forked_entities_and_removals_are_not_forked_again() {
+        let (store, subgraph_id, _) = fork_store();
+        let forked = user_key(&subgraph_id, "forked");
+        let removed = user_key(&subgraph_id, "removed");
+
+        let mut data = Entity::new();
+        data.set("name", "Changed");
+        store
+            .apply_entity_operations(
+                vec![
+                    EntityOperation::Set {
+                        key: forked.clone(),
+                        data,
+                    },
+                    EntityOperation::Remove {
+                        key: removed.clone(),
+                    },
+                ],
+                EventSource::None,
+            )
+            .unwrap();
+
+        let expected = user("forked", "Changed", Some(30));
+        assert_eq!(
+            store.store.get(forked.clone()).unwrap(),
+            Some(expected.clone())
+        );
+        assert_eq!(store.get(forked).unwrap(), Some(expected));
+        assert_eq!(store.get(removed).unwrap(), None);
+    }
+
+    #[test]
+    fn find_merges_local_and_forked_entities() {
+        let (store, subgraph_id, queries) = fork_store();
+        store
+            .apply_entity_operations(
+                vec![
+                    EntityOperation::Set {
+                        key: user_key(&subgraph_id, "local"),
+                        data: user("local", "Local", Some(20)),
+                    },
+                    EntityOperation::Remove {
+                        key: user_key(&subgraph_id, "removed"),
+                    },
+                ],
+                EventSource::None,
+            )
+            .unwrap();
+
+        let query = EntityQuery::new(
+            subgraph_id.clone(),
+            vec!["User".to_owned()],
+            EntityRange::first(10),
+        )
+        .filter(EntityFilter::In(
+            "name".to_owned(),
+            vec!["Forked".into(), "Local".into(), "Removed".into()],
+        ));
+        let ids = store
+            .find(query)
+            .unwrap()
+            .into_iter()
+            .map(|entity| entity.id().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["forked", "local"]);
+
+        let queries = queries.lock().unwrap();
+        let query = queries.last().unwrap();
+        assert!(query.contains("users(where: { name_in: [\"Forked\", \"Local\", \"Removed\"] }"));
+        assert!(query.contains("block: { number: 5 }"));
+    }
+}
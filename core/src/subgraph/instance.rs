@@ -1,5 +1,115 @@
+use graph::data::subgraph::schema::SUBGRAPHS_ID;
 use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
 use graph::web3::types::{Log, Transaction};
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::env;
+
+lazy_static! {
+    /// Whether to run the handlers of independent data sources in parallel.
+    static ref PARALLEL_HANDLERS: bool = env::var("GRAPH_PARALLEL_HANDLERS")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+}
+
+/// Runtime hosts whose data sources depend on each other's entities.
+///
+/// No data source of a group reads or writes an entity type that a data
+/// source of another group writes, so the triggers of a block can be
+/// processed by the groups concurrently. This relies on the entity types
+/// that data sources declare, which is checked against the entity types
+/// their mappings actually write and load.
+struct HostGroup<H> {
+    hosts: Vec<Arc<H>>,
+    /// The entity types the data sources of the group write.
+    entity_types: HashSet<String>,
+    /// The entity types the data sources of the group only read.
+    read_types: HashSet<String>,
+}
+
+impl<H> HostGroup<H> {
+    /// Whether either group writes an entity type that the other reads or
+    /// writes.
+    fn depends_on(&self, other: &HostGroup<H>) -> bool {
+        !self.entity_types.is_disjoint(&other.entity_types)
+            || !self.entity_types.is_disjoint(&other.read_types)
+            || !self.read_types.is_disjoint(&other.entity_types)
+    }
+
+    /// Returns the type of an entity written by `entity_operations` that
+    /// none of the data sources of the group declares, if there is one.
+    /// Data sources that mappings create are not entities of the subgraph.
+    fn undeclared_entity_type<'a>(
+        &self,
+        entity_operations: &'a [EntityOperation],
+    ) -> Option<&'a str> {
+        entity_operations
+            .iter()
+            .filter_map(|operation| match operation {
+                EntityOperation::Set { key, .. }
+                | EntityOperation::Update { key, .. }
+                | EntityOperation::Remove { key } => Some(key),
+                EntityOperation::AbortUnless { .. } => None,
+            })
+            .filter(|key| key.subgraph_id != *SUBGRAPHS_ID)
+            .map(|key| key.entity_type.as_str())
+            .find(|entity_type| !self.entity_types.contains(*entity_type))
+    }
+
+    /// Returns one of the entity types in `reads` that none of the data
+    /// sources of the group declares, if there is one.
+    fn undeclared_read_type<'a>(&self, reads: &'a HashSet<String>) -> Option<&'a str> {
+        reads.iter().map(String::as_str).find(|entity_type| {
+            !self.entity_types.contains(*entity_type) && !self.read_types.contains(*entity_type)
+        })
+    }
+}
+
+/// Groups hosts so that any two data sources where one writes an entity
+/// type that the other reads or writes, directly or through other data
+/// sources, end up in the same group. `entity_types` holds the types each
+/// data source writes and reads.
+fn group_hosts<H>(
+    hosts: &[Arc<H>],
+    entity_types: Vec<(Vec<String>, Vec<String>)>,
+) -> Vec<HostGroup<H>> {
+    let mut groups: Vec<HostGroup<H>> = vec![];
+    for (host, (entity_types, read_types)) in hosts.iter().zip(entity_types) {
+        let mut group = HostGroup {
+            hosts: vec![host.clone()],
+            entity_types: entity_types.into_iter().collect(),
+            read_types: read_types.into_iter().collect(),
+        };
+
+        // Merge all groups that this host depends on or that depend on it
+        let (dependent, independent): (Vec<_>, Vec<_>) = groups
+            .into_iter()
+            .partition(|other| group.depends_on(other));
+        for other in dependent.into_iter().rev() {
+            let mut hosts = other.hosts;
+            hosts.extend(group.hosts);
+            group.hosts = hosts;
+            group.entity_types.extend(other.entity_types);
+            group.read_types.extend(other.read_types);
+        }
+
+        groups = independent;
+        groups.push(group);
+    }
+
+    // Restore the manifest order within merged groups
+    let position = |host: &Arc<H>| {
+        hosts
+            .iter()
+            .position(|other| Arc::ptr_eq(host, other))
+            .unwrap()
+    };
+    for group in groups.iter_mut() {
+        group.hosts.sort_by_key(|host| position(host));
+    }
+    groups.sort_by_key(|group| position(&group.hosts[0]));
+    groups
+}
 
 pub struct SubgraphInstance<T>
 where
@@ -11,58 +121,20 @@ where
     /// data sources appear in the subgraph manifest. Incoming block
     /// stream events are processed by the mappings in this same order.
     hosts: Vec<Arc<T::Host>>,
+
+    /// The same runtime hosts, grouped by the entity types their data
+    /// sources declare. Hosts keep their manifest order within a group.
+    groups: Arc<Vec<HostGroup<T::Host>>>,
 }
 
-impl<T> SubgraphInstanceTrait<T> for SubgraphInstance<T>
+impl<T> SubgraphInstance<T>
 where
     T: RuntimeHostBuilder,
 {
-    fn from_manifest(
-        logger: &Logger,
-        manifest: SubgraphManifest,
-        host_builder: T,
-    ) -> Result<Self, Error> {
-        // Create a new runtime host for each data source in the subgraph manifest;
-        // we use the same order here as in the subgraph manifest to make the
-        // event processing behavior predictable
-        let manifest_id = manifest.id;
-
-        let (hosts, errors): (_, Vec<_>) = manifest
-            .data_sources
-            .into_iter()
-            .map(|d| host_builder.build(&logger, manifest_id.clone(), d))
-            .partition(|res| res.is_ok());
-
-        if !errors.is_empty() {
-            let joined_errors = errors
-                .into_iter()
-                .map(Result::unwrap_err)
-                .map(|e| e.to_string())
-                .collect::<Vec<_>>()
-                .join(", ");
-            return Err(format_err!(
-                "Errors loading data sources: {}",
-                joined_errors
-            ));
-        }
-
-        Ok(SubgraphInstance {
-            hosts: hosts
-                .into_iter()
-                .map(Result::unwrap)
-                .map(Arc::new)
-                .collect(),
-        })
-    }
-
-    /// Returns true if the subgraph has a handler for an Ethereum event.
-    fn matches_log(&self, log: &Log) -> bool {
-        self.hosts.iter().any(|host| host.matches_log(log))
-    }
-
-    fn process_trigger(
-        &self,
+    /// Processes a trigger with those of `hosts` that have a handler for it.
+    fn process_trigger_in_hosts(
         logger: &Logger,
+        hosts: &[Arc<T::Host>],
         block: Arc<EthereumBlock>,
         trigger: EthereumTrigger,
         entity_operations: Vec<EntityOperation>,
@@ -74,8 +146,7 @@ where
                     .transaction_for_log(&log)
                     .map(Arc::new)
                     .ok_or_else(|| format_err!("Found no transaction for event"));
-                let matching_hosts: Vec<_> = self
-                    .hosts
+                let matching_hosts: Vec<_> = hosts
                     .iter()
                     .filter(|host| host.matches_log(&log))
                     .cloned()
@@ -104,8 +175,7 @@ where
                     .transaction_for_call(&call)
                     .map(Arc::new)
                     .ok_or_else(|| format_err!("Found no transaction for call"));
-                let matching_hosts: Vec<_> = self
-                    .hosts
+                let matching_hosts: Vec<_> = hosts
                     .iter()
                     .filter(|host| host.matches_call(&call))
                     .cloned()
@@ -128,8 +198,7 @@ where
                 Box::new(eops)
             }
             EthereumTrigger::Block(trigger_type) => {
-                let matching_hosts: Vec<_> = self
-                    .hosts
+                let matching_hosts: Vec<_> = hosts
                     .iter()
                     .filter(|host| host.matches_block(trigger_type.clone()))
                     .cloned()
@@ -147,6 +216,332 @@ where
                 );
                 Box::new(eops)
             }
+            EthereumTrigger::EntityChange(entity_change) => {
+                let matching_hosts: Vec<_> = hosts
+                    .iter()
+                    .filter(|host| host.matches_entity_change(&entity_change))
+                    .cloned()
+                    .collect();
+                let entity_change = Arc::new(entity_change);
+                let eops = stream::iter_ok(matching_hosts).fold(
+                    entity_operations,
+                    move |entity_operations, host| {
+                        host.process_entity_change(
+                            logger.clone(),
+                            block.clone(),
+                            entity_change.clone(),
+                            entity_operations,
+                        )
+                    },
+                );
+                Box::new(eops)
+            }
+        }
+    }
+
+    /// Processes triggers one after the other with `hosts`, passing the
+    /// entity operations of earlier triggers to later ones.
+    fn process_triggers_in_hosts(
+        logger: Logger,
+        hosts: Vec<Arc<T::Host>>,
+        block: Arc<EthereumBlock>,
+        triggers: Vec<EthereumTrigger>,
+        pending_operations: Vec<EntityOperation>,
+    ) -> impl Future<Item = Vec<EntityOperation>, Error = Error> + Send {
+        // Handlers see the pending operations because they come first in
+        // the operations that are passed along; they are split off again
+        // once all triggers have been processed
+        let pending_count = pending_operations.len();
+        stream::iter_ok(triggers)
+            .fold(pending_operations, move |entity_operations, trigger| {
+                Self::process_trigger_in_hosts(
+                    &logger,
+                    &hosts,
+                    block.clone(),
+                    trigger,
+                    entity_operations,
+                )
+            })
+            .map(move |mut entity_operations| entity_operations.split_off(pending_count))
+    }
+}
+
+impl<T> SubgraphInstanceTrait<T> for SubgraphInstance<T>
+where
+    T: RuntimeHostBuilder,
+{
+    fn from_manifest(
+        logger: &Logger,
+        manifest: SubgraphManifest,
+        host_builder: T,
+        quotas: Arc<DeploymentQuotas>,
+    ) -> Result<Self, Error> {
+        // Create a new runtime host for each data source in the subgraph manifest;
+        // we use the same order here as in the subgraph manifest to make the
+        // event processing behavior predictable
+        let manifest_id = manifest.id.clone();
+
+        // All data sources share the allowlist, so that rate limits apply
+        // to the deployment as a whole
+        let external_hosts = Arc::new(ExternalHosts::from_manifest(&manifest)?);
+        if !external_hosts.is_empty() {
+            info!(
+                logger,
+                "Mappings may send HTTP requests to external hosts";
+                "hosts" => external_hosts.hosts().join(", ")
+            );
+        }
+
+        // Data sources that don't declare a network, such as file data
+        // sources, run on the network of the subgraph
+        let network_name = manifest.network_name();
+
+        // Mappings of all data sources can create data sources from the
+        // templates
+        let templates = Arc::new(manifest.templates.clone());
+
+        let entity_types = manifest
+            .data_sources
+            .iter()
+            .map(|data_source| {
+                (
+                    data_source.mapping.entities.clone(),
+                    data_source.mapping.reads.clone(),
+                )
+            })
+            .collect();
+
+        let (hosts, errors): (_, Vec<_>) = manifest
+            .data_sources
+            .into_iter()
+            .map(|mut d| {
+                d.network = d.network.or_else(|| network_name.clone());
+                host_builder.build(
+                    &logger,
+                    manifest_id.clone(),
+                    d,
+                    templates.clone(),
+                    external_hosts.clone(),
+                    quotas.clone(),
+                )
+            })
+            .partition(|res| res.is_ok());
+
+        if !errors.is_empty() {
+            let joined_errors = errors
+                .into_iter()
+                .map(Result::unwrap_err)
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(format_err!(
+                "Errors loading data sources: {}",
+                joined_errors
+            ));
         }
+
+        let hosts: Vec<_> = hosts
+            .into_iter()
+            .map(Result::unwrap)
+            .map(Arc::new)
+            .collect();
+        let groups = group_hosts(&hosts, entity_types);
+        if *PARALLEL_HANDLERS && groups.len() > 1 {
+            info!(
+                logger,
+                "Processing triggers of independent data sources in parallel";
+                "groups" => groups.len()
+            );
+        }
+
+        Ok(SubgraphInstance {
+            hosts,
+            groups: Arc::new(groups),
+        })
+    }
+
+    /// Returns true if the subgraph has a handler for an Ethereum event.
+    fn matches_log(&self, log: &Log) -> bool {
+        self.hosts.iter().any(|host| host.matches_log(log))
+    }
+
+    fn process_trigger(
+        &self,
+        logger: &Logger,
+        block: Arc<EthereumBlock>,
+        trigger: EthereumTrigger,
+        entity_operations: Vec<EntityOperation>,
+    ) -> Box<Future<Item = Vec<EntityOperation>, Error = Error> + Send> {
+        Self::process_trigger_in_hosts(logger, &self.hosts, block, trigger, entity_operations)
+    }
+
+    fn process_triggers(
+        &self,
+        logger: &Logger,
+        block: Arc<EthereumBlock>,
+        triggers: Vec<EthereumTrigger>,
+        pending_operations: Vec<EntityOperation>,
+    ) -> Box<Future<Item = Vec<EntityOperation>, Error = Error> + Send> {
+        let logger = logger.to_owned();
+        let hosts = self.hosts.clone();
+
+        if !*PARALLEL_HANDLERS || self.groups.len() < 2 {
+            return Box::new(Self::process_triggers_in_hosts(
+                logger,
+                hosts,
+                block,
+                triggers,
+                pending_operations,
+            ));
+        }
+
+        // Only the entity types that the handlers load while processing this
+        // block matter
+        for host in hosts.iter() {
+            host.take_entity_reads();
+        }
+
+        // Process the triggers in each group of hosts concurrently; no
+        // group reads or writes the entities another group writes, so the
+        // order in which their entity operations are applied does not matter
+        let groups = self.groups.clone();
+        let group_results = future::join_all(
+            groups
+                .iter()
+                .map(|group| {
+                    Self::process_triggers_in_hosts(
+                        logger.clone(),
+                        group.hosts.clone(),
+                        block.clone(),
+                        triggers.clone(),
+                        pending_operations.clone(),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        Box::new(group_results.and_then(
+            move |group_results| -> Box<Future<Item = _, Error = _> + Send> {
+                let undeclared = groups
+                    .iter()
+                    .zip(group_results.iter())
+                    .filter_map(|(group, entity_operations)| {
+                        let reads = group
+                            .hosts
+                            .iter()
+                            .flat_map(|host| host.take_entity_reads())
+                            .collect::<HashSet<_>>();
+                        group
+                            .undeclared_entity_type(entity_operations)
+                            .map(|entity_type| ("wrote", entity_type.to_owned()))
+                            .or_else(|| {
+                                group
+                                    .undeclared_read_type(&reads)
+                                    .map(|entity_type| ("loaded", entity_type.to_owned()))
+                            })
+                    })
+                    .next();
+
+                match undeclared {
+                    None => Box::new(future::ok(group_results.into_iter().flatten().collect())),
+                    Some((access, entity_type)) => {
+                        // A mapping wrote or loaded an entity type that its
+                        // data source doesn't declare, so the groups may not
+                        // have been independent; process the block again,
+                        // one trigger after the other
+                        warn!(
+                            logger,
+                            "Mapping {} an undeclared entity type, \
+                             processing block triggers sequentially", access;
+                            "entity_type" => entity_type
+                        );
+                        Box::new(Self::process_triggers_in_hosts(
+                            logger,
+                            hosts,
+                            block,
+                            triggers,
+                            pending_operations,
+                        ))
+                    }
+                }
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Groups data sources given by the space separated entity types they
+    /// write and read, and returns the positions of the data sources in
+    /// each group.
+    fn grouped(data_sources: &[(&str, &str)]) -> Vec<Vec<usize>> {
+        let types =
+            |names: &str| -> Vec<String> { names.split_whitespace().map(String::from).collect() };
+        let hosts: Vec<_> = (0..data_sources.len()).map(Arc::new).collect();
+        let entity_types = data_sources
+            .iter()
+            .map(|(writes, reads)| (types(*writes), types(*reads)))
+            .collect();
+        group_hosts(&hosts, entity_types)
+            .into_iter()
+            .map(|group| group.hosts.iter().map(|host| **host).collect())
+            .collect()
+    }
+
+    #[test]
+    fn data_sources_that_write_different_entities_are_independent() {
+        assert_eq!(
+            grouped(&[("A", ""), ("B", ""), ("C", "")]),
+            vec![vec![0], vec![1], vec![2]]
+        );
+    }
+
+    #[test]
+    fn data_sources_that_write_the_same_entities_are_grouped() {
+        assert_eq!(
+            grouped(&[("A", ""), ("B", ""), ("C A", ""), ("D", "")]),
+            vec![vec![0, 2], vec![1], vec![3]]
+        );
+
+        // Through a data source that writes the entities of both
+        assert_eq!(
+            grouped(&[("A", ""), ("B", ""), ("A B", "")]),
+            vec![vec![0, 1, 2]]
+        );
+    }
+
+    #[test]
+    fn data_sources_that_read_the_entities_of_another_are_grouped_with_it() {
+        // Before and after the data source that writes them
+        assert_eq!(
+            grouped(&[("B", "A"), ("A", ""), ("C", "A"), ("D", "")]),
+            vec![vec![0, 1, 2], vec![3]]
+        );
+    }
+
+    #[test]
+    fn data_sources_that_read_the_same_entities_are_independent() {
+        assert_eq!(grouped(&[("A", "C"), ("B", "C")]), vec![vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn loading_entity_types_that_are_not_declared_is_detected() {
+        let hosts = vec![Arc::new(0), Arc::new(1)];
+        let groups = group_hosts(
+            &hosts,
+            vec![
+                (vec!["A".to_owned()], vec!["C".to_owned()]),
+                (vec!["B".to_owned()], vec![]),
+            ],
+        );
+        let reads = |names: &[&str]| -> HashSet<String> {
+            names.iter().map(|name| name.to_string()).collect()
+        };
+
+        assert_eq!(groups[0].undeclared_read_type(&reads(&["A", "C"])), None);
+        assert_eq!(groups[0].undeclared_read_type(&reads(&["B"])), Some("B"));
+        assert_eq!(groups[1].undeclared_read_type(&reads(&["C"])), Some("C"));
     }
 }
@@ -1,8 +1,18 @@
 use futures::sync::mpsc::{channel, Receiver, Sender};
-use graph::data::subgraph::schema::SubgraphDeploymentEntity;
+use futures::sync::oneshot;
+use graph::data::subgraph::schema::{
+    DynamicEthereumContractDataSourceEntity, SubgraphDeploymentEntity, SUBGRAPHS_ID,
+};
 use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
-use std::collections::HashMap;
-use std::sync::RwLock;
+use graph::util::tracing;
+use lazy_static::lazy_static;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::env;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::thread;
 use std::time::Duration;
 
 use super::SubgraphInstance;
@@ -11,24 +21,72 @@ use crate::split_logger;
 use crate::ElasticDrainConfig;
 use crate::ElasticLoggingConfig;
 
+lazy_static! {
+    /// The Elasticsearch index that subgraph logs are written to.
+    static ref ELASTIC_SEARCH_INDEX: String = env::var("GRAPH_ELASTIC_SEARCH_INDEX")
+        .unwrap_or_else(|_| String::from("subgraph-logs"));
+
+    /// How often subgraph logs are sent to Elasticsearch.
+    static ref ELASTIC_SEARCH_FLUSH_INTERVAL: Duration =
+        env::var("GRAPH_ELASTIC_SEARCH_FLUSH_INTERVAL_SECS")
+            .ok()
+            .map(|s| {
+                Duration::from_secs(s.parse().unwrap_or_else(|_| {
+                    panic!("failed to parse env var GRAPH_ELASTIC_SEARCH_FLUSH_INTERVAL_SECS")
+                }))
+            })
+            .unwrap_or(Duration::from_secs(5));
+
+    /// How many processed blocks a subgraph instance can queue for writing
+    /// to the store before it waits for the writes to catch up.
+    static ref WRITE_QUEUE_SIZE: usize = env::var("GRAPH_SUBGRAPH_WRITE_QUEUE_SIZE")
+        .ok()
+        .map(|s| {
+            s.parse().unwrap_or_else(|_| {
+                panic!("failed to parse env var GRAPH_SUBGRAPH_WRITE_QUEUE_SIZE")
+            })
+        })
+        .unwrap_or(10);
+}
+
 type InstanceShutdownMap = Arc<RwLock<HashMap<SubgraphDeploymentId, CancelGuard>>>;
 
+/// Resolve when the tasks of subgraph instances have finished.
+type RunningInstances = Arc<Mutex<Vec<oneshot::Receiver<()>>>>;
+
+/// The entity operations of the blocks that are queued for writing, in
+/// block order. Handlers see them as if they had been written already.
+type PendingOperations = Arc<Mutex<VecDeque<Vec<EntityOperation>>>>;
+
+/// A processed block whose entity operations are queued for writing.
+struct BlockWrite {
+    block_ptr_now: EthereumBlockPointer,
+    block_ptr_after: EthereumBlockPointer,
+    entity_operations: Vec<EntityOperation>,
+    logger: Logger,
+    span: tracing::Span,
+}
+
 pub struct SubgraphInstanceManager {
     logger: Logger,
     input: Sender<SubgraphAssignmentProviderEvent>,
+    shutdown: Arc<SharedCancelGuard>,
+    running: RunningInstances,
 }
 
 impl SubgraphInstanceManager {
-    /// Creates a new runtime manager.
+    /// Creates a new runtime manager. The subgraphs it runs are limited by
+    /// `quotas`.
     pub fn new<B, S, T>(
         logger: &Logger,
         store: Arc<S>,
         host_builder: T,
         block_stream_builder: B,
         elastic_config: Option<ElasticLoggingConfig>,
+        quotas: Arc<ResourceQuotas>,
     ) -> Self
     where
-        S: Store + ChainStore,
+        S: Store,
         T: RuntimeHostBuilder,
         B: BlockStreamBuilder + 'static,
     {
@@ -37,6 +95,9 @@ impl SubgraphInstanceManager {
         // Create channel for receiving subgraph provider events.
         let (subgraph_sender, subgraph_receiver) = channel(100);
 
+        let shutdown = Arc::new(SharedCancelGuard::new());
+        let running = RunningInstances::default();
+
         // Handle incoming events from the subgraph provider.
         Self::handle_subgraph_events(
             logger.clone(),
@@ -45,14 +106,38 @@ impl SubgraphInstanceManager {
             host_builder,
             block_stream_builder,
             elastic_config,
+            quotas,
+            shutdown.clone(),
+            running.clone(),
         );
 
         SubgraphInstanceManager {
             logger,
             input: subgraph_sender,
+            shutdown,
+            running,
         }
     }
 
+    /// Stops all subgraph instances. Blocks that are being processed or
+    /// are queued for writing are finished and their entity operations are
+    /// written to the store; the returned future resolves once that is
+    /// done. Subgraphs that are started afterwards stop right away.
+    pub fn shutdown(&self) -> impl Future<Item = (), Error = ()> + Send {
+        self.shutdown.cancel();
+
+        let running = mem::replace(&mut *self.running.lock().unwrap(), vec![]);
+        info!(
+            self.logger,
+            "Waiting for subgraphs to finish their current block";
+            "subgraphs" => running.len()
+        );
+
+        // The receivers resolve with an error when their sender is dropped,
+        // which happens when the instance's task ends
+        future::join_all(running.into_iter().map(|done| done.then(|_| Ok(())))).map(|_| ())
+    }
+
     /// Handle incoming events from subgraph providers.
     fn handle_subgraph_events<B, S, T>(
         logger: Logger,
@@ -61,8 +146,11 @@ impl SubgraphInstanceManager {
         host_builder: T,
         block_stream_builder: B,
         elastic_config: Option<ElasticLoggingConfig>,
+        quotas: Arc<ResourceQuotas>,
+        shutdown: Arc<SharedCancelGuard>,
+        running: RunningInstances,
     ) where
-        S: Store + ChainStore,
+        S: Store,
         T: RuntimeHostBuilder,
         B: BlockStreamBuilder + 'static,
     {
@@ -84,10 +172,10 @@ impl SubgraphInstanceManager {
                                 elastic_logger(
                                     ElasticDrainConfig {
                                         general: elastic_config,
-                                        index: String::from("subgraph-logs"),
+                                        index: ELASTIC_SEARCH_INDEX.clone(),
                                         document_type: String::from("log"),
                                         subgraph_id: manifest.id.clone(),
-                                        flush_interval: Duration::from_secs(5),
+                                        flush_interval: *ELASTIC_SEARCH_FLUSH_INTERVAL,
                                     },
                                     term_logger.clone(),
                                 ),
@@ -97,6 +185,15 @@ impl SubgraphInstanceManager {
 
                     info!(logger, "Start subgraph");
 
+                    let quota = quotas.for_deployment(&manifest.id);
+                    if quota != ResourceQuota::default() {
+                        info!(
+                            logger,
+                            "Subgraph runs with a resource quota";
+                            "quota" => format!("{:?}", quota)
+                        );
+                    }
+
                     Self::start_subgraph(
                         logger.clone(),
                         instances.clone(),
@@ -104,6 +201,9 @@ impl SubgraphInstanceManager {
                         block_stream_builder.clone(),
                         store.clone(),
                         manifest,
+                        Arc::new(DeploymentQuotas::new(quota)),
+                        shutdown.clone(),
+                        running.clone(),
                     )
                     .map_err(|err| error!(logger, "Failed to start subgraph: {}", err))
                     .ok();
@@ -118,6 +218,12 @@ impl SubgraphInstanceManager {
         }));
     }
 
+    /// Starts processing blocks for the subgraph of `manifest`, with the
+    /// data sources that its mappings created so far. After a block in which
+    /// the mappings create data sources, the subgraph restarts once the block
+    /// has been written, so that the new data sources process the blocks
+    /// after it. Reverting that block removes the data sources, but they
+    /// keep running until the subgraph restarts.
     fn start_subgraph<B, T, S>(
         logger: Logger,
         instances: InstanceShutdownMap,
@@ -125,31 +231,61 @@ impl SubgraphInstanceManager {
         block_stream_builder: B,
         store: Arc<S>,
         manifest: SubgraphManifest,
+        quotas: Arc<DeploymentQuotas>,
+        shutdown: Arc<SharedCancelGuard>,
+        running: RunningInstances,
     ) -> Result<(), Error>
     where
         T: RuntimeHostBuilder,
-        B: BlockStreamBuilder,
-        S: Store + ChainStore,
+        B: BlockStreamBuilder + 'static,
+        S: Store,
     {
+        // Everything needed to start the subgraph again when its mappings
+        // create data sources
+        let restart = Arc::new(AtomicBool::new(false));
+        let restart_for_block = restart.clone();
+        let restart_logger = logger.clone();
+        let restart_instances = instances.clone();
+        let restart_host_builder = host_builder.clone();
+        let restart_block_stream_builder = block_stream_builder.clone();
+        let restart_store = store.clone();
+        let restart_manifest = manifest.clone();
+        let restart_quotas = quotas.clone();
+        let restart_shutdown = shutdown.clone();
+        let restart_running = running.clone();
+
+        let mut manifest = manifest;
+        load_dynamic_data_sources(&*store, &mut manifest)?;
+
         let id = manifest.id.clone();
         let id_for_block = manifest.id.clone();
         let id_for_err = manifest.id.clone();
-        let store_for_events = store.clone();
+        let block_detail = manifest.block_detail();
         let store_for_errors = store.clone();
 
-        // Request a block stream for this subgraph
+        // Request a block stream for this subgraph. Stopping the subgraph
+        // discards the block that is being processed, while shutting down
+        // only stops the stream once the block has been written
         let block_stream_canceler = CancelGuard::new();
         let block_stream_cancel_handle = block_stream_canceler.handle();
-        let block_stream = block_stream_builder
-            .from_subgraph(&manifest, logger.clone())
+        let block_stream = block_stream_builder.from_subgraph(&manifest, logger.clone())?;
+        let written_blocks = block_stream.written_blocks_sink();
+        let block_stream = block_stream
             .from_err()
-            .cancelable(&block_stream_canceler, || CancelableError::Cancel);
+            .cancelable(&block_stream_canceler, || CancelableError::Cancel)
+            .cancelable(&*shutdown, || CancelableError::Cancel);
+
+        // Dropped when the writer below ends; the writer only sends on
+        // `drained_sender` if it wrote every block it was given
+        let (done_sender, done_receiver) = oneshot::channel::<()>();
+        let (drained_sender, drained_receiver) = oneshot::channel::<()>();
 
         // Load the subgraph
         let instance = Arc::new(SubgraphInstance::from_manifest(
             &logger,
             manifest,
             host_builder,
+            quotas.clone(),
         )?);
 
         // Prepare loggers for different parts of the async processing
@@ -163,6 +299,24 @@ impl SubgraphInstanceManager {
         let status_ops = SubgraphDeploymentEntity::update_failed_operations(&id_for_err, false);
         store_for_errors.apply_entity_operations(status_ops, EventSource::None)?;
 
+        // Handlers keep processing blocks while earlier blocks are written
+        // to the store; the channel holds one more block than its buffer
+        let (write_sender, write_receiver) = channel(cmp::max(*WRITE_QUEUE_SIZE, 1) - 1);
+        let pending = PendingOperations::default();
+        Self::spawn_writer(
+            logger.clone(),
+            store.clone(),
+            id.clone(),
+            quotas,
+            write_receiver,
+            pending.clone(),
+            written_blocks,
+            block_stream_cancel_handle.clone(),
+            done_sender,
+            drained_sender,
+        )?;
+        let cancel_handle_for_restart = block_stream_cancel_handle.clone();
+
         // Forward block stream events to the subgraph for processing
         tokio::spawn(
             block_stream
@@ -171,8 +325,10 @@ impl SubgraphInstanceManager {
                     let block = block.ethereum_block;
                     let id = id_for_block.clone();
                     let instance = instance.clone();
-                    let store = store_for_events.clone();
+                    let pending = pending.clone();
+                    let write_sender = write_sender.clone();
                     let block_stream_cancel_handle = block_stream_cancel_handle.clone();
+                    let restart = restart_for_block.clone();
                     let logger = block_logger.new(o!(
                         "block_number" => format!("{:?}", block.block.number.unwrap()),
                         "block_hash" => format!("{:?}", block.block.hash.unwrap())
@@ -190,59 +346,118 @@ impl SubgraphInstanceManager {
                         );
                     }
 
+                    let block_ptr_for_errors = EthereumBlockPointer::from(&block);
+
+                    // Trace how long running the handlers and writing their
+                    // changes to the store takes
+                    let mut block_span = tracing::root_span("process_block");
+                    tracing::tag(&mut block_span, "subgraph_id", &id);
+                    tracing::tag(&mut block_span, "block_number", block_ptr_for_errors.number);
+                    tracing::tag(&mut block_span, "triggers", triggers.len());
+                    let handlers_span = tracing::child_span("handlers", &block_span);
+
                     // Process events one after the other, passing in entity operations
                     // collected previously to every new event being processed
-                    let block_for_process = Arc::new(block);
+                    let block_for_process = Arc::new(block.with_detail(block_detail));
                     let block_for_transact = block_for_process.clone();
-                    let logger_for_process = logger;
-                    let logger_for_transact = logger_for_process.clone();
-                    stream::iter_ok::<_, CancelableError<Error>>(triggers)
-                        .fold(vec![], move |entity_operations, trigger| {
-                            let logger = logger_for_process.clone();
-                            let instance = instance.clone();
-                            let block = block_for_process.clone();
-                            instance
-                                .process_trigger(&logger, block, trigger, entity_operations)
-                                .map_err(|e| format_err!("Failed to process trigger: {}", e))
+                    let logger_for_transact = logger.clone();
+                    let pending_operations =
+                        pending.lock().unwrap().iter().flatten().cloned().collect();
+                    instance
+                        .process_triggers(&logger, block_for_process, triggers, pending_operations)
+                        .then(move |result| {
+                            drop(handlers_span);
+                            result
+                        })
+                        .map_err(move |e| {
+                            // Remember which handler failed, for the indexing status
+                            let handler =
+                                e.downcast_ref::<HandlerError>().map(|e| e.handler.clone());
+                            CancelableError::Error(
+                                SubgraphFatalError {
+                                    message: format!("Failed to process trigger: {}", e),
+                                    block_ptr: Some(block_ptr_for_errors),
+                                    handler,
+                                }
+                                .into(),
+                            )
                         })
                         .and_then(move |entity_operations| {
                             let block = block_for_transact.clone();
-                            let logger = logger_for_transact.clone();
-
-                            let block_ptr_now = EthereumBlockPointer::to_parent(&block);
-                            let block_ptr_after = EthereumBlockPointer::from(&*block);
 
                             // Avoid writing to store if block stream has been canceled
                             if block_stream_cancel_handle.is_canceled() {
-                                return Err(CancelableError::Cancel);
+                                return future::Either::A(future::err(CancelableError::Cancel));
                             }
 
-                            info!(
-                                logger,
-                                "Applying {} entity operation(s) = {:#?}",
-                                entity_operations.len(),
+                            // Queue the entity operations for writing to the
+                            // store, waiting for the writer if the queue is full
+                            let creates_data_sources = creates_data_sources(&entity_operations);
+                            pending.lock().unwrap().push_back(entity_operations.clone());
+                            let write = BlockWrite {
+                                block_ptr_now: EthereumBlockPointer::to_parent(&block),
+                                block_ptr_after: EthereumBlockPointer::from(&*block),
                                 entity_operations,
-                            );
-
-                            // Transact entity operations into the store and update the
-                            // subgraph's block stream pointer
-                            store
-                                .transact_block_operations(
-                                    id.clone(),
-                                    block_ptr_now,
-                                    block_ptr_after,
-                                    entity_operations,
-                                )
-                                .map_err(|e| {
-                                    format_err!(
-                                        "Error while processing block stream for a subgraph: {}",
-                                        e
-                                    )
-                                    .into()
-                                })
+                                logger: logger_for_transact.clone(),
+                                span: block_span,
+                            };
+                            future::Either::B(
+                                write_sender
+                                    .send(write)
+                                    .map_err(|_| {
+                                        // The writer has stopped after a failed
+                                        // write, which marked the subgraph as
+                                        // failed already
+                                        CancelableError::Cancel
+                                    })
+                                    .and_then(move |_| {
+                                        // Stop processing blocks; the subgraph
+                                        // restarts with the new data sources
+                                        if creates_data_sources {
+                                            info!(
+                                                logger_for_transact,
+                                                "Mappings created data sources, \
+                                                 restarting subgraph"
+                                            );
+                                            restart.store(true, Ordering::SeqCst);
+                                            Err(CancelableError::Cancel)
+                                        } else {
+                                            Ok(())
+                                        }
+                                    }),
+                            )
                         })
                 })
                 .map_err(move |e| match e {
+                    CancelableError::Cancel if restart.load(Ordering::SeqCst) => {
+                        // Restart once the blocks that are queued for writing,
+                        // which include the one that created the data
+                        // sources, have been written, unless the subgraph was
+                        // stopped or failed in the meantime
+                        tokio::spawn(drained_receiver.then(move |drained| {
+                            if drained.is_ok()
+                                && !cancel_handle_for_restart.is_canceled()
+                                && !restart_shutdown.is_canceled()
+                            {
+                                Self::start_subgraph(
+                                    restart_logger.clone(),
+                                    restart_instances,
+                                    restart_host_builder,
+                                    restart_block_stream_builder,
+                                    restart_store,
+                                    restart_manifest,
+                                    restart_quotas,
+                                    restart_shutdown,
+                                    restart_running,
+                                )
+                                .map_err(|e| {
+                                    error!(restart_logger, "Failed to restart subgraph: {}", e)
+                                })
+                                .ok();
+                            }
+                            Ok(())
+                        }));
+                    }
                     CancelableError::Cancel => {
                         debug!(
                             error_logger,
@@ -251,33 +466,145 @@ impl SubgraphInstanceManager {
                         );
                     }
                     CancelableError::Error(e) => {
-                        error!(
-                            error_logger,
-                            "Subgraph instance failed to run: {}", e;
-                            "id" => id_for_err.to_string()
-                        );
-
-                        // Set subgraph status to Failed
-                        let status_ops =
-                            SubgraphDeploymentEntity::update_failed_operations(&id_for_err, true);
-                        if let Err(e) =
-                            store_for_errors.apply_entity_operations(status_ops, EventSource::None)
-                        {
-                            error!(
-                                error_logger,
-                                "Failed to set subgraph status to Failed: {}", e;
-                                "id" => id_for_err.to_string()
-                            );
-                        }
+                        // Set subgraph status to Failed, keeping the error around
+                        let error = e.downcast::<SubgraphFatalError>().unwrap_or_else(|e| {
+                            SubgraphFatalError {
+                                message: e.to_string(),
+                                block_ptr: None,
+                                handler: None,
+                            }
+                        });
+                        fail_subgraph(&error_logger, &*store_for_errors, &id_for_err, error);
                     }
                 }),
         );
 
+        // Keep track of the task, forgetting those that have ended
+        {
+            let mut running = running.lock().unwrap();
+            *running = mem::replace(&mut *running, vec![])
+                .into_iter()
+                .filter_map(|mut done| match done.try_recv() {
+                    Ok(None) => Some(done),
+                    _ => None,
+                })
+                .collect();
+            running.push(done_receiver);
+        }
+
         // Keep the cancel guard for shutting down the subgraph instance later
         instances.write().unwrap().insert(id, block_stream_canceler);
         Ok(())
     }
 
+    /// Writes the queued blocks to the store one after the other, on a
+    /// thread of its own. Stops at the first write that fails, after
+    /// marking the subgraph as failed, and discards the queued blocks once
+    /// the subgraph is stopped. A block that would take the subgraph over
+    /// its entity or data source quota fails the subgraph without being
+    /// written. Reports every block it wrote on `written_blocks`, and sends
+    /// on `drained_sender` once all queued blocks have been written.
+    fn spawn_writer<S>(
+        logger: Logger,
+        store: Arc<S>,
+        id: SubgraphDeploymentId,
+        quotas: Arc<DeploymentQuotas>,
+        writes: Receiver<BlockWrite>,
+        pending: PendingOperations,
+        mut written_blocks: Sender<EthereumBlockPointer>,
+        block_stream_cancel_handle: CancelHandle,
+        done_sender: oneshot::Sender<()>,
+        drained_sender: oneshot::Sender<()>,
+    ) -> Result<(), Error>
+    where
+        S: Store,
+    {
+        thread::Builder::new()
+            .name(format!("writer-{}", id))
+            .spawn(move || {
+                // Shutting down waits for the queued blocks to be written
+                let _done_sender = done_sender;
+
+                // Only deployments with a quota for them count their
+                // entities and data sources
+                let mut counts = QuotaCounts::default();
+
+                let mut drained = true;
+                for write in writes.wait() {
+                    let write = match write {
+                        Ok(write) => write,
+                        Err(()) => break,
+                    };
+                    if block_stream_cancel_handle.is_canceled() {
+                        drained = false;
+                        break;
+                    }
+
+                    info!(
+                        write.logger,
+                        "Applying {} entity operation(s) = {:#?}",
+                        write.entity_operations.len(),
+                        write.entity_operations,
+                    );
+
+                    // Check the quotas before writing anything, so that
+                    // the subgraph fails at the same block every time
+                    let block_ptr_after = write.block_ptr_after;
+                    let result =
+                        check_quotas(&*store, &id, &quotas, &mut counts, &write.entity_operations);
+                    if let Err(e) = result {
+                        pending.lock().unwrap().pop_front();
+                        let error = SubgraphFatalError {
+                            message: format!("Failed quota check: {}", e),
+                            block_ptr: Some(block_ptr_after),
+                            handler: None,
+                        };
+                        fail_subgraph(&logger, &*store, &id, error);
+                        drained = false;
+                        break;
+                    }
+
+                    // Transact entity operations into the store and update the
+                    // subgraph's block stream pointer
+                    let result = tracing::enter(&write.span, || {
+                        tracing::traced("transact_block", |_| {
+                            store.transact_block_operations(
+                                id.clone(),
+                                write.block_ptr_now,
+                                write.block_ptr_after,
+                                write.entity_operations,
+                            )
+                        })
+                    });
+                    pending.lock().unwrap().pop_front();
+
+                    if let Err(e) = result {
+                        let error = SubgraphFatalError {
+                            message: format!(
+                                "Error while processing block stream for a subgraph: {}",
+                                e
+                            ),
+                            block_ptr: Some(block_ptr_after),
+                            handler: None,
+                        };
+                        fail_subgraph(&logger, &*store, &id, error);
+                        drained = false;
+                        break;
+                    }
+
+                    // The block stream waits for this before it looks for
+                    // more blocks. If the channel is full, the stream has
+                    // yet to see an earlier notification and will find
+                    // this block in the store then
+                    let _ = written_blocks.try_send(block_ptr_after);
+                }
+                if drained {
+                    let _ = drained_sender.send(());
+                }
+            })?;
+        Ok(())
+    }
+
     fn stop_subgraph(instances: InstanceShutdownMap, id: SubgraphDeploymentId) {
         // Drop the cancel guard to shut down the subgraph now
         let mut instances = instances.write().unwrap();
@@ -285,6 +612,176 @@ impl SubgraphInstanceManager {
     }
 }
 
+/// Adds the data sources that the mappings of the deployment of `manifest`
+/// created to the manifest. Data sources are ordered by the block they were
+/// created in and by their ID within a block, so that a deployment processes
+/// triggers in the same order whenever it restarts.
+pub fn load_dynamic_data_sources<S: Store>(
+    store: &S,
+    manifest: &mut SubgraphManifest,
+) -> Result<(), Error> {
+    let mut data_sources = store
+        .find(DynamicEthereumContractDataSourceEntity::query_for_deployment(&manifest.id))?
+        .iter()
+        .map(|entity| {
+            let id = entity.id()?;
+            DynamicEthereumContractDataSourceEntity::from_entity(entity)
+                .map(|data_source| (data_source.block_ptr().number, id, data_source))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    data_sources
+        .sort_by(|(block_a, id_a, _), (block_b, id_b, _)| (block_a, id_a).cmp(&(block_b, id_b)));
+    manifest.add_dynamic_data_sources(
+        data_sources
+            .into_iter()
+            .map(|(_, _, data_source)| data_source)
+            .collect(),
+    )
+}
+
+/// The data sources that `operations` create from templates.
+fn created_data_sources(operations: &[EntityOperation]) -> BTreeSet<&EntityKey> {
+    operations
+        .iter()
+        .filter_map(|operation| match operation {
+            EntityOperation::Set { key, .. }
+                if key.subgraph_id == *SUBGRAPHS_ID
+                    && key.entity_type == DynamicEthereumContractDataSourceEntity::TYPENAME =>
+            {
+                Some(key)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether `operations` create data sources from templates.
+fn creates_data_sources(operations: &[EntityOperation]) -> bool {
+    !created_data_sources(operations).is_empty()
+}
+
+/// The number of entities and dynamic data sources of a deployment, for
+/// the quotas that limit them. Each is counted in the store once, before
+/// the first block is written, and kept up to date from then on.
+#[derive(Default)]
+struct QuotaCounts {
+    entities: Option<u64>,
+    data_sources: Option<u64>,
+}
+
+/// Checks that the deployment `id` stays within its entity and dynamic
+/// data source quotas after `operations`, updating `counts`.
+fn check_quotas<S: Store>(
+    store: &S,
+    id: &SubgraphDeploymentId,
+    quotas: &DeploymentQuotas,
+    counts: &mut QuotaCounts,
+    operations: &[EntityOperation],
+) -> Result<(), Error> {
+    if quotas.quota().max_entities.is_some() {
+        let count = match counts.entities {
+            Some(count) => count,
+            None => store.count_entities(id.clone())?,
+        };
+        let count = entity_count_after(store, id, count, operations)?;
+        counts.entities = Some(count);
+        quotas.check_entities(count)?;
+    }
+
+    if quotas.quota().max_dynamic_data_sources.is_some() {
+        let count = match counts.data_sources {
+            Some(count) => count,
+            None => store
+                .find(DynamicEthereumContractDataSourceEntity::query_for_deployment(id))?
+                .len() as u64,
+        };
+        let count = count + created_data_sources(operations).len() as u64;
+        counts.data_sources = Some(count);
+        quotas.check_dynamic_data_sources(count)?;
+    }
+    Ok(())
+}
+
+/// The number of entities that the deployment `id` has after `operations`,
+/// given that it has `count` entities before them. Which of the entities
+/// exist before `operations` is looked up with one query per entity type.
+fn entity_count_after<S: Store>(
+    store: &S,
+    id: &SubgraphDeploymentId,
+    count: u64,
+    operations: &[EntityOperation],
+) -> Result<u64, Error> {
+    // Whether each entity that the operations set or remove exists after them
+    let mut exists_after = BTreeMap::new();
+    for operation in operations {
+        match operation {
+            EntityOperation::Set { key, .. } if key.subgraph_id == *id => {
+                exists_after.insert(key, true);
+            }
+            EntityOperation::Remove { key } if key.subgraph_id == *id => {
+                exists_after.insert(key, false);
+            }
+            _ => (),
+        }
+    }
+
+    let mut ids_by_type = BTreeMap::new();
+    for key in exists_after.keys() {
+        ids_by_type
+            .entry(&key.entity_type)
+            .or_insert_with(Vec::new)
+            .push(key.entity_id.clone());
+    }
+    let mut exists_before = HashSet::new();
+    for (entity_type, ids) in ids_by_type {
+        let range = EntityRange {
+            first: None,
+            skip: 0,
+        };
+        let query = EntityQuery::new(id.clone(), vec![entity_type.clone()], range)
+            .filter(EntityFilter::new_in("id", ids));
+        for entity in store.find(query)? {
+            exists_before.insert((entity_type, entity.id()?));
+        }
+    }
+
+    let mut count = count;
+    for (key, exists_after) in exists_after {
+        match (
+            exists_before.contains(&(&key.entity_type, key.entity_id.clone())),
+            exists_after,
+        ) {
+            (false, true) => count += 1,
+            (true, false) => count = count.saturating_sub(1),
+            _ => (),
+        }
+    }
+    Ok(count)
+}
+
+/// Sets the status of the subgraph to failed, keeping `error` around.
+fn fail_subgraph<S: Store>(
+    logger: &Logger,
+    store: &S,
+    id: &SubgraphDeploymentId,
+    error: SubgraphFatalError,
+) {
+    error!(
+        logger,
+        "Subgraph instance failed to run: {}", error;
+        "id" => id.to_string()
+    );
+
+    let status_ops = SubgraphDeploymentEntity::fail_operations(id, &error);
+    if let Err(e) = store.apply_entity_operations(status_ops, EventSource::None) {
+        error!(
+            logger,
+            "Failed to set subgraph status to Failed: {}", e;
+            "id" => id.to_string()
+        );
+    }
+}
+
 impl EventConsumer<SubgraphAssignmentProviderEvent> for SubgraphInstanceManager {
     /// Get the wrapped event sink.
     fn event_sink(
@@ -296,3 +793,334 @@ impl EventConsumer<SubgraphAssignmentProviderEvent> for SubgraphInstanceManager
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::web3::types::{Address, H256};
+    use graph_mock::MockStore;
+
+    fn user_key(id: &SubgraphDeploymentId, user_id: &str) -> EntityKey {
+        EntityKey {
+            subgraph_id: id.clone(),
+            entity_type: "User".to_owned(),
+            entity_id: user_id.to_owned(),
+        }
+    }
+
+    fn set_user(id: &SubgraphDeploymentId, user_id: &str) -> EntityOperation {
+        EntityOperation::Set {
+            key: user_key(id, user_id),
+            data: Entity::new(),
+        }
+    }
+
+    fn remove_user(id: &SubgraphDeploymentId, user_id: &str) -> EntityOperation {
+        EntityOperation::Remove {
+            key: user_key(id, user_id),
+        }
+    }
+
+    fn create_data_source(id: &SubgraphDeploymentId, block: u64) -> Vec<EntityOperation> {
+        DynamicEthereumContractDataSourceEntity::new(
+            id.clone(),
+            "Token".to_owned(),
+            Address::from(block),
+            None,
+            EthereumBlockPointer::from((H256::from(block), block)),
+        )
+        .write_operations()
+        .unwrap()
+    }
+
+    fn quota_error(result: Result<(), Error>) -> QuotaError {
+        match result.unwrap_err().downcast::<QuotaError>() {
+            Ok(e) => e,
+            Err(e) => panic!("not a quota error: {}", e),
+        }
+    }
+
+    #[test]
+    fn quotas_keep_counting_entities_and_data_sources() {
+        let id = SubgraphDeploymentId::new("QmQuotas").unwrap();
+        let schema = Schema::parse("type User @entity { id: ID! }", id.clone()).unwrap();
+        let store = MockStore::new(vec![(id.clone(), schema)]);
+        let mut existing = vec![set_user(&id, "1"), set_user(&id, "2")];
+        existing.extend(create_data_source(&id, 1));
+        store
+            .apply_entity_operations(existing, EventSource::None)
+            .unwrap();
+
+        let quotas = DeploymentQuotas::new(ResourceQuota {
+            max_entities: Some(3),
+            max_dynamic_data_sources: Some(2),
+            ..ResourceQuota::default()
+        });
+        let mut counts = QuotaCounts::default();
+
+        // Updating an entity and removing one that doesn't exist don't
+        // change the count
+        let block = vec![
+            set_user(&id, "1"),
+            set_user(&id, "3"),
+            remove_user(&id, "4"),
+        ];
+        assert!(check_quotas(&store, &id, &quotas, &mut counts, &block).is_ok());
+        assert_eq!(counts.entities, Some(3));
+        assert_eq!(counts.data_sources, Some(1));
+        store
+            .apply_entity_operations(block, EventSource::None)
+            .unwrap();
+
+        let mut block = vec![remove_user(&id, "1"), set_user(&id, "4")];
+        block.extend(create_data_source(&id, 2));
+        assert!(check_quotas(&store, &id, &quotas, &mut counts, &block).is_ok());
+        assert_eq!(counts.entities, Some(3));
+        assert_eq!(counts.data_sources, Some(2));
+        store
+            .apply_entity_operations(block, EventSource::None)
+            .unwrap();
+
+        let block = vec![set_user(&id, "5")];
+        assert_eq!(
+            quota_error(check_quotas(&store, &id, &quotas, &mut counts, &block)),
+            QuotaError::Entities(3, 4)
+        );
+
+        // A restarted subgraph counts its entities and data sources again
+        let mut counts = QuotaCounts::default();
+        let block = create_data_source(&id, 3);
+        assert_eq!(
+            quota_error(check_quotas(&store, &id, &quotas, &mut counts, &block)),
+            QuotaError::DynamicDataSources(2, 3)
+        );
+        assert_eq!(counts.entities, Some(3));
+    }
+
+    fn block_ptr(number: u64) -> EthereumBlockPointer {
+        EthereumBlockPointer::from((H256::from(number), number))
+    }
+
+    fn set_user_at(id: &SubgraphDeploymentId, user_id: &str, block: u64) -> EntityOperation {
+        EntityOperation::Set {
+            key: user_key(id, user_id),
+            data: Entity::from(vec![("block", Value::from(block))]),
+        }
+    }
+
+    fn user_block(store: &MockStore, id: &SubgraphDeploymentId, user_id: &str) -> Option<Value> {
+        store
+            .get(user_key(id, user_id))
+            .unwrap()
+            .and_then(|user| user.get("block").cloned())
+    }
+
+    /// The writer of a deployment whose subgraph pointer is at block 0,
+    /// and the channels it is fed through. Up to two blocks can be queued
+    /// for it.
+    struct TestWriter {
+        id: SubgraphDeploymentId,
+        store: Arc<MockStore>,
+        pending: PendingOperations,
+        writes: Option<Sender<BlockWrite>>,
+        receivers: Option<(Receiver<BlockWrite>, Sender<EthereumBlockPointer>)>,
+        written_blocks: Receiver<EthereumBlockPointer>,
+        block_stream_canceler: Option<CancelGuard>,
+        done: Option<oneshot::Receiver<()>>,
+        drained: Option<oneshot::Receiver<()>>,
+    }
+
+    impl TestWriter {
+        fn new() -> Self {
+            let id = SubgraphDeploymentId::new("QmWriter").unwrap();
+            let schema = Schema::parse("type User @entity { id: ID!, block: Int }", id.clone());
+            let store = Arc::new(MockStore::new(vec![(id.clone(), schema.unwrap())]));
+            let mut deployment = Entity::new();
+            deployment.set("failed", false);
+            deployment.set("latestEthereumBlockHash", block_ptr(0).hash_hex());
+            deployment.set("latestEthereumBlockNumber", 0u64);
+            store
+                .apply_entity_operations(
+                    vec![EntityOperation::Set {
+                        key: SubgraphDeploymentEntity::key(id.clone()),
+                        data: deployment,
+                    }],
+                    EventSource::None,
+                )
+                .unwrap();
+
+            let (writes, write_receiver) = channel(1);
+            // Unlike the block stream, the test wants to see every
+            // notification
+            let (written_sender, written_blocks) = channel(10);
+            TestWriter {
+                id,
+                store,
+                pending: PendingOperations::default(),
+                writes: Some(writes),
+                receivers: Some((write_receiver, written_sender)),
+                written_blocks,
+                block_stream_canceler: Some(CancelGuard::new()),
+                done: None,
+                drained: None,
+            }
+        }
+
+        fn start(&mut self) {
+            let (write_receiver, written_sender) = self.receivers.take().unwrap();
+            let (done_sender, done_receiver) = oneshot::channel();
+            let (drained_sender, drained_receiver) = oneshot::channel();
+            SubgraphInstanceManager::spawn_writer(
+                Logger::root(slog::Discard, o!()),
+                self.store.clone(),
+                self.id.clone(),
+                Arc::new(DeploymentQuotas::default()),
+                write_receiver,
+                self.pending.clone(),
+                written_sender,
+                self.block_stream_canceler.as_ref().unwrap().handle(),
+                done_sender,
+                drained_sender,
+            )
+            .unwrap();
+            self.done = Some(done_receiver);
+            self.drained = Some(drained_receiver);
+        }
+
+        /// Queues the writes of block `number`.
+        fn queue(&mut self, number: u64, entity_operations: Vec<EntityOperation>) {
+            self.queue_write(block_ptr(number - 1), block_ptr(number), entity_operations)
+        }
+
+        /// Queues writes like processing a block does, waiting while the
+        /// queue is full. Does nothing once the writer has stopped.
+        fn queue_write(
+            &mut self,
+            block_ptr_now: EthereumBlockPointer,
+            block_ptr_after: EthereumBlockPointer,
+            entity_operations: Vec<EntityOperation>,
+        ) {
+            if let Some(writes) = self.writes.take() {
+                self.pending
+                    .lock()
+                    .unwrap()
+                    .push_back(entity_operations.clone());
+                let write = BlockWrite {
+                    block_ptr_now,
+                    block_ptr_after,
+                    entity_operations,
+                    logger: Logger::root(slog::Discard, o!()),
+                    span: tracing::root_span("process_block"),
+                };
+                self.writes = writes.send(write).wait().ok();
+            }
+        }
+
+        /// Whether there is room in the queue for another block.
+        fn has_room(&mut self) -> bool {
+            let writes = self.writes.as_mut().unwrap();
+            future::poll_fn(|| writes.poll_ready().map(Async::Ready))
+                .wait()
+                .unwrap()
+                .is_ready()
+        }
+
+        /// Stops the subgraph, like a stopped block stream does.
+        fn stop(&mut self) {
+            self.block_stream_canceler.take();
+        }
+
+        /// Stops queueing blocks and waits for the writer to end. Returns
+        /// whether it wrote every block and the blocks it reported.
+        fn finish(mut self) -> (bool, Vec<EthereumBlockPointer>) {
+            self.writes.take();
+            let _ = self.done.take().unwrap().wait();
+            let drained = self.drained.take().unwrap().wait().is_ok();
+            let written_blocks = self
+                .written_blocks
+                .wait()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            (drained, written_blocks)
+        }
+    }
+
+    #[test]
+    fn writer_writes_queued_blocks_in_order() {
+        let mut writer = TestWriter::new();
+        let (id, store, pending) = (
+            writer.id.clone(),
+            writer.store.clone(),
+            writer.pending.clone(),
+        );
+
+        // Handlers wait for the writer once two blocks are queued
+        writer.queue(1, vec![set_user_at(&id, "1", 1)]);
+        writer.queue(2, vec![set_user_at(&id, "1", 2), set_user_at(&id, "2", 2)]);
+        assert!(!writer.has_room());
+        assert_eq!(pending.lock().unwrap().len(), 2);
+
+        writer.start();
+        writer.queue(3, vec![remove_user(&id, "2")]);
+        let (drained, written_blocks) = writer.finish();
+        assert!(drained);
+        assert_eq!(
+            written_blocks,
+            vec![block_ptr(1), block_ptr(2), block_ptr(3)]
+        );
+        assert_eq!(store.block_ptr(id.clone()).unwrap(), block_ptr(3));
+        assert_eq!(user_block(&store, &id, "1"), Some(Value::from(2u64)));
+        assert_eq!(user_block(&store, &id, "2"), None);
+        assert!(pending.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn writer_fails_the_subgraph_at_the_first_failed_write() {
+        let mut writer = TestWriter::new();
+        let (id, store) = (writer.id.clone(), writer.store.clone());
+
+        // Block 2 is not a child of the block 1 that was written, which the
+        // store refuses
+        writer.queue(1, vec![set_user_at(&id, "1", 1)]);
+        writer.queue_write(
+            EthereumBlockPointer::from((H256::from(100), 1)),
+            block_ptr(2),
+            vec![set_user_at(&id, "2", 2)],
+        );
+        writer.start();
+        writer.queue(3, vec![set_user_at(&id, "3", 3)]);
+        let (drained, written_blocks) = writer.finish();
+        assert!(!drained);
+        assert_eq!(written_blocks, vec![block_ptr(1)]);
+        assert_eq!(store.block_ptr(id.clone()).unwrap(), block_ptr(1));
+        assert_eq!(user_block(&store, &id, "2"), None);
+        assert_eq!(user_block(&store, &id, "3"), None);
+
+        let deployment = store
+            .get(SubgraphDeploymentEntity::key(id.clone()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(deployment.get("failed"), Some(&Value::Bool(true)));
+        assert_eq!(
+            deployment.get("lastErrorBlockNumber"),
+            Some(&Value::from(2u64))
+        );
+    }
+
+    #[test]
+    fn writer_discards_queued_blocks_once_the_subgraph_is_stopped() {
+        let mut writer = TestWriter::new();
+        let (id, store) = (writer.id.clone(), writer.store.clone());
+
+        writer.queue(1, vec![set_user_at(&id, "1", 1)]);
+        writer.queue(2, vec![set_user_at(&id, "2", 2)]);
+        writer.stop();
+        writer.start();
+        let (drained, written_blocks) = writer.finish();
+        assert!(!drained);
+        assert!(written_blocks.is_empty());
+        assert_eq!(store.block_ptr(id.clone()).unwrap(), block_ptr(0));
+        assert_eq!(user_block(&store, &id, "1"), None);
+        assert_eq!(user_block(&store, &id, "2"), None);
+    }
+}
@@ -1,10 +1,16 @@
+mod fork;
 mod instance;
 mod instance_manager;
 mod provider;
 mod registrar;
+mod replay;
 mod validation;
 
+pub use self::fork::ForkStore;
 pub use self::instance::SubgraphInstance;
-pub use self::instance_manager::SubgraphInstanceManager;
+pub use self::instance_manager::{load_dynamic_data_sources, SubgraphInstanceManager};
 pub use self::provider::SubgraphAssignmentProvider;
-pub use self::registrar::SubgraphRegistrar;
+pub use self::registrar::{
+    reassign_subgraph, remove_subgraph, unassign_subgraph, SubgraphRegistrar,
+};
+pub use self::replay::{replay_subgraph, ReplayStore};
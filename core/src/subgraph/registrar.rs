@@ -1,6 +1,8 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::iter;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
 
 use super::validation;
 use graph::data::subgraph::schema::*;
@@ -9,12 +11,17 @@ use graph::prelude::{
     SubgraphRegistrar as SubgraphRegistrarTrait, *,
 };
 
+/// How long the result of a request with an idempotency key is kept, and
+/// with it the key.
+const IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
 pub struct SubgraphRegistrar<L, P, S, CS> {
     logger: Logger,
     resolver: Arc<L>,
     provider: Arc<P>,
     store: Arc<S>,
-    chain_store: Arc<CS>,
+    chain_stores: EthereumNetworks<CS>,
+    network_capabilities: EthereumNetworks<EthereumNetworkCapabilities>,
     node_id: NodeId,
     version_switching_mode: SubgraphVersionSwitchingMode,
     assignment_event_stream_cancel_guard: CancelGuard, // cancels on drop
@@ -32,7 +39,8 @@ where
         resolver: Arc<L>,
         provider: Arc<P>,
         store: Arc<S>,
-        chain_store: Arc<CS>,
+        chain_stores: EthereumNetworks<CS>,
+        network_capabilities: EthereumNetworks<EthereumNetworkCapabilities>,
         node_id: NodeId,
         version_switching_mode: SubgraphVersionSwitchingMode,
     ) -> Self {
@@ -43,7 +51,8 @@ where
             resolver,
             provider,
             store,
-            chain_store,
+            chain_stores,
+            network_capabilities,
             node_id,
             version_switching_mode,
             assignment_event_stream_cancel_guard: CancelGuard::new(),
@@ -142,35 +151,11 @@ where
 
                     match entity_change.operation {
                         EntityChangeOperation::Set => {
-                            store
-                                .get(SubgraphDeploymentAssignmentEntity::key(
-                                    subgraph_hash.clone(),
-                                ))
-                                .map_err(|e| {
-                                    format_err!("Failed to get subgraph assignment entity: {}", e)
-                                })
-                                .map(|entity_opt| -> Box<Stream<Item = _, Error = _> + Send> {
-                                    if let Some(entity) = entity_opt {
-                                        if entity.get("nodeId") == Some(&node_id.to_string().into())
-                                        {
-                                            // Start subgraph on this node
-                                            Box::new(stream::once(Ok(AssignmentEvent::Add {
-                                                subgraph_id: subgraph_hash,
-                                                node_id: node_id.clone(),
-                                            })))
-                                        } else {
-                                            // Ensure it is removed from this node
-                                            Box::new(stream::once(Ok(AssignmentEvent::Remove {
-                                                subgraph_id: subgraph_hash,
-                                                node_id: node_id.clone(),
-                                            })))
-                                        }
-                                    } else {
-                                        // Was added/updated, but is now gone.
-                                        // We will get a separate Removed event later.
-                                        Box::new(stream::empty())
-                                    }
-                                })
+                            assignment_set_event(&*store, &node_id, subgraph_hash).map(
+                                |event| -> Box<Stream<Item = _, Error = _> + Send> {
+                                    Box::new(stream::iter_ok(event))
+                                },
+                            )
                         }
                         EntityChangeOperation::Removed => {
                             // Send remove event without checking node ID.
@@ -190,6 +175,7 @@ where
     fn start_assigned_subgraphs(&self) -> impl Future<Item = (), Error = Error> {
         let provider = self.provider.clone();
         let logger = self.logger.clone();
+        let store = self.store.clone();
 
         // Create a query to find all assignments with this node ID
         let assignment_query = SubgraphDeploymentAssignmentEntity::query()
@@ -210,6 +196,17 @@ where
                     })
                     .collect::<Result<HashSet<SubgraphDeploymentId>, _>>()
             })
+            .and_then(move |subgraph_ids| {
+                // Skip deployments whose indexing has been disabled by an operator
+                subgraph_ids
+                    .into_iter()
+                    .filter_map(|id| match store.is_deployment_indexing_disabled(&id) {
+                        Ok(true) => None,
+                        Ok(false) => Some(Ok(id)),
+                        Err(e) => Some(Err(e)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
             .and_then(move |subgraph_ids| {
                 let provider = provider.clone();
                 stream::iter_ok(subgraph_ids).for_each(move |id| {
@@ -247,17 +244,21 @@ where
         let logger = self.logger.clone();
         let store = self.store.clone();
         let version_switching_mode = self.version_switching_mode;
-        let chain_store = self.chain_store.clone();
+        let chain_stores = self.chain_stores.clone();
+        let network_capabilities = self.network_capabilities.clone();
 
         Box::new(
             SubgraphManifest::resolve(hash.to_ipfs_link(), self.resolver.clone())
                 .map_err(SubgraphRegistrarError::ResolveError)
                 .and_then(validation::validate_manifest)
+                .and_then(move |manifest| {
+                    validation::validate_network_capabilities(manifest, &network_capabilities)
+                })
                 .and_then(move |manifest| {
                     create_subgraph_version(
                         &logger,
                         store,
-                        chain_store,
+                        chain_stores,
                         name,
                         manifest,
                         node_id,
@@ -267,6 +268,22 @@ where
         )
     }
 
+    fn validate_subgraph_version(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Box<Future<Item = (), Error = SubgraphRegistrarError> + Send + 'static> {
+        let network_capabilities = self.network_capabilities.clone();
+
+        Box::new(
+            SubgraphManifest::resolve(hash.to_ipfs_link(), self.resolver.clone())
+                .map_err(SubgraphRegistrarError::ResolveError)
+                .and_then(move |manifest| {
+                    validation::validate_deployment(manifest, &network_capabilities)
+                })
+                .map(|_| ()),
+        )
+    }
+
     fn remove_subgraph(
         &self,
         name: SubgraphName,
@@ -303,6 +320,223 @@ where
                 }),
         )
     }
+
+    fn rewind_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+        block_ptr: EthereumBlockPointer,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Box<Future<Item = (), Error = SubgraphRegistrarError> + Send + 'static> {
+        let logger = self.logger.clone();
+        let store = self.store.clone();
+        let chain_stores = self.chain_stores.clone();
+
+        if let Some(idempotency_key) = &idempotency_key {
+            match recorded_result(&*store, idempotency_key) {
+                Ok(Some(())) => return Box::new(future::ok(())),
+                Ok(None) => (),
+                Err(e) => return Box::new(future::err(e)),
+            }
+        }
+
+        // The manifest tells us which network, and therefore which chain
+        // store, the deployment's blocks come from
+        Box::new(
+            SubgraphManifest::resolve(hash.to_ipfs_link(), self.resolver.clone())
+                .map_err(SubgraphRegistrarError::ResolveError)
+                .and_then(move |manifest| {
+                    let network_name = manifest.network_name();
+                    let chain_store = chain_stores
+                        .get(network_name.as_ref().map(String::as_str))
+                        .map_err(SubgraphRegistrarError::NetworkError)?;
+                    rewind_subgraph(
+                        &logger,
+                        store,
+                        chain_store,
+                        hash,
+                        block_ptr,
+                        idempotency_key,
+                    )
+                }),
+        )
+    }
+
+    fn list_assignments(
+        &self,
+        node_id: Option<NodeId>,
+    ) -> Box<
+        Future<Item = Vec<(SubgraphDeploymentId, NodeId)>, Error = SubgraphRegistrarError>
+            + Send
+            + 'static,
+    > {
+        let mut query = SubgraphDeploymentAssignmentEntity::query();
+        if let Some(node_id) = node_id {
+            query = query.filter(EntityFilter::new_equal("nodeId", node_id.to_string()));
+        }
+
+        Box::new(future::result(self.store.find(query)).from_err().and_then(
+            |assignment_entities| {
+                assignment_entities
+                    .into_iter()
+                    .map(|entity| {
+                        let id = entity.id()?;
+                        let node_id = entity
+                            .get("nodeId")
+                            .and_then(|node_id| node_id.clone().as_string())
+                            .ok_or_else(|| {
+                                format_err!("Assignment entity without node ID: {}", id)
+                            })?;
+                        let hash = SubgraphDeploymentId::new(id.clone()).map_err(|()| {
+                            format_err!("Invalid subgraph hash in assignment entity: {}", id)
+                        })?;
+                        let node_id = NodeId::new(node_id.clone()).map_err(|()| {
+                            format_err!("Invalid node ID in assignment entity: {}", node_id)
+                        })?;
+                        Ok((hash, node_id))
+                    })
+                    .collect::<Result<_, Error>>()
+                    .map_err(SubgraphRegistrarError::from)
+            },
+        ))
+    }
+
+    fn set_deployment_enabled(
+        &self,
+        hash: SubgraphDeploymentId,
+        queries_enabled: Option<bool>,
+        indexing_enabled: Option<bool>,
+    ) -> Box<Future<Item = (), Error = SubgraphRegistrarError> + Send + 'static> {
+        Box::new(future::result(set_deployment_enabled(
+            &self.logger,
+            self.store.clone(),
+            hash,
+            queries_enabled,
+            indexing_enabled,
+        )))
+    }
+
+    fn reassign_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+        node_id: NodeId,
+    ) -> Box<Future<Item = (), Error = SubgraphRegistrarError> + Send + 'static> {
+        Box::new(future::result(reassign_subgraph(
+            &self.logger,
+            self.store.clone(),
+            hash,
+            node_id,
+        )))
+    }
+
+    fn apply_operations(
+        &self,
+        operations: Vec<SubgraphRegistrarOperation>,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Box<
+        Future<Item = Vec<Option<CreateSubgraphResult>>, Error = SubgraphRegistrarError>
+            + Send
+            + 'static,
+    > {
+        if let Err(e) = validate_batch(&operations) {
+            return Box::new(future::err(e));
+        }
+
+        if let Some(idempotency_key) = &idempotency_key {
+            match recorded_result(&*self.store, idempotency_key) {
+                Ok(Some(results)) => return Box::new(future::ok(results)),
+                Ok(None) => (),
+                Err(e) => return Box::new(future::err(e)),
+            }
+        }
+
+        let logger = self.logger.clone();
+        let resolver = self.resolver.clone();
+        let store = self.store.clone();
+        let chain_stores = self.chain_stores.clone();
+        let network_capabilities = self.network_capabilities.clone();
+        let version_switching_mode = self.version_switching_mode;
+
+        // Resolve and validate all manifests before generating any entity
+        // operations, so that an invalid deployment fails the entire batch
+        let hashes = operations
+            .iter()
+            .filter_map(|operation| match operation {
+                SubgraphRegistrarOperation::Deploy { hash, .. } => Some(hash.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        Box::new(
+            stream::futures_ordered(hashes.into_iter().map(move |hash| {
+                let network_capabilities = network_capabilities.clone();
+                SubgraphManifest::resolve(hash.to_ipfs_link(), resolver.clone())
+                    .map_err(SubgraphRegistrarError::ResolveError)
+                    .and_then(validation::validate_manifest)
+                    .and_then(move |manifest| {
+                        validation::validate_network_capabilities(manifest, &network_capabilities)
+                    })
+            }))
+            .collect()
+            .and_then(move |manifests| {
+                apply_operations(
+                    &logger,
+                    store,
+                    chain_stores,
+                    operations,
+                    manifests,
+                    version_switching_mode,
+                    idempotency_key,
+                )
+            }),
+        )
+    }
+}
+
+/// The event for a deployment whose assignment was added or updated: it
+/// is started if it is assigned to this node and its indexing is enabled,
+/// and removed from this node otherwise. A failed lookup of the assignment
+/// or of whether indexing is disabled is an error rather than a guess.
+fn assignment_set_event(
+    store: &impl Store,
+    node_id: &NodeId,
+    subgraph_hash: SubgraphDeploymentId,
+) -> Result<Option<AssignmentEvent>, Error> {
+    let entity = match store
+        .get(SubgraphDeploymentAssignmentEntity::key(
+            subgraph_hash.clone(),
+        ))
+        .map_err(|e| format_err!("Failed to get subgraph assignment entity: {}", e))?
+    {
+        Some(entity) => entity,
+        // Was added/updated, but is now gone. We will get a separate
+        // Removed event later.
+        None => return Ok(None),
+    };
+    let indexing_disabled = store
+        .is_deployment_indexing_disabled(&subgraph_hash)
+        .map_err(|e| {
+            format_err!(
+                "Failed to check whether indexing of {} is disabled: {}",
+                subgraph_hash,
+                e
+            )
+        })?;
+
+    Ok(Some(
+        if entity.get("nodeId") == Some(&node_id.to_string().into()) && !indexing_disabled {
+            // Start subgraph on this node
+            AssignmentEvent::Add {
+                subgraph_id: subgraph_hash,
+                node_id: node_id.clone(),
+            }
+        } else {
+            // Ensure it is removed from this node
+            AssignmentEvent::Remove {
+                subgraph_id: subgraph_hash,
+                node_id: node_id.clone(),
+            }
+        },
+    ))
 }
 
 fn handle_assignment_event<P>(
@@ -369,6 +603,22 @@ fn create_subgraph(
     store: Arc<impl Store>,
     name: SubgraphName,
 ) -> Result<CreateSubgraphResult, SubgraphRegistrarError> {
+    let (entity_id, ops) = create_subgraph_operations(logger, store.clone(), name.clone())?;
+
+    store.apply_entity_operations(ops, EventSource::None)?;
+
+    debug!(logger, "Created subgraph"; "subgraph_name" => name.to_string());
+
+    Ok(CreateSubgraphResult { id: entity_id })
+}
+
+/// Returns the ID of the new subgraph entity along with the operations that
+/// create it.
+fn create_subgraph_operations(
+    logger: &Logger,
+    store: Arc<impl Store>,
+    name: SubgraphName,
+) -> Result<(String, Vec<EntityOperation>), SubgraphRegistrarError> {
     let mut ops = vec![];
 
     // Check if this subgraph already exists
@@ -398,60 +648,140 @@ fn create_subgraph(
     let entity_id = generate_entity_id();
     ops.extend(entity.write_operations(&entity_id));
 
-    store.apply_entity_operations(ops, EventSource::None)?;
-
-    debug!(logger, "Created subgraph"; "subgraph_name" => name.to_string());
-
-    Ok(CreateSubgraphResult { id: entity_id })
+    Ok((entity_id, ops))
 }
 
 fn create_subgraph_version(
     logger: &Logger,
     store: Arc<impl Store>,
-    chain_store: Arc<impl ChainStore>,
+    chain_stores: EthereumNetworks<impl ChainStore>,
     name: SubgraphName,
     manifest: SubgraphManifest,
     node_id: NodeId,
     version_switching_mode: SubgraphVersionSwitchingMode,
 ) -> Result<(), SubgraphRegistrarError> {
+    let ops = create_subgraph_version_operations(
+        logger,
+        store.clone(),
+        chain_stores,
+        name.clone(),
+        &manifest,
+        node_id,
+        version_switching_mode,
+        None,
+    )?;
+
+    // Commit entity ops
+    store.apply_entity_operations(ops, EventSource::None)?;
+
+    debug!(
+        logger,
+        "Wrote new subgraph version to store";
+        "subgraph_name" => name.to_string(),
+        "subgraph_hash" => manifest.id.to_string()
+    );
+
+    Ok(())
+}
+
+/// Returns the operations that add a new version to a subgraph. If the
+/// subgraph is created in the same transaction, `new_subgraph_entity_id`
+/// is the ID of its entity.
+fn create_subgraph_version_operations(
+    logger: &Logger,
+    store: Arc<impl Store>,
+    chain_stores: EthereumNetworks<impl ChainStore>,
+    name: SubgraphName,
+    manifest: &SubgraphManifest,
+    node_id: NodeId,
+    version_switching_mode: SubgraphVersionSwitchingMode,
+    new_subgraph_entity_id: Option<String>,
+) -> Result<Vec<EntityOperation>, SubgraphRegistrarError> {
     let mut ops = vec![];
 
-    // Look up subgraph entity by name
-    let subgraph_entity_opt = store.find_one(
-        SubgraphEntity::query().filter(EntityFilter::new_equal("name", name.to_string())),
-    )?;
-    let subgraph_entity = subgraph_entity_opt.ok_or_else(|| {
-        debug!(
+    // Subgraphs that predate `features` don't declare any; deploy them, but
+    // point out what they should declare
+    let undeclared_features = manifest.undeclared_features();
+    if !undeclared_features.is_empty() {
+        warn!(
             logger,
-            "Subgraph not found, could not create_subgraph_version";
-            "subgraph_name" => name.to_string()
+            "Subgraph uses features that its manifest doesn't declare";
+            "subgraph_hash" => manifest.id.to_string(),
+            "features" => undeclared_features.join(", "),
         );
-        SubgraphRegistrarError::NameNotFound(name.to_string())
-    })?;
-    let subgraph_entity_id = subgraph_entity.id()?;
-    let current_version_id_opt = match subgraph_entity.get("currentVersion") {
-        Some(Value::String(current_version_id)) => Some(current_version_id.to_owned()),
-        Some(Value::Null) => None,
-        None => None,
-        Some(_) => panic!("subgraph entity has invalid type in currentVersion field"),
-    };
-    let pending_version_id_opt = match subgraph_entity.get("pendingVersion") {
-        Some(Value::String(pending_version_id)) => Some(pending_version_id.to_owned()),
-        Some(Value::Null) => None,
-        None => None,
-        Some(_) => panic!("subgraph entity has invalid type in pendingVersion field"),
-    };
-    ops.push(EntityOperation::AbortUnless {
-        description:
-            "Subgraph entity must still exist, have same name/currentVersion/pendingVersion"
-                .to_owned(),
-        query: SubgraphEntity::query().filter(EntityFilter::And(vec![
-            EntityFilter::new_equal("name", name.to_string()),
-            EntityFilter::new_equal("currentVersion", current_version_id_opt.clone()),
-            EntityFilter::new_equal("pendingVersion", pending_version_id_opt.clone()),
-        ])),
-        entity_ids: vec![subgraph_entity_id.clone()],
-    });
+    }
+
+    // The subgraph can only be deployed if the node indexes its network
+    let network_name = manifest.network_name();
+    let chain_store = chain_stores
+        .get(network_name.as_ref().map(String::as_str))
+        .map_err(SubgraphRegistrarError::NetworkError)?;
+
+    // Subgraph data sources can only consume deployments that exist
+    for base_id in manifest
+        .data_sources
+        .iter()
+        .filter_map(|data_source| data_source.source.subgraph.as_ref())
+    {
+        if store
+            .get(SubgraphDeploymentEntity::key(base_id.clone()))?
+            .is_none()
+        {
+            return Err(SubgraphRegistrarError::DeploymentNotFound(
+                base_id.to_string(),
+            ));
+        }
+    }
+
+    let (subgraph_entity_id, current_version_id_opt, pending_version_id_opt) =
+        match new_subgraph_entity_id {
+            // A subgraph that doesn't exist yet has no versions
+            Some(subgraph_entity_id) => (subgraph_entity_id, None, None),
+            None => {
+                // Look up subgraph entity by name
+                let subgraph_entity_opt = store.find_one(
+                    SubgraphEntity::query()
+                        .filter(EntityFilter::new_equal("name", name.to_string())),
+                )?;
+                let subgraph_entity = subgraph_entity_opt.ok_or_else(|| {
+                    debug!(
+                        logger,
+                        "Subgraph not found, could not create_subgraph_version";
+                        "subgraph_name" => name.to_string()
+                    );
+                    SubgraphRegistrarError::NameNotFound(name.to_string())
+                })?;
+                let subgraph_entity_id = subgraph_entity.id()?;
+                let current_version_id_opt = match subgraph_entity.get("currentVersion") {
+                    Some(Value::String(current_version_id)) => Some(current_version_id.to_owned()),
+                    Some(Value::Null) => None,
+                    None => None,
+                    Some(_) => panic!("subgraph entity has invalid type in currentVersion field"),
+                };
+                let pending_version_id_opt = match subgraph_entity.get("pendingVersion") {
+                    Some(Value::String(pending_version_id)) => Some(pending_version_id.to_owned()),
+                    Some(Value::Null) => None,
+                    None => None,
+                    Some(_) => panic!("subgraph entity has invalid type in pendingVersion field"),
+                };
+                ops.push(EntityOperation::AbortUnless {
+                    description: "Subgraph entity must still exist, have same \
+                                  name/currentVersion/pendingVersion"
+                        .to_owned(),
+                    query: SubgraphEntity::query().filter(EntityFilter::And(vec![
+                        EntityFilter::new_equal("name", name.to_string()),
+                        EntityFilter::new_equal("currentVersion", current_version_id_opt.clone()),
+                        EntityFilter::new_equal("pendingVersion", pending_version_id_opt.clone()),
+                    ])),
+                    entity_ids: vec![subgraph_entity_id.clone()],
+                });
+                (
+                    subgraph_entity_id,
+                    current_version_id_opt,
+                    pending_version_id_opt,
+                )
+            }
+        };
 
     // Look up current version's deployment hash
     let current_version_hash_opt = match current_version_id_opt {
@@ -667,17 +997,7 @@ fn create_subgraph_version(
         }
     }
 
-    // Commit entity ops
-    store.apply_entity_operations(ops, EventSource::None)?;
-
-    debug!(
-        logger,
-        "Wrote new subgraph version to store";
-        "subgraph_name" => name.to_string(),
-        "subgraph_hash" => manifest.id.to_string()
-    );
-
-    Ok(())
+    Ok(ops)
 }
 
 fn get_subgraph_version_deployment_id(
@@ -700,11 +1020,25 @@ fn get_subgraph_version_deployment_id(
     .unwrap())
 }
 
-fn remove_subgraph(
+pub fn remove_subgraph(
     logger: &Logger,
     store: Arc<impl Store>,
     name: SubgraphName,
 ) -> Result<(), SubgraphRegistrarError> {
+    let ops = remove_subgraph_operations(logger, store.clone(), name.clone())?;
+
+    store.apply_entity_operations(ops, EventSource::None)?;
+
+    debug!(logger, "Removed subgraph"; "subgraph_name" => name.to_string());
+
+    Ok(())
+}
+
+fn remove_subgraph_operations(
+    logger: &Logger,
+    store: Arc<impl Store>,
+    name: SubgraphName,
+) -> Result<Vec<EntityOperation>, SubgraphRegistrarError> {
     let mut ops = vec![];
 
     // Find the subgraph entity
@@ -749,13 +1083,479 @@ fn remove_subgraph(
         key: SubgraphEntity::key(subgraph_entity.id()?),
     });
 
+    Ok(ops)
+}
+
+fn set_deployment_enabled(
+    logger: &Logger,
+    store: Arc<impl Store>,
+    hash: SubgraphDeploymentId,
+    queries_enabled: Option<bool>,
+    indexing_enabled: Option<bool>,
+) -> Result<(), SubgraphRegistrarError> {
+    let ops = set_deployment_enabled_operations(
+        store.clone(),
+        hash.clone(),
+        queries_enabled,
+        indexing_enabled,
+    )?;
+
     store.apply_entity_operations(ops, EventSource::None)?;
 
-    debug!(logger, "Removed subgraph"; "subgraph_name" => name.to_string());
+    debug!(
+        logger, "Updated subgraph deployment";
+        "subgraph_id" => hash.to_string(),
+        "queries_enabled" => format!("{:?}", queries_enabled),
+        "indexing_enabled" => format!("{:?}", indexing_enabled),
+    );
+
+    Ok(())
+}
+
+fn set_deployment_enabled_operations(
+    store: Arc<impl Store>,
+    hash: SubgraphDeploymentId,
+    queries_enabled: Option<bool>,
+    indexing_enabled: Option<bool>,
+) -> Result<Vec<EntityOperation>, SubgraphRegistrarError> {
+    if store
+        .get(SubgraphDeploymentEntity::key(hash.clone()))?
+        .is_none()
+    {
+        return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()));
+    }
+
+    let mut ops = vec![];
+
+    // Abort unless the deployment still exists
+    ops.push(EntityOperation::AbortUnless {
+        description: "Subgraph deployment entity must still exist".to_owned(),
+        query: SubgraphDeploymentEntity::query()
+            .filter(EntityFilter::new_equal("id", hash.to_string())),
+        entity_ids: vec![hash.to_string()],
+    });
+
+    if let Some(queries_enabled) = queries_enabled {
+        ops.extend(
+            SubgraphDeploymentEntity::update_queries_disabled_operations(&hash, !queries_enabled),
+        );
+    }
+
+    if let Some(indexing_enabled) = indexing_enabled {
+        ops.extend(
+            SubgraphDeploymentEntity::update_indexing_disabled_operations(&hash, !indexing_enabled),
+        );
+
+        // Rewrite the assignment, if there is one, so that the node it is
+        // assigned to sees an assignment event and starts or stops indexing
+        if let Some(assignment) =
+            store.get(SubgraphDeploymentAssignmentEntity::key(hash.clone()))?
+        {
+            ops.push(EntityOperation::Set {
+                key: SubgraphDeploymentAssignmentEntity::key(hash.clone()),
+                data: assignment,
+            });
+        }
+    }
+
+    Ok(ops)
+}
+
+pub fn reassign_subgraph(
+    logger: &Logger,
+    store: Arc<impl Store>,
+    hash: SubgraphDeploymentId,
+    node_id: NodeId,
+) -> Result<(), SubgraphRegistrarError> {
+    let ops = reassign_subgraph_operations(store.clone(), hash.clone(), node_id.clone())?;
+
+    store.apply_entity_operations(ops, EventSource::None)?;
+
+    debug!(
+        logger, "Reassigned subgraph deployment";
+        "subgraph_id" => hash.to_string(),
+        "node_id" => node_id.to_string(),
+    );
 
     Ok(())
 }
 
+fn reassign_subgraph_operations(
+    store: Arc<impl Store>,
+    hash: SubgraphDeploymentId,
+    node_id: NodeId,
+) -> Result<Vec<EntityOperation>, SubgraphRegistrarError> {
+    if store
+        .get(SubgraphDeploymentEntity::key(hash.clone()))?
+        .is_none()
+    {
+        return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()));
+    }
+
+    let mut ops = vec![];
+
+    // Abort unless the deployment still exists
+    ops.push(EntityOperation::AbortUnless {
+        description: "Subgraph deployment entity must still exist".to_owned(),
+        query: SubgraphDeploymentEntity::query()
+            .filter(EntityFilter::new_equal("id", hash.to_string())),
+        entity_ids: vec![hash.to_string()],
+    });
+
+    // Overwriting the assignment sends an assignment event to all nodes;
+    // the old node stops indexing the deployment and the new one starts
+    ops.extend(SubgraphDeploymentAssignmentEntity::new(node_id).write_operations(&hash));
+
+    Ok(ops)
+}
+
+/// Removes the assignment of a subgraph deployment, so that no node indexes
+/// it until it is assigned again.
+pub fn unassign_subgraph(
+    logger: &Logger,
+    store: Arc<impl Store>,
+    hash: SubgraphDeploymentId,
+) -> Result<(), SubgraphRegistrarError> {
+    if store
+        .get(SubgraphDeploymentAssignmentEntity::key(hash.clone()))?
+        .is_none()
+    {
+        return Err(SubgraphRegistrarError::DeploymentAssignmentNotFound(
+            hash.to_string(),
+        ));
+    }
+
+    store.apply_entity_operations(
+        vec![EntityOperation::Remove {
+            key: SubgraphDeploymentAssignmentEntity::key(hash.clone()),
+        }],
+        EventSource::None,
+    )?;
+
+    debug!(
+        logger, "Unassigned subgraph deployment";
+        "subgraph_id" => hash.to_string(),
+    );
+
+    Ok(())
+}
+
+fn rewind_subgraph(
+    logger: &Logger,
+    store: Arc<impl Store>,
+    chain_store: Arc<impl ChainStore>,
+    hash: SubgraphDeploymentId,
+    block_ptr: EthereumBlockPointer,
+    idempotency_key: Option<IdempotencyKey>,
+) -> Result<(), SubgraphRegistrarError> {
+    if store
+        .get(SubgraphDeploymentEntity::key(hash.clone()))?
+        .is_none()
+    {
+        return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()));
+    }
+
+    // Rewinding while the deployment is being indexed would race with the
+    // block stream, which also moves the block pointer
+    if !store.is_deployment_indexing_disabled(&hash)? {
+        return Err(SubgraphRegistrarError::InvalidRewind(format!(
+            "deployment {} must be paused first",
+            hash
+        )));
+    }
+
+    let current_ptr = store.block_ptr(hash.clone())?;
+    if block_ptr.number >= current_ptr.number {
+        return Err(SubgraphRegistrarError::InvalidRewind(format!(
+            "block #{} is not before the current block #{} of deployment {}",
+            block_ptr.number, current_ptr.number, hash
+        )));
+    }
+
+    // Only blocks that the deployment has processed can be reverted
+    let ancestor = chain_store
+        .ancestor_block(current_ptr, current_ptr.number - block_ptr.number)?
+        .ok_or_else(|| {
+            SubgraphRegistrarError::InvalidRewind(format!(
+                "blocks between #{} and #{} are missing from the chain store",
+                block_ptr.number, current_ptr.number
+            ))
+        })?;
+    if EthereumBlockPointer::from(&ancestor) != block_ptr {
+        return Err(SubgraphRegistrarError::InvalidRewind(format!(
+            "block {} (#{}) is not an ancestor of the current block {} (#{}) of deployment {}",
+            block_ptr.hash_hex(),
+            block_ptr.number,
+            current_ptr.hash_hex(),
+            current_ptr.number,
+            hash
+        )));
+    }
+
+    // Follow the parent hashes from the current block down to the target
+    // block, then revert all blocks in between in a single store
+    // transaction, so an interrupted rewind leaves the deployment untouched
+    let parent_hashes = chain_store
+        .block_pointers_in_range(block_ptr.number, current_ptr.number)?
+        .into_iter()
+        .map(|(ptr, parent_hash)| (ptr.hash, parent_hash))
+        .collect::<HashMap<_, _>>();
+    let mut block_ptrs = vec![current_ptr];
+    let mut ptr = current_ptr;
+    while ptr.number > block_ptr.number {
+        let parent_hash = parent_hashes.get(&ptr.hash).ok_or_else(|| {
+            SubgraphRegistrarError::InvalidRewind(format!(
+                "parent of block {} (#{}) is missing from the chain store",
+                ptr.hash_hex(),
+                ptr.number
+            ))
+        })?;
+        ptr = EthereumBlockPointer {
+            hash: *parent_hash,
+            number: ptr.number - 1,
+        };
+        block_ptrs.push(ptr);
+    }
+    let ops = match idempotency_key {
+        Some(idempotency_key) => record_result_operations(&*store, idempotency_key, &())?,
+        None => vec![],
+    };
+    store.revert_block_range(hash.clone(), block_ptrs, ops)?;
+
+    info!(
+        logger, "Rewound subgraph deployment";
+        "subgraph_id" => hash.to_string(),
+        "from_block_number" => current_ptr.number,
+        "to_block_number" => block_ptr.number,
+        "to_block_hash" => block_ptr.hash_hex(),
+    );
+
+    Ok(())
+}
+
+/// Checks that the operations of a batch don't depend on each other, except
+/// for deploying to a subgraph that is created earlier in the batch. The
+/// operations of a batch are all generated from the store before any of
+/// them are applied, so they can't observe each other's effects.
+fn validate_batch(operations: &[SubgraphRegistrarOperation]) -> Result<(), SubgraphRegistrarError> {
+    let mut created_names = HashSet::new();
+    let mut names = HashSet::new();
+    let mut hashes = HashSet::new();
+
+    for operation in operations {
+        let error = match operation {
+            SubgraphRegistrarOperation::Create { name } => {
+                if names.contains(name) || !created_names.insert(name) {
+                    Some(format!(
+                        "subgraph {} must be created once and before it is deployed",
+                        name
+                    ))
+                } else {
+                    None
+                }
+            }
+            SubgraphRegistrarOperation::Deploy { name, hash, .. } => {
+                if !names.insert(name) {
+                    Some(format!(
+                        "subgraph {} is deployed or removed more than once",
+                        name
+                    ))
+                } else if !hashes.insert(hash) {
+                    Some(format!("deployment {} is used more than once", hash))
+                } else {
+                    None
+                }
+            }
+            SubgraphRegistrarOperation::Remove { name } => {
+                if created_names.contains(name) || !names.insert(name) {
+                    Some(format!(
+                        "subgraph {} is removed but also created or deployed",
+                        name
+                    ))
+                } else {
+                    None
+                }
+            }
+            SubgraphRegistrarOperation::SetEnabled { hash, .. }
+            | SubgraphRegistrarOperation::Reassign { hash, .. } => {
+                if !hashes.insert(hash) {
+                    Some(format!("deployment {} is used more than once", hash))
+                } else {
+                    None
+                }
+            }
+        };
+        if let Some(error) = error {
+            return Err(SubgraphRegistrarError::InvalidBatch(error));
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_operations(
+    logger: &Logger,
+    store: Arc<impl Store>,
+    chain_stores: EthereumNetworks<impl ChainStore>,
+    operations: Vec<SubgraphRegistrarOperation>,
+    manifests: Vec<SubgraphManifest>,
+    version_switching_mode: SubgraphVersionSwitchingMode,
+    idempotency_key: Option<IdempotencyKey>,
+) -> Result<Vec<Option<CreateSubgraphResult>>, SubgraphRegistrarError> {
+    let mut manifests = manifests.into_iter();
+    let mut created_subgraph_ids = HashMap::new();
+    let mut results = vec![];
+    let mut ops = vec![];
+
+    for operation in operations {
+        match operation {
+            SubgraphRegistrarOperation::Create { name } => {
+                let (entity_id, create_ops) =
+                    create_subgraph_operations(logger, store.clone(), name.clone())?;
+                ops.extend(create_ops);
+                created_subgraph_ids.insert(name, entity_id.clone());
+                results.push(Some(CreateSubgraphResult { id: entity_id }));
+            }
+            SubgraphRegistrarOperation::Deploy { name, node_id, .. } => {
+                let manifest = manifests
+                    .next()
+                    .expect("a manifest is resolved for every deploy operation");
+                let new_subgraph_entity_id = created_subgraph_ids.get(&name).cloned();
+                ops.extend(create_subgraph_version_operations(
+                    logger,
+                    store.clone(),
+                    chain_stores.clone(),
+                    name,
+                    &manifest,
+                    node_id,
+                    version_switching_mode,
+                    new_subgraph_entity_id,
+                )?);
+                results.push(None);
+            }
+            SubgraphRegistrarOperation::Remove { name } => {
+                ops.extend(remove_subgraph_operations(logger, store.clone(), name)?);
+                results.push(None);
+            }
+            SubgraphRegistrarOperation::SetEnabled {
+                hash,
+                queries_enabled,
+                indexing_enabled,
+            } => {
+                ops.extend(set_deployment_enabled_operations(
+                    store.clone(),
+                    hash,
+                    queries_enabled,
+                    indexing_enabled,
+                )?);
+                results.push(None);
+            }
+            SubgraphRegistrarOperation::Reassign { hash, node_id } => {
+                ops.extend(reassign_subgraph_operations(store.clone(), hash, node_id)?);
+                results.push(None);
+            }
+        }
+    }
+
+    if let Some(idempotency_key) = idempotency_key {
+        ops.extend(record_result_operations(
+            &*store,
+            idempotency_key,
+            &results,
+        )?);
+    }
+
+    store.apply_entity_operations(ops, EventSource::None)?;
+
+    debug!(logger, "Applied batch of subgraph operations"; "operations" => results.len());
+
+    Ok(results)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// The result that was recorded for the request with the idempotency key,
+/// if the key was used before and hasn't expired yet.
+fn recorded_result<T: DeserializeOwned>(
+    store: &impl Store,
+    idempotency_key: &IdempotencyKey,
+) -> Result<Option<T>, SubgraphRegistrarError> {
+    let entity = match store.get(SubgraphIdempotencyKeyEntity::key(
+        idempotency_key.key.clone(),
+    ))? {
+        Some(entity) => entity,
+        None => return Ok(None),
+    };
+
+    let expires_at = entity
+        .get("expiresAt")
+        .and_then(|value| value.clone().as_bigint())
+        .map(|expires_at| expires_at.to_u64())
+        .ok_or_else(|| format_err!("Idempotency key without expiry: {}", idempotency_key.key))?;
+    if expires_at <= now() {
+        return Ok(None);
+    }
+
+    let request = entity
+        .get("request")
+        .and_then(|value| value.clone().as_string());
+    if request.as_ref() != Some(&idempotency_key.request) {
+        return Err(SubgraphRegistrarError::IdempotencyKeyReused(
+            idempotency_key.key.clone(),
+        ));
+    }
+
+    let result = entity
+        .get("result")
+        .and_then(|value| value.clone().as_string())
+        .ok_or_else(|| format_err!("Idempotency key without result: {}", idempotency_key.key))?;
+    serde_json::from_str(&result)
+        .map(Some)
+        .map_err(|e| SubgraphRegistrarError::from(Error::from(e)))
+}
+
+/// Records the result of the request with the idempotency key, and removes
+/// the keys that have expired. Applying the operations fails if another
+/// request recorded the same key in the meantime.
+fn record_result_operations(
+    store: &impl Store,
+    idempotency_key: IdempotencyKey,
+    result: &impl serde::Serialize,
+) -> Result<Vec<EntityOperation>, SubgraphRegistrarError> {
+    let now = now();
+    let result = serde_json::to_string(result).map_err(Error::from)?;
+
+    let mut ops: Vec<_> = store
+        .find(
+            SubgraphIdempotencyKeyEntity::query().filter(EntityFilter::LessOrEqual(
+                "expiresAt".to_owned(),
+                now.into(),
+            )),
+        )?
+        .into_iter()
+        .map(|entity| {
+            Ok(EntityOperation::Remove {
+                key: SubgraphIdempotencyKeyEntity::key(entity.id()?),
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+
+    ops.extend(
+        SubgraphIdempotencyKeyEntity::new(
+            idempotency_key.request,
+            result,
+            now + IDEMPOTENCY_KEY_TTL.as_secs(),
+        )
+        .create_operations(&idempotency_key.key, now),
+    );
+    Ok(ops)
+}
+
 /// Remove a set of subgraph versions atomically.
 ///
 /// It may seem like it would be easier to generate the EntityOperations for subgraph versions
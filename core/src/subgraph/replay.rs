@@ -0,0 +1,344 @@
+use graph::data::subgraph::schema::{DynamicEthereumContractDataSourceEntity, SUBGRAPHS_ID};
+use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
+use graph::web3::types::H256;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::Mutex;
+
+use super::SubgraphInstance;
+
+/// A store for replaying a subgraph deployment. The entities of the
+/// deployment are kept in memory, starting out empty, so that mappings see
+/// the same state they saw when the deployment was indexed; everything else
+/// is read from the underlying store. Replaying never writes to the
+/// underlying store.
+pub struct ReplayStore<S> {
+    store: Arc<S>,
+    subgraph_id: SubgraphDeploymentId,
+    entities: Mutex<BTreeMap<EntityKey, Entity>>,
+}
+
+impl<S> ReplayStore<S>
+where
+    S: Store,
+{
+    pub fn new(store: Arc<S>, subgraph_id: SubgraphDeploymentId) -> Self {
+        ReplayStore {
+            store,
+            subgraph_id,
+            entities: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn apply(&self, operations: Vec<EntityOperation>) -> Result<(), StoreError> {
+        let mut entities = self.entities.lock().unwrap();
+        for operation in operations {
+            let key = operation.entity_key().clone();
+
+            // The data sources that the mappings create are in the manifest
+            // of the replay from the start
+            if key.subgraph_id == *SUBGRAPHS_ID
+                && key.entity_type == DynamicEthereumContractDataSourceEntity::TYPENAME
+            {
+                continue;
+            }
+            if key.subgraph_id != self.subgraph_id {
+                return Err(format_err!(
+                    "cannot write entities of subgraph `{}` while replaying `{}`",
+                    key.subgraph_id,
+                    self.subgraph_id
+                )
+                .into());
+            }
+            match operation.apply(entities.remove(&key))? {
+                Some(entity) => entities.insert(key, entity),
+                None => None,
+            };
+        }
+        Ok(())
+    }
+
+    fn unsupported<T>(&self, what: &str) -> Result<T, StoreError> {
+        Err(format_err!("{} is not supported while replaying a subgraph", what).into())
+    }
+}
+
+impl<S> Store for ReplayStore<S>
+where
+    S: Store,
+{
+    fn block_ptr(&self, subgraph_id: SubgraphDeploymentId) -> Result<EthereumBlockPointer, Error> {
+        self.store.block_ptr(subgraph_id)
+    }
+
+    fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        if key.subgraph_id == self.subgraph_id {
+            Ok(self.entities.lock().unwrap().get(&key).cloned())
+        } else {
+            self.store.get(key)
+        }
+    }
+
+    fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        if query.subgraph_id == self.subgraph_id {
+            Err(QueryExecutionError::StoreError(format_err!(
+                "queries are not supported while replaying a subgraph"
+            )))
+        } else {
+            self.store.find(query)
+        }
+    }
+
+    fn find_one(&self, query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
+        if query.subgraph_id == self.subgraph_id {
+            Err(QueryExecutionError::StoreError(format_err!(
+                "queries are not supported while replaying a subgraph"
+            )))
+        } else {
+            self.store.find_one(query)
+        }
+    }
+
+    fn snapshot_at_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_number: u64,
+    ) -> Result<Box<EntitySnapshot>, QueryExecutionError> {
+        if *subgraph_id == self.subgraph_id {
+            Err(QueryExecutionError::StoreError(format_err!(
+                "queries are not supported while replaying a subgraph"
+            )))
+        } else {
+            self.store.snapshot_at_block(subgraph_id, block_number)
+        }
+    }
+
+    fn set_block_ptr_with_no_changes(
+        &self,
+        _: SubgraphDeploymentId,
+        _: EthereumBlockPointer,
+        _: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        self.unsupported("moving the block pointer")
+    }
+
+    fn transact_block_operations(
+        &self,
+        _: SubgraphDeploymentId,
+        _: EthereumBlockPointer,
+        _: EthereumBlockPointer,
+        operations: Vec<EntityOperation>,
+    ) -> Result<(), StoreError> {
+        self.apply(operations)
+    }
+
+    fn apply_entity_operations(
+        &self,
+        operations: Vec<EntityOperation>,
+        _: EventSource,
+    ) -> Result<(), StoreError> {
+        self.apply(operations)
+    }
+
+    fn build_entity_attribute_indexes(
+        &self,
+        _: Vec<AttributeIndexDefinition>,
+    ) -> Result<(), SubgraphAssignmentProviderError> {
+        Ok(())
+    }
+
+    fn revert_block_operations(
+        &self,
+        _: SubgraphDeploymentId,
+        _: EthereumBlockPointer,
+        _: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        self.unsupported("reverting blocks")
+    }
+
+    fn revert_block_range(
+        &self,
+        _: SubgraphDeploymentId,
+        _: Vec<EthereumBlockPointer>,
+        _: Vec<EntityOperation>,
+    ) -> Result<(), StoreError> {
+        self.unsupported("reverting blocks")
+    }
+
+    fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
+        self.store.subscribe(entities)
+    }
+
+    fn count_entities(&self, subgraph: SubgraphDeploymentId) -> Result<u64, Error> {
+        if subgraph == self.subgraph_id {
+            Ok(self.entities.lock().unwrap().len() as u64)
+        } else {
+            self.store.count_entities(subgraph)
+        }
+    }
+
+    fn block_timestamp(&self, block_hash: H256) -> Result<Option<u64>, Error> {
+        self.store.block_timestamp(block_hash)
+    }
+
+    fn background_jobs(&self) -> Result<Vec<BackgroundJob>, Error> {
+        self.store.background_jobs()
+    }
+
+    fn blocks_with_entity_changes(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: &[String],
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<EthereumBlockPointer>, Error> {
+        self.store
+            .blocks_with_entity_changes(subgraph_id, entity_types, from, to)
+    }
+
+    fn entity_changes_in_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: &[String],
+        block_hash: H256,
+    ) -> Result<Vec<EntityChangeTrigger>, Error> {
+        self.store
+            .entity_changes_in_block(subgraph_id, entity_types, block_hash)
+    }
+
+    fn subgraph_schema(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Arc<Schema>, Error> {
+        self.store.subgraph_schema(subgraph_id)
+    }
+}
+
+/// Replays a subgraph deployment from `blocks` and writes the entity
+/// operations of every block to `output`, one JSON object per line.
+///
+/// The output only depends on the blocks and the mappings, so the output of
+/// two replays can be diffed to find nondeterminism in the mappings or
+/// differences between versions of `graph-node`. Resolves to the number of
+/// blocks that were replayed.
+pub fn replay_subgraph<T, S, W>(
+    logger: &Logger,
+    manifest: SubgraphManifest,
+    host_builder: T,
+    store: Arc<ReplayStore<S>>,
+    blocks: Box<Stream<Item = EthereumBlockWithTriggers, Error = Error> + Send>,
+    output: W,
+) -> Box<Future<Item = u64, Error = Error> + Send>
+where
+    T: RuntimeHostBuilder,
+    S: Store,
+    W: Write + Send + 'static,
+{
+    let logger = logger.new(o!(
+        "component" => "SubgraphReplay",
+        "subgraph_id" => manifest.id.to_string(),
+    ));
+    // Replays don't enforce quotas; they only reproduce what the handlers
+    // did when they ran
+    let quotas = Arc::new(DeploymentQuotas::default());
+    let instance = match SubgraphInstance::from_manifest(&logger, manifest, host_builder, quotas) {
+        Ok(instance) => Arc::new(instance),
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    Box::new(
+        blocks
+            .fold((output, 0), move |(mut output, count), block| {
+                let block_ptr = EthereumBlockPointer::from(&block.ethereum_block);
+                let logger = logger.new(o!(
+                    "block_number" => block_ptr.number,
+                    "block_hash" => block_ptr.hash_hex(),
+                ));
+                debug!(logger, "Replaying block"; "triggers" => block.triggers.len());
+
+                let store = store.clone();
+                instance
+                    .process_triggers(
+                        &logger,
+                        Arc::new(block.ethereum_block),
+                        block.triggers,
+                        vec![],
+                    )
+                    .and_then(move |operations| {
+                        for operation in operations.iter() {
+                            writeln!(output, "{}", operation_json(block_ptr, operation)?)?;
+                        }
+                        store.apply(operations)?;
+                        Ok((output, count + 1))
+                    })
+            })
+            .and_then(|(mut output, count)| {
+                output.flush()?;
+                Ok(count)
+            }),
+    )
+}
+
+/// Describes an entity operation as JSON, with the attributes of the
+/// entity in a stable order.
+fn operation_json(
+    block_ptr: EthereumBlockPointer,
+    operation: &EntityOperation,
+) -> Result<serde_json::Value, Error> {
+    let (kind, key, data) = match operation {
+        EntityOperation::Set { key, data } => ("set", key, Some(data)),
+        EntityOperation::Update { key, data, .. } => ("update", key, Some(data)),
+        EntityOperation::Remove { key } => ("remove", key, None),
+        EntityOperation::AbortUnless { .. } => {
+            bail!(
+                "unexpected entity operation while replaying: {:?}",
+                operation
+            )
+        }
+    };
+    let data = match data {
+        Some(data) => serde_json::to_value(data.iter().collect::<BTreeMap<_, _>>())?,
+        None => serde_json::Value::Null,
+    };
+
+    Ok(json!({
+        "blockNumber": block_ptr.number,
+        "blockHash": block_ptr.hash_hex(),
+        "operation": kind,
+        "entity": key.entity_type,
+        "id": key.entity_id,
+        "data": data,
+    }))
+}
+
+#[test]
+fn operation_json_sorts_attributes() {
+    use graph::web3::types::H256;
+
+    let block_ptr = EthereumBlockPointer::from((H256::zero(), 1u64));
+    let key = EntityKey {
+        subgraph_id: SubgraphDeploymentId::new("QmReplay").unwrap(),
+        entity_type: "User".to_owned(),
+        entity_id: "1".to_owned(),
+    };
+    let operation = EntityOperation::Set {
+        key: key.clone(),
+        data: Entity::from(vec![
+            ("name", Value::from("Alice")),
+            ("id", Value::from("1")),
+            ("age", Value::Int(30)),
+        ]),
+    };
+
+    let json = operation_json(block_ptr, &operation).unwrap();
+    assert_eq!(json["operation"], "set");
+    let data = json["data"].to_string();
+    let attributes = ["\"age\"", "\"id\"", "\"name\""]
+        .iter()
+        .map(|attribute| data.find(attribute).unwrap())
+        .collect::<Vec<_>>();
+    assert!(attributes.windows(2).all(|pair| pair[0] < pair[1]));
+
+    let remove = EntityOperation::Remove { key };
+    assert_eq!(
+        operation_json(block_ptr, &remove).unwrap()["data"],
+        serde_json::Value::Null
+    );
+}
@@ -1,10 +1,275 @@
 use graph::prelude::*;
+use graph::util::ethereum::string_to_h256;
+use graph_graphql::prelude::api_schema;
+use graph_graphql::schema::ast::get_named_type;
+use graph_runtime_wasm::validate_mapping;
+use std::collections::{BTreeSet, HashSet};
 
 pub fn validate_manifest(
     manifest: SubgraphManifest,
 ) -> Result<SubgraphManifest, SubgraphRegistrarError> {
+    let validation_errs = manifest_validation_errors(&manifest);
+    if validation_errs.is_empty() {
+        return Ok(manifest);
+    }
+    return Err(SubgraphRegistrarError::ManifestValidationError(
+        SubgraphManifestValidationErrors(validation_errs),
+    ));
+}
+
+/// Validates a subgraph deployment without deploying it: on top of the
+/// checks of `validate_manifest` and `validate_network_capabilities`, the
+/// schema must produce a valid API schema and the WASM module of every
+/// mapping must be one this node can run. Fails with all problems found.
+pub fn validate_deployment(
+    manifest: SubgraphManifest,
+    network_capabilities: &EthereumNetworks<EthereumNetworkCapabilities>,
+) -> Result<SubgraphManifest, SubgraphRegistrarError> {
+    let mut validation_errs = manifest_validation_errors(&manifest);
+
+    if let Err(e) = api_schema(&manifest.schema.document) {
+        validation_errs.push(SubgraphManifestValidationError::SchemaInvalid(
+            e.to_string(),
+        ))
+    }
+
+    let mappings = manifest
+        .data_sources
+        .iter()
+        .map(|data_source| {
+            (
+                format!("dataSources[{}]", data_source.name),
+                &data_source.mapping,
+            )
+        })
+        .chain(
+            manifest
+                .templates
+                .iter()
+                .map(|template| (format!("templates[{}]", template.name), &template.mapping)),
+        );
+    for (location, mapping) in mappings {
+        for problem in validate_mapping(mapping) {
+            validation_errs.push(SubgraphManifestValidationError::MappingInvalid(
+                location.clone(),
+                problem,
+            ))
+        }
+    }
+
+    let result = validate_network_capabilities(manifest, network_capabilities);
+    if let Err(e) = &result {
+        validation_errs.push(SubgraphManifestValidationError::NetworkNotSupported(
+            e.to_string(),
+        ))
+    }
+
+    if validation_errs.is_empty() {
+        return result;
+    }
+    Err(SubgraphRegistrarError::ManifestValidationError(
+        SubgraphManifestValidationErrors(validation_errs),
+    ))
+}
+
+fn manifest_validation_errors(manifest: &SubgraphManifest) -> Vec<SubgraphManifestValidationError> {
     let mut validation_errs: Vec<SubgraphManifestValidationError> = Vec::new();
 
+    // Validate that external hosts are plain host names
+    for host in manifest.external_hosts.iter() {
+        if host.is_empty()
+            || host
+                .chars()
+                .any(|c| !(c.is_ascii_alphanumeric() || c == '-' || c == '.'))
+        {
+            validation_errs.push(SubgraphManifestValidationError::InvalidExternalHost(
+                host.clone(),
+            ))
+        }
+    }
+
+    // Validate that all data sources are on the same network, which the
+    // node indexes the entire subgraph on
+    let networks = manifest
+        .data_sources
+        .iter()
+        .filter_map(|data_source| data_source.network.as_ref())
+        .collect::<BTreeSet<_>>();
+    if networks.len() > 1 {
+        validation_errs.push(SubgraphManifestValidationError::MultipleNetworks(
+            networks
+                .into_iter()
+                .map(|network| network.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        ))
+    }
+
+    // Validate that the ABI names of each mapping are unique, so that
+    // `ethereum.call` can look up contracts by name
+    let mappings = manifest
+        .data_sources
+        .iter()
+        .map(|data_source| {
+            (
+                format!("dataSources[{}]", data_source.name),
+                &data_source.mapping,
+            )
+        })
+        .chain(
+            manifest
+                .templates
+                .iter()
+                .map(|template| (format!("templates[{}]", template.name), &template.mapping)),
+        );
+    for (location, mapping) in mappings {
+        let mut abi_names = HashSet::new();
+        for abi in mapping.abis.iter() {
+            if !abi_names.insert(&abi.name) {
+                validation_errs.push(SubgraphManifestValidationError::DuplicateAbiName(
+                    location.clone(),
+                    abi.name.clone(),
+                ))
+            }
+        }
+    }
+
+    for data_source in manifest.data_sources.iter() {
+        // Validate that the entities a mapping declares exist in the schema
+        for entity in data_source.mapping.entities.iter() {
+            if get_named_type(&manifest.schema.document, entity).is_none() {
+                validation_errs.push(SubgraphManifestValidationError::EntityNotFoundInSchema(
+                    data_source.name.clone(),
+                    entity.clone(),
+                ))
+            }
+        }
+        for entity in data_source.mapping.reads.iter() {
+            if get_named_type(&manifest.schema.document, entity).is_none() {
+                validation_errs.push(SubgraphManifestValidationError::ReadEntityNotFoundInSchema(
+                    data_source.name.clone(),
+                    entity.clone(),
+                ))
+            }
+        }
+
+        // File and subgraph data sources have no contract, their checks
+        // follow below
+        if !data_source.has_contract() {
+            continue;
+        }
+
+        // Validate that the contract ABI is included in the mapping and that
+        // it defines the events the event handlers are for
+        match data_source
+            .mapping
+            .abis
+            .iter()
+            .find(|abi| abi.name == data_source.source.abi)
+        {
+            None => validation_errs.push(SubgraphManifestValidationError::DataSourceAbiNotFound(
+                data_source.name.clone(),
+                data_source.source.abi.clone(),
+            )),
+            Some(abi) => {
+                for event_handler in data_source.mapping.event_handlers.iter() {
+                    let signature = string_to_h256(&event_handler.event);
+                    if !abi
+                        .contract
+                        .events()
+                        .any(|event| event.signature() == signature)
+                    {
+                        validation_errs.push(SubgraphManifestValidationError::EventNotFoundInAbi(
+                            data_source.name.clone(),
+                            event_handler.event.clone(),
+                            abi.name.clone(),
+                        ))
+                    }
+                }
+            }
+        }
+    }
+
+    // Validate that file and subgraph data sources only have the fields of
+    // their kind, that only they have these, and that they write to entity
+    // types of their own, so that their results never affect chain data
+    // sources
+    let chain_entities = manifest
+        .data_sources
+        .iter()
+        .filter(|data_source| data_source.has_contract())
+        .flat_map(|data_source| data_source.mapping.entities.iter())
+        .collect::<HashSet<_>>();
+    for data_source in manifest.data_sources.iter() {
+        let name = data_source.name.clone();
+        if !data_source.is_file()
+            && (data_source.source.file.is_some() || data_source.mapping.file_handler.is_some())
+        {
+            validation_errs.push(SubgraphManifestValidationError::FileFieldsNotSupported(
+                name.clone(),
+            ))
+        }
+        if !data_source.is_subgraph()
+            && (data_source.source.subgraph.is_some()
+                || !data_source.mapping.entity_handlers.is_empty())
+        {
+            validation_errs.push(SubgraphManifestValidationError::SubgraphFieldsNotSupported(
+                name.clone(),
+            ))
+        }
+        if data_source.has_contract() {
+            continue;
+        }
+
+        if data_source.is_file() {
+            if data_source.source.file.is_none() {
+                validation_errs.push(SubgraphManifestValidationError::FileSourceRequired(
+                    name.clone(),
+                ))
+            }
+            if data_source.mapping.file_handler.is_none() {
+                validation_errs.push(SubgraphManifestValidationError::FileHandlerRequired(
+                    name.clone(),
+                ))
+            }
+        }
+        if data_source.is_subgraph() {
+            match data_source.source.subgraph {
+                None => validation_errs.push(
+                    SubgraphManifestValidationError::SubgraphSourceRequired(name.clone()),
+                ),
+                Some(ref subgraph) if *subgraph == manifest.id => validation_errs.push(
+                    SubgraphManifestValidationError::SubgraphSourceIsSelf(name.clone()),
+                ),
+                Some(_) => (),
+            }
+            if data_source.mapping.entity_handlers.is_empty() {
+                validation_errs.push(SubgraphManifestValidationError::EntityHandlersRequired(
+                    name.clone(),
+                ))
+            }
+        }
+        if data_source.source.address.is_some()
+            || !data_source.source.abi.is_empty()
+            || !data_source.mapping.event_handlers.is_empty()
+            || !data_source.mapping.call_handlers.is_empty()
+            || !data_source.mapping.block_handlers.is_empty()
+        {
+            validation_errs.push(SubgraphManifestValidationError::DataSourceHasContract(
+                name.clone(),
+                data_source.kind.clone(),
+            ))
+        }
+        for entity in data_source.mapping.entities.iter() {
+            if chain_entities.contains(entity) {
+                validation_errs.push(SubgraphManifestValidationError::EntityNotIsolated(
+                    name.clone(),
+                    entity.clone(),
+                ))
+            }
+        }
+    }
+
     // Validate that the manifest has a `source` address in each data source
     // which has call or block handlers
     let has_invalid_data_source = manifest.data_sources.iter().any(|data_source| {
@@ -25,27 +290,217 @@ pub fn validate_manifest(
         }
         let mut non_filtered_block_handler_count = 0;
         let mut call_filtered_block_handler_count = 0;
+        let mut polling_intervals = HashSet::new();
+        let mut has_duplicate_polling_interval = false;
         data_source
             .mapping
             .block_handlers
             .iter()
-            .for_each(|block_handler| {
-                if block_handler.filter.is_none() {
-                    non_filtered_block_handler_count += 1
-                } else {
-                    call_filtered_block_handler_count += 1
+            .for_each(|block_handler| match block_handler.filter {
+                None => non_filtered_block_handler_count += 1,
+                Some(BlockHandlerFilter::Call) => call_filtered_block_handler_count += 1,
+                Some(BlockHandlerFilter::Polling { every }) => {
+                    if !polling_intervals.insert(every) {
+                        has_duplicate_polling_interval = true
+                    }
                 }
             });
-        return non_filtered_block_handler_count > 1 || call_filtered_block_handler_count > 1;
+        return non_filtered_block_handler_count > 1
+            || call_filtered_block_handler_count > 1
+            || has_duplicate_polling_interval;
     });
     if has_too_many_block_handlers {
         validation_errs.push(SubgraphManifestValidationError::DataSourceBlockHandlerLimitExceeded)
     }
 
-    if validation_errs.is_empty() {
-        return Ok(manifest);
+    // Validate that polling block handlers have a non-zero interval
+    let has_invalid_polling_interval = manifest.data_sources.iter().any(|data_source| {
+        data_source
+            .mapping
+            .block_handlers
+            .iter()
+            .any(|block_handler| match block_handler.filter {
+                Some(BlockHandlerFilter::Polling { every }) => every == 0,
+                _ => false,
+            })
+    });
+    if has_invalid_polling_interval {
+        validation_errs.push(SubgraphManifestValidationError::InvalidBlockHandlerPollingInterval)
+    }
+
+    // Validate that data source templates have unique names and that the
+    // ABI of their source is included in their mapping
+    let mut template_names = HashSet::new();
+    for template in manifest.templates.iter() {
+        if !template_names.insert(&template.name) {
+            validation_errs.push(SubgraphManifestValidationError::DuplicateTemplateName(
+                template.name.clone(),
+            ))
+        }
+        if !template
+            .mapping
+            .abis
+            .iter()
+            .any(|abi| abi.name == template.source.abi)
+        {
+            validation_errs.push(SubgraphManifestValidationError::TemplateAbiNotFound(
+                template.name.clone(),
+                template.source.abi.clone(),
+            ))
+        }
+    }
+
+    validation_errs
+}
+
+/// Checks that the Ethereum nodes of the network of the subgraph support
+/// what the subgraph needs, so that a subgraph that can't be indexed is
+/// rejected when it is deployed rather than failing while it is indexed.
+pub fn validate_network_capabilities(
+    manifest: SubgraphManifest,
+    network_capabilities: &EthereumNetworks<EthereumNetworkCapabilities>,
+) -> Result<SubgraphManifest, SubgraphRegistrarError> {
+    let network_name = manifest.network_name();
+    let capabilities = network_capabilities
+        .get(network_name.as_ref().map(String::as_str))
+        .map_err(SubgraphRegistrarError::NetworkError)?;
+
+    // Call handlers and block handlers with a call filter need the calls
+    // in each block
+    let needs_traces = manifest
+        .data_sources
+        .iter()
+        .map(|data_source| &data_source.mapping)
+        .chain(manifest.templates.iter().map(|template| &template.mapping))
+        .any(|mapping| {
+            !mapping.call_handlers.is_empty()
+                || mapping
+                    .block_handlers
+                    .iter()
+                    .any(|handler| handler.filter == Some(BlockHandlerFilter::Call))
+        });
+    if needs_traces && !capabilities.traces() {
+        return Err(SubgraphRegistrarError::NetworkError(
+            EthereumNetworkError::MissingCapability(
+                capabilities.network.clone(),
+                "call tracing (trace_filter or debug_traceBlockByNumber)".to_owned(),
+            ),
+        ));
+    }
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::components::link_resolver::StreamValue;
+    use std::collections::HashMap;
+
+    const MANIFEST: &str = "
+specVersion: 0.0.1
+schema:
+  file:
+    /: /ipfs/schema
+dataSources:
+- kind: ethereum/contract
+  name: Token
+  network: mainnet
+  source:
+    address: \"22843e74c59580b3eaf6c233fa67d8b7c561a835\"
+    abi: Token
+  mapping:
+    kind: ethereum/events
+    apiVersion: 0.0.2
+    language: wasm/assemblyscript
+    entities:
+    - Token
+    abis:
+    - name: Token
+      file:
+        /: /ipfs/abi
+    eventHandlers:
+    - event: Transfer(address,address,uint256)
+      handler: handleTransfer
+    file:
+      /: /ipfs/mapping
+";
+
+    /// Serves the files it is given and fails to fetch any other link.
+    struct FilesResolver(HashMap<&'static str, &'static [u8]>);
+
+    impl LinkResolver for FilesResolver {
+        fn cat(&self, link: &Link) -> Box<Future<Item = Vec<u8>, Error = Error> + Send> {
+            Box::new(future::result(
+                self.0
+                    .get(link.link.as_str())
+                    .map(|file| file.to_vec())
+                    .ok_or_else(|| format_err!("file not found: {}", link.link)),
+            ))
+        }
+
+        fn json_stream(
+            &self,
+            _: &Link,
+        ) -> Box<
+            Future<
+                    Item = Box<Stream<Item = StreamValue, Error = Error> + Send + 'static>,
+                    Error = Error,
+                > + Send
+                + 'static,
+        > {
+            unimplemented!()
+        }
+    }
+
+    /// Resolves and validates the manifest like `subgraph_validate` does,
+    /// with `files` next to the manifest.
+    fn validate(files: Vec<(&'static str, &'static [u8])>) -> Result<(), SubgraphRegistrarError> {
+        let mut files: HashMap<_, _> = files.into_iter().collect();
+        files.insert("/ipfs/QmManifest", MANIFEST.as_bytes());
+        let resolver = Arc::new(FilesResolver(files));
+        let link = Link {
+            link: "/ipfs/QmManifest".to_owned(),
+        };
+        SubgraphManifest::resolve(link, resolver)
+            .map_err(SubgraphRegistrarError::ResolveError)
+            .and_then(|manifest| validate_deployment(manifest, &EthereumNetworks::new()))
+            .map(|_| ())
+            .wait()
+    }
+
+    #[test]
+    fn validate_deployment_reports_every_missing_file() {
+        assert_eq!(
+            validate(vec![]).unwrap_err().to_string(),
+            "subgraph resolve error: resolve error: \
+             dataSources[Token].mapping.abis[Token]: file not found: /ipfs/abi; \
+             dataSources[Token].mapping.file: file not found: /ipfs/mapping; \
+             schema: file not found: /ipfs/schema"
+        );
+    }
+
+    #[test]
+    fn validate_deployment_reports_every_problem() {
+        let errors = match validate(vec![
+            ("/ipfs/schema", &b"type Token @entity { id: ID! }"[..]),
+            ("/ipfs/abi", &b"[]"[..]),
+            ("/ipfs/mapping", &b"\0asm\x01\0\0\0"[..]),
+        ]) {
+            Err(SubgraphRegistrarError::ManifestValidationError(errors)) => errors.0,
+            result => panic!("unexpected validation result: {:?}", result),
+        };
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "dataSources[Token].mapping.eventHandlers: \
+                 event Transfer(address,address,uint256) is not defined in ABI Token",
+                "dataSources[Token].mapping: WASM module does not export its memory",
+                "dataSources[Token].mapping: \
+                 handler handleTransfer is not exported by the WASM module",
+                "dataSources.network: subgraph network error: \
+                 Ethereum network not supported by this node: mainnet",
+            ]
+        );
     }
-    return Err(SubgraphRegistrarError::ManifestValidationError(
-        validation_errs,
-    ));
 }
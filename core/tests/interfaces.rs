@@ -20,8 +20,12 @@ fn insert_and_query(
         spec_version: "1".to_owned(),
         description: None,
         repository: None,
+        author: None,
         schema: schema.clone(),
         data_sources: vec![],
+        templates: vec![],
+        external_hosts: vec![],
+        features: vec![],
     };
 
     STORE
@@ -58,6 +62,7 @@ fn insert_and_query(
         schema: STORE.subgraph_schema(&subgraph_id).unwrap(),
         document,
         variables: None,
+        block: None,
     };
     Ok(execute_query(&query, options))
 }
@@ -272,3 +277,47 @@ fn two_interfaces() {
                  \"ifoos\": List([Object({\"foo\": String(\"bla\")}), Object({\"foo\": String(\"ble\")})])})"
     );
 }
+
+#[test]
+fn filter_interface_across_types() {
+    let subgraph_id = "FilterInterfaceAcrossTypes";
+    let schema = "interface Legged { legs: Int }
+                  type Animal implements Legged @entity { id: ID!, legs: Int }
+                  type Furniture implements Legged @entity { id: ID!, legs: Int }
+                  ";
+
+    let animal = (
+        Entity::from(vec![("id", Value::from("1")), ("legs", Value::from(2))]),
+        "Animal",
+    );
+    let furniture = (
+        Entity::from(vec![("id", Value::from("2")), ("legs", Value::from(4))]),
+        "Furniture",
+    );
+    let other_furniture = (
+        Entity::from(vec![("id", Value::from("3")), ("legs", Value::from(3))]),
+        "Furniture",
+    );
+
+    let query = "query {
+                    leggeds(first: 100, where: { legs_gt: 2 }, orderBy: legs) {
+                        __typename
+                        legs
+                    }
+                }";
+
+    let res = insert_and_query(
+        subgraph_id,
+        schema,
+        vec![animal, furniture, other_furniture],
+        query,
+    )
+    .unwrap();
+    assert!(res.errors.is_none());
+    assert_eq!(
+        format!("{:?}", res.data.unwrap()),
+        "Object({\"leggeds\": List([\
+                    Object({\"__typename\": String(\"Furniture\"), \"legs\": Int(Number(3))}), \
+                    Object({\"__typename\": String(\"Furniture\"), \"legs\": Int(Number(4))})])})"
+    );
+}
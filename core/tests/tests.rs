@@ -113,6 +113,9 @@ fn multiple_data_sources_per_subgraph() {
             _: &Logger,
             _: SubgraphDeploymentId,
             data_source: DataSource,
+            _: Arc<Vec<DataSourceTemplate>>,
+            _: Arc<ExternalHosts>,
+            _: Arc<DeploymentQuotas>,
         ) -> Result<Self::Host, Error> {
             self.data_sources_received.lock().unwrap().push(data_source);
 
@@ -130,8 +133,11 @@ fn multiple_data_sources_per_subgraph() {
 
     runtime
         .block_on(future::lazy(|| {
-            let resolver = Arc::new(IpfsClient::default());
             let logger = Logger::root(slog::Discard, o!());
+            let resolver = Arc::new(IpfsResolver::new(
+                &logger,
+                vec![Arc::new(IpfsClient::default())],
+            ));
             let store = Arc::new(FakeStore);
             let host_builder = MockRuntimeHostBuilder::new();
             let block_stream_builder = MockBlockStreamBuilder::new();
@@ -141,6 +147,7 @@ fn multiple_data_sources_per_subgraph() {
                 host_builder.clone(),
                 block_stream_builder,
                 None,
+                Arc::new(ResourceQuotas::default()),
             );
 
             // Load a subgraph with two data sources
@@ -189,6 +196,34 @@ fn multiple_data_sources_per_subgraph() {
         .unwrap();
 }
 
+/// The Ethereum networks of the node under test: a single network that
+/// `store` is the chain store for, served by an archive node with traces.
+fn mock_networks(
+    store: Arc<MockStore>,
+) -> (
+    EthereumNetworks<MockStore>,
+    EthereumNetworks<EthereumNetworkCapabilities>,
+) {
+    let mut chain_stores = EthereumNetworks::new();
+    chain_stores.insert("mainnet".to_owned(), store);
+
+    let mut network_capabilities = EthereumNetworks::new();
+    network_capabilities.insert(
+        "mainnet".to_owned(),
+        Arc::new(EthereumNetworkCapabilities {
+            network: "mainnet".to_owned(),
+            providers: vec![EthereumProviderCapabilities {
+                provider: "mock".to_owned(),
+                archive: true,
+                traces: true,
+                max_log_range: None,
+            }],
+        }),
+    );
+
+    (chain_stores, network_capabilities)
+}
+
 fn added_subgraph_id_eq(
     event: &SubgraphAssignmentProviderEvent,
     id: &SubgraphDeploymentId,
@@ -205,7 +240,8 @@ fn subgraph_provider_events() {
     runtime
         .block_on(future::lazy(|| {
             let logger = Logger::root(slog::Discard, o!());
-            let resolver = Arc::new(IpfsClient::default());
+            let client = Arc::new(IpfsClient::default());
+            let resolver = Arc::new(IpfsResolver::new(&logger, vec![client.clone()]));
             let store = Arc::new(MockStore::new(vec![]));
             let mut provider = graph_core::SubgraphAssignmentProvider::new(
                 logger.clone(),
@@ -215,20 +251,22 @@ fn subgraph_provider_events() {
             let provider_events = provider.take_event_stream().unwrap();
             let node_id = NodeId::new("test").unwrap();
 
+            let (chain_stores, network_capabilities) = mock_networks(store.clone());
             let registrar = graph_core::SubgraphRegistrar::new(
                 logger.clone(),
                 resolver.clone(),
                 Arc::new(provider),
                 store.clone(),
-                store,
+                chain_stores,
+                network_capabilities,
                 node_id.clone(),
                 SubgraphVersionSwitchingMode::Instant,
             );
             registrar
                 .start()
                 .and_then(move |_| {
-                    add_subgraph_to_ipfs(resolver.clone(), "two-datasources")
-                        .join(add_subgraph_to_ipfs(resolver, "dummy"))
+                    add_subgraph_to_ipfs(client.clone(), "two-datasources")
+                        .join(add_subgraph_to_ipfs(client, "dummy"))
                 })
                 .and_then(move |(subgraph1_link, subgraph2_link)| {
                     let registrar = Arc::new(registrar);
@@ -354,7 +392,10 @@ fn subgraph_list() {
         .block_on(future::lazy(|| {
             let logger = Logger::root(slog::Discard, o!());
             let store = Arc::new(MockStore::new(vec![]));
-            let resolver = Arc::new(IpfsClient::default());
+            let resolver = Arc::new(IpfsResolver::new(
+                &logger,
+                vec![Arc::new(IpfsClient::default())],
+            ));
             let provider = graph_core::SubgraphAssignmentProvider::new(
                 logger.clone(),
                 resolver.clone(),
@@ -362,12 +403,14 @@ fn subgraph_list() {
             );
             let node_id = NodeId::new("testnode").unwrap();
 
+            let (chain_stores, network_capabilities) = mock_networks(store.clone());
             let registrar = graph_core::SubgraphRegistrar::new(
                 logger.clone(),
                 resolver,
                 Arc::new(provider),
                 store.clone(),
-                store,
+                chain_stores,
+                network_capabilities,
                 node_id.clone(),
                 SubgraphVersionSwitchingMode::Instant,
             );
@@ -4,6 +4,10 @@ use std::time::Instant;
 use graph::prelude::*;
 use graph::web3::types::*;
 
+/// How long to poll at the polling interval after a subscription to new
+/// chain heads failed, before trying to subscribe again.
+const NEW_HEADS_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(30);
+
 pub struct BlockIngestor<S, E>
 where
     S: ChainStore,
@@ -46,32 +50,84 @@ where
             .map_err(move |e| {
                 error!(static_self.logger, "timer::Interval failed: {:?}", e);
             })
-            .for_each(move |_| {
-                // Attempt to poll
-                static_self.do_poll().then(move |result| {
-                    if let Err(err) = result {
-                        // Some polls will fail due to transient issues
-                        match err {
-                            EthereumAdapterError::BlockUnavailable(_) => {
-                                trace!(
-                                    static_self.logger,
-                                    "Trying again after block polling failed: {}",
-                                    err
-                                );
-                            }
-                            EthereumAdapterError::Unknown(inner_err) => {
-                                warn!(
-                                    static_self.logger,
-                                    "Trying again after block polling failed: {}", inner_err
-                                );
-                            }
-                        }
+            .for_each(move |_| static_self.poll())
+    }
+
+    /// Like `into_polling_stream`, but polls whenever the Ethereum node
+    /// announces a new chain head through the stream returned by
+    /// `new_heads`. If the subscription fails or ends, e.g. because the
+    /// connection was lost, falls back to polling at the polling interval
+    /// for a while and then subscribes again.
+    pub fn into_subscription_stream<F>(self, new_heads: F) -> impl Future<Item = (), Error = ()>
+    where
+        F: Fn() -> Box<Stream<Item = (), Error = Error> + Send> + Send + 'static,
+    {
+        // Currently, there is no way to stop block ingestion, so just leak self
+        let static_self: &'static _ = Box::leak(Box::new(self));
+
+        future::loop_fn((), move |()| {
+            debug!(
+                static_self.logger,
+                "Subscribing to new Ethereum chain heads"
+            );
+
+            // Poll once right away to pick up blocks that were produced
+            // while we were not subscribed
+            stream::once(Ok(()))
+                .chain(new_heads())
+                .for_each(move |()| static_self.poll().then(|_| Ok(())))
+                .then(move |result| {
+                    match result {
+                        Ok(()) => warn!(
+                            static_self.logger,
+                            "Subscription to new Ethereum chain heads ended, \
+                             falling back to polling"
+                        ),
+                        Err(e) => warn!(
+                            static_self.logger,
+                            "Subscription to new Ethereum chain heads failed, \
+                             falling back to polling";
+                            "error" => e.to_string(),
+                        ),
                     }
 
-                    // Continue polling even if polling failed
-                    future::ok(())
+                    let resubscribe_at = Instant::now() + NEW_HEADS_RESUBSCRIBE_DELAY;
+                    tokio::timer::Interval::new(Instant::now(), static_self.polling_interval)
+                        .map_err(move |e| {
+                            error!(static_self.logger, "timer::Interval failed: {:?}", e);
+                        })
+                        .take_while(move |_| Ok(Instant::now() < resubscribe_at))
+                        .for_each(move |_| static_self.poll())
                 })
-            })
+                .map(|()| future::Loop::Continue(()))
+        })
+    }
+
+    /// Polls once, logging failures instead of returning them.
+    fn poll<'a>(&'a self) -> impl Future<Item = (), Error = ()> + 'a {
+        self.do_poll().then(move |result| {
+            if let Err(err) = result {
+                // Some polls will fail due to transient issues
+                match err {
+                    EthereumAdapterError::BlockUnavailable(_) => {
+                        trace!(
+                            self.logger,
+                            "Trying again after block polling failed: {}",
+                            err
+                        );
+                    }
+                    EthereumAdapterError::Unknown(inner_err) => {
+                        warn!(
+                            self.logger,
+                            "Trying again after block polling failed: {}", inner_err
+                        );
+                    }
+                }
+            }
+
+            // Continue polling even if polling failed
+            future::ok(())
+        })
     }
 
     fn do_poll<'a>(&'a self) -> impl Future<Item = (), Error = EthereumAdapterError> + 'a {
@@ -17,6 +17,7 @@ use graph::prelude::{
     BlockStream as BlockStreamTrait, BlockStreamBuilder as BlockStreamBuilderTrait, *,
 };
 use graph::util::ethereum::string_to_h256;
+use graph::util::tracing;
 use graph::web3::types::*;
 
 enum BlockStreamState {
@@ -104,9 +105,21 @@ struct BlockStreamContext<S, C, E> {
     node_id: NodeId,
     subgraph_id: SubgraphDeploymentId,
     reorg_threshold: u64,
+    /// The block beyond the reorg threshold that the subgraph ptr will be
+    /// at once the last blocks found by scanning have been processed. Such
+    /// blocks are final, so the subgraph ptr doesn't need to be checked
+    /// against the main chain while it points to this block.
+    final_ptr: Arc<Mutex<Option<EthereumBlockPointer>>>,
+    start_block: u64,
     log_filter: Option<EthereumLogFilter>,
     call_filter: Option<EthereumCallFilter>,
     block_filter: Option<EthereumBlockFilter>,
+    entity_change_filter: Option<EntityChangeFilter>,
+    /// How much of the blocks that aren't in the chain store is requested
+    /// from the Ethereum node.
+    block_detail: BlockDetail,
+    /// Notifications from the consumer that it has written blocks.
+    written_blocks: Arc<Mutex<Receiver<EthereumBlockPointer>>>,
     logger: Logger,
 }
 
@@ -119,9 +132,14 @@ impl<S, C, E> Clone for BlockStreamContext<S, C, E> {
             node_id: self.node_id.clone(),
             subgraph_id: self.subgraph_id.clone(),
             reorg_threshold: self.reorg_threshold,
+            final_ptr: self.final_ptr.clone(),
+            start_block: self.start_block,
             log_filter: self.log_filter.clone(),
             call_filter: self.call_filter.clone(),
             block_filter: self.block_filter.clone(),
+            entity_change_filter: self.entity_change_filter.clone(),
+            block_detail: self.block_detail,
+            written_blocks: self.written_blocks.clone(),
             logger: self.logger.clone(),
         }
     }
@@ -130,8 +148,13 @@ impl<S, C, E> Clone for BlockStreamContext<S, C, E> {
 pub struct BlockStream<S, C, E> {
     state: Mutex<BlockStreamState>,
     consecutive_err_count: u32,
+    /// The last block that was yielded. Reconciliation only starts once
+    /// the consumer has written it to the store, since consumers may queue
+    /// their writes.
+    last_yielded_block: Option<EthereumBlockPointer>,
     chain_head_update_sink: Sender<ChainHeadUpdate>,
     chain_head_update_stream: Receiver<ChainHeadUpdate>,
+    written_blocks_sink: Sender<EthereumBlockPointer>,
     ctx: BlockStreamContext<S, C, E>,
 }
 
@@ -150,6 +173,9 @@ where
         log_filter: Option<EthereumLogFilter>,
         call_filter: Option<EthereumCallFilter>,
         block_filter: Option<EthereumBlockFilter>,
+        entity_change_filter: Option<EntityChangeFilter>,
+        block_detail: BlockDetail,
+        start_block: u64,
         reorg_threshold: u64,
         logger: Logger,
     ) -> Self {
@@ -158,12 +184,15 @@ where
         ));
 
         let (chain_head_update_sink, chain_head_update_stream) = channel(100);
+        let (written_blocks_sink, written_blocks) = channel(0);
 
         BlockStream {
             state: Mutex::new(BlockStreamState::New),
             consecutive_err_count: 0,
+            last_yielded_block: None,
             chain_head_update_sink,
             chain_head_update_stream,
+            written_blocks_sink,
             ctx: BlockStreamContext {
                 subgraph_store,
                 chain_store,
@@ -171,10 +200,15 @@ where
                 node_id,
                 subgraph_id,
                 reorg_threshold,
+                final_ptr: Arc::new(Mutex::new(None)),
+                start_block,
                 logger,
                 log_filter,
                 call_filter,
                 block_filter,
+                entity_change_filter,
+                block_detail,
+                written_blocks: Arc::new(Mutex::new(written_blocks)),
             },
         }
     }
@@ -197,9 +231,98 @@ where
         call_filter_requirement || block_filter_requirement
     }
 
+    /// A subgraph with subgraph data sources can't get ahead of the
+    /// deployments it consumes, since it needs their entity changes for
+    /// every block it processes. Returns the pointer of the deployment that
+    /// is furthest behind if it is not ahead of `head_ptr`, so that the
+    /// subgraph follows the chain of that deployment and reverts with it.
+    fn limit_head_ptr(
+        &self,
+        head_ptr: EthereumBlockPointer,
+    ) -> Result<EthereumBlockPointer, Error> {
+        let filter = match self.entity_change_filter {
+            Some(ref filter) => filter,
+            None => return Ok(head_ptr),
+        };
+        filter
+            .entity_types
+            .keys()
+            .try_fold(head_ptr, |head_ptr, base_id| {
+                let base_ptr = self.subgraph_store.block_ptr(base_id.clone())?;
+                Ok(if base_ptr.number <= head_ptr.number {
+                    base_ptr
+                } else {
+                    head_ptr
+                })
+            })
+    }
+
+    /// Finds the blocks from `from` to `to` in which the deployments that
+    /// the subgraph consumes changed entities it has handlers for.
+    ///
+    /// The history of a deployment still includes the blocks it reverted,
+    /// so only blocks that are on the main chain are returned. Like the
+    /// other scans, this relies on being beyond the reorg threshold.
+    fn blocks_with_entity_changes(
+        &self,
+        from: u64,
+        to: u64,
+    ) -> Box<Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        let filter = match self.entity_change_filter {
+            Some(ref filter) => filter,
+            None => return Box::new(future::ok(vec![])),
+        };
+        let mut candidates = vec![];
+        for (base_id, entity_types) in filter.entity_types.iter() {
+            match self
+                .subgraph_store
+                .blocks_with_entity_changes(base_id, entity_types, from, to)
+            {
+                Ok(block_ptrs) => candidates.extend(block_ptrs),
+                Err(e) => return Box::new(future::err(e)),
+            }
+        }
+
+        let eth_adapter = self.eth_adapter.clone();
+        let logger = self.logger.clone();
+        Box::new(
+            stream::iter_ok(candidates)
+                .map(move |block_ptr| {
+                    eth_adapter
+                        .block_hash_by_block_number(&logger, block_ptr.number)
+                        .map(move |hash| match hash {
+                            Some(hash) if hash == block_ptr.hash => Some(block_ptr),
+                            _ => None,
+                        })
+                })
+                .buffered(10)
+                .filter_map(|block_ptr| block_ptr)
+                .collect(),
+        )
+    }
+
+    /// Returns a trigger for each entity that a deployment the subgraph
+    /// consumes changed in the block, if the subgraph has a handler for it.
+    fn entity_change_triggers(&self, block_hash: H256) -> Result<Vec<EthereumTrigger>, Error> {
+        let mut triggers = vec![];
+        if let Some(ref filter) = self.entity_change_filter {
+            for (base_id, entity_types) in filter.entity_types.iter() {
+                triggers.extend(
+                    self.subgraph_store
+                        .entity_changes_in_block(base_id, entity_types, block_hash)?
+                        .into_iter()
+                        .map(EthereumTrigger::EntityChange),
+                );
+            }
+        }
+        Ok(triggers)
+    }
+
     /// Perform reconciliation steps until there are blocks to yield or we are up-to-date.
+    /// Waits for the subgraph pointer to reach `last_yielded_block` first.
     fn next_blocks(
         &self,
+        last_yielded_block: Option<EthereumBlockPointer>,
     ) -> Box<
         Future<
                 Item = Option<Box<Stream<Item = EthereumBlockWithTriggers, Error = Error> + Send>>,
@@ -208,7 +331,8 @@ where
     > {
         let ctx = self.clone();
 
-        Box::new(future::loop_fn((), move |()| {
+        // The loop only starts once it is polled, after the wait
+        let reconcile = future::loop_fn((), move |()| {
             let ctx1 = ctx.clone();
             let ctx2 = ctx.clone();
             let ctx3 = ctx.clone();
@@ -233,6 +357,41 @@ where
                         Ok(future::Loop::Break(None))
                     }
                 })
+        });
+
+        Box::new(
+            self.wait_for_writes(last_yielded_block)
+                .and_then(|()| reconcile),
+        )
+    }
+
+    /// Resolves once the subgraph pointer has reached `block`, that is,
+    /// once the consumer has written all blocks it was given. The pointer
+    /// is checked again whenever the consumer reports a written block.
+    fn wait_for_writes(
+        &self,
+        block: Option<EthereumBlockPointer>,
+    ) -> Box<Future<Item = (), Error = Error> + Send> {
+        let block = match block {
+            Some(block) => block,
+            None => return Box::new(future::ok(())),
+        };
+        let ctx = self.clone();
+
+        Box::new(future::poll_fn(move || loop {
+            // Notifications are dropped while the channel is full, so the
+            // store has the final say
+            let subgraph_ptr = ctx.subgraph_store.block_ptr(ctx.subgraph_id.clone())?;
+            if subgraph_ptr.number >= block.number {
+                return Ok(Async::Ready(()));
+            }
+            match ctx.written_blocks.lock().unwrap().poll() {
+                Ok(Async::Ready(Some(_))) => continue,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(None)) | Err(()) => {
+                    return Err(format_err!("consumer stopped reporting written blocks"))
+                }
+            }
         }))
     }
 
@@ -260,7 +419,12 @@ where
                 as Box<Future<Item = _, Error = _> + Send>;
         }
 
-        let head_ptr = head_ptr_opt.unwrap();
+        let head_ptr = match ctx.limit_head_ptr(head_ptr_opt.unwrap()) {
+            Ok(head_ptr) => head_ptr,
+            Err(e) => {
+                return Box::new(future::err(e)) as Box<Future<Item = _, Error = _> + Send>;
+            }
+        };
 
         debug!(
             ctx.logger, "Chain head pointer";
@@ -320,8 +484,18 @@ where
             // This allows us to ask the node: does subgraph_ptr point to a block that was
             // permanently accepted into the main chain, or does it point to a block that was
             // uncled?
-            Box::new(ctx.eth_adapter
-                .is_on_main_chain(&ctx.logger, subgraph_ptr)
+            //
+            // If this stream moved the subgraph ptr to a block that was already beyond the
+            // reorg threshold when it was found, that block is final and there is no need to
+            // ask. This saves a call to the Ethereum node for every range of blocks scanned
+            // during a backfill.
+            let is_final = *ctx.final_ptr.lock().unwrap() == Some(subgraph_ptr);
+            let is_on_main_chain: Box<Future<Item = _, Error = _> + Send> = if is_final {
+                Box::new(future::ok(true))
+            } else {
+                Box::new(ctx.eth_adapter.is_on_main_chain(&ctx.logger, subgraph_ptr))
+            };
+            Box::new(is_on_main_chain
                 .and_then(move |is_on_main_chain| -> Box<Future<Item = _, Error = _> + Send> {
                     if is_on_main_chain {
                         // The subgraph ptr points to a block on the main chain.
@@ -338,8 +512,9 @@ where
                         // It is only safe to use block numbers because we are beyond the reorg
                         // threshold.
 
-                        // Start with first block after subgraph ptr
-                        let from = subgraph_ptr.number + 1;
+                        // Start with first block after subgraph ptr, but skip any blocks
+                        // before the earliest start block of the data sources.
+                        let from = cmp::max(subgraph_ptr.number + 1, ctx.start_block);
 
                         // End just prior to reorg threshold.
                         // It isn't safe to go any farther due to race conditions.
@@ -349,17 +524,42 @@ where
                         // updated frequently.
                         let to = cmp::min(from + (10_000 - 1), to_limit);
 
-                        debug!(ctx.logger, "Finding next blocks with relevant events...");
+                        // If the start block lies beyond the reorg threshold, there is
+                        // nothing to scan yet; just advance the subgraph ptr to `to`.
+                        let descendant_ptrs: Box<Future<Item = _, Error = _> + Send> = if from > to {
+                            Box::new(future::ok(vec![]))
+                        } else {
+                            debug!(ctx.logger, "Finding next blocks with relevant events...");
+                            let mut span = tracing::root_span("scan_blocks");
+                            tracing::tag(&mut span, "subgraph_id", &ctx.subgraph_id);
+                            tracing::tag(&mut span, "from", from);
+                            tracing::tag(&mut span, "to", to);
+                            Box::new(
+                                ctx.eth_adapter
+                                    .blocks_with_triggers(
+                                        &ctx.logger,
+                                        from,
+                                        to,
+                                        log_filter.clone(),
+                                        call_filter.clone(),
+                                        block_filter.clone(),
+                                    )
+                                    .join(ctx.blocks_with_entity_changes(from, to))
+                                    .map(|(mut block_ptrs, entity_change_block_ptrs)| {
+                                        block_ptrs.extend(entity_change_block_ptrs);
+                                        block_ptrs.sort_by_key(|block_ptr| block_ptr.number);
+                                        block_ptrs.dedup();
+                                        block_ptrs
+                                    })
+                                    .then(move |result| {
+                                        drop(span);
+                                        result
+                                    }),
+                            )
+                        };
+
                         Box::new(
-                            ctx.eth_adapter
-                                .blocks_with_triggers(
-                                    &ctx.logger,
-                                    from,
-                                    to,
-                                    log_filter.clone(),
-                                    call_filter.clone(),
-                                    block_filter.clone(),
-                                )
+                            descendant_ptrs
                                 .and_then(move |descendant_ptrs| -> Box<Future<Item = _, Error = _> + Send> {
                                     debug!(ctx.logger, "Done finding next blocks.");
 
@@ -380,9 +580,11 @@ where
                                                              format_err!("Ethereum node could not find block with number {}", to)
                                                          })
                                                          .map(|to_block_hash| {
+                                                             let to_ptr: EthereumBlockPointer = (to_block_hash, to).into();
+                                                             *ctx.final_ptr.lock().unwrap() = Some(to_ptr);
                                                              ReconciliationStep::AdvanceToDescendantBlock {
                                                                  from: subgraph_ptr,
-                                                                 to: (to_block_hash, to).into(),
+                                                                 to: to_ptr,
                                                              }
                                                          })
                                                  }))
@@ -401,6 +603,7 @@ where
                                             "Found {} block(s) with events.",
                                             descendant_ptrs.len()
                                         );
+                                        *ctx.final_ptr.lock().unwrap() = descendant_ptrs.last().cloned();
                                         let descendant_hashes = descendant_ptrs.into_iter().map(|ptr| ptr.hash).collect();
                                         Box::new(future::ok(
                                             // Proceed to those blocks
@@ -475,18 +678,18 @@ where
                                     return Box::new(future::ok(EthereumBlockWithCalls {
                                         ethereum_block: block,
                                         calls: None,
+                                        logs: None,
                                     }));
                                 }
                                 let block_with_calls = ctx
-                                    .eth_adapter
-                                    .calls_in_block(
+                                    .load_calls_in_block(
                                         &logger,
-                                        block.block.number.unwrap().as_u64(),
-                                        block.block.hash.unwrap(),
+                                        EthereumBlockPointer::from(&block),
                                     )
                                     .map(move |calls| EthereumBlockWithCalls {
                                         ethereum_block: block,
                                         calls: Some(calls),
+                                        logs: None,
                                     });
                                 Box::new(block_with_calls)
                             },
@@ -580,6 +783,7 @@ where
                 descendant_blocks,
             } => {
                 let mut subgraph_ptr = from;
+                let trigger_ctx = ctx.clone();
                 let log_filter = log_filter.clone();
                 let call_filter = call_filter.clone();
                 let block_filter = block_filter.clone();
@@ -621,20 +825,23 @@ where
                                 Ok(descendant_block)
                             })
                             .and_then(move |descendant_block| {
-                                future::result(
-                                    parse_triggers(
-                                        log_filter.clone(),
-                                        call_filter.clone(),
-                                        block_filter.clone(),
-                                        &descendant_block,
-                                    )
-                                    .map(move |triggers| {
-                                        EthereumBlockWithTriggers {
-                                            ethereum_block: descendant_block.ethereum_block,
-                                            triggers,
-                                        }
-                                    }),
-                                )
+                                let mut triggers = parse_triggers(
+                                    log_filter.clone(),
+                                    call_filter.clone(),
+                                    block_filter.clone(),
+                                    &descendant_block,
+                                )?;
+
+                                // Entity changes of consumed deployments come
+                                // after the Ethereum triggers of the block
+                                let block_hash =
+                                    descendant_block.ethereum_block.block.hash.unwrap();
+                                triggers.extend(trigger_ctx.entity_change_triggers(block_hash)?);
+
+                                Ok(EthereumBlockWithTriggers {
+                                    ethereum_block: descendant_block.ethereum_block,
+                                    triggers,
+                                })
                             }),
                     ) as Box<Stream<Item = _, Error = _> + Send>,
                 )))
@@ -846,69 +1053,161 @@ where
         include_calls_in_block: bool,
     ) -> impl Future<Item = EthereumBlockWithCalls, Error = Error> + Send {
         let ctx = self.clone();
-        let eth = self.eth_adapter.clone();
+        let ctx_calls = self.clone();
         let logger = self.logger.clone();
 
+        let mut span = tracing::root_span("load_block");
+        tracing::tag(&mut span, "subgraph_id", &self.subgraph_id);
+        tracing::tag(&mut span, "block_hash", format!("{:x}", block_hash));
+
         // Search for the block in the store first then use the ethereum adapter as a backup
         let block = future::result(ctx.chain_store.block(block_hash))
             .and_then(
                 move |local_block_opt| -> Box<Future<Item = _, Error = _> + Send> {
                     match local_block_opt {
-                        Some(block) => Box::new(future::ok(block)),
-                        None => {
-                            let logger = ctx.logger.clone();
-                            let ctx_1 = ctx.clone();
-                            let ctx_2 = ctx_1.clone();
-                            Box::new(
-                                ctx_1
-                                    .eth_adapter
-                                    .block_by_hash(&logger, block_hash)
-                                    .and_then(move |block_opt| {
-                                        block_opt.ok_or_else(move || {
-                                            format_err!(
-                                                "Ethereum node could not find block with hash {}",
-                                                block_hash
-                                            )
-                                        })
-                                    })
-                                    .and_then(move |block| {
-                                        ctx_1.eth_adapter.load_full_block(&logger, block).map_err(
-                                            |e| format_err!("Error loading full block: {}", e),
-                                        )
-                                    })
-                                    .and_then(move |block| {
-                                        // Cache in store for later
-                                        ctx_2
-                                            .chain_store
-                                            .upsert_blocks(stream::once(Ok(block.clone())))
-                                            .map(move |()| block)
-                                    }),
-                            )
-                        }
+                        Some(block) => Box::new(future::ok((block, None))),
+                        None => ctx.fetch_block(block_hash),
                     }
                 },
             )
-            .and_then(move |block| -> Box<Future<Item = _, Error = _> + Send> {
-                if !include_calls_in_block {
-                    return Box::new(future::ok(EthereumBlockWithCalls {
-                        ethereum_block: block,
-                        calls: None,
-                    }));
-                }
-                let block = eth
-                    .calls_in_block(
-                        &logger,
-                        block.block.number.unwrap().as_u64(),
-                        block.block.hash.unwrap(),
-                    )
-                    .map(move |calls| EthereumBlockWithCalls {
-                        ethereum_block: block,
-                        calls: Some(calls),
-                    });
-                Box::new(block)
+            .and_then(
+                move |(block, logs)| -> Box<Future<Item = _, Error = _> + Send> {
+                    if !include_calls_in_block {
+                        return Box::new(future::ok(EthereumBlockWithCalls {
+                            ethereum_block: block,
+                            calls: None,
+                            logs,
+                        }));
+                    }
+                    let block = ctx_calls
+                        .load_calls_in_block(&logger, EthereumBlockPointer::from(&block))
+                        .map(move |calls| EthereumBlockWithCalls {
+                            ethereum_block: block,
+                            calls: Some(calls),
+                            logs,
+                        });
+                    Box::new(block)
+                },
+            )
+            .then(move |result| {
+                drop(span);
+                result
             });
         Box::new(block)
     }
+
+    /// Requests a block from the Ethereum node with only as much detail as
+    /// the subgraph needs. The logs of blocks that are requested without
+    /// their transaction receipts are loaded on their own, if the subgraph
+    /// has event handlers.
+    ///
+    /// Only blocks with their receipts are cached in the chain store, since
+    /// other subgraphs may need the receipts.
+    fn fetch_block(
+        &self,
+        block_hash: H256,
+    ) -> Box<Future<Item = (EthereumBlock, Option<Vec<Log>>), Error = Error> + Send> {
+        let ctx = self.clone();
+        let logger = self.logger.clone();
+        let block_detail = self.block_detail;
+        let include_logs = self.log_filter.is_some();
+
+        let block_opt = match block_detail {
+            BlockDetail::Header => self.eth_adapter.block_header_by_hash(&logger, block_hash),
+            BlockDetail::Transactions | BlockDetail::Receipts => {
+                self.eth_adapter.block_by_hash(&logger, block_hash)
+            }
+        };
+        Box::new(
+            block_opt
+                .and_then(move |block_opt| {
+                    block_opt.ok_or_else(move || {
+                        format_err!(
+                            "Ethereum node could not find block with hash {}",
+                            block_hash
+                        )
+                    })
+                })
+                .and_then(move |block| -> Box<Future<Item = _, Error = _> + Send> {
+                    if block_detail == BlockDetail::Receipts {
+                        let chain_store = ctx.chain_store.clone();
+                        return Box::new(
+                            ctx.eth_adapter
+                                .load_full_block(&logger, block)
+                                .map_err(|e| format_err!("Error loading full block: {}", e))
+                                .and_then(move |block| {
+                                    // Cache in store for later
+                                    chain_store
+                                        .upsert_blocks(stream::once(Ok(block.clone())))
+                                        .map(move |()| (block, None))
+                                }),
+                        );
+                    }
+
+                    let block = EthereumBlock {
+                        block,
+                        transaction_receipts: vec![],
+                    };
+                    if !include_logs {
+                        return Box::new(future::ok((block, None)));
+                    }
+                    Box::new(
+                        ctx.load_logs_in_block(&logger, EthereumBlockPointer::from(&block))
+                            .map(move |logs| (block, Some(logs))),
+                    )
+                }),
+        )
+    }
+
+    /// Loads the logs of a block, from the store if they are cached there
+    /// and from the Ethereum node otherwise.
+    fn load_logs_in_block(
+        &self,
+        logger: &Logger,
+        block_ptr: EthereumBlockPointer,
+    ) -> Box<Future<Item = Vec<Log>, Error = Error> + Send> {
+        match self.chain_store.block_logs(block_ptr.hash) {
+            Ok(Some(logs)) => return Box::new(future::ok(logs)),
+            Ok(None) => (),
+            Err(e) => return Box::new(future::err(e)),
+        }
+
+        let chain_store = self.chain_store.clone();
+        Box::new(
+            self.eth_adapter
+                .logs_in_block(logger, block_ptr.number, block_ptr.hash)
+                .and_then(move |logs| {
+                    // Cache in store for later
+                    chain_store.upsert_block_logs(block_ptr, &logs)?;
+                    Ok(logs)
+                }),
+        )
+    }
+
+    /// Loads the calls traced in a block, from the store if they are cached
+    /// there and from the Ethereum node otherwise.
+    fn load_calls_in_block(
+        &self,
+        logger: &Logger,
+        block_ptr: EthereumBlockPointer,
+    ) -> Box<Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
+        match self.chain_store.block_calls(block_ptr.hash) {
+            Ok(Some(calls)) => return Box::new(future::ok(calls)),
+            Ok(None) => (),
+            Err(e) => return Box::new(future::err(e)),
+        }
+
+        let chain_store = self.chain_store.clone();
+        Box::new(
+            self.eth_adapter
+                .calls_in_block(logger, block_ptr.number, block_ptr.hash)
+                .and_then(move |calls| {
+                    // Cache in store for later
+                    chain_store.upsert_block_calls(block_ptr, &calls)?;
+                    Ok(calls)
+                }),
+        )
+    }
 }
 
 impl<S, C, E> BlockStreamTrait for BlockStream<S, C, E>
@@ -917,6 +1216,9 @@ where
     C: ChainStore,
     E: EthereumAdapter,
 {
+    fn written_blocks_sink(&self) -> Sender<EthereumBlockPointer> {
+        self.written_blocks_sink.clone()
+    }
 }
 
 impl<S, C, E> Stream for BlockStream<S, C, E>
@@ -940,7 +1242,7 @@ where
                 // First time being polled
                 BlockStreamState::New => {
                     // Start the reconciliation process by asking for blocks
-                    let next_blocks_future = self.ctx.next_blocks();
+                    let next_blocks_future = self.ctx.next_blocks(self.last_yielded_block);
                     state = BlockStreamState::Reconciliation(next_blocks_future);
 
                     // Poll the next_blocks() future
@@ -991,7 +1293,7 @@ where
                             );
 
                             // Try again by restarting reconciliation
-                            let next_blocks_future = self.ctx.next_blocks();
+                            let next_blocks_future = self.ctx.next_blocks(self.last_yielded_block);
                             state = BlockStreamState::Reconciliation(next_blocks_future);
 
                             // Poll the next_blocks() future
@@ -1005,6 +1307,8 @@ where
                     match next_blocks.poll() {
                         // Yield one block
                         Ok(Async::Ready(Some(next_block))) => {
+                            self.last_yielded_block =
+                                Some(EthereumBlockPointer::from(&next_block.ethereum_block));
                             state = BlockStreamState::YieldingBlocks(next_blocks);
                             break Async::Ready(Some(next_block));
                         }
@@ -1015,7 +1319,7 @@ where
                             self.consecutive_err_count = 0;
 
                             // Restart reconciliation until more blocks or done
-                            let next_blocks_future = self.ctx.next_blocks();
+                            let next_blocks_future = self.ctx.next_blocks(self.last_yielded_block);
                             state = BlockStreamState::Reconciliation(next_blocks_future);
 
                             // Poll the next_blocks() future
@@ -1042,7 +1346,7 @@ where
                             );
 
                             // Try again by restarting reconciliation
-                            let next_blocks_future = self.ctx.next_blocks();
+                            let next_blocks_future = self.ctx.next_blocks(self.last_yielded_block);
                             state = BlockStreamState::Reconciliation(next_blocks_future);
 
                             // Poll the next_blocks() future
@@ -1055,9 +1359,24 @@ where
                 BlockStreamState::Idle => {
                     match self.chain_head_update_stream.poll() {
                         // Chain head was updated
-                        Ok(Async::Ready(Some(_chain_head_update))) => {
+                        Ok(Async::Ready(Some(chain_head_update))) => {
+                            // The block ingestor tells us when the chain head
+                            // switched branches; reconciliation reverts the
+                            // blocks of this subgraph that are no longer on the
+                            // main chain
+                            if let Some(common_ancestor) = chain_head_update.reverted_to() {
+                                debug!(
+                                    self.ctx.logger,
+                                    "Chain head switched to a different branch";
+                                    "common_ancestor_number" => common_ancestor.number,
+                                    "common_ancestor_hash" => format!("{:?}", common_ancestor.hash),
+                                    "head_number" => chain_head_update.head_block_number,
+                                    "reverted_blocks" => chain_head_update.reverted_blocks.len(),
+                                );
+                            }
+
                             // Start reconciliation process
-                            let next_blocks_future = self.ctx.next_blocks();
+                            let next_blocks_future = self.ctx.next_blocks(self.last_yielded_block);
                             state = BlockStreamState::Reconciliation(next_blocks_future);
 
                             // Poll the next_blocks() future
@@ -1113,20 +1432,22 @@ where
 
 pub struct BlockStreamBuilder<S, C, E> {
     subgraph_store: Arc<S>,
-    chain_store: Arc<C>,
-    eth_adapter: Arc<E>,
+    chain_stores: EthereumNetworks<C>,
+    eth_adapters: EthereumNetworks<E>,
     node_id: NodeId,
     reorg_threshold: u64,
+    network_reorg_thresholds: HashMap<String, u64>,
 }
 
 impl<S, C, E> Clone for BlockStreamBuilder<S, C, E> {
     fn clone(&self) -> Self {
         BlockStreamBuilder {
             subgraph_store: self.subgraph_store.clone(),
-            chain_store: self.chain_store.clone(),
-            eth_adapter: self.eth_adapter.clone(),
+            chain_stores: self.chain_stores.clone(),
+            eth_adapters: self.eth_adapters.clone(),
             node_id: self.node_id.clone(),
             reorg_threshold: self.reorg_threshold,
+            network_reorg_thresholds: self.network_reorg_thresholds.clone(),
         }
     }
 }
@@ -1139,19 +1460,30 @@ where
 {
     pub fn new(
         subgraph_store: Arc<S>,
-        chain_store: Arc<C>,
-        eth_adapter: Arc<E>,
+        chain_stores: EthereumNetworks<C>,
+        eth_adapters: EthereumNetworks<E>,
         node_id: NodeId,
         reorg_threshold: u64,
     ) -> Self {
         BlockStreamBuilder {
             subgraph_store,
-            chain_store,
-            eth_adapter,
+            chain_stores,
+            eth_adapters,
             node_id,
             reorg_threshold,
+            network_reorg_thresholds: HashMap::new(),
         }
     }
+
+    /// Uses a reorg threshold of its own for each network in
+    /// `network_reorg_thresholds` instead of the default one.
+    pub fn with_network_reorg_thresholds(
+        mut self,
+        network_reorg_thresholds: HashMap<String, u64>,
+    ) -> Self {
+        self.network_reorg_thresholds = network_reorg_thresholds;
+        self
+    }
 }
 
 impl<S, C, E> BlockStreamBuilderTrait for BlockStreamBuilder<S, C, E>
@@ -1162,21 +1494,43 @@ where
 {
     type Stream = BlockStream<S, C, E>;
 
-    fn from_subgraph(&self, manifest: &SubgraphManifest, logger: Logger) -> Self::Stream {
+    fn from_subgraph(
+        &self,
+        manifest: &SubgraphManifest,
+        logger: Logger,
+    ) -> Result<Self::Stream, Error> {
+        // Index the subgraph on the network its data sources are on
+        let network_name = manifest.network_name();
+        let chain_store = self
+            .chain_stores
+            .get(network_name.as_ref().map(String::as_str))?;
+        let eth_adapter = self
+            .eth_adapters
+            .get(network_name.as_ref().map(String::as_str))?;
+
+        let reorg_threshold = network_name
+            .as_ref()
+            .and_then(|network_name| self.network_reorg_thresholds.get(network_name))
+            .cloned()
+            .unwrap_or(self.reorg_threshold);
+
         // Listen for chain head block updates
-        let mut chain_head_update_listener = self.chain_store.chain_head_updates();
+        let mut chain_head_update_listener = chain_store.chain_head_updates();
 
         // Create the actual subgraph-specific block stream
         let block_stream = BlockStream::new(
             self.subgraph_store.clone(),
-            self.chain_store.clone(),
-            self.eth_adapter.clone(),
+            chain_store,
+            eth_adapter,
             self.node_id.clone(),
             manifest.id.clone(),
             create_log_filter_from_subgraph(manifest),
             create_call_filter_from_subgraph(manifest),
             create_block_filter_from_subgraph(manifest),
-            self.reorg_threshold,
+            create_entity_change_filter_from_subgraph(manifest),
+            manifest.block_detail(),
+            start_block_from_subgraph(manifest),
+            reorg_threshold,
             logger,
         );
 
@@ -1190,8 +1544,135 @@ where
         // block stream's chain head update sink
         std::mem::forget(chain_head_update_listener);
 
-        block_stream
+        Ok(block_stream)
+    }
+}
+
+/// Returns the blocks of a subgraph from `startBlock` up to `block_ptr`,
+/// with their triggers, using only the blocks cached in the chain store.
+///
+/// This is used to replay a subgraph without talking to an Ethereum node.
+/// Since calls are not cached, subgraphs with call handlers or with block
+/// handlers that filter by calls cannot be replayed. Neither can subgraphs
+/// with subgraph data sources, whose triggers depend on other deployments.
+pub fn cached_blocks_from_subgraph<C>(
+    chain_store: Arc<C>,
+    manifest: &SubgraphManifest,
+    block_ptr: EthereumBlockPointer,
+) -> Result<Box<Stream<Item = EthereumBlockWithTriggers, Error = Error> + Send>, Error>
+where
+    C: ChainStore,
+{
+    let log_filter = create_log_filter_from_subgraph(manifest);
+    let call_filter = create_call_filter_from_subgraph(manifest);
+    let block_filter = create_block_filter_from_subgraph(manifest);
+    if call_filter.is_some()
+        || block_filter
+            .as_ref()
+            .map_or(false, |filter| !filter.contract_addresses.is_empty())
+    {
+        bail!("subgraphs with call handlers cannot be replayed from cached blocks");
+    }
+    if create_entity_change_filter_from_subgraph(manifest).is_some() {
+        bail!("subgraphs with subgraph data sources cannot be replayed from cached blocks");
+    }
+
+    let start_block = start_block_from_subgraph(manifest);
+    let block_ptrs = canonical_block_pointers(
+        chain_store.block_pointers_in_range(start_block, block_ptr.number)?,
+        block_ptr,
+    )?;
+
+    Ok(Box::new(stream::iter_ok(block_ptrs).and_then(
+        move |block_ptr| {
+            let ethereum_block = chain_store.block(block_ptr.hash)?.ok_or_else(|| {
+                format_err!(
+                    "block #{} ({}) disappeared from the chain store",
+                    block_ptr.number,
+                    block_ptr.hash_hex()
+                )
+            })?;
+            let triggers = parse_triggers(
+                log_filter.clone(),
+                None,
+                block_filter.clone(),
+                &EthereumBlockWithCalls {
+                    ethereum_block: ethereum_block.clone(),
+                    calls: None,
+                    logs: None,
+                },
+            )?;
+            Ok(EthereumBlockWithTriggers {
+                ethereum_block,
+                triggers,
+            })
+        },
+    )))
+}
+
+/// Picks the blocks on the chain that ends in `head` from the cached blocks
+/// in `candidates`, which are ordered by block number and may include
+/// blocks from forks. Returns the picked blocks in ascending order.
+///
+/// Only blocks with triggers and the blocks near the chain head are cached,
+/// so the chain can only be followed through parent hashes where blocks are
+/// consecutive. Elsewhere, a block number must have a single candidate.
+fn canonical_block_pointers(
+    candidates: Vec<(EthereumBlockPointer, H256)>,
+    head: EthereumBlockPointer,
+) -> Result<Vec<EthereumBlockPointer>, Error> {
+    let mut by_number: Vec<(u64, Vec<(EthereumBlockPointer, H256)>)> = vec![];
+    for (ptr, parent_hash) in candidates {
+        if by_number
+            .last()
+            .map_or(true, |(number, _)| *number != ptr.number)
+        {
+            by_number.push((ptr.number, vec![]));
+        }
+        by_number.last_mut().unwrap().1.push((ptr, parent_hash));
+    }
+
+    // Walk backwards from the head; `expected` is the hash of the block
+    // with the next lower number, if it is known
+    let mut expected = Some((head.number, head.hash));
+    let mut picked = vec![];
+    for (number, mut candidates) in by_number.into_iter().rev() {
+        let expected_hash = match expected {
+            Some((expected_number, hash)) if expected_number == number => Some(hash),
+            _ => None,
+        };
+        let choice = match expected_hash {
+            Some(hash) => candidates.into_iter().find(|(ptr, _)| ptr.hash == hash),
+            None if candidates.len() == 1 => candidates.pop(),
+            None => bail!(
+                "cannot tell which of the {} cached blocks with number {} \
+                 is on the main chain",
+                candidates.len(),
+                number
+            ),
+        };
+        expected = match choice {
+            Some((ptr, parent_hash)) => {
+                picked.push(ptr);
+                number.checked_sub(1).map(|parent| (parent, parent_hash))
+            }
+            None => None,
+        };
     }
+    picked.reverse();
+    Ok(picked)
+}
+
+/// Returns the earliest `startBlock` of all data sources; nothing before
+/// this block can be relevant to the subgraph.
+fn start_block_from_subgraph(manifest: &SubgraphManifest) -> u64 {
+    manifest
+        .data_sources
+        .iter()
+        .filter(|data_source| data_source.has_contract())
+        .map(|data_source| data_source.source.start_block)
+        .min()
+        .unwrap_or(0)
 }
 
 fn create_log_filter_from_subgraph(manifest: &SubgraphManifest) -> Option<EthereumLogFilter> {
@@ -1269,15 +1750,52 @@ fn create_block_filter_from_subgraph(manifest: &SubgraphManifest) -> Option<Ethe
             .any(|block_handler| block_handler.filter.is_none());
         has_address && has_block_handler_without_filter
     });
-    if contract_addresses.is_empty() && !trigger_every_block {
+    let polling_intervals = manifest
+        .data_sources
+        .iter()
+        .filter(|data_source| data_source.source.address.is_some())
+        .flat_map(|data_source| data_source.mapping.block_handlers.iter())
+        .filter_map(|block_handler| match block_handler.filter {
+            Some(BlockHandlerFilter::Polling { every }) if every > 0 => Some(every),
+            _ => None,
+        })
+        .collect::<HashSet<u64>>();
+    if contract_addresses.is_empty() && !trigger_every_block && polling_intervals.is_empty() {
         return None;
     }
     return Some(EthereumBlockFilter {
         contract_addresses,
         trigger_every_block,
+        polling_intervals,
     });
 }
 
+fn create_entity_change_filter_from_subgraph(
+    manifest: &SubgraphManifest,
+) -> Option<EntityChangeFilter> {
+    let mut filter = EntityChangeFilter::default();
+    for data_source in manifest.data_sources.iter() {
+        let base_id = match data_source.source.subgraph {
+            Some(ref base_id) if data_source.is_subgraph() => base_id,
+            _ => continue,
+        };
+        let entity_types = filter
+            .entity_types
+            .entry(base_id.clone())
+            .or_insert_with(Vec::new);
+        for handler in data_source.mapping.entity_handlers.iter() {
+            if !entity_types.contains(&handler.entity) {
+                entity_types.push(handler.entity.clone());
+            }
+        }
+    }
+    if filter.entity_types.is_empty() {
+        None
+    } else {
+        Some(filter)
+    }
+}
+
 fn parse_triggers(
     log_filter_opt: Option<EthereumLogFilter>,
     call_filter_opt: Option<EthereumCallFilter>,
@@ -1285,21 +1803,28 @@ fn parse_triggers(
     descendant_block: &EthereumBlockWithCalls,
 ) -> Result<Vec<EthereumTrigger>, Error> {
     let mut triggers = Vec::new();
-    triggers.append(&mut parse_log_triggers(
-        log_filter_opt,
-        &descendant_block.ethereum_block,
-    ));
+    triggers.append(&mut parse_log_triggers(log_filter_opt, descendant_block));
     triggers.append(&mut parse_call_triggers(call_filter_opt, descendant_block));
     triggers.append(&mut parse_block_triggers(
         block_filter_opt,
         descendant_block,
     ));
-    let tx_hash_indexes = descendant_block
+    // Blocks that are loaded without receipts still have the index of each
+    // of their transactions
+    let mut tx_hash_indexes = descendant_block
         .ethereum_block
-        .transaction_receipts
+        .block
+        .transactions
         .iter()
-        .map(|receipt| (receipt.transaction_hash, receipt.transaction_index.as_u64()))
+        .filter_map(|tx| tx.transaction_index.map(|index| (tx.hash, index.as_u64())))
         .collect::<HashMap<H256, u64>>();
+    tx_hash_indexes.extend(
+        descendant_block
+            .ethereum_block
+            .transaction_receipts
+            .iter()
+            .map(|receipt| (receipt.transaction_hash, receipt.transaction_index.as_u64())),
+    );
 
     // Ensure all `Call` and `Log` triggers have a transaction index
     for trigger in triggers.iter() {
@@ -1324,7 +1849,7 @@ fn parse_triggers(
                     ));
                 }
             }
-            EthereumTrigger::Block(_) => continue,
+            EthereumTrigger::Block(_) | EthereumTrigger::EntityChange(_) => continue,
         }
     }
 
@@ -1349,20 +1874,24 @@ fn parse_triggers(
 
 fn parse_log_triggers(
     log_filter: Option<EthereumLogFilter>,
-    block: &EthereumBlock,
+    block: &EthereumBlockWithCalls,
 ) -> Vec<EthereumTrigger> {
     log_filter.map_or(vec![], |log_filter| {
+        // Blocks either come with their logs or with the receipts that
+        // contain the logs
         block
-            .transaction_receipts
+            .logs
             .iter()
-            .flat_map(move |receipt| {
-                let log_filter = log_filter.clone();
-                receipt
-                    .logs
+            .flatten()
+            .chain(
+                block
+                    .ethereum_block
+                    .transaction_receipts
                     .iter()
-                    .filter(move |log| log_filter.matches(log))
-                    .map(move |log| EthereumTrigger::Log(log.clone()))
-            })
+                    .flat_map(|receipt| receipt.logs.iter()),
+            )
+            .filter(|log| log_filter.matches(log))
+            .map(|log| EthereumTrigger::Log(log.clone()))
             .collect()
     })
 }
@@ -1388,6 +1917,8 @@ fn parse_block_triggers(
 ) -> Vec<EthereumTrigger> {
     block_filter.map_or(vec![], move |block_filter| {
         let trigger_every_block = block_filter.trigger_every_block;
+        let block_number = block.ethereum_block.block.number.unwrap().as_u64();
+        let polling_intervals = block_filter.polling_intervals_for_block(block_number);
         let call_filter = EthereumCallFilter::from(block_filter);
         let mut triggers = block.calls.as_ref().map_or(vec![], |calls| {
             calls
@@ -1401,6 +1932,79 @@ fn parse_block_triggers(
         if trigger_every_block {
             triggers.push(EthereumTrigger::Block(EthereumBlockTriggerType::Every));
         }
+        triggers.extend(
+            polling_intervals
+                .into_iter()
+                .map(|every| EthereumTrigger::Block(EthereumBlockTriggerType::Polling(every))),
+        );
         triggers
     })
 }
+
+#[test]
+fn canonical_block_pointers_follows_parent_hashes() {
+    let ptr = |number: u64, hash: u64| EthereumBlockPointer::from((H256::from(hash), number));
+    let candidates = vec![
+        (ptr(1, 10), H256::from(0)),
+        (ptr(3, 30), H256::from(20)),
+        (ptr(4, 40), H256::from(30)),
+        (ptr(4, 41), H256::from(31)),
+        (ptr(5, 50), H256::from(40)),
+    ];
+
+    // The uncle at #4 is skipped since #5 points to the other block
+    assert_eq!(
+        canonical_block_pointers(candidates.clone(), ptr(5, 50)).unwrap(),
+        vec![ptr(1, 10), ptr(3, 30), ptr(4, 40), ptr(5, 50)]
+    );
+
+    // Without a cached child, the blocks at #4 are ambiguous
+    assert!(canonical_block_pointers(candidates[..4].to_vec(), ptr(6, 60)).is_err());
+}
+
+#[test]
+fn log_triggers_are_found_in_logs_loaded_without_receipts() {
+    let transaction_hash = H256::from(100);
+    let log = |address: u64, signature: u64| Log {
+        address: Address::from(address),
+        topics: vec![H256::from(signature)],
+        data: Bytes(vec![]),
+        block_hash: None,
+        block_number: None,
+        transaction_hash: Some(transaction_hash),
+        transaction_index: Some(0.into()),
+        log_index: None,
+        transaction_log_index: None,
+        log_type: None,
+        removed: None,
+    };
+
+    let mut ethereum_block = EthereumBlock::default();
+    ethereum_block.block.transactions.push(Transaction {
+        hash: transaction_hash,
+        nonce: U256::zero(),
+        block_hash: None,
+        block_number: None,
+        transaction_index: Some(0.into()),
+        from: Address::zero(),
+        to: Some(Address::from(1)),
+        value: U256::zero(),
+        gas_price: U256::zero(),
+        gas: U256::zero(),
+        input: Bytes::default(),
+    });
+    let block = EthereumBlockWithCalls {
+        ethereum_block,
+        calls: None,
+        logs: Some(vec![log(1, 10), log(1, 11), log(2, 10)]),
+    };
+
+    let log_filter = vec![(Some(Address::from(1)), H256::from(10))]
+        .into_iter()
+        .collect::<EthereumLogFilter>();
+    let triggers = parse_triggers(Some(log_filter), None, None, &block).unwrap();
+    match triggers.as_slice() {
+        [EthereumTrigger::Log(trigger)] => assert_eq!(trigger, &log(1, 10)),
+        _ => panic!("unexpected triggers: {:?}", triggers),
+    }
+}
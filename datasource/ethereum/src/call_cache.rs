@@ -0,0 +1,96 @@
+use lru_time_cache::LruCache;
+use std::sync::{Arc, Mutex};
+
+use graph::prelude::*;
+use graph::util::ethereum::contract_call_id;
+use graph::web3::types::{Address, H256};
+
+/// Caches the results of contract calls, first in memory and then, if
+/// configured, in the store.
+///
+/// Calls are identified by their contract address, call data and block
+/// hash, so cached results stay valid across chain reorganizations.
+#[derive(Clone)]
+pub struct CallCache {
+    memory: Option<Arc<Mutex<LruCache<H256, Vec<u8>>>>>,
+    store: Option<Arc<EthereumCallCache>>,
+}
+
+impl CallCache {
+    /// Creates a cache that keeps the results of up to `capacity` calls in
+    /// memory. A capacity of zero disables the in-memory cache.
+    pub fn new(capacity: usize) -> Self {
+        CallCache {
+            memory: if capacity > 0 {
+                Some(Arc::new(Mutex::new(LruCache::with_capacity(capacity))))
+            } else {
+                None
+            },
+            store: None,
+        }
+    }
+
+    pub fn with_store(self, store: Arc<EthereumCallCache>) -> Self {
+        CallCache {
+            store: Some(store),
+            ..self
+        }
+    }
+
+    /// Returns the cached result of a call, if there is one. Results found
+    /// in the store are added to the in-memory cache.
+    pub fn get(
+        &self,
+        logger: &Logger,
+        address: Address,
+        call_data: &[u8],
+        block: EthereumBlockPointer,
+    ) -> Option<Vec<u8>> {
+        let call_id = contract_call_id(&address, call_data, &block.hash);
+
+        if let Some(ref memory) = self.memory {
+            if let Some(output) = memory.lock().unwrap().get(&call_id) {
+                return Some(output.clone());
+            }
+        }
+
+        let output = match self.store {
+            Some(ref store) => match store.get_call(address, call_data, block) {
+                Ok(output) => output,
+                Err(e) => {
+                    warn!(logger, "Failed to look up cached contract call";
+                          "error" => format!("{}", e));
+                    None
+                }
+            },
+            None => None,
+        };
+
+        if let (Some(memory), Some(output)) = (&self.memory, &output) {
+            memory.lock().unwrap().insert(call_id, output.clone());
+        }
+        output
+    }
+
+    /// Caches the result of a call in memory and in the store.
+    pub fn set(
+        &self,
+        logger: &Logger,
+        address: Address,
+        call_data: &[u8],
+        block: EthereumBlockPointer,
+        output: &[u8],
+    ) {
+        if let Some(ref memory) = self.memory {
+            let call_id = contract_call_id(&address, call_data, &block.hash);
+            memory.lock().unwrap().insert(call_id, output.to_vec());
+        }
+
+        if let Some(ref store) = self.store {
+            if let Err(e) = store.set_call(address, call_data, block, output) {
+                warn!(logger, "Failed to cache contract call";
+                      "error" => format!("{}", e));
+            }
+        }
+    }
+}
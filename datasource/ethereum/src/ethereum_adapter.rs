@@ -3,11 +3,14 @@ use futures::prelude::*;
 use graph::ethabi::Token;
 use lazy_static::lazy_static;
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tiny_keccak::keccak256;
 
+use call_cache::CallCache;
 use graph::components::ethereum::{EthereumAdapter as EthereumAdapterTrait, *};
 use graph::prelude::*;
+use graph::serde_json::{self, Value};
 use graph::web3;
 use graph::web3::api::Web3;
 use graph::web3::transports::batch::Batch;
@@ -16,6 +19,22 @@ use graph::web3::types::{Filter, *};
 #[derive(Clone)]
 pub struct EthereumAdapter<T: web3::Transport> {
     web3: Arc<Web3<T>>,
+    /// Set once the node rejected `trace_filter`; calls are then found with
+    /// `debug_traceBlockByNumber` instead.
+    trace_filter_unsupported: Arc<AtomicBool>,
+    /// Set once the node rejected a block hash as the block parameter of
+    /// `eth_call` (EIP-1898); calls are then made at the block number.
+    block_hash_param_unsupported: Arc<AtomicBool>,
+    /// Whether the node keeps the state of old blocks. If it doesn't,
+    /// contract calls against old blocks fail instead of being retried.
+    archive: bool,
+    /// The largest block range the node accepts for `eth_getLogs`, if it
+    /// limits it.
+    log_range_limit: Option<u64>,
+    call_cache: CallCache,
+    /// Whether contract calls are only answered from the call cache, e.g.
+    /// when replaying a subgraph without talking to the Ethereum node.
+    cached_calls_only: bool,
 }
 
 /// Number of chunks to request in parallel when streaming logs.
@@ -28,11 +47,191 @@ lazy_static! {
         .expect("invalid fast scan end block number (character encoding error)")
         .parse::<u64>()
         .expect("invalid fast scan end block number");
+
+    /// Number of contract call results to keep in memory.
+    static ref ETH_CALL_CACHE_SIZE: usize = ::std::env::var_os("GRAPH_ETH_CALL_CACHE_SIZE")
+        .unwrap_or_else(|| "10000".into())
+        .to_str()
+        .expect("invalid eth_call cache size (character encoding error)")
+        .parse::<usize>()
+        .expect("invalid eth_call cache size");
+
+    /// Number of `eth_getLogs` and `trace_filter` requests to have in flight
+    /// at once when scanning a block range for triggers.
+    static ref TRIGGER_REQUESTS_IN_PARALLEL: usize =
+        ::std::env::var_os("ETHEREUM_PARALLEL_TRIGGER_REQUESTS")
+            .unwrap_or_else(|| "10".into())
+            .to_str()
+            .expect("invalid number of parallel trigger requests (character encoding error)")
+            .parse::<usize>()
+            .expect("invalid number of parallel trigger requests");
+}
+
+/// Largest number of contract addresses to filter by in one `eth_getLogs` or
+/// `trace_filter` request. Subgraphs with more data sources than this are
+/// scanned with several requests in parallel.
+const ADDRESSES_PER_TRIGGER_REQUEST: usize = 500;
+
+/// Number of blocks to request traces for at once.
+const TRACE_STREAM_CHUNK_SIZE_IN_BLOCKS: u64 = 200;
+
+/// Splits `addresses` into groups of at most `ADDRESSES_PER_TRIGGER_REQUEST`.
+/// An empty list stays a single group, for requests without an address filter.
+fn address_chunks(addresses: &[H160]) -> Vec<Vec<H160>> {
+    if addresses.is_empty() {
+        vec![vec![]]
+    } else {
+        addresses
+            .chunks(ADDRESSES_PER_TRIGGER_REQUEST)
+            .map(|chunk| chunk.to_vec())
+            .collect()
+    }
 }
 
 /// Number of blocks to request in each chunk.
 const LOG_STREAM_CHUNK_SIZE_IN_BLOCKS: u64 = 10000;
 
+/// Number of attempts for an `eth_getLogs` request over more than one block
+/// before the block range is split in half.
+const LOG_RANGE_ATTEMPTS_BEFORE_SPLIT: usize = 3;
+
+/// Returns `true` if the Ethereum node rejected an `eth_getLogs` request
+/// because the block range would return too many logs or take too long.
+/// Providers like Infura and Alchemy fail such requests instead of
+/// returning partial results.
+fn is_log_range_too_large(error: &Error) -> bool {
+    let message = error.to_string().to_lowercase();
+    [
+        "query returned more than",
+        "response size exceeded",
+        "response size should not",
+        "block range is too wide",
+        "block range too large",
+        "query timeout exceeded",
+        "limit exceeded",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+/// Retry condition for RPC calls. Errors that may be transient, like
+/// connection problems or an overloaded node, are retried; requests that the
+/// Ethereum node rejected, like calls to unsupported methods or reverted
+/// contract calls, are not, since they would fail the same way again.
+fn retry_rpc_error<I>(result: &Result<I, Error>) -> bool {
+    match result {
+        Ok(_) => false,
+        Err(e) => e
+            .downcast_ref::<EthereumContractCallError>()
+            .map_or(true, |e| e.is_retriable()),
+    }
+}
+
+/// Largest block range to request logs for at once, starting at `start`,
+/// for a node that accepts ranges of up to `limit` blocks.
+fn max_log_range(start: u64, limit: Option<u64>) -> u64 {
+    let range = if start < *LOG_STREAM_FAST_SCAN_END {
+        100_000
+    } else {
+        1_000
+    };
+    limit.map_or(range, |limit| range.min(limit))
+}
+
+/// How far behind the chain head to probe for state when checking whether a
+/// node is an archive node. Pruning nodes keep the state of the most recent
+/// blocks only.
+const ARCHIVE_PROBE_DEPTH: u64 = 10_000;
+
+/// Block ranges to try `eth_getLogs` with when probing how large a range a
+/// node accepts, largest first.
+const LOG_RANGE_PROBES: &[u64] = &[100_000, 10_000, 1_000, 100];
+
+/// Returns `true` if the error says that the Ethereum node does not have the
+/// state of the block a request was made at.
+fn is_missing_state(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "missing trie node",
+        "state pruning",
+        "state is not available",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+/// Returns `true` if the Ethereum node rejected the parameters of a request,
+/// e.g. because it does not support a block hash as the block parameter.
+fn is_invalid_params(error: &web3::error::Error) -> bool {
+    match error.kind() {
+        web3::error::ErrorKind::Rpc(rpc_error) => rpc_error.code.code() == -32602,
+        _ => false,
+    }
+}
+
+/// Number of blocks to trace in parallel with `debug_traceBlockByNumber`.
+const DEBUG_TRACE_PARALLEL_BLOCKS: usize = 10;
+
+/// Returns `true` if the Ethereum node rejected the request, e.g. because it
+/// does not support the method.
+fn is_rejected(error: &Error) -> bool {
+    match error.downcast_ref::<EthereumContractCallError>() {
+        Some(EthereumContractCallError::Rejected(_)) => true,
+        _ => false,
+    }
+}
+
+/// Returns `true` if the trace is for a call that succeeded.
+fn is_successful_call(trace: &Trace) -> bool {
+    let is_call = match trace.action {
+        Action::Call(_) => true,
+        _ => false,
+    };
+    is_call && trace.result.is_some() && trace.error.is_none()
+}
+
+/// Collects the successful calls in a call frame returned by the
+/// `callTracer` of `debug_traceBlockByNumber`, including the internal calls
+/// it made, in the same order as `trace_filter` returns them.
+fn collect_traced_calls(
+    frame: &Value,
+    block_ptr: EthereumBlockPointer,
+    transaction_hash: Option<H256>,
+    calls: &mut Vec<EthereumCall>,
+) -> Result<(), Error> {
+    // A call that failed was reverted, including the calls it made
+    if frame.get("error").is_some() {
+        return Ok(());
+    }
+
+    let call_type = frame.get("type").and_then(Value::as_str).unwrap_or("");
+    if ["CALL", "CALLCODE", "DELEGATECALL", "STATICCALL"].contains(&call_type) {
+        let field = |name: &str| frame.get(name).cloned().unwrap_or(Value::Null);
+        calls.push(EthereumCall {
+            from: serde_json::from_value(field("from"))?,
+            to: serde_json::from_value(field("to"))?,
+            value: serde_json::from_value::<Option<U256>>(field("value"))?
+                .unwrap_or_else(U256::zero),
+            gas_used: serde_json::from_value::<Option<U256>>(field("gasUsed"))?
+                .unwrap_or_else(U256::zero),
+            input: serde_json::from_value::<Option<Bytes>>(field("input"))?
+                .unwrap_or_else(|| Bytes::from(vec![])),
+            output: serde_json::from_value::<Option<Bytes>>(field("output"))?
+                .unwrap_or_else(|| Bytes::from(vec![])),
+            block_number: block_ptr.number,
+            block_hash: block_ptr.hash,
+            transaction_hash,
+        });
+    }
+
+    if let Some(Value::Array(children)) = frame.get("calls") {
+        for child in children {
+            collect_traced_calls(child, block_ptr, transaction_hash, calls)?;
+        }
+    }
+    Ok(())
+}
+
 impl<T> EthereumAdapter<T>
 where
     T: web3::BatchTransport + Send + Sync + 'static,
@@ -41,9 +240,189 @@ where
     pub fn new(transport: T) -> Self {
         EthereumAdapter {
             web3: Arc::new(Web3::new(transport)),
+            trace_filter_unsupported: Arc::new(AtomicBool::new(false)),
+            block_hash_param_unsupported: Arc::new(AtomicBool::new(false)),
+            archive: true,
+            log_range_limit: None,
+            call_cache: CallCache::new(*ETH_CALL_CACHE_SIZE),
+            cached_calls_only: false,
+        }
+    }
+
+    /// Also caches the results of contract calls in `cache`, so that they
+    /// survive restarts and are shared with other nodes.
+    pub fn with_call_cache(self, cache: Arc<EthereumCallCache>) -> Self {
+        EthereumAdapter {
+            call_cache: self.call_cache.with_store(cache),
+            ..self
+        }
+    }
+
+    /// Fails contract calls that are not in the call cache instead of
+    /// sending them to the Ethereum node.
+    pub fn with_cached_calls_only(self) -> Self {
+        EthereumAdapter {
+            cached_calls_only: true,
+            ..self
         }
     }
 
+    /// Tells the adapter whether the node is an archive node, e.g. as found
+    /// out with `is_archive`.
+    pub fn with_archive(self, archive: bool) -> Self {
+        EthereumAdapter { archive, ..self }
+    }
+
+    /// Tells the adapter the largest block range the node accepts for
+    /// `eth_getLogs`, e.g. as found out with `probe_capabilities`.
+    pub fn with_log_range_limit(self, log_range_limit: Option<u64>) -> Self {
+        EthereumAdapter {
+            log_range_limit,
+            ..self
+        }
+    }
+
+    /// Finds out what the node can do: whether it is an archive node,
+    /// whether it can trace calls and how large a block range it accepts
+    /// for `eth_getLogs`.
+    pub fn probe_capabilities(
+        &self,
+        logger: &Logger,
+        provider: String,
+    ) -> impl Future<Item = EthereumProviderCapabilities, Error = Error> + Send {
+        self.is_archive(logger)
+            .join3(self.supports_traces(), self.probe_log_range_limit())
+            .map(
+                move |(archive, traces, max_log_range)| EthereumProviderCapabilities {
+                    provider,
+                    archive,
+                    traces,
+                    max_log_range,
+                },
+            )
+    }
+
+    /// Checks whether the node can trace the calls in a block, with either
+    /// `trace_filter` or `debug_traceBlockByNumber`.
+    fn supports_traces(&self) -> impl Future<Item = bool, Error = Error> + Send {
+        let web3 = self.web3.clone();
+
+        // Block 1 is cheap to trace on any chain; the genesis block can't
+        // be traced with `debug_traceBlockByNumber`
+        let mut filter = serde_json::Map::new();
+        filter.insert("fromBlock".to_owned(), Value::String("0x1".to_owned()));
+        filter.insert("toBlock".to_owned(), Value::String("0x1".to_owned()));
+        let mut options = serde_json::Map::new();
+        options.insert("tracer".to_owned(), Value::String("callTracer".to_owned()));
+
+        let probe = move |method: &'static str, params: Vec<Value>| {
+            web3::Transport::execute(web3.transport(), method, params)
+                .from_err::<EthereumContractCallError>()
+                .from_err::<Error>()
+                .then(|result| match result {
+                    Ok(_) => Ok(true),
+                    Err(ref e) if is_rejected(e) => Ok(false),
+                    Err(e) => Err(e),
+                })
+        };
+
+        probe("trace_filter", vec![Value::Object(filter)]).and_then(move |trace_filter| {
+            if trace_filter {
+                future::Either::A(future::ok(true))
+            } else {
+                future::Either::B(probe(
+                    "debug_traceBlockByNumber",
+                    vec![Value::String("0x1".to_owned()), Value::Object(options)],
+                ))
+            }
+        })
+    }
+
+    /// Finds the largest block range, out of `LOG_RANGE_PROBES`, that the
+    /// node accepts for `eth_getLogs`. Returns `None` if it accepts the
+    /// largest one.
+    fn probe_log_range_limit(&self) -> impl Future<Item = Option<u64>, Error = Error> + Send {
+        let web3 = self.web3.clone();
+
+        web3.eth()
+            .block_number()
+            .from_err::<EthereumContractCallError>()
+            .from_err()
+            .and_then(move |head| {
+                let head = head.as_u64();
+                future::loop_fn(0, move |i| {
+                    let range = LOG_RANGE_PROBES[i];
+
+                    // Logs of the zero address are rare, so the responses
+                    // stay small
+                    let filter = FilterBuilder::default()
+                        .from_block(head.saturating_sub(range - 1).into())
+                        .to_block(head.into())
+                        .address(vec![H160::zero()])
+                        .build();
+                    web3.eth()
+                        .logs(filter)
+                        .from_err::<EthereumContractCallError>()
+                        .from_err::<Error>()
+                        .then(move |result| match result {
+                            Ok(_) if i == 0 => Ok(future::Loop::Break(None)),
+                            Ok(_) => Ok(future::Loop::Break(Some(range))),
+                            Err(ref e)
+                                if is_log_range_too_large(e) && i + 1 < LOG_RANGE_PROBES.len() =>
+                            {
+                                Ok(future::Loop::Continue(i + 1))
+                            }
+                            Err(e) => Err(e),
+                        })
+                })
+            })
+    }
+
+    /// Checks whether the node keeps the state of old blocks, by asking for
+    /// a balance at a block whose state a pruning node has discarded.
+    pub fn is_archive(&self, logger: &Logger) -> impl Future<Item = bool, Error = Error> + Send {
+        let web3 = self.web3.clone();
+
+        retry("eth_blockNumber RPC call", logger)
+            .when(retry_rpc_error)
+            .no_limit()
+            .timeout_secs(30)
+            .run({
+                let web3 = web3.clone();
+                move || {
+                    web3.eth()
+                        .block_number()
+                        .from_err::<EthereumContractCallError>()
+                        .from_err()
+                }
+            })
+            .map_err(|e| {
+                e.into_inner().unwrap_or_else(|| {
+                    format_err!("Ethereum node took too long to return the latest block number")
+                })
+            })
+            .and_then(move |head| {
+                let head = head.as_u64();
+                if head < ARCHIVE_PROBE_DEPTH {
+                    // Even pruning nodes have the state of all blocks
+                    return future::Either::A(future::ok(true));
+                }
+
+                future::Either::B(
+                    web3.eth()
+                        .balance(H160::zero(), Some((head - ARCHIVE_PROBE_DEPTH).into()))
+                        .then(|result| match result {
+                            Ok(_) => Ok(true),
+                            Err(ref e) if is_missing_state(&e.to_string()) => Ok(false),
+                            Err(e) => Err(format_err!(
+                                "failed to check whether Ethereum node is an archive node: {}",
+                                e
+                            )),
+                        }),
+                )
+            })
+    }
+
     fn traces(
         &self,
         logger: &Logger,
@@ -55,6 +434,7 @@ where
         let logger = logger.to_owned();
 
         retry("trace_filter RPC call", &logger)
+            .when(retry_rpc_error)
             .no_limit()
             .timeout_secs(60)
             .run(move || {
@@ -128,8 +508,24 @@ where
         let logger = logger.to_owned();
         let event_sig_count = event_signatures.len();
 
-        retry("eth_getLogs RPC call", &logger)
-            .no_limit()
+        // Requests over more than one block give up after a few attempts so
+        // that `log_stream` can split the block range; requests for a single
+        // block are retried until they succeed
+        let can_split = from < to;
+        let logs_retry =
+            retry("eth_getLogs RPC call", &logger).when(move |result: &Result<Vec<Log>, Error>| {
+                match result {
+                    Ok(_) => false,
+                    Err(e) => retry_rpc_error(result) && !(can_split && is_log_range_too_large(e)),
+                }
+            });
+        let logs_retry = if can_split {
+            logs_retry.limit(LOG_RANGE_ATTEMPTS_BEFORE_SPLIT)
+        } else {
+            logs_retry.no_limit()
+        };
+
+        logs_retry
             .timeout_secs(60)
             .run(move || {
                 // Create a log filter
@@ -137,7 +533,13 @@ where
                     .from_block(from.into())
                     .to_block(to.into())
                     .address(addresses.clone())
-                    .topics(Some(event_signatures.clone()), None, None, None)
+                    .topics(
+                        // Without event signatures, logs with any topics match
+                        Some(event_signatures.clone()).filter(|sigs| !sigs.is_empty()),
+                        None,
+                        None,
+                        None,
+                    )
                     .build();
 
                 // Request logs from client
@@ -182,21 +584,138 @@ where
 
         let eth = self.clone();
         let logger = logger.to_owned();
-        stream::unfold(from, move |start| {
-            if start > to {
-                return None;
-            }
-            let end = (start + 200 - 1).min(to);
-            let new_start = end + 1;
-            debug!(
+        let address_chunks = address_chunks(&addresses);
+
+        // Block ranges, and groups of addresses within each range, are
+        // requested in parallel; the traces of each range come out in order
+        let ranges = (from..=to)
+            .step_by(TRACE_STREAM_CHUNK_SIZE_IN_BLOCKS as usize)
+            .map(move |start| {
+                (
+                    start,
+                    (start + TRACE_STREAM_CHUNK_SIZE_IN_BLOCKS - 1).min(to),
+                )
+            });
+        stream::iter_ok(ranges)
+            .map(move |(start, end)| {
+                debug!(
+                    logger,
+                    "Starting request in for traces block range: [{}, {}]", start, end
+                );
+                let requests = address_chunks
+                    .iter()
+                    .map(|addresses| eth.traces(&logger, start, end, addresses.clone()))
+                    .collect::<Vec<_>>();
+                future::join_all(requests).map(|traces| {
+                    // Calls of the same block come out of the requests in
+                    // any order; restore the order in which they were made
+                    let mut traces = traces.into_iter().flatten().collect::<Vec<_>>();
+                    traces.sort_by(|a, b| {
+                        (a.block_number, a.transaction_position, &a.trace_address).cmp(&(
+                            b.block_number,
+                            b.transaction_position,
+                            &b.trace_address,
+                        ))
+                    });
+                    traces
+                })
+            })
+            .buffered(*TRIGGER_REQUESTS_IN_PARALLEL)
+    }
+
+    /// Finds the successful calls in a block, including internal calls,
+    /// with the `callTracer` of `debug_traceBlockByNumber`. This is for nodes
+    /// that don't support `trace_filter`, like Geth. Returns the hash of the
+    /// traced block along with the calls.
+    fn debug_trace_calls(
+        &self,
+        logger: &Logger,
+        block_number: u64,
+    ) -> impl Future<Item = (H256, Vec<EthereumCall>), Error = Error> + Send {
+        let web3 = self.web3.clone();
+
+        retry("debug_traceBlockByNumber RPC call", &logger)
+            .when(retry_rpc_error)
+            .no_limit()
+            .timeout_secs(120)
+            .run(move || {
+                let mut options = serde_json::Map::new();
+                options.insert("tracer".to_owned(), Value::String("callTracer".to_owned()));
+
+                let block = web3
+                    .eth()
+                    .block(BlockId::Number(block_number.into()))
+                    .from_err::<EthereumContractCallError>()
+                    .from_err();
+                let traces = web3::Transport::execute(
+                    web3.transport(),
+                    "debug_traceBlockByNumber",
+                    vec![
+                        Value::String(format!("0x{:x}", block_number)),
+                        Value::Object(options),
+                    ],
+                )
+                .from_err::<EthereumContractCallError>()
+                .from_err();
+
+                block.join(traces).and_then(move |(block_opt, traces)| {
+                    let block = block_opt.ok_or_else(|| {
+                        format_err!("Ethereum node could not find block #{}", block_number)
+                    })?;
+                    let block_hash = block.hash.ok_or_else(|| {
+                        format_err!(
+                            "Ethereum node returned block #{} without hash",
+                            block_number
+                        )
+                    })?;
+                    let traces = match traces {
+                        Value::Array(traces) => traces,
+                        _ => {
+                            return Err(format_err!(
+                                "invalid debug_traceBlockByNumber response for block #{}",
+                                block_number
+                            ))
+                        }
+                    };
+
+                    // One trace per transaction, in order; newer nodes wrap
+                    // each trace in a `result` object
+                    let block_ptr = EthereumBlockPointer::from((block_hash, block_number));
+                    let mut calls = vec![];
+                    for (i, trace) in traces.iter().enumerate() {
+                        let frame = trace.get("result").unwrap_or(trace);
+                        let transaction_hash = block.transactions.get(i).cloned();
+                        collect_traced_calls(frame, block_ptr, transaction_hash, &mut calls)?;
+                    }
+                    Ok((block_hash, calls))
+                })
+            })
+            .map_err(move |e| {
+                e.into_inner().unwrap_or_else(move || {
+                    format_err!(
+                        "Ethereum node took too long to trace block #{}",
+                        block_number
+                    )
+                })
+            })
+    }
+
+    /// Remembers that the node does not support `trace_filter`, if `error`
+    /// says so. Returns `true` if calls should be found with
+    /// `debug_traceBlockByNumber` instead.
+    fn fall_back_to_debug_trace(&self, logger: &Logger, error: &Error) -> bool {
+        if !is_rejected(error) {
+            return false;
+        }
+        if !self.trace_filter_unsupported.swap(true, Ordering::SeqCst) {
+            warn!(
                 logger,
-                "Starting request in for traces block range: [{}, {}]", start, end
+                "Ethereum node does not support trace_filter, \
+                 falling back to debug_traceBlockByNumber";
+                "error" => error.to_string(),
             );
-            Some(
-                eth.traces(&logger, start, end, addresses.clone())
-                    .map(move |traces| (traces, new_start)),
-            )
-        })
+        }
+        true
     }
 
     fn log_stream(
@@ -216,93 +735,126 @@ where
         let eth = self.clone();
         let logger = logger.to_owned();
 
-        let event_sigs = log_filter
-            .contract_address_and_event_sig_pairs
-            .iter()
-            .map(|(_addr, sig)| *sig)
-            .collect::<HashSet<H256>>()
-            .into_iter()
-            .collect::<Vec<H256>>();
-        // Collect all contract addresses; if we have a data source without a contract
-        // address, we can't add addresses to the filter because it would only match
-        // the contracts for which we _have_ addresses; therefore if we have a data source
-        // without a contract address, we perform a broader logs scan and filter out
-        // irrelevant events ourselves.
+        // Split the filter into one `eth_getLogs` request per group of contracts
+        // with the same event handlers, plus one request without an address
+        // filter for data sources without a contract address. This way the
+        // node only downloads logs with a topic0 that some handler of the
+        // emitting contract cares about.
         //
-        // Our own filtering is performed later when the events are passed to
-        // subgraphs and runtime hosts for processing:
+        // Logs may still match an event signature but not the exact
+        // (address, signature) pair, so they are filtered again below, and
+        // later when the events are passed to subgraphs and runtime hosts:
         // - At the top level in `BlockStreamContext::do_step`
         // - At the subgraph level in `SubgraphInstance::matches_log`
         // - At the data source level in `RuntimeHost::matches_log`
-        let addresses = if log_filter
-            .contract_address_and_event_sig_pairs
-            .iter()
-            .any(|(addr, _)| addr.is_none())
-        {
-            vec![]
-        } else {
+        let eth_get_logs_filters = Arc::new(
             log_filter
-                .contract_address_and_event_sig_pairs
-                .iter()
-                .map(|(addr, _sig)| match addr {
-                    None => unreachable!(
-                        "shouldn't include addresses in Ethereum logs filter \
-                         if there are data sources without a contract address"
-                    ),
-                    Some(addr) => *addr,
-                })
-                .collect::<HashSet<H160>>()
+                .eth_get_logs_filters()
                 .into_iter()
-                .collect::<Vec<H160>>()
-        };
-        stream::unfold(from, move |start| {
+                .flat_map(|(addresses, event_sigs)| {
+                    address_chunks(&addresses)
+                        .into_iter()
+                        .map(move |addresses| (addresses, event_sigs.clone()))
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        // The block range of each request adapts to what the Ethereum node
+        // can handle: if a request fails, the range is split in half and the
+        // first half is requested again; after each successful request, the
+        // range grows back towards the maximum.
+        let log_range_limit = self.log_range_limit;
+        let initial_range = max_log_range(from, log_range_limit);
+        stream::unfold((from, initial_range), move |(start, range)| {
             if start > to {
                 return None;
             }
-            let end = if start < *LOG_STREAM_FAST_SCAN_END {
-                (start + 100_000 - 1).min(to).min(*LOG_STREAM_FAST_SCAN_END)
-            } else {
-                (start + 1_000 - 1).min(to)
-            };
-            let new_start = end + 1;
-
-            debug!(
-                logger,
-                "Starting request for logs in block range: [{}, {}]", start, end
-            );
 
+            let eth = eth.clone();
+            let logger = logger.clone();
             let log_filter = log_filter.clone();
+            let eth_get_logs_filters = eth_get_logs_filters.clone();
+
             Some(
-                eth.logs_with_sigs(&logger, start, end, addresses.clone(), event_sigs.clone())
-                    .map(move |logs| {
-                        logs.into_iter()
-                            .filter(move |log| log_filter.matches(log))
-                            .collect()
+                future::loop_fn(range, move |range| {
+                    let mut end = (start + range - 1).min(to);
+                    if start < *LOG_STREAM_FAST_SCAN_END {
+                        end = end.min(*LOG_STREAM_FAST_SCAN_END);
+                    }
+
+                    debug!(
+                        logger,
+                        "Starting request for logs in block range: [{}, {}]", start, end
+                    );
+
+                    let eth = eth.clone();
+                    let request_logger = logger.clone();
+                    let requests = stream::iter_ok::<_, Error>(eth_get_logs_filters.to_vec())
+                        .map(move |(addresses, event_sigs)| {
+                            eth.logs_with_sigs(&request_logger, start, end, addresses, event_sigs)
+                        })
+                        .buffer_unordered(*TRIGGER_REQUESTS_IN_PARALLEL)
+                        .collect();
+                    let logger = logger.clone();
+                    requests.then(move |result| match result {
+                        Ok(logs) => Ok(future::Loop::Break((logs, end, end - start + 1))),
+                        Err(e) if end > start => {
+                            let range = (end - start + 1) / 2;
+                            warn!(
+                                logger,
+                                "Failed to get logs for block range, splitting it";
+                                "from" => start,
+                                "to" => end,
+                                "new_range" => range,
+                                "error" => e.to_string(),
+                            );
+                            Ok(future::Loop::Continue(range))
+                        }
+                        Err(e) => Err(e),
                     })
-                    .map(move |logs| (logs, new_start)),
+                })
+                .map(move |(logs, end, range)| {
+                    // Requests finish in any order, but logs are expected
+                    // in the order they were emitted in
+                    let mut logs = logs
+                        .into_iter()
+                        .flatten()
+                        .filter(move |log| log_filter.matches(log))
+                        .collect::<Vec<_>>();
+                    logs.sort_by_key(|log| (log.block_number, log.log_index));
+                    let new_start = end + 1;
+                    let new_range = (range * 2).min(max_log_range(new_start, log_range_limit));
+                    (logs, (new_start, new_range))
+                }),
             )
         })
     }
 
+    /// Calls a contract at the state of `block_ptr`. The block is passed
+    /// by hash where the node supports it (EIP-1898), so that the call is
+    /// not made on a different block with the same number after a reorg.
+    /// Returns the output of the call and whether it was made by hash.
     fn call(
         &self,
         logger: &Logger,
         contract_address: Address,
         call_data: Bytes,
-        block_number_opt: Option<BlockNumber>,
-    ) -> impl Future<Item = Bytes, Error = Error> + Send {
+        block_ptr: EthereumBlockPointer,
+    ) -> impl Future<Item = (Bytes, bool), Error = Error> + Send {
         let web3 = self.web3.clone();
         let logger = logger.clone();
+        let block_hash_param_unsupported = self.block_hash_param_unsupported.clone();
+        let archive = self.archive;
 
         // Outer retry used only for 0-byte responses,
         // where we can't guarantee the problem is temporary.
         // If we keep getting back 0-byte responses,
         // eventually we assume it's right and return it.
         retry("eth_call RPC call (outer)", &logger)
-            .when(|result: &Result<Bytes, _>| {
+            .when(|result: &Result<(Bytes, bool), _>| {
                 match result {
                     // Retry only if zero-length response received
-                    Ok(bytes) => bytes.0.is_empty(),
+                    Ok((bytes, _)) => bytes.0.is_empty(),
 
                     // Errors are retried in the inner retry
                     Err(_) => false,
@@ -313,29 +865,93 @@ where
             .no_timeout()
             .run(move || {
                 let web3 = web3.clone();
+                let logger = logger.clone();
                 let call_data = call_data.clone();
+                let block_hash_param_unsupported = block_hash_param_unsupported.clone();
 
                 retry("eth_call RPC call", &logger)
+                    .when(move |result: &Result<(Bytes, bool), Error>| match result {
+                        // Without an archive node, missing state won't show up
+                        Err(e) if !archive && is_missing_state(&e.to_string()) => false,
+                        result => retry_rpc_error(result),
+                    })
                     .no_limit()
                     .timeout_secs(60)
                     .run(move || {
-                        let req = CallRequest {
+                        let req = web3::helpers::serialize(&CallRequest {
                             from: None,
                             to: contract_address,
                             gas: None,
                             gas_price: None,
                             value: None,
                             data: Some(call_data.clone()),
+                        });
+                        let block_number = Value::String(format!("0x{:x}", block_ptr.number));
+
+                        let execute = {
+                            let web3 = web3.clone();
+                            move |block: Value| {
+                                web3::Transport::execute(
+                                    web3.transport(),
+                                    "eth_call",
+                                    vec![req.clone(), block],
+                                )
+                            }
+                        };
+
+                        let result = if block_hash_param_unsupported.load(Ordering::SeqCst) {
+                            future::Either::A(execute(block_number).map(|output| (output, false)))
+                        } else {
+                            let mut block_hash = serde_json::Map::new();
+                            block_hash.insert(
+                                "blockHash".to_owned(),
+                                Value::String(format!("0x{:x}", block_ptr.hash)),
+                            );
+
+                            let by_hash = execute(Value::Object(block_hash));
+
+                            let logger = logger.clone();
+                            let block_hash_param_unsupported = block_hash_param_unsupported.clone();
+                            let by_number = move |e: web3::error::Error| {
+                                if !is_invalid_params(&e) {
+                                    return future::Either::A(future::err(e));
+                                }
+                                if !block_hash_param_unsupported.swap(true, Ordering::SeqCst) {
+                                    warn!(
+                                        logger,
+                                        "Ethereum node does not support calls by block hash \
+                                         (EIP-1898), calling by block number instead";
+                                        "error" => e.to_string(),
+                                    );
+                                }
+                                future::Either::B(
+                                    execute(block_number).map(|output| (output, false)),
+                                )
+                            };
+                            future::Either::B(
+                                by_hash.map(|output| (output, true)).or_else(by_number),
+                            )
                         };
-                        web3.eth()
-                            .call(req, block_number_opt)
+
+                        result
                             .from_err::<EthereumContractCallError>()
                             .from_err()
+                            .and_then(|(output, by_hash)| {
+                                serde_json::from_value(output)
+                                    .map(|output| (output, by_hash))
+                                    .map_err(Error::from)
+                            })
                     })
-                    .map_err(|e| {
-                        e.into_inner().unwrap_or_else(|| {
-                            format_err!("Ethereum node took too long to perform function call")
-                        })
+                    .map_err(move |e| match e.into_inner() {
+                        Some(ref e) if !archive && is_missing_state(&e.to_string()) => {
+                            EthereumContractCallError::ArchiveNodeRequired(
+                                block_ptr.number,
+                                format_err!("{}", e),
+                            )
+                            .into()
+                        }
+                        Some(e) => e,
+                        None => format_err!("Ethereum node took too long to perform function call"),
                     })
             })
     }
@@ -355,6 +971,7 @@ where
 
         let web3 = self.web3.clone();
         let net_version_future = retry("net_version RPC call", &logger)
+            .when(retry_rpc_error)
             .no_limit()
             .timeout_secs(20)
             .run(move || {
@@ -366,6 +983,7 @@ where
 
         let web3 = self.web3.clone();
         let gen_block_hash_future = retry("eth_getBlockByNumber(0, false) RPC call", &logger)
+            .when(retry_rpc_error)
             .no_limit()
             .timeout_secs(30)
             .run(move || {
@@ -440,6 +1058,7 @@ where
 
         Box::new(
             retry("eth_getBlockByHash RPC call", &logger)
+                .when(retry_rpc_error)
                 .no_limit()
                 .timeout_secs(60)
                 .run(move || {
@@ -456,6 +1075,45 @@ where
         )
     }
 
+    fn block_header_by_hash(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<Future<Item = Option<Block<Transaction>>, Error = Error> + Send> {
+        let web3 = self.web3.clone();
+        let logger = logger.clone();
+
+        Box::new(
+            retry("eth_getBlockByHash RPC call", &logger)
+                .when(retry_rpc_error)
+                .no_limit()
+                .timeout_secs(60)
+                .run(move || {
+                    web3.eth()
+                        .block(BlockId::Hash(block_hash))
+                        .from_err::<EthereumContractCallError>()
+                        .from_err()
+                })
+                .map_err(move |e| {
+                    e.into_inner().unwrap_or_else(move || {
+                        format_err!("Ethereum node took too long to return block {}", block_hash)
+                    })
+                })
+                .and_then(|block_opt| -> Result<_, Error> {
+                    // The header is the same for blocks with transaction
+                    // hashes and blocks with full transactions
+                    match block_opt {
+                        Some(block) => {
+                            let mut block = serde_json::to_value(block)?;
+                            block["transactions"] = Value::Array(vec![]);
+                            Ok(Some(serde_json::from_value(block)?))
+                        }
+                        None => Ok(None),
+                    }
+                }),
+        )
+    }
+
     fn load_full_block(
         &self,
         logger: &Logger,
@@ -577,6 +1235,7 @@ where
 
         Box::new(
             retry("eth_getBlockByHash RPC call", &logger)
+                .when(retry_rpc_error)
                 .no_limit()
                 .timeout_secs(60)
                 .run(move || {
@@ -606,6 +1265,7 @@ where
 
         Box::new(
             retry("eth_getBlockByNumber RPC call", &logger)
+                .when(retry_rpc_error)
                 .no_limit()
                 .timeout_secs(60)
                 .run(move || {
@@ -650,11 +1310,29 @@ where
         block_hash: H256,
     ) -> Box<Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
         let eth = self.clone();
-        let call_filter = EthereumCallFilter {
-            contract_addresses_function_signatures: HashMap::new(),
+        let logger = logger.clone();
+
+        let debug_trace_calls = move |eth: &Self, logger: &Logger| {
+            eth.debug_trace_calls(logger, block_number).and_then(
+                move |(traced_block_hash, calls)| {
+                    if traced_block_hash != block_hash {
+                        return Err(format_err!(
+                            "Ethereum node traced an unexpected block: number = {}, hash = {}",
+                            block_number,
+                            block_hash,
+                        ));
+                    }
+                    Ok(calls)
+                },
+            )
         };
+
+        if self.trace_filter_unsupported.load(Ordering::SeqCst) {
+            return Box::new(debug_trace_calls(&eth, &logger));
+        }
+
         let addresses = Vec::new();
-        let calls = eth.trace_stream(&logger, block_number, block_number, addresses)
+        let calls = eth.clone().trace_stream(&logger, block_number, block_number, addresses)
             .collect()
             .map(|trace_chunks| match trace_chunks.len() {
                 0 => vec![],
@@ -692,22 +1370,44 @@ where
             .map(move |traces| {
                 traces
                     .iter()
-                    .filter(|trace| {
-                        let is_call = match trace.action {
-                            Action::Call(_) => true,
-                            _ => false,
-                        };
-                        if !is_call || trace.result.is_none() || trace.error.is_some() {
-                            return false
-                        }
-                        true
-                    })
+                    .filter(|trace| is_successful_call(trace))
                     .map(EthereumCall::from)
                     .collect()
+            })
+            .or_else(move |e| -> Box<Future<Item = _, Error = _> + Send> {
+                if eth.fall_back_to_debug_trace(&logger, &e) {
+                    Box::new(debug_trace_calls(&eth, &logger))
+                } else {
+                    Box::new(future::err(e))
+                }
             });
         Box::new(calls)
     }
 
+    fn logs_in_block(
+        &self,
+        logger: &Logger,
+        block_number: u64,
+        block_hash: H256,
+    ) -> Box<Future<Item = Vec<Log>, Error = Error> + Send> {
+        // Logs can only be requested by block number, so the logs are
+        // checked to belong to the expected block, as in `calls_in_block`
+        Box::new(
+            self.logs_with_sigs(logger, block_number, block_number, vec![], vec![])
+                .and_then(move |logs| {
+                    if logs.iter().any(|log| log.block_hash != Some(block_hash)) {
+                        return Err(format_err!(
+                            "Ethereum node returned logs for an unexpected block: \
+                             number = {}, hash = {}",
+                            block_number,
+                            block_hash,
+                        ));
+                    }
+                    Ok(logs)
+                }),
+        )
+    }
+
     fn blocks_with_triggers(
         &self,
         logger: &Logger,
@@ -752,6 +1452,31 @@ where
             }
             if block_filter_opt.is_some() {
                 let block_filter = block_filter_opt.unwrap();
+                if !block_filter.polling_intervals.is_empty() {
+                    // Only the blocks whose number is a multiple of one of the
+                    // polling intervals contain a trigger, so only those are
+                    // fetched
+                    let eth = eth.clone();
+                    let logger = logger.clone();
+                    block_futs.push(Box::new(
+                        stream::iter_ok(block_filter.polling_block_numbers(from, to))
+                            .map(move |number| {
+                                eth.block_hash_by_block_number(&logger, number).and_then(
+                                    move |hash| {
+                                        hash.map(|hash| EthereumBlockPointer { hash, number })
+                                            .ok_or_else(|| {
+                                                format_err!(
+                                                    "Ethereum node is missing block #{}",
+                                                    number
+                                                )
+                                            })
+                                    },
+                                )
+                            })
+                            .buffered(*TRIGGER_REQUESTS_IN_PARALLEL)
+                            .collect(),
+                    ));
+                }
                 match block_filter.contract_addresses.len() {
                     0 => (),
                     _ => {
@@ -870,47 +1595,69 @@ where
         call_filter: EthereumCallFilter,
     ) -> Box<Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
         let eth = self.clone();
+        let logger = logger.clone();
 
-        let addresses: Vec<H160> = call_filter
-            .contract_addresses_function_signatures
-            .iter()
-            .map(|(addr, _fsigs)| *addr)
-            .collect::<HashSet<H160>>()
-            .into_iter()
-            .collect::<Vec<H160>>();
-        let blocks = eth
-            .trace_stream(&logger, from, to, addresses)
-            .collect()
-            .map(move |trace_chunks| {
-                match trace_chunks.len() {
-                    0 => vec![],
-                    _ => {
-                        trace_chunks
-                            .iter()
-                            .flatten()
-                            .filter(|trace| {
-                                let is_call = match trace.action {
-                                    Action::Call(_) => true,
-                                    _ => false,
-                                };
-                                // Remove traces that are not for a call, do not have a result, or
-                                // are for a transaction which errored.
-                                if !is_call || trace.result.is_none() || trace.error.is_some() {
-                                    return false;
-                                }
-                                true
-                            })
-                            .map(EthereumCall::from)
-                            .filter(|call| {
-                                // `trace_filter` can only filter by calls `to` an address and
-                                // a block range. Since subgraphs are subscribing to calls
-                                // for a specific contract function an additional filter needs
-                                // to be applied
-                                call_filter.matches(&call)
-                            })
-                            .collect()
-                    }
-                }
+        // Without `trace_filter`, every block in the range has to be traced
+        let debug_trace_calls = move |eth: &Self, logger: &Logger| {
+            let eth = eth.clone();
+            let logger = logger.clone();
+            stream::iter_ok::<_, Error>(from..=to)
+                .map(move |block_number| {
+                    eth.debug_trace_calls(&logger, block_number)
+                        .map(|(_, calls)| calls)
+                })
+                .buffered(DEBUG_TRACE_PARALLEL_BLOCKS)
+                .concat2()
+        };
+
+        let calls: Box<Future<Item = Vec<EthereumCall>, Error = Error> + Send> =
+            if self.trace_filter_unsupported.load(Ordering::SeqCst) {
+                Box::new(debug_trace_calls(&eth, &logger))
+            } else {
+                let addresses: Vec<H160> = call_filter
+                    .contract_addresses_function_signatures
+                    .iter()
+                    .map(|(addr, _fsigs)| *addr)
+                    .collect::<HashSet<H160>>()
+                    .into_iter()
+                    .collect::<Vec<H160>>();
+                let eth = eth.clone();
+                Box::new(
+                    eth.clone()
+                        .trace_stream(&logger, from, to, addresses)
+                        .collect()
+                        .map(move |trace_chunks| {
+                            trace_chunks
+                                .iter()
+                                .flatten()
+                                // Remove traces that are not for a call, do not have a
+                                // result, or are for a transaction which errored.
+                                .filter(|trace| is_successful_call(trace))
+                                .map(EthereumCall::from)
+                                .collect()
+                        })
+                        .or_else(move |e| -> Box<Future<Item = _, Error = _> + Send> {
+                            if eth.fall_back_to_debug_trace(&logger, &e) {
+                                Box::new(debug_trace_calls(&eth, &logger))
+                            } else {
+                                Box::new(future::err(e))
+                            }
+                        }),
+                )
+            };
+
+        let blocks = calls
+            .map(move |calls| {
+                calls
+                    .into_iter()
+                    .filter(|call| {
+                        // `trace_filter` can only filter by calls `to` an address and
+                        // a block range. Since subgraphs are subscribing to calls
+                        // for a specific contract function an additional filter needs
+                        // to be applied
+                        call_filter.matches(&call)
+                    })
+                    .collect::<Vec<_>>()
             })
             .map(|calls| {
                 let mut block_ptrs = vec![];
@@ -952,20 +1699,57 @@ where
         // Encode the call parameters according to the ABI
         let call_data = call.function.encode_input(&call.args).unwrap();
 
+        // Mappings often make the same call over and over again, e.g. to
+        // look up the decimals of a token
+        if let Some(output) = self
+            .call_cache
+            .get(logger, call.address, &call_data, call.block_ptr)
+        {
+            return Box::new(future::result(
+                call.function
+                    .decode_output(&output)
+                    .map_err(EthereumContractCallError::from),
+            ));
+        }
+        if self.cached_calls_only {
+            return Box::new(future::err(EthereumContractCallError::Rejected(
+                format_err!(
+                    "contract call to {:x} at block #{} is not in the call cache",
+                    call.address,
+                    call.block_ptr.number
+                ),
+            )));
+        }
+
+        let logger = logger.clone();
+        let call_cache = self.call_cache.clone();
         Box::new(
             // Make the actual function call
             self.call(
-                logger,
+                &logger,
                 call.address,
-                Bytes(call_data),
-                Some(call.block_ptr.number.into()),
+                Bytes(call_data.clone()),
+                call.block_ptr,
             )
-            .map_err(EthereumContractCallError::from)
-            .and_then(move |output| {
+            .map_err(|e| match e.downcast::<EthereumContractCallError>() {
+                Ok(e) => e,
+                Err(e) => EthereumContractCallError::from(e),
+            })
+            .and_then(move |(output, by_hash)| {
                 // Decode the return values according to the ABI
-                call.function
+                let tokens = call
+                    .function
                     .decode_output(&output.0)
-                    .map_err(EthereumContractCallError::from)
+                    .map_err(EthereumContractCallError::from)?;
+
+                // Only cache results that could be decoded, and only if the
+                // call was made on the block with the hash they are cached
+                // under; a call by number may have been made on a block
+                // that a reorg replaced
+                if by_hash {
+                    call_cache.set(&logger, call.address, &call_data, call.block_ptr, &output.0);
+                }
+                Ok(tokens)
             }),
         )
     }
@@ -3,14 +3,19 @@ extern crate futures;
 extern crate graph;
 extern crate jsonrpc_core;
 extern crate lazy_static;
+extern crate lru_time_cache;
 extern crate tiny_keccak;
 
 mod block_ingestor;
 mod block_stream;
+mod call_cache;
 mod ethereum_adapter;
+mod provider_pool;
+mod rate_limiter;
 mod transport;
 
 pub use self::block_ingestor::BlockIngestor;
-pub use self::block_stream::{BlockStream, BlockStreamBuilder};
+pub use self::block_stream::{cached_blocks_from_subgraph, BlockStream, BlockStreamBuilder};
 pub use self::ethereum_adapter::EthereumAdapter;
+pub use self::provider_pool::{Provider, ProviderCapability};
 pub use self::transport::{EventLoopHandle, Transport};
@@ -0,0 +1,447 @@
+use futures::future;
+use futures::prelude::*;
+use graph::serde_json::Value;
+use jsonrpc_core::types::{Call, MethodCall};
+use std::cmp;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use graph::prelude::{format_err, info, o, warn, Error, Logger};
+use graph::web3;
+use graph::web3::error::ErrorKind;
+use graph::web3::helpers::build_request;
+use graph::web3::RequestId;
+
+use transport::Transport;
+
+/// How long a provider is skipped after its first failure. The delay doubles
+/// with every consecutive failure, up to `PROVIDER_MAX_BACKOFF`.
+const PROVIDER_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Upper bound for how long an unhealthy provider is skipped.
+const PROVIDER_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Something an Ethereum node can do that not all nodes can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProviderCapability {
+    /// The node keeps the state of old blocks, so contract calls against
+    /// historical blocks succeed.
+    Archive,
+    /// The node supports the `trace_*` methods.
+    Traces,
+}
+
+impl ProviderCapability {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "archive" => Some(ProviderCapability::Archive),
+            "traces" => Some(ProviderCapability::Traces),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ProviderCapability::Archive => "archive",
+            ProviderCapability::Traces => "traces",
+        }
+    }
+
+    /// The capability that requests for `method` should preferably be sent
+    /// to providers with.
+    fn required_by(method: &str) -> Option<Self> {
+        if method.starts_with("trace_") {
+            Some(ProviderCapability::Traces)
+        } else if method == "eth_call" {
+            Some(ProviderCapability::Archive)
+        } else {
+            None
+        }
+    }
+}
+
+/// An Ethereum node that is one of several for the same network.
+pub struct Provider {
+    pub name: String,
+    pub transport: Transport,
+    pub weight: usize,
+    pub capabilities: Vec<ProviderCapability>,
+}
+
+#[derive(Default)]
+struct ProviderHealth {
+    consecutive_failures: u32,
+    unhealthy_until: Option<Instant>,
+}
+
+/// Spreads requests across several Ethereum nodes for the same network,
+/// in proportion to their weights.
+///
+/// Requests that need a capability, like `trace_filter`, go to the
+/// providers that have it if there are any. A provider whose connection
+/// fails is skipped for a while, with exponential backoff, and the request
+/// fails over to the next provider. If all providers are unhealthy, requests
+/// are sent to them anyway.
+#[derive(Clone)]
+pub struct ProviderPool {
+    logger: Logger,
+    providers: Arc<Vec<(Provider, Mutex<ProviderHealth>)>>,
+    next_request: Arc<AtomicUsize>,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl ProviderPool {
+    pub fn new(providers: Vec<Provider>, logger: &Logger) -> Self {
+        assert!(!providers.is_empty(), "provider pool must not be empty");
+
+        ProviderPool {
+            logger: logger.new(o!("component" => "ProviderPool")),
+            providers: Arc::new(
+                providers
+                    .into_iter()
+                    .map(|provider| (provider, Mutex::new(ProviderHealth::default())))
+                    .collect(),
+            ),
+            next_request: Arc::new(AtomicUsize::new(0)),
+            next_id: Arc::new(AtomicUsize::new(1)),
+        }
+    }
+
+    /// The transports of all providers, e.g. to subscribe to new chain
+    /// heads through one of them.
+    pub fn transports(&self) -> impl Iterator<Item = &Transport> {
+        self.providers
+            .iter()
+            .map(|(provider, _)| &provider.transport)
+    }
+
+    /// Returns the indexes of the providers to try for a request, in order:
+    /// one provider picked by weight first, followed by all others as
+    /// fallbacks. Healthy providers with the capability come first, then
+    /// the remaining healthy providers, then the unhealthy ones.
+    fn candidates(&self, capability: Option<ProviderCapability>) -> Vec<usize> {
+        let now = Instant::now();
+        let is_healthy = |i: usize| {
+            let health = self.providers[i].1.lock().unwrap();
+            health.unhealthy_until.map_or(true, |until| until <= now)
+        };
+        let is_capable = |i: usize| {
+            capability.map_or(true, |capability| {
+                self.providers[i].0.capabilities.contains(&capability)
+            })
+        };
+
+        let mut tiers: [Vec<usize>; 3] = [vec![], vec![], vec![]];
+        for i in 0..self.providers.len() {
+            let tier = match (is_healthy(i), is_capable(i)) {
+                (true, true) => 0,
+                (true, false) => 1,
+                (false, _) => 2,
+            };
+            tiers[tier].push(i);
+        }
+
+        let mut candidates = vec![];
+        for mut tier in tiers.iter().cloned() {
+            if candidates.is_empty() && !tier.is_empty() {
+                // Pick the first provider to try by weight, round robin
+                let total_weight: usize = tier.iter().map(|&i| self.providers[i].0.weight).sum();
+                let mut ticket =
+                    self.next_request.fetch_add(1, Ordering::SeqCst) % cmp::max(total_weight, 1);
+                let position = tier
+                    .iter()
+                    .position(|&i| {
+                        let weight = self.providers[i].0.weight;
+                        if ticket < weight {
+                            true
+                        } else {
+                            ticket -= weight;
+                            false
+                        }
+                    })
+                    .unwrap_or(0);
+                tier.rotate_left(position);
+            }
+            candidates.extend(tier);
+        }
+        candidates
+    }
+
+    fn record_success(&self, i: usize) {
+        let (provider, health) = &self.providers[i];
+        let mut health = health.lock().unwrap();
+        if health.consecutive_failures > 0 {
+            info!(self.logger, "Ethereum provider is healthy again"; "provider" => &provider.name);
+        }
+        *health = ProviderHealth::default();
+    }
+
+    fn record_failure(&self, i: usize, error: &web3::error::Error) {
+        let (provider, health) = &self.providers[i];
+        let mut health = health.lock().unwrap();
+        let backoff = cmp::min(
+            PROVIDER_INITIAL_BACKOFF * 2u32.pow(cmp::min(health.consecutive_failures, 6)),
+            PROVIDER_MAX_BACKOFF,
+        );
+        health.consecutive_failures += 1;
+        health.unhealthy_until = Some(Instant::now() + backoff);
+        warn!(
+            self.logger,
+            "Ethereum provider failed, failing over to other providers";
+            "provider" => &provider.name,
+            "error" => format!("{}", error),
+            "retry_after_s" => backoff.as_secs(),
+        );
+    }
+
+    /// Returns `true` if the error means that the provider itself is having
+    /// problems, as opposed to rejecting an individual request.
+    fn is_provider_error(error: &web3::error::Error) -> bool {
+        match error.kind() {
+            ErrorKind::Transport(_) | ErrorKind::Io(_) | ErrorKind::Unreachable => true,
+            _ => false,
+        }
+    }
+
+    /// Sends a request to the candidate providers in turn, until one of
+    /// them doesn't fail with a provider error.
+    fn send_with_failover<F, R, I>(
+        &self,
+        candidates: Vec<usize>,
+        send: F,
+    ) -> Box<Future<Item = I, Error = web3::error::Error> + Send>
+    where
+        F: Fn(&Provider) -> R + Send + 'static,
+        R: Future<Item = I, Error = web3::error::Error> + Send + 'static,
+        I: Send + 'static,
+    {
+        let pool = self.clone();
+        Box::new(future::loop_fn(
+            (candidates.into_iter(), send),
+            move |(mut candidates, send)| {
+                let pool = pool.clone();
+                let i = candidates
+                    .next()
+                    .expect("provider pool has at least one provider");
+                send(&pool.providers[i].0).then(move |result| match result {
+                    Ok(response) => {
+                        pool.record_success(i);
+                        Ok(future::Loop::Break(response))
+                    }
+                    Err(e) if Self::is_provider_error(&e) => {
+                        pool.record_failure(i, &e);
+                        if candidates.len() > 0 {
+                            Ok(future::Loop::Continue((candidates, send)))
+                        } else {
+                            Err(e)
+                        }
+                    }
+                    Err(e) => Err(e),
+                })
+            },
+        ))
+    }
+}
+
+/// Extracts the method call of a request, so that it can be prepared
+/// again by the transport of the provider it is sent to.
+fn method_call(request: Call) -> Result<MethodCall, Error> {
+    match request {
+        Call::MethodCall(call) => Ok(call),
+        _ => Err(format_err!("unsupported JSON-RPC request: {:?}", request)),
+    }
+}
+
+/// Prepares `call` for `transport`. The request gets its ID from the
+/// transport, which matches responses to requests by it, and keeps the
+/// parameters of `call`, whether they are an array, a map or missing.
+fn prepare(transport: &Transport, call: &MethodCall) -> (RequestId, Call) {
+    let (id, request) = web3::Transport::prepare(transport, &call.method, vec![]);
+    let request = match request {
+        Call::MethodCall(request) => Call::MethodCall(MethodCall {
+            params: call.params.clone(),
+            ..request
+        }),
+        request => request,
+    };
+    (id, request)
+}
+
+impl fmt::Debug for ProviderPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.providers.iter().map(|(provider, _)| &provider.name))
+            .finish()
+    }
+}
+
+impl web3::Transport for ProviderPool {
+    type Out = Box<Future<Item = Value, Error = web3::error::Error> + Send>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        (id, build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: Call) -> Self::Out {
+        let call = match method_call(request) {
+            Ok(call) => call,
+            Err(e) => return Box::new(future::err(e.to_string().into())),
+        };
+        let candidates = self.candidates(ProviderCapability::required_by(&call.method));
+        self.send_with_failover(candidates, move |provider| {
+            let (id, request) = prepare(&provider.transport, &call);
+            web3::Transport::send(&provider.transport, id, request)
+        })
+    }
+}
+
+impl web3::BatchTransport for ProviderPool {
+    type Batch = Box<
+        Future<Item = Vec<Result<Value, web3::error::Error>>, Error = web3::error::Error> + Send,
+    >;
+
+    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    where
+        T: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let calls = match requests
+            .into_iter()
+            .map(|(_, request)| method_call(request))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(calls) => calls,
+            Err(e) => return Box::new(future::err(e.to_string().into())),
+        };
+
+        // The whole batch goes to one provider; it needs a capability if
+        // any of its requests does
+        let capability = calls
+            .iter()
+            .filter_map(|call| ProviderCapability::required_by(&call.method))
+            .next();
+        let candidates = self.candidates(capability);
+        self.send_with_failover(candidates, move |provider| {
+            let requests = calls
+                .iter()
+                .map(|call| prepare(&provider.transport, call))
+                .collect::<Vec<_>>();
+            web3::BatchTransport::send_batch(&provider.transport, requests)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::slog;
+    use graph::serde_json;
+    use jsonrpc_core::types::{Id, Params};
+
+    /// A provider whose transport is never connected to; tests send their
+    /// requests through `send_with_failover` instead.
+    fn provider(name: &str, weight: usize, capabilities: Vec<ProviderCapability>) -> Provider {
+        let (_event_loop, transport) = Transport::new_rpc("http://127.0.0.1:8545");
+        Provider {
+            name: name.to_owned(),
+            transport,
+            weight,
+            capabilities,
+        }
+    }
+
+    fn pool(providers: Vec<Provider>) -> ProviderPool {
+        ProviderPool::new(providers, &Logger::root(slog::Discard, o!()))
+    }
+
+    #[test]
+    fn requests_are_spread_by_weight() {
+        let pool = pool(vec![
+            provider("light", 1, vec![]),
+            provider("heavy", 3, vec![ProviderCapability::Traces]),
+        ]);
+
+        let mut first_choices = [0, 0];
+        for _ in 0..400 {
+            first_choices[pool.candidates(None)[0]] += 1;
+        }
+        assert_eq!(first_choices, [100, 300]);
+
+        // Requests that need a capability go to the providers that have it
+        for _ in 0..4 {
+            assert_eq!(
+                pool.candidates(Some(ProviderCapability::Traces)),
+                vec![1, 0]
+            );
+        }
+    }
+
+    #[test]
+    fn requests_fail_over_to_healthy_providers() {
+        let pool = pool(vec![provider("down", 1, vec![]), provider("up", 1, vec![])]);
+
+        let send = |provider: &Provider| {
+            future::result(match provider.name.as_str() {
+                "down" => Err(ErrorKind::Unreachable.into()),
+                name => Ok(name.to_owned()),
+            })
+        };
+        assert_eq!(
+            pool.send_with_failover(vec![0, 1], send).wait().unwrap(),
+            "up"
+        );
+
+        // The failed provider is only tried after the healthy one until its
+        // backoff expires
+        for _ in 0..4 {
+            assert_eq!(pool.candidates(None), vec![1, 0]);
+        }
+
+        // Requests that a provider rejects are not sent to other providers
+        let send = |provider: &Provider| {
+            future::result(match provider.name.as_str() {
+                "up" => Err(ErrorKind::Rpc(jsonrpc_core::Error::invalid_request()).into()),
+                name => Ok(name.to_owned()),
+            })
+        };
+        assert!(pool.send_with_failover(vec![1, 0], send).wait().is_err());
+
+        // If all providers fail, the request fails with the last error
+        let send = |_: &Provider| future::err::<String, _>(ErrorKind::Unreachable.into());
+        match pool.send_with_failover(vec![1, 0], send).wait() {
+            Err(web3::error::Error(ErrorKind::Unreachable, _)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn requests_are_forwarded_with_all_kinds_of_params() {
+        let (_event_loop, transport) = Transport::new_rpc("http://127.0.0.1:8545");
+        let mut map = serde_json::Map::new();
+        map.insert("key".to_owned(), Value::from("value"));
+
+        for params in vec![
+            Some(Params::Array(vec![Value::from(1)])),
+            Some(Params::Map(map)),
+            Some(Params::None),
+            None,
+        ] {
+            let call = MethodCall {
+                jsonrpc: None,
+                method: "test_method".to_owned(),
+                params: params.clone(),
+                id: Id::Num(1000),
+            };
+            match prepare(&transport, &call) {
+                (id, Call::MethodCall(request)) => {
+                    assert_eq!(request.method, "test_method");
+                    assert_eq!(request.params, params);
+                    assert_eq!(request.id, Id::Num(id as u64));
+                }
+                (_, request) => panic!("unexpected request: {:?}", request),
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use futures::future;
+use futures::prelude::*;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use graph::prelude::{format_err, Error};
+use graph::tokio::timer::Delay;
+
+/// Limits the rate of requests to an Ethereum node with a token bucket, so
+/// that `graph-node` stays within the request quota of a hosted provider.
+///
+/// The bucket holds up to `burst` tokens and is refilled at
+/// `requests_per_second`. Every request takes a token; requests that find
+/// the bucket empty are delayed until a token is available, in the order
+/// in which they arrive, instead of failing.
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests_per_second: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Tokens left at `updated_at`. Negative if requests are waiting for
+    /// tokens that are not available yet.
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: u32, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        RateLimiter {
+            requests_per_second: f64::from(requests_per_second.max(1)),
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                updated_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes `count` tokens and returns how long the requests they are for
+    /// have to wait before they may be sent.
+    fn reserve(&self, count: usize, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+
+        let elapsed = now.duration_since(bucket.updated_at);
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+        bucket.tokens = (bucket.tokens + elapsed * self.requests_per_second).min(self.burst);
+        bucket.updated_at = now;
+        bucket.tokens -= count as f64;
+
+        if bucket.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            let wait = -bucket.tokens / self.requests_per_second;
+            Duration::from_nanos((wait * 1e9).round() as u64)
+        }
+    }
+
+    /// Resolves once `count` requests may be sent.
+    pub fn acquire(&self, count: usize) -> Box<Future<Item = (), Error = Error> + Send> {
+        let now = Instant::now();
+        let wait = self.reserve(count, now);
+        if wait == Duration::from_secs(0) {
+            Box::new(future::ok(()))
+        } else {
+            Box::new(
+                Delay::new(now + wait)
+                    .map_err(|e| format_err!("failed to wait for the rate limit: {}", e)),
+            )
+        }
+    }
+}
+
+#[test]
+fn rate_limiter_delays_requests_over_the_limit() {
+    let limiter = RateLimiter::new(10, 2);
+    let start = limiter.bucket.lock().unwrap().updated_at;
+
+    // The burst goes through right away
+    assert_eq!(limiter.reserve(1, start), Duration::from_secs(0));
+    assert_eq!(limiter.reserve(1, start), Duration::from_secs(0));
+
+    // Later requests wait for the bucket to refill, one after the other
+    assert_eq!(limiter.reserve(1, start), Duration::from_millis(100));
+    assert_eq!(limiter.reserve(2, start), Duration::from_millis(300));
+
+    // After a while, the bucket is full again
+    let later = start + Duration::from_secs(10);
+    assert_eq!(limiter.reserve(2, later), Duration::from_secs(0));
+    assert_eq!(limiter.reserve(1, later), Duration::from_millis(100));
+}
@@ -1,12 +1,25 @@
 use futures::prelude::*;
+use futures::stream;
+use futures::sync::mpsc;
 use graph::serde_json::Value;
 use jsonrpc_core::types::Call;
 use std::env;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
 
+use graph::prelude::{error, format_err, info, o, warn, Error, EthereumNetworkIdentifier, Logger};
 use graph::web3;
+use graph::web3::error::ErrorKind;
 use graph::web3::transports::{http, ipc, ws};
+use graph::web3::types::BlockNumber;
 use graph::web3::RequestId;
 
+use provider_pool::{Provider, ProviderPool};
+use rate_limiter::RateLimiter;
+
 pub use graph::web3::transports::EventLoopHandle;
 
 /// Abstraction over the different web3 transports.
@@ -14,7 +27,9 @@ pub use graph::web3::transports::EventLoopHandle;
 pub enum Transport {
     RPC(http::Http),
     IPC(ipc::Ipc),
-    WS(ws::WebSocket),
+    WS(ReconnectingWebSocket),
+    Pool(ProviderPool),
+    RateLimited(Box<Transport>, Arc<RateLimiter>),
 }
 
 impl Transport {
@@ -25,10 +40,16 @@ impl Transport {
             .expect("Failed to connect to Ethereum IPC")
     }
 
-    /// Creates a WebSocket transport.
-    pub fn new_ws(ws: &str) -> (EventLoopHandle, Self) {
+    /// Creates a WebSocket transport that reconnects automatically when
+    /// the connection to the Ethereum node is lost.
+    pub fn new_ws(ws: &str, logger: &Logger) -> (EventLoopHandle, Self) {
         ws::WebSocket::new(ws)
-            .map(|(event_loop, transport)| (event_loop, Transport::WS(transport)))
+            .map(|(event_loop, transport)| {
+                (
+                    event_loop,
+                    Transport::WS(ReconnectingWebSocket::new(ws, transport, logger)),
+                )
+            })
             .expect("Failed to connect to Ethereum WS")
     }
 
@@ -45,6 +66,68 @@ impl Transport {
             .map(|(event_loop, transport)| (event_loop, Transport::RPC(transport)))
             .expect("Failed to connect to Ethereum RPC")
     }
+
+    /// Creates a transport that spreads requests across several Ethereum
+    /// nodes for the same network and fails over between them.
+    pub fn new_pool(providers: Vec<Provider>, logger: &Logger) -> Self {
+        Transport::Pool(ProviderPool::new(providers, logger))
+    }
+
+    /// Limits the rate of requests sent over the transport to
+    /// `requests_per_second`, allowing bursts of up to `burst` requests.
+    pub fn with_rate_limit(self, requests_per_second: u32, burst: u32) -> Self {
+        Transport::RateLimited(
+            Box::new(self),
+            Arc::new(RateLimiter::new(requests_per_second, burst)),
+        )
+    }
+
+    /// Makes the transport check that the Ethereum node is still on the
+    /// chain identified by `network_identifier` whenever it reconnects to
+    /// it. Only WebSocket transports reconnect.
+    pub fn with_network_identifier(self, network_identifier: EthereumNetworkIdentifier) -> Self {
+        match self {
+            Transport::WS(ws) => Transport::WS(ReconnectingWebSocket {
+                network_identifier: Some(Arc::new(network_identifier)),
+                ..ws
+            }),
+            Transport::RateLimited(transport, limiter) => Transport::RateLimited(
+                Box::new(transport.with_network_identifier(network_identifier)),
+                limiter,
+            ),
+            transport => transport,
+        }
+    }
+
+    /// Returns `true` if `new_heads` is supported.
+    pub fn supports_new_heads(&self) -> bool {
+        match self {
+            Transport::WS(_) => true,
+            Transport::Pool(pool) => pool.transports().any(|t| t.supports_new_heads()),
+            Transport::RateLimited(transport, _) => transport.supports_new_heads(),
+            _ => false,
+        }
+    }
+
+    /// Subscribes to new chain heads via `eth_subscribe("newHeads")`. The
+    /// returned stream emits once per new head and ends when the
+    /// connection is lost. Only WebSocket transports support this; provider
+    /// pools subscribe through their first WebSocket provider.
+    pub fn new_heads(&self) -> Box<Stream<Item = (), Error = Error> + Send> {
+        match self {
+            Transport::WS(ws) => ws.new_heads(),
+            Transport::Pool(pool) => match pool.transports().find(|t| t.supports_new_heads()) {
+                Some(transport) => transport.new_heads(),
+                None => Box::new(stream::once(Err(format_err!(
+                    "subscribing to new chain heads requires a WebSocket connection"
+                )))),
+            },
+            Transport::RateLimited(transport, _) => transport.new_heads(),
+            _ => Box::new(stream::once(Err(format_err!(
+                "subscribing to new chain heads requires a WebSocket connection"
+            )))),
+        }
+    }
 }
 
 impl web3::Transport for Transport {
@@ -55,6 +138,8 @@ impl web3::Transport for Transport {
             Transport::RPC(http) => http.prepare(method, params),
             Transport::IPC(ipc) => ipc.prepare(method, params),
             Transport::WS(ws) => ws.prepare(method, params),
+            Transport::Pool(pool) => pool.prepare(method, params),
+            Transport::RateLimited(transport, _) => transport.prepare(method, params),
         }
     }
 
@@ -63,6 +148,16 @@ impl web3::Transport for Transport {
             Transport::RPC(http) => Box::new(http.send(id, request)),
             Transport::IPC(ipc) => Box::new(ipc.send(id, request)),
             Transport::WS(ws) => Box::new(ws.send(id, request)),
+            Transport::Pool(pool) => pool.send(id, request),
+            Transport::RateLimited(transport, limiter) => {
+                let transport = transport.clone();
+                Box::new(
+                    limiter
+                        .acquire(1)
+                        .map_err(|e| e.to_string().into())
+                        .and_then(move |()| web3::Transport::send(&*transport, id, request)),
+                )
+            }
         }
     }
 }
@@ -80,6 +175,372 @@ impl web3::BatchTransport for Transport {
             Transport::RPC(http) => Box::new(http.send_batch(requests)),
             Transport::IPC(ipc) => Box::new(ipc.send_batch(requests)),
             Transport::WS(ws) => Box::new(ws.send_batch(requests)),
+            Transport::Pool(pool) => pool.send_batch(requests),
+            Transport::RateLimited(transport, limiter) => {
+                // Providers count every request in a batch against the quota
+                let requests = requests.into_iter().collect::<Vec<_>>();
+                let transport = transport.clone();
+                Box::new(
+                    limiter
+                        .acquire(requests.len())
+                        .map_err(|e| e.to_string().into())
+                        .and_then(move |()| {
+                            web3::BatchTransport::send_batch(&*transport, requests)
+                        }),
+                )
+            }
+        }
+    }
+}
+
+/// Initial delay before trying to re-establish a lost WebSocket connection.
+const WS_RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// Upper bound for the exponential backoff between reconnection attempts.
+const WS_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// A WebSocket transport that re-establishes the connection to the Ethereum
+/// node with exponential backoff whenever it drops.
+///
+/// Requests that fail because the connection is gone still fail; callers
+/// retry them (see `graph::util::futures::retry`) and the retries go out
+/// over the new connection once it is up. Subscriptions to new chain heads
+/// are re-created on the new connection, and announce a new head right
+/// away so that the subscriber picks up the blocks that were produced
+/// while disconnected; the block ingestor backfills missing ancestors of
+/// the chain head.
+///
+/// If the network identifiers of the node are known, a new connection is
+/// only used once the node reports the same identifiers again. This keeps
+/// a node that was replaced with one on a different chain from corrupting
+/// the data of subgraphs.
+#[derive(Clone)]
+pub struct ReconnectingWebSocket {
+    url: String,
+    logger: Logger,
+    connection: Arc<RwLock<ws::WebSocket>>,
+    /// The event loop of the current connection if it was established by
+    /// reconnecting. The event loop of the initial connection is owned by
+    /// the caller of `Transport::new_ws`.
+    event_loop: Arc<Mutex<Option<EventLoopHandle>>>,
+    /// Incremented whenever a new connection replaces the current one.
+    generation: Arc<AtomicUsize>,
+    reconnecting: Arc<AtomicBool>,
+    reconnect_listeners: Arc<Mutex<Vec<mpsc::UnboundedSender<()>>>>,
+    network_identifier: Option<Arc<EthereumNetworkIdentifier>>,
+}
+
+impl ReconnectingWebSocket {
+    fn new(url: &str, transport: ws::WebSocket, logger: &Logger) -> Self {
+        ReconnectingWebSocket {
+            url: url.to_owned(),
+            logger: logger.new(o!("component" => "ReconnectingWebSocket")),
+            connection: Arc::new(RwLock::new(transport)),
+            event_loop: Arc::new(Mutex::new(None)),
+            generation: Arc::new(AtomicUsize::new(0)),
+            reconnecting: Arc::new(AtomicBool::new(false)),
+            reconnect_listeners: Arc::new(Mutex::new(vec![])),
+            network_identifier: None,
+        }
+    }
+
+    fn current(&self) -> ws::WebSocket {
+        self.connection.read().unwrap().clone()
+    }
+
+    /// Subscribes to new chain heads, and subscribes again whenever the
+    /// connection is re-established.
+    fn new_heads(&self) -> Box<Stream<Item = (), Error = Error> + Send> {
+        let (sender, reconnects) = mpsc::unbounded();
+        self.reconnect_listeners.lock().unwrap().push(sender);
+
+        let this = self.clone();
+        resubscribing(reconnects, move || this.subscribe_new_heads())
+    }
+
+    /// Subscribes to new chain heads over the current connection. The
+    /// stream ends when the connection is closed.
+    fn subscribe_new_heads(&self) -> Box<Stream<Item = (), Error = web3::error::Error> + Send> {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let failed = self.clone();
+        let ended = self.clone();
+        Box::new(
+            web3::Web3::new(self.current())
+                .eth_subscribe()
+                .subscribe_new_heads()
+                .flatten_stream()
+                .map(|_header| ())
+                .map_err(move |e| {
+                    // Subscriptions on a replaced connection fail when its
+                    // event loop is dropped; that is not a reason to reconnect
+                    if failed.generation.load(Ordering::SeqCst) == generation {
+                        failed.check_error(&e);
+                    }
+                    e
+                })
+                .chain(stream::poll_fn(move || {
+                    if ended.generation.load(Ordering::SeqCst) == generation {
+                        ended.start_reconnecting("newHeads subscription ended".to_owned());
+                    }
+                    Ok(Async::Ready(None))
+                })),
+        )
+    }
+
+    /// Returns `true` if the error indicates that the connection itself is
+    /// broken, as opposed to the node rejecting an individual request.
+    fn is_connection_error(error: &web3::error::Error) -> bool {
+        match error.kind() {
+            ErrorKind::Transport(_) | ErrorKind::Io(_) | ErrorKind::Unreachable => true,
+            _ => false,
+        }
+    }
+
+    /// Inspects the outcome of a request and starts reconnecting in the
+    /// background if the connection was lost.
+    fn check_error(&self, error: &web3::error::Error) {
+        if Self::is_connection_error(error) {
+            self.start_reconnecting(format!("{}", error));
+        }
+    }
+
+    /// Starts reconnecting in the background unless that is already
+    /// underway.
+    fn start_reconnecting(&self, reason: String) {
+        // Only one reconnection attempt at a time
+        if self.reconnecting.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        warn!(
+            self.logger,
+            "Lost WebSocket connection to Ethereum node, reconnecting";
+            "error" => reason,
+        );
+
+        let this = self.clone();
+        thread::Builder::new()
+            .name("ethereum-ws-reconnect".to_owned())
+            .spawn(move || this.reconnect())
+            .expect("failed to spawn WebSocket reconnection thread");
+    }
+
+    /// Checks that the node behind a new connection is on the expected chain.
+    fn check_network(&self, transport: &ws::WebSocket) -> Result<(), Error> {
+        let expected = match self.network_identifier {
+            Some(ref expected) => expected,
+            None => return Ok(()),
+        };
+
+        let web3 = web3::Web3::new(transport.clone());
+        let (net_version, genesis_block) = web3
+            .net()
+            .version()
+            .join(web3.eth().block(BlockNumber::Earliest.into()))
+            .wait()
+            .map_err(|e| format_err!("failed to identify Ethereum node: {}", e))?;
+        let genesis_block_hash = genesis_block
+            .and_then(|block| block.hash)
+            .ok_or_else(|| format_err!("Ethereum node could not find genesis block"))?;
+
+        EthereumNetworkIdentifier {
+            net_version,
+            genesis_block_hash,
+        }
+        .check(expected)
+        .map_err(Error::from)
+    }
+
+    fn reconnect(&self) {
+        let mut delay = WS_RECONNECT_INITIAL_DELAY;
+        let mut attempt = 1;
+
+        loop {
+            thread::sleep(delay);
+            delay = ::std::cmp::min(delay * 2, WS_RECONNECT_MAX_DELAY);
+
+            match ws::WebSocket::new(&self.url) {
+                Ok((event_loop, transport)) => match self.check_network(&transport) {
+                    Ok(()) => {
+                        *self.connection.write().unwrap() = transport;
+                        self.generation.fetch_add(1, Ordering::SeqCst);
+
+                        // The event loop drives the connection and has to
+                        // live as long as the connection is in use. Dropping
+                        // the event loop of the previous connection closes it.
+                        let previous = self.event_loop.lock().unwrap().replace(event_loop);
+                        drop(previous);
+
+                        self.reconnecting.store(false, Ordering::SeqCst);
+                        info!(
+                            self.logger,
+                            "Reconnected to Ethereum node over WebSocket";
+                            "attempt" => attempt,
+                        );
+
+                        // Let subscribers subscribe again; forget those that
+                        // are gone
+                        self.reconnect_listeners
+                            .lock()
+                            .unwrap()
+                            .retain(|listener| listener.unbounded_send(()).is_ok());
+                        return;
+                    }
+                    Err(e) => {
+                        // Dropping the event loop closes the new connection
+                        error!(
+                            self.logger,
+                            "Refusing to use Ethereum node after reconnecting";
+                            "error" => format!("{}", e),
+                            "attempt" => attempt,
+                            "retry_delay_s" => delay.as_secs(),
+                        );
+                    }
+                },
+                Err(e) => {
+                    warn!(
+                        self.logger,
+                        "Failed to reconnect to Ethereum node over WebSocket";
+                        "error" => format!("{}", e),
+                        "attempt" => attempt,
+                        "retry_delay_s" => delay.as_secs(),
+                    );
+                }
+            }
+            attempt += 1;
+        }
+    }
+}
+
+/// Subscribes with `subscribe` right away and again whenever `reconnects`
+/// reports that the connection was re-established. Subscriptions end when
+/// their connection is lost; errors that are not caused by the connection
+/// fail the stream.
+///
+/// Every resubscription starts by announcing a new head, so that the
+/// subscriber catches up on the blocks it missed while disconnected.
+fn resubscribing<F>(
+    reconnects: mpsc::UnboundedReceiver<()>,
+    subscribe: F,
+) -> Box<Stream<Item = (), Error = Error> + Send>
+where
+    F: Fn() -> Box<Stream<Item = (), Error = web3::error::Error> + Send> + Send + 'static,
+{
+    Box::new(
+        stream::once(Ok(false))
+            .chain(
+                reconnects
+                    .map(|()| true)
+                    .map_err(|()| format_err!("reconnect notifications failed")),
+            )
+            .map(move |reconnected| {
+                let subscription = subscribe()
+                    .then(|result| match result {
+                        Ok(()) => Ok(true),
+                        Err(ref e) if ReconnectingWebSocket::is_connection_error(e) => Ok(false),
+                        Err(e) => Err(format_err!("newHeads subscription failed: {}", e)),
+                    })
+                    .take_while(|connected| Ok(*connected))
+                    .map(|_| ());
+                let catch_up = if reconnected { Some(()) } else { None };
+                stream::iter_ok::<_, Error>(catch_up).chain(subscription)
+            })
+            .flatten(),
+    )
+}
+
+impl fmt::Debug for ReconnectingWebSocket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReconnectingWebSocket")
+            .field("url", &self.url)
+            .field("reconnecting", &self.reconnecting.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+impl web3::Transport for ReconnectingWebSocket {
+    type Out = Box<Future<Item = Value, Error = web3::error::Error> + Send>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.current().prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let this = self.clone();
+        Box::new(self.current().send(id, request).map_err(move |e| {
+            this.check_error(&e);
+            e
+        }))
+    }
+}
+
+impl web3::BatchTransport for ReconnectingWebSocket {
+    type Batch = Box<
+        Future<Item = Vec<Result<Value, web3::error::Error>>, Error = web3::error::Error> + Send,
+    >;
+
+    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    where
+        T: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let this = self.clone();
+        Box::new(self.current().send_batch(requests).map_err(move |e| {
+            this.check_error(&e);
+            e
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    fn subscriptions(
+        subscriptions: Vec<Vec<Result<(), web3::error::Error>>>,
+    ) -> impl Fn() -> Box<Stream<Item = (), Error = web3::error::Error> + Send> {
+        let subscriptions = Mutex::new(subscriptions.into_iter().collect::<VecDeque<_>>());
+        move || {
+            let heads = subscriptions
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("subscribed too often");
+            Box::new(stream::iter_result(heads))
         }
     }
+
+    #[test]
+    fn new_heads_are_resubscribed_after_reconnecting() {
+        let (reconnected, reconnects) = mpsc::unbounded();
+        let heads = resubscribing(
+            reconnects,
+            subscriptions(vec![
+                vec![Ok(()), Err(ErrorKind::Unreachable.into())],
+                vec![Ok(()), Ok(())],
+            ]),
+        );
+        reconnected.unbounded_send(()).unwrap();
+        drop(reconnected);
+
+        // One head from the first connection, one to catch up on the blocks
+        // missed while disconnected and two from the new connection
+        assert_eq!(heads.collect().wait().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn new_heads_fail_if_the_node_rejects_the_subscription() {
+        let (_reconnected, reconnects) = mpsc::unbounded();
+        let heads = resubscribing(
+            reconnects,
+            subscriptions(vec![vec![
+                Ok(()),
+                Err(ErrorKind::Rpc(jsonrpc_core::Error::method_not_found()).into()),
+            ]]),
+        );
+
+        let mut heads = heads.wait();
+        assert!(heads.next().unwrap().is_ok());
+        assert!(heads.next().unwrap().is_err());
+    }
 }
@@ -50,7 +50,7 @@ fn mock_block() -> Block<U256> {
 pub struct TestTransport {
     asserted: usize,
     requests: Arc<Mutex<Vec<(String, Vec<jsonrpc_core::Value>)>>>,
-    response: Arc<Mutex<VecDeque<jsonrpc_core::Value>>>,
+    response: Arc<Mutex<VecDeque<::std::result::Result<jsonrpc_core::Value, Error>>>>,
 }
 
 impl Transport for TestTransport {
@@ -68,7 +68,8 @@ impl Transport for TestTransport {
 
     fn send(&self, _: RequestId, _: jsonrpc_core::Call) -> Result<jsonrpc_core::Value> {
         match self.response.lock().unwrap().pop_front() {
-            Some(response) => Box::new(finished(response)),
+            Some(Ok(response)) => Box::new(finished(response)),
+            Some(Err(e)) => Box::new(failed(e)),
             None => Box::new(failed(ErrorKind::Unreachable.into())),
         }
     }
@@ -94,11 +95,29 @@ impl BatchTransport for TestTransport {
 
 impl TestTransport {
     pub fn set_response(&mut self, value: jsonrpc_core::Value) {
-        *self.response.lock().unwrap() = vec![value].into();
+        *self.response.lock().unwrap() = vec![Ok(value)].into();
     }
 
     pub fn add_response(&mut self, value: jsonrpc_core::Value) {
-        self.response.lock().unwrap().push_back(value);
+        self.response.lock().unwrap().push_back(Ok(value));
+    }
+
+    pub fn add_error(&mut self, error: jsonrpc_core::Error) {
+        self.response
+            .lock()
+            .unwrap()
+            .push_back(Err(ErrorKind::Rpc(error).into()));
+    }
+
+    /// Returns the block parameters of the `eth_call` requests.
+    pub fn call_blocks(&self) -> Vec<jsonrpc_core::Value> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(method, _)| method == "eth_call")
+            .map(|(_, params)| params[1].clone())
+            .collect()
     }
 
     pub fn assert_request(&mut self, method: &str, params: &[String]) {
@@ -169,3 +188,88 @@ fn contract_call() {
 
     assert_eq!(call_result[0], Token::Uint(U256::from(100000)));
 }
+
+fn balance_call(block_ptr: EthereumBlockPointer) -> EthereumContractCall {
+    EthereumContractCall {
+        address: Address::from_str("eF7FfF64389B814A946f3E92105513705CA6B990").unwrap(),
+        block_ptr,
+        function: Function {
+            name: "balanceOf".to_owned(),
+            inputs: vec![Param {
+                name: "_owner".to_owned(),
+                kind: ParamType::Address,
+            }],
+            outputs: vec![Param {
+                name: "balance".to_owned(),
+                kind: ParamType::Uint(256),
+            }],
+            constant: true,
+        },
+        args: vec![Token::Address(
+            Address::from_str("00d04c4b12C4686305bb4F4fC93487CdFBa62580").unwrap(),
+        )],
+    }
+}
+
+fn by_hash(hash: H256) -> jsonrpc_core::Value {
+    let mut block = serde_json::Map::new();
+    block.insert(
+        "blockHash".to_owned(),
+        jsonrpc_core::Value::String(format!("0x{:x}", hash)),
+    );
+    jsonrpc_core::Value::Object(block)
+}
+
+fn balance_output() -> jsonrpc_core::Value {
+    jsonrpc_core::Value::String(format!("{:?}", H256::from(100000)))
+}
+
+#[test]
+fn contract_calls_by_block_hash_are_cached() {
+    let mut transport = TestTransport::default();
+    transport.add_response(balance_output());
+
+    let logger = Logger::root(slog::Discard, o!());
+    let adapter = EthereumAdapter::new(transport.clone());
+    let block_ptr = EthereumBlockPointer::from((H256::from(7), 5 as i64));
+    let mut runtime = graph::tokio::runtime::Runtime::new().unwrap();
+    for _ in 0..2 {
+        let tokens = runtime
+            .block_on(adapter.contract_call(&logger, balance_call(block_ptr)))
+            .unwrap();
+        assert_eq!(tokens, vec![Token::Uint(U256::from(100000))]);
+    }
+
+    // The second call is answered from the cache
+    assert_eq!(transport.call_blocks(), vec![by_hash(H256::from(7))]);
+}
+
+#[test]
+fn contract_calls_by_block_number_are_not_cached() {
+    let mut transport = TestTransport::default();
+    transport.add_error(jsonrpc_core::Error::invalid_params("blockHash"));
+    transport.add_response(balance_output());
+    transport.add_response(balance_output());
+
+    let logger = Logger::root(slog::Discard, o!());
+    let adapter = EthereumAdapter::new(transport.clone());
+    let block_ptr = EthereumBlockPointer::from((H256::from(7), 5 as i64));
+    let mut runtime = graph::tokio::runtime::Runtime::new().unwrap();
+    for _ in 0..2 {
+        let tokens = runtime
+            .block_on(adapter.contract_call(&logger, balance_call(block_ptr)))
+            .unwrap();
+        assert_eq!(tokens, vec![Token::Uint(U256::from(100000))]);
+    }
+
+    // A call by number may have been made on a block that replaced the one
+    // with the hash, so its result is not cached under that hash
+    assert_eq!(
+        transport.call_blocks(),
+        vec![
+            by_hash(H256::from(7)),
+            jsonrpc_core::Value::String("0x5".to_owned()),
+            jsonrpc_core::Value::String("0x5".to_owned()),
+        ]
+    );
+}
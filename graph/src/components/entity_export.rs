@@ -0,0 +1,435 @@
+use failure::Error;
+use reqwest::Url;
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::sync::mpsc::{sync_channel, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::components::store::{EntityChange, EntityChangeOperation};
+use crate::data::subgraph::SubgraphDeploymentId;
+use crate::prelude::{info, o, warn, EthereumBlockPointer, Logger};
+
+/// The subject prefix of NATS sinks whose URL doesn't have a path.
+const DEFAULT_NATS_SUBJECT: &str = "graph.entity-changes";
+
+/// How long a NATS sink waits for the server to acknowledge a batch.
+const NATS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The longest time between attempts to publish changes to a sink.
+const MAX_EXPORT_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// How long the exporter waits for new changes before it looks at the
+/// outbox again, in case changes were recorded by another node.
+const EXPORT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many changes are published to a sink at a time.
+const EXPORT_BATCH_SIZE: usize = 1000;
+
+lazy_static! {
+    /// The number of changes the outbox holds before writes to the store
+    /// wait for the exporter to catch up.
+    pub static ref ENTITY_CHANGE_OUTBOX_SIZE: u64 = env::var("GRAPH_ENTITY_CHANGE_OUTBOX_SIZE")
+        .ok()
+        .map(|s| u64::from_str(&s).unwrap_or_else(|_| panic!(
+            "failed to parse env var GRAPH_ENTITY_CHANGE_OUTBOX_SIZE"
+        )))
+        .unwrap_or(100_000);
+}
+
+/// A change of an entity that was committed to the store, as published to
+/// an `EntityChangeSink`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommittedEntityChange {
+    pub deployment: SubgraphDeploymentId,
+    /// The block the deployment is at after the change, or `None` for
+    /// changes that aren't tied to a block, e.g. those of file data sources
+    /// or of copying a deployment.
+    pub block_hash: Option<String>,
+    pub block_number: Option<u64>,
+    pub entity_type: String,
+    pub entity_id: String,
+    pub operation: EntityChangeOperation,
+    /// Whether the change undoes the changes of a reverted block or file;
+    /// the entity then has the data it had before them.
+    pub revert: bool,
+}
+
+impl CommittedEntityChange {
+    pub fn new(
+        change: EntityChange,
+        block_ptr: Option<EthereumBlockPointer>,
+        revert: bool,
+    ) -> Self {
+        CommittedEntityChange {
+            deployment: change.subgraph_id,
+            block_hash: block_ptr.map(|ptr| ptr.hash_hex()),
+            block_number: block_ptr.map(|ptr| ptr.number),
+            entity_type: change.entity_type,
+            entity_id: change.entity_id,
+            operation: change.operation,
+            revert,
+        }
+    }
+}
+
+/// An external system that mirrors the entity changes of subgraphs, e.g. a
+/// message queue.
+pub trait EntityChangeSink: Send + Sync + 'static {
+    /// Publishes changes, in order. Changes that failed to be published
+    /// are published again, so they may arrive more than once.
+    fn publish(&self, changes: &[CommittedEntityChange]) -> Result<(), Error>;
+}
+
+/// The changes that were committed to the store but not published yet.
+/// The store records changes in the outbox in the same transaction that
+/// makes them, so that no change is lost when the node stops before it is
+/// published.
+pub trait EntityChangeOutbox: Send + Sync + 'static {
+    /// Returns up to `limit` of the oldest changes in the outbox, in the
+    /// order they were committed, with their position in the outbox.
+    fn pending(&self, limit: usize) -> Result<Vec<(i64, CommittedEntityChange)>, Error>;
+
+    /// Removes the changes up to and including `position` from the outbox.
+    fn acknowledge(&self, position: i64) -> Result<(), Error>;
+}
+
+/// Publishes the entity changes that the store recorded in an outbox to a
+/// sink, on a thread of its own so that writing to the store doesn't wait
+/// for the sink. Changes are published in the order they were committed
+/// and only removed from the outbox once the sink accepted them; when
+/// publishing fails, it is retried until it succeeds.
+pub struct EntityChangeExporter {
+    wakeup: Mutex<SyncSender<()>>,
+}
+
+impl EntityChangeExporter {
+    pub fn new(
+        logger: &Logger,
+        outbox: Arc<EntityChangeOutbox>,
+        sink: Arc<EntityChangeSink>,
+    ) -> Result<Self, Error> {
+        let logger = logger.new(o!("component" => "EntityChangeExporter"));
+        let (wakeup, woken) = sync_channel::<()>(1);
+
+        thread::Builder::new()
+            .name("entity-change-export".to_owned())
+            .spawn(move || loop {
+                let mut delay = Duration::from_secs(1);
+                let exported = loop {
+                    match Self::export_batch(&*outbox, &*sink) {
+                        Err(e) => {
+                            warn!(
+                                logger,
+                                "Failed to publish entity changes, retrying";
+                                "error" => e.to_string(),
+                                "retry_delay_s" => delay.as_secs(),
+                            );
+                            thread::sleep(delay);
+                            delay = (delay * 2).min(MAX_EXPORT_RETRY_DELAY);
+                        }
+                        Ok(count) => break count,
+                    }
+                };
+
+                // Keep going while the outbox has a backlog, otherwise wait
+                // until the store records more changes
+                if exported < EXPORT_BATCH_SIZE {
+                    if let Err(RecvTimeoutError::Disconnected) =
+                        woken.recv_timeout(EXPORT_POLL_INTERVAL)
+                    {
+                        info!(logger, "Stopped exporting entity changes");
+                        return;
+                    }
+                }
+            })?;
+
+        Ok(EntityChangeExporter {
+            wakeup: Mutex::new(wakeup),
+        })
+    }
+
+    /// Publishes the oldest changes in the outbox and removes them from
+    /// it. Returns the number of changes that were published.
+    fn export_batch(outbox: &EntityChangeOutbox, sink: &EntityChangeSink) -> Result<usize, Error> {
+        let changes = outbox.pending(EXPORT_BATCH_SIZE)?;
+        let last = match changes.last() {
+            Some((position, _)) => *position,
+            None => return Ok(0),
+        };
+        let changes: Vec<_> = changes.into_iter().map(|(_, change)| change).collect();
+        sink.publish(&changes)?;
+        outbox.acknowledge(last)?;
+        Ok(changes.len())
+    }
+
+    /// Tells the exporter that the store committed changes to the outbox.
+    pub fn notify(&self) {
+        // If a wakeup is pending already, the exporter sees these changes
+        // too
+        let _ = self.wakeup.lock().unwrap().try_send(());
+    }
+}
+
+/// Publishes entity changes to a NATS server, as JSON messages with the
+/// subject `<prefix>.<deployment>`. The server is given by a URL of the
+/// form `nats://[user:password@]host[:port][/prefix]`.
+pub struct NatsEntityChangeSink {
+    address: String,
+    user: Option<String>,
+    password: Option<String>,
+    subject_prefix: String,
+    connection: Mutex<Option<NatsConnection>>,
+}
+
+struct NatsConnection {
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl NatsEntityChangeSink {
+    pub fn new(url: &str) -> Result<Self, Error> {
+        let url = Url::parse(url)?;
+        if url.scheme() != "nats" {
+            return Err(format_err!(
+                "entity change sink URL must start with `nats://`: {}",
+                url
+            ));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| format_err!("entity change sink URL has no host: {}", url))?;
+        let subject_prefix = match url.path().trim_matches('/') {
+            "" => DEFAULT_NATS_SUBJECT.to_owned(),
+            path => path.replace('/', "."),
+        };
+
+        Ok(NatsEntityChangeSink {
+            address: format!("{}:{}", host, url.port().unwrap_or(4222)),
+            user: Some(url.username())
+                .filter(|user| !user.is_empty())
+                .map(str::to_owned),
+            password: url.password().map(str::to_owned),
+            subject_prefix,
+            connection: Mutex::new(None),
+        })
+    }
+
+    fn connect(&self) -> Result<NatsConnection, Error> {
+        let writer = TcpStream::connect(&self.address)?;
+        writer.set_read_timeout(Some(NATS_TIMEOUT))?;
+        writer.set_write_timeout(Some(NATS_TIMEOUT))?;
+        let mut connection = NatsConnection {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        };
+
+        // The server greets clients with an `INFO` message
+        let info = connection.read_line()?;
+        if !info.starts_with("INFO") {
+            return Err(format_err!(
+                "unexpected greeting from NATS server: {}",
+                info
+            ));
+        }
+
+        let mut options = serde_json::json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "graph-node",
+        });
+        if let Some(user) = &self.user {
+            options["user"] = user.as_str().into();
+        }
+        if let Some(password) = &self.password {
+            options["pass"] = password.as_str().into();
+        }
+        write!(connection.writer, "CONNECT {}\r\n", options)?;
+        Ok(connection)
+    }
+
+    fn publish_with(
+        &self,
+        connection: &mut NatsConnection,
+        changes: &[CommittedEntityChange],
+    ) -> Result<(), Error> {
+        let mut buffer = vec![];
+        for change in changes {
+            let payload = serde_json::to_vec(change)?;
+            write!(
+                buffer,
+                "PUB {}.{} {}\r\n",
+                self.subject_prefix,
+                change.deployment,
+                payload.len()
+            )?;
+            buffer.extend_from_slice(&payload);
+            buffer.extend_from_slice(b"\r\n");
+        }
+        // The server answers the `PING` once it has processed everything
+        // before it, or reports an error first
+        buffer.extend_from_slice(b"PING\r\n");
+        connection.writer.write_all(&buffer)?;
+
+        loop {
+            let line = connection.read_line()?;
+            if line == "PONG" {
+                return Ok(());
+            } else if line == "PING" {
+                connection.writer.write_all(b"PONG\r\n")?;
+            } else if line.starts_with("-ERR") {
+                return Err(format_err!("NATS server error: {}", line));
+            }
+        }
+    }
+}
+
+impl NatsConnection {
+    fn read_line(&mut self) -> Result<String, Error> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(format_err!("NATS server closed the connection"));
+        }
+        Ok(line.trim_end().to_owned())
+    }
+}
+
+impl EntityChangeSink for NatsEntityChangeSink {
+    fn publish(&self, changes: &[CommittedEntityChange]) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.is_none() {
+            *connection = Some(self.connect()?);
+        }
+        let result = self.publish_with(connection.as_mut().unwrap(), changes);
+        if result.is_err() {
+            // Reconnect the next time
+            *connection = None;
+        }
+        result
+    }
+}
+
+#[test]
+fn nats_sink_publishes_changes() {
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        stream.write_all(b"INFO {}\r\n").unwrap();
+
+        let mut messages = vec![];
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 {
+            if line.starts_with("PUB") {
+                let subject = line.split_whitespace().nth(1).unwrap().to_owned();
+                let mut payload = String::new();
+                reader.read_line(&mut payload).unwrap();
+                messages.push((subject, payload.trim_end().to_owned()));
+            } else if line.starts_with("PING") {
+                stream.write_all(b"PONG\r\n").unwrap();
+                return messages;
+            }
+            line.clear();
+        }
+        messages
+    });
+
+    let sink = NatsEntityChangeSink::new(&format!("nats://127.0.0.1:{}/mirror", port)).unwrap();
+    let change = CommittedEntityChange {
+        deployment: SubgraphDeploymentId::new("QmExport").unwrap(),
+        block_hash: Some("0x01".to_owned()),
+        block_number: Some(1),
+        entity_type: "User".to_owned(),
+        entity_id: "1".to_owned(),
+        operation: EntityChangeOperation::Set,
+        revert: false,
+    };
+    sink.publish(&[change]).unwrap();
+
+    let messages = server.join().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].0, "mirror.QmExport");
+    assert_eq!(
+        messages[0].1,
+        "{\"deployment\":\"QmExport\",\"blockHash\":\"0x01\",\"blockNumber\":1,\
+         \"entityType\":\"User\",\"entityId\":\"1\",\"operation\":\"set\",\"revert\":false}"
+    );
+}
+
+#[test]
+fn exporter_publishes_the_outbox_in_order() {
+    use std::sync::mpsc::channel;
+
+    struct MemoryOutbox(Mutex<Vec<(i64, CommittedEntityChange)>>);
+
+    impl EntityChangeOutbox for MemoryOutbox {
+        fn pending(&self, limit: usize) -> Result<Vec<(i64, CommittedEntityChange)>, Error> {
+            Ok(self.0.lock().unwrap().iter().take(limit).cloned().collect())
+        }
+
+        fn acknowledge(&self, position: i64) -> Result<(), Error> {
+            self.0.lock().unwrap().retain(|(p, _)| *p > position);
+            Ok(())
+        }
+    }
+
+    /// Fails the first time, then reports what it published.
+    struct FlakySink(Mutex<(bool, std::sync::mpsc::Sender<Vec<String>>)>);
+
+    impl EntityChangeSink for FlakySink {
+        fn publish(&self, changes: &[CommittedEntityChange]) -> Result<(), Error> {
+            let mut state = self.0.lock().unwrap();
+            if !state.0 {
+                state.0 = true;
+                return Err(format_err!("sink is down"));
+            }
+            let ids = changes.iter().map(|c| c.entity_id.clone()).collect();
+            state.1.send(ids).unwrap();
+            Ok(())
+        }
+    }
+
+    let change = |id: &str| CommittedEntityChange {
+        deployment: SubgraphDeploymentId::new("QmExport").unwrap(),
+        block_hash: None,
+        block_number: None,
+        entity_type: "User".to_owned(),
+        entity_id: id.to_owned(),
+        operation: EntityChangeOperation::Set,
+        revert: false,
+    };
+
+    // Changes that were recorded before the exporter started, e.g. by a
+    // node that stopped before publishing them, are published first
+    let outbox = Arc::new(MemoryOutbox(Mutex::new(vec![
+        (1, change("1")),
+        (2, change("2")),
+    ])));
+    let (sender, published) = channel();
+    let logger = Logger::root(slog::Discard, o!());
+    let exporter = EntityChangeExporter::new(
+        &logger,
+        outbox.clone(),
+        Arc::new(FlakySink(Mutex::new((false, sender)))),
+    )
+    .unwrap();
+
+    let timeout = Duration::from_secs(10);
+    assert_eq!(published.recv_timeout(timeout).unwrap(), vec!["1", "2"]);
+
+    outbox.0.lock().unwrap().push((3, change("3")));
+    exporter.notify();
+    assert_eq!(published.recv_timeout(timeout).unwrap(), vec!["3"]);
+
+    // Published changes are removed from the outbox once the sink took
+    // them
+    thread::sleep(Duration::from_millis(100));
+    assert!(outbox.0.lock().unwrap().is_empty());
+}
@@ -2,19 +2,107 @@ use ethabi::{Bytes, Error as ABIError, Function, ParamType, Token};
 use failure::{Error, SyncFailure};
 use futures::Future;
 use slog::Logger;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::iter::FromIterator;
-use web3::error::Error as Web3Error;
+use web3::error::{Error as Web3Error, ErrorKind as Web3ErrorKind};
 use web3::types::*;
 
 use super::types::*;
 
 /// A collection of attributes that (kind of) uniquely identify an Ethereum blockchain.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct EthereumNetworkIdentifier {
     pub net_version: String,
     pub genesis_block_hash: H256,
 }
 
+impl EthereumNetworkIdentifier {
+    /// Returns the identifiers of the public network with the given name,
+    /// if it is one that `graph-node` knows about.
+    pub fn well_known(network_name: &str) -> Option<Self> {
+        let (net_version, genesis_block_hash) = match network_name {
+            "mainnet" => (
+                "1",
+                "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3",
+            ),
+            "ropsten" => (
+                "3",
+                "41941023680923e0fe4d74a34bdac8141f2540e3ae90623718e47d66d1ca4a2d",
+            ),
+            "rinkeby" => (
+                "4",
+                "6341fd3daf94b748c72ced5a5b26028f2474f5f00d824504e4fa37a75767e177",
+            ),
+            "goerli" => (
+                "5",
+                "bf7e331f7f7c1dd2e05159666b3bf8bc7a8a3a9eb1d518969eab529dd9b88c1a",
+            ),
+            "kovan" => (
+                "42",
+                "a3c565fc15c7478862d50ccd6561e3c06b24cc509bf388941c25ea985ce32cb9",
+            ),
+            _ => return None,
+        };
+        Some(EthereumNetworkIdentifier {
+            net_version: net_version.to_owned(),
+            genesis_block_hash: genesis_block_hash.parse().unwrap(),
+        })
+    }
+
+    /// Checks that an Ethereum node that reported these identifiers is on
+    /// the `expected` chain.
+    pub fn check(&self, expected: &Self) -> Result<(), EthereumNetworkMismatch> {
+        if self == expected {
+            Ok(())
+        } else {
+            Err(EthereumNetworkMismatch {
+                net_version: self.net_version.clone(),
+                genesis_block_hash: self.genesis_block_hash,
+                expected_net_version: expected.net_version.clone(),
+                expected_genesis_block_hash: expected.genesis_block_hash,
+            })
+        }
+    }
+}
+
+/// An Ethereum node is on a different chain than the one it is configured for.
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Ethereum node is on the wrong chain: it reports net_version {} and \
+               genesis block {:x}, expected net_version {} and genesis block {:x}",
+    net_version, genesis_block_hash, expected_net_version, expected_genesis_block_hash
+)]
+pub struct EthereumNetworkMismatch {
+    pub net_version: String,
+    pub genesis_block_hash: H256,
+    pub expected_net_version: String,
+    pub expected_genesis_block_hash: H256,
+}
+
+/// Persistent cache for the results of contract calls. Calls at a given
+/// block hash always return the same result, so they never need to be made
+/// more than once.
+pub trait EthereumCallCache: Send + Sync + 'static {
+    /// Returns the cached result of calling `contract_address` with
+    /// `call_data` at `block`, if there is one.
+    fn get_call(
+        &self,
+        contract_address: Address,
+        call_data: &[u8],
+        block: EthereumBlockPointer,
+    ) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Caches the result of calling `contract_address` with `call_data`
+    /// at `block`.
+    fn set_call(
+        &self,
+        contract_address: Address,
+        call_data: &[u8],
+        block: EthereumBlockPointer,
+        return_value: &[u8],
+    ) -> Result<(), Error>;
+}
+
 /// A request for the state of a contract at a specific block hash and address.
 pub struct EthereumContractStateRequest {
     pub address: Address,
@@ -52,11 +140,66 @@ pub enum EthereumContractCallError {
     TypeError(Token, ParamType),
     #[fail(display = "call error: {}", _0)]
     Error(Error),
+    /// The Ethereum node rejected the request, e.g. because the method is
+    /// not supported or the contract call reverted.
+    #[fail(display = "Ethereum node rejected the call: {}", _0)]
+    Rejected(Error),
+    /// The Ethereum node no longer has the state of the block the call was
+    /// made at, because it is not an archive node.
+    #[fail(
+        display = "contract call at block #{} requires an Ethereum archive node: {}",
+        _0, _1
+    )]
+    ArchiveNodeRequired(u64, Error),
+}
+
+impl EthereumContractCallError {
+    /// Returns `true` if the call may succeed when it is tried again.
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            EthereumContractCallError::CallError(_) | EthereumContractCallError::Error(_) => true,
+            EthereumContractCallError::ABIError(_)
+            | EthereumContractCallError::TypeError(_, _)
+            | EthereumContractCallError::Rejected(_)
+            | EthereumContractCallError::ArchiveNodeRequired(_, _) => false,
+        }
+    }
 }
 
 impl From<Web3Error> for EthereumContractCallError {
     fn from(e: Web3Error) -> Self {
-        EthereumContractCallError::CallError(failure::err_msg(e.to_string()))
+        let rejected = is_rejected_request(&e);
+        let e = failure::err_msg(e.to_string());
+        if rejected {
+            EthereumContractCallError::Rejected(e)
+        } else {
+            EthereumContractCallError::CallError(e)
+        }
+    }
+}
+
+/// Returns `true` if the Ethereum node rejected a request in a way that
+/// doesn't change when the request is sent again. Transport errors and
+/// other RPC errors, e.g. rate limits or missing state on a load balanced
+/// node, are considered transient.
+fn is_rejected_request(e: &Web3Error) -> bool {
+    match e.kind() {
+        Web3ErrorKind::Rpc(rpc_error) => {
+            let message = rpc_error.message.to_lowercase();
+            match rpc_error.code.code() {
+                // Invalid request, method not found, invalid params
+                -32600 | -32601 | -32602 => true,
+                _ => [
+                    "revert",
+                    "invalid opcode",
+                    "bad instruction",
+                    "vm execution error",
+                ]
+                .iter()
+                .any(|pattern| message.contains(pattern)),
+            }
+        }
+        _ => false,
     }
 }
 
@@ -127,6 +270,48 @@ impl EthereumLogFilter {
                 }),
         }
     }
+
+    /// Splits this filter into the `(addresses, event signatures)` pairs to
+    /// request with `eth_getLogs`.
+    ///
+    /// Event signatures of data sources without a contract address are
+    /// requested once, without an address filter. Contracts are grouped by
+    /// their set of event signatures, so contracts that are instances of the
+    /// same ABI share one request while no contract is asked for logs of
+    /// events that only other contracts have handlers for.
+    pub fn eth_get_logs_filters(&self) -> Vec<(Vec<Address>, Vec<H256>)> {
+        let wildcard_sigs = self
+            .contract_address_and_event_sig_pairs
+            .iter()
+            .filter(|(addr, _)| addr.is_none())
+            .map(|(_, sig)| *sig)
+            .collect::<BTreeSet<H256>>();
+
+        let mut sigs_by_address: HashMap<Address, BTreeSet<H256>> = HashMap::new();
+        for (addr, sig) in self.contract_address_and_event_sig_pairs.iter() {
+            if let Some(addr) = addr {
+                // Logs for this signature are already requested for all contracts
+                if !wildcard_sigs.contains(sig) {
+                    sigs_by_address.entry(*addr).or_default().insert(*sig);
+                }
+            }
+        }
+
+        let mut addresses_by_sigs: BTreeMap<BTreeSet<H256>, Vec<Address>> = BTreeMap::new();
+        for (addr, sigs) in sigs_by_address {
+            addresses_by_sigs.entry(sigs).or_default().push(addr);
+        }
+
+        let mut filters = vec![];
+        if !wildcard_sigs.is_empty() {
+            filters.push((vec![], wildcard_sigs.into_iter().collect()));
+        }
+        filters.extend(addresses_by_sigs.into_iter().map(|(sigs, mut addresses)| {
+            addresses.sort();
+            (addresses, sigs.into_iter().collect())
+        }));
+        filters
+    }
 }
 
 impl FromIterator<(Option<Address>, H256)> for EthereumLogFilter {
@@ -211,6 +396,35 @@ impl From<EthereumBlockFilter> for EthereumCallFilter {
 pub struct EthereumBlockFilter {
     pub contract_addresses: HashSet<Address>,
     pub trigger_every_block: bool,
+    pub polling_intervals: HashSet<u64>,
+}
+
+impl EthereumBlockFilter {
+    /// Returns the polling intervals that apply to the block with the given number.
+    pub fn polling_intervals_for_block(&self, block_number: u64) -> Vec<u64> {
+        self.polling_intervals
+            .iter()
+            .filter(|every| block_number % **every == 0)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the numbers of the blocks from `from` to `to`, inclusive,
+    /// that one of the polling intervals applies to, in ascending order.
+    pub fn polling_block_numbers(&self, from: u64, to: u64) -> Vec<u64> {
+        let mut numbers = self
+            .polling_intervals
+            .iter()
+            .filter(|every| **every > 0)
+            .flat_map(|every| {
+                let first = (from + every - 1) / every * every;
+                (first..=to).step_by(*every as usize)
+            })
+            .collect::<Vec<_>>();
+        numbers.sort();
+        numbers.dedup();
+        numbers
+    }
 }
 
 impl FromIterator<Address> for EthereumBlockFilter {
@@ -221,6 +435,7 @@ impl FromIterator<Address> for EthereumBlockFilter {
         EthereumBlockFilter {
             contract_addresses: iter.into_iter().collect(),
             trigger_every_block: false,
+            polling_intervals: HashSet::new(),
         }
     }
 }
@@ -250,6 +465,14 @@ pub trait EthereumAdapter: Send + Sync + 'static {
         block_hash: H256,
     ) -> Box<Future<Item = Option<Block<Transaction>>, Error = Error> + Send>;
 
+    /// Find the header of a block by its hash. The returned block has no
+    /// transactions.
+    fn block_header_by_hash(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<Future<Item = Option<Block<Transaction>>, Error = Error> + Send>;
+
     /// Load full information for the specified `block` (in particular, transaction receipts).
     fn load_full_block(
         &self,
@@ -302,6 +525,15 @@ pub trait EthereumAdapter: Send + Sync + 'static {
         block_hash: H256,
     ) -> Box<Future<Item = Vec<EthereumCall>, Error = Error> + Send>;
 
+    /// Find all logs that the transactions of a block emitted, without
+    /// loading the transaction receipts of the block.
+    fn logs_in_block(
+        &self,
+        logger: &Logger,
+        block_number: u64,
+        block_hash: H256,
+    ) -> Box<Future<Item = Vec<Log>, Error = Error> + Send>;
+
     fn blocks_with_triggers(
         &self,
         logger: &Logger,
@@ -354,3 +586,55 @@ pub trait EthereumAdapter: Send + Sync + 'static {
         call: EthereumContractCall,
     ) -> Box<Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send>;
 }
+
+#[test]
+fn eth_get_logs_filters_group_contracts_by_event_signatures() {
+    let (a, b, c) = (Address::from(1), Address::from(2), Address::from(3));
+    let (transfer, approval, mint) = (H256::from(10), H256::from(11), H256::from(12));
+
+    let filter: EthereumLogFilter = vec![
+        (Some(a), transfer),
+        (Some(a), approval),
+        (Some(b), transfer),
+        (Some(b), approval),
+        (Some(c), transfer),
+        (Some(c), mint),
+        (None, mint),
+    ]
+    .into_iter()
+    .collect();
+
+    let mut filters = filter.eth_get_logs_filters();
+    filters.sort();
+    assert_eq!(
+        filters,
+        vec![
+            (vec![], vec![mint]),
+            (vec![a, b], vec![transfer, approval]),
+            (vec![c], vec![transfer]),
+        ]
+    );
+}
+
+#[test]
+fn polling_filters_select_the_blocks_at_their_intervals() {
+    let filter = EthereumBlockFilter {
+        contract_addresses: HashSet::new(),
+        trigger_every_block: false,
+        polling_intervals: vec![4, 6].into_iter().collect(),
+    };
+    assert_eq!(filter.polling_block_numbers(1, 13), vec![4, 6, 8, 12]);
+    assert_eq!(filter.polling_block_numbers(12, 12), vec![12]);
+    assert_eq!(filter.polling_block_numbers(13, 15), Vec::<u64>::new());
+    assert_eq!(filter.polling_block_numbers(0, 4), vec![0, 4]);
+}
+
+#[test]
+fn ethereum_network_identifier_check() {
+    let mainnet = EthereumNetworkIdentifier::well_known("mainnet").unwrap();
+    let ropsten = EthereumNetworkIdentifier::well_known("ropsten").unwrap();
+
+    assert!(mainnet.check(&mainnet).is_ok());
+    assert!(ropsten.check(&mainnet).is_err());
+    assert!(EthereumNetworkIdentifier::well_known("my-devnet").is_none());
+}
@@ -2,6 +2,7 @@ use serde::de::{Deserialize, Deserializer, Error as DeserializerError};
 use std::str::FromStr;
 use web3::types::H256;
 
+use super::types::EthereumBlockPointer;
 use crate::components::EventProducer;
 
 /// Deserialize an H256 hash (with or without '0x' prefix).
@@ -14,15 +15,141 @@ where
     H256::from_str(block_hash).map_err(D::Error::custom)
 }
 
+/// Deserialize an optional H256 hash (with or without '0x' prefix).
+fn deserialize_optional_h256<'de, D>(deserializer: D) -> Result<Option<H256>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Deserialize::deserialize(deserializer)?;
+    s.map(|s| H256::from_str(s.trim_start_matches("0x")).map_err(D::Error::custom))
+        .transpose()
+}
+
+/// Deserialize a list of block pointers whose hashes may lack the '0x' prefix.
+fn deserialize_block_ptrs<'de, D>(deserializer: D) -> Result<Vec<EthereumBlockPointer>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct BlockPtr {
+        #[serde(deserialize_with = "deserialize_h256")]
+        hash: H256,
+        number: u64,
+    }
+
+    let ptrs: Option<Vec<BlockPtr>> = Deserialize::deserialize(deserializer)?;
+    Ok(ptrs
+        .unwrap_or_default()
+        .into_iter()
+        .map(|ptr| (ptr.hash, ptr.number).into())
+        .collect())
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ChainHeadUpdate {
     pub network_name: String,
     #[serde(deserialize_with = "deserialize_h256")]
     pub head_block_hash: H256,
     pub head_block_number: u64,
+    /// The chain head before this update, if there was one.
+    #[serde(default, deserialize_with = "deserialize_optional_h256")]
+    pub previous_head_block_hash: Option<H256>,
+    #[serde(default)]
+    pub previous_head_block_number: Option<u64>,
+    /// The most recent block that the previous and the new chain head have
+    /// in common. Only known if it is one of the most recent ancestors that
+    /// the block ingestor keeps in the store.
+    #[serde(default, deserialize_with = "deserialize_optional_h256")]
+    pub common_ancestor_block_hash: Option<H256>,
+    #[serde(default)]
+    pub common_ancestor_block_number: Option<u64>,
+    /// The blocks of the previous branch that the update reverted, most
+    /// recent first. Empty if the update advanced the chain head.
+    #[serde(default, deserialize_with = "deserialize_block_ptrs")]
+    pub reverted_blocks: Vec<EthereumBlockPointer>,
+}
+
+impl ChainHeadUpdate {
+    pub fn head_block_ptr(&self) -> EthereumBlockPointer {
+        (self.head_block_hash, self.head_block_number).into()
+    }
+
+    /// If the update switched the chain head to a different branch, returns
+    /// the common ancestor of both branches; all blocks after it on the
+    /// previous branch were reverted. Returns `None` if the update advanced
+    /// the chain head, or if the common ancestor is not known.
+    pub fn reverted_to(&self) -> Option<EthereumBlockPointer> {
+        match (
+            self.previous_head_block_hash,
+            self.common_ancestor_block_hash,
+            self.common_ancestor_block_number,
+        ) {
+            (Some(previous_hash), Some(ancestor_hash), Some(ancestor_number))
+                if previous_hash != ancestor_hash =>
+            {
+                Some((ancestor_hash, ancestor_number).into())
+            }
+            _ => None,
+        }
+    }
 }
 
 pub trait ChainHeadUpdateListener: EventProducer<ChainHeadUpdate> {
     /// Begin processing notifications coming in from Postgres.
     fn start(&mut self);
 }
+
+#[test]
+fn chain_head_update_reverted_to() {
+    let advance: ChainHeadUpdate = serde_json::from_str(
+        r#"{
+            "network_name": "mainnet",
+            "head_block_hash": "0x0000000000000000000000000000000000000000000000000000000000000003",
+            "head_block_number": 3,
+            "previous_head_block_hash": "0000000000000000000000000000000000000000000000000000000000000002",
+            "previous_head_block_number": 2,
+            "common_ancestor_block_hash": "0000000000000000000000000000000000000000000000000000000000000002",
+            "common_ancestor_block_number": 2,
+            "reverted_blocks": []
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(advance.reverted_to(), None);
+    assert!(advance.reverted_blocks.is_empty());
+
+    let revert: ChainHeadUpdate = serde_json::from_str(
+        r#"{
+            "network_name": "mainnet",
+            "head_block_hash": "0000000000000000000000000000000000000000000000000000000000000013",
+            "head_block_number": 3,
+            "previous_head_block_hash": "0000000000000000000000000000000000000000000000000000000000000002",
+            "previous_head_block_number": 2,
+            "common_ancestor_block_hash": "0000000000000000000000000000000000000000000000000000000000000001",
+            "common_ancestor_block_number": 1,
+            "reverted_blocks": [
+                {
+                    "hash": "0000000000000000000000000000000000000000000000000000000000000002",
+                    "number": 2
+                }
+            ]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(revert.reverted_to(), Some((H256::from(1), 1u64).into()));
+    assert_eq!(
+        revert.reverted_blocks,
+        vec![EthereumBlockPointer::from((H256::from(2), 2u64))]
+    );
+
+    // Events from before the common ancestor was included
+    let unknown: ChainHeadUpdate = serde_json::from_str(
+        r#"{
+            "network_name": "mainnet",
+            "head_block_hash": "0000000000000000000000000000000000000000000000000000000000000003",
+            "head_block_number": 3
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(unknown.reverted_to(), None);
+    assert!(unknown.reverted_blocks.is_empty());
+}
@@ -1,18 +1,24 @@
 mod adapter;
 mod listener;
+mod network;
 mod stream;
 mod types;
 
 pub use self::adapter::{
-    EthereumAdapter, EthereumAdapterError, EthereumBlockFilter, EthereumCallFilter,
-    EthereumContractCall, EthereumContractCallError, EthereumContractState,
+    EthereumAdapter, EthereumAdapterError, EthereumBlockFilter, EthereumCallCache,
+    EthereumCallFilter, EthereumContractCall, EthereumContractCallError, EthereumContractState,
     EthereumContractStateError, EthereumContractStateRequest, EthereumLogFilter,
-    EthereumNetworkIdentifier,
+    EthereumNetworkIdentifier, EthereumNetworkMismatch,
 };
 pub use self::listener::{ChainHeadUpdate, ChainHeadUpdateListener};
+pub use self::network::{
+    EthereumNetworkCapabilities, EthereumNetworkError, EthereumNetworks,
+    EthereumProviderCapabilities,
+};
 pub use self::stream::{BlockStream, BlockStreamBuilder};
 pub use self::types::{
-    EthereumBlock, EthereumBlockData, EthereumBlockPointer, EthereumBlockTriggerType,
-    EthereumBlockWithCalls, EthereumBlockWithTriggers, EthereumCall, EthereumCallData,
-    EthereumEventData, EthereumTransactionData, EthereumTrigger,
+    BlockDetail, EntityChangeFilter, EntityChangeTrigger, EthereumBlock, EthereumBlockData,
+    EthereumBlockPointer, EthereumBlockTriggerType, EthereumBlockWithCalls,
+    EthereumBlockWithTriggers, EthereumCall, EthereumCallData, EthereumEventData,
+    EthereumTransactionData, EthereumTransactionReceiptData, EthereumTrigger,
 };
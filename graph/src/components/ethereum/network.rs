@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[derive(Debug, Fail)]
+pub enum EthereumNetworkError {
+    #[fail(display = "Ethereum network not supported by this node: {}", _0)]
+    NotSupported(String),
+    #[fail(
+        display = "no Ethereum network declared, but this node supports several: {}",
+        _0
+    )]
+    NotDeclared(String),
+    #[fail(
+        display = "subgraph requires {}, but none of the Ethereum nodes for network `{}` support it",
+        _1, _0
+    )]
+    MissingCapability(String, String),
+}
+
+/// What an Ethereum node can do, as probed when `graph-node` connects to it.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EthereumProviderCapabilities {
+    /// The name of the provider, e.g. its URL.
+    pub provider: String,
+    /// The node keeps the state of old blocks, so contract calls against
+    /// them succeed.
+    pub archive: bool,
+    /// The node can trace the calls in a block, with `trace_filter` or
+    /// `debug_traceBlockByNumber`.
+    pub traces: bool,
+    /// The largest block range the node accepts for `eth_getLogs`, if it
+    /// limits it.
+    pub max_log_range: Option<u64>,
+}
+
+/// What the Ethereum nodes of a network can do between them.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EthereumNetworkCapabilities {
+    pub network: String,
+    pub providers: Vec<EthereumProviderCapabilities>,
+}
+
+impl EthereumNetworkCapabilities {
+    pub fn archive(&self) -> bool {
+        self.providers.iter().any(|provider| provider.archive)
+    }
+
+    pub fn traces(&self) -> bool {
+        self.providers.iter().any(|provider| provider.traces)
+    }
+
+    /// The largest block range that all nodes accept for `eth_getLogs`.
+    pub fn max_log_range(&self) -> Option<u64> {
+        self.providers
+            .iter()
+            .filter_map(|provider| provider.max_log_range)
+            .min()
+    }
+}
+
+/// Components of one kind, such as Ethereum adapters or chain stores, for
+/// each Ethereum network the node is connected to.
+#[derive(Debug)]
+pub struct EthereumNetworks<T> {
+    networks: BTreeMap<String, Arc<T>>,
+}
+
+impl<T> Clone for EthereumNetworks<T> {
+    fn clone(&self) -> Self {
+        EthereumNetworks {
+            networks: self.networks.clone(),
+        }
+    }
+}
+
+impl<T> EthereumNetworks<T> {
+    pub fn new() -> Self {
+        EthereumNetworks {
+            networks: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, network_name: String, component: Arc<T>) {
+        self.networks.insert(network_name, component);
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.networks.keys().map(|name| name.as_str()).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Arc<T>)> {
+        self.networks.iter()
+    }
+
+    /// Returns the component for `network_name`. Subgraphs that don't
+    /// declare a network use the only network the node is connected to;
+    /// if there are several, that is an error.
+    pub fn get(&self, network_name: Option<&str>) -> Result<Arc<T>, EthereumNetworkError> {
+        match network_name {
+            Some(network_name) => self
+                .networks
+                .get(network_name)
+                .cloned()
+                .ok_or_else(|| EthereumNetworkError::NotSupported(network_name.to_owned())),
+            None if self.networks.len() == 1 => Ok(self.networks.values().next().unwrap().clone()),
+            None => Err(EthereumNetworkError::NotDeclared(self.names().join(", "))),
+        }
+    }
+}
+
+#[test]
+fn ethereum_networks_get() {
+    let mut networks = EthereumNetworks::new();
+    networks.insert("mainnet".to_owned(), Arc::new(1));
+    assert_eq!(*networks.get(Some("mainnet")).unwrap(), 1);
+    assert_eq!(*networks.get(None).unwrap(), 1);
+    assert!(networks.get(Some("ropsten")).is_err());
+
+    networks.insert("ropsten".to_owned(), Arc::new(3));
+    assert_eq!(*networks.get(Some("ropsten")).unwrap(), 3);
+    assert!(networks.get(None).is_err());
+}
@@ -1,4 +1,5 @@
 use failure::Error;
+use futures::sync::mpsc::Sender;
 use futures::Stream;
 
 use crate::prelude::*;
@@ -6,10 +7,22 @@ use crate::prelude::*;
 pub trait BlockStream:
     Stream<Item = EthereumBlockWithTriggers, Error = Error> + EventConsumer<ChainHeadUpdate>
 {
+    /// A sender for the pointers of the blocks that the consumer has
+    /// written to the store. Before the stream looks for more blocks, it
+    /// waits until the blocks it yielded have been written, and it is woken
+    /// up by these notifications. Consumers must send on it after every
+    /// block they write; a notification that doesn't fit into the channel
+    /// can be dropped, since one that the stream hasn't seen yet is still
+    /// queued.
+    fn written_blocks_sink(&self) -> Sender<EthereumBlockPointer>;
 }
 
 pub trait BlockStreamBuilder: Clone + Send + Sync {
     type Stream: BlockStream + Send + 'static;
 
-    fn from_subgraph(&self, manifest: &SubgraphManifest, logger: Logger) -> Self::Stream;
+    fn from_subgraph(
+        &self,
+        manifest: &SubgraphManifest,
+        logger: Logger,
+    ) -> Result<Self::Stream, Error>;
 }
@@ -1,8 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use ethabi::{LogParam, Param};
 use web3::types::*;
 
+use crate::data::store::Entity;
+use crate::data::subgraph::SubgraphDeploymentId;
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct EthereumBlockWithTriggers {
     pub ethereum_block: EthereumBlock,
@@ -13,6 +16,9 @@ pub struct EthereumBlockWithTriggers {
 pub struct EthereumBlockWithCalls {
     pub ethereum_block: EthereumBlock,
     pub calls: Option<Vec<EthereumCall>>,
+    /// The logs of the block, if they were loaded without the transaction
+    /// receipts of the block.
+    pub logs: Option<Vec<Log>>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -21,7 +27,31 @@ pub struct EthereumBlock {
     pub transaction_receipts: Vec<TransactionReceipt>,
 }
 
+/// How much of a block the handlers of a subgraph need. Each level includes
+/// the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BlockDetail {
+    /// Only the block header.
+    Header,
+    /// The transactions, which event and call handlers are passed.
+    Transactions,
+    /// The transaction receipts, for event handlers with `receipt: true`.
+    Receipts,
+}
+
 impl EthereumBlock {
+    /// Drops the transactions and receipts of the block that go beyond
+    /// `detail`, so that they aren't kept in memory while handlers run.
+    pub fn with_detail(mut self, detail: BlockDetail) -> Self {
+        if detail < BlockDetail::Receipts {
+            self.transaction_receipts = vec![];
+        }
+        if detail < BlockDetail::Transactions {
+            self.block.transactions = vec![];
+        }
+        self
+    }
+
     pub fn transaction_for_log(&self, log: &Log) -> Option<Transaction> {
         log.transaction_hash
             .and_then(|hash| self.block.transactions.iter().find(|tx| tx.hash == hash))
@@ -114,12 +144,32 @@ pub enum EthereumTrigger {
     Block(EthereumBlockTriggerType),
     Call(EthereumCall),
     Log(Log),
+    EntityChange(EntityChangeTrigger),
+}
+
+/// A change that another subgraph deployment made to one of its entities
+/// in a block, for the entity handlers of subgraph data sources.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EntityChangeTrigger {
+    pub subgraph_id: SubgraphDeploymentId,
+    pub entity_type: String,
+    pub entity_id: String,
+    /// The entity as of the end of the block, `None` if it was removed.
+    pub entity: Option<Entity>,
+}
+
+/// The entity types of other subgraph deployments whose changes trigger the
+/// entity handlers of a subgraph, by deployment.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EntityChangeFilter {
+    pub entity_types: BTreeMap<SubgraphDeploymentId, Vec<String>>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum EthereumBlockTriggerType {
     Every,
     WithCallTo(Address),
+    Polling(u64),
 }
 
 impl EthereumTrigger {
@@ -140,7 +190,7 @@ impl EthereumTrigger {
                     None => Err(()),
                 }
             }
-            EthereumTrigger::Block(_call) => Ok(None),
+            EthereumTrigger::Block(_) | EthereumTrigger::EntityChange(_) => Ok(None),
         }
     }
 }
@@ -213,6 +263,32 @@ impl<'a> From<&'a Transaction> for EthereumTransactionData {
     }
 }
 
+/// Ethereum transaction receipt data.
+#[derive(Clone, Debug)]
+pub struct EthereumTransactionReceiptData {
+    pub transaction_hash: H256,
+    pub transaction_index: U128,
+    pub cumulative_gas_used: U256,
+    pub gas_used: U256,
+    pub contract_address: Option<Address>,
+    pub status: Option<U256>,
+    pub logs: Vec<Log>,
+}
+
+impl<'a> From<&'a TransactionReceipt> for EthereumTransactionReceiptData {
+    fn from(receipt: &'a TransactionReceipt) -> EthereumTransactionReceiptData {
+        EthereumTransactionReceiptData {
+            transaction_hash: receipt.transaction_hash,
+            transaction_index: receipt.transaction_index,
+            cumulative_gas_used: receipt.cumulative_gas_used,
+            gas_used: receipt.gas_used,
+            contract_address: receipt.contract_address,
+            status: receipt.status.map(|status| U256::from(status.low_u64())),
+            logs: receipt.logs.clone(),
+        }
+    }
+}
+
 /// An Ethereum event logged from a specific contract address and block.
 #[derive(Debug)]
 pub struct EthereumEventData {
@@ -223,6 +299,8 @@ pub struct EthereumEventData {
     pub block: EthereumBlockData,
     pub transaction: EthereumTransactionData,
     pub params: Vec<LogParam>,
+    /// Only set for event handlers with `receipt: true`.
+    pub receipt: Option<EthereumTransactionReceiptData>,
 }
 
 impl Clone for EthereumEventData {
@@ -242,6 +320,7 @@ impl Clone for EthereumEventData {
                     value: log_param.value.clone(),
                 })
                 .collect(),
+            receipt: self.receipt.clone(),
         }
     }
 }
@@ -2,15 +2,28 @@ use crate::data::subgraph::Link;
 use bytes::BytesMut;
 use failure;
 use futures::{stream::poll_fn, try_ready};
+use hex;
 use ipfs_api;
+use reqwest;
 use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::prelude::*;
 
+use crate::prelude::{o, warn, Logger};
+use crate::util::futures::retry;
+
 const MAX_IPFS_FILE_BYTES_ENV_VAR: &str = "GRAPH_MAX_IPFS_FILE_BYTES";
 
+const IPFS_CACHE_DIR_ENV_VAR: &str = "GRAPH_IPFS_CACHE_DIR";
+
 const MAX_IPFS_MAP_FILE_SIZE_ENV_VAR: &str = "GRAPH_MAX_IPFS_MAP_FILE_SIZE";
 
 // Default size limitation for streaming files through ipfs_map is
@@ -19,12 +32,19 @@ const MAX_IPFS_MAP_FILE_SIZE: u64 = 256 * 1024 * 1024;
 
 /// The values that `json_stream` returns. The struct contains the deserialized
 /// JSON value from the input stream, together with the line number from which
-/// the value was read.
+/// the value was read and the size of that line in bytes.
 pub struct StreamValue {
     pub value: Value,
     pub line: usize,
+    pub bytes: usize,
 }
 
+/// The error of `LinkResolver::cat_at_most` for files that are larger than
+/// the caller is willing to read.
+#[derive(Debug, Fail)]
+#[fail(display = "file {} is larger than {} bytes", _0, _1)]
+pub struct FileTooLarge(pub String, pub u64);
+
 type ValueStream = Box<Stream<Item = StreamValue, Error = failure::Error> + Send + 'static>;
 
 /// Resolves links to subgraph manifests and resources referenced by them.
@@ -32,6 +52,24 @@ pub trait LinkResolver: Send + Sync + 'static {
     /// Fetches the link contents as bytes.
     fn cat(&self, link: &Link) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send>;
 
+    /// Fetches the link contents as bytes, failing with `FileTooLarge` as
+    /// soon as they turn out to be larger than `max_bytes`. Resolvers that
+    /// can tell while they download a file should stop downloading then.
+    fn cat_at_most(
+        &self,
+        link: &Link,
+        max_bytes: u64,
+    ) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+        let link = link.link.clone();
+        Box::new(
+            self.cat(&Link { link: link.clone() })
+                .and_then(move |bytes| match bytes.len() as u64 > max_bytes {
+                    false => Ok(bytes),
+                    true => Err(FileTooLarge(link, max_bytes).into()),
+                }),
+        )
+    }
+
     /// Read the contents of `link` and deserialize them into a stream of JSON
     /// values. The values must each be on a single line; newlines are significant
     /// as they are used to split the file contents and each line is deserialized
@@ -61,57 +99,344 @@ fn read_u64_from_env(name: &str) -> Option<u64> {
     })
 }
 
-/// Wrap the future `fut` into another future that only resolves successfully
-/// if the IPFS file at `path` is no bigger than `max_file_bytes`.
-/// If `max_file_bytes` is `None`, do not restrict the size of the file
-fn restrict_file_size<T>(
-    client: &ipfs_api::IpfsClient,
-    path: String,
-    max_file_bytes: Option<u64>,
-    fut: Box<Future<Item = T, Error = failure::Error> + Send>,
-) -> Box<Future<Item = T, Error = failure::Error> + Send>
-where
-    T: Send + 'static,
-{
-    match max_file_bytes {
-        Some(max_bytes) => Box::new(
+/// The maximum size of files that are streamed with `json_stream`.
+fn max_map_file_size() -> u64 {
+    read_u64_from_env(MAX_IPFS_MAP_FILE_SIZE_ENV_VAR).unwrap_or(MAX_IPFS_MAP_FILE_SIZE)
+}
+
+/// How long an IPFS node that failed a request is tried only after the
+/// other IPFS nodes.
+const UNHEALTHY_IPFS_NODE_BACKOFF: Duration = Duration::from_secs(60);
+
+/// An IPFS node and whether it failed recently.
+struct IpfsNode {
+    client: Arc<ipfs_api::IpfsClient>,
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl IpfsNode {
+    fn is_healthy(&self, now: Instant) -> bool {
+        match *self.unhealthy_until.lock().unwrap() {
+            Some(unhealthy_until) => unhealthy_until <= now,
+            None => true,
+        }
+    }
+
+    fn mark_healthy(&self) {
+        *self.unhealthy_until.lock().unwrap() = None;
+    }
+
+    fn mark_unhealthy(&self, now: Instant) {
+        *self.unhealthy_until.lock().unwrap() = Some(now + UNHEALTHY_IPFS_NODE_BACKOFF);
+    }
+}
+
+/// Resolves links with one or more IPFS nodes.
+///
+/// Requests go to the first IPFS node that is healthy. If a node fails a
+/// request or doesn't respond within `timeout`, it is marked as unhealthy
+/// and the request falls back to the next node; unhealthy nodes are only
+/// tried after all healthy ones, until they have been given a minute to
+/// recover. If all nodes fail, the request is retried up to `retries`
+/// times, so that an outage of one IPFS node can't hang a subgraph
+/// deployment. Files larger than `max_file_size` are rejected before they
+/// are downloaded.
+///
+/// If there is a `cache_dir`, files fetched with `cat` are also stored in
+/// it, named after their hash, and later read from there instead of IPFS.
+/// Since IPFS files are content-addressed, cached files never go stale.
+#[derive(Clone)]
+pub struct IpfsResolver {
+    logger: Logger,
+    nodes: Arc<Vec<IpfsNode>>,
+    timeout: Duration,
+    retries: usize,
+    max_file_size: Option<u64>,
+    cache_dir: Option<PathBuf>,
+}
+
+impl IpfsResolver {
+    /// Creates a resolver that uses `clients`, in order of preference, with
+    /// the timeout, the maximum file size and the cache directory taken
+    /// from the environment and 3 retries.
+    pub fn new(logger: &Logger, clients: Vec<Arc<ipfs_api::IpfsClient>>) -> Self {
+        assert!(!clients.is_empty(), "at least one IPFS node is required");
+
+        IpfsResolver {
+            logger: logger.new(o!("component" => "IpfsResolver")),
+            nodes: Arc::new(
+                clients
+                    .into_iter()
+                    .map(|client| IpfsNode {
+                        client,
+                        unhealthy_until: Mutex::new(None),
+                    })
+                    .collect(),
+            ),
+            timeout: ipfs_timeout(),
+            retries: 3,
+            max_file_size: read_u64_from_env(MAX_IPFS_FILE_BYTES_ENV_VAR),
+            cache_dir: env::var_os(IPFS_CACHE_DIR_ENV_VAR).map(PathBuf::from),
+        }
+    }
+
+    /// Sets how long to wait for a request to an IPFS node before falling
+    /// back to the next node.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        IpfsResolver { timeout, ..self }
+    }
+
+    /// Sets how many times requests that failed on all IPFS nodes are
+    /// retried.
+    pub fn with_retries(self, retries: usize) -> Self {
+        IpfsResolver { retries, ..self }
+    }
+
+    /// Sets the maximum size of files that `cat` downloads; `None` removes
+    /// the limit.
+    pub fn with_max_file_size(self, max_file_size: Option<u64>) -> Self {
+        IpfsResolver {
+            max_file_size,
+            ..self
+        }
+    }
+
+    /// Sets the directory in which files fetched with `cat` are cached;
+    /// `None` disables the cache.
+    pub fn with_cache_dir(self, cache_dir: Option<PathBuf>) -> Self {
+        IpfsResolver { cache_dir, ..self }
+    }
+
+    /// Returns where the file at `path` is cached, if the cache is enabled.
+    /// Only files that are addressed by a hash alone are cached, since
+    /// other paths can't be safely turned into file names.
+    fn cache_path(&self, path: &str) -> Option<PathBuf> {
+        let cache_dir = self.cache_dir.as_ref()?;
+        if !path.is_empty() && path.chars().all(|c| c.is_ascii_alphanumeric()) {
+            Some(cache_dir.join(path))
+        } else {
+            None
+        }
+    }
+
+    /// Reads a cached file, unless it is not cached or larger than the
+    /// maximum file size.
+    fn read_cached(&self, cache_path: &PathBuf) -> Option<Vec<u8>> {
+        let bytes = fs::read(cache_path).ok()?;
+        match self.max_file_size {
+            Some(max_bytes) if bytes.len() as u64 > max_bytes => None,
+            _ => Some(bytes),
+        }
+    }
+
+    /// Fetches the file of `link`, from the cache if possible, failing with
+    /// `FileTooLarge` once more than `max_bytes` have been read.
+    fn cat_limited(
+        &self,
+        link: &Link,
+        max_bytes: Option<u64>,
+    ) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+        // Discard the `/ipfs/` prefix (if present) to get the hash.
+        let path = link.link.trim_start_matches("/ipfs/").to_owned();
+        let too_large = {
+            let link = link.link.clone();
+            move |max_bytes| failure::Error::from(FileTooLarge(link.clone(), max_bytes))
+        };
+
+        let cache_path = self.cache_path(&path);
+        if let Some(bytes) = cache_path.as_ref().and_then(|p| self.read_cached(p)) {
+            return Box::new(match max_bytes {
+                Some(max_bytes) if bytes.len() as u64 > max_bytes => {
+                    future::err(too_large(max_bytes))
+                }
+                _ => future::ok(bytes),
+            });
+        }
+
+        let cat_path = path.clone();
+        let cat = self.request(format!("IPFS cat {}", path), move |client| {
+            let too_large = too_large.clone();
             client
-                .object_stat(&path)
-                .timeout(ipfs_timeout())
+                .cat(&cat_path)
                 .map_err(|e| failure::err_msg(e.to_string()))
-                .and_then(move |stat| match stat.cumulative_size > max_bytes {
-                    false => Ok(()),
-                    true => Err(format_err!(
-                        "IPFS file {} is too large. It can be at most {} bytes but is {} bytes",
-                        path,
-                        max_bytes,
-                        stat.cumulative_size
-                    )),
+                .fold(Vec::new(), move |mut bytes, chunk| {
+                    bytes.extend_from_slice(&chunk);
+                    match max_bytes {
+                        Some(max_bytes) if bytes.len() as u64 > max_bytes => {
+                            Err(too_large(max_bytes))
+                        }
+                        _ => Ok(bytes),
+                    }
                 })
-                .and_then(|()| fut),
-        ),
-        None => fut,
+        });
+
+        let logger = self.logger.clone();
+        Box::new(
+            self.restrict_file_size(path, self.max_file_size, cat)
+                .map(move |bytes| {
+                    if let Some(cache_path) = cache_path {
+                        Self::write_cached(&logger, &cache_path, &bytes);
+                    }
+                    bytes
+                }),
+        )
+    }
+
+    /// Stores a file in the cache. The file is written under a temporary
+    /// name first, so that other readers never see a partial file. Failing
+    /// to cache a file is not an error.
+    fn write_cached(logger: &Logger, cache_path: &PathBuf, bytes: &[u8]) {
+        let tmp_path = cache_path.with_extension("tmp");
+        let result = cache_path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&tmp_path, bytes))
+            .and_then(|()| fs::rename(&tmp_path, cache_path));
+        if let Err(e) = result {
+            warn!(
+                logger,
+                "Failed to cache IPFS file";
+                "path" => cache_path.display().to_string(),
+                "error" => e.to_string(),
+            );
+        }
+    }
+
+    /// Returns the indexes of the IPFS nodes in the order in which they
+    /// should be tried: healthy nodes first, then the unhealthy ones.
+    fn node_order(nodes: &[IpfsNode], now: Instant) -> Vec<usize> {
+        let (healthy, unhealthy): (Vec<_>, Vec<_>) =
+            (0..nodes.len()).partition(|index| nodes[*index].is_healthy(now));
+        healthy.into_iter().chain(unhealthy).collect()
+    }
+
+    /// Sends a request to the IPFS nodes, falling back to the next node
+    /// whenever a node fails or times out, and retries it if all nodes
+    /// failed.
+    fn request<T, F, R>(
+        &self,
+        description: String,
+        request: F,
+    ) -> Box<Future<Item = T, Error = failure::Error> + Send>
+    where
+        T: fmt::Debug + Send + 'static,
+        F: Fn(&ipfs_api::IpfsClient) -> R + Send + Sync + 'static,
+        R: Future<Item = T, Error = failure::Error> + Send + 'static,
+    {
+        let logger = self.logger.clone();
+        let nodes = self.nodes.clone();
+        let timeout = self.timeout;
+        let request = Arc::new(request);
+
+        Box::new(
+            retry(description.clone(), &self.logger)
+                .limit(self.retries)
+                // Files that are too large are just as large on every node
+                .when(|result| match result {
+                    Ok(_) => false,
+                    Err(e) => e.downcast_ref::<FileTooLarge>().is_none(),
+                })
+                .no_timeout()
+                .run(move || {
+                    let logger = logger.clone();
+                    let nodes = nodes.clone();
+                    let request = request.clone();
+                    let description = description.clone();
+                    let order = Self::node_order(&nodes, Instant::now()).into_iter();
+
+                    future::loop_fn(order, move |mut order| {
+                        let index = order.next().expect("there is always an IPFS node to try");
+                        let logger = logger.clone();
+                        let nodes = nodes.clone();
+                        let description = description.clone();
+
+                        request(&nodes[index].client)
+                            .timeout(timeout)
+                            .then(move |result| match result {
+                                Ok(value) => {
+                                    nodes[index].mark_healthy();
+                                    Ok(future::Loop::Break(value))
+                                }
+                                Err(e) => {
+                                    let e = e.into_inner().unwrap_or_else(|| {
+                                        format_err!(
+                                            "IPFS node took too long to respond to {}",
+                                            description
+                                        )
+                                    });
+                                    if e.downcast_ref::<FileTooLarge>().is_some() {
+                                        return Err(e);
+                                    }
+                                    nodes[index].mark_unhealthy(Instant::now());
+                                    warn!(
+                                        logger,
+                                        "IPFS node failed, marking it as unhealthy";
+                                        "node" => index,
+                                        "request" => &description,
+                                        "error" => e.to_string(),
+                                    );
+                                    if order.len() == 0 {
+                                        Err(e)
+                                    } else {
+                                        Ok(future::Loop::Continue(order))
+                                    }
+                                }
+                            })
+                    })
+                }),
+        )
+    }
+
+    /// Wrap the future `fut` into another future that only resolves successfully
+    /// if the IPFS file at `path` is no bigger than `max_file_bytes`.
+    /// If `max_file_bytes` is `None`, do not restrict the size of the file
+    fn restrict_file_size<T>(
+        &self,
+        path: String,
+        max_file_bytes: Option<u64>,
+        fut: Box<Future<Item = T, Error = failure::Error> + Send>,
+    ) -> Box<Future<Item = T, Error = failure::Error> + Send>
+    where
+        T: Send + 'static,
+    {
+        let max_bytes = match max_file_bytes {
+            Some(max_bytes) => max_bytes,
+            None => return fut,
+        };
+
+        let stat_path = path.clone();
+        Box::new(
+            self.request(format!("IPFS object stat {}", path), move |client| {
+                client
+                    .object_stat(&stat_path)
+                    .map_err(|e| failure::err_msg(e.to_string()))
+            })
+            .and_then(move |stat| match stat.cumulative_size > max_bytes {
+                false => Ok(()),
+                true => Err(format_err!(
+                    "IPFS file {} is too large. It can be at most {} bytes but is {} bytes",
+                    path,
+                    max_bytes,
+                    stat.cumulative_size
+                )),
+            })
+            .and_then(|()| fut),
+        )
     }
 }
 
-impl LinkResolver for ipfs_api::IpfsClient {
+impl LinkResolver for IpfsResolver {
     /// Supports links of the form `/ipfs/ipfs_hash` or just `ipfs_hash`.
     fn cat(&self, link: &Link) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
-        // Grab env vars.
-        let max_file_bytes = read_u64_from_env(MAX_IPFS_FILE_BYTES_ENV_VAR);
-
-        // Discard the `/ipfs/` prefix (if present) to get the hash.
-        let path = link.link.trim_start_matches("/ipfs/").to_owned();
-
-        let ipfs_timeout = ipfs_timeout();
-        let cat = self
-            .cat(&path)
-            .concat2()
-            .timeout(ipfs_timeout)
-            .map(|x| x.to_vec())
-            .map_err(|e| failure::err_msg(e.to_string()));
+        self.cat_limited(link, None)
+    }
 
-        restrict_file_size(&self, path, max_file_bytes, Box::new(cat))
+    fn cat_at_most(
+        &self,
+        link: &Link,
+        max_bytes: u64,
+    ) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+        self.cat_limited(link, Some(max_bytes))
     }
 
     fn json_stream(
@@ -120,7 +445,10 @@ impl LinkResolver for ipfs_api::IpfsClient {
     ) -> Box<Future<Item = ValueStream, Error = failure::Error> + Send + 'static> {
         // Discard the `/ipfs/` prefix (if present) to get the hash.
         let path = link.link.trim_start_matches("/ipfs/").to_owned();
-        let mut stream = self.cat(&path).fuse();
+        // Streams can't fall back to another IPFS node halfway through, so
+        // they are read from the first healthy node
+        let node = Self::node_order(&self.nodes, Instant::now())[0];
+        let mut stream = self.nodes[node].client.cat(&path).fuse();
         let mut buf = BytesMut::with_capacity(1024);
         // Count the number of lines we've already successfully deserialized.
         // We need that to adjust the line number in error messages from serde_json
@@ -140,6 +468,7 @@ impl LinkResolver for ipfs_api::IpfsClient {
                                 Ok(v) => Ok(Async::Ready(Some(StreamValue {
                                     value: v,
                                     line: count,
+                                    bytes: line_bytes.len(),
                                 }))),
                                 Err(e) => {
                                     // Adjust the line number in the serde error. This
@@ -176,23 +505,757 @@ impl LinkResolver for ipfs_api::IpfsClient {
             },
         ));
         // Check the size of the file
-        let max_file_bytes =
-            read_u64_from_env(MAX_IPFS_MAP_FILE_SIZE_ENV_VAR).unwrap_or(MAX_IPFS_MAP_FILE_SIZE);
-
-        restrict_file_size(
-            &self,
+        self.restrict_file_size(
             path,
-            Some(max_file_bytes),
+            Some(max_map_file_size()),
             Box::new(future::ok(stream)),
         )
     }
 }
 
+/// Resolves links from the local filesystem, for developing subgraphs
+/// without publishing every change to IPFS; all other links are resolved
+/// with the `fallback` resolver.
+///
+/// A local subgraph is a manifest on disk that is served under an alias,
+/// so that it can be deployed like a subgraph on IPFS with the alias as its
+/// hash. Once there is a local subgraph, links of the form `file:PATH` are
+/// read from disk, too, with relative paths taken relative to the root
+/// directory, which is the working directory unless set otherwise. Links to
+/// files outside of the root are rejected, so that a manifest can't make
+/// the node read arbitrary files. Without local subgraphs, `file:` links
+/// are passed on to the fallback, so that deployed subgraphs can't read
+/// files from the node.
+pub struct FileLinkResolver<R> {
+    fallback: Arc<R>,
+    local_subgraphs: HashMap<String, PathBuf>,
+    root: PathBuf,
+}
+
+impl<R> FileLinkResolver<R>
+where
+    R: LinkResolver,
+{
+    pub fn new(fallback: Arc<R>) -> Self {
+        FileLinkResolver {
+            fallback,
+            local_subgraphs: HashMap::new(),
+            root: PathBuf::from("."),
+        }
+    }
+
+    /// Only reads `file:` links to files in `root` or below it.
+    pub fn with_root(self, root: PathBuf) -> Self {
+        FileLinkResolver { root, ..self }
+    }
+
+    /// Serves the manifest at `path` under `alias`.
+    pub fn with_local_subgraph(mut self, alias: String, path: PathBuf) -> Self {
+        self.local_subgraphs.insert(alias, path);
+        self
+    }
+
+    /// Returns the file that `link` refers to, if it is a local file, or an
+    /// error if the file is outside of the root directory.
+    fn local_path(&self, link: &Link) -> Option<Result<PathBuf, failure::Error>> {
+        if let Some(path) = self
+            .local_subgraphs
+            .get(link.link.trim_start_matches("/ipfs/"))
+        {
+            return Some(Ok(path.clone()));
+        }
+
+        if self.local_subgraphs.is_empty() || !link.link.starts_with("file:") {
+            return None;
+        }
+        let path = link
+            .link
+            .trim_start_matches("file://")
+            .trim_start_matches("file:");
+
+        // Resolve `..` and symlinks before comparing the path with the root
+        let canonical_path = |path: &Path| {
+            fs::canonicalize(path)
+                .map_err(|e| format_err!("failed to read local file {}: {}", path.display(), e))
+        };
+        Some(canonical_path(&self.root.join(path)).and_then(|file| {
+            let root = canonical_path(&self.root)?;
+            if file.starts_with(&root) {
+                Ok(file)
+            } else {
+                Err(format_err!(
+                    "local file {} is outside of {}",
+                    path,
+                    root.display()
+                ))
+            }
+        }))
+    }
+}
+
+impl<R> LinkResolver for FileLinkResolver<R>
+where
+    R: LinkResolver,
+{
+    fn cat(&self, link: &Link) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+        match self.local_path(link) {
+            Some(Ok(path)) => {
+                Box::new(future::result(fs::read(&path).map_err(|e| {
+                    format_err!("failed to read local file {}: {}", path.display(), e)
+                })))
+            }
+            Some(Err(e)) => Box::new(future::err(e)),
+            None => self.fallback.cat(link),
+        }
+    }
+
+    fn cat_at_most(
+        &self,
+        link: &Link,
+        max_bytes: u64,
+    ) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+        match self.local_path(link) {
+            Some(Ok(path)) => {
+                // Check the size before reading the file
+                let result = fs::metadata(&path)
+                    .map_err(|e| format_err!("failed to read local file {}: {}", path.display(), e))
+                    .and_then(|metadata| match metadata.len() > max_bytes {
+                        false => Ok(()),
+                        true => Err(FileTooLarge(link.link.clone(), max_bytes).into()),
+                    });
+                match result {
+                    Ok(()) => self.cat(link),
+                    Err(e) => Box::new(future::err(e)),
+                }
+            }
+            Some(Err(e)) => Box::new(future::err(e)),
+            None => self.fallback.cat_at_most(link, max_bytes),
+        }
+    }
+
+    fn json_stream(
+        &self,
+        link: &Link,
+    ) -> Box<Future<Item = ValueStream, Error = failure::Error> + Send + 'static> {
+        let path = match self.local_path(link) {
+            Some(Ok(path)) => path,
+            Some(Err(e)) => return Box::new(future::err(e)),
+            None => return self.fallback.json_stream(link),
+        };
+
+        Box::new(
+            self.cat(link).and_then(move |bytes| {
+                json_values(bytes, format!("local file {}", path.display()))
+            }),
+        )
+    }
+}
+
+/// Resolves links to files served over HTTP(S), for organizations that host
+/// subgraph artifacts in their own artifact store; all other links are
+/// resolved with the `fallback` resolver.
+///
+/// Unlike IPFS links, HTTP links don't identify the contents of a file, so
+/// they must carry the SHA-256 hash of the file, as in
+/// `https://artifacts.example.com/mapping.wasm#sha256=<hex>`, and files that
+/// don't match it are rejected. Only links under one of the
+/// `allowed_prefixes` are fetched, so that deployed subgraphs can't make the
+/// node send requests to arbitrary hosts. Like files on IPFS, files larger
+/// than `max_file_size`, or than the maximum size of files for `ipfs.map`
+/// when they are streamed, are rejected while they are downloaded.
+pub struct HttpLinkResolver<R> {
+    fallback: Arc<R>,
+    client: reqwest::r#async::Client,
+    allowed_prefixes: Vec<reqwest::Url>,
+    timeout: Duration,
+    max_file_size: Option<u64>,
+}
+
+impl<R> HttpLinkResolver<R>
+where
+    R: LinkResolver,
+{
+    /// Creates a resolver that fetches links under `allowed_prefixes`, with
+    /// the timeout and the maximum file size taken from the environment.
+    /// Fails if one of the prefixes is not an HTTP(S) URL.
+    pub fn new(fallback: Arc<R>, allowed_prefixes: Vec<String>) -> Result<Self, failure::Error> {
+        let allowed_prefixes: Vec<reqwest::Url> = allowed_prefixes
+            .iter()
+            .map(|prefix| match reqwest::Url::parse(prefix) {
+                Ok(url) if is_http(&url) => Ok(url),
+                _ => Err(format_err!("{} is not an HTTP(S) URL", prefix)),
+            })
+            .collect::<Result<_, _>>()?;
+
+        // Redirects are checked like links, or any allowed server could
+        // send the node to an arbitrary host
+        let redirect_prefixes = allowed_prefixes.clone();
+        let redirect_policy = reqwest::RedirectPolicy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_HTTP_REDIRECTS {
+                return attempt.too_many_redirects();
+            }
+            if redirect_prefixes
+                .iter()
+                .any(|prefix| is_under(attempt.url(), prefix))
+            {
+                return attempt.follow();
+            }
+            let url = attempt.url().to_string();
+            attempt.error(format!(
+                "redirect to {} is not under any of the URLs this node may fetch files from",
+                url
+            ))
+        });
+        let client = reqwest::r#async::Client::builder()
+            .redirect(redirect_policy)
+            .build()?;
+
+        Ok(HttpLinkResolver {
+            fallback,
+            client,
+            allowed_prefixes,
+            timeout: ipfs_timeout(),
+            max_file_size: read_u64_from_env(MAX_IPFS_FILE_BYTES_ENV_VAR),
+        })
+    }
+
+    /// Sets how long to wait for a file to download.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        HttpLinkResolver { timeout, ..self }
+    }
+
+    /// Sets the maximum size of files that `cat` downloads; `None` removes
+    /// the limit.
+    pub fn with_max_file_size(self, max_file_size: Option<u64>) -> Self {
+        HttpLinkResolver {
+            max_file_size,
+            ..self
+        }
+    }
+
+    /// Splits an HTTP link into the URL to fetch and the expected SHA-256
+    /// hash of the file. Returns `None` for links that are not HTTP links.
+    fn parse_link(&self, link: &Link) -> Option<Result<(String, String), failure::Error>> {
+        if !link.link.starts_with("http://") && !link.link.starts_with("https://") {
+            return None;
+        }
+
+        let mut parts = link.link.splitn(2, "#sha256=");
+        let url = parts.next().unwrap().to_owned();
+        let checksum = match parts.next() {
+            Some(checksum) if !checksum.is_empty() => checksum.to_lowercase(),
+            _ => {
+                return Some(Err(format_err!(
+                    "HTTP link {} has no checksum; add `#sha256=<hex>` to it",
+                    link.link
+                )))
+            }
+        };
+        let allowed = match reqwest::Url::parse(&url) {
+            Ok(parsed) => self
+                .allowed_prefixes
+                .iter()
+                .any(|prefix| is_under(&parsed, prefix)),
+            Err(_) => false,
+        };
+        if !allowed {
+            return Some(Err(format_err!(
+                "HTTP link {} is not under any of the URLs this node may fetch files from",
+                url
+            )));
+        }
+        Some(Ok((url, checksum)))
+    }
+
+    /// Downloads the file at `url`, failing as soon as it turns out to be
+    /// larger than `max_file_size`, or with `FileTooLarge` as soon as it
+    /// turns out to be larger than `max_bytes`.
+    fn fetch(
+        &self,
+        url: String,
+        checksum: String,
+        max_file_size: Option<u64>,
+        max_bytes: Option<u64>,
+    ) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+        let timeout_url = url.clone();
+        let size_url = url.clone();
+        let too_large = move |length: u64| match max_bytes {
+            Some(max_bytes) if length > max_bytes => {
+                Some(FileTooLarge(size_url.clone(), max_bytes).into())
+            }
+            _ => match max_file_size {
+                Some(max_file_size) if length > max_file_size => Some(format_err!(
+                    "file {} is too large. It can be at most {} bytes",
+                    size_url,
+                    max_file_size
+                )),
+                _ => None,
+            },
+        };
+        Box::new(
+            self.client
+                .get(url.as_str())
+                .send()
+                .and_then(|response| response.error_for_status())
+                .map_err(failure::Error::from)
+                .and_then(move |response| {
+                    // Servers don't have to announce the size of a file, so
+                    // the size is also checked while the file downloads
+                    if let Some(e) = response.content_length().and_then(&too_large) {
+                        return future::Either::A(future::err(e));
+                    }
+                    future::Either::B(response.into_body().map_err(failure::Error::from).fold(
+                        Vec::new(),
+                        move |mut bytes, chunk| {
+                            bytes.extend_from_slice(&chunk);
+                            match too_large(bytes.len() as u64) {
+                                Some(e) => Err(e),
+                                None => Ok(bytes),
+                            }
+                        },
+                    ))
+                })
+                .timeout(self.timeout)
+                .map_err(move |e| {
+                    e.into_inner().unwrap_or_else(|| {
+                        format_err!("HTTP server took too long to return file {}", timeout_url)
+                    })
+                })
+                .and_then(move |bytes| {
+                    verify_checksum(&url, &bytes, &checksum)?;
+                    Ok(bytes)
+                }),
+        )
+    }
+}
+
+/// How many redirects `HttpLinkResolver` follows for one file.
+const MAX_HTTP_REDIRECTS: usize = 10;
+
+fn is_http(url: &reqwest::Url) -> bool {
+    (url.scheme() == "http" || url.scheme() == "https") && url.host_str().is_some()
+}
+
+/// Checks that `url` is on the same server as `prefix` and that its path
+/// starts with the path of `prefix`, segment by segment, so that e.g.
+/// `https://files.example.com.evil.org/` is not under
+/// `https://files.example.com`.
+fn is_under(url: &reqwest::Url, prefix: &reqwest::Url) -> bool {
+    let segments = |url: &reqwest::Url| {
+        url.path()
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+    is_http(url)
+        && url.scheme() == prefix.scheme()
+        && url.host_str() == prefix.host_str()
+        && url.port_or_known_default() == prefix.port_or_known_default()
+        && url.username().is_empty()
+        && url.password().is_none()
+        && segments(url).starts_with(&segments(prefix))
+}
+
+/// Checks that `bytes` hash to the hex-encoded SHA-256 `checksum`.
+fn verify_checksum(url: &str, bytes: &[u8], checksum: &str) -> Result<(), failure::Error> {
+    let actual = hex::encode(Sha256::digest(bytes));
+    if actual == checksum {
+        Ok(())
+    } else {
+        Err(format_err!(
+            "file {} does not match its checksum: expected sha256 {}, but got {}",
+            url,
+            checksum,
+            actual
+        ))
+    }
+}
+
+impl<R> LinkResolver for HttpLinkResolver<R>
+where
+    R: LinkResolver,
+{
+    fn cat(&self, link: &Link) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+        match self.parse_link(link) {
+            Some(Ok((url, checksum))) => self.fetch(url, checksum, self.max_file_size, None),
+            Some(Err(e)) => Box::new(future::err(e)),
+            None => self.fallback.cat(link),
+        }
+    }
+
+    fn cat_at_most(
+        &self,
+        link: &Link,
+        max_bytes: u64,
+    ) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+        match self.parse_link(link) {
+            Some(Ok((url, checksum))) => {
+                self.fetch(url, checksum, self.max_file_size, Some(max_bytes))
+            }
+            Some(Err(e)) => Box::new(future::err(e)),
+            None => self.fallback.cat_at_most(link, max_bytes),
+        }
+    }
+
+    fn json_stream(
+        &self,
+        link: &Link,
+    ) -> Box<Future<Item = ValueStream, Error = failure::Error> + Send + 'static> {
+        match self.parse_link(link) {
+            Some(Ok((url, checksum))) => Box::new(
+                self.fetch(url.clone(), checksum, Some(max_map_file_size()), None)
+                    .and_then(move |bytes| json_values(bytes, format!("file {}", url))),
+            ),
+            Some(Err(e)) => Box::new(future::err(e)),
+            None => self.fallback.json_stream(link),
+        }
+    }
+}
+
+/// Deserializes a file that has already been downloaded into a stream of
+/// JSON values, one per line, like `LinkResolver::json_stream`.
+fn json_values(bytes: Vec<u8>, description: String) -> Result<ValueStream, failure::Error> {
+    let text = String::from_utf8(bytes).map_err(|_| format_err!("{} is not UTF-8", description))?;
+    let values: Vec<_> = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str::<Value>(line)
+                .map(|value| StreamValue {
+                    value,
+                    line: index + 1,
+                    bytes: line.len() + 1,
+                })
+                .map_err(|e| {
+                    let msg = e.to_string();
+                    let msg = msg.split(" at line ").next().unwrap();
+                    format_err!(
+                        "{} at line {} column {}: '{}'",
+                        msg,
+                        index + 1,
+                        e.column(),
+                        line
+                    )
+                })
+        })
+        .collect();
+    Ok(Box::new(stream::iter_result(values)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use serde_json::json;
 
+    fn resolver(client: ipfs_api::IpfsClient) -> IpfsResolver {
+        let logger = Logger::root(slog::Discard, o!());
+        IpfsResolver::new(&logger, vec![Arc::new(client)])
+    }
+
+    #[test]
+    fn cat_reads_cached_files() {
+        let cache_dir = env::temp_dir().join(format!("graph-ipfs-cache-{}", std::process::id()));
+        fs::create_dir_all(&cache_dir).unwrap();
+        let hash = "QmCachedFileThatIsNotOnIpfs";
+        fs::write(cache_dir.join(hash), b"cached").unwrap();
+
+        // No IPFS node is needed for cached files
+        let resolver = resolver(ipfs_api::IpfsClient::new("127.0.0.1", 1).unwrap())
+            .with_cache_dir(Some(cache_dir.clone()));
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let link = Link {
+            link: format!("/ipfs/{}", hash),
+        };
+        let bytes = runtime.block_on(resolver.cat(&link));
+        let too_large = runtime.block_on(resolver.cat_at_most(&link, 5));
+        fs::remove_dir_all(&cache_dir).unwrap();
+        assert_eq!(bytes.unwrap(), b"cached".to_vec());
+        assert!(too_large
+            .unwrap_err()
+            .downcast_ref::<FileTooLarge>()
+            .is_some());
+
+        // Paths that aren't a plain hash are not cached
+        assert_eq!(resolver.cache_path("QmHash/../../etc/passwd"), None);
+    }
+
+    #[test]
+    fn file_link_resolver_reads_local_files() {
+        let dir = env::temp_dir().join(format!("graph-local-subgraph-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("subgraph.yaml"), b"manifest").unwrap();
+        fs::write(dir.join("values.json"), b"1\n\n\"two\"\n").unwrap();
+        let outside_name = format!("graph-local-subgraph-{}.json", std::process::id());
+        let outside_file = env::temp_dir().join(&outside_name);
+        fs::write(&outside_file, b"[]").unwrap();
+
+        let ipfs =
+            Arc::new(resolver(ipfs_api::IpfsClient::new("127.0.0.1", 1).unwrap()).with_retries(0));
+        let file_link = |name: &str| Link {
+            link: format!("file:{}", dir.join(name).display()),
+        };
+
+        // Without local subgraphs, `file:` links are not read from disk
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let resolver = FileLinkResolver::new(ipfs.clone());
+        assert!(runtime
+            .block_on(resolver.cat(&file_link("subgraph.yaml")))
+            .is_err());
+
+        let resolver = resolver
+            .with_local_subgraph("QmLocal".to_owned(), dir.join("subgraph.yaml"))
+            .with_root(dir.clone());
+        let manifest = runtime.block_on(resolver.cat(&Link {
+            link: "/ipfs/QmLocal".to_owned(),
+        }));
+        let values = runtime.block_on(
+            resolver
+                .json_stream(&file_link("values.json"))
+                .and_then(|stream| stream.map(|sv| (sv.line, sv.value)).collect()),
+        );
+        let relative = runtime.block_on(resolver.cat(&Link {
+            link: "file:./values.json".to_owned(),
+        }));
+        let at_most = runtime.block_on(resolver.cat_at_most(&file_link("subgraph.yaml"), 8));
+        let too_large = runtime.block_on(resolver.cat_at_most(&file_link("subgraph.yaml"), 7));
+
+        // Files outside of the root are not read, even through `..`
+        let outside = runtime.block_on(resolver.cat(&Link {
+            link: format!("file:{}", dir.join("..").join(&outside_name).display()),
+        }));
+        let escaped = runtime.block_on(resolver.cat(&Link {
+            link: format!("file:../{}", outside_name),
+        }));
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_file(&outside_file).unwrap();
+
+        assert_eq!(manifest.unwrap(), b"manifest".to_vec());
+        assert_eq!(values.unwrap(), vec![(1, json!(1)), (3, json!("two"))]);
+        assert_eq!(relative.unwrap(), b"1\n\n\"two\"\n".to_vec());
+        assert_eq!(at_most.unwrap(), b"manifest".to_vec());
+        assert!(too_large
+            .unwrap_err()
+            .downcast_ref::<FileTooLarge>()
+            .is_some());
+        assert!(outside.unwrap_err().to_string().contains("is outside of"));
+        assert!(escaped.unwrap_err().to_string().contains("is outside of"));
+    }
+
+    #[test]
+    fn http_links_need_a_checksum_and_an_allowed_prefix() {
+        let ipfs = Arc::new(resolver(ipfs_api::IpfsClient::default()));
+        let resolver = HttpLinkResolver::new(
+            ipfs.clone(),
+            vec!["https://artifacts.example.com/subgraphs".to_owned()],
+        )
+        .unwrap();
+        let link = |link: &str| Link {
+            link: link.to_owned(),
+        };
+        let allowed = |url: &str| {
+            resolver
+                .parse_link(&link(&format!("{}#sha256=ab12", url)))
+                .unwrap()
+                .is_ok()
+        };
+
+        assert!(resolver.parse_link(&link("/ipfs/QmHash")).is_none());
+        assert_eq!(
+            resolver
+                .parse_link(&link(
+                    "https://artifacts.example.com/subgraphs/a.wasm#sha256=AB12"
+                ))
+                .unwrap()
+                .unwrap(),
+            (
+                "https://artifacts.example.com/subgraphs/a.wasm".to_owned(),
+                "ab12".to_owned()
+            )
+        );
+        assert!(resolver
+            .parse_link(&link("https://artifacts.example.com/subgraphs/a.wasm"))
+            .unwrap()
+            .is_err());
+
+        // The scheme, host, port and path segments have to match the prefix
+        assert!(allowed(
+            "https://ARTIFACTS.example.com:443/subgraphs/a.wasm"
+        ));
+        assert!(!allowed("https://elsewhere.example.com/subgraphs/a.wasm"));
+        assert!(!allowed(
+            "https://artifacts.example.com.evil.org/subgraphs/a.wasm"
+        ));
+        assert!(!allowed(
+            "https://artifacts.example.com@evil.org/subgraphs/a.wasm"
+        ));
+        assert!(!allowed("http://artifacts.example.com/subgraphs/a.wasm"));
+        assert!(!allowed(
+            "https://artifacts.example.com:8443/subgraphs/a.wasm"
+        ));
+        assert!(!allowed(
+            "https://artifacts.example.com/subgraphs-evil/a.wasm"
+        ));
+        assert!(!allowed(
+            "https://artifacts.example.com/subgraphs/../a.wasm"
+        ));
+
+        assert!(HttpLinkResolver::new(ipfs, vec!["artifacts.example.com".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn http_files_are_limited_in_size() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+
+        // Serves a file of 100 bytes without announcing its size
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let prefix = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n")
+                    .unwrap();
+                stream.write_all(&[b'x'; 100]).unwrap();
+            }
+        });
+
+        let ipfs = Arc::new(resolver(ipfs_api::IpfsClient::default()));
+        let resolver = HttpLinkResolver::new(ipfs, vec![prefix.clone()]).unwrap();
+        let checksum = hex::encode(Sha256::digest(&[b'x'; 100]));
+        let link = Link {
+            link: format!("{}file#sha256={}", prefix, checksum),
+        };
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let resolver = resolver.with_max_file_size(Some(100));
+        assert_eq!(runtime.block_on(resolver.cat(&link)).unwrap().len(), 100);
+        let resolver = resolver.with_max_file_size(Some(99));
+        let err = runtime.block_on(resolver.cat(&link)).unwrap_err();
+        assert!(err.to_string().contains("is too large"));
+
+        // Callers can limit the size of a file further while it downloads
+        let resolver = resolver.with_max_file_size(None);
+        assert_eq!(
+            runtime
+                .block_on(resolver.cat_at_most(&link, 100))
+                .unwrap()
+                .len(),
+            100
+        );
+        let err = runtime
+            .block_on(resolver.cat_at_most(&link, 50))
+            .unwrap_err();
+        assert!(err.downcast_ref::<FileTooLarge>().is_some());
+    }
+
+    #[test]
+    fn http_redirects_must_stay_under_an_allowed_prefix() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        // Serves `file` and redirects to it, either on the same server or
+        // on `other`; returns whether the server was contacted
+        let serve = |listener: TcpListener, other: String| {
+            let contacted = Arc::new(AtomicBool::new(false));
+            let server_contacted = contacted.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    server_contacted.store(true, Ordering::SeqCst);
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request = String::new();
+                    reader.read_line(&mut request).unwrap();
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap() > 2 {
+                        line.clear();
+                    }
+                    let response = if request.starts_with("GET /inside ") {
+                        "HTTP/1.1 302 Found\r\nLocation: /file\r\n\
+                         Content-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_owned()
+                    } else if request.starts_with("GET /outside ") {
+                        format!(
+                            "HTTP/1.1 302 Found\r\nLocation: {}file\r\n\
+                             Content-Length: 0\r\nConnection: close\r\n\r\n",
+                            other
+                        )
+                    } else {
+                        "HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\
+                         Connection: close\r\n\r\nfile"
+                            .to_owned()
+                    };
+                    stream.write_all(response.as_bytes()).unwrap();
+                }
+            });
+            contacted
+        };
+        let allowed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let other = TcpListener::bind("127.0.0.1:0").unwrap();
+        let prefix = format!("http://{}/", allowed.local_addr().unwrap());
+        let other_prefix = format!("http://{}/", other.local_addr().unwrap());
+        serve(allowed, other_prefix.clone());
+        let other_contacted = serve(other, other_prefix);
+
+        let ipfs = Arc::new(resolver(ipfs_api::IpfsClient::default()));
+        let resolver = HttpLinkResolver::new(ipfs, vec![prefix.clone()]).unwrap();
+        let checksum = hex::encode(Sha256::digest(b"file"));
+        let link = |path: &str| Link {
+            link: format!("{}{}#sha256={}", prefix, path, checksum),
+        };
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        assert_eq!(
+            runtime.block_on(resolver.cat(&link("inside"))).unwrap(),
+            b"file".to_vec()
+        );
+        let err = runtime
+            .block_on(resolver.cat(&link("outside")))
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("is not under any of the URLs this node may fetch files from"));
+        assert!(!other_contacted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn verify_sha256_checksum() {
+        let checksum = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert!(verify_checksum("hello.txt", b"hello", checksum).is_ok());
+        assert!(verify_checksum("hello.txt", b"hello!", checksum).is_err());
+    }
+
+    #[test]
+    fn unhealthy_nodes_are_tried_last() {
+        let nodes: Vec<_> = (0..3)
+            .map(|_| IpfsNode {
+                client: Arc::new(ipfs_api::IpfsClient::default()),
+                unhealthy_until: Mutex::new(None),
+            })
+            .collect();
+        let now = Instant::now();
+        assert_eq!(IpfsResolver::node_order(&nodes, now), vec![0, 1, 2]);
+
+        nodes[0].mark_unhealthy(now);
+        assert_eq!(IpfsResolver::node_order(&nodes, now), vec![1, 2, 0]);
+
+        // Unhealthy nodes get another chance after a while
+        let later = now + UNHEALTHY_IPFS_NODE_BACKOFF;
+        assert_eq!(IpfsResolver::node_order(&nodes, later), vec![0, 1, 2]);
+
+        nodes[2].mark_unhealthy(now);
+        nodes[0].mark_healthy();
+        assert_eq!(IpfsResolver::node_order(&nodes, now), vec![0, 1, 2]);
+    }
+
     #[test]
     fn max_file_size() {
         env::set_var(MAX_IPFS_FILE_BYTES_ENV_VAR, "200");
@@ -202,7 +1265,7 @@ mod tests {
         let mut runtime = tokio::runtime::Runtime::new().unwrap();
         let link = runtime.block_on(client.add(file)).unwrap().hash;
         let err = runtime
-            .block_on(LinkResolver::cat(&client, &Link { link: link.clone() }))
+            .block_on(resolver(client).cat(&Link { link: link.clone() }))
             .unwrap_err();
         env::remove_var(MAX_IPFS_FILE_BYTES_ENV_VAR);
         assert_eq!(
@@ -220,7 +1283,8 @@ mod tests {
         let mut runtime = tokio::runtime::Runtime::new().unwrap();
         let link = runtime.block_on(client.add(text.as_bytes())).unwrap().hash;
         runtime.block_on(
-            LinkResolver::json_stream(&client, &Link { link: link.clone() })
+            resolver(client)
+                .json_stream(&Link { link: link.clone() })
                 .and_then(|stream| stream.map(|sv| sv.value).collect()),
         )
     }
@@ -52,6 +52,8 @@ pub mod store;
 
 pub mod link_resolver;
 
+pub mod entity_export;
+
 /// Plug the outputs of `output` of type `E` to the matching inputs in `input`.
 /// This is a lazy operation, nothing will be sent until you spawn the returned
 /// future. Returns `Some` in the first call and `None` on any further calls.
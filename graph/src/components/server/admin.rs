@@ -1,19 +1,141 @@
+use std::fmt;
 use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
 use std::sync::Arc;
 
 use crate::prelude::Logger;
 use crate::prelude::NodeId;
+use crate::prelude::{EthereumNetworkCapabilities, EthereumNetworks};
 
 /// Common trait for JSON-RPC admin server implementations.
+///
+/// The server listens on `listen_address`. If there is an `auth_token`,
+/// only requests that send it as a bearer token are served. If there are
+/// `allowed_ips`, only clients in one of these networks are served.
 pub trait JsonRpcServer<P> {
     type Server;
 
     fn serve(
+        listen_address: IpAddr,
         port: u16,
+        auth_token: Option<String>,
+        allowed_ips: Vec<IpNetwork>,
         http_port: u16,
         ws_port: u16,
         provider: Arc<P>,
+        network_capabilities: EthereumNetworks<EthereumNetworkCapabilities>,
         node_id: NodeId,
         logger: Logger,
     ) -> Result<Self::Server, io::Error>;
 }
+
+/// A network in CIDR notation, like `10.0.0.0/8` or `fd00::/8`. A plain
+/// address is a network of that address alone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    /// Whether `ip` is in the network. IPv4 addresses that arrive as
+    /// IPv4-mapped IPv6 addresses, as they do on a server that listens on
+    /// an IPv6 address, are in the IPv4 networks they belong to.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, unmap_ipv4(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid IP network `{}`", s);
+        let mut parts = s.splitn(2, '/');
+        let address: IpAddr = parts.next().unwrap().parse().map_err(|_| invalid())?;
+        let max_prefix_len = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len = match parts.next() {
+            Some(prefix_len) => prefix_len.parse().map_err(|_| invalid())?,
+            None => max_prefix_len,
+        };
+        if prefix_len > max_prefix_len {
+            return Err(invalid());
+        }
+        Ok(IpNetwork {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// The IPv4 address in an IPv4-mapped IPv6 address (`::ffff:a.b.c.d`), and
+/// any other address as it is.
+fn unmap_ipv4(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip) => match ip.segments() {
+            [0, 0, 0, 0, 0, 0xffff, high, low] => IpAddr::V4(Ipv4Addr::new(
+                (high >> 8) as u8,
+                high as u8,
+                (low >> 8) as u8,
+                low as u8,
+            )),
+            _ => IpAddr::V6(ip),
+        },
+        ip => ip,
+    }
+}
+
+/// Whether the first `prefix_len` bits of `a` and `b` are the same.
+fn prefix_matches(a: &[u8], b: &[u8], prefix_len: u8) -> bool {
+    let full_bytes = (prefix_len / 8) as usize;
+    let rest_bits = prefix_len % 8;
+    a[..full_bytes] == b[..full_bytes]
+        && (rest_bits == 0 || {
+            let mask = 0xffu8 << (8 - rest_bits);
+            a[full_bytes] & mask == b[full_bytes] & mask
+        })
+}
+
+#[test]
+fn ip_networks_contain_the_addresses_in_them() {
+    let network = |s: &str| s.parse::<IpNetwork>().unwrap();
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+    assert!(network("10.0.0.0/8").contains(ip("10.1.2.3")));
+    assert!(!network("10.0.0.0/8").contains(ip("11.0.0.1")));
+    assert!(network("192.168.1.0/25").contains(ip("192.168.1.127")));
+    assert!(!network("192.168.1.0/25").contains(ip("192.168.1.128")));
+    assert!(network("0.0.0.0/0").contains(ip("8.8.8.8")));
+    assert!(network("127.0.0.1").contains(ip("127.0.0.1")));
+    assert!(!network("127.0.0.1").contains(ip("127.0.0.2")));
+    assert!(network("fd00::/8").contains(ip("fd12::1")));
+    assert!(!network("fd00::/8").contains(ip("fe80::1")));
+    assert!(network("::1").contains(ip("::1")));
+
+    // Clients of servers that listen on IPv6 addresses
+    assert!(network("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+    assert!(!network("::1").contains(ip("127.0.0.1")));
+
+    assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+    assert!("10.0.0/8".parse::<IpNetwork>().is_err());
+    assert!("localhost".parse::<IpNetwork>().is_err());
+    assert_eq!(network("10.0.0.0/8").to_string(), "10.0.0.0/8");
+}
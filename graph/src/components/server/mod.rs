@@ -6,3 +6,6 @@ pub mod subscription;
 
 /// Component for the JSON-RPC admin API.
 pub mod admin;
+
+/// Component for the index node server, which reports indexing statuses.
+pub mod index_node;
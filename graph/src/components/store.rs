@@ -9,7 +9,7 @@ use std::fmt;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
-use web3::types::H256;
+use web3::types::{Log, H256};
 
 use crate::data::store::*;
 use crate::data::subgraph::schema::*;
@@ -58,6 +58,27 @@ pub enum EntityFilter {
     NotStartsWith(Attribute, Value),
     EndsWith(Attribute, Value),
     NotEndsWith(Attribute, Value),
+    ContainsNoCase(Attribute, Value),
+    NotContainsNoCase(Attribute, Value),
+    StartsWithNoCase(Attribute, Value),
+    NotStartsWithNoCase(Attribute, Value),
+    EndsWithNoCase(Attribute, Value),
+    NotEndsWithNoCase(Attribute, Value),
+    /// Matches entities that are linked to an entity of one of the given
+    /// types for which the nested filter matches.
+    Child(ChildLink, Vec<String>, Box<EntityFilter>),
+}
+
+/// How the entities a `EntityFilter::Child` filter looks at are linked to
+/// the entities that are being filtered. The flag is set if the attribute
+/// holds a list of IDs rather than a single ID.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChildLink {
+    /// The entity stores the IDs of its children in the attribute.
+    Direct(Attribute, bool),
+    /// The children store the ID of the entity in the attribute, as for
+    /// fields with `@derivedFrom`.
+    Derived(Attribute, bool),
 }
 
 // Define some convenience methods
@@ -123,11 +144,20 @@ pub struct EntityQuery {
     /// An optional attribute to order the entities by.
     pub order_by: Option<(String, ValueType)>,
 
+    /// If set, `order_by` is an attribute of the entities that this
+    /// attribute of the queried entities refers to, which are of one of the
+    /// given entity types.
+    pub order_by_child: Option<(Attribute, Vec<String>)>,
+
     /// The direction to order entities in.
     pub order_direction: Option<EntityOrder>,
 
     /// A range to limit the size of the result.
     pub range: EntityRange,
+
+    /// Query the entities as they were at this block number instead of
+    /// the latest ones.
+    pub block: Option<u64>,
 }
 
 impl EntityQuery {
@@ -141,8 +171,10 @@ impl EntityQuery {
             entity_types,
             filter: None,
             order_by: None,
+            order_by_child: None,
             order_direction: None,
             range,
+            block: None,
         }
     }
 
@@ -161,6 +193,11 @@ impl EntityQuery {
         self.range = range;
         self
     }
+
+    pub fn at_block(mut self, block: u64) -> Self {
+        self.block = Some(block);
+        self
+    }
 }
 
 /// Operation types that lead to entity changes.
@@ -539,10 +576,14 @@ impl EntityOperation {
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum EventSource {
     None,
     EthereumBlock(EthereumBlockPointer),
+    /// The handler of a file data source, identified by the link of the
+    /// file. Its entity operations are not tied to a block but can still be
+    /// reverted as a unit.
+    File(String),
 }
 
 impl fmt::Display for EventSource {
@@ -550,6 +591,7 @@ impl fmt::Display for EventSource {
         match self {
             EventSource::None => f.write_str("none"),
             EventSource::EthereumBlock(block_ptr) => f.write_str(&block_ptr.hash_hex()),
+            EventSource::File(link) => write!(f, "file:{}", link),
         }
     }
 }
@@ -561,6 +603,12 @@ pub struct AttributeIndexDefinition {
     pub field_value_type: ValueType,
     pub attribute_name: String,
     pub entity_name: String,
+    /// Index the lowercased values of a string attribute, for prefix
+    /// filters that ignore case.
+    pub case_insensitive: bool,
+    /// The attributes that follow `attribute_name` in a composite index,
+    /// declared with `@index(fields: [...])`, with their types.
+    pub composite_attributes: Vec<(String, ValueType)>,
 }
 
 #[derive(Fail, Debug)]
@@ -575,6 +623,24 @@ pub enum StoreError {
         _0, _1, _2, _0
     )]
     ConflictingId(String, String, String), // (entity, id, conflicting_entity)
+    #[fail(
+        display = "tried to set field `{}` of entity of type `{}` with ID \"{}\" to `{}`, \
+                   which is not a value of enum `{}`",
+        _0, _1, _2, _3, _4
+    )]
+    InvalidEnumValue(String, String, String, Value, String), // (field, entity, id, value, enum)
+    #[fail(
+        display = "tried to change or remove entity of type `{}` with ID \"{}\", \
+                   but entities of that type are immutable",
+        _0, _1
+    )]
+    ImmutableEntity(String, String), // (entity, id)
+    #[fail(
+        display = "tried to write an entity of type `{}`, but aggregations are only \
+                   written by the store",
+        _0
+    )]
+    AggregationWrite(String), // (entity)
 }
 
 impl From<TransactionAbortError> for StoreError {
@@ -616,6 +682,70 @@ pub enum TransactionAbortError {
     Other(String),
 }
 
+/// The state of a job in the background job queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackgroundJobStatus {
+    /// Waiting for a node to run it.
+    Queued,
+    /// Being run by a node; a node that restarts resumes the jobs it was
+    /// running.
+    Running,
+    Finished,
+    Failed,
+}
+
+impl BackgroundJobStatus {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "queued" => Some(BackgroundJobStatus::Queued),
+            "running" => Some(BackgroundJobStatus::Running),
+            "finished" => Some(BackgroundJobStatus::Finished),
+            "failed" => Some(BackgroundJobStatus::Failed),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackgroundJobStatus::Queued => "queued",
+            BackgroundJobStatus::Running => "running",
+            BackgroundJobStatus::Finished => "finished",
+            BackgroundJobStatus::Failed => "failed",
+        }
+    }
+}
+
+/// A long-running operation on a subgraph deployment, e.g. copying or
+/// exporting it, that is stored in the job queue and run by an index node
+/// in the background.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackgroundJob {
+    pub id: i32,
+    /// What the job does, e.g. `copy` or `export`.
+    pub kind: String,
+    pub deployment: SubgraphDeploymentId,
+    /// The arguments of the job, as a JSON object.
+    pub args: serde_json::Value,
+    pub status: BackgroundJobStatus,
+    /// What the job has done so far, as a JSON object. Jobs that are
+    /// resumed after a restart continue from there.
+    pub progress: serde_json::Value,
+    pub error: Option<String>,
+    /// The node that runs, or ran, the job.
+    pub node_id: Option<NodeId>,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+/// The entities of one deployment as they were at one block, opened with
+/// `Store::snapshot_at_block`.
+pub trait EntitySnapshot: Send {
+    /// Queries the entities of the snapshot. The query must be for the
+    /// deployment of the snapshot; its `block` is ignored.
+    fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError>;
+}
+
 /// Common trait for store implementations.
 pub trait Store: Send + Sync + 'static {
     /// Get a pointer to the most recently processed block in the subgraph.
@@ -630,6 +760,16 @@ pub trait Store: Send + Sync + 'static {
     /// Queries the store for a single entity matching the store query.
     fn find_one(&self, query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError>;
 
+    /// Opens a snapshot of the entities of `subgraph_id` as they were at
+    /// block `block_number`. The entities that changed after the block are
+    /// looked up once, when the snapshot is opened, so that all queries of
+    /// a GraphQL request at that block can share them.
+    fn snapshot_at_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_number: u64,
+    ) -> Result<Box<EntitySnapshot>, QueryExecutionError>;
+
     /// Updates the block pointer.  Careful: this is only safe to use if it is known that no store
     /// changes are needed to go from `block_ptr_from` to `block_ptr_to`.
     ///
@@ -679,6 +819,21 @@ pub trait Store: Send + Sync + 'static {
         block_ptr_to: EthereumBlockPointer,
     ) -> Result<(), StoreError>;
 
+    /// Revert the entity changes from a range of blocks atomically in the store, and update the
+    /// subgraph block pointer from the first to the last pointer in `block_ptrs`.
+    ///
+    /// The first pointer must match the current value of the subgraph block pointer, and every
+    /// other pointer must point to the parent block of the one before it. All blocks but the
+    /// last one are reverted.
+    ///
+    /// The `operations` are applied in the same transaction.
+    fn revert_block_range(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        block_ptrs: Vec<EthereumBlockPointer>,
+        operations: Vec<EntityOperation>,
+    ) -> Result<(), StoreError>;
+
     /// Subscribe to changes for specific subgraphs and entities.
     ///
     /// Returns a stream of store events that match the input arguments.
@@ -687,6 +842,36 @@ pub trait Store: Send + Sync + 'static {
     /// Counts the total number of entities in a subgraph.
     fn count_entities(&self, subgraph: SubgraphDeploymentId) -> Result<u64, Error>;
 
+    /// Looks up the timestamp of a block that has been stored for any of
+    /// the networks, or `None` if the block is not in the store.
+    fn block_timestamp(&self, block_hash: H256) -> Result<Option<u64>, Error>;
+
+    /// Returns the jobs in the background job queue, including finished
+    /// ones, oldest first.
+    fn background_jobs(&self) -> Result<Vec<BackgroundJob>, Error>;
+
+    /// Returns the blocks numbered `from` to `to`, inclusive, in which
+    /// `subgraph_id` changed entities of one of `entity_types`, ordered by
+    /// number. Blocks that were reverted keep their history, so there can be
+    /// several blocks with the same number.
+    fn blocks_with_entity_changes(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: &[String],
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<EthereumBlockPointer>, Error>;
+
+    /// Returns the changes that `subgraph_id` made to entities of one of
+    /// `entity_types` in the block `block_hash`, ordered by entity type and
+    /// ID, with each entity as of the end of that block.
+    fn entity_changes_in_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: &[String],
+        block_hash: H256,
+    ) -> Result<Vec<EntityChangeTrigger>, Error>;
+
     fn resolve_subgraph_name_to_id(
         &self,
         name: SubgraphName,
@@ -966,6 +1151,30 @@ pub trait Store: Send + Sync + 'static {
             })
             .unwrap_or(Ok(false))
     }
+
+    /// Return true if an operator has disabled queries against the
+    /// deployment with the given id.
+    fn is_deployment_query_disabled(&self, id: &SubgraphDeploymentId) -> Result<bool, Error> {
+        let entity = self.get(SubgraphDeploymentEntity::key(id.to_owned()))?;
+        entity
+            .map(|entity| match entity.get("queriesDisabled") {
+                Some(Value::Bool(true)) => Ok(true),
+                _ => Ok(false),
+            })
+            .unwrap_or(Ok(false))
+    }
+
+    /// Return true if an operator has disabled indexing of the deployment
+    /// with the given id.
+    fn is_deployment_indexing_disabled(&self, id: &SubgraphDeploymentId) -> Result<bool, Error> {
+        let entity = self.get(SubgraphDeploymentEntity::key(id.to_owned()))?;
+        entity
+            .map(|entity| match entity.get("indexingDisabled") {
+                Some(Value::Bool(true)) => Ok(true),
+                _ => Ok(false),
+            })
+            .unwrap_or(Ok(false))
+    }
 }
 
 pub trait SubgraphDeploymentStore: Send + Sync + 'static {
@@ -1026,4 +1235,33 @@ pub trait ChainStore: Send + Sync + 'static {
         block_ptr: EthereumBlockPointer,
         offset: u64,
     ) -> Result<Option<EthereumBlock>, Error>;
+
+    /// Get pointers to all blocks in the chain store with numbers between
+    /// `from` and `to` (inclusive), ordered by block number, together with
+    /// the hashes of their parents. There may be several blocks with the
+    /// same number if the chain store has seen reorgs.
+    fn block_pointers_in_range(
+        &self,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<(EthereumBlockPointer, H256)>, Error>;
+
+    /// Get Some(calls) if the calls traced in the block with `block_hash`
+    /// are present in the chain store, or None.
+    fn block_calls(&self, block_hash: H256) -> Result<Option<Vec<EthereumCall>>, Error>;
+
+    /// Insert the calls traced in a block into the chain store.
+    fn upsert_block_calls(
+        &self,
+        block_ptr: EthereumBlockPointer,
+        calls: &[EthereumCall],
+    ) -> Result<(), Error>;
+
+    /// Get Some(logs) if the logs of the block with `block_hash` are
+    /// present in the chain store, or None.
+    fn block_logs(&self, block_hash: H256) -> Result<Option<Vec<Log>>, Error>;
+
+    /// Insert the logs of a block into the chain store.
+    fn upsert_block_logs(&self, block_ptr: EthereumBlockPointer, logs: &[Log])
+        -> Result<(), Error>;
 }
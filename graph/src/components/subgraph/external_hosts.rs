@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::prelude::*;
+
+/// Number of requests per second that the mappings of a subgraph deployment
+/// may send to each of its external hosts, unless
+/// `GRAPH_EXTERNAL_HOST_REQUESTS_PER_SECOND` is set.
+const DEFAULT_REQUESTS_PER_SECOND: u32 = 10;
+
+/// Reads the maximum number of requests per second that the mappings of a
+/// subgraph deployment may send to each of its external hosts.
+fn requests_per_second() -> Result<u32, Error> {
+    match env::var("GRAPH_EXTERNAL_HOST_REQUESTS_PER_SECOND") {
+        Ok(s) => match u32::from_str(&s) {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format_err!(
+                "invalid GRAPH_EXTERNAL_HOST_REQUESTS_PER_SECOND `{}`, \
+                 it must be a positive number",
+                s
+            )),
+        },
+        Err(_) => Ok(DEFAULT_REQUESTS_PER_SECOND),
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum ExternalHostError {
+    #[fail(display = "invalid HTTP(S) URL: {}", _0)]
+    InvalidUrl(String),
+    #[fail(
+        display = "host {} is not declared in the externalHosts of the subgraph manifest",
+        _0
+    )]
+    HostNotAllowed(String),
+}
+
+/// The external hosts that the mappings of a subgraph deployment may send
+/// HTTP requests to, as declared in `externalHosts` in the manifest.
+///
+/// Requests are rate limited per host, across all data sources of the
+/// deployment. Instead of failing, requests over the limit are delayed
+/// until they are within the limit again.
+#[derive(Debug)]
+pub struct ExternalHosts {
+    /// Maps each host to the earliest time the next request may be sent.
+    hosts: HashMap<String, Mutex<Instant>>,
+    request_interval: Duration,
+}
+
+impl ExternalHosts {
+    pub fn new<I>(hosts: I, requests_per_second: u32) -> Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let now = Instant::now();
+        ExternalHosts {
+            hosts: hosts
+                .into_iter()
+                .map(|host| (host.to_lowercase(), Mutex::new(now)))
+                .collect(),
+            request_interval: Duration::from_secs(1) / requests_per_second.max(1),
+        }
+    }
+
+    /// Creates the allowlist of a deployment, rate limited according to
+    /// `GRAPH_EXTERNAL_HOST_REQUESTS_PER_SECOND`.
+    pub fn from_manifest(manifest: &SubgraphManifest) -> Result<Self, Error> {
+        Ok(Self::new(
+            manifest.external_hosts.iter().cloned(),
+            requests_per_second()?,
+        ))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    pub fn hosts(&self) -> Vec<&str> {
+        let mut hosts = self
+            .hosts
+            .keys()
+            .map(|host| host.as_str())
+            .collect::<Vec<_>>();
+        hosts.sort();
+        hosts
+    }
+
+    /// Checks that the host of `url` is allowed and blocks the calling
+    /// thread until a request to it is within the rate limit.
+    pub fn acquire(&self, url: &str) -> Result<(), ExternalHostError> {
+        let host = url_host(url).ok_or_else(|| ExternalHostError::InvalidUrl(url.to_owned()))?;
+        let next_request = self
+            .hosts
+            .get(&host)
+            .ok_or_else(|| ExternalHostError::HostNotAllowed(host.clone()))?;
+
+        let delay = {
+            let mut next_request = next_request.lock().unwrap();
+            let now = Instant::now();
+            let send_at = (*next_request).max(now);
+            *next_request = send_at + self.request_interval;
+            send_at - now
+        };
+        if delay > Duration::from_secs(0) {
+            thread::sleep(delay);
+        }
+        Ok(())
+    }
+}
+
+/// Returns `true` if `link` is an HTTP(S) URL rather than, e.g., an IPFS hash.
+pub fn is_http_url(link: &str) -> bool {
+    ["https://", "http://"].iter().any(|scheme| {
+        link.get(..scheme.len())
+            .map_or(false, |prefix| prefix.eq_ignore_ascii_case(scheme))
+    })
+}
+
+/// Returns the lowercased host of an HTTP(S) URL, without user info and port.
+fn url_host(url: &str) -> Option<String> {
+    if !is_http_url(url) {
+        return None;
+    }
+    let rest = &url[url.find("://")? + 3..];
+
+    let authority = rest.split(|c| c == '/' || c == '?' || c == '#').next()?;
+    let host_and_port = authority.rsplit('@').next()?;
+    let host = host_and_port.split(':').next()?;
+    if host.is_empty() {
+        None
+    } else {
+        Some(host.to_lowercase())
+    }
+}
+
+#[test]
+fn external_hosts_allowlist() {
+    let hosts = ExternalHosts::new(vec!["api.example.com".to_owned()], 1000);
+
+    assert!(hosts
+        .acquire("https://api.example.com/v1/prices?id=1")
+        .is_ok());
+    assert!(hosts.acquire("http://user@API.example.com:8080").is_ok());
+    assert!(hosts.acquire("https://example.com/").is_err());
+    assert!(hosts.acquire("https://api.example.com.evil.org/").is_err());
+    assert!(hosts.acquire("ftp://api.example.com/").is_err());
+    assert!(hosts.acquire("api.example.com").is_err());
+}
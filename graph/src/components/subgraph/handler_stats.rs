@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::prelude::*;
+
+/// How often a handler of a deployment ran and how long it took in total.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HandlerStatsSummary {
+    pub data_source: String,
+    pub handler: String,
+    pub invocations: u64,
+    pub total_time: Duration,
+}
+
+/// How many handlers of a deployment run at the same time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HandlerConcurrency {
+    /// Handlers that are running right now.
+    pub running: u64,
+    /// The most handlers that ran at the same time since the node started.
+    pub peak: u64,
+}
+
+#[derive(Debug, Default)]
+struct HandlerTotals {
+    invocations: u64,
+    total_time: Duration,
+}
+
+/// Invocation counts, execution times and concurrency of the handlers of
+/// each deployment, kept in memory by each index node since it started.
+#[derive(Debug, Default)]
+pub struct HandlerStats {
+    deployments: Mutex<HashMap<SubgraphDeploymentId, HashMap<(String, String), HandlerTotals>>>,
+    concurrency: Mutex<HashMap<SubgraphDeploymentId, HandlerConcurrency>>,
+}
+
+/// Counts a handler of a deployment as running until it is dropped.
+#[derive(Debug)]
+pub struct RunningHandler {
+    stats: Arc<HandlerStats>,
+    subgraph_id: SubgraphDeploymentId,
+}
+
+impl Drop for RunningHandler {
+    fn drop(&mut self) {
+        let mut concurrency = self.stats.concurrency.lock().unwrap();
+        if let Some(concurrency) = concurrency.get_mut(&self.subgraph_id) {
+            concurrency.running -= 1;
+        }
+    }
+}
+
+impl HandlerStats {
+    /// Counts a handler of `subgraph_id` as running until the returned
+    /// guard is dropped.
+    pub fn start(stats: &Arc<Self>, subgraph_id: &SubgraphDeploymentId) -> RunningHandler {
+        let mut concurrency = stats.concurrency.lock().unwrap();
+        let concurrency = concurrency
+            .entry(subgraph_id.clone())
+            .or_insert_with(HandlerConcurrency::default);
+        concurrency.running += 1;
+        concurrency.peak = concurrency.peak.max(concurrency.running);
+        RunningHandler {
+            stats: stats.clone(),
+            subgraph_id: subgraph_id.clone(),
+        }
+    }
+
+    /// Records that `handler` of `data_source` ran for `duration`, whether
+    /// it succeeded or not.
+    pub fn record(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        data_source: &str,
+        handler: &str,
+        duration: Duration,
+    ) {
+        let mut deployments = self.deployments.lock().unwrap();
+        let totals = deployments
+            .entry(subgraph_id.clone())
+            .or_insert_with(HashMap::new)
+            .entry((data_source.to_owned(), handler.to_owned()))
+            .or_insert_with(HandlerTotals::default);
+        totals.invocations += 1;
+        totals.total_time += duration;
+    }
+
+    /// The handlers of `subgraph_id` that ran since the node started, the
+    /// ones that took the longest in total first.
+    pub fn summary(&self, subgraph_id: &SubgraphDeploymentId) -> Vec<HandlerStatsSummary> {
+        let deployments = self.deployments.lock().unwrap();
+        let mut summary = deployments
+            .get(subgraph_id)
+            .into_iter()
+            .flat_map(|handlers| handlers.iter())
+            .map(|((data_source, handler), totals)| HandlerStatsSummary {
+                data_source: data_source.clone(),
+                handler: handler.clone(),
+                invocations: totals.invocations,
+                total_time: totals.total_time,
+            })
+            .collect::<Vec<_>>();
+        summary.sort_by(|a, b| {
+            b.total_time
+                .cmp(&a.total_time)
+                .then_with(|| a.data_source.cmp(&b.data_source))
+                .then_with(|| a.handler.cmp(&b.handler))
+        });
+        summary
+    }
+
+    /// How many handlers of `subgraph_id` run at the same time.
+    pub fn concurrency(&self, subgraph_id: &SubgraphDeploymentId) -> HandlerConcurrency {
+        self.concurrency
+            .lock()
+            .unwrap()
+            .get(subgraph_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[test]
+fn handler_stats_are_summed_per_handler() {
+    let id = SubgraphDeploymentId::new("QmHandlers").unwrap();
+    let stats = HandlerStats::default();
+    stats.record(&id, "Token", "handleTransfer", Duration::from_millis(2));
+    stats.record(&id, "Token", "handleTransfer", Duration::from_millis(3));
+    stats.record(&id, "Token", "handleApproval", Duration::from_millis(10));
+
+    let summary = stats.summary(&id);
+    assert_eq!(summary.len(), 2);
+    assert_eq!(summary[0].handler, "handleApproval");
+    assert_eq!(summary[1].handler, "handleTransfer");
+    assert_eq!(summary[1].invocations, 2);
+    assert_eq!(summary[1].total_time, Duration::from_millis(5));
+    assert!(stats
+        .summary(&SubgraphDeploymentId::new("QmOther").unwrap())
+        .is_empty());
+}
+
+#[test]
+fn handler_concurrency_is_counted_per_deployment() {
+    let id = SubgraphDeploymentId::new("QmHandlers").unwrap();
+    let other = SubgraphDeploymentId::new("QmOther").unwrap();
+    let stats = Arc::new(HandlerStats::default());
+
+    let first = HandlerStats::start(&stats, &id);
+    let second = HandlerStats::start(&stats, &id);
+    let _other = HandlerStats::start(&stats, &other);
+    assert_eq!(
+        stats.concurrency(&id),
+        HandlerConcurrency {
+            running: 2,
+            peak: 2
+        }
+    );
+
+    drop(first);
+    drop(second);
+    let _third = HandlerStats::start(&stats, &id);
+    assert_eq!(
+        stats.concurrency(&id),
+        HandlerConcurrency {
+            running: 1,
+            peak: 2
+        }
+    );
+    assert_eq!(
+        stats.concurrency(&other),
+        HandlerConcurrency {
+            running: 1,
+            peak: 1
+        }
+    );
+}
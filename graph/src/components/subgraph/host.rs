@@ -1,10 +1,24 @@
 use failure::Error;
 use futures::prelude::*;
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use crate::prelude::*;
 use web3::types::{Log, Transaction};
 
+/// A mapping handler failed while processing a trigger.
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Failed to handle {} with handler \"{}\": {}",
+    trigger, handler, error
+)]
+pub struct HandlerError {
+    /// What the handler was called for, e.g. `Ethereum event`.
+    pub trigger: String,
+    pub handler: String,
+    pub error: String,
+}
+
 /// Common trait for runtime host implementations.
 pub trait RuntimeHost: Send + Sync + Debug {
     /// Returns true if the RuntimeHost has a handler for an Ethereum event.
@@ -16,6 +30,10 @@ pub trait RuntimeHost: Send + Sync + Debug {
     /// Returns true if the RuntimeHost has a handler for an Ethereum block.
     fn matches_block(&self, call: EthereumBlockTriggerType) -> bool;
 
+    /// Returns true if the RuntimeHost has a handler for a change to an
+    /// entity of another subgraph.
+    fn matches_entity_change(&self, trigger: &EntityChangeTrigger) -> bool;
+
     /// Process an Ethereum event and return a vector of entity operations.
     fn process_log(
         &self,
@@ -44,16 +62,35 @@ pub trait RuntimeHost: Send + Sync + Debug {
         trigger_type: EthereumBlockTriggerType,
         entity_operations: Vec<EntityOperation>,
     ) -> Box<Future<Item = Vec<EntityOperation>, Error = Error> + Send>;
+
+    /// Process a change to an entity of another subgraph with every handler
+    /// for its entity type and return a vector of entity operations
+    fn process_entity_change(
+        &self,
+        logger: Logger,
+        block: Arc<EthereumBlock>,
+        trigger: Arc<EntityChangeTrigger>,
+        entity_operations: Vec<EntityOperation>,
+    ) -> Box<Future<Item = Vec<EntityOperation>, Error = Error> + Send>;
+
+    /// Returns the entity types that handlers of this host loaded with
+    /// `store.get` since the last call, and forgets them.
+    fn take_entity_reads(&self) -> HashSet<String>;
 }
 
 pub trait RuntimeHostBuilder: Clone + Send + 'static {
     type Host: RuntimeHost;
 
-    /// Build a new runtime host for a subgraph data source.
+    /// Build a new runtime host for a subgraph data source. `templates`,
+    /// from which the mappings can create data sources, `external_hosts`
+    /// and `quotas` are shared by all data sources of the subgraph deployment.
     fn build(
         &self,
         logger: &Logger,
         subgraph_id: SubgraphDeploymentId,
         data_source: DataSource,
+        templates: Arc<Vec<DataSourceTemplate>>,
+        external_hosts: Arc<ExternalHosts>,
+        quotas: Arc<DeploymentQuotas>,
     ) -> Result<Self::Host, Error>;
 }
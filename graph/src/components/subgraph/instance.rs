@@ -6,11 +6,13 @@ pub trait SubgraphInstance<T>: Sized
 where
     T: RuntimeHostBuilder,
 {
-    /// Creates a subgraph instance from a manifest.
+    /// Creates a subgraph instance from a manifest, whose mappings are
+    /// limited by `quotas`.
     fn from_manifest(
         logger: &Logger,
         manifest: SubgraphManifest,
         host_builder: T,
+        quotas: Arc<DeploymentQuotas>,
     ) -> Result<Self, Error>;
 
     /// Returns true if the subgraph has a handler for an Ethereum event.
@@ -24,4 +26,20 @@ where
         trigger: EthereumTrigger,
         entity_operations: Vec<EntityOperation>,
     ) -> Box<Future<Item = Vec<EntityOperation>, Error = Error> + Send>;
+
+    /// Process all triggers of a block and return the resulting entity
+    /// operations as a future. Triggers are processed in order, but the
+    /// handlers of data sources that don't share any entity types may run
+    /// in parallel.
+    ///
+    /// `pending_operations` are the entity operations of earlier blocks
+    /// that have not been written to the store yet. Handlers see their
+    /// changes, but they are not part of the result.
+    fn process_triggers(
+        &self,
+        logger: &Logger,
+        block: Arc<EthereumBlock>,
+        triggers: Vec<EthereumTrigger>,
+        pending_operations: Vec<EntityOperation>,
+    ) -> Box<Future<Item = Vec<EntityOperation>, Error = Error> + Send>;
 }
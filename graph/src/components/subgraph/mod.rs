@@ -1,13 +1,23 @@
+mod external_hosts;
+mod handler_stats;
 mod host;
 mod instance;
 mod instance_manager;
 mod provider;
+mod quotas;
 mod registrar;
 
 pub use crate::prelude::Entity;
 
-pub use self::host::{RuntimeHost, RuntimeHostBuilder};
+pub use self::external_hosts::{is_http_url, ExternalHostError, ExternalHosts};
+pub use self::handler_stats::{
+    HandlerConcurrency, HandlerStats, HandlerStatsSummary, RunningHandler,
+};
+pub use self::host::{HandlerError, RuntimeHost, RuntimeHostBuilder};
 pub use self::instance::SubgraphInstance;
 pub use self::instance_manager::SubgraphInstanceManager;
 pub use self::provider::SubgraphAssignmentProvider;
-pub use self::registrar::{SubgraphRegistrar, SubgraphVersionSwitchingMode};
+pub use self::quotas::{DeploymentQuotas, QuotaAction, QuotaError, ResourceQuota, ResourceQuotas};
+pub use self::registrar::{
+    IdempotencyKey, SubgraphRegistrar, SubgraphRegistrarOperation, SubgraphVersionSwitchingMode,
+};
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use web3::types::H256;
+
+use crate::prelude::*;
+
+/// How long calls over a quota are delayed when the deployment is
+/// throttled, unless the quota says otherwise.
+const DEFAULT_THROTTLE_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum QuotaError {
+    #[fail(
+        display = "subgraph would have {} entities, more than its quota of {} entities",
+        _1, _0
+    )]
+    Entities(u64, u64),
+    #[fail(
+        display = "subgraph would have {} dynamic data sources, more than its quota of {}",
+        _1, _0
+    )]
+    DynamicDataSources(u64, u64),
+    #[fail(
+        display = "subgraph read more than its quota of {} bytes from IPFS in this block",
+        _0
+    )]
+    IpfsBytesPerBlock(u64),
+    #[fail(
+        display = "handler made more than the quota of {} Ethereum calls per handler",
+        _0
+    )]
+    EthCallsPerHandler(u64),
+}
+
+/// What happens when a deployment exceeds one of its quotas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuotaAction {
+    /// The handler, or the block for entity and data source quotas, fails,
+    /// which fails the subgraph at the block it is processing.
+    Fail,
+    /// Calls over the quota are delayed instead of failing. Entity and
+    /// data source quotas always fail the subgraph, since delaying doesn't
+    /// help with them.
+    Throttle,
+}
+
+impl QuotaAction {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "fail" => Some(QuotaAction::Fail),
+            "throttle" => Some(QuotaAction::Throttle),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaAction::Fail => "fail",
+            QuotaAction::Throttle => "throttle",
+        }
+    }
+}
+
+/// Limits on the resources a subgraph deployment may use. Limits that are
+/// not set don't apply.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResourceQuota {
+    pub max_entities: Option<u64>,
+    pub max_dynamic_data_sources: Option<u64>,
+    pub max_ipfs_bytes_per_block: Option<u64>,
+    pub max_eth_calls_per_handler: Option<u64>,
+    pub on_exceeded: Option<QuotaAction>,
+    pub throttle_delay: Option<Duration>,
+}
+
+impl ResourceQuota {
+    /// Takes the settings of `self`, and those that `self` doesn't set
+    /// from `defaults`.
+    pub fn or(&self, defaults: &ResourceQuota) -> ResourceQuota {
+        ResourceQuota {
+            max_entities: self.max_entities.or(defaults.max_entities),
+            max_dynamic_data_sources: self
+                .max_dynamic_data_sources
+                .or(defaults.max_dynamic_data_sources),
+            max_ipfs_bytes_per_block: self
+                .max_ipfs_bytes_per_block
+                .or(defaults.max_ipfs_bytes_per_block),
+            max_eth_calls_per_handler: self
+                .max_eth_calls_per_handler
+                .or(defaults.max_eth_calls_per_handler),
+            on_exceeded: self.on_exceeded.or(defaults.on_exceeded),
+            throttle_delay: self.throttle_delay.or(defaults.throttle_delay),
+        }
+    }
+}
+
+/// The quotas of the deployments on a node: a default quota, and quotas
+/// of individual deployments that override some of its settings.
+#[derive(Clone, Debug, Default)]
+pub struct ResourceQuotas {
+    pub default: ResourceQuota,
+    pub deployments: HashMap<SubgraphDeploymentId, ResourceQuota>,
+}
+
+impl ResourceQuotas {
+    pub fn for_deployment(&self, subgraph_id: &SubgraphDeploymentId) -> ResourceQuota {
+        self.deployments
+            .get(subgraph_id)
+            .map_or_else(|| self.default.clone(), |quota| quota.or(&self.default))
+    }
+}
+
+/// Keeps track of the resources a running subgraph deployment uses, and
+/// enforces its quota. Shared by all data sources of the deployment.
+#[derive(Debug)]
+pub struct DeploymentQuotas {
+    quota: ResourceQuota,
+    /// The block that is being processed and the bytes read from IPFS
+    /// while processing it.
+    ipfs_bytes: Mutex<(Option<H256>, u64)>,
+}
+
+impl Default for DeploymentQuotas {
+    /// No limits at all.
+    fn default() -> Self {
+        Self::new(ResourceQuota::default())
+    }
+}
+
+impl DeploymentQuotas {
+    pub fn new(quota: ResourceQuota) -> Self {
+        DeploymentQuotas {
+            quota,
+            ipfs_bytes: Mutex::new((None, 0)),
+        }
+    }
+
+    pub fn quota(&self) -> &ResourceQuota {
+        &self.quota
+    }
+
+    /// Fails, or blocks the calling thread for the throttle delay if the
+    /// deployment is throttled.
+    fn exceeded(&self, error: QuotaError) -> Result<(), QuotaError> {
+        match self.quota.on_exceeded.unwrap_or(QuotaAction::Fail) {
+            QuotaAction::Fail => Err(error),
+            QuotaAction::Throttle => {
+                thread::sleep(self.quota.throttle_delay.unwrap_or(DEFAULT_THROTTLE_DELAY));
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks that the deployment stays within its quota when it has
+    /// `count` entities.
+    pub fn check_entities(&self, count: u64) -> Result<(), QuotaError> {
+        match self.quota.max_entities {
+            Some(max) if count > max => Err(QuotaError::Entities(max, count)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the deployment stays within its quota when it has
+    /// `count` dynamic data sources.
+    pub fn check_dynamic_data_sources(&self, count: u64) -> Result<(), QuotaError> {
+        match self.quota.max_dynamic_data_sources {
+            Some(max) if count > max => Err(QuotaError::DynamicDataSources(max, count)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The bytes read from IPFS while processing `block`, resetting the
+    /// count when a new block is processed.
+    fn ipfs_bytes_read(&self, block: &EthereumBlock, bytes: u64) -> u64 {
+        let mut ipfs_bytes = self.ipfs_bytes.lock().unwrap();
+        if ipfs_bytes.0 != block.block.hash {
+            *ipfs_bytes = (block.block.hash, 0);
+        }
+        ipfs_bytes.1 += bytes;
+        ipfs_bytes.1
+    }
+
+    /// How many more bytes handlers may read from IPFS while processing
+    /// `block` before they fail, or `None` if reading more doesn't fail,
+    /// so that files can be rejected while they are downloaded.
+    pub fn ipfs_bytes_left(&self, block: &EthereumBlock) -> Option<u64> {
+        match (self.quota.max_ipfs_bytes_per_block, self.quota.on_exceeded) {
+            (Some(max), None) | (Some(max), Some(QuotaAction::Fail)) => {
+                Some(max.saturating_sub(self.ipfs_bytes_read(block, 0)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Counts `bytes` that a handler read from IPFS, with `ipfs.cat` or
+    /// `ipfs.map`, while processing `block` towards the quota of that block.
+    pub fn ipfs_read(&self, block: &EthereumBlock, bytes: u64) -> Result<(), QuotaError> {
+        let max = match self.quota.max_ipfs_bytes_per_block {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        if self.ipfs_bytes_read(block, bytes) > max {
+            self.exceeded(QuotaError::IpfsBytesPerBlock(max))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks that a handler that makes its `calls`-th Ethereum call stays
+    /// within the quota.
+    pub fn eth_call(&self, calls: u64) -> Result<(), QuotaError> {
+        match self.quota.max_eth_calls_per_handler {
+            Some(max) if calls > max => self.exceeded(QuotaError::EthCallsPerHandler(max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn deployment_quotas() {
+    let id = SubgraphDeploymentId::new("QmQuota").unwrap();
+    let mut quotas = ResourceQuotas::default();
+    quotas.default.max_entities = Some(10);
+    quotas.default.max_dynamic_data_sources = Some(3);
+    quotas.default.max_ipfs_bytes_per_block = Some(100);
+    quotas.deployments.insert(
+        id.clone(),
+        ResourceQuota {
+            max_eth_calls_per_handler: Some(2),
+            ..ResourceQuota::default()
+        },
+    );
+
+    let quota = quotas.for_deployment(&id);
+    assert_eq!(quota.max_entities, Some(10));
+    assert_eq!(quota.max_eth_calls_per_handler, Some(2));
+    let other = quotas.for_deployment(&SubgraphDeploymentId::new("QmOther").unwrap());
+    assert_eq!(other.max_eth_calls_per_handler, None);
+
+    let quotas = DeploymentQuotas::new(quota);
+    assert!(quotas.check_entities(10).is_ok());
+    assert_eq!(quotas.check_entities(11), Err(QuotaError::Entities(10, 11)));
+    assert!(quotas.check_dynamic_data_sources(3).is_ok());
+    assert_eq!(
+        quotas.check_dynamic_data_sources(4),
+        Err(QuotaError::DynamicDataSources(3, 4))
+    );
+    assert!(quotas.eth_call(2).is_ok());
+    assert_eq!(quotas.eth_call(3), Err(QuotaError::EthCallsPerHandler(2)));
+
+    // The bytes read from IPFS are counted per block
+    let mut block = EthereumBlock::default();
+    block.block.hash = Some(H256::from(1));
+    assert_eq!(quotas.ipfs_bytes_left(&block), Some(100));
+    assert!(quotas.ipfs_read(&block, 60).is_ok());
+    assert_eq!(quotas.ipfs_bytes_left(&block), Some(40));
+    assert_eq!(
+        quotas.ipfs_read(&block, 60),
+        Err(QuotaError::IpfsBytesPerBlock(100))
+    );
+    assert_eq!(quotas.ipfs_bytes_left(&block), Some(0));
+    block.block.hash = Some(H256::from(2));
+    assert_eq!(quotas.ipfs_bytes_left(&block), Some(100));
+    assert!(quotas.ipfs_read(&block, 60).is_ok());
+
+    // Throttled deployments read files of any size
+    let throttled = DeploymentQuotas::new(ResourceQuota {
+        max_ipfs_bytes_per_block: Some(100),
+        on_exceeded: Some(QuotaAction::Throttle),
+        throttle_delay: Some(Duration::from_millis(1)),
+        ..ResourceQuota::default()
+    });
+    assert_eq!(throttled.ipfs_bytes_left(&block), None);
+    assert!(throttled.ipfs_read(&block, 200).is_ok());
+}
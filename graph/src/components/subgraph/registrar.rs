@@ -16,6 +16,45 @@ impl SubgraphVersionSwitchingMode {
     }
 }
 
+/// A single operation in a batch of operations applied by
+/// `SubgraphRegistrar::apply_operations`.
+#[derive(Clone, Debug)]
+pub enum SubgraphRegistrarOperation {
+    Create {
+        name: SubgraphName,
+    },
+    Deploy {
+        name: SubgraphName,
+        hash: SubgraphDeploymentId,
+        node_id: NodeId,
+    },
+    Remove {
+        name: SubgraphName,
+    },
+    SetEnabled {
+        hash: SubgraphDeploymentId,
+        queries_enabled: Option<bool>,
+        indexing_enabled: Option<bool>,
+    },
+    Reassign {
+        hash: SubgraphDeploymentId,
+        node_id: NodeId,
+    },
+}
+
+/// The idempotency key that a client sent with a request to change
+/// subgraphs, together with the request. The registrar records the key
+/// with the result of the request, in the same store transaction as the
+/// changes, so that a request that repeats the key is answered with that
+/// result instead of being applied again, by any node.
+#[derive(Clone, Debug)]
+pub struct IdempotencyKey {
+    pub key: String,
+    /// Method and parameters of the request, to detect keys that are
+    /// reused for different requests.
+    pub request: String,
+}
+
 /// Common trait for named subgraph providers.
 pub trait SubgraphRegistrar: Send + Sync + 'static {
     fn create_subgraph(
@@ -30,6 +69,15 @@ pub trait SubgraphRegistrar: Send + Sync + 'static {
         assignment_node_id: NodeId,
     ) -> Box<Future<Item = (), Error = SubgraphRegistrarError> + Send + 'static>;
 
+    /// Resolves and validates the subgraph deployment `hash` like
+    /// `create_subgraph_version` does, and checks that this node can run
+    /// its mappings, without creating, assigning or indexing anything.
+    /// Fails with all problems that were found.
+    fn validate_subgraph_version(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Box<Future<Item = (), Error = SubgraphRegistrarError> + Send + 'static>;
+
     fn remove_subgraph(
         &self,
         name: SubgraphName,
@@ -38,4 +86,67 @@ pub trait SubgraphRegistrar: Send + Sync + 'static {
     fn list_subgraphs(
         &self,
     ) -> Box<Future<Item = Vec<SubgraphName>, Error = SubgraphRegistrarError> + Send + 'static>;
+
+    /// Reverts the entities of a paused subgraph deployment to their state
+    /// after `block_ptr` and resets the deployment's block pointer to it,
+    /// so that indexing resumes from there. The block must be an ancestor
+    /// of the block the deployment has processed last. With an idempotency
+    /// key, the rewind is applied at most once, like `apply_operations`.
+    fn rewind_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+        block_ptr: EthereumBlockPointer,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Box<Future<Item = (), Error = SubgraphRegistrarError> + Send + 'static>;
+
+    /// Lists the nodes that subgraph deployments are assigned to, either
+    /// for all nodes or for the node with the given ID.
+    fn list_assignments(
+        &self,
+        node_id: Option<NodeId>,
+    ) -> Box<
+        Future<Item = Vec<(SubgraphDeploymentId, NodeId)>, Error = SubgraphRegistrarError>
+            + Send
+            + 'static,
+    >;
+
+    /// Enables or disables serving queries and indexing for a subgraph
+    /// deployment independently of each other. `None` leaves the respective
+    /// setting unchanged.
+    fn set_deployment_enabled(
+        &self,
+        hash: SubgraphDeploymentId,
+        queries_enabled: Option<bool>,
+        indexing_enabled: Option<bool>,
+    ) -> Box<Future<Item = (), Error = SubgraphRegistrarError> + Send + 'static>;
+
+    /// Assigns a subgraph deployment to a different node, which stops
+    /// indexing it on the node it was assigned to before.
+    fn reassign_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+        node_id: NodeId,
+    ) -> Box<Future<Item = (), Error = SubgraphRegistrarError> + Send + 'static>;
+
+    /// Applies several operations in a single store transaction, so that
+    /// either all of them take effect or none of them do. Returns the result
+    /// of each `Create` operation, in the order of the operations.
+    ///
+    /// Apart from deploying to a subgraph created earlier in the batch, the
+    /// operations of a batch must not depend on each other: a subgraph name
+    /// or deployment can only be the target of one operation.
+    ///
+    /// With an idempotency key, the batch is applied at most once: until
+    /// the key expires, a batch that repeats the key returns the results of
+    /// the first one, and a different batch with the same key fails with
+    /// `IdempotencyKeyReused`. Failed batches don't record their key.
+    fn apply_operations(
+        &self,
+        operations: Vec<SubgraphRegistrarOperation>,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Box<
+        Future<Item = Vec<Option<CreateSubgraphResult>>, Error = SubgraphRegistrarError>
+            + Send
+            + 'static,
+    >;
 }
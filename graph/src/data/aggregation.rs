@@ -0,0 +1,457 @@
+use failure::Error;
+use graphql_parser::schema::{self, Directive, ObjectType};
+use std::collections::BTreeMap;
+
+use crate::data::graphql::validation::{get_object_type_directive, SchemaValidationError};
+use crate::data::store::{Entity, Value};
+
+/// The field of timeseries entities and aggregations that holds a Unix
+/// timestamp in seconds. For aggregations, it is the start of the interval.
+pub const TIMESTAMP_FIELD: &str = "timestamp";
+
+/// The field of aggregations that holds the name of their interval.
+pub const INTERVAL_FIELD: &str = "interval";
+
+/// The length of time an aggregation rolls timeseries entities up over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AggregationInterval {
+    Hour,
+    Day,
+}
+
+impl AggregationInterval {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "hour" => Some(AggregationInterval::Hour),
+            "day" => Some(AggregationInterval::Day),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AggregationInterval::Hour => "hour",
+            AggregationInterval::Day => "day",
+        }
+    }
+
+    pub fn seconds(&self) -> i32 {
+        match self {
+            AggregationInterval::Hour => 60 * 60,
+            AggregationInterval::Day => 24 * 60 * 60,
+        }
+    }
+
+    /// The start of the interval that `timestamp` falls into.
+    pub fn start(&self, timestamp: i32) -> i32 {
+        let offset = timestamp % self.seconds();
+        if offset < 0 {
+            timestamp - offset - self.seconds()
+        } else {
+            timestamp - offset
+        }
+    }
+}
+
+/// How an aggregate is computed from the timeseries entities of an interval.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AggregateFn {
+    Sum,
+    Count,
+    Min,
+    Max,
+}
+
+impl AggregateFn {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "sum" => Some(AggregateFn::Sum),
+            "count" => Some(AggregateFn::Count),
+            "min" => Some(AggregateFn::Min),
+            "max" => Some(AggregateFn::Max),
+            _ => None,
+        }
+    }
+}
+
+/// A field of an aggregation that is computed with `function` from the
+/// field `arg` of the timeseries entities.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregate {
+    pub field: String,
+    pub function: AggregateFn,
+    pub arg: Option<String>,
+}
+
+/// An entity type declared with `@aggregation`, whose entities are rolled up
+/// by the store from the entities of the timeseries type `source`, one entity
+/// per interval and combination of dimension values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregation {
+    pub entity_type: String,
+    pub source: String,
+    pub intervals: Vec<AggregationInterval>,
+    /// Fields that are copied from the timeseries entities; entities with
+    /// different values for them are rolled up separately.
+    pub dimensions: Vec<String>,
+    pub aggregates: Vec<Aggregate>,
+}
+
+/// Whether `object_type` is declared with `@entity(timeseries: true)`.
+pub fn is_timeseries_type(object_type: &ObjectType) -> bool {
+    get_object_type_directive(object_type, "entity".to_owned())
+        .and_then(|directive| argument(directive, "timeseries"))
+        == Some(&schema::Value::Boolean(true))
+}
+
+fn argument<'a>(directive: &'a Directive, name: &str) -> Option<&'a schema::Value> {
+    directive
+        .arguments
+        .iter()
+        .find(|(arg, _)| arg == name)
+        .map(|(_, value)| value)
+}
+
+fn string_argument<'a>(directive: &'a Directive, name: &str) -> Option<&'a str> {
+    match argument(directive, name) {
+        Some(schema::Value::String(s)) => Some(s),
+        _ => None,
+    }
+}
+
+fn named_type(field_type: &schema::Type) -> &str {
+    match field_type {
+        schema::Type::NamedType(name) => name,
+        schema::Type::ListType(t) | schema::Type::NonNullType(t) => named_type(t),
+    }
+}
+
+impl Aggregation {
+    /// Returns the aggregation declared by `object_type`, if it has an
+    /// `@aggregation` directive. `object_types` are all the object types of
+    /// the schema, to check the source of the aggregation against.
+    pub fn from_object_type(
+        object_type: &ObjectType,
+        object_types: &[&ObjectType],
+    ) -> Result<Option<Self>, SchemaValidationError> {
+        let directive = match get_object_type_directive(object_type, "aggregation".to_owned()) {
+            Some(directive) => directive,
+            None => return Ok(None),
+        };
+        let invalid = |reason: String| {
+            SchemaValidationError::InvalidAggregation(object_type.name.clone(), reason)
+        };
+
+        let source = string_argument(directive, "source")
+            .ok_or_else(|| invalid("`source` must name a timeseries entity type".to_owned()))?;
+        let source_type = object_types
+            .iter()
+            .find(|t| t.name == source)
+            .filter(|t| is_timeseries_type(t))
+            .ok_or_else(|| {
+                invalid(format!(
+                    "source `{}` is not declared with `@entity(timeseries: true)`",
+                    source
+                ))
+            })?;
+
+        let intervals = match argument(directive, "intervals") {
+            Some(schema::Value::List(values)) if !values.is_empty() => values
+                .iter()
+                .map(|value| match value {
+                    schema::Value::String(s) => AggregationInterval::parse(s),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>(),
+            _ => None,
+        }
+        .ok_or_else(|| invalid("`intervals` must be a list of \"hour\" and \"day\"".to_owned()))?;
+
+        for required in &["id", TIMESTAMP_FIELD, INTERVAL_FIELD] {
+            if !object_type.fields.iter().any(|f| f.name == *required) {
+                return Err(invalid(format!("field `{}` is missing", required)));
+            }
+        }
+
+        let mut dimensions = vec![];
+        let mut aggregates = vec![];
+        for field in &object_type.fields {
+            if ["id", TIMESTAMP_FIELD, INTERVAL_FIELD].contains(&field.name.as_str()) {
+                continue;
+            }
+            let source_field = |name: &str| {
+                source_type
+                    .fields
+                    .iter()
+                    .find(|f| f.name == name)
+                    .ok_or_else(|| {
+                        invalid(format!(
+                            "field `{}` refers to `{}`, which `{}` does not have",
+                            field.name, name, source
+                        ))
+                    })
+            };
+
+            let directive = match field.directives.iter().find(|d| d.name == "aggregate") {
+                Some(directive) => directive,
+                None => {
+                    source_field(&field.name)?;
+                    dimensions.push(field.name.clone());
+                    continue;
+                }
+            };
+            let function = string_argument(directive, "fn")
+                .and_then(AggregateFn::parse)
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "`fn` of field `{}` must be one of \"sum\", \"count\", \"min\" and \"max\"",
+                        field.name
+                    ))
+                })?;
+            let arg = string_argument(directive, "arg");
+            match (function, arg) {
+                (AggregateFn::Count, _) => {
+                    if named_type(&field.field_type) != "Int" {
+                        return Err(invalid(format!("count `{}` must be an Int", field.name)));
+                    }
+                }
+                (AggregateFn::Sum, Some(arg)) => {
+                    let arg_type = named_type(&source_field(arg)?.field_type);
+                    if !["Int", "BigInt", "BigDecimal"].contains(&arg_type) {
+                        return Err(invalid(format!(
+                            "sum `{}` needs a numeric `arg`, but `{}` is a {}",
+                            field.name, arg, arg_type
+                        )));
+                    }
+                }
+                (_, Some(arg)) => {
+                    source_field(arg)?;
+                }
+                (_, None) => {
+                    return Err(invalid(format!("field `{}` needs an `arg`", field.name)));
+                }
+            }
+            aggregates.push(Aggregate {
+                field: field.name.clone(),
+                function,
+                arg: arg.map(|arg| arg.to_owned()),
+            });
+        }
+
+        Ok(Some(Aggregation {
+            entity_type: object_type.name.clone(),
+            source: source.to_owned(),
+            intervals,
+            dimensions,
+            aggregates,
+        }))
+    }
+
+    /// The ID of the entity of this aggregation that the timeseries entity
+    /// `source` is rolled up into for `interval`: the values of the
+    /// dimensions, the interval and its start, separated by `-`.
+    pub fn entity_id(
+        &self,
+        interval: AggregationInterval,
+        source: &Entity,
+    ) -> Result<String, Error> {
+        let mut parts = self
+            .dimensions
+            .iter()
+            .map(|dimension| {
+                source
+                    .get(dimension)
+                    .map_or("null".to_owned(), |value| value.to_string())
+            })
+            .collect::<Vec<_>>();
+        parts.push(interval.as_str().to_owned());
+        parts.push(interval.start(timestamp(source)?).to_string());
+        Ok(parts.join("-"))
+    }
+
+    /// Rolls the timeseries entity `source` up into `current`, the entity of
+    /// this aggregation for the interval and dimension values of `source`, or
+    /// into a new entity if there is none yet.
+    pub fn roll_up(
+        &self,
+        interval: AggregationInterval,
+        current: Option<Entity>,
+        source: &Entity,
+    ) -> Result<Entity, Error> {
+        let mut entity = match current {
+            Some(entity) => entity,
+            None => {
+                let mut entity = Entity::new();
+                entity.set("id", self.entity_id(interval, source)?);
+                entity.set(TIMESTAMP_FIELD, interval.start(timestamp(source)?));
+                entity.set(INTERVAL_FIELD, interval.as_str());
+                for dimension in &self.dimensions {
+                    entity.set(
+                        dimension.as_str(),
+                        source.get(dimension).cloned().unwrap_or(Value::Null),
+                    );
+                }
+                entity
+            }
+        };
+
+        for aggregate in &self.aggregates {
+            let current = entity.get(&aggregate.field).cloned();
+            let value = aggregate.arg.as_ref().and_then(|arg| source.get(arg));
+            let updated = match (aggregate.function, current, value) {
+                (AggregateFn::Count, Some(Value::Int(n)), _) => Value::Int(n + 1),
+                (AggregateFn::Count, _, _) => Value::Int(1),
+
+                // Timeseries entities without a value don't change the aggregate
+                (_, current, None) | (_, current, Some(Value::Null)) => {
+                    current.unwrap_or(Value::Null)
+                }
+                (_, None, Some(value)) | (_, Some(Value::Null), Some(value)) => value.clone(),
+                (AggregateFn::Sum, Some(current), Some(value)) => sum(&current, value)?,
+                (AggregateFn::Min, Some(current), Some(value)) => {
+                    if less_than(value, &current)? {
+                        value.clone()
+                    } else {
+                        current
+                    }
+                }
+                (AggregateFn::Max, Some(current), Some(value)) => {
+                    if less_than(&current, value)? {
+                        value.clone()
+                    } else {
+                        current
+                    }
+                }
+            };
+            entity.set(aggregate.field.as_str(), updated);
+        }
+        Ok(entity)
+    }
+}
+
+/// The aggregations of a schema, by the timeseries type they roll up.
+pub type AggregationsBySource = BTreeMap<String, Vec<Aggregation>>;
+
+fn timestamp(source: &Entity) -> Result<i32, Error> {
+    match source.get(TIMESTAMP_FIELD) {
+        Some(Value::Int(timestamp)) => Ok(*timestamp),
+        _ => Err(format_err!(
+            "timeseries entity has no `{}` Int field",
+            TIMESTAMP_FIELD
+        )),
+    }
+}
+
+fn sum(a: &Value, b: &Value) -> Result<Value, Error> {
+    Ok(match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Value::Int(
+            a.checked_add(*b)
+                .ok_or_else(|| format_err!("sum of {} and {} overflows an Int", a, b))?,
+        ),
+        (Value::BigInt(a), Value::BigInt(b)) => Value::BigInt(a.clone() + b.clone()),
+        (Value::BigDecimal(a), Value::BigDecimal(b)) => Value::BigDecimal(a + b),
+        _ => bail!("cannot add {} and {}", a, b),
+    })
+}
+
+fn less_than(a: &Value, b: &Value) -> Result<bool, Error> {
+    Ok(match (a, b) {
+        (Value::Int(a), Value::Int(b)) => a < b,
+        (Value::BigInt(a), Value::BigInt(b)) => a < b,
+        (Value::BigDecimal(a), Value::BigDecimal(b)) => a < b,
+        (Value::String(a), Value::String(b)) => a < b,
+        _ => bail!("cannot compare {} and {}", a, b),
+    })
+}
+
+#[test]
+fn roll_up_timeseries_entities() {
+    let document = graphql_parser::parse_schema(
+        "type Swap @entity(timeseries: true) {
+            id: ID!, timestamp: Int!, token: String!, amount: Int
+        }
+        type TokenStats @aggregation(intervals: [\"hour\", \"day\"], source: \"Swap\") {
+            id: ID!, timestamp: Int!, interval: String!, token: String!
+            volume: Int @aggregate(fn: \"sum\", arg: \"amount\")
+            largest: Int @aggregate(fn: \"max\", arg: \"amount\")
+            swaps: Int! @aggregate(fn: \"count\")
+        }",
+    )
+    .unwrap();
+    let object_types = crate::data::graphql::validation::get_object_type_definitions(&document);
+    let aggregation = Aggregation::from_object_type(object_types[1], &object_types)
+        .unwrap()
+        .unwrap();
+    assert_eq!(aggregation.dimensions, vec!["token".to_owned()]);
+
+    let swap = |timestamp: i32, amount: Value| {
+        Entity::from(vec![
+            ("timestamp", Value::Int(timestamp)),
+            ("token", Value::from("GRT")),
+            ("amount", amount),
+        ])
+    };
+    let hour = AggregationInterval::Hour;
+    let stats = aggregation
+        .roll_up(hour, None, &swap(7300, Value::Int(3)))
+        .unwrap();
+    let stats = aggregation
+        .roll_up(hour, Some(stats), &swap(7400, Value::Int(5)))
+        .unwrap();
+    let stats = aggregation
+        .roll_up(hour, Some(stats), &swap(7500, Value::Null))
+        .unwrap();
+
+    assert_eq!(
+        aggregation
+            .entity_id(hour, &swap(10799, Value::Null))
+            .unwrap(),
+        "GRT-hour-7200"
+    );
+    assert_eq!(
+        stats,
+        Entity::from(vec![
+            ("id", Value::from("GRT-hour-7200")),
+            ("timestamp", Value::Int(7200)),
+            ("interval", Value::from("hour")),
+            ("token", Value::from("GRT")),
+            ("volume", Value::Int(8)),
+            ("largest", Value::Int(5)),
+            ("swaps", Value::Int(3)),
+        ])
+    );
+}
+
+#[test]
+fn invalid_aggregations() {
+    let error = |aggregation: &str| {
+        let document = graphql_parser::parse_schema(&format!(
+            "type Swap @entity(timeseries: true) {{ id: ID!, timestamp: Int!, pair: String }}
+             {}",
+            aggregation
+        ))
+        .unwrap();
+        let object_types = crate::data::graphql::validation::get_object_type_definitions(&document);
+        Aggregation::from_object_type(object_types[1], &object_types)
+            .unwrap_err()
+            .to_string()
+    };
+
+    assert_eq!(
+        error(
+            "type Stats @aggregation(intervals: [\"week\"], source: \"Swap\") {
+                id: ID!, timestamp: Int!, interval: String!
+             }"
+        ),
+        "Aggregation `Stats` is invalid: `intervals` must be a list of \"hour\" and \"day\""
+    );
+    assert_eq!(
+        error(
+            "type Stats @aggregation(intervals: [\"day\"], source: \"Swap\") {
+                id: ID!, timestamp: Int!, interval: String!
+                total: Int @aggregate(fn: \"sum\", arg: \"pair\")
+             }"
+        ),
+        "Aggregation `Stats` is invalid: sum `total` needs a numeric `arg`, but `pair` is a String"
+    );
+}
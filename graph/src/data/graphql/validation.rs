@@ -25,6 +25,12 @@ pub enum SchemaValidationError {
         _0, _1, _2
     )]
     CannotImplement(String, String, Strings), // (type, interface, missing_fields)
+
+    #[fail(display = "Aggregation `{}` is invalid: {}", _0, _1)]
+    InvalidAggregation(String, String), // (type, reason)
+
+    #[fail(display = "Index on entity type `{}` is invalid: {}", _0, _1)]
+    InvalidIndex(String, String), // (type, reason)
 }
 
 /// Validates whether a GraphQL schema is compatible with The Graph.
@@ -32,13 +38,17 @@ pub(crate) fn validate_schema(schema: &Document) -> Result<(), SchemaValidationE
     validate_schema_types(schema)
 }
 
-/// Validates whether all object types in the schema are declared with an @entity directive.
+/// Validates whether all object types in the schema are declared with an @entity
+/// or @aggregation directive.
 fn validate_schema_types(schema: &Document) -> Result<(), SchemaValidationError> {
     use self::SchemaValidationError::*;
 
     let types_without_entity_directive = get_object_type_definitions(schema)
         .iter()
-        .filter(|t| get_object_type_directive(t, String::from("entity")).is_none())
+        .filter(|t| {
+            get_object_type_directive(t, String::from("entity")).is_none()
+                && get_object_type_directive(t, String::from("aggregation")).is_none()
+        })
         .map(|t| t.name.to_owned())
         .collect::<Vec<_>>();
 
@@ -79,6 +89,57 @@ pub(crate) fn validate_interface_implementation(
     }
 }
 
+/// Validates the `@index(fields: [...])` directives of `object`, which
+/// declare composite indexes on two or more of its fields.
+pub(crate) fn validate_index_directives(object: &ObjectType) -> Result<(), SchemaValidationError> {
+    let invalid = |reason: String| SchemaValidationError::InvalidIndex(object.name.clone(), reason);
+
+    for directive in object.directives.iter().filter(|d| d.name == "index") {
+        let fields = index_directive_fields(directive).ok_or_else(|| {
+            invalid("`fields` must be a list of at least two field names".to_owned())
+        })?;
+        for name in fields {
+            let field = object
+                .fields
+                .iter()
+                .find(|field| field.name == name)
+                .ok_or_else(|| invalid(format!("field `{}` does not exist", name)))?;
+            if field.name == "id" || is_list_type(&field.field_type) {
+                return Err(invalid(format!("field `{}` can't be indexed", name)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the field names of an `@index(fields: [...])` directive, if
+/// there are at least two.
+pub fn index_directive_fields(directive: &Directive) -> Option<Vec<&str>> {
+    let values = directive
+        .arguments
+        .iter()
+        .find(|(name, _)| name == "fields")
+        .and_then(|(_, value)| match value {
+            Value::List(values) if values.len() >= 2 => Some(values),
+            _ => None,
+        })?;
+    values
+        .iter()
+        .map(|value| match value {
+            Value::String(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn is_list_type(field_type: &Type) -> bool {
+    match field_type {
+        Type::NamedType(_) => false,
+        Type::ListType(_) => true,
+        Type::NonNullType(inner) => is_list_type(inner),
+    }
+}
+
 /// Returns all object type definitions in the schema.
 pub fn get_object_type_definitions(schema: &Document) -> Vec<&ObjectType> {
     schema
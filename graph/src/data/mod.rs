@@ -7,6 +7,9 @@ pub mod query;
 /// Data types for dealing with GraphQL schemas.
 pub mod schema;
 
+/// Data types for dealing with timeseries entities and their aggregations.
+pub mod aggregation;
+
 /// Data types for dealing with storing entities.
 pub mod store;
 
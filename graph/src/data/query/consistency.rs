@@ -0,0 +1,108 @@
+use failure::Error;
+use std::fmt;
+use std::str::FromStr;
+use web3::types::H256;
+
+use crate::components::ethereum::EthereumBlockPointer;
+
+/// An opaque token handed out with query results that identifies how far
+/// the subgraph deployment had been indexed when the query was served.
+///
+/// Clients can send the token back with later queries to require that
+/// the node serving them is at least as far along, which gives them
+/// read-your-writes semantics across nodes behind a load balancer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConsistencyToken(pub EthereumBlockPointer);
+
+impl ConsistencyToken {
+    /// Returns `true` if the given block pointer is the block this token was
+    /// issued for or one of its descendants. For a later block,
+    /// `ancestor_hash` looks up the hash of the ancestor of that block that
+    /// is the given number of blocks back; blocks whose ancestor isn't
+    /// known don't satisfy the token.
+    pub fn is_satisfied_by<F>(
+        &self,
+        block_ptr: &EthereumBlockPointer,
+        ancestor_hash: F,
+    ) -> Result<bool, Error>
+    where
+        F: FnOnce(EthereumBlockPointer, u64) -> Result<Option<H256>, Error>,
+    {
+        if block_ptr.number < self.0.number {
+            Ok(false)
+        } else if block_ptr.number == self.0.number {
+            Ok(block_ptr.hash == self.0.hash)
+        } else {
+            let offset = block_ptr.number - self.0.number;
+            Ok(ancestor_hash(*block_ptr, offset)? == Some(self.0.hash))
+        }
+    }
+}
+
+impl From<EthereumBlockPointer> for ConsistencyToken {
+    fn from(block_ptr: EthereumBlockPointer) -> Self {
+        ConsistencyToken(block_ptr)
+    }
+}
+
+impl fmt::Display for ConsistencyToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.0.number, self.0.hash_hex())
+    }
+}
+
+impl FromStr for ConsistencyToken {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(2, ':');
+        let number = parts
+            .next()
+            .unwrap_or("")
+            .parse::<u64>()
+            .map_err(|e| format_err!("invalid consistency token `{}`: {}", s, e))?;
+        let hash = parts
+            .next()
+            .ok_or_else(|| format_err!("invalid consistency token `{}`", s))?
+            .parse::<H256>()
+            .map_err(|e| format_err!("invalid consistency token `{}`: {}", s, e))?;
+        Ok(ConsistencyToken(EthereumBlockPointer::from((hash, number))))
+    }
+}
+
+#[test]
+fn consistency_token_roundtrip() {
+    let token = ConsistencyToken(EthereumBlockPointer {
+        hash: H256::from(42),
+        number: 1234,
+    });
+    assert_eq!(token, token.to_string().parse().unwrap());
+    assert!("1234".parse::<ConsistencyToken>().is_err());
+    assert!("abc:00".parse::<ConsistencyToken>().is_err());
+}
+
+#[test]
+fn consistency_tokens_are_satisfied_by_their_block_and_its_descendants() {
+    let ptr = |hash: u64, number: u64| EthereumBlockPointer {
+        hash: H256::from(hash),
+        number,
+    };
+    let token = ConsistencyToken(ptr(1, 10));
+    // Block 12 (hash 3) descends from block 10 (hash 1), block 12 (hash 4)
+    // from block 10 (hash 2)
+    let ancestor_hash = |block_ptr: EthereumBlockPointer, offset: u64| {
+        assert_eq!(offset, 2);
+        Ok(match block_ptr.hash {
+            hash if hash == H256::from(3) => Some(H256::from(1)),
+            hash if hash == H256::from(4) => Some(H256::from(2)),
+            _ => None,
+        })
+    };
+
+    assert!(token.is_satisfied_by(&ptr(1, 10), ancestor_hash).unwrap());
+    assert!(token.is_satisfied_by(&ptr(3, 12), ancestor_hash).unwrap());
+    assert!(!token.is_satisfied_by(&ptr(1, 9), ancestor_hash).unwrap());
+    assert!(!token.is_satisfied_by(&ptr(2, 10), ancestor_hash).unwrap());
+    assert!(!token.is_satisfied_by(&ptr(4, 12), ancestor_hash).unwrap());
+    assert!(!token.is_satisfied_by(&ptr(5, 12), ancestor_hash).unwrap());
+}
@@ -35,7 +35,8 @@ pub enum QueryExecutionError {
     EmptyQuery,
     MultipleSubscriptionFields,
     SubgraphDeploymentIdError(String),
-    RangeArgumentsError(Vec<&'static str>),
+    RangeArgumentsError(Vec<&'static str>, u32, u32),
+    DerivedFieldLimitError(String, u32),
     InvalidFilterError,
     EntityFieldError(String, String),
     ListTypesError(String, Vec<String>),
@@ -128,16 +129,21 @@ impl fmt::Display for QueryExecutionError {
             SubgraphDeploymentIdError(s) => {
                 write!(f, "Failed to get subgraph ID from type: `{}`", s)
             }
-            RangeArgumentsError(args) => {
+            RangeArgumentsError(args, max_first, max_skip) => {
                 let msg = args.into_iter().map(|arg| {
                     match *arg {
-                        "first" => format!("Value of \"first\" must be between 1 and 100"),
-                        "skip" => format!("Value of \"skip\" must be greater than 0"),
+                        "first" => format!("Value of \"first\" must be between 1 and {}", max_first),
+                        "skip" if *max_skip == u32::max_value() => format!("Value of \"skip\" must be greater than 0"),
+                        "skip" => format!("Value of \"skip\" must be between 0 and {}", max_skip),
                         _ => format!("Value of \"{}\" is must be an integer", arg),
                     }
                 }).collect::<Vec<_>>().join(", ");
                 write!(f, "{}", msg)
             }
+            DerivedFieldLimitError(field, max_first) => {
+                write!(f, "Value of \"first\" for the derived field `{}` must be between 1 and {}",
+                       field, max_first)
+            }
             InvalidFilterError => write!(f, "Filter must by an object"),
             EntityFieldError(e, a) => {
                 write!(f, "Entity `{}` has no attribute `{}`", e, a)
@@ -216,6 +222,9 @@ pub enum QueryError {
     EncodingError(FromUtf8Error),
     ParseError(q::ParseError),
     ExecutionError(QueryExecutionError),
+    /// An error that only affected the field at the given response path;
+    /// the data of the other fields is still part of the result.
+    FieldError(Vec<String>, Box<QueryError>),
 }
 
 impl From<FromUtf8Error> for QueryError {
@@ -245,6 +254,7 @@ impl Error for QueryError {
         match *self {
             QueryError::EncodingError(ref e) => Some(e),
             QueryError::ExecutionError(ref e) => Some(e),
+            QueryError::FieldError(_, ref e) => Some(e.as_ref()),
             _ => None,
         }
     }
@@ -256,6 +266,7 @@ impl fmt::Display for QueryError {
             QueryError::EncodingError(ref e) => write!(f, "{}", e),
             QueryError::ExecutionError(ref e) => write!(f, "{}", e),
             QueryError::ParseError(ref e) => write!(f, "{}", e),
+            QueryError::FieldError(_, ref e) => write!(f, "{}", e),
         }
     }
 }
@@ -267,15 +278,22 @@ impl Serialize for QueryError {
     {
         use self::QueryExecutionError::*;
 
-        let mut map = serializer.serialize_map(Some(1))?;
+        let mut map = serializer.serialize_map(None)?;
+
+        // Field errors are serialized like the error they wrap, plus the
+        // path of the field in the response
+        let (error, path) = match self {
+            QueryError::FieldError(path, error) => (error.as_ref(), Some(path)),
+            error => (error, None),
+        };
 
-        let msg = match self {
+        let msg = match error {
             // Serialize parse errors with their location (line, column) to make it easier
             // for users to find where the errors are; this is likely to change as the
             // graphql_parser team makes improvements to their error reporting
             QueryError::ParseError(_) => {
                 // Split the inner message into (first line, rest)
-                let msg = format!("{}", self);
+                let msg = format!("{}", error);
                 let inner_msg = msg.replace("query parse error:", "");
                 let inner_msg = inner_msg.trim();
                 let parts: Vec<&str> = inner_msg.splitn(2, '\n').collect();
@@ -320,12 +338,15 @@ impl Serialize for QueryError {
                 location.insert("line", pos.line);
                 location.insert("column", pos.column);
                 map.serialize_entry("locations", &vec![location])?;
-                format!("{}", self)
+                format!("{}", error)
             }
-            _ => format!("{}", self),
+            _ => format!("{}", error),
         };
 
         map.serialize_entry("message", msg.as_str())?;
+        if let Some(path) = path {
+            map.serialize_entry("path", path)?;
+        }
         map.end()
     }
 }
@@ -1,7 +1,11 @@
+mod consistency;
 mod error;
 mod query;
 mod result;
+mod stats;
 
+pub use self::consistency::ConsistencyToken;
 pub use self::error::{QueryError, QueryExecutionError};
 pub use self::query::{Query, QueryVariables};
 pub use self::result::QueryResult;
+pub use self::stats::{store_timed, QueryStats, QueryStatsSummary, SlowQuery};
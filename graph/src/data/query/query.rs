@@ -4,6 +4,7 @@ use std::collections::{BTreeMap, HashMap};
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 
+use crate::components::ethereum::EthereumBlockPointer;
 use crate::data::schema::Schema;
 
 fn deserialize_number<'de, D>(deserializer: D) -> Result<q::Number, D::Error>
@@ -92,4 +93,7 @@ pub struct Query {
     pub schema: Arc<Schema>,
     pub document: q::Document,
     pub variables: Option<QueryVariables>,
+    /// Run the query against the data as it was at this block instead of
+    /// the latest data.
+    pub block: Option<EthereumBlockPointer>,
 }
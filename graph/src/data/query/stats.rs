@@ -0,0 +1,220 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::env;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::data::graphql::SerializableValue;
+use crate::data::query::Query;
+use crate::data::subgraph::SubgraphDeploymentId;
+
+/// How many of the latest queries of each deployment the latency
+/// percentiles are computed from.
+const LATENCY_WINDOW: usize = 1000;
+
+lazy_static! {
+    /// Queries that take at least this long end up in the slow query log.
+    static ref SLOW_QUERY_THRESHOLD: Duration = env::var("GRAPH_SLOW_QUERY_THRESHOLD")
+        .ok()
+        .map(|s| Duration::from_millis(
+            u64::from_str(&s)
+                .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_SLOW_QUERY_THRESHOLD"))
+        ))
+        .unwrap_or(Duration::from_secs(1));
+
+    /// How many slow queries are kept, across all deployments.
+    static ref SLOW_QUERY_LOG_SIZE: usize = env::var("GRAPH_SLOW_QUERY_LOG_SIZE")
+        .ok()
+        .map(|s| usize::from_str(&s)
+            .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_SLOW_QUERY_LOG_SIZE")))
+        .unwrap_or(100);
+}
+
+thread_local! {
+    /// The time the query running on this thread has spent in the store.
+    static STORE_TIME: Cell<Duration> = Cell::new(Duration::from_secs(0));
+}
+
+/// Runs `f`, which queries the database, and counts the time it takes
+/// towards the store time of the query that runs on this thread. `f`
+/// should not wait for a database connection, since that time is spent
+/// waiting for other queries rather than in the store.
+pub fn store_timed<T>(f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    STORE_TIME.with(|time| time.set(time.get() + start.elapsed()));
+    result
+}
+
+/// The number of queries of a deployment and how long they took.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryStatsSummary {
+    pub count: u64,
+    pub p50: Duration,
+    pub p95: Duration,
+}
+
+/// A query that took at least `GRAPH_SLOW_QUERY_THRESHOLD`.
+#[derive(Clone, Debug)]
+pub struct SlowQuery {
+    pub subgraph_id: SubgraphDeploymentId,
+    pub query: String,
+    /// The variables of the query as a JSON object.
+    pub variables: Option<String>,
+    pub duration: Duration,
+    /// How much of `duration` was spent in the store.
+    pub store_time: Duration,
+    pub received_at: SystemTime,
+}
+
+#[derive(Debug, Default)]
+struct DeploymentQueryStats {
+    count: u64,
+    latencies: VecDeque<Duration>,
+}
+
+/// Query counts and latencies per deployment, and a log of the latest slow
+/// queries, kept in memory by each query node since it started.
+#[derive(Debug)]
+pub struct QueryStats {
+    slow_query_threshold: Duration,
+    slow_query_log_size: usize,
+    deployments: Mutex<HashMap<SubgraphDeploymentId, DeploymentQueryStats>>,
+    slow_queries: Mutex<VecDeque<SlowQuery>>,
+}
+
+impl Default for QueryStats {
+    fn default() -> Self {
+        QueryStats::new(*SLOW_QUERY_THRESHOLD, *SLOW_QUERY_LOG_SIZE)
+    }
+}
+
+impl QueryStats {
+    pub fn new(slow_query_threshold: Duration, slow_query_log_size: usize) -> Self {
+        QueryStats {
+            slow_query_threshold,
+            slow_query_log_size,
+            deployments: Mutex::new(HashMap::new()),
+            slow_queries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Runs `query` with `run` on the current thread and records how long
+    /// it took. Returns the result of `run` and the slow query entry, if the
+    /// query was slow.
+    pub fn measure<T>(&self, query: &Query, run: impl FnOnce() -> T) -> (T, Option<SlowQuery>) {
+        let received_at = SystemTime::now();
+        let start = Instant::now();
+        let outer_store_time = STORE_TIME.with(|time| time.replace(Duration::from_secs(0)));
+        let result = run();
+        let duration = start.elapsed();
+        let store_time = STORE_TIME.with(|time| time.replace(outer_store_time));
+
+        let subgraph_id = query.schema.id.clone();
+        self.record(&subgraph_id, duration);
+        if duration < self.slow_query_threshold {
+            return (result, None);
+        }
+
+        let variables = query.variables.as_ref().map(|variables| {
+            let variables = variables
+                .iter()
+                .map(|(name, value)| (name, SerializableValue(value)))
+                .collect::<BTreeMap<_, _>>();
+            serde_json::to_string(&variables).expect("failed to serialize query variables")
+        });
+        let slow_query = SlowQuery {
+            subgraph_id,
+            query: query.document.to_string(),
+            variables,
+            duration,
+            store_time,
+            received_at,
+        };
+        let mut slow_queries = self.slow_queries.lock().unwrap();
+        slow_queries.push_back(slow_query.clone());
+        while slow_queries.len() > self.slow_query_log_size {
+            slow_queries.pop_front();
+        }
+        (result, Some(slow_query))
+    }
+
+    fn record(&self, subgraph_id: &SubgraphDeploymentId, duration: Duration) {
+        let mut deployments = self.deployments.lock().unwrap();
+        let stats = deployments
+            .entry(subgraph_id.clone())
+            .or_insert_with(DeploymentQueryStats::default);
+        stats.count += 1;
+        stats.latencies.push_back(duration);
+        if stats.latencies.len() > LATENCY_WINDOW {
+            stats.latencies.pop_front();
+        }
+    }
+
+    /// The number of queries of `subgraph_id` and the median and 95th
+    /// percentile of the latency of its latest queries, if it had any.
+    pub fn summary(&self, subgraph_id: &SubgraphDeploymentId) -> Option<QueryStatsSummary> {
+        let deployments = self.deployments.lock().unwrap();
+        let stats = deployments.get(subgraph_id)?;
+        let mut latencies = stats.latencies.iter().cloned().collect::<Vec<_>>();
+        latencies.sort();
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+        Some(QueryStatsSummary {
+            count: stats.count,
+            p50: percentile(50),
+            p95: percentile(95),
+        })
+    }
+
+    /// The slow queries in the log, oldest first, of all deployments or
+    /// only of `subgraph_id`.
+    pub fn slow_queries(&self, subgraph_id: Option<&SubgraphDeploymentId>) -> Vec<SlowQuery> {
+        self.slow_queries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|slow_query| subgraph_id.map_or(true, |id| slow_query.subgraph_id == *id))
+            .cloned()
+            .collect()
+    }
+}
+
+#[test]
+fn query_stats_track_latencies_and_slow_queries() {
+    use crate::data::schema::Schema;
+    use graphql_parser::parse_query;
+    use std::sync::Arc;
+    use std::thread;
+
+    let id = SubgraphDeploymentId::new("QmStats").unwrap();
+    let query = Query {
+        schema: Arc::new(Schema::parse("type User @entity { id: ID! }", id.clone()).unwrap()),
+        document: parse_query("{ users { id } }").unwrap(),
+        variables: None,
+        block: None,
+    };
+
+    let stats = QueryStats::new(Duration::from_millis(50), 1);
+    for _ in 0..19 {
+        let (result, slow_query) = stats.measure(&query, || 1);
+        assert_eq!(result, 1);
+        assert!(slow_query.is_none());
+    }
+    let (_, slow_query) = stats.measure(&query, || {
+        store_timed(|| thread::sleep(Duration::from_millis(60)))
+    });
+    let slow_query = slow_query.unwrap();
+    assert!(slow_query.store_time >= Duration::from_millis(60));
+    assert!(slow_query.duration >= slow_query.store_time);
+    assert!(slow_query.query.contains("users"));
+
+    let summary = stats.summary(&id).unwrap();
+    assert_eq!(summary.count, 20);
+    assert!(summary.p50 < Duration::from_millis(50));
+    assert!(summary.p95 < Duration::from_millis(50));
+    assert_eq!(stats.slow_queries(Some(&id)).len(), 1);
+    assert!(stats
+        .slow_queries(Some(&SubgraphDeploymentId::new("QmOther").unwrap()))
+        .is_empty());
+}
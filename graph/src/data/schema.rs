@@ -1,13 +1,15 @@
+use crate::data::aggregation::{is_timeseries_type, Aggregation, AggregationsBySource};
 use crate::data::graphql::validation::{
-    get_object_type_definitions, validate_interface_implementation, validate_schema,
-    SchemaValidationError,
+    get_object_type_definitions, get_object_type_directive, validate_index_directives,
+    validate_interface_implementation, validate_schema, SchemaValidationError,
 };
+use crate::data::store::{Entity, Value};
 use crate::data::subgraph::SubgraphDeploymentId;
 use failure::Error;
 use graphql_parser;
 use graphql_parser::{
     query::Name,
-    schema::{self, InterfaceType, ObjectType, TypeDefinition},
+    schema::{self, EnumType, InterfaceType, ObjectType, TypeDefinition},
     Pos,
 };
 use std::collections::BTreeMap;
@@ -24,6 +26,9 @@ pub struct Schema {
 
     // Maps an interface name to the list of entities that implement it.
     types_for_interface: BTreeMap<Name, Vec<ObjectType>>,
+
+    // Maps a timeseries type to the aggregations that roll it up.
+    aggregations: AggregationsBySource,
 }
 
 impl Schema {
@@ -36,6 +41,7 @@ impl Schema {
             document,
             interfaces_for_type: BTreeMap::new(),
             types_for_interface: BTreeMap::new(),
+            aggregations: BTreeMap::new(),
         }
     }
 
@@ -84,11 +90,24 @@ impl Schema {
             }
         }
 
+        let object_types = get_object_type_definitions(&document);
+        let mut aggregations = AggregationsBySource::new();
+        for object_type in &object_types {
+            validate_index_directives(object_type)?;
+            if let Some(aggregation) = Aggregation::from_object_type(object_type, &object_types)? {
+                aggregations
+                    .entry(aggregation.source.clone())
+                    .or_default()
+                    .push(aggregation);
+            }
+        }
+
         let mut schema = Schema {
             id: id.clone(),
             document,
             interfaces_for_type,
             types_for_interface,
+            aggregations,
         };
         schema.add_subgraph_id_directives(id);
 
@@ -105,6 +124,103 @@ impl Schema {
         self.interfaces_for_type.get(type_name)
     }
 
+    /// The aggregations that roll up the timeseries type `entity_type`.
+    pub fn aggregations_for_source(&self, entity_type: &str) -> &[Aggregation] {
+        self.aggregations
+            .get(entity_type)
+            .map(|aggregations| aggregations.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Whether `entity_type` is declared with `@entity(timeseries: true)`.
+    pub fn is_timeseries_type(&self, entity_type: &str) -> bool {
+        self.object_type(entity_type)
+            .map_or(false, |object_type| is_timeseries_type(object_type))
+    }
+
+    /// Whether `entity_type` is declared with `@entity(immutable: true)` or is
+    /// a timeseries. Entities of immutable types can't be changed or removed
+    /// once they are stored.
+    pub fn is_immutable_type(&self, entity_type: &str) -> bool {
+        self.object_type(entity_type).map_or(false, |object_type| {
+            is_timeseries_type(object_type)
+                || get_object_type_directive(object_type, "entity".to_owned())
+                    .and_then(|directive| {
+                        directive
+                            .arguments
+                            .iter()
+                            .find(|(name, _)| name == "immutable")
+                    })
+                    .map_or(false, |(_, value)| *value == schema::Value::Boolean(true))
+        })
+    }
+
+    /// The entity types that are immutable, see `is_immutable_type`.
+    pub fn immutable_types(&self) -> Vec<String> {
+        get_object_type_definitions(&self.document)
+            .into_iter()
+            .map(|object_type| object_type.name.clone())
+            .filter(|name| self.is_immutable_type(name))
+            .collect()
+    }
+
+    /// Whether `entity_type` is declared with `@aggregation`. Only the store
+    /// writes entities of aggregation types.
+    pub fn is_aggregation_type(&self, entity_type: &str) -> bool {
+        self.aggregations
+            .values()
+            .flatten()
+            .any(|aggregation| aggregation.entity_type == entity_type)
+    }
+
+    fn object_type(&self, name: &str) -> Option<&ObjectType> {
+        get_object_type_definitions(&self.document)
+            .into_iter()
+            .find(|object_type| object_type.name == name)
+    }
+
+    /// Returns the first field of `entity`, an entity of type `entity_type`,
+    /// whose type is an enum or a list of an enum and whose value is not one of
+    /// the values of the enum, together with the value and the name of the enum.
+    pub fn invalid_enum_value(
+        &self,
+        entity_type: &str,
+        entity: &Entity,
+    ) -> Option<(String, Value, String)> {
+        let object_type = self.object_type(entity_type)?;
+
+        object_type.fields.iter().find_map(|field| {
+            let enum_type = self.enum_type(named_type(&field.field_type))?;
+            let is_enum_value = |value: &Value| match value {
+                Value::String(s) => enum_type.values.iter().any(|v| &v.name == s),
+                Value::Null => true,
+                _ => false,
+            };
+            let value = entity.get(&field.name)?;
+            let is_valid = match value {
+                Value::List(values) => values.iter().all(is_enum_value),
+                value => is_enum_value(value),
+            };
+            if is_valid {
+                None
+            } else {
+                Some((field.name.clone(), value.clone(), enum_type.name.clone()))
+            }
+        })
+    }
+
+    fn enum_type(&self, name: &str) -> Option<&EnumType> {
+        self.document
+            .definitions
+            .iter()
+            .find_map(|definition| match definition {
+                schema::Definition::TypeDefinition(TypeDefinition::Enum(t)) if t.name == name => {
+                    Some(t)
+                }
+                _ => None,
+            })
+    }
+
     // Adds a @subgraphId(id: ...) directive to object/interface/enum types in the schema.
     pub fn add_subgraph_id_directives(&mut self, id: SubgraphDeploymentId) {
         for definition in self.document.definitions.iter_mut() {
@@ -143,6 +259,14 @@ impl Schema {
     }
 }
 
+/// The name of the type of a field, without lists and non-null markers.
+fn named_type(field_type: &schema::Type) -> &str {
+    match field_type {
+        schema::Type::NamedType(name) => name,
+        schema::Type::ListType(t) | schema::Type::NonNullType(t) => named_type(t),
+    }
+}
+
 #[test]
 fn non_existing_interface() {
     let schema = "type Foo implements Bar @entity { foo: Int }";
@@ -176,3 +300,111 @@ fn invalid_interface_implementation() {
          required fields: x: Int, y: Int"
     );
 }
+
+#[test]
+fn invalid_enum_values() {
+    let schema = Schema::parse(
+        "enum Color { RED, GREEN }
+         type Pet @entity { id: ID!, color: Color, colors: [Color!], name: String }",
+        SubgraphDeploymentId::new("id").unwrap(),
+    )
+    .unwrap();
+    let pet = |field: &str, value: Value| {
+        Entity::from(vec![
+            ("id", Value::from("1")),
+            ("name", Value::from("BLUE")),
+            (field, value),
+        ])
+    };
+
+    assert_eq!(
+        schema.invalid_enum_value("Pet", &pet("color", Value::from("RED"))),
+        None
+    );
+    assert_eq!(
+        schema.invalid_enum_value("Pet", &pet("color", Value::Null)),
+        None
+    );
+    assert_eq!(
+        schema.invalid_enum_value(
+            "Pet",
+            &pet("colors", Value::List(vec![Value::from("GREEN")]))
+        ),
+        None
+    );
+    assert_eq!(
+        schema.invalid_enum_value("Pet", &pet("color", Value::from("BLUE"))),
+        Some(("color".to_owned(), Value::from("BLUE"), "Color".to_owned()))
+    );
+    assert_eq!(
+        schema.invalid_enum_value(
+            "Pet",
+            &pet(
+                "colors",
+                Value::List(vec![Value::from("RED"), Value::Int(1)])
+            )
+        ),
+        Some((
+            "colors".to_owned(),
+            Value::List(vec![Value::from("RED"), Value::Int(1)]),
+            "Color".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn immutable_types() {
+    let schema = Schema::parse(
+        "type Transfer @entity(immutable: true) { id: ID! }
+         type Swap @entity(timeseries: true) { id: ID!, timestamp: Int! }
+         type Token @entity(immutable: false) { id: ID! }
+         type Account @entity { id: ID! }",
+        SubgraphDeploymentId::new("id").unwrap(),
+    )
+    .unwrap();
+
+    assert!(schema.is_immutable_type("Transfer"));
+    assert!(schema.is_immutable_type("Swap"));
+    assert!(!schema.is_immutable_type("Token"));
+    assert!(!schema.is_immutable_type("Account"));
+}
+
+#[test]
+fn invalid_index_directives() {
+    let parse = |schema: &str| {
+        Schema::parse(schema, SubgraphDeploymentId::new("id").unwrap())
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    };
+
+    assert_eq!(
+        parse(
+            "type Transfer @entity @index(fields: [\"from\", \"timestamp\"]) {
+                id: ID!, from: String!, timestamp: Int!
+             }"
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        parse("type Transfer @entity @index(fields: [\"from\"]) { id: ID!, from: String! }"),
+        Err("Index on entity type `Transfer` is invalid: \
+             `fields` must be a list of at least two field names"
+            .to_owned())
+    );
+    assert_eq!(
+        parse(
+            "type Transfer @entity @index(fields: [\"from\", \"to\"]) {
+                id: ID!, from: String!
+             }"
+        ),
+        Err("Index on entity type `Transfer` is invalid: field `to` does not exist".to_owned())
+    );
+    assert_eq!(
+        parse(
+            "type Transfer @entity @index(fields: [\"from\", \"tags\"]) {
+                id: ID!, from: String!, tags: [String!]!
+             }"
+        ),
+        Err("Index on entity type `Transfer` is invalid: field `tags` can't be indexed".to_owned())
+    );
+}
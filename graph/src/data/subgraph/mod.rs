@@ -14,10 +14,13 @@ use std::sync::Arc;
 use tokio::prelude::*;
 use web3::types::Address;
 
+use crate::components::ethereum::{BlockDetail, EthereumBlockPointer, EthereumNetworkError};
 use crate::components::link_resolver::LinkResolver;
 use crate::components::store::StoreError;
 use crate::data::query::QueryExecutionError;
 use crate::data::schema::Schema;
+use crate::data::store::Entity;
+use crate::data::subgraph::schema::DynamicEthereumContractDataSourceEntity;
 
 /// Rust representation of the GraphQL schema for a `SubgraphManifest`.
 pub mod schema;
@@ -200,7 +203,7 @@ fn test_subgraph_name_validation() {
 }
 
 /// Result of a creating a subgraph in the registar.
-#[derive(Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CreateSubgraphResult {
     /// The ID of the subgraph that was created.
     pub id: String,
@@ -214,12 +217,24 @@ pub enum SubgraphRegistrarError {
     NameExists(String),
     #[fail(display = "subgraph name not found: {}", _0)]
     NameNotFound(String),
+    #[fail(display = "subgraph deployment not found: {}", _0)]
+    DeploymentNotFound(String),
+    #[fail(display = "subgraph deployment is not assigned to a node: {}", _0)]
+    DeploymentAssignmentNotFound(String),
     #[fail(display = "subgraph registrar internal query error: {}", _0)]
     QueryExecutionError(QueryExecutionError),
     #[fail(display = "subgraph registrar error with store: {}", _0)]
     StoreError(StoreError),
-    #[fail(display = "subgraph validation error: {:?}", _0)]
-    ManifestValidationError(Vec<SubgraphManifestValidationError>),
+    #[fail(display = "invalid batch of operations: {}", _0)]
+    InvalidBatch(String),
+    #[fail(display = "cannot rewind subgraph deployment: {}", _0)]
+    InvalidRewind(String),
+    #[fail(display = "idempotency key was used for a different request: {}", _0)]
+    IdempotencyKeyReused(String),
+    #[fail(display = "subgraph network error: {}", _0)]
+    NetworkError(EthereumNetworkError),
+    #[fail(display = "subgraph validation error: {}", _0)]
+    ManifestValidationError(SubgraphManifestValidationErrors),
     #[fail(display = "subgraph registrar error: {}", _0)]
     Unknown(failure::Error),
 }
@@ -290,6 +305,171 @@ pub enum SubgraphManifestValidationError {
     SourceAddressRequired,
     #[fail(display = "subgraph data source has too many similar block handlers")]
     DataSourceBlockHandlerLimitExceeded,
+    #[fail(display = "block handler polling interval must be greater than zero")]
+    InvalidBlockHandlerPollingInterval,
+    #[fail(display = "data source template name is used more than once: {}", _0)]
+    DuplicateTemplateName(String),
+    #[fail(
+        display = "data source template {} references ABI {} which is not in its mapping",
+        _0, _1
+    )]
+    TemplateAbiNotFound(String, String),
+    #[fail(
+        display = "dataSources[{}].source.abi: ABI {} is not listed in mapping.abis",
+        _0, _1
+    )]
+    DataSourceAbiNotFound(String, String),
+    #[fail(
+        display = "dataSources[{}].mapping.eventHandlers: event {} is not defined in ABI {}",
+        _0, _1, _2
+    )]
+    EventNotFoundInAbi(String, String, String),
+    #[fail(
+        display = "dataSources[{}].mapping.entities: entity {} is not defined in the schema",
+        _0, _1
+    )]
+    EntityNotFoundInSchema(String, String),
+    #[fail(
+        display = "dataSources[{}].mapping.reads: entity {} is not defined in the schema",
+        _0, _1
+    )]
+    ReadEntityNotFoundInSchema(String, String),
+    #[fail(
+        display = "externalHosts: {} is not a host name, it must not include a scheme, port or path",
+        _0
+    )]
+    InvalidExternalHost(String),
+    #[fail(
+        display = "{}.mapping.abis: ABI name {} is used more than once",
+        _0, _1
+    )]
+    DuplicateAbiName(String, String),
+    #[fail(
+        display = "dataSources: all data sources must use the same network, found {}",
+        _0
+    )]
+    MultipleNetworks(String),
+    #[fail(
+        display = "dataSources[{}]: source.file and mapping.fileHandler are only supported by file/ipfs data sources",
+        _0
+    )]
+    FileFieldsNotSupported(String),
+    #[fail(
+        display = "dataSources[{}].source.file: required for file/ipfs data sources",
+        _0
+    )]
+    FileSourceRequired(String),
+    #[fail(
+        display = "dataSources[{}].mapping.fileHandler: required for file/ipfs data sources",
+        _0
+    )]
+    FileHandlerRequired(String),
+    #[fail(
+        display = "dataSources[{}]: source.subgraph and mapping.entityHandlers are only supported by subgraph data sources",
+        _0
+    )]
+    SubgraphFieldsNotSupported(String),
+    #[fail(
+        display = "dataSources[{}].source.subgraph: required for subgraph data sources",
+        _0
+    )]
+    SubgraphSourceRequired(String),
+    #[fail(
+        display = "dataSources[{}].source.subgraph: a subgraph cannot consume its own entities",
+        _0
+    )]
+    SubgraphSourceIsSelf(String),
+    #[fail(
+        display = "dataSources[{}].mapping.entityHandlers: required for subgraph data sources",
+        _0
+    )]
+    EntityHandlersRequired(String),
+    #[fail(
+        display = "dataSources[{}]: {} data sources cannot have a source address or ABI, or event, call and block handlers",
+        _0, _1
+    )]
+    DataSourceHasContract(String, String),
+    #[fail(
+        display = "dataSources[{}].mapping.entities: entity {} is also written by Ethereum data sources, file/ipfs and subgraph data sources need entity types of their own",
+        _0, _1
+    )]
+    EntityNotIsolated(String, String),
+    #[fail(display = "schema: {}", _0)]
+    SchemaInvalid(String),
+    #[fail(display = "{}.mapping: {}", _0, _1)]
+    MappingInvalid(String, String),
+    #[fail(display = "dataSources.network: {}", _0)]
+    NetworkNotSupported(String),
+}
+
+/// All problems found while validating a subgraph manifest, so that they
+/// can be reported at once.
+#[derive(Debug)]
+pub struct SubgraphManifestValidationErrors(pub Vec<SubgraphManifestValidationError>);
+
+impl fmt::Display for SubgraphManifestValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let errors = self
+            .0
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join("; ");
+        write!(f, "{}", errors)
+    }
+}
+
+/// All files of a subgraph manifest that could not be resolved, each
+/// prefixed with where in the manifest it is referenced, so that they can
+/// be reported at once.
+#[derive(Debug)]
+pub struct ManifestResolveErrors(pub Vec<String>);
+
+impl fmt::Display for ManifestResolveErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.join("; "))
+    }
+}
+
+impl failure::Fail for ManifestResolveErrors {}
+
+impl ManifestResolveErrors {
+    /// Prefixes the problems of `e` with `location`.
+    fn at(location: &str, e: Error) -> Vec<String> {
+        match e.downcast::<ManifestResolveErrors>() {
+            Ok(errors) => errors
+                .0
+                .into_iter()
+                .map(|error| format!("{}.{}", location, error))
+                .collect(),
+            Err(e) => vec![format!("{}: {}", location, e)],
+        }
+    }
+}
+
+/// Resolves all `futures`, collecting the problems of every one that fails
+/// instead of stopping at the first.
+fn resolve_all<F>(
+    futures: impl IntoIterator<Item = (String, F)>,
+) -> impl Future<Item = (Vec<F::Item>, Vec<String>), Error = Error>
+where
+    F: Future<Error = Error>,
+{
+    stream::futures_ordered(
+        futures.into_iter().map(|(location, future)| {
+            future.then(move |result| Ok::<_, Error>((location, result)))
+        }),
+    )
+    .fold(
+        (vec![], vec![]),
+        |(mut items, mut errors), (location, result)| {
+            match result {
+                Ok(item) => items.push(item),
+                Err(e) => errors.extend(ManifestResolveErrors::at(&location, e)),
+            }
+            Ok::<_, Error>((items, errors))
+        },
+    )
 }
 
 #[derive(Fail, Debug)]
@@ -302,6 +482,16 @@ pub enum SubgraphManifestResolveError {
     InvalidFormat,
     #[fail(display = "resolve error: {}", _0)]
     ResolveError(failure::Error),
+    #[fail(
+        display = "specVersion: {} is not supported, supported versions are: {}",
+        _0, _1
+    )]
+    SpecVersionNotSupported(String, String),
+    #[fail(
+        display = "features: {} not supported by this Graph Node, supported features are: {}",
+        _0, _1
+    )]
+    FeaturesNotSupported(String, String),
 }
 
 impl From<serde_yaml::Error> for SubgraphManifestResolveError {
@@ -310,6 +500,16 @@ impl From<serde_yaml::Error> for SubgraphManifestResolveError {
     }
 }
 
+/// The error that made a subgraph deployment fail, along with the block
+/// and the mapping handler it happened in, if they are known.
+#[derive(Clone, Debug, Fail)]
+#[fail(display = "{}", message)]
+pub struct SubgraphFatalError {
+    pub message: String,
+    pub block_ptr: Option<EthereumBlockPointer>,
+    pub handler: Option<String>,
+}
+
 /// IPLD link.
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
 pub struct Link {
@@ -338,7 +538,20 @@ impl SchemaData {
 pub struct Source {
     #[serde(default, deserialize_with = "deserialize_address")]
     pub address: Option<Address>,
+    /// Name of the contract ABI. Empty for `file/ipfs` and `subgraph` data
+    /// sources.
+    #[serde(default)]
     pub abi: String,
+    /// The IPFS file of a `file/ipfs` data source.
+    #[serde(default)]
+    pub file: Option<Link>,
+    /// The deployment whose entity changes a `subgraph` data source consumes.
+    #[serde(default)]
+    pub subgraph: Option<SubgraphDeploymentId>,
+    /// Block from which on the data source is processed; earlier blocks are
+    /// skipped. Defaults to the genesis block.
+    #[serde(default, rename = "startBlock")]
+    pub start_block: u64,
 }
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
@@ -382,6 +595,27 @@ pub enum BlockHandlerFilter {
     // Call filter will trigger on all blocks where the data source contract
     // address has been called
     Call,
+    // Polling filter will trigger on every block whose number is a multiple
+    // of `every`
+    Polling { every: u64 },
+}
+
+#[test]
+fn test_block_handler_filter_deserialization() {
+    let handler: MappingBlockHandler = serde_yaml::from_str("handler: handleBlock").unwrap();
+    assert_eq!(handler.filter, None);
+
+    let handler: MappingBlockHandler =
+        serde_yaml::from_str("handler: handleBlock\nfilter:\n  kind: call").unwrap();
+    assert_eq!(handler.filter, Some(BlockHandlerFilter::Call));
+
+    let handler: MappingBlockHandler =
+        serde_yaml::from_str("handler: handleBlock\nfilter:\n  kind: polling\n  every: 10")
+            .unwrap();
+    assert_eq!(
+        handler.filter,
+        Some(BlockHandlerFilter::Polling { every: 10 })
+    );
 }
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
@@ -394,6 +628,15 @@ pub struct MappingCallHandler {
 pub struct MappingEventHandler {
     pub event: String,
     pub handler: String,
+    /// Whether to pass the receipt of the transaction to the handler.
+    #[serde(default)]
+    pub receipt: bool,
+}
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub struct MappingEntityHandler {
+    pub entity: String,
+    pub handler: String,
 }
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
@@ -403,10 +646,16 @@ pub struct UnresolvedMapping {
     pub api_version: String,
     pub language: String,
     pub entities: Vec<String>,
+    /// Entity types that the mapping loads but does not write.
+    #[serde(default)]
+    pub reads: Vec<String>,
+    #[serde(default)]
     pub abis: Vec<UnresolvedMappingABI>,
     pub block_handlers: Option<Vec<MappingBlockHandler>>,
     pub call_handlers: Option<Vec<MappingCallHandler>>,
     pub event_handlers: Option<Vec<MappingEventHandler>>,
+    pub entity_handlers: Option<Vec<MappingEntityHandler>>,
+    pub file_handler: Option<String>,
     pub file: Link,
 }
 
@@ -417,10 +666,13 @@ pub struct Mapping {
     pub api_version: String,
     pub language: String,
     pub entities: Vec<String>,
+    pub reads: Vec<String>,
     pub abis: Vec<MappingABI>,
     pub block_handlers: Vec<MappingBlockHandler>,
     pub call_handlers: Vec<MappingCallHandler>,
     pub event_handlers: Vec<MappingEventHandler>,
+    pub entity_handlers: Vec<MappingEntityHandler>,
+    pub file_handler: Option<String>,
     pub runtime: Module,
     pub link: Link,
 }
@@ -435,51 +687,126 @@ impl UnresolvedMapping {
             api_version,
             language,
             entities,
+            reads,
             abis,
             block_handlers,
             call_handlers,
             event_handlers,
+            entity_handlers,
+            file_handler,
             file: link,
         } = self;
 
         // resolve each abi
-        stream::futures_ordered(
-            abis.into_iter()
-                .map(|unresolved_abi| unresolved_abi.resolve(resolver)),
-        )
-        .collect()
+        resolve_all(abis.into_iter().map(|unresolved_abi| {
+            (
+                format!("abis[{}]", unresolved_abi.name),
+                unresolved_abi.resolve(resolver),
+            )
+        }))
         .join(
             resolver
                 .cat(&link)
-                .and_then(|module_bytes| Ok(parity_wasm::deserialize_buffer(&module_bytes)?)),
+                .and_then(|module_bytes| Ok(parity_wasm::deserialize_buffer(&module_bytes)?))
+                .then(Ok),
         )
-        .map(|(abis, runtime)| Mapping {
-            kind,
-            api_version,
-            language,
-            entities,
-            abis,
-            block_handlers: block_handlers.unwrap_or(Vec::new()),
-            call_handlers: call_handlers.unwrap_or(Vec::new()),
-            event_handlers: event_handlers.unwrap_or(Vec::new()),
-            runtime,
-            link,
+        .and_then(|((abis, mut errors), runtime)| {
+            let runtime = runtime.map_err(|e| errors.push(format!("file: {}", e)));
+            match runtime {
+                Ok(runtime) if errors.is_empty() => Ok(Mapping {
+                    kind,
+                    api_version,
+                    language,
+                    entities,
+                    reads,
+                    abis,
+                    block_handlers: block_handlers.unwrap_or(Vec::new()),
+                    call_handlers: call_handlers.unwrap_or(Vec::new()),
+                    event_handlers: event_handlers.unwrap_or(Vec::new()),
+                    entity_handlers: entity_handlers.unwrap_or(Vec::new()),
+                    file_handler,
+                    runtime,
+                    link,
+                }),
+                _ => Err(ManifestResolveErrors(errors).into()),
+            }
         })
     }
 }
 
-#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+/// Arbitrary key/value pairs attached to a data source, made available to
+/// its mappings through `dataSource.context()`.
+pub type DataSourceContext = Entity;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct BaseDataSource<M> {
     pub kind: String,
     pub network: Option<String>,
     pub name: String,
     pub source: Source,
     pub mapping: M,
+    pub context: Option<DataSourceContext>,
 }
 
 pub type UnresolvedDataSource = BaseDataSource<UnresolvedMapping>;
 pub type DataSource = BaseDataSource<Mapping>;
 
+/// Kind of data sources that are triggered by an IPFS file instead of by
+/// Ethereum blocks.
+pub const FILE_IPFS_KIND: &str = "file/ipfs";
+
+/// Kind of data sources that are triggered by the entity changes of another
+/// subgraph deployment instead of by Ethereum blocks.
+pub const SUBGRAPH_KIND: &str = "subgraph";
+
+impl<M> BaseDataSource<M> {
+    /// Returns `true` for `file/ipfs` data sources. Their handler is invoked
+    /// once the IPFS file in `source.file` is available, independently of
+    /// block processing.
+    pub fn is_file(&self) -> bool {
+        self.kind == FILE_IPFS_KIND
+    }
+
+    /// Returns `true` for `subgraph` data sources. Their handlers are invoked
+    /// for the entities of the deployment in `source.subgraph`.
+    pub fn is_subgraph(&self) -> bool {
+        self.kind == SUBGRAPH_KIND
+    }
+
+    /// Returns `true` for data sources that are backed by a contract and
+    /// triggered by Ethereum blocks.
+    pub fn has_contract(&self) -> bool {
+        !self.is_file() && !self.is_subgraph()
+    }
+}
+
+impl DataSource {
+    /// Creates a data source from `template` for the contract at `address`
+    /// that processes blocks from `start_block` on. Its mappings read
+    /// `context` through `dataSource.context()`.
+    pub fn from_template(
+        template: &DataSourceTemplate,
+        address: Address,
+        context: Option<DataSourceContext>,
+        start_block: u64,
+    ) -> Self {
+        DataSource {
+            kind: template.kind.clone(),
+            network: template.network.clone(),
+            name: template.name.clone(),
+            source: Source {
+                address: Some(address),
+                abi: template.source.abi.clone(),
+                file: None,
+                subgraph: None,
+                start_block,
+            },
+            mapping: template.mapping.clone(),
+            context,
+        }
+    }
+}
+
 impl UnresolvedDataSource {
     pub fn resolve(
         self,
@@ -491,6 +818,7 @@ impl UnresolvedDataSource {
             name,
             source,
             mapping,
+            context,
         } = self;
         mapping.resolve(resolver).map(|mapping| DataSource {
             kind,
@@ -498,31 +826,97 @@ impl UnresolvedDataSource {
             name,
             source,
             mapping,
+            context,
         })
     }
 }
 
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub struct TemplateSource {
+    pub abi: String,
+}
+
+/// A data source template: a data source without a fixed contract address,
+/// from which data sources can be created while the subgraph is running.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct BaseDataSourceTemplate<M> {
+    pub kind: String,
+    pub network: Option<String>,
+    pub name: String,
+    pub source: TemplateSource,
+    pub mapping: M,
+}
+
+pub type UnresolvedDataSourceTemplate = BaseDataSourceTemplate<UnresolvedMapping>;
+pub type DataSourceTemplate = BaseDataSourceTemplate<Mapping>;
+
+impl UnresolvedDataSourceTemplate {
+    pub fn resolve(
+        self,
+        resolver: &impl LinkResolver,
+    ) -> impl Future<Item = DataSourceTemplate, Error = failure::Error> {
+        let UnresolvedDataSourceTemplate {
+            kind,
+            network,
+            name,
+            source,
+            mapping,
+        } = self;
+        mapping.resolve(resolver).map(|mapping| DataSourceTemplate {
+            kind,
+            network,
+            name,
+            source,
+            mapping,
+        })
+    }
+}
+
+/// Manifest spec versions this node can process.
+pub const SUPPORTED_SPEC_VERSIONS: &[&str] = &["0.0.1"];
+
+/// The features that subgraphs may declare in their manifest and that this
+/// node supports.
+pub const SUPPORTED_FEATURES: &[&str] = &[
+    "blockHandlers",
+    "callHandlers",
+    "externalHosts",
+    "fileDataSources",
+    "subgraphDataSources",
+];
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct BaseSubgraphManifest<S, D> {
+pub struct BaseSubgraphManifest<S, D, T> {
     pub id: SubgraphDeploymentId,
     pub location: String,
     pub spec_version: String,
     pub description: Option<String>,
     pub repository: Option<String>,
+    pub author: Option<String>,
     pub schema: S,
     pub data_sources: Vec<D>,
+    #[serde(default)]
+    pub templates: Vec<T>,
+    /// Hosts that the mappings may send HTTP requests to.
+    #[serde(default)]
+    pub external_hosts: Vec<String>,
+    /// The features the subgraph needs, out of `SUPPORTED_FEATURES`. Nodes
+    /// reject subgraphs that need features they don't support.
+    #[serde(default)]
+    pub features: Vec<String>,
 }
 
 /// Consider two subgraphs to be equal if they come from the same IPLD link.
-impl<S, D> PartialEq for BaseSubgraphManifest<S, D> {
+impl<S, D, T> PartialEq for BaseSubgraphManifest<S, D, T> {
     fn eq(&self, other: &Self) -> bool {
         self.location == other.location
     }
 }
 
-pub type UnresolvedSubgraphManifest = BaseSubgraphManifest<SchemaData, UnresolvedDataSource>;
-pub type SubgraphManifest = BaseSubgraphManifest<Schema, DataSource>;
+pub type UnresolvedSubgraphManifest =
+    BaseSubgraphManifest<SchemaData, UnresolvedDataSource, UnresolvedDataSourceTemplate>;
+pub type SubgraphManifest = BaseSubgraphManifest<Schema, DataSource, DataSourceTemplate>;
 
 impl SubgraphManifest {
     /// Entry point for resolving a subgraph definition.
@@ -560,6 +954,10 @@ impl SubgraphManifest {
                 }
                 // Parse the YAML data into an UnresolvedSubgraphManifest
                 let unresolved: UnresolvedSubgraphManifest = serde_yaml::from_value(raw)?;
+
+                // Fail before resolving the files of a subgraph this node
+                // can't index
+                unresolved.check_supported()?;
                 Ok(unresolved)
             })
             .and_then(move |unresolved| {
@@ -568,9 +966,126 @@ impl SubgraphManifest {
                     .map_err(SubgraphManifestResolveError::ResolveError)
             })
     }
+
+    /// Returns the Ethereum network the subgraph indexes, as declared by its
+    /// data sources. Validation makes sure they don't declare different
+    /// networks.
+    pub fn network_name(&self) -> Option<String> {
+        self.data_sources
+            .iter()
+            .filter_map(|data_source| data_source.network.clone())
+            .next()
+    }
+
+    /// Adds the data sources that the mappings of the subgraph created from
+    /// its templates, in the order in which they were created. Each of them
+    /// processes the blocks after the block it was created in.
+    pub fn add_dynamic_data_sources(
+        &mut self,
+        dynamic_data_sources: Vec<DynamicEthereumContractDataSourceEntity>,
+    ) -> Result<(), Error> {
+        for dynamic_data_source in dynamic_data_sources {
+            let template = self
+                .templates
+                .iter()
+                .find(|template| template.name == dynamic_data_source.template())
+                .ok_or_else(|| {
+                    format_err!(
+                        "data source template `{}` does not exist",
+                        dynamic_data_source.template()
+                    )
+                })?;
+            self.data_sources.push(DataSource::from_template(
+                template,
+                dynamic_data_source.address(),
+                dynamic_data_source.context().cloned(),
+                dynamic_data_source.block_ptr().number + 1,
+            ));
+        }
+        Ok(())
+    }
+
+    /// How much of each block the handlers of the data sources and
+    /// templates of the subgraph need; subgraphs with only block handlers
+    /// just need the block header.
+    pub fn block_detail(&self) -> BlockDetail {
+        let mappings = self
+            .data_sources
+            .iter()
+            .map(|data_source| &data_source.mapping)
+            .chain(self.templates.iter().map(|template| &template.mapping));
+        mappings
+            .map(|mapping| {
+                if mapping.event_handlers.iter().any(|handler| handler.receipt) {
+                    BlockDetail::Receipts
+                } else if !mapping.event_handlers.is_empty() || !mapping.call_handlers.is_empty() {
+                    BlockDetail::Transactions
+                } else {
+                    BlockDetail::Header
+                }
+            })
+            .max()
+            .unwrap_or(BlockDetail::Header)
+    }
+
+    /// Returns the features that the subgraph uses, whether it declares
+    /// them in its manifest or not.
+    pub fn used_features(&self) -> Vec<&'static str> {
+        let mappings = self
+            .data_sources
+            .iter()
+            .map(|data_source| &data_source.mapping)
+            .chain(self.templates.iter().map(|template| &template.mapping))
+            .collect::<Vec<_>>();
+        SUPPORTED_FEATURES
+            .iter()
+            .cloned()
+            .filter(|feature| match *feature {
+                "blockHandlers" => mappings.iter().any(|m| !m.block_handlers.is_empty()),
+                "callHandlers" => mappings.iter().any(|m| !m.call_handlers.is_empty()),
+                "externalHosts" => !self.external_hosts.is_empty(),
+                "fileDataSources" => self.data_sources.iter().any(|ds| ds.is_file()),
+                "subgraphDataSources" => self.data_sources.iter().any(|ds| ds.is_subgraph()),
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// Returns the features that the subgraph uses without declaring them.
+    pub fn undeclared_features(&self) -> Vec<&'static str> {
+        self.used_features()
+            .into_iter()
+            .filter(|feature| !self.features.iter().any(|declared| declared == feature))
+            .collect()
+    }
 }
 
 impl UnresolvedSubgraphManifest {
+    /// Checks that this node supports the spec version of the manifest and
+    /// every feature it declares.
+    pub fn check_supported(&self) -> Result<(), SubgraphManifestResolveError> {
+        if !SUPPORTED_SPEC_VERSIONS.contains(&self.spec_version.as_str()) {
+            return Err(SubgraphManifestResolveError::SpecVersionNotSupported(
+                self.spec_version.clone(),
+                SUPPORTED_SPEC_VERSIONS.join(", "),
+            ));
+        }
+
+        let unsupported = self
+            .features
+            .iter()
+            .filter(|feature| !SUPPORTED_FEATURES.contains(&feature.as_str()))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if !unsupported.is_empty() {
+            return Err(SubgraphManifestResolveError::FeaturesNotSupported(
+                unsupported.join(", "),
+                SUPPORTED_FEATURES.join(", "),
+            ));
+        }
+        Ok(())
+    }
+
     pub fn resolve(
         self,
         resolver: &impl LinkResolver,
@@ -581,26 +1096,162 @@ impl UnresolvedSubgraphManifest {
             spec_version,
             description,
             repository,
+            author,
             schema,
             data_sources,
+            templates,
+            external_hosts,
+            features,
         } = self;
 
-        // resolve each data set
-        stream::futures_ordered(
-            data_sources
-                .into_iter()
-                .map(|data_set| data_set.resolve(resolver)),
+        // resolve each data set, reporting the problems of all of them
+        resolve_all(data_sources.into_iter().map(|data_set| {
+            (
+                format!("dataSources[{}].mapping", data_set.name),
+                data_set.resolve(resolver),
+            )
+        }))
+        .join(resolve_all(templates.into_iter().map(|template| {
+            (
+                format!("templates[{}].mapping", template.name),
+                template.resolve(resolver),
+            )
+        })))
+        .join(schema.resolve(id.clone(), resolver).then(Ok))
+        .and_then(
+            |(((data_sources, mut errors), (templates, template_errors)), schema)| {
+                errors.extend(template_errors);
+                let schema = schema.map_err(|e| errors.push(format!("schema: {}", e)));
+                match schema {
+                    Ok(schema) if errors.is_empty() => Ok(SubgraphManifest {
+                        id,
+                        location,
+                        spec_version,
+                        description,
+                        repository,
+                        author,
+                        schema,
+                        data_sources,
+                        templates,
+                        external_hosts,
+                        features,
+                    }),
+                    _ => Err(ManifestResolveErrors(errors).into()),
+                }
+            },
         )
-        .collect()
-        .join(schema.resolve(id.clone(), resolver))
-        .map(|(data_sources, schema)| SubgraphManifest {
-            id,
-            location,
-            spec_version,
-            description,
-            repository,
-            schema,
-            data_sources,
-        })
     }
 }
+
+#[test]
+fn unsupported_manifest_features_are_rejected() {
+    let manifest = |features: &str| -> UnresolvedSubgraphManifest {
+        serde_yaml::from_str(&format!(
+            "id: QmFeatures\nlocation: /ipfs/QmFeatures\nspecVersion: 0.0.1\n\
+             schema:\n  file:\n    /: /ipfs/QmSchema\ndataSources: []\nfeatures: {}\n",
+            features
+        ))
+        .unwrap()
+    };
+
+    assert!(manifest("[]").check_supported().is_ok());
+    assert!(manifest("[callHandlers, fileDataSources]")
+        .check_supported()
+        .is_ok());
+    assert_eq!(
+        manifest("[callHandlers, fullTextSearch, grafting]")
+            .check_supported()
+            .unwrap_err()
+            .to_string(),
+        "features: fullTextSearch, grafting not supported by this Graph Node, supported \
+         features are: blockHandlers, callHandlers, externalHosts, fileDataSources, \
+         subgraphDataSources"
+    );
+}
+
+#[test]
+fn data_sources_created_from_templates_keep_their_context() {
+    use web3::types::H256;
+
+    let id = SubgraphDeploymentId::new("QmContext").unwrap();
+    let mapping = Mapping {
+        kind: "ethereum/events".to_owned(),
+        api_version: "0.0.3".to_owned(),
+        language: "wasm/assemblyscript".to_owned(),
+        entities: vec![],
+        reads: vec![],
+        abis: vec![],
+        block_handlers: vec![],
+        call_handlers: vec![],
+        event_handlers: vec![],
+        entity_handlers: vec![],
+        file_handler: None,
+        runtime: Module::default(),
+        link: Link {
+            link: "/ipfs/QmMapping".to_owned(),
+        },
+    };
+    let mut manifest = SubgraphManifest {
+        id: id.clone(),
+        location: String::new(),
+        spec_version: "0.0.1".to_owned(),
+        description: None,
+        repository: None,
+        author: None,
+        schema: Schema::parse("type User @entity { id: ID! }", id.clone()).unwrap(),
+        data_sources: vec![],
+        templates: vec![DataSourceTemplate {
+            kind: "ethereum/contract".to_owned(),
+            network: Some("mainnet".to_owned()),
+            name: "Exchange".to_owned(),
+            source: TemplateSource {
+                abi: "Exchange".to_owned(),
+            },
+            mapping,
+        }],
+        external_hosts: vec![],
+        features: vec![],
+    };
+
+    // Data sources are loaded from their entities when the subgraph starts
+    let mut context = Entity::new();
+    context.set("token", "0x0123");
+    let created = vec![(Some(context.clone()), 3), (None, 5)]
+        .into_iter()
+        .map(|(context, block)| {
+            let data_source = DynamicEthereumContractDataSourceEntity::new(
+                id.clone(),
+                "Exchange".to_owned(),
+                Address::from(block),
+                context,
+                EthereumBlockPointer::from((H256::from(block), block)),
+            );
+            match data_source.write_operations().unwrap().pop() {
+                Some(crate::data::store::EntityOperation::Set { data, .. }) => {
+                    DynamicEthereumContractDataSourceEntity::from_entity(&data).unwrap()
+                }
+                op => panic!("expected the data source to be set, got {:?}", op),
+            }
+        })
+        .collect();
+    manifest.add_dynamic_data_sources(created).unwrap();
+
+    let data_sources = manifest
+        .data_sources
+        .iter()
+        .map(|data_source| {
+            (
+                data_source.source.address,
+                data_source.source.start_block,
+                data_source.context.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        data_sources,
+        vec![
+            (Some(Address::from(3)), 4, Some(context)),
+            (Some(Address::from(5)), 6, None),
+        ]
+    );
+}
@@ -24,8 +24,9 @@ use crate::components::ethereum::EthereumBlockPointer;
 use crate::components::store::{
     AttributeIndexDefinition, EntityFilter, EntityKey, EntityOperation, EntityQuery, EntityRange,
 };
+use crate::data::graphql::validation::index_directive_fields;
 use crate::data::store::{Entity, NodeId, SubgraphEntityPair, Value, ValueType};
-use crate::data::subgraph::{SubgraphManifest, SubgraphName};
+use crate::data::subgraph::{SubgraphFatalError, SubgraphManifest, SubgraphName};
 
 /// ID of the subgraph of subgraphs.
 lazy_static! {
@@ -284,6 +285,33 @@ impl SubgraphDeploymentEntity {
         }]
     }
 
+    /// Marks the deployment as failed and records the error that made it
+    /// fail. The error is kept when the deployment is restarted, so that it
+    /// can still be looked up.
+    pub fn fail_operations(
+        id: &SubgraphDeploymentId,
+        error: &SubgraphFatalError,
+    ) -> Vec<EntityOperation> {
+        let mut entity = Entity::new();
+        entity.set("failed", true);
+        entity.set("lastErrorMessage", error.message.clone());
+        entity.set(
+            "lastErrorBlockHash",
+            error.block_ptr.map(|block_ptr| block_ptr.hash_hex()),
+        );
+        entity.set(
+            "lastErrorBlockNumber",
+            error.block_ptr.map(|block_ptr| block_ptr.number),
+        );
+        entity.set("lastErrorHandler", error.handler.clone());
+
+        vec![EntityOperation::Update {
+            key: Self::key(id.clone()),
+            data: entity,
+            guard: None,
+        }]
+    }
+
     pub fn update_synced_operations(
         id: &SubgraphDeploymentId,
         synced: bool,
@@ -297,6 +325,34 @@ impl SubgraphDeploymentEntity {
             guard: None,
         }]
     }
+
+    pub fn update_queries_disabled_operations(
+        id: &SubgraphDeploymentId,
+        queries_disabled: bool,
+    ) -> Vec<EntityOperation> {
+        let mut entity = Entity::new();
+        entity.set("queriesDisabled", queries_disabled);
+
+        vec![EntityOperation::Update {
+            key: Self::key(id.clone()),
+            data: entity,
+            guard: None,
+        }]
+    }
+
+    pub fn update_indexing_disabled_operations(
+        id: &SubgraphDeploymentId,
+        indexing_disabled: bool,
+    ) -> Vec<EntityOperation> {
+        let mut entity = Entity::new();
+        entity.set("indexingDisabled", indexing_disabled);
+
+        vec![EntityOperation::Update {
+            key: Self::key(id.clone()),
+            data: entity,
+            guard: None,
+        }]
+    }
 }
 
 #[derive(Debug)]
@@ -324,13 +380,224 @@ impl SubgraphDeploymentAssignmentEntity {
     }
 }
 
+/// Marks the file of a file data source as handled by a deployment, so
+/// that the handler does not run again when the deployment restarts.
+#[derive(Debug)]
+pub struct SubgraphDeploymentFileEntity {
+    deployment: SubgraphDeploymentId,
+    link: String,
+}
+
+impl TypedEntity for SubgraphDeploymentFileEntity {
+    const TYPENAME: &'static str = "SubgraphDeploymentFile";
+    type IdType = String;
+}
+
+impl SubgraphDeploymentFileEntity {
+    pub fn new(deployment: SubgraphDeploymentId, link: String) -> Self {
+        Self { deployment, link }
+    }
+
+    pub fn id(deployment: &SubgraphDeploymentId, link: &str) -> String {
+        format!("{}-{}", deployment, link)
+    }
+
+    pub fn write_operations(self) -> Vec<EntityOperation> {
+        let id = Self::id(&self.deployment, &self.link);
+        let mut entity = Entity::new();
+        entity.set("id", id.clone());
+        entity.set("deployment", self.deployment.to_string());
+        entity.set("link", self.link);
+        vec![set_entity_operation(Self::TYPENAME, id, entity)]
+    }
+}
+
+/// A request to change subgraphs that was sent with an idempotency key,
+/// with its result as JSON, kept until `expires_at` (in seconds since the
+/// Unix epoch) so that the request is applied at most once per key.
+#[derive(Debug)]
+pub struct SubgraphIdempotencyKeyEntity {
+    request: String,
+    result: String,
+    expires_at: u64,
+}
+
+impl TypedEntity for SubgraphIdempotencyKeyEntity {
+    const TYPENAME: &'static str = "SubgraphIdempotencyKey";
+    type IdType = String;
+}
+
+impl SubgraphIdempotencyKeyEntity {
+    pub fn new(request: String, result: String, expires_at: u64) -> Self {
+        Self {
+            request,
+            result,
+            expires_at,
+        }
+    }
+
+    /// Records the request under `key`, unless a request that has not
+    /// expired at `now` already has that key (in which case the transaction
+    /// is aborted).
+    pub fn create_operations(self, key: &str, now: u64) -> Vec<EntityOperation> {
+        let mut entity = Entity::new();
+        entity.set("id", key);
+        entity.set("request", self.request);
+        entity.set("result", self.result);
+        entity.set("expiresAt", self.expires_at);
+        vec![
+            EntityOperation::AbortUnless {
+                description: "Idempotency key must not be in use to be recorded".to_owned(),
+                query: Self::query().filter(EntityFilter::And(vec![
+                    EntityFilter::new_equal("id", key),
+                    EntityFilter::GreaterThan("expiresAt".to_owned(), now.into()),
+                ])),
+                entity_ids: vec![],
+            },
+            set_entity_operation(Self::TYPENAME, key, entity),
+        ]
+    }
+}
+
+/// A data source that a mapping of a deployment created from one of the
+/// templates of the deployment while processing a block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DynamicEthereumContractDataSourceEntity {
+    deployment: SubgraphDeploymentId,
+    template: String,
+    address: Address,
+    context: Option<Entity>,
+    block_ptr: EthereumBlockPointer,
+}
+
+impl TypedEntity for DynamicEthereumContractDataSourceEntity {
+    const TYPENAME: &'static str = "DynamicEthereumContractDataSource";
+    type IdType = String;
+}
+
+impl DynamicEthereumContractDataSourceEntity {
+    pub fn new(
+        deployment: SubgraphDeploymentId,
+        template: String,
+        address: Address,
+        context: Option<Entity>,
+        block_ptr: EthereumBlockPointer,
+    ) -> Self {
+        Self {
+            deployment,
+            template,
+            address,
+            context,
+            block_ptr,
+        }
+    }
+
+    /// Creating the same data source twice in a block creates it once. The
+    /// ID starts with the manifest ID, so that the data source is removed
+    /// together with the deployment.
+    pub fn id(&self) -> String {
+        format!(
+            "{}-dynamic-data-source-{}-{}-{:x}",
+            SubgraphManifestEntity::id(&self.deployment),
+            self.block_ptr.number,
+            self.template,
+            self.address
+        )
+    }
+
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    pub fn context(&self) -> Option<&Entity> {
+        self.context.as_ref()
+    }
+
+    pub fn block_ptr(&self) -> EthereumBlockPointer {
+        self.block_ptr
+    }
+
+    /// The data sources that the mappings of `deployment` created.
+    pub fn query_for_deployment(deployment: &SubgraphDeploymentId) -> EntityQuery {
+        Self::query().filter(EntityFilter::new_equal(
+            "deployment",
+            deployment.to_string(),
+        ))
+    }
+
+    /// The data sources that the mappings of `deployment` created in the
+    /// block with the number `block_number`.
+    pub fn query_for_block(deployment: &SubgraphDeploymentId, block_number: u64) -> EntityQuery {
+        Self::query().filter(EntityFilter::And(vec![
+            EntityFilter::new_equal("deployment", deployment.to_string()),
+            EntityFilter::new_equal("ethereumBlockNumber", block_number),
+        ]))
+    }
+
+    pub fn write_operations(self) -> Result<Vec<EntityOperation>, Error> {
+        let id = self.id();
+        let mut entity = Entity::new();
+        entity.set("id", id.clone());
+        entity.set("deployment", self.deployment.to_string());
+        entity.set("template", self.template);
+        entity.set("address", format!("{:x}", self.address));
+        entity.set(
+            "context",
+            self.context
+                .map(|context| serde_json::to_string(&context))
+                .transpose()?,
+        );
+        entity.set("ethereumBlockHash", self.block_ptr.hash_hex());
+        entity.set("ethereumBlockNumber", self.block_ptr.number);
+        Ok(vec![set_entity_operation(Self::TYPENAME, id, entity)])
+    }
+
+    /// Reads a data source back from its entity.
+    pub fn from_entity(entity: &Entity) -> Result<Self, Error> {
+        let string = |name: &str| match entity.get(name) {
+            Some(Value::String(s)) => Ok(s.clone()),
+            _ => Err(format_err!(
+                "dynamic data source `{}` has no `{}`",
+                entity.get("id").map_or(String::new(), |id| id.to_string()),
+                name
+            )),
+        };
+        let deployment = SubgraphDeploymentId::new(string("deployment")?)
+            .map_err(|()| format_err!("dynamic data source has an invalid deployment ID"))?;
+        let context = match entity.get("context") {
+            Some(Value::String(context)) => Some(serde_json::from_str(context)?),
+            _ => None,
+        };
+        let number = match entity.get("ethereumBlockNumber") {
+            Some(Value::BigInt(number)) => number.to_u64(),
+            _ => return Err(format_err!("dynamic data source has no block number")),
+        };
+        Ok(Self {
+            deployment,
+            template: string("template")?,
+            address: Address::from_str(&string("address")?)?,
+            context,
+            block_ptr: EthereumBlockPointer {
+                hash: H256::from_str(&string("ethereumBlockHash")?)?,
+                number,
+            },
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct SubgraphManifestEntity {
     spec_version: String,
     description: Option<String>,
     repository: Option<String>,
+    author: Option<String>,
     schema: String,
     data_sources: Vec<EthereumContractDataSourceEntity>,
+    external_hosts: Vec<String>,
 }
 
 impl TypedEntity for SubgraphManifestEntity {
@@ -358,8 +625,16 @@ impl SubgraphManifestEntity {
         entity.set("specVersion", self.spec_version);
         entity.set("description", self.description);
         entity.set("repository", self.repository);
+        entity.set("author", self.author);
         entity.set("schema", self.schema);
         entity.set("dataSources", data_source_ids);
+        entity.set(
+            "externalHosts",
+            self.external_hosts
+                .into_iter()
+                .map(Value::from)
+                .collect::<Vec<_>>(),
+        );
         ops.push(set_entity_operation(Self::TYPENAME, id, entity));
 
         ops
@@ -372,8 +647,10 @@ impl<'a> From<&'a super::SubgraphManifest> for SubgraphManifestEntity {
             spec_version: manifest.spec_version.clone(),
             description: manifest.description.clone(),
             repository: manifest.repository.clone(),
+            author: manifest.author.clone(),
             schema: manifest.schema.document.clone().to_string(),
             data_sources: manifest.data_sources.iter().map(Into::into).collect(),
+            external_hosts: manifest.external_hosts.clone(),
         }
     }
 }
@@ -431,6 +708,7 @@ impl<'a> From<&'a super::DataSource> for EthereumContractDataSourceEntity {
 struct EthereumContractSourceEntity {
     address: Option<super::Address>,
     abi: String,
+    start_block: u64,
 }
 
 impl TypedEntity for EthereumContractSourceEntity {
@@ -444,6 +722,7 @@ impl EthereumContractSourceEntity {
         entity.set("id", id);
         entity.set("address", self.address);
         entity.set("abi", self.abi);
+        entity.set("startBlock", self.start_block);
         vec![set_entity_operation(Self::TYPENAME, id, entity)]
     }
 }
@@ -453,6 +732,7 @@ impl From<super::Source> for EthereumContractSourceEntity {
         Self {
             address: source.address,
             abi: source.abi,
+            start_block: source.start_block,
         }
     }
 }
@@ -486,6 +766,20 @@ impl EthereumContractMappingEntity {
             abi_ids.push(abi_id.into());
         }
 
+        let mut block_handler_ids: Vec<Value> = vec![];
+        for (i, block_handler) in self.block_handlers.into_iter().enumerate() {
+            let handler_id = format!("{}-block-handler-{}", id, i);
+            ops.extend(block_handler.write_operations(&handler_id));
+            block_handler_ids.push(handler_id.into());
+        }
+
+        let mut call_handler_ids: Vec<Value> = vec![];
+        for (i, call_handler) in self.call_handlers.into_iter().enumerate() {
+            let handler_id = format!("{}-call-handler-{}", id, i);
+            ops.extend(call_handler.write_operations(&handler_id));
+            call_handler_ids.push(handler_id.into());
+        }
+
         let mut event_handler_ids: Vec<Value> = vec![];
         for (i, event_handler) in self.event_handlers.into_iter().enumerate() {
             let handler_id = format!("{}-event-handler-{}", id, i);
@@ -507,6 +801,8 @@ impl EthereumContractMappingEntity {
                 .map(Value::from)
                 .collect::<Vec<Value>>(),
         );
+        entity.set("blockHandlers", block_handler_ids);
+        entity.set("callHandlers", call_handler_ids);
         entity.set("eventHandlers", event_handler_ids);
         ops.push(set_entity_operation(Self::TYPENAME, id, entity));
 
@@ -578,17 +874,75 @@ impl<'a> From<&'a super::MappingABI> for EthereumContractAbiEntity {
 #[derive(Debug)]
 struct EthereumBlockHandlerEntity {
     handler: String,
+    filter: Option<EthereumBlockFilterEntity>,
 }
 
 impl TypedEntity for EthereumBlockHandlerEntity {
-    const TYPENAME: &'static str = "EthereumBlockHandlerEntity";
+    const TYPENAME: &'static str = "EthereumBlockHandler";
     type IdType = String;
 }
 
+impl EthereumBlockHandlerEntity {
+    fn write_operations(self, id: &str) -> Vec<EntityOperation> {
+        let mut ops = vec![];
+
+        let filter_id = self.filter.map(|filter| {
+            let filter_id = format!("{}-filter", id);
+            ops.extend(filter.write_operations(&filter_id));
+            filter_id
+        });
+
+        let mut entity = Entity::new();
+        entity.set("id", id);
+        entity.set("handler", self.handler);
+        entity.set("filter", filter_id);
+        ops.push(set_entity_operation(Self::TYPENAME, id, entity));
+
+        ops
+    }
+}
+
 impl From<super::MappingBlockHandler> for EthereumBlockHandlerEntity {
     fn from(block_handler: super::MappingBlockHandler) -> Self {
         Self {
             handler: block_handler.handler,
+            filter: block_handler.filter.map(Into::into),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct EthereumBlockFilterEntity {
+    kind: String,
+    every: Option<u64>,
+}
+
+impl TypedEntity for EthereumBlockFilterEntity {
+    const TYPENAME: &'static str = "EthereumBlockFilter";
+    type IdType = String;
+}
+
+impl EthereumBlockFilterEntity {
+    fn write_operations(self, id: &str) -> Vec<EntityOperation> {
+        let mut entity = Entity::new();
+        entity.set("id", id);
+        entity.set("kind", self.kind);
+        entity.set("every", self.every);
+        vec![set_entity_operation(Self::TYPENAME, id, entity)]
+    }
+}
+
+impl From<super::BlockHandlerFilter> for EthereumBlockFilterEntity {
+    fn from(filter: super::BlockHandlerFilter) -> Self {
+        match filter {
+            super::BlockHandlerFilter::Call => Self {
+                kind: "call".to_owned(),
+                every: None,
+            },
+            super::BlockHandlerFilter::Polling { every } => Self {
+                kind: "polling".to_owned(),
+                every: Some(every),
+            },
         }
     }
 }
@@ -600,10 +954,20 @@ struct EthereumCallHandlerEntity {
 }
 
 impl TypedEntity for EthereumCallHandlerEntity {
-    const TYPENAME: &'static str = "EthereumCallHandlerEntity";
+    const TYPENAME: &'static str = "EthereumCallHandler";
     type IdType = String;
 }
 
+impl EthereumCallHandlerEntity {
+    fn write_operations(self, id: &str) -> Vec<EntityOperation> {
+        let mut entity = Entity::new();
+        entity.set("id", id);
+        entity.set("function", self.function);
+        entity.set("handler", self.handler);
+        vec![set_entity_operation(Self::TYPENAME, id, entity)]
+    }
+}
+
 impl From<super::MappingCallHandler> for EthereumCallHandlerEntity {
     fn from(call_handler: super::MappingCallHandler) -> Self {
         Self {
@@ -617,6 +981,7 @@ impl From<super::MappingCallHandler> for EthereumCallHandlerEntity {
 struct EthereumContractEventHandlerEntity {
     event: String,
     handler: String,
+    receipt: bool,
 }
 
 impl TypedEntity for EthereumContractEventHandlerEntity {
@@ -630,6 +995,7 @@ impl EthereumContractEventHandlerEntity {
         entity.set("id", id);
         entity.set("event", self.event);
         entity.set("handler", self.handler);
+        entity.set("receipt", self.receipt);
         vec![set_entity_operation(Self::TYPENAME, id, entity)]
     }
 }
@@ -639,6 +1005,7 @@ impl From<super::MappingEventHandler> for EthereumContractEventHandlerEntity {
         Self {
             event: event_handler.event,
             handler: event_handler.handler,
+            receipt: event_handler.receipt,
         }
     }
 }
@@ -685,7 +1052,11 @@ pub fn attribute_index_definitions(
                     .filter(|f| f.name != "id")
                     .enumerate()
                 {
-                    indexing_ops.push(AttributeIndexDefinition {
+                    let field_value_type = match inner_type_name(&entity_field.field_type) {
+                        Ok(value_type) => value_type,
+                        Err(_) => continue,
+                    };
+                    let index = AttributeIndexDefinition {
                         subgraph_id: subgraph_id.clone(),
                         index_name: format!(
                             "{}_{}_{}_idx",
@@ -693,12 +1064,61 @@ pub fn attribute_index_definitions(
                             entity_number,
                             attribute_number,
                         ),
-                        field_value_type: match inner_type_name(&entity_field.field_type) {
-                            Ok(value_type) => value_type,
-                            Err(_) => continue,
-                        },
+                        field_value_type,
                         attribute_name: entity_field.name,
                         entity_name: schema_object.name.clone(),
+                        case_insensitive: false,
+                        composite_attributes: vec![],
+                    };
+
+                    // Strings get a second index for `_starts_with_nocase`
+                    if index.field_value_type == ValueType::String {
+                        indexing_ops.push(AttributeIndexDefinition {
+                            index_name: format!(
+                                "{}_{}_{}_lower_idx",
+                                subgraph_id.clone(),
+                                entity_number,
+                                attribute_number,
+                            ),
+                            case_insensitive: true,
+                            ..index.clone()
+                        });
+                    }
+                    indexing_ops.push(index);
+                }
+
+                // Composite indexes declared with `@index(fields: [...])`
+                for (index_number, fields) in schema_object
+                    .directives
+                    .iter()
+                    .filter(|directive| directive.name == "index")
+                    .filter_map(index_directive_fields)
+                    .enumerate()
+                {
+                    let attributes = fields
+                        .into_iter()
+                        .filter_map(|name| {
+                            let field = schema_object.fields.iter().find(|f| f.name == name)?;
+                            Some((name.to_owned(), index_value_type(&field.field_type)))
+                        })
+                        .collect::<Vec<_>>();
+                    let (first, rest) = match attributes.split_first() {
+                        Some((first, rest)) if !rest.is_empty() => (first, rest),
+                        _ => continue,
+                    };
+                    indexing_ops.push(AttributeIndexDefinition {
+                        subgraph_id: subgraph_id.clone(),
+                        index_name: format!(
+                            "{}_{}_composite_{}_idx",
+                            subgraph_id.clone(),
+                            entity_number,
+                            index_number,
+                        ),
+                        field_value_type: first.1.clone(),
+                        attribute_name: first.0.clone(),
+                        entity_name: schema_object.name.clone(),
+                        case_insensitive: false,
+                        composite_attributes: rest.to_vec(),
                     });
                 }
             }
@@ -707,6 +1127,12 @@ pub fn attribute_index_definitions(
     indexing_ops
 }
 
+/// The value type of a field in a composite index. References to other
+/// entities and enum values are stored as strings.
+fn index_value_type(field_type: &Type) -> ValueType {
+    inner_type_name(field_type).unwrap_or(ValueType::String)
+}
+
 /// Returns the value type for a GraphQL field type.
 pub fn inner_type_name(field_type: &Type) -> Result<ValueType, Error> {
     match field_type {
@@ -715,3 +1141,33 @@ pub fn inner_type_name(field_type: &Type) -> Result<ValueType, Error> {
         Type::ListType(inner) => inner_type_name(inner).and(Ok(ValueType::List)),
     }
 }
+
+#[test]
+fn dynamic_data_sources_round_trip_through_their_entities() {
+    let mut context = Entity::new();
+    context.set("token", "0x0123");
+    context.set("decimals", 18);
+    let data_source = DynamicEthereumContractDataSourceEntity::new(
+        SubgraphDeploymentId::new("QmDynamic").unwrap(),
+        "Exchange".to_owned(),
+        Address::from(7),
+        Some(context),
+        EthereumBlockPointer {
+            hash: H256::from(1),
+            number: 3,
+        },
+    );
+
+    let ops = data_source.clone().write_operations().unwrap();
+    let entity = match &ops[..] {
+        [EntityOperation::Set { key, data }] => {
+            assert_eq!(key.entity_id, data_source.id());
+            data
+        }
+        _ => panic!("expected a single set operation, got {:?}", ops),
+    };
+    assert_eq!(
+        DynamicEthereumContractDataSourceEntity::from_entity(entity).unwrap(),
+        data_source
+    );
+}
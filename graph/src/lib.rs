@@ -12,12 +12,16 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_yaml;
+extern crate sha2;
 pub extern crate slog;
 #[macro_use]
 pub extern crate failure;
 extern crate ipfs_api;
 extern crate parity_wasm;
 extern crate rand;
+extern crate reqwest;
+extern crate rustracing;
+extern crate rustracing_jaeger;
 extern crate semver;
 pub extern crate serde_json;
 pub extern crate slog_async;
@@ -59,36 +63,50 @@ pub mod prelude {
     pub use tokio;
     pub use tokio::prelude::*;
 
+    pub use crate::components::entity_export::{
+        CommittedEntityChange, EntityChangeExporter, EntityChangeOutbox, EntityChangeSink,
+    };
     pub use crate::components::ethereum::{
-        BlockStream, BlockStreamBuilder, ChainHeadUpdate, ChainHeadUpdateListener, EthereumAdapter,
-        EthereumAdapterError, EthereumBlock, EthereumBlockData, EthereumBlockFilter,
-        EthereumBlockPointer, EthereumBlockTriggerType, EthereumBlockWithCalls,
-        EthereumBlockWithTriggers, EthereumCall, EthereumCallData, EthereumCallFilter,
-        EthereumEventData, EthereumLogFilter, EthereumNetworkIdentifier, EthereumTransactionData,
+        BlockDetail, BlockStream, BlockStreamBuilder, ChainHeadUpdate, ChainHeadUpdateListener,
+        EntityChangeFilter, EntityChangeTrigger, EthereumAdapter, EthereumAdapterError,
+        EthereumBlock, EthereumBlockData, EthereumBlockFilter, EthereumBlockPointer,
+        EthereumBlockTriggerType, EthereumBlockWithCalls, EthereumBlockWithTriggers, EthereumCall,
+        EthereumCallCache, EthereumCallData, EthereumCallFilter, EthereumEventData,
+        EthereumLogFilter, EthereumNetworkCapabilities, EthereumNetworkError,
+        EthereumNetworkIdentifier, EthereumNetworkMismatch, EthereumNetworks,
+        EthereumProviderCapabilities, EthereumTransactionData, EthereumTransactionReceiptData,
         EthereumTrigger,
     };
     pub use crate::components::graphql::{
         GraphQlRunner, QueryResultFuture, SubscriptionResultFuture,
     };
-    pub use crate::components::link_resolver::LinkResolver;
-    pub use crate::components::server::admin::JsonRpcServer;
+    pub use crate::components::link_resolver::{
+        FileLinkResolver, FileTooLarge, HttpLinkResolver, IpfsResolver, LinkResolver,
+    };
+    pub use crate::components::server::admin::{IpNetwork, JsonRpcServer};
+    pub use crate::components::server::index_node::IndexNodeServer;
     pub use crate::components::server::query::GraphQLServer;
     pub use crate::components::server::subscription::SubscriptionServer;
     pub use crate::components::store::{
-        AttributeIndexDefinition, ChainStore, EntityChange, EntityChangeOperation, EntityFilter,
-        EntityKey, EntityOperation, EntityOrder, EntityQuery, EntityRange, EventSource, Store,
-        StoreError, StoreEvent, StoreEventStream, StoreEventStreamBox, SubgraphDeploymentStore,
-        TransactionAbortError, SUBSCRIPTION_THROTTLE_INTERVAL,
+        AttributeIndexDefinition, BackgroundJob, BackgroundJobStatus, ChainStore, ChildLink,
+        EntityChange, EntityChangeOperation, EntityFilter, EntityKey, EntityOperation, EntityOrder,
+        EntityQuery, EntityRange, EntitySnapshot, EventSource, Store, StoreError, StoreEvent,
+        StoreEventStream, StoreEventStreamBox, SubgraphDeploymentStore, TransactionAbortError,
+        SUBSCRIPTION_THROTTLE_INTERVAL,
     };
     pub use crate::components::subgraph::{
-        RuntimeHost, RuntimeHostBuilder, SubgraphAssignmentProvider, SubgraphInstance,
-        SubgraphInstanceManager, SubgraphRegistrar, SubgraphVersionSwitchingMode,
+        DeploymentQuotas, ExternalHostError, ExternalHosts, HandlerConcurrency, HandlerError,
+        HandlerStats, HandlerStatsSummary, IdempotencyKey, QuotaAction, QuotaError, ResourceQuota,
+        ResourceQuotas, RunningHandler, RuntimeHost, RuntimeHostBuilder,
+        SubgraphAssignmentProvider, SubgraphInstance, SubgraphInstanceManager, SubgraphRegistrar,
+        SubgraphRegistrarOperation, SubgraphVersionSwitchingMode,
     };
     pub use crate::components::{EventConsumer, EventProducer};
 
     pub use crate::data::graphql::SerializableValue;
     pub use crate::data::query::{
-        Query, QueryError, QueryExecutionError, QueryResult, QueryVariables,
+        ConsistencyToken, Query, QueryError, QueryExecutionError, QueryResult, QueryStats,
+        QueryVariables, SlowQuery,
     };
     pub use crate::data::schema::Schema;
     pub use crate::data::store::scalar::{BigDecimal, BigInt, BigIntSign};
@@ -98,11 +116,13 @@ pub mod prelude {
     };
     pub use crate::data::subgraph::schema::{SubgraphDeploymentEntity, TypedEntity};
     pub use crate::data::subgraph::{
-        BlockHandlerFilter, CreateSubgraphResult, DataSource, Link, MappingABI,
-        MappingBlockHandler, MappingCallHandler, MappingEventHandler,
+        BlockHandlerFilter, CreateSubgraphResult, DataSource, DataSourceContext,
+        DataSourceTemplate, Link, ManifestResolveErrors, MappingABI, MappingBlockHandler,
+        MappingCallHandler, MappingEntityHandler, MappingEventHandler,
         SubgraphAssignmentProviderError, SubgraphAssignmentProviderEvent, SubgraphDeploymentId,
-        SubgraphManifest, SubgraphManifestResolveError, SubgraphManifestValidationError,
-        SubgraphName, SubgraphRegistrarError,
+        SubgraphFatalError, SubgraphManifest, SubgraphManifestResolveError,
+        SubgraphManifestValidationError, SubgraphManifestValidationErrors, SubgraphName,
+        SubgraphRegistrarError,
     };
     pub use crate::data::subscription::{
         QueryResultStream, Subscription, SubscriptionError, SubscriptionResult,
@@ -1,6 +1,6 @@
 use ethabi::{Contract, Event, Function};
 use tiny_keccak::Keccak;
-use web3::types::H256;
+use web3::types::{Address, H256};
 
 /// Hashes a string to a H256 hash.
 pub fn string_to_h256(s: &str) -> H256 {
@@ -16,6 +16,18 @@ pub fn string_to_h256(s: &str) -> H256 {
     H256::from_slice(&result)
 }
 
+/// Identifies a contract call by its contract address, call data and the hash
+/// of the block it is made at, e.g. to cache its result.
+pub fn contract_call_id(contract_address: &Address, call_data: &[u8], block_hash: &H256) -> H256 {
+    let mut result = [0u8; 32];
+    let mut sponge = Keccak::new_keccak256();
+    sponge.update(&contract_address[..]);
+    sponge.update(call_data);
+    sponge.update(&block_hash[..]);
+    sponge.finalize(&mut result);
+    H256::from(result)
+}
+
 /// Returns the contract event with the given signature, if it exists.
 pub fn contract_event_with_signature<'a>(
     contract: &'a Contract,
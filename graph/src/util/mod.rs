@@ -4,5 +4,8 @@ pub mod futures;
 /// Logging facilities based on `slog`.
 pub mod log;
 
+/// Tracing spans that are reported to Jaeger.
+pub mod tracing;
+
 /// Utils for working with ethereum data types
 pub mod ethereum;
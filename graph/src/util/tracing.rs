@@ -0,0 +1,141 @@
+use rustracing::sampler::{ProbabilisticSampler, Sampler};
+use rustracing::tag::Tag;
+use rustracing_jaeger::reporter::JaegerCompactReporter;
+use rustracing_jaeger::span::SpanContext;
+use rustracing_jaeger::Tracer;
+use slog::{warn, Logger};
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::thread;
+
+pub use rustracing_jaeger::Span;
+
+lazy_static! {
+    /// The tracer spans are started with, once tracing is enabled.
+    static ref TRACER: Mutex<Option<Tracer>> = Mutex::new(None);
+}
+
+thread_local! {
+    /// Each thread's copy of `TRACER`, so that starting a span doesn't
+    /// need a lock.
+    static LOCAL_TRACER: Option<Tracer> = TRACER.lock().unwrap().clone();
+
+    /// The spans that code on this thread is running in, innermost last.
+    static CURRENT_SPANS: RefCell<Vec<SpanContext>> = RefCell::new(vec![]);
+}
+
+/// Starts reporting a fraction `sample_rate` of all traces to the Jaeger
+/// agent at `agent_addr`. Until this is called, all spans are inactive and
+/// cost next to nothing. Must be called before any spans are started.
+pub fn init_jaeger(
+    logger: &Logger,
+    service_name: &str,
+    agent_addr: SocketAddr,
+    sample_rate: f64,
+) -> Result<(), failure::Error> {
+    let sampler = ProbabilisticSampler::new(sample_rate)
+        .map_err(|e| format_err!("invalid tracing sample rate {}: {}", sample_rate, e))?;
+    let (tracer, spans) = Tracer::new(sampler.boxed());
+    let mut reporter = JaegerCompactReporter::new(service_name)
+        .map_err(|e| format_err!("failed to create Jaeger reporter: {}", e))?;
+    reporter.set_agent_addr(agent_addr);
+
+    let logger = logger.clone();
+    thread::Builder::new()
+        .name("jaeger-reporter".to_owned())
+        .spawn(move || {
+            // Report spans in batches of whatever has finished since the
+            // last report
+            while let Ok(span) = spans.recv() {
+                let mut batch = vec![span];
+                batch.extend(spans.try_iter().take(99));
+                if let Err(e) = reporter.report(&batch) {
+                    warn!(logger, "Failed to report spans to Jaeger"; "error" => e.to_string());
+                }
+            }
+        })?;
+
+    *TRACER.lock().unwrap() = Some(tracer);
+    Ok(())
+}
+
+fn start(operation: &'static str, parent: Option<&SpanContext>) -> Span {
+    LOCAL_TRACER.with(|tracer| match (tracer, parent) {
+        (Some(tracer), Some(parent)) => tracer.span(operation).child_of(parent).start(),
+        (Some(tracer), None) => tracer.span(operation).start(),
+        (None, _) => Span::inactive(),
+    })
+}
+
+/// Starts a span that is not part of another trace.
+pub fn root_span(operation: &'static str) -> Span {
+    start(operation, None)
+}
+
+/// Starts a span that is part of the trace of `parent`.
+pub fn child_span(operation: &'static str, parent: &Span) -> Span {
+    start(operation, parent.context())
+}
+
+/// Starts a span that is part of the trace this thread is running in, see
+/// `enter`. Outside of a trace, the span is inactive, so that code that is
+/// called from many places only shows up in the traces it is part of.
+pub fn current_span(operation: &'static str) -> Span {
+    match CURRENT_SPANS.with(|spans| spans.borrow().last().cloned()) {
+        Some(parent) => start(operation, Some(&parent)),
+        None => Span::inactive(),
+    }
+}
+
+/// Leaves the innermost span of this thread when dropped, even if the code
+/// running in the span panics.
+struct Leave;
+
+impl Drop for Leave {
+    fn drop(&mut self) {
+        CURRENT_SPANS.with(|spans| spans.borrow_mut().pop());
+    }
+}
+
+fn run_in<T>(context: Option<SpanContext>, f: impl FnOnce() -> T) -> T {
+    match context {
+        Some(context) => {
+            CURRENT_SPANS.with(|spans| spans.borrow_mut().push(context));
+            let _leave = Leave;
+            f()
+        }
+        None => f(),
+    }
+}
+
+/// Runs `f` in `span`, so that the spans `f` starts with `current_span`
+/// are part of the same trace. This only covers code that runs on the
+/// current thread before `f` returns; futures need to be passed their
+/// spans explicitly.
+pub fn enter<T>(span: &Span, f: impl FnOnce() -> T) -> T {
+    run_in(span.context().cloned(), f)
+}
+
+/// Runs `f` in a new span that is part of the trace this thread is running
+/// in. The span ends when `f` returns.
+pub fn traced<T>(operation: &'static str, f: impl FnOnce(&mut Span) -> T) -> T {
+    let mut span = current_span(operation);
+    run_in(span.context().cloned(), || f(&mut span))
+}
+
+/// Adds a tag to `span`, if it is being recorded.
+pub fn tag(span: &mut Span, name: &'static str, value: impl ToString) {
+    span.set_tag(|| Tag::new(name, value.to_string()));
+}
+
+#[test]
+fn spans_are_inactive_without_a_tracer() {
+    let span = root_span("query");
+    assert!(span.context().is_none());
+    let depth = traced("execute", |span| {
+        assert!(span.context().is_none());
+        CURRENT_SPANS.with(|spans| spans.borrow().len())
+    });
+    assert_eq!(depth, 0);
+}
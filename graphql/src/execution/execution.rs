@@ -70,63 +70,116 @@ where
 }
 
 /// Executes the root selection set of a query.
+///
+/// The root fields are executed independently of each other. If only some
+/// of them fail, e.g. because the store timed out, the result contains the
+/// data of the others, `null` for the failed ones, and the errors of the
+/// failed fields with their path in the response. Since a failed field that
+/// can't be `null`, like a list of entities, makes the whole `data` `null`
+/// according to the GraphQL spec, the result only contains the errors if
+/// such a field fails or if all fields fail.
 pub fn execute_root_selection_set<'a, R>(
     ctx: &ExecutionContext<'a, R>,
     selection_set: &'a q::SelectionSet,
     initial_value: &Option<q::Value>,
-) -> Result<q::Value, Vec<QueryExecutionError>>
+) -> QueryResult
 where
     R: Resolver,
 {
     // Obtain the root Query type and fail if there isn't one
     let query_type = match sast::get_root_query_type(&ctx.schema.document) {
         Some(t) => t,
-        None => return Err(vec![QueryExecutionError::NoRootQueryObjectType]),
-    };
-
-    // Split the toplevel fields into introspection fields and
-    // 'normal' data fields
-    let mut data_set = q::SelectionSet {
-        span: selection_set.span.clone(),
-        items: Vec::new(),
-    };
-    let mut intro_set = q::SelectionSet {
-        span: selection_set.span.clone(),
-        items: Vec::new(),
+        None => return QueryResult::from(QueryExecutionError::NoRootQueryObjectType),
     };
 
+    // Split the toplevel fields into introspection fields and 'normal' data
+    // fields, with a selection set for each response key. Unknown fields
+    // make the whole query invalid
     let ictx = ctx.as_introspection_context();
     let introspection_query_type = sast::get_root_query_type(&ictx.schema.document).unwrap();
-    for (_, fields) in collect_fields(ctx.clone(), query_type, selection_set, None) {
+    let mut root_fields = vec![];
+    let mut unknown_fields = vec![];
+    for (response_key, fields) in collect_fields(ctx.clone(), query_type, selection_set, None) {
         let name = fields[0].name.clone();
-        let selections = fields.into_iter().map(|f| q::Selection::Field(f.clone()));
-        // See if this is an introspection or data field. We don't worry about
-        // nonexistant fields; those will cause an error later when we execute
-        // the data_set SelectionSet
-        if sast::get_field_type(introspection_query_type, &name).is_some() {
-            intro_set.items.extend(selections)
+        let introspection_field = sast::get_field_type(introspection_query_type, &name);
+        let is_introspection = introspection_field.is_some();
+        let non_null = match introspection_field.or_else(|| sast::get_field_type(query_type, &name))
+        {
+            Some(field) => match field.field_type {
+                s::Type::NonNullType(_) => true,
+                _ => false,
+            },
+            None => {
+                unknown_fields.push(QueryExecutionError::UnknownField(
+                    fields[0].position,
+                    query_type.name.clone(),
+                    name,
+                ));
+                continue;
+            }
+        };
+        let field_set = q::SelectionSet {
+            span: selection_set.span.clone(),
+            items: fields
+                .into_iter()
+                .map(|f| q::Selection::Field(f.clone()))
+                .collect(),
+        };
+        root_fields.push((
+            response_key.to_owned(),
+            is_introspection,
+            non_null,
+            field_set,
+        ));
+    }
+    if !unknown_fields.is_empty() {
+        return QueryResult::from(unknown_fields);
+    }
+    if root_fields.is_empty() {
+        return QueryResult::from(QueryExecutionError::EmptySelectionSet(
+            query_type.name.clone(),
+        ));
+    }
+
+    // Execute the root fields against the root query type or the
+    // introspection root query type
+    let mut data = BTreeMap::new();
+    let mut failed_fields = vec![];
+    for (response_key, is_introspection, non_null, field_set) in root_fields.iter() {
+        let values = if *is_introspection {
+            execute_selection_set_to_map(&ictx, field_set, introspection_query_type, initial_value)
         } else {
-            data_set.items.extend(selections)
+            execute_selection_set_to_map(ctx, field_set, query_type, initial_value)
+        };
+        match values {
+            Ok(values) => data.extend(values),
+            Err(errors) => failed_fields.push((response_key, *non_null, errors)),
         }
     }
 
-    // Execute the root selection set against the root query type
-    if data_set.items.is_empty() {
-        // Only introspection
-        execute_selection_set(&ictx, &intro_set, introspection_query_type, initial_value)
-    } else if intro_set.items.is_empty() {
-        // Only data
-        execute_selection_set(&ctx, &data_set, query_type, initial_value)
-    } else {
-        // Both introspection and data
-        let mut values = execute_selection_set_to_map(&ctx, &data_set, query_type, initial_value)?;
-        values.extend(execute_selection_set_to_map(
-            &ictx,
-            &intro_set,
-            introspection_query_type,
-            initial_value,
-        )?);
-        Ok(q::Value::Object(values))
+    if data.is_empty() || failed_fields.iter().any(|(_, non_null, _)| *non_null) {
+        return QueryResult::from(
+            failed_fields
+                .into_iter()
+                .flat_map(|(_, _, errors)| errors)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    let mut errors = vec![];
+    for (response_key, _, field_errors) in failed_fields {
+        data.insert(response_key.to_owned(), q::Value::Null);
+        errors.extend(field_errors.into_iter().map(|e| {
+            QueryError::FieldError(vec![response_key.to_owned()], Box::new(QueryError::from(e)))
+        }));
+    }
+    QueryResult {
+        data: Some(q::Value::Object(data)),
+        errors: if errors.is_empty() {
+            None
+        } else {
+            Some(errors)
+        },
     }
 }
 
@@ -439,7 +492,7 @@ where
             field_definition,
             t.into(),
             argument_values,
-            ctx.schema.types_for_interface(),
+            &ctx.schema,
         ),
 
         // Let the resolver decide how values in the resolved object value
@@ -468,7 +521,7 @@ where
             field_definition,
             i.into(),
             argument_values,
-            ctx.schema.types_for_interface(),
+            &ctx.schema,
         ),
 
         s::TypeDefinition::Union(_) => Err(QueryExecutionError::Unimplemented("unions".to_owned())),
@@ -517,7 +570,7 @@ where
                         field_definition,
                         t.into(),
                         argument_values,
-                        ctx.schema.types_for_interface(),
+                        &ctx.schema,
                     )
                     .map_err(|e| vec![e]),
 
@@ -549,7 +602,7 @@ where
                         field_definition,
                         t.into(),
                         argument_values,
-                        ctx.schema.types_for_interface(),
+                        &ctx.schema,
                     )
                     .map_err(|e| vec![e]),
 
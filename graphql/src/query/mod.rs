@@ -78,9 +78,9 @@ where
         }
 
         // Everything else (e.g. mutations) is unsupported
-        _ => Err(vec![QueryExecutionError::NotSupported(
+        _ => QueryResult::from(QueryExecutionError::NotSupported(
             "Only queries are supported".to_string(),
-        )]),
+        )),
     };
 
     debug!(
@@ -89,8 +89,5 @@ where
         "time" => format!("{}ms", start_time.elapsed().as_millis())
     );
 
-    match result {
-        Ok(value) => QueryResult::new(Some(value)),
-        Err(e) => QueryResult::from(e),
-    }
+    result
 }
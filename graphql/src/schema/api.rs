@@ -12,6 +12,15 @@ pub enum APISchemaError {
     TypeNotFound(String),
 }
 
+/// Name of the root query field that exposes subgraph metadata.
+pub const META_FIELD_NAME: &str = "_meta";
+
+/// Name of the object type returned by the `_meta` query field.
+pub const META_FIELD_TYPE: &str = "_Meta_";
+
+/// Name of the object type of the `block` field of `_Meta_`.
+pub const BLOCK_FIELD_TYPE: &str = "_Block_";
+
 /// Derives a full-fledged GraphQL API schema from an input schema.
 ///
 /// The input schema should only have type/enum/interface/union definitions
@@ -30,6 +39,7 @@ pub fn api_schema(input_schema: &Document) -> Result<Document, APISchemaError> {
     add_field_arguments(&mut schema, &input_schema)?;
     add_types_for_object_types(&mut schema, &object_types)?;
     add_types_for_interface_types(&mut schema, &interface_types)?;
+    add_meta_field_type(&mut schema)?;
     add_query_type(&mut schema, &object_types, &interface_types)?;
     add_subscription_type(&mut schema, &object_types, &interface_types)?;
     Ok(schema)
@@ -119,18 +129,19 @@ fn add_order_by_type(
 
     match ast::get_named_type(schema, &type_name) {
         None => {
+            let mut names: Vec<Name> = fields.iter().map(|field| field.name.clone()).collect();
+            names.extend(child_order_by_names(schema, fields));
             let typedef = TypeDefinition::Enum(EnumType {
                 position: Pos::default(),
                 description: None,
                 name: type_name,
                 directives: vec![],
-                values: fields
-                    .iter()
-                    .map(|field| &field.name)
+                values: names
+                    .into_iter()
                     .map(|name| EnumValue {
                         position: Pos::default(),
                         description: None,
-                        name: name.to_owned(),
+                        name,
                         directives: vec![],
                     })
                     .collect(),
@@ -143,6 +154,36 @@ fn add_order_by_type(
     Ok(())
 }
 
+/// Generates the `<field>__<child field>` names for ordering by the fields
+/// of the entities that single references among `fields` refer to. Lists
+/// and derived fields aren't stored with the child, so they are left out.
+fn child_order_by_names(schema: &Document, fields: &[Field]) -> Vec<Name> {
+    let is_stored_value = |field: &Field| {
+        !ast::is_list_or_non_null_list_field(field)
+            && !field
+                .directives
+                .iter()
+                .any(|directive| directive.name == "derivedFrom")
+    };
+
+    let mut names = vec![];
+    for field in fields.iter().filter(|field| is_stored_value(field)) {
+        let child_fields =
+            match ast::get_named_type(schema, &ast::get_field_name(&field.field_type)) {
+                Some(TypeDefinition::Object(t)) => &t.fields,
+                Some(TypeDefinition::Interface(t)) => &t.fields,
+                _ => continue,
+            };
+        names.extend(
+            child_fields
+                .iter()
+                .filter(|child_field| is_stored_value(child_field))
+                .map(|child_field| format!("{}__{}", field.name, child_field.name)),
+        );
+    }
+    names
+}
+
 /// Adds a `<type_name>_filter` enum type for the given fields to the schema.
 fn add_filter_type(
     schema: &mut Document,
@@ -157,7 +198,7 @@ fn add_filter_type(
             // Don't generate an input object with no fields, this makes the JS
             // graphql library, which graphiql uses, very confused and graphiql
             // is unable to load the schema. This happens for example with the
-            // definition `interface Foo { x: OtherEntity }`.
+            // definition `interface Foo { x: [[Int]] }`.
             if input_values.is_empty() {
                 return Ok(());
             }
@@ -206,6 +247,9 @@ fn field_filter_input_values(
             Ok(match named_type {
                 TypeDefinition::Scalar(ref t) => field_scalar_filter_input_values(schema, field, t),
                 TypeDefinition::Enum(ref t) => field_enum_filter_input_values(schema, field, t),
+                TypeDefinition::Object(_) | TypeDefinition::Interface(_) => {
+                    vec![child_filter_input_value(field, name)]
+                }
                 _ => vec![],
             })
         }
@@ -240,11 +284,17 @@ fn field_scalar_filter_input_values(
             "in",
             "not_in",
             "contains",
+            "contains_nocase",
             "not_contains",
+            "not_contains_nocase",
             "starts_with",
+            "starts_with_nocase",
             "not_starts_with",
+            "not_starts_with_nocase",
             "ends_with",
+            "ends_with_nocase",
             "not_ends_with",
+            "not_ends_with_nocase",
         ],
         _ => vec!["", "not"],
     }
@@ -266,30 +316,39 @@ fn field_enum_filter_input_values(
     field: &Field,
     field_type: &EnumType,
 ) -> Vec<InputValue> {
-    vec![
-        Some(input_value(
-            &field.name,
-            "",
-            Type::NamedType(field_type.name.to_owned()),
-        )),
-        Some(input_value(
-            &field.name,
-            "not",
-            Type::NamedType(field_type.name.to_owned()),
-        )),
-    ]
-    .into_iter()
-    .filter_map(|value_opt| value_opt)
-    .collect()
+    vec!["", "not", "in", "not_in"]
+        .into_iter()
+        .map(|filter_type| {
+            let field_type = Type::NamedType(field_type.name.to_owned());
+            let value_type = match filter_type {
+                "in" | "not_in" => {
+                    Type::ListType(Box::new(Type::NonNullType(Box::new(field_type))))
+                }
+                _ => field_type,
+            };
+            input_value(&field.name, filter_type, value_type)
+        })
+        .collect()
 }
 
 /// Generates `*_filter` input values for the given list field.
 fn field_list_filter_input_values(
-    _schema: &Document,
+    schema: &Document,
     field: &Field,
     field_type: &Type,
 ) -> Option<Vec<InputValue>> {
-    let _value_type = ast::get_field_value_type(field_type).ok()?;
+    // Lists of scalars and enums can be filtered by their values, lists of
+    // references by the entities they refer to
+    if ast::get_field_value_type(field_type).is_err() {
+        let type_name = ast::get_field_name(field_type);
+        match ast::get_named_type(schema, &type_name) {
+            Some(TypeDefinition::Enum(_)) => (),
+            Some(TypeDefinition::Object(_)) | Some(TypeDefinition::Interface(_)) => {
+                return Some(vec![child_filter_input_value(field, &type_name)]);
+            }
+            _ => return None,
+        }
+    }
     Some(
         vec!["", "not", "contains", "not_contains"]
             .into_iter()
@@ -304,6 +363,19 @@ fn field_list_filter_input_values(
     )
 }
 
+/// Generates the `<field>_` input value for the given reference field, which
+/// filters by the fields of the entities the field refers to.
+fn child_filter_input_value(field: &Field, type_name: &Name) -> InputValue {
+    InputValue {
+        position: Pos::default(),
+        description: None,
+        name: format!("{}_", field.name),
+        value_type: Type::NamedType(format!("{}_filter", type_name)),
+        default_value: None,
+        directives: vec![],
+    }
+}
+
 /// Generates a `*_filter` input value for the given field name, suffix and value type.
 fn input_value(name: &Name, suffix: &'static str, value_type: Type) -> InputValue {
     InputValue {
@@ -320,6 +392,59 @@ fn input_value(name: &Name, suffix: &'static str, value_type: Type) -> InputValu
     }
 }
 
+/// Adds the `_Meta_` object type that describes the subgraph deployment
+/// (e.g. its description and repository, and the latest block it has
+/// indexed) to the schema, along with the `_Block_` type for that block.
+fn add_meta_field_type(schema: &mut Document) -> Result<(), APISchemaError> {
+    for type_name in &[META_FIELD_TYPE, BLOCK_FIELD_TYPE] {
+        if ast::get_named_type(schema, &type_name.to_string()).is_some() {
+            return Err(APISchemaError::TypeExists(type_name.to_string()));
+        }
+    }
+
+    let field = |name: &str, field_type: Type| Field {
+        position: Pos::default(),
+        description: None,
+        name: name.to_owned(),
+        arguments: vec![],
+        field_type,
+        directives: vec![],
+    };
+    let named_type = |name: &str| Type::NamedType(name.to_owned());
+    let non_null = |name: &str| Type::NonNullType(Box::new(named_type(name)));
+    let object_type = |name: &str, fields: Vec<Field>| {
+        Definition::TypeDefinition(TypeDefinition::Object(ObjectType {
+            position: Pos::default(),
+            description: None,
+            name: name.to_owned(),
+            implements_interfaces: vec![],
+            directives: vec![],
+            fields,
+        }))
+    };
+
+    schema.definitions.push(object_type(
+        META_FIELD_TYPE,
+        vec![
+            field("block", non_null(BLOCK_FIELD_TYPE)),
+            field("deployment", non_null("String")),
+            field("hasIndexingErrors", non_null("Boolean")),
+            field("description", named_type("String")),
+            field("repository", named_type("String")),
+            field("author", named_type("String")),
+        ],
+    ));
+    schema.definitions.push(object_type(
+        BLOCK_FIELD_TYPE,
+        vec![
+            field("number", non_null("Int")),
+            field("hash", named_type("Bytes")),
+            field("timestamp", named_type("Int")),
+        ],
+    ));
+    Ok(())
+}
+
 /// Adds a root `Query` object type to the schema.
 fn add_query_type(
     schema: &mut Document,
@@ -343,6 +468,7 @@ fn add_query_type(
             .map(|t| &t.name)
             .chain(interface_types.iter().map(|t| &t.name))
             .flat_map(|name| query_fields_for_type(schema, name))
+            .chain(vec![meta_field()])
             .collect(),
     });
     let def = Definition::TypeDefinition(typedef);
@@ -350,6 +476,18 @@ fn add_query_type(
     Ok(())
 }
 
+/// Generates the `_meta` field for the root `Query` type.
+fn meta_field() -> Field {
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: META_FIELD_NAME.to_owned(),
+        arguments: vec![],
+        field_type: Type::NamedType(META_FIELD_TYPE.to_owned()),
+        directives: vec![],
+    }
+}
+
 /// Adds a root `Subscription` object type to the schema.
 fn add_subscription_type(
     schema: &mut Document,
@@ -423,8 +561,9 @@ fn collection_arguments_for_named_type(
     let mut skip = input_value(&"skip".to_string(), "", Type::NamedType("Int".to_string()));
     skip.default_value = Some(Value::Int(0.into()));
 
-    let mut first = input_value(&"first".to_string(), "", Type::NamedType("Int".to_string()));
-    first.default_value = Some(Value::Int(100.into()));
+    // `first` has no default in the schema since its default depends on the
+    // limit for the field; see `build_range`
+    let first = input_value(&"first".to_string(), "", Type::NamedType("Int".to_string()));
 
     let mut args = vec![
         skip,
@@ -584,6 +723,27 @@ mod tests {
             .expect("Root Query type is missing in API schema");
     }
 
+    #[test]
+    fn api_schema_contains_meta_field_on_query_type() {
+        let input_schema =
+            parse_schema("type User { id: ID! }").expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let query_type = ast::get_named_type(&schema, &"Query".to_string())
+            .expect("Query type is missing in derived API schema");
+        let meta_field = match query_type {
+            TypeDefinition::Object(t) => ast::get_field_type(t, &"_meta".to_string()),
+            _ => None,
+        }
+        .expect("\"_meta\" field is missing on Query type");
+        assert_eq!(meta_field.field_type, Type::NamedType("_Meta_".to_string()));
+
+        ast::get_named_type(&schema, &"_Meta_".to_string())
+            .expect("_Meta_ type is missing in derived API schema");
+        ast::get_named_type(&schema, &"_Block_".to_string())
+            .expect("_Block_ type is missing in derived API schema");
+    }
+
     #[test]
     fn api_schema_contains_field_order_by_enum() {
         let input_schema = parse_schema("type User { id: ID!, name: String! }")
@@ -603,6 +763,29 @@ mod tests {
         assert_eq!(values, [&"id".to_string(), &"name".to_string()]);
     }
 
+    #[test]
+    fn api_schema_contains_child_order_by_values() {
+        let input_schema = parse_schema(
+            "type Position { id: ID!, pool: Pool!, pools: [Pool!]! }
+             type Pool {
+               id: ID!
+               symbol: String!
+               positions: [Position!]! @derivedFrom(field: \"pool\")
+             }",
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derived API schema");
+
+        let values = match ast::get_named_type(&schema, &"Position_orderBy".to_string()) {
+            Some(TypeDefinition::Enum(t)) => t.values.iter().map(|value| value.name.as_str()),
+            _ => panic!("Position_orderBy type is missing or not an enum"),
+        };
+        assert_eq!(
+            values.collect::<Vec<_>>(),
+            ["id", "pool", "pools", "pool__id", "pool__symbol"]
+        );
+    }
+
     #[test]
     fn api_schema_contains_object_type_filter_enum() {
         let input_schema = parse_schema("type User { id: ID!, name: String!, pets: [String!]}")
@@ -642,11 +825,17 @@ mod tests {
                 "name_in",
                 "name_not_in",
                 "name_contains",
+                "name_contains_nocase",
                 "name_not_contains",
+                "name_not_contains_nocase",
                 "name_starts_with",
+                "name_starts_with_nocase",
                 "name_not_starts_with",
+                "name_not_starts_with_nocase",
                 "name_ends_with",
+                "name_ends_with_nocase",
                 "name_not_ends_with",
+                "name_not_ends_with_nocase",
                 "pets",
                 "pets_not",
                 "pets_contains",
@@ -658,6 +847,78 @@ mod tests {
         );
     }
 
+    #[test]
+    fn api_schema_contains_enum_field_filters() {
+        let input_schema = parse_schema(
+            "enum Color { RED, GREEN } type Pet { id: ID!, color: Color!, colors: [Color!] }",
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derived API schema");
+
+        let pet_filter = match ast::get_named_type(&schema, &"Pet_filter".to_string()) {
+            Some(TypeDefinition::InputObject(t)) => t,
+            _ => panic!("Pet_filter type is missing or not an input object"),
+        };
+
+        assert_eq!(
+            pet_filter
+                .fields
+                .iter()
+                .map(|field| (field.name.as_str(), field.value_type.to_string()))
+                .collect::<Vec<_>>(),
+            [
+                ("id", "ID"),
+                ("id_not", "ID"),
+                ("id_gt", "ID"),
+                ("id_lt", "ID"),
+                ("id_gte", "ID"),
+                ("id_lte", "ID"),
+                ("id_in", "[ID!]"),
+                ("id_not_in", "[ID!]"),
+                ("color", "Color"),
+                ("color_not", "Color"),
+                ("color_in", "[Color!]"),
+                ("color_not_in", "[Color!]"),
+                ("colors", "[Color!]"),
+                ("colors_not", "[Color!]"),
+                ("colors_contains", "[Color!]"),
+                ("colors_not_contains", "[Color!]"),
+            ]
+            .iter()
+            .map(|(name, value_type)| (*name, value_type.to_string()))
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn api_schema_contains_child_filters() {
+        let input_schema = parse_schema(
+            "type Pool { id: ID!, token: Token!, swaps: [Swap!]! @derivedFrom(field: \"pool\") }
+             type Token { id: ID! }
+             type Swap { id: ID!, pool: Pool! }",
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derived API schema");
+
+        let pool_filter = match ast::get_named_type(&schema, &"Pool_filter".to_string()) {
+            Some(TypeDefinition::InputObject(t)) => t,
+            _ => panic!("Pool_filter type is missing or not an input object"),
+        };
+
+        assert_eq!(
+            pool_filter
+                .fields
+                .iter()
+                .filter(|field| field.name.ends_with('_'))
+                .map(|field| (field.name.as_str(), field.value_type.to_string()))
+                .collect::<Vec<_>>(),
+            [("token_", "Token_filter"), ("swaps_", "Swap_filter")]
+                .iter()
+                .map(|(name, value_type)| (*name, value_type.to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn api_schema_contains_object_fields_on_query_type() {
         let input_schema = parse_schema(
@@ -22,12 +22,32 @@ pub(crate) enum FilterOp {
     NotStartsWith,
     EndsWith,
     NotEndsWith,
+    ContainsNoCase,
+    NotContainsNoCase,
+    StartsWithNoCase,
+    NotStartsWithNoCase,
+    EndsWithNoCase,
+    NotEndsWithNoCase,
     Equal,
 }
 
 /// Split a "name_eq" style name into an attribute ("name") and a filter op (`Equal`).
 pub(crate) fn parse_field_as_filter(key: &Name) -> (Name, FilterOp) {
     let (suffix, op) = match key {
+        k if k.ends_with("_not_contains_nocase") => {
+            ("_not_contains_nocase", FilterOp::NotContainsNoCase)
+        }
+        k if k.ends_with("_contains_nocase") => ("_contains_nocase", FilterOp::ContainsNoCase),
+        k if k.ends_with("_not_starts_with_nocase") => {
+            ("_not_starts_with_nocase", FilterOp::NotStartsWithNoCase)
+        }
+        k if k.ends_with("_starts_with_nocase") => {
+            ("_starts_with_nocase", FilterOp::StartsWithNoCase)
+        }
+        k if k.ends_with("_not_ends_with_nocase") => {
+            ("_not_ends_with_nocase", FilterOp::NotEndsWithNoCase)
+        }
+        k if k.ends_with("_ends_with_nocase") => ("_ends_with_nocase", FilterOp::EndsWithNoCase),
         k if k.ends_with("_not") => ("_not", FilterOp::Not),
         k if k.ends_with("_gt") => ("_gt", FilterOp::GreaterThan),
         k if k.ends_with("_lt") => ("_lt", FilterOp::LessThan),
@@ -379,9 +399,10 @@ pub fn is_entity_type_definition(type_def: &TypeDefinition) -> bool {
     use self::TypeDefinition::*;
 
     match type_def {
-        // Entity types are obvious
+        // Entity types are obvious; aggregations are entities written by the store
         Object(object_type) => {
             get_object_type_directive(object_type, Name::from("entity")).is_some()
+                || get_object_type_directive(object_type, Name::from("aggregation")).is_some()
         }
 
         // For now, we'll assume that only entities can implement interfaces;
@@ -4,4 +4,6 @@ pub mod api;
 /// Utilities for working with GraphQL schema ASTs.
 pub mod ast;
 
-pub use self::api::{api_schema, APISchemaError};
+pub use self::api::{
+    api_schema, APISchemaError, BLOCK_FIELD_TYPE, META_FIELD_NAME, META_FIELD_TYPE,
+};
@@ -1,9 +1,36 @@
 use crate::execution::ObjectOrInterface;
 use crate::schema::ast as sast;
 use graph::prelude::*;
-use graphql_parser::{query as q, query::Name, schema as s, schema::ObjectType};
+use graphql_parser::{query as q, query::Name, schema as s};
+use lazy_static::lazy_static;
 use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::env;
 use std::mem::discriminant;
+use std::str::FromStr;
+
+lazy_static! {
+    /// The largest value of `first` that queries may use.
+    static ref MAX_FIRST: u32 = env_limit("GRAPH_GRAPHQL_MAX_FIRST").unwrap_or(100);
+
+    /// The largest value of `skip` that queries may use.
+    static ref MAX_SKIP: u32 = env_limit("GRAPH_GRAPHQL_MAX_SKIP").unwrap_or(u32::max_value());
+
+    /// The largest value of `first` for fields with `@derivedFrom`, which
+    /// can fan out to far more entities than a field that stores its
+    /// references.
+    pub(crate) static ref MAX_DERIVED_FIRST: u32 =
+        env_limit("GRAPH_GRAPHQL_MAX_DERIVED_FIRST").unwrap_or(*MAX_FIRST);
+}
+
+/// The value of `first` if the query doesn't specify it, unless the limit for
+/// the field is lower.
+const DEFAULT_FIRST: u32 = 100;
+
+fn env_limit(name: &str) -> Option<u32> {
+    env::var(name)
+        .ok()
+        .map(|s| u32::from_str(&s).unwrap_or_else(|_| panic!("failed to parse env var {}", name)))
+}
 
 /// Builds a EntityQuery from GraphQL arguments.
 ///
@@ -11,47 +38,66 @@ use std::mem::discriminant;
 pub fn build_query<'a>(
     entity: impl Into<ObjectOrInterface<'a>>,
     arguments: &HashMap<&q::Name, q::Value>,
-    types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
+    schema: &Schema,
+) -> Result<EntityQuery, QueryExecutionError> {
+    build_query_with_max_first(entity, arguments, schema, *MAX_FIRST)
+}
+
+/// Like `build_query`, but limits `first` to `max_first` instead of
+/// `MAX_FIRST`.
+pub(crate) fn build_query_with_max_first<'a>(
+    entity: impl Into<ObjectOrInterface<'a>>,
+    arguments: &HashMap<&q::Name, q::Value>,
+    schema: &Schema,
+    max_first: u32,
 ) -> Result<EntityQuery, QueryExecutionError> {
     let entity = entity.into();
-    let entity_types = match &entity {
-        ObjectOrInterface::Object(object) => vec![object.name.clone()],
-        ObjectOrInterface::Interface(interface) => types_for_interface[&interface.name]
-            .iter()
-            .map(|o| o.name.clone())
-            .collect(),
-    };
+    let (order_by, order_by_child) = build_order_by(entity, arguments, schema)?;
     Ok(EntityQuery {
         subgraph_id: parse_subgraph_id(entity)?,
-        entity_types,
-        range: build_range(arguments)?,
-        filter: build_filter(entity, arguments)?,
-        order_by: build_order_by(entity, arguments)?,
+        entity_types: entity_types(entity, schema),
+        range: build_range(arguments, max_first)?,
+        filter: build_filter(entity, arguments, schema)?,
+        order_by,
+        order_by_child,
         order_direction: build_order_direction(arguments)?,
+        block: None,
     })
 }
 
+/// The names of the entity types that are queried for `entity`.
+fn entity_types(entity: ObjectOrInterface, schema: &Schema) -> Vec<String> {
+    match entity {
+        ObjectOrInterface::Object(object) => vec![object.name.clone()],
+        ObjectOrInterface::Interface(interface) => schema.types_for_interface()[&interface.name]
+            .iter()
+            .map(|o| o.name.clone())
+            .collect(),
+    }
+}
+
 /// Parses GraphQL arguments into a EntityRange, if present.
 fn build_range(
     arguments: &HashMap<&q::Name, q::Value>,
+    max_first: u32,
 ) -> Result<EntityRange, QueryExecutionError> {
     let first = match arguments.get(&"first".to_string()) {
         Some(q::Value::Int(n)) => {
             let n = n.as_i64().expect("first is Int");
-            if n > 0 && n <= 100 {
+            if n > 0 && n <= max_first as i64 {
                 Ok(n as u32)
             } else {
                 Err("first")
             }
         }
-        Some(q::Value::Null) => Ok(100),
-        _ => unreachable!("first is an Int with a default value"),
+        None | Some(q::Value::Null) => Ok(DEFAULT_FIRST.min(max_first)),
+        _ => unreachable!("first is an Int"),
     };
 
     let skip = match arguments.get(&"skip".to_string()) {
         Some(q::Value::Int(n)) => {
             let n = n.as_i64().expect("skip is Int");
-            if n >= 0 {
+            if n >= 0 && n <= *MAX_SKIP as i64 {
                 Ok(n as u32)
             } else {
                 Err("skip")
@@ -72,7 +118,9 @@ fn build_range(
                 .filter(|r| r.is_err())
                 .map(|e| e.unwrap_err())
                 .collect();
-            Err(QueryExecutionError::RangeArgumentsError(errors))
+            Err(QueryExecutionError::RangeArgumentsError(
+                errors, max_first, *MAX_SKIP,
+            ))
         }
     }
 }
@@ -81,9 +129,10 @@ fn build_range(
 fn build_filter(
     entity: ObjectOrInterface,
     arguments: &HashMap<&q::Name, q::Value>,
+    schema: &Schema,
 ) -> Result<Option<EntityFilter>, QueryExecutionError> {
     match arguments.get(&"where".to_string()) {
-        Some(q::Value::Object(object)) => build_filter_from_object(entity, object),
+        Some(q::Value::Object(object)) => build_filter_from_object(entity, object, schema),
         None | Some(q::Value::Null) => Ok(None),
         _ => Err(QueryExecutionError::InvalidFilterError),
     }
@@ -93,6 +142,7 @@ fn build_filter(
 fn build_filter_from_object(
     entity: ObjectOrInterface,
     object: &BTreeMap<q::Name, q::Value>,
+    schema: &Schema,
 ) -> Result<Option<EntityFilter>, QueryExecutionError> {
     Ok(Some(EntityFilter::And({
         object
@@ -100,6 +150,11 @@ fn build_filter_from_object(
             .map(|(key, value)| {
                 use self::sast::FilterOp::*;
 
+                // `field_: { ... }` filters by the entities `field` refers to
+                if key.ends_with('_') {
+                    return build_child_filter(entity, &key[..key.len() - 1], value, schema);
+                }
+
                 let (field_name, op) = sast::parse_field_as_filter(key);
 
                 let field = sast::get_field_type(entity, &field_name).ok_or_else(|| {
@@ -126,6 +181,14 @@ fn build_filter_from_object(
                     NotStartsWith => EntityFilter::NotStartsWith(field_name, store_value),
                     EndsWith => EntityFilter::EndsWith(field_name, store_value),
                     NotEndsWith => EntityFilter::NotEndsWith(field_name, store_value),
+                    ContainsNoCase => EntityFilter::ContainsNoCase(field_name, store_value),
+                    NotContainsNoCase => EntityFilter::NotContainsNoCase(field_name, store_value),
+                    StartsWithNoCase => EntityFilter::StartsWithNoCase(field_name, store_value),
+                    NotStartsWithNoCase => {
+                        EntityFilter::NotStartsWithNoCase(field_name, store_value)
+                    }
+                    EndsWithNoCase => EntityFilter::EndsWithNoCase(field_name, store_value),
+                    NotEndsWithNoCase => EntityFilter::NotEndsWithNoCase(field_name, store_value),
                     Equal => EntityFilter::Equal(field_name, store_value),
                 })
             })
@@ -133,6 +196,55 @@ fn build_filter_from_object(
     })))
 }
 
+/// Parses the filter for the entities that the field `field_name` of
+/// `entity` refers to into a `EntityFilter::Child`.
+fn build_child_filter(
+    entity: ObjectOrInterface,
+    field_name: &str,
+    value: &q::Value,
+    schema: &Schema,
+) -> Result<EntityFilter, QueryExecutionError> {
+    let field = sast::get_field_type(entity, &field_name.to_owned()).ok_or_else(|| {
+        QueryExecutionError::EntityFieldError(entity.name().to_owned(), field_name.to_owned())
+    })?;
+    let child = referenced_type(field, schema).ok_or_else(|| {
+        QueryExecutionError::EntityFieldError(entity.name().to_owned(), format!("{}_", field_name))
+    })?;
+
+    let child_filter = match value {
+        q::Value::Object(object) => build_filter_from_object(child, object, schema)?,
+        _ => return Err(QueryExecutionError::InvalidFilterError),
+    };
+
+    // Derived fields are stored with the children, pointing back at the
+    // entity, all other references are stored with the entity
+    let link = match sast::get_derived_from_field(child, field) {
+        Some(derived_from) => ChildLink::Derived(
+            derived_from.name.clone(),
+            sast::is_list_or_non_null_list_field(derived_from),
+        ),
+        None => ChildLink::Direct(
+            field_name.to_owned(),
+            sast::is_list_or_non_null_list_field(field),
+        ),
+    };
+
+    Ok(EntityFilter::Child(
+        link,
+        entity_types(child, schema),
+        Box::new(child_filter.unwrap_or_else(|| EntityFilter::And(vec![]))),
+    ))
+}
+
+/// The entity type that `field` refers to, if it is a reference field.
+fn referenced_type<'a>(field: &s::Field, schema: &'a Schema) -> Option<ObjectOrInterface<'a>> {
+    match sast::get_named_type(&schema.document, &sast::get_field_name(&field.field_type)) {
+        Some(s::TypeDefinition::Object(t)) => Some(t.into()),
+        Some(s::TypeDefinition::Interface(t)) => Some(t.into()),
+        _ => None,
+    }
+}
+
 /// Parses a list of GraphQL values into a vector of entity field values.
 fn list_values(value: Value, filter_type: &str) -> Result<Vec<Value>, QueryExecutionError> {
     match value {
@@ -162,27 +274,79 @@ fn list_values(value: Value, filter_type: &str) -> Result<Vec<Value>, QueryExecu
 }
 
 /// Parses GraphQL arguments into an field name to order by, if present.
+/// Names like `pool__symbol` order by the field `symbol` of the entity the
+/// field `pool` refers to; the second value is then the `pool` attribute
+/// and the types of the entities it can refer to.
 fn build_order_by(
     entity: ObjectOrInterface,
     arguments: &HashMap<&q::Name, q::Value>,
-) -> Result<Option<(String, ValueType)>, QueryExecutionError> {
-    arguments
-        .get(&"orderBy".to_string())
-        .map_or(Ok(None), |value| match value {
-            q::Value::Enum(name) => {
-                let field = sast::get_field_type(entity, &name).ok_or_else(|| {
-                    QueryExecutionError::EntityFieldError(entity.name().to_owned(), name.clone())
-                })?;
-                sast::get_field_value_type(&field.field_type)
-                    .map(|value_type| Some((name.to_owned(), value_type)))
-                    .map_err(|_| {
-                        QueryExecutionError::OrderByNotSupportedError(
-                            entity.name().to_owned(),
-                            name.clone(),
-                        )
-                    })
+    schema: &Schema,
+) -> Result<
+    (
+        Option<(String, ValueType)>,
+        Option<(Attribute, Vec<String>)>,
+    ),
+    QueryExecutionError,
+> {
+    let name = match arguments.get(&"orderBy".to_string()) {
+        Some(q::Value::Enum(name)) => name,
+        _ => return Ok((None, None)),
+    };
+
+    let (parent_name, child_name) = match name.find("__") {
+        Some(pos) => (&name[..pos], &name[pos + 2..]),
+        None => {
+            return Ok((
+                Some((name.to_owned(), order_value_type(entity, name)?)),
+                None,
+            ))
+        }
+    };
+
+    // Only single references that are stored with the entity can be
+    // ordered by
+    let parent_field = sast::get_field_type(entity, &parent_name.to_owned()).ok_or_else(|| {
+        QueryExecutionError::EntityFieldError(entity.name().to_owned(), parent_name.to_owned())
+    })?;
+    let child = referenced_type(parent_field, schema)
+        .filter(|child| {
+            !sast::is_list_or_non_null_list_field(parent_field)
+                && sast::get_derived_from_field(*child, parent_field).is_none()
+        })
+        .ok_or_else(|| {
+            QueryExecutionError::OrderByNotSupportedError(entity.name().to_owned(), name.clone())
+        })?;
+
+    let child_name = child_name.to_owned();
+    let value_type = order_value_type(child, &child_name)?;
+    Ok((
+        Some((child_name, value_type)),
+        Some((parent_name.to_owned(), entity_types(child, schema))),
+    ))
+}
+
+/// The type of the values of the field `name` of `entity`, for ordering by
+/// the field.
+fn order_value_type(
+    entity: ObjectOrInterface,
+    name: &Name,
+) -> Result<ValueType, QueryExecutionError> {
+    let field = sast::get_field_type(entity, name).ok_or_else(|| {
+        QueryExecutionError::EntityFieldError(entity.name().to_owned(), name.clone())
+    })?;
+    sast::get_field_value_type(&field.field_type)
+        .or_else(|e| {
+            // Named types other than scalars are enums, whose
+            // values are stored as strings, or entity types,
+            // whose references are stored as ID strings
+            if sast::is_list_or_non_null_list_field(field) {
+                Err(e)
+            } else {
+                Ok(ValueType::String)
             }
-            _ => Ok(None),
+        })
+        .map_err(|_| {
+            QueryExecutionError::OrderByNotSupportedError(entity.name().to_owned(), name.clone())
         })
 }
 
@@ -247,10 +411,8 @@ pub fn collect_entities_from_query_field(
             {
                 // If the field's type definition is an object type, extract that type
                 if let s::TypeDefinition::Object(object_type) = type_definition {
-                    // Only collect whether the field's type has an @entity directive
-                    if sast::get_object_type_directive(object_type, String::from("entity"))
-                        .is_some()
-                    {
+                    // Only collect entity types, including aggregations
+                    if sast::is_entity_type_definition(type_definition) {
                         // Obtain the subgraph ID from the object type
                         if let Ok(subgraph_id) = parse_subgraph_id(object_type) {
                             // Add the (subgraph_id, entity_name) tuple to the result set
@@ -285,6 +447,7 @@ mod tests {
     use graph::prelude::*;
 
     use super::build_query;
+    use crate::schema::ast as sast;
 
     fn default_object() -> ObjectType {
         let subgraph_id_argument = (
@@ -349,11 +512,18 @@ mod tests {
         }
     }
 
+    fn schema() -> Schema {
+        Schema::new(
+            SubgraphDeploymentId::new("test").unwrap(),
+            s::Document {
+                definitions: vec![],
+            },
+        )
+    }
+
     fn default_arguments<'a>() -> HashMap<&'a String, q::Value> {
         let mut map = HashMap::new();
-        let first: &String = Box::leak(Box::new("first".to_owned()));
         let skip: &String = Box::leak(Box::new("skip".to_owned()));
-        map.insert(first, q::Value::Int(100.into()));
         map.insert(skip, q::Value::Int(0.into()));
         map
     }
@@ -361,13 +531,13 @@ mod tests {
     #[test]
     fn build_query_uses_the_entity_name() {
         assert_eq!(
-            build_query(&object("Entity1"), &default_arguments(), &BTreeMap::new())
+            build_query(&object("Entity1"), &default_arguments(), &schema())
                 .unwrap()
                 .entity_types,
             vec!["Entity1".to_string()]
         );
         assert_eq!(
-            build_query(&object("Entity2"), &default_arguments(), &BTreeMap::new())
+            build_query(&object("Entity2"), &default_arguments(), &schema())
                 .unwrap()
                 .entity_types,
             vec!["Entity2".to_string()]
@@ -377,13 +547,13 @@ mod tests {
     #[test]
     fn build_query_yields_no_order_if_order_arguments_are_missing() {
         assert_eq!(
-            build_query(&default_object(), &default_arguments(), &BTreeMap::new())
+            build_query(&default_object(), &default_arguments(), &schema())
                 .unwrap()
                 .order_by,
             None,
         );
         assert_eq!(
-            build_query(&default_object(), &default_arguments(), &BTreeMap::new())
+            build_query(&default_object(), &default_arguments(), &schema())
                 .unwrap()
                 .order_direction,
             None,
@@ -396,7 +566,7 @@ mod tests {
         let mut args = default_arguments();
         args.insert(&order_by, q::Value::Enum("name".to_string()));
         assert_eq!(
-            build_query(&default_object(), &args, &BTreeMap::new(),)
+            build_query(&default_object(), &args, &schema(),)
                 .unwrap()
                 .order_by,
             Some(("name".to_string(), ValueType::String))
@@ -405,20 +575,44 @@ mod tests {
         let mut args = default_arguments();
         args.insert(&order_by, q::Value::Enum("email".to_string()));
         assert_eq!(
-            build_query(&default_object(), &args, &BTreeMap::new(),)
+            build_query(&default_object(), &args, &schema(),)
                 .unwrap()
                 .order_by,
             Some(("email".to_string(), ValueType::String))
         );
     }
 
+    #[test]
+    fn build_query_orders_enum_fields_as_strings() {
+        let order_by = "orderBy".to_string();
+        let mut object = default_object();
+        object
+            .fields
+            .push(field("color", Type::NamedType("Color".to_owned())));
+        object.fields.push(field(
+            "colors",
+            Type::ListType(Box::new(Type::NamedType("Color".to_owned()))),
+        ));
+
+        let mut args = default_arguments();
+        args.insert(&order_by, q::Value::Enum("color".to_string()));
+        assert_eq!(
+            build_query(&object, &args, &schema()).unwrap().order_by,
+            Some(("color".to_string(), ValueType::String))
+        );
+
+        let mut args = default_arguments();
+        args.insert(&order_by, q::Value::Enum("colors".to_string()));
+        assert!(build_query(&object, &args, &schema()).is_err());
+    }
+
     #[test]
     fn build_query_ignores_order_by_from_non_enum_values() {
         let order_by = "orderBy".to_string();
         let mut args = default_arguments();
         args.insert(&order_by, q::Value::String("name".to_string()));
         assert_eq!(
-            build_query(&default_object(), &args, &BTreeMap::new(),)
+            build_query(&default_object(), &args, &schema(),)
                 .unwrap()
                 .order_by,
             None,
@@ -427,7 +621,7 @@ mod tests {
         let mut args = default_arguments();
         args.insert(&order_by, q::Value::String("email".to_string()));
         assert_eq!(
-            build_query(&default_object(), &args, &BTreeMap::new(),)
+            build_query(&default_object(), &args, &schema(),)
                 .unwrap()
                 .order_by,
             None,
@@ -440,7 +634,7 @@ mod tests {
         let mut args = default_arguments();
         args.insert(&order_direction, q::Value::Enum("asc".to_string()));
         assert_eq!(
-            build_query(&default_object(), &args, &BTreeMap::new(),)
+            build_query(&default_object(), &args, &schema(),)
                 .unwrap()
                 .order_direction,
             Some(EntityOrder::Ascending)
@@ -449,7 +643,7 @@ mod tests {
         let mut args = default_arguments();
         args.insert(&order_direction, q::Value::Enum("desc".to_string()));
         assert_eq!(
-            build_query(&default_object(), &args, &BTreeMap::new(),)
+            build_query(&default_object(), &args, &schema(),)
                 .unwrap()
                 .order_direction,
             Some(EntityOrder::Descending)
@@ -458,7 +652,7 @@ mod tests {
         let mut args = default_arguments();
         args.insert(&order_direction, q::Value::Enum("ascending...".to_string()));
         assert_eq!(
-            build_query(&default_object(), &args, &BTreeMap::new(),)
+            build_query(&default_object(), &args, &schema(),)
                 .unwrap()
                 .order_direction,
             None,
@@ -471,7 +665,7 @@ mod tests {
         let mut args = default_arguments();
         args.insert(&order_direction, q::Value::String("asc".to_string()));
         assert_eq!(
-            build_query(&default_object(), &args, &BTreeMap::new(),)
+            build_query(&default_object(), &args, &schema(),)
                 .unwrap()
                 .order_direction,
             None,
@@ -480,7 +674,7 @@ mod tests {
         let mut args = default_arguments();
         args.insert(&order_direction, q::Value::String("desc".to_string()));
         assert_eq!(
-            build_query(&default_object(), &args, &BTreeMap::new())
+            build_query(&default_object(), &args, &schema())
                 .unwrap()
                 .order_direction,
             None,
@@ -490,7 +684,7 @@ mod tests {
     #[test]
     fn build_query_yields_default_range_if_none_is_present() {
         assert_eq!(
-            build_query(&default_object(), &default_arguments(), &BTreeMap::new())
+            build_query(&default_object(), &default_arguments(), &schema())
                 .unwrap()
                 .range,
             EntityRange::first(100)
@@ -503,7 +697,7 @@ mod tests {
         let mut args = default_arguments();
         args.insert(&skip, q::Value::Int(q::Number::from(50)));
         assert_eq!(
-            build_query(&default_object(), &args, &BTreeMap::new(),)
+            build_query(&default_object(), &args, &schema(),)
                 .unwrap()
                 .range,
             EntityRange {
@@ -513,6 +707,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn build_query_rejects_first_above_the_limit() {
+        let first = "first".to_string();
+        let mut args = default_arguments();
+        args.insert(
+            &first,
+            q::Value::Int(q::Number::from(*MAX_FIRST as i32 + 1)),
+        );
+        match build_query(&default_object(), &args, &schema()) {
+            Err(QueryExecutionError::RangeArgumentsError(args, max_first, _)) => {
+                assert_eq!(args, vec!["first"]);
+                assert_eq!(max_first, *MAX_FIRST);
+            }
+            result => panic!("expected a range error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn build_query_clamps_the_default_first_to_the_limit() {
+        assert_eq!(
+            build_query_with_max_first(&default_object(), &default_arguments(), &schema(), 10)
+                .unwrap()
+                .range,
+            EntityRange::first(10)
+        );
+
+        let first = "first".to_string();
+        let mut args = default_arguments();
+        args.insert(&first, q::Value::Int(q::Number::from(11)));
+        match build_query_with_max_first(&default_object(), &args, &schema(), 10) {
+            Err(QueryExecutionError::RangeArgumentsError(args, max_first, _)) => {
+                assert_eq!(args, vec!["first"]);
+                assert_eq!(max_first, 10);
+            }
+            result => panic!("expected a range error, got {:?}", result),
+        }
+    }
+
     #[test]
     fn build_query_yields_filters() {
         let whre = "where".to_string();
@@ -531,7 +763,7 @@ mod tests {
                     ..default_object()
                 },
                 &args,
-                &BTreeMap::new()
+                &schema()
             )
             .unwrap()
             .filter,
@@ -541,4 +773,106 @@ mod tests {
             )]))
         )
     }
+
+    fn pool_schema() -> Schema {
+        Schema::new(
+            SubgraphDeploymentId::new("test").unwrap(),
+            graphql_parser::parse_schema(
+                "
+                type Pool @subgraphId(id: \"test\") {
+                  id: ID!
+                  token: Token!
+                  swaps: [Swap!]! @derivedFrom(field: \"pool\")
+                }
+                type Token @subgraphId(id: \"test\") {
+                  id: ID!
+                  symbol: String!
+                }
+                type Swap @subgraphId(id: \"test\") {
+                  id: ID!
+                  pool: Pool!
+                }
+                ",
+            )
+            .unwrap(),
+        )
+    }
+
+    fn pool_type(schema: &Schema) -> ObjectType {
+        match sast::get_named_type(&schema.document, &"Pool".to_owned()) {
+            Some(s::TypeDefinition::Object(pool)) => pool.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn build_query_yields_child_filters() {
+        let schema = pool_schema();
+        let pool = pool_type(&schema);
+
+        let whre = "where".to_string();
+        let mut args = default_arguments();
+        args.insert(
+            &whre,
+            q::Value::Object(BTreeMap::from_iter(vec![
+                (
+                    "token_".to_string(),
+                    q::Value::Object(BTreeMap::from_iter(vec![(
+                        "symbol".to_string(),
+                        q::Value::String("ETH".to_string()),
+                    )])),
+                ),
+                (
+                    "swaps_".to_string(),
+                    q::Value::Object(BTreeMap::from_iter(vec![(
+                        "id".to_string(),
+                        q::Value::String("1".to_string()),
+                    )])),
+                ),
+            ])),
+        );
+        assert_eq!(
+            build_query(&pool, &args, &schema).unwrap().filter,
+            Some(EntityFilter::And(vec![
+                EntityFilter::Child(
+                    ChildLink::Derived("pool".to_string(), false),
+                    vec!["Swap".to_string()],
+                    Box::new(EntityFilter::And(vec![EntityFilter::Equal(
+                        "id".to_string(),
+                        Value::String("1".to_string()),
+                    )])),
+                ),
+                EntityFilter::Child(
+                    ChildLink::Direct("token".to_string(), false),
+                    vec!["Token".to_string()],
+                    Box::new(EntityFilter::And(vec![EntityFilter::Equal(
+                        "symbol".to_string(),
+                        Value::String("ETH".to_string()),
+                    )])),
+                ),
+            ]))
+        )
+    }
+
+    #[test]
+    fn build_query_orders_by_child_fields() {
+        let schema = pool_schema();
+        let order_by = "orderBy".to_string();
+        let mut args = default_arguments();
+        args.insert(&order_by, q::Value::Enum("token__symbol".to_string()));
+
+        let query = build_query(&pool_type(&schema), &args, &schema).unwrap();
+        assert_eq!(
+            query.order_by,
+            Some(("symbol".to_string(), ValueType::String))
+        );
+        assert_eq!(
+            query.order_by_child,
+            Some(("token".to_string(), vec!["Token".to_string()]))
+        );
+
+        // Lists of references can't be ordered by
+        args.insert(&order_by, q::Value::Enum("swaps__id".to_string()));
+        assert!(build_query(&pool_type(&schema), &args, &schema).is_err());
+    }
 }
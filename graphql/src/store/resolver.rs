@@ -2,19 +2,32 @@ use graphql_parser::{query as q, schema as s};
 use std::collections::{BTreeMap, HashMap};
 use std::ops::Deref;
 use std::result;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use graph::components::store::*;
+use graph::data::subgraph::schema::{
+    SubgraphDeploymentEntity, SubgraphManifestEntity, SUBGRAPHS_ID,
+};
 use graph::prelude::*;
+use graph::web3::types::H256;
 
 use crate::prelude::*;
 use crate::schema::ast as sast;
-use crate::store::query::{collect_entities_from_query_field, parse_subgraph_id};
+use crate::schema::{BLOCK_FIELD_TYPE, META_FIELD_TYPE};
+use crate::store::query::{
+    build_query_with_max_first, collect_entities_from_query_field, parse_subgraph_id,
+    MAX_DERIVED_FIRST,
+};
 
 /// A resolver that fetches entities from a `Store`.
 pub struct StoreResolver<S> {
     logger: Logger,
     store: Arc<S>,
+    block: Option<EthereumBlockPointer>,
+    /// The snapshots of the deployments at `block`, opened by the first
+    /// query of each deployment and shared by all clones of the resolver,
+    /// so that the queries of a request don't each have to open one.
+    snapshots: Arc<Mutex<HashMap<SubgraphDeploymentId, Box<EntitySnapshot>>>>,
 }
 
 impl<S> Clone for StoreResolver<S>
@@ -25,6 +38,8 @@ where
         StoreResolver {
             logger: self.logger.clone(),
             store: self.store.clone(),
+            block: self.block,
+            snapshots: self.snapshots.clone(),
         }
     }
 }
@@ -37,9 +52,132 @@ where
         StoreResolver {
             logger: logger.new(o!("component" => "StoreResolver")),
             store,
+            block: None,
+            snapshots: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    /// Resolves entities as they were at `block` instead of the latest
+    /// ones, if it is given.
+    pub fn at_block(mut self, block: Option<EthereumBlockPointer>) -> Self {
+        self.block = block;
+        self
+    }
+
+    fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        let block = match self.block {
+            Some(block) => block,
+            None => return self.store.find(query),
+        };
+
+        let mut snapshots = self.snapshots.lock().unwrap();
+        if !snapshots.contains_key(&query.subgraph_id) {
+            let snapshot = self
+                .store
+                .snapshot_at_block(&query.subgraph_id, block.number)?;
+            snapshots.insert(query.subgraph_id.clone(), snapshot);
+        }
+        snapshots[&query.subgraph_id].find(query)
+    }
+
+    fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        match self.block {
+            None => self.store.get(key),
+            Some(_) => {
+                let query = EntityQuery::new(
+                    key.subgraph_id,
+                    vec![key.entity_type],
+                    EntityRange::first(1),
+                )
+                .filter(EntityFilter::Equal("id".to_owned(), key.entity_id.into()));
+                Ok(self.find(query)?.pop())
+            }
+        }
+    }
+
+    /// Resolves the `_meta` field from the manifest metadata and the
+    /// deployment entity stored for the subgraph deployment in the subgraph
+    /// of subgraphs.
+    fn resolve_meta(
+        &self,
+        object_type: ObjectOrInterface<'_>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        // subgraph_id directive is injected in all types.
+        let subgraph_id = parse_subgraph_id(object_type).unwrap();
+
+        let manifest = self.store.get(EntityKey {
+            subgraph_id: SUBGRAPHS_ID.clone(),
+            entity_type: SubgraphManifestEntity::TYPENAME.to_owned(),
+            entity_id: SubgraphManifestEntity::id(&subgraph_id),
+        })?;
+
+        let deployment = self
+            .store
+            .get(SubgraphDeploymentEntity::key(subgraph_id.clone()))?;
+
+        let field_value = |name: &str| {
+            manifest
+                .as_ref()
+                .and_then(|manifest| manifest.get(name).cloned())
+                .map_or(q::Value::Null, Into::into)
+        };
+        let deployment_value = |name: &str| {
+            deployment
+                .as_ref()
+                .and_then(|deployment| deployment.get(name))
+        };
+
+        let (number, hash) = match self.block {
+            Some(block) => (block.number, Some(block.hash)),
+            None => (
+                match deployment_value("latestEthereumBlockNumber") {
+                    Some(Value::BigInt(number)) => number.to_u64(),
+                    _ => 0,
+                },
+                match deployment_value("latestEthereumBlockHash") {
+                    Some(Value::String(hash)) => hash.parse::<H256>().ok(),
+                    _ => None,
+                },
+            ),
+        };
+        let timestamp = match hash {
+            Some(hash) => self
+                .store
+                .block_timestamp(hash)
+                .map_err(QueryExecutionError::StoreError)?,
+            None => None,
+        };
+        let block = object_value(vec![
+            ("__typename", q::Value::String(BLOCK_FIELD_TYPE.to_owned())),
+            ("number", q::Value::Int(q::Number::from(number as i32))),
+            (
+                "hash",
+                hash.map_or(q::Value::Null, |hash| {
+                    q::Value::String(format!("{:#x}", hash))
+                }),
+            ),
+            (
+                "timestamp",
+                timestamp.map_or(q::Value::Null, |timestamp| {
+                    q::Value::Int(q::Number::from(timestamp as i32))
+                }),
+            ),
+        ]);
+
+        Ok(object_value(vec![
+            ("__typename", q::Value::String(META_FIELD_TYPE.to_owned())),
+            ("block", block),
+            ("deployment", q::Value::String(subgraph_id.to_string())),
+            (
+                "hasIndexingErrors",
+                q::Value::Boolean(deployment_value("failed") == Some(&Value::Bool(true))),
+            ),
+            ("description", field_value("description")),
+            ("repository", field_value("repository")),
+            ("author", field_value("author")),
+        ]))
+    }
+
     /// Adds a filter for matching entities that correspond to a derived field.
     ///
     /// Returns true if the field is a derived field (i.e., if it is defined with
@@ -236,14 +374,34 @@ where
         field_definition: &s::Field,
         object_type: ObjectOrInterface<'_>,
         arguments: &HashMap<&q::Name, q::Value>,
-        types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
+        schema: &Schema,
     ) -> Result<q::Value, QueryExecutionError> {
         let object_type = object_type.into();
-        let mut query = build_query(object_type, arguments, types_for_interface)?;
-
-        // Add matching filter for derived fields
         let derived_from_field = sast::get_derived_from_field(object_type, field_definition);
         let is_derived = derived_from_field.is_some();
+
+        // Derived fields can refer to any number of entities, which is
+        // limited separately from top-level queries
+        let mut query = if is_derived {
+            let max_first = *MAX_DERIVED_FIRST;
+            build_query_with_max_first(object_type, arguments, schema, max_first).map_err(|e| {
+                match e {
+                    QueryExecutionError::RangeArgumentsError(ref args, _, _)
+                        if args.as_slice() == ["first"] =>
+                    {
+                        QueryExecutionError::DerivedFieldLimitError(
+                            field_definition.name.clone(),
+                            max_first,
+                        )
+                    }
+                    e => e,
+                }
+            })?
+        } else {
+            build_query(object_type, arguments, schema)?
+        };
+
+        // Add matching filter for derived fields
         if let Some(derived_from_field) = derived_from_field {
             Self::add_filter_for_derived_field(&mut query, parent, derived_from_field);
         }
@@ -264,7 +422,7 @@ where
         }
 
         let mut entity_values = Vec::new();
-        for entity in self.store.find(query)? {
+        for entity in self.find(query)? {
             entity_values.push(entity.into())
         }
         Ok(q::Value::List(entity_values))
@@ -277,8 +435,22 @@ where
         field_definition: &s::Field,
         object_type: ObjectOrInterface<'_>,
         arguments: &HashMap<&q::Name, q::Value>,
-        types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
+        schema: &Schema,
     ) -> Result<q::Value, QueryExecutionError> {
+        if object_type.name() == META_FIELD_TYPE {
+            return self.resolve_meta(object_type);
+        }
+
+        // The block of `_meta` is resolved along with `_meta` itself
+        if object_type.name() == BLOCK_FIELD_TYPE {
+            return Ok(match parent {
+                Some(q::Value::Object(parent)) => {
+                    parent.get(&field.name).cloned().unwrap_or(q::Value::Null)
+                }
+                _ => q::Value::Null,
+            });
+        }
+
         let id = arguments.get(&"id".to_string()).and_then(|id| match id {
             q::Value::String(s) => Some(s),
             _ => None,
@@ -288,19 +460,19 @@ where
         let subgraph_id = parse_subgraph_id(object_type).unwrap();
         let entity = if let Some(id) = id {
             match object_type {
-                ObjectOrInterface::Object(_) => self.store.get(EntityKey {
+                ObjectOrInterface::Object(_) => self.get(EntityKey {
                     subgraph_id,
                     entity_type: object_type.name().to_owned(),
                     entity_id: id.to_owned(),
                 })?,
                 ObjectOrInterface::Interface(interface) => {
-                    let entity_types = types_for_interface[&interface.name]
+                    let entity_types = schema.types_for_interface()[&interface.name]
                         .iter()
                         .map(|o| o.name.clone())
                         .collect();
                     let range = EntityRange::first(1);
                     let query = EntityQuery::new(subgraph_id, entity_types, range);
-                    self.store.find(query)?.into_iter().next()
+                    self.find(query)?.into_iter().next()
                 }
             }
         } else {
@@ -319,11 +491,11 @@ where
 
                 let skip_arg_name = q::Name::from("skip");
                 arguments.insert(&skip_arg_name, q::Value::Int(q::Number::from(0)));
-                let mut query = build_query(object_type, &arguments, types_for_interface)?;
+                let mut query = build_query(object_type, &arguments, schema)?;
                 Self::add_filter_for_derived_field(&mut query, parent, derived_from_field);
 
                 // Find the entity or entities that reference the parent entity
-                let entities = self.store.find(query)?;
+                let entities = self.find(query)?;
 
                 if entities.len() > 1 {
                     return Err(QueryExecutionError::AmbiguousDerivedFromResult(
@@ -338,7 +510,7 @@ where
             } else {
                 match parent {
                     Some(q::Value::Object(parent_object)) => match parent_object.get(&field.name) {
-                        Some(q::Value::String(id)) => self.store.get(EntityKey {
+                        Some(q::Value::String(id)) => self.get(EntityKey {
                             subgraph_id,
                             entity_type: object_type.name().to_owned(),
                             entity_id: id.to_owned(),
@@ -2,7 +2,7 @@
 extern crate pretty_assertions;
 
 use graphql_parser::{query as q, schema as s};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::HashMap;
 
 use graph::prelude::*;
 use graph_graphql::prelude::*;
@@ -19,7 +19,7 @@ impl Resolver for MockResolver {
         _field_definition: &s::Field,
         _object_type: ObjectOrInterface<'_>,
         _arguments: &HashMap<&q::Name, q::Value>,
-        _types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
+        _schema: &Schema,
     ) -> Result<q::Value, QueryExecutionError> {
         Ok(q::Value::Null)
     }
@@ -31,7 +31,7 @@ impl Resolver for MockResolver {
         _field_definition: &s::Field,
         _object_type: ObjectOrInterface<'_>,
         _arguments: &HashMap<&q::Name, q::Value>,
-        _types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
+        _schema: &Schema,
     ) -> Result<q::Value, QueryExecutionError> {
         Ok(q::Value::Null)
     }
@@ -527,6 +527,7 @@ fn introspection_query(schema: Schema, query: &str) -> QueryResult {
         schema: Arc::new(schema),
         document: graphql_parser::parse_query(query).unwrap(),
         variables: None,
+        block: None,
     };
 
     // Execute it
@@ -68,8 +68,12 @@ fn insert_test_entities(store: &impl Store, id: SubgraphDeploymentId) {
         spec_version: "1".to_owned(),
         description: None,
         repository: None,
+        author: None,
         schema: test_schema(id.clone()),
         data_sources: vec![],
+        templates: vec![],
+        external_hosts: vec![],
+        features: vec![],
     };
 
     store
@@ -199,6 +203,7 @@ fn execute_query_document_with_variables(
         schema: Arc::new(api_test_schema()),
         document: query,
         variables,
+        block: None,
     };
 
     let logger = Logger::root(slog::Discard, o!());
@@ -677,6 +682,7 @@ fn instant_timeout() {
         schema: Arc::new(api_test_schema()),
         document: graphql_parser::parse_query("query { musicians(first: 100) { name } }").unwrap(),
         variables: None,
+        block: None,
     };
     let logger = Logger::root(slog::Discard, o!());
     let store_resolver = StoreResolver::new(&logger, STORE.clone());
@@ -875,3 +881,83 @@ fn ambiguous_derived_from_result() {
         )),
     }
 }
+
+#[test]
+fn failing_root_fields_do_not_fail_other_root_fields() {
+    let query = graphql_parser::parse_query(
+        "
+        {
+          musicians(first: 100, orderBy: id) {
+            name
+          }
+          song(id: \"s1\") {
+            id
+            band
+          }
+        }
+        ",
+    )
+    .expect("invalid test query");
+
+    let result = execute_query_document_with_variables(query, None);
+
+    match result.data {
+        Some(q::Value::Object(ref data)) => {
+            match data.get("musicians") {
+                Some(q::Value::List(musicians)) => assert!(!musicians.is_empty()),
+                value => panic!("expected a list of musicians, got {:?}", value),
+            }
+            assert_eq!(data.get("song"), Some(&q::Value::Null));
+        }
+        ref data => panic!("expected partial data, got {:?}", data),
+    }
+
+    let errors = result.errors.expect("expected an error for `song`");
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        QueryError::FieldError(path, error) => {
+            assert_eq!(path, &vec!["song".to_owned()]);
+            match error.as_ref() {
+                QueryError::ExecutionError(e) => match e {
+                    QueryExecutionError::AmbiguousDerivedFromResult(..) => (),
+                    e => panic!("expected AmbiguousDerivedFromResult error, got {}", e),
+                },
+                e => panic!("expected an execution error, got {}", e),
+            }
+        }
+        e => panic!("expected an error scoped to `song`, got {}", e),
+    }
+
+    let json = graph::serde_json::to_string(&errors[0]).unwrap();
+    assert!(json.contains(r#""path":["song"]"#));
+}
+
+#[test]
+fn failing_non_null_root_fields_make_data_null() {
+    // `songs` is a `[Song!]!`, so it can't be `null`, and the `null` takes
+    // the place of all of `data` instead
+    let query = graphql_parser::parse_query(
+        "
+        {
+          musicians(first: 100, orderBy: id) {
+            name
+          }
+          songs(first: 100, orderBy: id) {
+            id
+            band
+          }
+        }
+        ",
+    )
+    .expect("invalid test query");
+
+    let result = execute_query_document_with_variables(query, None);
+
+    assert_eq!(result.data, None);
+    let errors = result.errors.expect("expected an error for `songs`");
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        QueryError::ExecutionError(QueryExecutionError::AmbiguousDerivedFromResult(..)) => (),
+        e => panic!("expected AmbiguousDerivedFromResult error, got {}", e),
+    }
+}
@@ -5,15 +5,20 @@ use graph::prelude::*;
 pub struct MockBlockStream {
     chain_head_update_sink: Sender<ChainHeadUpdate>,
     _chain_head_update_stream: Receiver<ChainHeadUpdate>,
+    written_blocks_sink: Sender<EthereumBlockPointer>,
+    _written_blocks: Receiver<EthereumBlockPointer>,
 }
 
 impl MockBlockStream {
     fn new() -> Self {
         let (chain_head_update_sink, chain_head_update_stream) = channel(100);
+        let (written_blocks_sink, written_blocks) = channel(0);
 
         Self {
             chain_head_update_sink,
             _chain_head_update_stream: chain_head_update_stream,
+            written_blocks_sink,
+            _written_blocks: written_blocks,
         }
     }
 }
@@ -33,7 +38,11 @@ impl EventConsumer<ChainHeadUpdate> for MockBlockStream {
     }
 }
 
-impl BlockStream for MockBlockStream {}
+impl BlockStream for MockBlockStream {
+    fn written_blocks_sink(&self) -> Sender<EthereumBlockPointer> {
+        self.written_blocks_sink.clone()
+    }
+}
 
 #[derive(Clone)]
 pub struct MockBlockStreamBuilder;
@@ -47,7 +56,11 @@ impl MockBlockStreamBuilder {
 impl BlockStreamBuilder for MockBlockStreamBuilder {
     type Stream = MockBlockStream;
 
-    fn from_subgraph(&self, _manifest: &SubgraphManifest, _logger: Logger) -> Self::Stream {
-        MockBlockStream::new()
+    fn from_subgraph(
+        &self,
+        _manifest: &SubgraphManifest,
+        _logger: Logger,
+    ) -> Result<Self::Stream, Error> {
+        Ok(MockBlockStream::new())
     }
 }
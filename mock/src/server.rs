@@ -65,6 +65,7 @@ where
                     schema: Arc::new(schema.clone().unwrap()),
                     document: graphql_parser::parse_query("{ allUsers { name }}").unwrap(),
                     variables: None,
+                    block: None,
                 }
             })
             .collect::<Vec<Query>>();
@@ -7,7 +7,7 @@ use std::sync::Mutex;
 
 use graph::components::store::*;
 use graph::prelude::*;
-use graph::web3::types::H256;
+use graph::web3::types::{Log, H256};
 
 /// A mock `ChainHeadUpdateListener`
 pub struct MockChainHeadUpdateListener {}
@@ -75,8 +75,10 @@ impl MockStore {
             entity_types,
             filter,
             order_by,
+            order_by_child: _,
             order_direction,
             range: _,
+            block: _,
         } = query;
 
         // List all entities with correct type
@@ -134,10 +136,33 @@ impl Store for MockStore {
         Ok(self.find(query)?.pop())
     }
 
-    fn block_ptr(&self, _: SubgraphDeploymentId) -> Result<EthereumBlockPointer, Error> {
+    fn snapshot_at_block(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: u64,
+    ) -> Result<Box<EntitySnapshot>, QueryExecutionError> {
         unimplemented!();
     }
 
+    fn block_ptr(&self, subgraph_id: SubgraphDeploymentId) -> Result<EthereumBlockPointer, Error> {
+        let entity = self
+            .get(SubgraphDeploymentEntity::key(subgraph_id.clone()))?
+            .ok_or_else(|| format_err!("no deployment entity for subgraph {}", subgraph_id))?;
+
+        let hash = entity
+            .get("latestEthereumBlockHash")
+            .and_then(|value| value.clone().as_string())
+            .ok_or_else(|| format_err!("invalid latestEthereumBlockHash"))?
+            .parse::<H256>()?;
+        let number = entity
+            .get("latestEthereumBlockNumber")
+            .and_then(|value| value.clone().as_bigint())
+            .ok_or_else(|| format_err!("invalid latestEthereumBlockNumber"))?
+            .to_u64();
+
+        Ok(EthereumBlockPointer { hash, number })
+    }
+
     fn set_block_ptr_with_no_changes(
         &self,
         _: SubgraphDeploymentId,
@@ -149,12 +174,19 @@ impl Store for MockStore {
 
     fn transact_block_operations(
         &self,
-        _: SubgraphDeploymentId,
-        _: EthereumBlockPointer,
-        _: EthereumBlockPointer,
-        _: Vec<EntityOperation>,
+        subgraph_id: SubgraphDeploymentId,
+        block_ptr_from: EthereumBlockPointer,
+        block_ptr_to: EthereumBlockPointer,
+        mut operations: Vec<EntityOperation>,
     ) -> Result<(), StoreError> {
-        unimplemented!();
+        operations.extend(
+            SubgraphDeploymentEntity::update_ethereum_block_pointer_operations(
+                &subgraph_id,
+                block_ptr_from,
+                block_ptr_to,
+            ),
+        );
+        self.apply_entity_operations(operations, EventSource::EthereumBlock(block_ptr_to))
     }
 
     fn apply_entity_operations(
@@ -309,6 +341,15 @@ impl Store for MockStore {
         unimplemented!();
     }
 
+    fn revert_block_range(
+        &self,
+        _: SubgraphDeploymentId,
+        _: Vec<EthereumBlockPointer>,
+        _: Vec<EntityOperation>,
+    ) -> Result<(), StoreError> {
+        unimplemented!();
+    }
+
     fn subscribe(&self, entity_types: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
         let (sender, receiver) = mpsc::channel(100);
 
@@ -320,9 +361,46 @@ impl Store for MockStore {
         StoreEventStream::new(Box::new(receiver))
     }
 
-    fn count_entities(&self, _: SubgraphDeploymentId) -> Result<u64, Error> {
+    fn count_entities(&self, subgraph: SubgraphDeploymentId) -> Result<u64, Error> {
+        Ok(self
+            .entities
+            .lock()
+            .unwrap()
+            .get(&subgraph)
+            .map_or(0, |entities_in_subgraph| {
+                entities_in_subgraph
+                    .values()
+                    .map(|entities_of_type| entities_of_type.len() as u64)
+                    .sum()
+            }))
+    }
+
+    fn block_timestamp(&self, _: H256) -> Result<Option<u64>, Error> {
+        Ok(None)
+    }
+
+    fn background_jobs(&self) -> Result<Vec<BackgroundJob>, Error> {
         unimplemented!();
     }
+
+    fn blocks_with_entity_changes(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &[String],
+        _: u64,
+        _: u64,
+    ) -> Result<Vec<EthereumBlockPointer>, Error> {
+        Ok(vec![])
+    }
+
+    fn entity_changes_in_block(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &[String],
+        _: H256,
+    ) -> Result<Vec<EntityChangeTrigger>, Error> {
+        Ok(vec![])
+    }
 }
 
 impl SubgraphDeploymentStore for MockStore {
@@ -372,6 +450,30 @@ impl ChainStore for MockStore {
     ) -> Result<Option<EthereumBlock>, Error> {
         unimplemented!();
     }
+
+    fn block_pointers_in_range(
+        &self,
+        _: u64,
+        _: u64,
+    ) -> Result<Vec<(EthereumBlockPointer, H256)>, Error> {
+        unimplemented!();
+    }
+
+    fn block_calls(&self, _: H256) -> Result<Option<Vec<EthereumCall>>, Error> {
+        unimplemented!();
+    }
+
+    fn upsert_block_calls(&self, _: EthereumBlockPointer, _: &[EthereumCall]) -> Result<(), Error> {
+        unimplemented!();
+    }
+
+    fn block_logs(&self, _: H256) -> Result<Option<Vec<Log>>, Error> {
+        unimplemented!();
+    }
+
+    fn upsert_block_logs(&self, _: EthereumBlockPointer, _: &[Log]) -> Result<(), Error> {
+        unimplemented!();
+    }
 }
 
 pub struct FakeStore;
@@ -382,13 +484,21 @@ impl Store for FakeStore {
     }
 
     fn find(&self, _: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
-        unimplemented!();
+        Ok(vec![])
     }
 
     fn find_one(&self, _: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
         unimplemented!();
     }
 
+    fn snapshot_at_block(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: u64,
+    ) -> Result<Box<EntitySnapshot>, QueryExecutionError> {
+        unimplemented!();
+    }
+
     fn block_ptr(&self, _: SubgraphDeploymentId) -> Result<EthereumBlockPointer, Error> {
         unimplemented!();
     }
@@ -436,6 +546,15 @@ impl Store for FakeStore {
         unimplemented!();
     }
 
+    fn revert_block_range(
+        &self,
+        _: SubgraphDeploymentId,
+        _: Vec<EthereumBlockPointer>,
+        _: Vec<EntityOperation>,
+    ) -> Result<(), StoreError> {
+        unimplemented!();
+    }
+
     fn subscribe(&self, _: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
         unimplemented!();
     }
@@ -443,6 +562,33 @@ impl Store for FakeStore {
     fn count_entities(&self, _: SubgraphDeploymentId) -> Result<u64, Error> {
         unimplemented!();
     }
+
+    fn block_timestamp(&self, _: H256) -> Result<Option<u64>, Error> {
+        Ok(None)
+    }
+
+    fn background_jobs(&self) -> Result<Vec<BackgroundJob>, Error> {
+        unimplemented!();
+    }
+
+    fn blocks_with_entity_changes(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &[String],
+        _: u64,
+        _: u64,
+    ) -> Result<Vec<EthereumBlockPointer>, Error> {
+        unimplemented!();
+    }
+
+    fn entity_changes_in_block(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &[String],
+        _: H256,
+    ) -> Result<Vec<EntityChangeTrigger>, Error> {
+        unimplemented!();
+    }
 }
 
 impl ChainStore for FakeStore {
@@ -483,4 +629,28 @@ impl ChainStore for FakeStore {
     ) -> Result<Option<EthereumBlock>, Error> {
         unimplemented!();
     }
+
+    fn block_pointers_in_range(
+        &self,
+        _: u64,
+        _: u64,
+    ) -> Result<Vec<(EthereumBlockPointer, H256)>, Error> {
+        unimplemented!();
+    }
+
+    fn block_calls(&self, _: H256) -> Result<Option<Vec<EthereumCall>>, Error> {
+        unimplemented!();
+    }
+
+    fn upsert_block_calls(&self, _: EthereumBlockPointer, _: &[EthereumCall]) -> Result<(), Error> {
+        unimplemented!();
+    }
+
+    fn block_logs(&self, _: H256) -> Result<Option<Vec<Log>>, Error> {
+        unimplemented!();
+    }
+
+    fn upsert_block_logs(&self, _: EthereumBlockPointer, _: &[Log]) -> Result<(), Error> {
+        unimplemented!();
+    }
 }
@@ -0,0 +1,212 @@
+use graphql_parser::schema::{Definition, ObjectType, TypeDefinition};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::path::Path;
+
+use graph::prelude::*;
+use graph::serde_json;
+use graph_store_postgres::Store as DieselStore;
+
+/// How many entities to load from the store at a time.
+const EXPORT_PAGE_SIZE: u32 = 1000;
+
+/// The entity types that an export has written, with the number of
+/// entities of each type.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ExportProgress {
+    pub entity_types: BTreeMap<String, usize>,
+}
+
+/// Writes the entities of a subgraph deployment to `output_dir`, one CSV
+/// file per entity type, named after the type. If `block_number` is given,
+/// the entities are exported as they were at that block instead of as they
+/// are now.
+///
+/// Entity types that are in `progress` have been written before and are
+/// skipped, so that an interrupted export can be resumed. Every type that
+/// is written is added to `progress`, and `on_progress` is called with it;
+/// if it fails, the export stops with its error. Returns the number of
+/// entities in the files.
+pub fn export_entities(
+    logger: &Logger,
+    store: &DieselStore,
+    schema: &Schema,
+    block_number: Option<u64>,
+    output_dir: &Path,
+    progress: &mut ExportProgress,
+    mut on_progress: impl FnMut(&ExportProgress) -> Result<(), Error>,
+) -> Result<usize, Error> {
+    let subgraph_id = &schema.id;
+    let block_ptr = store.block_ptr(subgraph_id.clone())?;
+    let changes = match block_number {
+        Some(block_number) if block_number > block_ptr.number => bail!(
+            "subgraph `{}` has only been indexed up to block {}",
+            subgraph_id,
+            block_ptr.number
+        ),
+        Some(block_number) => store.entities_changed_after_block(subgraph_id, block_number)?,
+        None => BTreeMap::new(),
+    };
+
+    fs::create_dir_all(output_dir)?;
+
+    for object_type in entity_types(schema) {
+        if progress.entity_types.contains_key(&object_type.name) {
+            continue;
+        }
+
+        let path = output_dir.join(format!("{}.csv", object_type.name));
+        let columns = entity_columns(object_type);
+        let mut writer = csv::Writer::from_writer(File::create(&path)?);
+        writer.write_record(&columns)?;
+
+        // Page through the entities by ID, so that the export doesn't slow
+        // down as it gets further into the table
+        let mut count = 0;
+        let mut exported = BTreeSet::new();
+        let mut last_id: Option<String> = None;
+        loop {
+            let mut query = EntityQuery::new(
+                subgraph_id.clone(),
+                vec![object_type.name.clone()],
+                EntityRange::first(EXPORT_PAGE_SIZE),
+            )
+            .order_by(("id".to_owned(), ValueType::ID), EntityOrder::Ascending);
+            if let Some(last_id) = last_id.take() {
+                query = query.filter(EntityFilter::GreaterThan(
+                    "id".to_owned(),
+                    Value::String(last_id),
+                ));
+            }
+
+            let entities = store.find(query)?;
+            let page_size = entities.len();
+            for entity in entities {
+                let entity_id = entity.id()?;
+                let key = EntityKey {
+                    subgraph_id: subgraph_id.clone(),
+                    entity_type: object_type.name.clone(),
+                    entity_id: entity_id.clone(),
+                };
+                last_id = Some(entity_id);
+
+                // Entities that changed after the block are exported with
+                // the data they had at the block
+                let entity = match changes.get(&key) {
+                    Some(Some(entity)) => entity,
+                    Some(None) => continue,
+                    None => &entity,
+                };
+                writer.write_record(csv_record(&columns, entity))?;
+                exported.insert(key);
+                count += 1;
+            }
+
+            if page_size < EXPORT_PAGE_SIZE as usize {
+                break;
+            }
+        }
+
+        // Entities that existed at the block but have been removed since
+        for (key, entity) in changes.iter() {
+            if key.entity_type != object_type.name || exported.contains(key) {
+                continue;
+            }
+            if let Some(entity) = entity {
+                writer.write_record(csv_record(&columns, entity))?;
+                count += 1;
+            }
+        }
+
+        writer.flush()?;
+        info!(
+            logger, "Exported entities";
+            "entity_type" => &object_type.name,
+            "entities" => count,
+            "path" => path.display().to_string(),
+        );
+        progress
+            .entity_types
+            .insert(object_type.name.clone(), count);
+        on_progress(progress)?;
+    }
+    Ok(progress.entity_types.values().sum())
+}
+
+/// The object types of the schema; every object type is an entity type.
+fn entity_types(schema: &Schema) -> Vec<&ObjectType> {
+    schema
+        .document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            Definition::TypeDefinition(TypeDefinition::Object(object_type)) => Some(object_type),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The columns of the CSV file for an entity type: `id` first, then the
+/// other fields in the order of the schema. Fields with `@derivedFrom` are
+/// not stored with the entity and are left out.
+fn entity_columns(object_type: &ObjectType) -> Vec<String> {
+    let mut columns = vec!["id".to_owned()];
+    columns.extend(
+        object_type
+            .fields
+            .iter()
+            .filter(|field| field.name != "id")
+            .filter(|field| {
+                !field
+                    .directives
+                    .iter()
+                    .any(|directive| directive.name == "derivedFrom")
+            })
+            .map(|field| field.name.clone()),
+    );
+    columns
+}
+
+fn csv_record(columns: &[String], entity: &Entity) -> Vec<String> {
+    columns
+        .iter()
+        .map(|column| entity.get(column).map_or(String::new(), csv_cell))
+        .collect()
+}
+
+/// Formats a value for a CSV cell. Nulls are empty cells and lists are
+/// written as JSON arrays.
+fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::List(_) => json_value(value).to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn json_value(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Bool(b) => serde_json::Value::Bool(*b),
+        Value::Int(i) => serde_json::Value::from(*i),
+        Value::List(values) => serde_json::Value::Array(values.iter().map(json_value).collect()),
+        value => serde_json::Value::String(value.to_string()),
+    }
+}
+
+#[test]
+fn csv_cells() {
+    assert_eq!(csv_cell(&Value::Null), "");
+    assert_eq!(csv_cell(&Value::Int(-3)), "-3");
+    assert_eq!(csv_cell(&Value::from("a,b")), "a,b");
+    assert_eq!(
+        csv_cell(&Value::List(vec![
+            Value::from("a"),
+            Value::Int(1),
+            Value::Null,
+            Value::List(vec![Value::Bool(true)]),
+        ])),
+        r#"["a",1,null,[true]]"#
+    );
+}
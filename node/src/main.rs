@@ -1,4 +1,5 @@
 extern crate clap;
+extern crate csv;
 extern crate env_logger;
 extern crate futures;
 extern crate itertools;
@@ -9,42 +10,70 @@ extern crate graph_core;
 extern crate graph_datasource_ethereum;
 extern crate graph_runtime_wasm;
 extern crate graph_server_http;
+extern crate graph_server_index_node;
 extern crate graph_server_json_rpc;
 extern crate graph_server_websocket;
 extern crate graph_store_postgres;
+extern crate graphql_parser;
 extern crate http;
 extern crate ipfs_api;
 extern crate lazy_static;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate tokio_signal;
+extern crate toml;
 extern crate url;
 
-use clap::{App, Arg};
+mod config;
+mod export;
+mod jobs;
+mod manage;
+mod static_subgraphs;
+
+use clap::{App, AppSettings, Arg, SubCommand};
+use config::{
+    Config, GeneralSection, IpfsSection, NetworkSection, ProviderSection, QuotasSection,
+    ServerSection, StoreSection,
+};
 use futures::sync::oneshot;
 use ipfs_api::IpfsClient;
 use itertools::FoldWhile::{Continue, Done};
 use itertools::Itertools;
 use lazy_static::lazy_static;
+use std::collections::HashMap;
 use std::env;
-use std::net::ToSocketAddrs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
+use graph::components::entity_export::NatsEntityChangeSink;
 use graph::components::forward;
-use graph::prelude::{JsonRpcServer as JsonRpcServerTrait, *};
+use graph::prelude::{
+    IndexNodeServer as IndexNodeServerTrait, JsonRpcServer as JsonRpcServerTrait, *,
+};
 use graph::tokio_executor;
 use graph::tokio_timer;
 use graph::tokio_timer::timer::Timer;
 use graph::util::log::{guarded_logger, logger, register_panic_hook};
 use graph_core::{
-    ElasticLoggingConfig, SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider,
-    SubgraphInstanceManager, SubgraphRegistrar as IpfsSubgraphRegistrar,
+    load_dynamic_data_sources, replay_subgraph, ElasticLoggingConfig, ForkStore, ReplayStore,
+    SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
+    SubgraphRegistrar as IpfsSubgraphRegistrar,
+};
+use graph_datasource_ethereum::{
+    cached_blocks_from_subgraph, BlockStreamBuilder, Provider, ProviderCapability, Transport,
 };
-use graph_datasource_ethereum::{BlockStreamBuilder, Transport};
 use graph_runtime_wasm::RuntimeHostBuilder as WASMRuntimeHostBuilder;
 use graph_server_http::GraphQLServer as GraphQLQueryServer;
+use graph_server_index_node::IndexNodeServer;
 use graph_server_json_rpc::JsonRpcServer;
 use graph_server_websocket::SubscriptionServer as GraphQLSubscriptionServer;
-use graph_store_postgres::{Store as DieselStore, StoreConfig};
+use graph_store_postgres::{copy_deployment, postgres_version, Store as DieselStore, StoreConfig};
 
 lazy_static! {
     // Default to an Ethereum reorg threshold to 50 blocks
@@ -113,17 +142,66 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
         .version("0.1.0")
         .author("Graph Protocol, Inc.")
         .about("Scalable queries for a decentralized future")
+        .arg(
+            Arg::with_name("config")
+                .takes_value(true)
+                .long("config")
+                .value_name("FILE")
+                .env("GRAPH_NODE_CONFIG")
+                .help(
+                    "TOML file with the Ethereum providers of every network, the store, \
+                     IPFS nodes, node ID and role and server ports",
+                ),
+        )
         .arg(
             Arg::with_name("subgraph")
                 .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
                 .long("subgraph")
                 .value_name("[NAME:]IPFS_HASH")
-                .help("name and IPFS hash of the subgraph manifest"),
+                .help(
+                    "Name and IPFS hash of a subgraph to deploy to this node when it starts; \
+                     may be given several times. The name defaults to `cli`",
+                ),
+        )
+        .arg(
+            Arg::with_name("local-subgraph")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .long("local-subgraph")
+                .value_name("PATH:ALIAS")
+                .help(
+                    "Serve the subgraph manifest at PATH as if it was on IPFS with the hash \
+                     ALIAS, and read `file:` links from disk, for local development",
+                ),
+        )
+        .arg(
+            Arg::with_name("entity-change-sink")
+                .takes_value(true)
+                .long("entity-change-sink")
+                .value_name("URL")
+                .help(
+                    "Publish every entity change that is written to the store to the NATS \
+                     server at URL, e.g. nats://localhost:4222/graph.entity-changes",
+                ),
+        )
+        .arg(
+            Arg::with_name("fork-base")
+                .long("fork-base")
+                .value_name("URL")
+                .help(
+                    "Read the entities that subgraphs don't have yet from the deployments \
+                     with the same IDs under URL, the query URL of another Graph Node without \
+                     the deployment ID, e.g. https://api.example.com/subgraphs/id/, \
+                     for debugging mapping failures",
+                ),
         )
         .arg(
             Arg::with_name("postgres-url")
                 .takes_value(true)
-                .required(true)
+                .required_unless("config")
                 .long("postgres-url")
                 .value_name("URL")
                 .help("Location of the Postgres database used for storing entities"),
@@ -131,46 +209,105 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
         .arg(
             Arg::with_name("ethereum-rpc")
                 .takes_value(true)
-                .required_unless_one(&["ethereum-ws", "ethereum-ipc"])
-                .conflicts_with_all(&["ethereum-ws", "ethereum-ipc"])
+                .multiple(true)
+                .min_values(1)
+                .required_unless_one(&["ethereum-ws", "ethereum-ipc", "config"])
                 .long("ethereum-rpc")
-                .value_name("NETWORK_NAME:URL")
+                .value_name("NETWORK_NAME:[OPTIONS:]URL")
                 .help(
                     "Ethereum network name (e.g. 'mainnet') and \
-                     Ethereum RPC URL, separated by a ':'",
+                     Ethereum RPC URL, separated by a ':'. May be given \
+                     for several networks and several times per network; \
+                     OPTIONS are the provider's capabilities ('archive', \
+                     'traces'), 'weight=N' and 'rps=N' to limit the requests \
+                     per second, separated by ','",
                 ),
         )
         .arg(
             Arg::with_name("ethereum-ws")
                 .takes_value(true)
-                .required_unless_one(&["ethereum-rpc", "ethereum-ipc"])
-                .conflicts_with_all(&["ethereum-rpc", "ethereum-ipc"])
+                .multiple(true)
+                .min_values(1)
+                .required_unless_one(&["ethereum-rpc", "ethereum-ipc", "config"])
                 .long("ethereum-ws")
-                .value_name("NETWORK_NAME:URL")
+                .value_name("NETWORK_NAME:[OPTIONS:]URL")
                 .help(
                     "Ethereum network name (e.g. 'mainnet') and \
-                     Ethereum WebSocket URL, separated by a ':'",
+                     Ethereum WebSocket URL, separated by a ':'. May be given \
+                     for several networks and several times per network; \
+                     OPTIONS as for --ethereum-rpc",
                 ),
         )
         .arg(
             Arg::with_name("ethereum-ipc")
                 .takes_value(true)
-                .required_unless_one(&["ethereum-rpc", "ethereum-ws"])
-                .conflicts_with_all(&["ethereum-rpc", "ethereum-ws"])
+                .multiple(true)
+                .min_values(1)
+                .required_unless_one(&["ethereum-rpc", "ethereum-ws", "config"])
                 .long("ethereum-ipc")
-                .value_name("NETWORK_NAME:FILE")
+                .value_name("NETWORK_NAME:[OPTIONS:]FILE")
                 .help(
                     "Ethereum network name (e.g. 'mainnet') and \
-                     Ethereum IPC pipe, separated by a ':'",
+                     Ethereum IPC pipe, separated by a ':'. May be given \
+                     for several networks and several times per network; \
+                     OPTIONS as for --ethereum-rpc",
                 ),
         )
         .arg(
             Arg::with_name("ipfs")
                 .takes_value(true)
-                .required(true)
+                .required_unless("config")
+                .multiple(true)
+                .number_of_values(1)
                 .long("ipfs")
                 .value_name("HOST:PORT")
-                .help("HTTP address of an IPFS node"),
+                .help(
+                    "HTTP address of an IPFS node; may be passed several times to \
+                     fall back to other IPFS nodes if one is unavailable",
+                ),
+        )
+        .arg(
+            Arg::with_name("ipfs-timeout")
+                .long("ipfs-timeout")
+                .value_name("SECONDS")
+                .env("GRAPH_IPFS_TIMEOUT")
+                .help("How long to wait for a request to an IPFS node [default: 30]"),
+        )
+        .arg(
+            Arg::with_name("ipfs-retries")
+                .long("ipfs-retries")
+                .value_name("COUNT")
+                .default_value("3")
+                .help("How many times to retry IPFS requests that failed or timed out"),
+        )
+        .arg(
+            Arg::with_name("ipfs-max-file-size")
+                .long("ipfs-max-file-size")
+                .value_name("BYTES")
+                .env("GRAPH_MAX_IPFS_FILE_BYTES")
+                .help("Largest file to load from IPFS, e.g. for subgraph manifests and mappings"),
+        )
+        .arg(
+            Arg::with_name("ipfs-cache-dir")
+                .long("ipfs-cache-dir")
+                .value_name("DIR")
+                .env("GRAPH_IPFS_CACHE_DIR")
+                .help(
+                    "Directory in which to cache subgraph manifests, schemas and mappings \
+                     loaded from IPFS",
+                ),
+        )
+        .arg(
+            Arg::with_name("http-links")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .long("http-links")
+                .value_name("URL_PREFIX")
+                .help(
+                    "Allow subgraphs to link to files under URL_PREFIX over HTTP(S), \
+                     e.g. to an internal artifact store; may be passed several times",
+                ),
         )
         .arg(
             Arg::with_name("http-port")
@@ -193,12 +330,69 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
                 .value_name("PORT")
                 .help("Port for the JSON-RPC admin server"),
         )
+        .arg(
+            Arg::with_name("admin-listen-address")
+                .default_value("0.0.0.0")
+                .long("admin-listen-address")
+                .value_name("IP")
+                .help(
+                    "IP address the JSON-RPC admin server listens on, e.g. 127.0.0.1 \
+                     to only accept requests from this machine",
+                ),
+        )
+        .arg(
+            Arg::with_name("admin-allowed-ips")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .long("admin-allowed-ips")
+                .value_name("CIDR")
+                .env("GRAPH_ADMIN_ALLOWED_IPS")
+                .help(
+                    "Only serve JSON-RPC admin requests from these IP networks, \
+                     e.g. 10.0.0.0/8,127.0.0.1; may be given several times",
+                ),
+        )
+        .arg(
+            Arg::with_name("admin-auth-token")
+                .long("admin-auth-token")
+                .value_name("TOKEN")
+                .env("GRAPH_ADMIN_AUTH_TOKEN")
+                .hide_env_values(true)
+                .help(
+                    "Require requests to the JSON-RPC admin server to send this token \
+                     in an `Authorization: Bearer <TOKEN>` header",
+                ),
+        )
+        .arg(
+            Arg::with_name("index-node-port")
+                .default_value("8030")
+                .long("index-node-port")
+                .value_name("PORT")
+                .help("Port for the index node server"),
+        )
         .arg(
             Arg::with_name("node-id")
                 .default_value("default")
                 .long("node-id")
                 .value_name("NODE_ID")
-                .help("a unique identifier for this node"),
+                .env("GRAPH_NODE_ID")
+                .help(
+                    "A unique identifier for this node. Nodes that share a \
+                     database only index the subgraphs assigned to them",
+                ),
+        )
+        .arg(
+            Arg::with_name("node-role")
+                .default_value("combined")
+                .long("node-role")
+                .value_name("ROLE")
+                .possible_values(&["combined", "query", "index"])
+                .env("GRAPH_NODE_ROLE")
+                .help(
+                    "What the node does: 'query' nodes only serve GraphQL queries, \
+                     'index' nodes only index subgraphs, 'combined' nodes do both",
+                ),
         )
         .arg(
             Arg::with_name("debug")
@@ -227,6 +421,24 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
                 .hide_env_values(true)
                 .help("Password to use for Elasticsearch logging"),
         )
+        .arg(
+            Arg::with_name("jaeger-agent")
+                .long("jaeger-agent")
+                .value_name("HOST:PORT")
+                .env("GRAPH_JAEGER_AGENT")
+                .help(
+                    "UDP address of a Jaeger agent to report traces of queries \
+                     and block processing to, e.g. localhost:6831",
+                ),
+        )
+        .arg(
+            Arg::with_name("tracing-sample-rate")
+                .long("tracing-sample-rate")
+                .value_name("RATE")
+                .default_value("1")
+                .env("GRAPH_TRACING_SAMPLE_RATE")
+                .help("Fraction of queries and blocks to trace, between 0 and 1"),
+        )
         .arg(
             Arg::with_name("ethereum-polling-interval")
                 .long("ethereum-polling-interval")
@@ -235,24 +447,279 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
                 .env("ETHEREUM_POLLING_INTERVAL")
                 .help("How often to poll the Ethereum node for new blocks"),
         )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .value_name("IPFS_HASH")
+                .requires("replay-output")
+                .help(
+                    "Replay a subgraph deployment from the blocks and contract calls \
+                     cached in the store, write its entity operations to \
+                     --replay-output and exit",
+                ),
+        )
+        .arg(
+            Arg::with_name("replay-output")
+                .long("replay-output")
+                .value_name("FILE")
+                .help("File to write the entity operations of --replay to"),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Inspect the configuration of the node")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("check").about(
+                    "Connect to every Ethereum node, the database and the IPFS nodes, \
+                     check the capabilities of the Ethereum nodes and print the effective \
+                     configuration, without starting the node",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("manage")
+                .about("Inspect and maintain the subgraph deployments in the store, and exit")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("info")
+                        .about("Show the names, assignment and progress of a deployment")
+                        .arg(
+                            Arg::with_name("deployment")
+                                .value_name("IPFS_HASH_OR_NAME")
+                                .required(true)
+                                .help(
+                                    "A deployment, or a subgraph name to show its current \
+                                     and pending deployments",
+                                ),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("stats")
+                        .about("Count the entities of a deployment by type and its entity history")
+                        .arg(
+                            Arg::with_name("deployment")
+                                .value_name("IPFS_HASH")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("unassign")
+                        .about("Stop indexing a deployment by removing its assignment to a node")
+                        .arg(
+                            Arg::with_name("deployment")
+                                .value_name("IPFS_HASH")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("reassign")
+                        .about("Assign a deployment to a different node")
+                        .arg(
+                            Arg::with_name("deployment")
+                                .value_name("IPFS_HASH")
+                                .required(true),
+                        )
+                        .arg(Arg::with_name("node").value_name("NODE_ID").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about(
+                            "Remove a subgraph name and its versions; deployments are kept \
+                             until they are removed with `unused remove`",
+                        )
+                        .arg(Arg::with_name("name").value_name("NAME").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("unused")
+                        .about("Find and remove deployments that are no longer used")
+                        .setting(AppSettings::SubcommandRequiredElseHelp)
+                        .subcommand(SubCommand::with_name("record").about(
+                            "Record the deployments that no subgraph version refers to and \
+                             that are not assigned to a node, and list them",
+                        ))
+                        .subcommand(
+                            SubCommand::with_name("remove")
+                                .about(
+                                    "Remove recorded deployments with their entities, unless \
+                                     they are in use again",
+                                )
+                                .arg(
+                                    Arg::with_name("deployment")
+                                        .value_name("IPFS_HASH")
+                                        .multiple(true)
+                                        .help(
+                                            "Deployments to remove, all recorded ones if omitted",
+                                        ),
+                                ),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("copy")
+                .about(
+                    "Copy a subgraph deployment with its entities from another \
+                     database into this node's database, and exit",
+                )
+                .arg(
+                    Arg::with_name("subgraph")
+                        .value_name("IPFS_HASH")
+                        .required(true)
+                        .help("The subgraph deployment to copy"),
+                )
+                .arg(
+                    Arg::with_name("from-postgres-url")
+                        .long("from-postgres-url")
+                        .value_name("URL")
+                        .required(true)
+                        .help("Location of the Postgres database to copy the deployment from"),
+                )
+                .arg(Arg::with_name("background").long("background").help(
+                    "Add the copy to the background job queue for an index node \
+                     to run, instead of running it now. The password in \
+                     --from-postgres-url is not added to the queue; the node \
+                     that runs the copy takes it from its .pgpass file or PGPASSWORD",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about(
+                    "Export the entities of a subgraph deployment to CSV files, \
+                     one file per entity type, and exit",
+                )
+                .arg(
+                    Arg::with_name("subgraph")
+                        .value_name("IPFS_HASH")
+                        .required(true)
+                        .help("The subgraph deployment to export"),
+                )
+                .arg(
+                    Arg::with_name("output-dir")
+                        .long("output-dir")
+                        .value_name("DIR")
+                        .required(true)
+                        .help("Directory to write the CSV files to"),
+                )
+                .arg(
+                    Arg::with_name("block")
+                        .long("block")
+                        .value_name("BLOCK_NUMBER")
+                        .help(
+                            "Export the entities as they were at this block \
+                             instead of as they are now",
+                        ),
+                )
+                .arg(Arg::with_name("background").long("background").help(
+                    "Add the export to the background job queue for an index node \
+                     to run, instead of running it now",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("prune")
+                .about(
+                    "Remove the entity history of a subgraph deployment that is too \
+                     old to be reverted, and exit",
+                )
+                .arg(
+                    Arg::with_name("subgraph")
+                        .value_name("IPFS_HASH")
+                        .required(true)
+                        .help("The subgraph deployment to prune"),
+                )
+                .arg(
+                    Arg::with_name("keep-blocks")
+                        .long("keep-blocks")
+                        .value_name("BLOCKS")
+                        .help(
+                            "Keep the entity history of this many of the latest blocks \
+                             of the deployment; defaults to ETHEREUM_REORG_THRESHOLD",
+                        ),
+                )
+                .arg(Arg::with_name("background").long("background").help(
+                    "Add the pruning to the background job queue for an index node \
+                     to run, instead of running it now",
+                )),
+        )
         .get_matches();
 
     // Set up logger
     let logger = logger(matches.is_present("debug"));
 
-    // Safe to unwrap because a value is required by CLI
-    let postgres_url = matches.value_of("postgres-url").unwrap().to_string();
+    // Report traces to Jaeger; this has to happen before any spans are
+    // started
+    if let Some(agent) = matches.value_of("jaeger-agent") {
+        let agent_addr = agent
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .unwrap_or_else(|| panic!("invalid Jaeger agent address: {}", agent));
+        let sample_rate = matches
+            .value_of("tracing-sample-rate")
+            .unwrap()
+            .parse()
+            .expect("tracing sample rate must be a number between 0 and 1");
+        graph::util::tracing::init_jaeger(&logger, "graph-node", agent_addr, sample_rate)
+            .unwrap_or_else(|e| panic!("failed to set up tracing: {}", e));
+        info!(logger, "Reporting traces to Jaeger"; "agent" => agent);
+    }
 
-    let node_id = NodeId::new(matches.value_of("node-id").unwrap())
-        .expect("Node ID must contain only a-z, A-Z, 0-9, and '_'");
+    // Settings in the config file take precedence over the command line
+    // and the environment
+    let config = match matches.value_of("config") {
+        Some(path) => Config::load(path, *ANCESTOR_COUNT).unwrap_or_else(|e| panic!("{}", e)),
+        None => Config::default(),
+    };
+    let resource_quotas = Arc::new(
+        config
+            .resource_quotas()
+            .expect("config file was validated when it was loaded"),
+    );
+    let reorg_thresholds = config.reorg_thresholds();
+
+    let postgres_url = config
+        .store
+        .postgres_url
+        .clone()
+        .or_else(|| matches.value_of("postgres-url").map(|s| s.to_owned()))
+        .expect("A Postgres URL must be given with --postgres-url or in the config file");
+
+    let node_id = NodeId::new(
+        config
+            .general
+            .node_id
+            .as_ref()
+            .map(String::as_str)
+            .unwrap_or_else(|| matches.value_of("node-id").unwrap()),
+    )
+    .expect("Node ID must contain only a-z, A-Z, 0-9, and '_'");
+    let node_role = config
+        .general
+        .node_role
+        .as_ref()
+        .map(String::as_str)
+        .or_else(|| matches.value_of("node-role"))
+        .and_then(NodeRole::from_str)
+        .expect("invalid node role");
 
     // Obtain subgraph related command-line arguments
-    let subgraph = matches.value_of("subgraph").map(|s| s.to_owned());
+    let static_subgraphs = matches
+        .values_of("subgraph")
+        .into_iter()
+        .flatten()
+        .map(|subgraph| {
+            let (name, hash) = match subgraph.find(':') {
+                Some(index) => (&subgraph[..index], &subgraph[index + 1..]),
+                None => ("cli", subgraph),
+            };
+            let name = SubgraphName::new(name)
+                .expect("Subgraph name must contain only a-z, A-Z, 0-9, '-' and '_'");
+            let subgraph_id =
+                SubgraphDeploymentId::new(hash).expect("Subgraph hash must be a valid IPFS hash");
+            (name, subgraph_id)
+        })
+        .collect::<Vec<_>>();
 
     // Obtain the Ethereum parameters
-    let ethereum_rpc = matches.value_of("ethereum-rpc");
-    let ethereum_ipc = matches.value_of("ethereum-ipc");
-    let ethereum_ws = matches.value_of("ethereum-ws");
+    let ethereum_rpc = matches.values_of("ethereum-rpc");
+    let ethereum_ipc = matches.values_of("ethereum-ipc");
+    let ethereum_ws = matches.values_of("ethereum-ws");
 
     let block_polling_interval = Duration::from_millis(
         matches
@@ -263,23 +730,69 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
     );
 
     // Obtain ports to use for the GraphQL server(s)
-    let http_port = matches
-        .value_of("http-port")
-        .unwrap()
-        .parse()
-        .expect("invalid GraphQL HTTP server port");
-    let ws_port = matches
-        .value_of("ws-port")
-        .unwrap()
-        .parse()
-        .expect("invalid GraphQL WebSocket server port");
+    let http_port = config.server.http_port.unwrap_or_else(|| {
+        matches
+            .value_of("http-port")
+            .unwrap()
+            .parse()
+            .expect("invalid GraphQL HTTP server port")
+    });
+    let ws_port = config.server.ws_port.unwrap_or_else(|| {
+        matches
+            .value_of("ws-port")
+            .unwrap()
+            .parse()
+            .expect("invalid GraphQL WebSocket server port")
+    });
 
     // Obtain JSON-RPC server port
-    let json_rpc_port = matches
-        .value_of("admin-port")
+    let json_rpc_port = config.server.admin_port.unwrap_or_else(|| {
+        matches
+            .value_of("admin-port")
+            .unwrap()
+            .parse()
+            .expect("invalid admin port")
+    });
+
+    // Obtain who may use the JSON-RPC server
+    let json_rpc_listen_address: IpAddr = matches
+        .value_of("admin-listen-address")
         .unwrap()
         .parse()
-        .expect("invalid admin port");
+        .expect("invalid admin listen address");
+    let json_rpc_auth_token = matches
+        .value_of("admin-auth-token")
+        .map(|token| token.to_owned());
+    let json_rpc_allowed_ips: Vec<IpNetwork> = matches
+        .values_of("admin-allowed-ips")
+        .map(|networks| {
+            networks
+                .map(|network| network.parse().expect("invalid admin allowed IP network"))
+                .collect()
+        })
+        .unwrap_or_default();
+    if node_role.indexes()
+        && json_rpc_auth_token.is_none()
+        && json_rpc_allowed_ips.is_empty()
+        && !json_rpc_listen_address.is_loopback()
+    {
+        warn!(
+            logger,
+            "The JSON-RPC admin server accepts requests from anyone who can reach it; \
+             set --admin-auth-token, --admin-allowed-ips or --admin-listen-address \
+             to protect it";
+            "listen_address" => json_rpc_listen_address.to_string(),
+        );
+    }
+
+    // Obtain index node server port
+    let index_node_port = config.server.index_node_port.unwrap_or_else(|| {
+        matches
+            .value_of("index-node-port")
+            .unwrap()
+            .parse()
+            .expect("invalid index node server port")
+    });
 
     debug!(logger, "Setting up Sentry");
 
@@ -296,8 +809,792 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
     sentry::integrations::panic::register_panic_handler();
     info!(logger, "Starting up");
 
-    // Try to create an IPFS client for one of the resolved IPFS addresses
-    let ipfs_address = matches.value_of("ipfs").unwrap();
+    // Create an IPFS client for every IPFS node; links are resolved with
+    // the first node that is healthy, falling back to the others
+    let config_check = matches
+        .subcommand_matches("config")
+        .map_or(false, |config| config.subcommand_matches("check").is_some());
+
+    let ipfs_addresses: Vec<_> = config
+        .ipfs
+        .nodes
+        .iter()
+        .map(String::as_str)
+        .chain(matches.values_of("ipfs").into_iter().flatten())
+        .collect();
+    if ipfs_addresses.is_empty() {
+        panic!("An IPFS node must be given with --ipfs or in the config file");
+    }
+    let ipfs_clients = ipfs_addresses
+        .iter()
+        .map(|ipfs_address| {
+            create_ipfs_client(
+                &logger,
+                ipfs_address,
+                ipfs_addresses.len() == 1 && !config_check,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // Resolve links to subgraph manifests and their files with the IPFS nodes
+    let link_resolver = IpfsResolver::new(&logger, ipfs_clients.clone()).with_retries(
+        matches
+            .value_of("ipfs-retries")
+            .unwrap()
+            .parse()
+            .expect("IPFS retries must be a nonnegative integer"),
+    );
+    let link_resolver = match matches.value_of("ipfs-timeout") {
+        Some(timeout) => link_resolver.with_timeout(Duration::from_secs(
+            timeout
+                .parse()
+                .expect("IPFS timeout must be a nonnegative integer"),
+        )),
+        None => link_resolver,
+    };
+    let link_resolver = match matches.value_of("ipfs-max-file-size") {
+        Some(max_file_size) => link_resolver.with_max_file_size(Some(
+            max_file_size
+                .parse()
+                .expect("IPFS maximum file size must be a nonnegative integer"),
+        )),
+        None => link_resolver,
+    };
+    let link_resolver = match matches.value_of("ipfs-cache-dir") {
+        Some(cache_dir) => link_resolver.with_cache_dir(Some(PathBuf::from(cache_dir))),
+        None => link_resolver,
+    };
+
+    // Fetch files linked over HTTP(S) from the allowed URLs
+    let http_link_prefixes = matches
+        .values_of("http-links")
+        .into_iter()
+        .flatten()
+        .map(|prefix| prefix.to_owned())
+        .collect();
+    let link_resolver = HttpLinkResolver::new(Arc::new(link_resolver), http_link_prefixes)
+        .expect("--http-links must be HTTP(S) URLs");
+    let link_resolver = match matches.value_of("ipfs-timeout") {
+        Some(timeout) => link_resolver.with_timeout(Duration::from_secs(
+            timeout
+                .parse()
+                .expect("IPFS timeout must be a nonnegative integer"),
+        )),
+        None => link_resolver,
+    };
+
+    // Serve local subgraphs from disk, for developing subgraphs without
+    // publishing them to IPFS
+    let mut link_resolver = FileLinkResolver::new(Arc::new(link_resolver));
+    for local_subgraph in matches.values_of("local-subgraph").into_iter().flatten() {
+        let (path, alias) = match local_subgraph.rfind(':') {
+            Some(index) => (&local_subgraph[..index], &local_subgraph[index + 1..]),
+            None => panic!("Local subgraph must be given as PATH:ALIAS"),
+        };
+        SubgraphDeploymentId::new(alias)
+            .expect("Local subgraph alias must be a valid subgraph deployment ID");
+        info!(
+            logger,
+            "Serving local subgraph";
+            "path" => path,
+            "alias" => alias,
+        );
+        link_resolver = link_resolver.with_local_subgraph(alias.to_owned(), PathBuf::from(path));
+    }
+    let link_resolver = Arc::new(link_resolver);
+
+    // Parse the Ethereum connection strings and group them by network,
+    // together with the providers from the config file; a network may be
+    // served by several providers
+    let mut ethereum_networks = config
+        .ethereum_networks()
+        .expect("config file was validated when it was loaded");
+    for (s, kind) in ethereum_ipc
+        .into_iter()
+        .flatten()
+        .map(|s| (s, "ipc"))
+        .chain(ethereum_ws.into_iter().flatten().map(|s| (s, "ws")))
+        .chain(ethereum_rpc.into_iter().flatten().map(|s| (s, "rpc")))
+    {
+        let (network_name, options, node_url) =
+            parse_ethereum_network_and_node(s).expect("failed to parse Ethereum connection string");
+        let provider = (node_url.to_owned(), kind, options);
+        match ethereum_networks
+            .iter_mut()
+            .find(|(name, _)| name == network_name)
+        {
+            Some((_, providers)) => {
+                if providers.iter().any(|(url, _, _)| url == node_url) {
+                    panic!(
+                        "Ethereum node `{}` is configured more than once for network `{}`",
+                        node_url, network_name
+                    );
+                }
+                providers.push(provider)
+            }
+            None => ethereum_networks.push((network_name.to_owned(), vec![provider])),
+        }
+    }
+
+    // Check the configuration instead of running the node
+    if config_check {
+        let effective_config = Config {
+            general: GeneralSection {
+                node_id: Some(node_id.to_string()),
+                node_role: Some(node_role.as_str().to_owned()),
+            },
+            store: StoreSection {
+                postgres_url: Some(hide_password(&postgres_url)),
+            },
+            ipfs: IpfsSection {
+                nodes: ipfs_addresses.iter().map(|s| s.to_string()).collect(),
+            },
+            server: ServerSection {
+                http_port: Some(http_port),
+                ws_port: Some(ws_port),
+                admin_port: Some(json_rpc_port),
+                index_node_port: Some(index_node_port),
+            },
+            networks: ethereum_networks
+                .iter()
+                .map(|(network_name, providers)| {
+                    let providers = providers
+                        .iter()
+                        .map(|(url, kind, options)| ProviderSection::new(url, kind, options))
+                        .collect();
+                    let reorg_threshold = reorg_thresholds
+                        .get(network_name)
+                        .cloned()
+                        .unwrap_or(*REORG_THRESHOLD);
+                    (
+                        network_name.clone(),
+                        NetworkSection {
+                            reorg_threshold: Some(reorg_threshold),
+                            providers,
+                        },
+                    )
+                })
+                .collect(),
+            quotas: QuotasSection::new(&resource_quotas),
+        };
+        let ipfs_nodes = ipfs_addresses.into_iter().zip(ipfs_clients).collect();
+        return future::Either::A(Box::new(check_config(
+            logger,
+            effective_config,
+            ethereum_networks,
+            &postgres_url,
+            ipfs_nodes,
+        )) as Box<Future<Item = (), Error = ()> + Send>);
+    }
+
+    // Connect to the Ethereum node and set up a chain store for every
+    // network. All stores share the same database, the first one is used
+    // for everything that is independent of the network.
+    let eth_call_cache_in_store =
+        env::var_os("GRAPH_ETH_CALL_CACHE_IN_STORE").unwrap_or("".into()) == "true";
+    let replay = matches.value_of("replay");
+    let mut eth_adapters = EthereumNetworks::new();
+    let mut chain_stores = EthereumNetworks::new();
+    let mut eth_network_capabilities = EthereumNetworks::new();
+    let mut stores = vec![];
+    let mut ws_transports = HashMap::new();
+
+    let mut change_exporter = None;
+
+    for (network_name, providers) in ethereum_networks {
+        let mut eth_net_identifiers = None;
+        let mut network_capabilities = vec![];
+        let mut pool = vec![];
+        for (node_url, kind, options) in providers {
+            // Set up Ethereum transport
+            let transport = ethereum_transport(&logger, &node_url, kind);
+
+            // Stay within the request quota of the provider
+            let transport = match options.requests_per_second {
+                Some(requests_per_second) => {
+                    transport.with_rate_limit(requests_per_second, requests_per_second)
+                }
+                None => transport,
+            };
+
+            // Ask Ethereum node for network identifiers
+            info!(
+                logger, "Connecting to Ethereum...";
+                "network" => &network_name,
+                "node" => &node_url,
+            );
+            let eth_adapter = graph_datasource_ethereum::EthereumAdapter::new(transport.clone());
+            let net_identifiers = match eth_adapter.net_identifiers(&logger).wait() {
+                Ok(net) => {
+                    info!(
+                        logger, "Connected to Ethereum";
+                        "network" => &network_name,
+                        "node" => &node_url,
+                    );
+                    net
+                }
+                Err(e) => {
+                    error!(logger, "Was a valid Ethereum node provided?");
+                    panic!("Failed to connect to Ethereum node: {}", e);
+                }
+            };
+
+            // All providers of a network must be on the same chain, and
+            // that must be the right chain for well-known networks
+            let expected = eth_net_identifiers
+                .clone()
+                .or_else(|| EthereumNetworkIdentifier::well_known(&network_name));
+            if let Some(expected) = expected {
+                if let Err(e) = net_identifiers.check(&expected) {
+                    error!(
+                        logger,
+                        "Ethereum node is not on the chain of its network, refusing to index";
+                        "network" => &network_name,
+                        "node" => &node_url,
+                        "error" => format!("{}", e),
+                    );
+                    panic!(
+                        "Ethereum node `{}` is not on the chain of network `{}`: {}",
+                        node_url, network_name, e
+                    );
+                }
+            }
+            if eth_net_identifiers.is_none() {
+                eth_net_identifiers = Some(net_identifiers.clone());
+            }
+
+            // Find out what the node can do, so that requests that need a
+            // capability go to the nodes that have it
+            let provider_capabilities = match eth_adapter
+                .probe_capabilities(&logger, node_url.clone())
+                .wait()
+            {
+                Ok(provider_capabilities) => provider_capabilities,
+                Err(e) => panic!("Failed to check Ethereum node `{}`: {}", node_url, e),
+            };
+            info!(
+                logger, "Checked Ethereum node capabilities";
+                "network" => &network_name,
+                "node" => &node_url,
+                "archive" => provider_capabilities.archive,
+                "traces" => provider_capabilities.traces,
+                "max_log_range" => format!("{:?}", provider_capabilities.max_log_range),
+            );
+            let mut capabilities = options.capabilities;
+            for (capability, supported) in vec![
+                (ProviderCapability::Archive, provider_capabilities.archive),
+                (ProviderCapability::Traces, provider_capabilities.traces),
+            ] {
+                if capabilities.contains(&capability) {
+                    if !supported {
+                        panic!(
+                            "Ethereum node `{}` is configured with capability {:?}, \
+                             but does not support it",
+                            node_url, capability
+                        );
+                    }
+                } else if supported {
+                    capabilities.push(capability);
+                }
+            }
+            network_capabilities.push(provider_capabilities);
+
+            pool.push(Provider {
+                name: node_url,
+                transport: transport.with_network_identifier(net_identifiers),
+                weight: options.weight,
+                capabilities,
+            });
+        }
+        let eth_net_identifiers = eth_net_identifiers.unwrap();
+
+        let transport = if pool.len() == 1 {
+            pool.pop().unwrap().transport
+        } else {
+            Transport::new_pool(pool, &logger.new(o!("network" => network_name.clone())))
+        };
+        if transport.supports_new_heads() {
+            ws_transports.insert(network_name.clone(), transport.clone());
+        }
+
+        // Set up Store
+        info!(
+            logger, "Connecting to Postgres";
+            "url" => &postgres_url,
+            "network" => &network_name,
+        );
+        let mut store = DieselStore::new(
+            StoreConfig {
+                postgres_url: postgres_url.clone(),
+                network_name: network_name.clone(),
+            },
+            &logger,
+            eth_net_identifiers,
+        );
+
+        // Optionally, publish the entity changes that are written to the
+        // stores, so that other systems can mirror subgraphs. The stores
+        // share the database and with it the outbox, so a single exporter
+        // publishes the changes of all of them
+        if let Some(url) = matches.value_of("entity-change-sink") {
+            let change_exporter = change_exporter.get_or_insert_with(|| {
+                let sink = NatsEntityChangeSink::new(url).expect("invalid entity change sink URL");
+                info!(logger, "Publishing entity changes"; "sink" => url);
+                Arc::new(
+                    EntityChangeExporter::new(
+                        &logger,
+                        Arc::new(store.entity_change_outbox()),
+                        Arc::new(sink),
+                    )
+                    .expect("failed to start exporting entity changes"),
+                )
+            });
+            store = store.with_entity_change_exporter(change_exporter.clone());
+        }
+        let store = Arc::new(store);
+
+        // Create Ethereum adapter
+        let network_capabilities = EthereumNetworkCapabilities {
+            network: network_name.clone(),
+            providers: network_capabilities,
+        };
+        if !network_capabilities.archive() {
+            warn!(
+                logger,
+                "None of the Ethereum nodes of this network is an archive node; \
+                 contract calls against old blocks will fail";
+                "network" => &network_name,
+            );
+        }
+        if !network_capabilities.traces() {
+            warn!(
+                logger,
+                "None of the Ethereum nodes of this network can trace calls; \
+                 subgraphs with call handlers will be rejected";
+                "network" => &network_name,
+            );
+        }
+        let eth_adapter = graph_datasource_ethereum::EthereumAdapter::new(transport)
+            .with_archive(network_capabilities.archive())
+            .with_log_range_limit(network_capabilities.max_log_range());
+        let eth_adapter = if eth_call_cache_in_store || replay.is_some() {
+            eth_adapter.with_call_cache(store.clone())
+        } else {
+            eth_adapter
+        };
+        // Replays must not depend on the current state of the Ethereum node
+        let eth_adapter = if replay.is_some() {
+            eth_adapter.with_cached_calls_only()
+        } else {
+            eth_adapter
+        };
+        let eth_adapter = Arc::new(eth_adapter);
+
+        eth_adapters.insert(network_name.clone(), eth_adapter);
+        chain_stores.insert(network_name.clone(), store.clone());
+        eth_network_capabilities.insert(network_name, Arc::new(network_capabilities));
+        stores.push(store);
+    }
+    let store = stores
+        .into_iter()
+        .next()
+        .expect("at least one Ethereum network must be configured");
+
+    // Replay a subgraph instead of running the node
+    if let Some(replay) = replay {
+        let subgraph_id = SubgraphDeploymentId::new(replay)
+            .expect("Subgraph to replay must be a valid IPFS hash");
+        let output_path = matches.value_of("replay-output").unwrap().to_owned();
+        return future::Either::A(Box::new(replay_from_cache(
+            logger,
+            subgraph_id,
+            output_path,
+            store,
+            chain_stores,
+            eth_adapters,
+            link_resolver,
+        )));
+    }
+
+    // Run a maintenance command instead of running the node
+    if let Some(manage) = matches.subcommand_matches("manage") {
+        let manage = manage.clone();
+        return future::Either::A(Box::new(future::lazy(move || {
+            manage::run(&logger, store, &manage).map_err(|e| eprintln!("error: {}", e))
+        })));
+    }
+
+    // Copy a subgraph from another database instead of running the node
+    if let Some(copy) = matches.subcommand_matches("copy") {
+        let subgraph_id = SubgraphDeploymentId::new(copy.value_of("subgraph").unwrap())
+            .expect("Subgraph to copy must be a valid IPFS hash");
+        let source_url = copy.value_of("from-postgres-url").unwrap().to_owned();
+        if copy.is_present("background") {
+            // Jobs are stored in the database, so they only refer to the
+            // source database without its password
+            let source_url = jobs::without_password(&source_url)
+                .expect("--from-postgres-url must be a Postgres URL");
+            return future::Either::A(Box::new(enqueue_job(
+                logger,
+                store,
+                jobs::Job::Copy {
+                    subgraph_id,
+                    source_url,
+                },
+            )));
+        }
+        return future::Either::A(Box::new(future::lazy(move || -> Result<(), ()> {
+            info!(
+                logger, "Copying subgraph deployment";
+                "subgraph_id" => subgraph_id.to_string(),
+                "from" => hide_password(&source_url),
+            );
+            let count = copy_deployment(&logger, &source_url, &store, &subgraph_id, |_| Ok(()))
+                .expect("Failed to copy subgraph deployment");
+            info!(
+                logger,
+                "Copy complete; deploy the subgraph with `subgraph_deploy` to assign it \
+                 to a node and give it a name";
+                "entities" => count,
+            );
+            Ok(())
+        })));
+    }
+
+    // Export the entities of a subgraph instead of running the node
+    if let Some(export) = matches.subcommand_matches("export") {
+        let subgraph_id = SubgraphDeploymentId::new(export.value_of("subgraph").unwrap())
+            .expect("Subgraph to export must be a valid IPFS hash");
+        let output_dir = PathBuf::from(export.value_of("output-dir").unwrap());
+        let block_number = export.value_of("block").map(|block| {
+            block
+                .parse::<u64>()
+                .expect("--block must be a block number")
+        });
+        if export.is_present("background") {
+            // The job may run on another node, in another directory
+            let output_dir = env::current_dir()
+                .expect("failed to get the current directory")
+                .join(output_dir);
+            return future::Either::A(Box::new(enqueue_job(
+                logger,
+                store,
+                jobs::Job::Export {
+                    subgraph_id,
+                    output_dir,
+                    block_number,
+                },
+            )));
+        }
+        return future::Either::A(Box::new(export_from_store(
+            logger,
+            subgraph_id,
+            block_number,
+            output_dir,
+            store,
+            link_resolver,
+        )));
+    }
+
+    // Prune the entity history of a subgraph instead of running the node
+    if let Some(prune) = matches.subcommand_matches("prune") {
+        let subgraph_id = SubgraphDeploymentId::new(prune.value_of("subgraph").unwrap())
+            .expect("Subgraph to prune must be a valid IPFS hash");
+        let keep_blocks = prune
+            .value_of("keep-blocks")
+            .map(|blocks| {
+                blocks
+                    .parse::<u64>()
+                    .expect("--keep-blocks must be a number of blocks")
+            })
+            .unwrap_or(*REORG_THRESHOLD);
+        if keep_blocks < *REORG_THRESHOLD {
+            panic!(
+                "--keep-blocks must be at least ETHEREUM_REORG_THRESHOLD ({}), \
+                 otherwise blocks that can still be reverted lose their history",
+                *REORG_THRESHOLD
+            );
+        }
+        if prune.is_present("background") {
+            return future::Either::A(Box::new(enqueue_job(
+                logger,
+                store,
+                jobs::Job::Prune {
+                    subgraph_id,
+                    keep_blocks,
+                },
+            )));
+        }
+        return future::Either::A(Box::new(future::lazy(move || -> Result<(), ()> {
+            let logger = logger.new(o!("subgraph_id" => subgraph_id.to_string()));
+            let count = jobs::prune(&logger, &store, &subgraph_id, keep_blocks)
+                .expect("Failed to prune subgraph deployment");
+            info!(logger, "Pruning complete"; "entity_changes" => count);
+            Ok(())
+        })));
+    }
+
+    // Queries and the index node server share the statistics of queries
+    let query_stats = Arc::new(QueryStats::default());
+    let graphql_runner = Arc::new(
+        graph_core::GraphQlRunner::new(&logger, store.clone())
+            .with_query_stats(query_stats.clone()),
+    );
+    let mut graphql_server = GraphQLQueryServer::new(
+        &logger,
+        graphql_runner.clone(),
+        store.clone(),
+        node_id.clone(),
+    );
+    let mut subscription_server =
+        GraphQLSubscriptionServer::new(&logger, graphql_runner.clone(), store.clone());
+    // Handlers record their execution times for the index node server
+    let handler_stats = Arc::new(HandlerStats::default());
+    let mut index_node_server = IndexNodeServer::new(
+        &logger,
+        store.clone(),
+        chain_stores.clone(),
+        query_stats,
+        handler_stats.clone(),
+    );
+
+    // Only nodes that index subgraphs need to follow the chain head
+    if node_role.indexes() && env::var_os("DISABLE_BLOCK_INGESTOR").unwrap_or("".into()) != "true" {
+        // BlockIngestor must be configured to keep at least REORG_THRESHOLD ancestors,
+        // otherwise BlockStream will not work properly.
+        // BlockStream expects the blocks after the reorg threshold to be present in the
+        // database. The reorg thresholds of individual networks are checked when the
+        // config file is loaded.
+        assert!(*ANCESTOR_COUNT >= *REORG_THRESHOLD);
+
+        let ethereum_ws_new_heads =
+            env::var_os("ETHEREUM_WS_NEW_HEADS").unwrap_or("".into()) == "true";
+
+        for (network_name, chain_store) in chain_stores.iter() {
+            let eth_adapter = eth_adapters
+                .get(Some(network_name))
+                .expect("every network has an Ethereum adapter");
+
+            // Create Ethereum block ingestor
+            let block_ingestor = graph_datasource_ethereum::BlockIngestor::new(
+                chain_store.clone(),
+                eth_adapter,
+                *ANCESTOR_COUNT,
+                logger.new(o!("network" => network_name.clone())),
+                block_polling_interval,
+            )
+            .expect("failed to create Ethereum block ingestor");
+
+            // Run the Ethereum block ingestor in the background, following
+            // new chain heads over WebSocket if requested
+            match ws_transports.get(network_name) {
+                Some(transport) if ethereum_ws_new_heads => {
+                    let transport = transport.clone();
+                    tokio::spawn(
+                        block_ingestor.into_subscription_stream(move || transport.new_heads()),
+                    );
+                }
+                _ => {
+                    tokio::spawn(block_ingestor.into_polling_stream());
+                }
+            }
+        }
+    }
+
+    // Index nodes run the long-running operations that were added to the
+    // background job queue, e.g. with `copy --background`
+    if node_role.indexes() {
+        jobs::start_job_worker(&logger, store.clone(), node_id.clone());
+    }
+
+    // Subgraphs are only deployed and indexed on nodes that index; query
+    // nodes read what index nodes write to the shared store
+    let indexing = if node_role.indexes() {
+        // Optionally, fork the subgraphs from another Graph Node, so that
+        // mappings read the entities they don't have yet from there. The
+        // fork store needs to see the writes and reverts of the subgraphs.
+        let fork_base = matches.value_of("fork-base").map(|fork_base| {
+            let fork_base = url::Url::parse(fork_base).expect("invalid fork base URL");
+            warn!(
+                logger,
+                "Forking subgraphs; their entities will be read from another Graph Node";
+                "fork_base" => fork_base.as_str(),
+            );
+            fork_base
+        });
+        let subgraph_store = Arc::new(ForkStore::new(&logger, store.clone(), fork_base));
+
+        // Prepare a block stream builder for subgraphs
+        let block_stream_builder = BlockStreamBuilder::new(
+            subgraph_store.clone(),
+            chain_stores.clone(),
+            eth_adapters.clone(),
+            node_id.clone(),
+            *REORG_THRESHOLD,
+        )
+        .with_network_reorg_thresholds(reorg_thresholds);
+
+        // Optionally, identify the Elasticsearch logging configuration
+        let elastic_config =
+            matches
+                .value_of("elasticsearch-url")
+                .map(|endpoint| ElasticLoggingConfig {
+                    endpoint: endpoint.into(),
+                    username: matches.value_of("elasticsearch-user").map(|s| s.into()),
+                    password: matches.value_of("elasticsearch-password").map(|s| s.into()),
+                });
+
+        // Prepare for hosting WASM runtimes and managing subgraph instances
+        let runtime_host_builder = WASMRuntimeHostBuilder::new(
+            eth_adapters,
+            link_resolver.clone(),
+            subgraph_store.clone(),
+        )
+        .with_handler_stats(handler_stats);
+        let subgraph_instance_manager = SubgraphInstanceManager::new(
+            &logger,
+            subgraph_store,
+            runtime_host_builder,
+            block_stream_builder,
+            elastic_config,
+            resource_quotas,
+        );
+
+        // Create IPFS-based subgraph provider
+        let mut subgraph_provider = IpfsSubgraphAssignmentProvider::new(
+            logger.clone(),
+            link_resolver.clone(),
+            store.clone(),
+        );
+
+        // Forward subgraph events from the subgraph provider to the subgraph instance manager
+        tokio::spawn(forward(&mut subgraph_provider, &subgraph_instance_manager).unwrap());
+
+        // Check version switching mode environment variable; new versions of a
+        // subgraph only replace the current version once they are synced, unless
+        // configured otherwise
+        let version_switching_mode = SubgraphVersionSwitchingMode::parse(
+            env::var_os("SUBGRAPH_VERSION_SWITCHING_MODE")
+                .or_else(|| env::var_os("EXPERIMENTAL_SUBGRAPH_VERSION_SWITCHING_MODE"))
+                .unwrap_or_else(|| "synced".into())
+                .to_str()
+                .expect("invalid version switching mode"),
+        );
+
+        // Create named subgraph provider for resolving subgraph name->ID mappings
+        let subgraph_registrar = Arc::new(IpfsSubgraphRegistrar::new(
+            logger.clone(),
+            link_resolver,
+            Arc::new(subgraph_provider),
+            store.clone(),
+            chain_stores,
+            eth_network_capabilities.clone(),
+            node_id.clone(),
+            version_switching_mode,
+        ));
+        tokio::spawn(subgraph_registrar.start().then(|start_result| {
+            Ok(start_result.expect("failed to initialize subgraph provider"))
+        }));
+
+        // Start admin JSON-RPC server.
+        let json_rpc_server = JsonRpcServer::serve(
+            json_rpc_listen_address,
+            json_rpc_port,
+            json_rpc_auth_token,
+            json_rpc_allowed_ips,
+            http_port,
+            ws_port,
+            subgraph_registrar.clone(),
+            eth_network_capabilities,
+            node_id.clone(),
+            logger.clone(),
+        )
+        .expect("failed to start JSON-RPC admin server");
+
+        // Deploy the subgraphs given with `--subgraph`
+        if !static_subgraphs.is_empty() {
+            tokio::spawn(static_subgraphs::deploy(
+                logger.clone(),
+                subgraph_registrar,
+                store.clone(),
+                static_subgraphs,
+                node_id,
+            ));
+        }
+
+        Some((subgraph_instance_manager, json_rpc_server))
+    } else {
+        if !static_subgraphs.is_empty() {
+            warn!(logger, "Ignoring `--subgraph` on a query node");
+        }
+        None
+    };
+
+    // The GraphQL servers run until this guard is dropped
+    let servers_canceler = CancelGuard::new();
+
+    if node_role.queries() {
+        // Serve GraphQL queries over HTTP
+        tokio::spawn(
+            graphql_server
+                .serve(http_port, ws_port)
+                .expect("Failed to start GraphQL query server")
+                .cancelable(&servers_canceler, || ()),
+        );
+
+        // Serve GraphQL subscriptions over WebSockets
+        tokio::spawn(
+            subscription_server
+                .serve(ws_port)
+                .expect("Failed to start GraphQL subscription server")
+                .cancelable(&servers_canceler, || ()),
+        );
+    }
+
+    // Serve the indexing statuses of subgraphs over HTTP
+    tokio::spawn(
+        index_node_server
+            .serve(index_node_port)
+            .expect("Failed to start index node server")
+            .cancelable(&servers_canceler, || ()),
+    );
+
+    // Run until SIGINT or SIGTERM, then stop indexing once the blocks that
+    // are being processed have been written to the store, and only then
+    // close the servers and exit. A second signal exits right away.
+    let shutdown_logger = logger.clone();
+    future::Either::B(shutdown_signals().into_future().then(move |result| {
+        let signals = match result {
+            Ok((_, signals)) => signals,
+            Err((_, signals)) => signals,
+        };
+        tokio::spawn(signals.into_future().then(|_| -> Result<(), ()> {
+            std::process::exit(1);
+        }));
+
+        info!(
+            shutdown_logger,
+            "Shutting down gracefully, signal again to exit immediately"
+        );
+        let stop_indexing: Box<Future<Item = (), Error = ()> + Send> = match indexing {
+            Some((subgraph_instance_manager, json_rpc_server)) => Box::new(
+                subgraph_instance_manager
+                    .shutdown()
+                    .map(move |()| json_rpc_server.close()),
+            ),
+            None => Box::new(future::ok(())),
+        };
+        stop_indexing.map(move |()| {
+            drop(servers_canceler);
+            info!(shutdown_logger, "Shutdown complete");
+        })
+    }))
+}
+
+/// Creates an IPFS client for one of the addresses `ipfs_address` resolves
+/// to and checks in the background that the IPFS node is running. If the
+/// node is `required`, `graph-node` can't run without it.
+fn create_ipfs_client(logger: &Logger, ipfs_address: &str, required: bool) -> Arc<IpfsClient> {
     let (ipfs_client, ipfs_address) = match ipfs_address
         // Resolve the IPFS address into socket addresses
         .to_socket_addrs()
@@ -338,9 +1635,12 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
             .map_err(move |e| {
                 error!(
                     ipfs_err_logger,
-                    "Is there an IPFS node running at \"{}\"?", ipfs_address
+                    "Is there an IPFS node running at \"{}\"?", ipfs_address;
+                    "error" => format!("{}", e),
                 );
-                panic!("Failed to connect to IPFS: {}", e);
+                if required {
+                    panic!("Failed to connect to IPFS: {}", e);
+                }
             })
             .map(move |_| {
                 info!(
@@ -350,215 +1650,344 @@ fn async_main() -> impl Future<Item = (), Error = ()> + Send + 'static {
             }),
     );
 
-    // Parse the Ethereum URL
-    let (ethereum_network_name, ethereum_node_url) = parse_ethereum_network_and_node(
-        [ethereum_ipc, ethereum_rpc, ethereum_ws]
-            .into_iter()
-            .filter_map(|x| x.to_owned())
-            .next()
-            .expect("one of --ethereum-ipc, --ethereum-rpc or --ethereum-ws must be provided"),
-    )
-    .expect("failed to parse Ethereum connection string");
+    ipfs_client
+}
 
-    // Set up Ethereum transport
-    let (transport_event_loop, transport) = ethereum_ipc
-        .map(|_| Transport::new_ipc(ethereum_node_url))
-        .or_else(|| ethereum_ws.map(|_| Transport::new_ws(ethereum_node_url)))
-        .or_else(|| ethereum_rpc.map(|_| Transport::new_rpc(ethereum_node_url)))
-        .expect("One of --ethereum-ipc, --ethereum-rpc or --ethereum-ws must be provided");
+/// Replaces the password in a Postgres URL, so that it can be printed.
+fn hide_password(postgres_url: &str) -> String {
+    match url::Url::parse(postgres_url) {
+        Ok(mut url) => {
+            if url.password().is_some() {
+                let _ = url.set_password(Some("HIDDEN_PASSWORD"));
+            }
+            url.to_string()
+        }
+        Err(_) => postgres_url.to_owned(),
+    }
+}
+
+/// Creates the transport for an Ethereum node of the given `kind`.
+fn ethereum_transport(logger: &Logger, node_url: &str, kind: &str) -> Transport {
+    let (transport_event_loop, transport) = match kind {
+        "ipc" => Transport::new_ipc(node_url),
+        "ws" => Transport::new_ws(node_url, logger),
+        _ => Transport::new_rpc(node_url),
+    };
 
     // If we drop the event loop the transport will stop working.
     // For now it's fine to just leak it.
     std::mem::forget(transport_event_loop);
 
-    // Create Ethereum adapter
-    let eth_adapter = Arc::new(graph_datasource_ethereum::EthereumAdapter::new(transport));
+    transport
+}
 
-    // Ask Ethereum node for network identifiers
-    info!(
-        logger, "Connecting to Ethereum...";
-        "network" => &ethereum_network_name,
-        "node" => &ethereum_node_url,
-    );
-    let eth_net_identifiers = match eth_adapter.net_identifiers(&logger).wait() {
-        Ok(net) => {
-            info!(
-                logger, "Connected to Ethereum";
-                "network" => &ethereum_network_name,
-                "node" => &ethereum_node_url,
-            );
-            net
-        }
+/// Connects to every Ethereum node, the database and the IPFS nodes, checks
+/// that the Ethereum nodes are on the right chain and have the capabilities
+/// they are configured with, and prints the results followed by the
+/// effective configuration. Fails if any of the checks failed.
+fn check_config(
+    logger: Logger,
+    effective_config: Config,
+    ethereum_networks: Vec<(String, Vec<(String, &'static str, EthereumProviderOptions)>)>,
+    postgres_url: &str,
+    ipfs_nodes: Vec<(&str, Arc<IpfsClient>)>,
+) -> impl Future<Item = (), Error = ()> + Send {
+    let mut failed = false;
+    let mut report = |what: String, result: Result<String, Error>| match result {
+        Ok(details) => println!("ok     {}: {}", what, details),
         Err(e) => {
-            error!(logger, "Was a valid Ethereum node provided?");
-            panic!("Failed to connect to Ethereum node: {}", e);
+            failed = true;
+            println!("failed {}: {}", what, e)
         }
     };
 
-    // Set up Store
-    info!(logger, "Connecting to Postgres"; "url" => &postgres_url);
-    let store = Arc::new(DieselStore::new(
-        StoreConfig {
-            postgres_url,
-            network_name: ethereum_network_name.to_owned(),
-        },
-        &logger,
-        eth_net_identifiers,
-    ));
-    let graphql_runner = Arc::new(graph_core::GraphQlRunner::new(&logger, store.clone()));
-    let mut graphql_server = GraphQLQueryServer::new(
-        &logger,
-        graphql_runner.clone(),
-        store.clone(),
-        node_id.clone(),
-    );
-    let mut subscription_server =
-        GraphQLSubscriptionServer::new(&logger, graphql_runner.clone(), store.clone());
+    for (network_name, providers) in ethereum_networks {
+        let mut network_identifiers: Option<EthereumNetworkIdentifier> = None;
+        for (node_url, kind, options) in providers {
+            let transport = ethereum_transport(&logger, &node_url, kind);
+            let eth_adapter = graph_datasource_ethereum::EthereumAdapter::new(transport);
+            let result = eth_adapter
+                .net_identifiers(&logger)
+                .wait()
+                .map_err(|e| format_err!("failed to connect: {}", e))
+                .and_then(|net_identifiers| {
+                    let expected = network_identifiers
+                        .clone()
+                        .or_else(|| EthereumNetworkIdentifier::well_known(&network_name));
+                    if let Some(expected) = expected {
+                        net_identifiers.check(&expected)?;
+                    }
+                    network_identifiers.get_or_insert(net_identifiers.clone());
 
-    if env::var_os("DISABLE_BLOCK_INGESTOR").unwrap_or("".into()) != "true" {
-        // BlockIngestor must be configured to keep at least REORG_THRESHOLD ancestors,
-        // otherwise BlockStream will not work properly.
-        // BlockStream expects the blocks after the reorg threshold to be present in the
-        // database.
-        assert!(*ANCESTOR_COUNT >= *REORG_THRESHOLD);
+                    let capabilities = eth_adapter
+                        .probe_capabilities(&logger, node_url.clone())
+                        .wait()?;
+                    for capability in options.capabilities.iter() {
+                        let supported = match capability {
+                            ProviderCapability::Archive => capabilities.archive,
+                            ProviderCapability::Traces => capabilities.traces,
+                        };
+                        if !supported {
+                            return Err(format_err!(
+                                "configured with capability `{}`, but does not support it",
+                                capability.as_str()
+                            ));
+                        }
+                    }
+                    Ok(format!(
+                        "net_version {}, genesis block {:x}, archive: {}, traces: {}, \
+                         max log range: {:?}",
+                        net_identifiers.net_version,
+                        net_identifiers.genesis_block_hash,
+                        capabilities.archive,
+                        capabilities.traces,
+                        capabilities.max_log_range
+                    ))
+                });
+            report(
+                format!("Ethereum node {} of network {}", node_url, network_name),
+                result,
+            );
+        }
+    }
 
-        // Create Ethereum block ingestor
-        let block_ingestor = graph_datasource_ethereum::BlockIngestor::new(
-            store.clone(),
-            eth_adapter.clone(),
-            *ANCESTOR_COUNT,
-            logger.clone(),
-            block_polling_interval,
-        )
-        .expect("failed to create Ethereum block ingestor");
+    report(
+        "Postgres".to_owned(),
+        postgres_version(postgres_url).map_err(|e| format_err!("failed to connect: {}", e)),
+    );
 
-        // Run the Ethereum block ingestor in the background
-        tokio::spawn(block_ingestor.into_polling_stream());
+    for (ipfs_address, ipfs_client) in ipfs_nodes {
+        report(
+            format!("IPFS node {}", ipfs_address),
+            ipfs_client
+                .version()
+                .wait()
+                .map(|version| format!("version {}", version.version))
+                .map_err(|e| format_err!("failed to connect: {}", e)),
+        );
     }
 
-    // Prepare a block stream builder for subgraphs
-    let block_stream_builder = BlockStreamBuilder::new(
-        store.clone(),
-        store.clone(),
-        eth_adapter.clone(),
-        node_id.clone(),
-        *REORG_THRESHOLD,
-    );
+    println!();
+    match toml::to_string_pretty(&effective_config) {
+        Ok(text) => print!("{}", text),
+        Err(e) => report("effective configuration".to_owned(), Err(e.into())),
+    }
 
-    // Optionally, identify the Elasticsearch logging configuration
-    let elastic_config =
-        matches
-            .value_of("elasticsearch-url")
-            .map(|endpoint| ElasticLoggingConfig {
-                endpoint: endpoint.into(),
-                username: matches.value_of("elasticsearch-user").map(|s| s.into()),
-                password: matches.value_of("elasticsearch-password").map(|s| s.into()),
-            });
+    if failed {
+        future::err(())
+    } else {
+        future::ok(())
+    }
+}
 
-    // Prepare for hosting WASM runtimes and managing subgraph instances
-    let runtime_host_builder =
-        WASMRuntimeHostBuilder::new(eth_adapter.clone(), ipfs_client.clone(), store.clone());
-    let subgraph_instance_manager = SubgraphInstanceManager::new(
-        &logger,
-        store.clone(),
-        runtime_host_builder,
-        block_stream_builder,
-        elastic_config,
+/// Replays a subgraph deployment up to its current block from the blocks
+/// cached in the chain store and writes its entity operations to
+/// `output_path`.
+fn replay_from_cache<S, C, E>(
+    logger: Logger,
+    subgraph_id: SubgraphDeploymentId,
+    output_path: String,
+    store: Arc<S>,
+    chain_stores: EthereumNetworks<C>,
+    eth_adapters: EthereumNetworks<E>,
+    link_resolver: Arc<FileLinkResolver<HttpLinkResolver<IpfsResolver>>>,
+) -> impl Future<Item = (), Error = ()> + Send
+where
+    S: Store,
+    C: ChainStore,
+    E: EthereumAdapter,
+{
+    let block_ptr = store
+        .block_ptr(subgraph_id.clone())
+        .expect("Subgraph to replay is not deployed");
+    info!(
+        logger, "Replaying subgraph from cached blocks";
+        "subgraph_id" => subgraph_id.to_string(),
+        "block_number" => block_ptr.number,
+        "output" => &output_path,
     );
 
-    // Create IPFS-based subgraph provider
-    let mut subgraph_provider =
-        IpfsSubgraphAssignmentProvider::new(logger.clone(), ipfs_client.clone(), store.clone());
+    SubgraphManifest::resolve(subgraph_id.to_ipfs_link(), link_resolver.clone())
+        .map_err(Error::from)
+        .and_then(move |mut manifest| {
+            load_dynamic_data_sources(&*store, &mut manifest)?;
+            let network_name = manifest.network_name();
+            let chain_store = chain_stores.get(network_name.as_ref().map(String::as_str))?;
+            let blocks = cached_blocks_from_subgraph(chain_store, &manifest, block_ptr)?;
 
-    // Forward subgraph events from the subgraph provider to the subgraph instance manager
-    tokio::spawn(forward(&mut subgraph_provider, &subgraph_instance_manager).unwrap());
+            let replay_store = Arc::new(ReplayStore::new(store, subgraph_id));
+            let host_builder =
+                WASMRuntimeHostBuilder::new(eth_adapters, link_resolver, replay_store.clone());
+            let output = BufWriter::new(File::create(&output_path)?);
+            Ok(replay_subgraph(
+                &logger,
+                manifest,
+                host_builder,
+                replay_store,
+                blocks,
+                output,
+            )
+            .map(move |block_count| {
+                info!(logger, "Replay complete"; "blocks" => block_count);
+            }))
+        })
+        .flatten()
+        .then(|result| Ok(result.expect("Failed to replay subgraph")))
+}
 
-    // Check version switching mode environment variable
-    let version_switching_mode = SubgraphVersionSwitchingMode::parse(
-        env::var_os("EXPERIMENTAL_SUBGRAPH_VERSION_SWITCHING_MODE")
-            .unwrap_or_else(|| "instant".into())
-            .to_str()
-            .expect("invalid version switching mode"),
-    );
+/// Adds `job` to the background job queue instead of running it.
+fn enqueue_job(
+    logger: Logger,
+    store: Arc<DieselStore>,
+    job: jobs::Job,
+) -> impl Future<Item = (), Error = ()> + Send {
+    future::lazy(move || {
+        let id = job
+            .enqueue(&store)
+            .expect("Failed to add job to the background job queue");
+        info!(
+            logger,
+            "Added job to the background job queue; an index node will run it";
+            "job" => id,
+        );
+        Ok(())
+    })
+}
 
-    // Create named subgraph provider for resolving subgraph name->ID mappings
-    let subgraph_registrar = Arc::new(IpfsSubgraphRegistrar::new(
-        logger.clone(),
-        ipfs_client,
-        Arc::new(subgraph_provider),
-        store.clone(),
-        store.clone(),
-        node_id.clone(),
-        version_switching_mode,
-    ));
-    tokio::spawn(
-        subgraph_registrar
-            .start()
-            .then(|start_result| Ok(start_result.expect("failed to initialize subgraph provider"))),
+/// Exports the entities of a subgraph deployment to CSV files in
+/// `output_dir`, as of `block_number` or as they are now.
+fn export_from_store(
+    logger: Logger,
+    subgraph_id: SubgraphDeploymentId,
+    block_number: Option<u64>,
+    output_dir: PathBuf,
+    store: Arc<DieselStore>,
+    link_resolver: Arc<FileLinkResolver<HttpLinkResolver<IpfsResolver>>>,
+) -> impl Future<Item = (), Error = ()> + Send {
+    info!(
+        logger, "Exporting subgraph entities";
+        "subgraph_id" => subgraph_id.to_string(),
+        "block_number" => block_number,
+        "output_dir" => output_dir.display().to_string(),
     );
 
-    // Start admin JSON-RPC server.
-    let json_rpc_server = JsonRpcServer::serve(
-        json_rpc_port,
-        http_port,
-        ws_port,
-        subgraph_registrar.clone(),
-        node_id.clone(),
-        logger.clone(),
-    )
-    .expect("failed to start JSON-RPC admin server");
+    SubgraphManifest::resolve(subgraph_id.to_ipfs_link(), link_resolver)
+        .map_err(Error::from)
+        .and_then(move |manifest| {
+            let count = export::export_entities(
+                &logger,
+                &store,
+                &manifest.schema,
+                block_number,
+                &output_dir,
+                &mut export::ExportProgress::default(),
+                |_| Ok(()),
+            )?;
+            info!(logger, "Export complete"; "entities" => count);
+            Ok(())
+        })
+        .then(|result: Result<(), Error>| Ok(result.expect("Failed to export subgraph")))
+}
 
-    // Let the server run forever.
-    std::mem::forget(json_rpc_server);
+/// What a node does. Query and index nodes share the same store, so that
+/// queries can be served by several query nodes behind a load balancer
+/// while other nodes index subgraphs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum NodeRole {
+    Combined,
+    Query,
+    Index,
+}
 
-    // Add the CLI subgraph with a REST request to the admin server.
-    if let Some(subgraph) = subgraph {
-        let (name, hash) = if subgraph.contains(':') {
-            let mut split = subgraph.split(':');
-            (split.next().unwrap(), split.next().unwrap().to_owned())
-        } else {
-            ("cli", subgraph)
-        };
+impl NodeRole {
+    fn from_str(role: &str) -> Option<Self> {
+        match role {
+            "combined" => Some(NodeRole::Combined),
+            "query" => Some(NodeRole::Query),
+            "index" => Some(NodeRole::Index),
+            _ => None,
+        }
+    }
 
-        let name = SubgraphName::new(name)
-            .expect("Subgraph name must contain only a-z, A-Z, 0-9, '-' and '_'");
-        let subgraph_id =
-            SubgraphDeploymentId::new(hash).expect("Subgraph hash must be a valid IPFS hash");
+    fn as_str(self) -> &'static str {
+        match self {
+            NodeRole::Combined => "combined",
+            NodeRole::Query => "query",
+            NodeRole::Index => "index",
+        }
+    }
 
-        tokio::spawn(
-            subgraph_registrar
-                .create_subgraph(name.clone())
-                .then(
-                    |result| Ok(result.expect("Failed to create subgraph from `--subgraph` flag")),
-                )
-                .and_then(move |_| {
-                    subgraph_registrar.create_subgraph_version(name, subgraph_id, node_id)
-                })
-                .then(|result| {
-                    Ok(result.expect("Failed to deploy subgraph from `--subgraph` flag"))
-                }),
-        );
+    /// Whether the node deploys and indexes subgraphs.
+    fn indexes(self) -> bool {
+        self != NodeRole::Query
     }
 
-    // Serve GraphQL queries over HTTP
-    tokio::spawn(
-        graphql_server
-            .serve(http_port, ws_port)
-            .expect("Failed to start GraphQL query server"),
-    );
+    /// Whether the node serves GraphQL queries and subscriptions.
+    fn queries(self) -> bool {
+        self != NodeRole::Index
+    }
+}
 
-    // Serve GraphQL subscriptions over WebSockets
-    tokio::spawn(
-        subscription_server
-            .serve(ws_port)
-            .expect("Failed to start GraphQL subscription server"),
+/// Returns a stream of the signals that ask `graph-node` to shut down.
+fn shutdown_signals() -> Box<Stream<Item = (), Error = ()> + Send> {
+    let signals = tokio_signal::ctrl_c().flatten_stream();
+
+    #[cfg(unix)]
+    let signals = signals.select(
+        tokio_signal::unix::Signal::new(tokio_signal::unix::SIGTERM)
+            .flatten_stream()
+            .map(|_| ()),
     );
 
-    future::empty()
+    Box::new(signals.map_err(|e| panic!("failed to listen for shutdown signals: {}", e)))
 }
 
 /// Parses an Ethereum connection string and returns the network name and Ethereum node.
-fn parse_ethereum_network_and_node(s: &str) -> Result<(&str, &str), Error> {
+/// Options of an Ethereum provider that are given in its connection string.
+struct EthereumProviderOptions {
+    weight: usize,
+    capabilities: Vec<ProviderCapability>,
+    requests_per_second: Option<u32>,
+}
+
+impl Default for EthereumProviderOptions {
+    fn default() -> Self {
+        EthereumProviderOptions {
+            weight: 1,
+            capabilities: vec![],
+            requests_per_second: None,
+        }
+    }
+}
+
+impl EthereumProviderOptions {
+    /// Parses a comma-separated list of capabilities, `weight=N` and
+    /// `rps=N`, e.g. `archive,traces,weight=2,rps=25`. Returns `None` if `s`
+    /// is not such a list.
+    fn parse(s: &str) -> Option<Self> {
+        let mut options = EthereumProviderOptions::default();
+        for option in s.split(',') {
+            if option.starts_with("weight=") {
+                options.weight = option["weight=".len()..]
+                    .parse()
+                    .ok()
+                    .filter(|weight| *weight > 0)?;
+            } else if option.starts_with("rps=") {
+                options.requests_per_second =
+                    Some(option["rps=".len()..].parse().ok().filter(|rps| *rps > 0)?);
+            } else {
+                options
+                    .capabilities
+                    .push(ProviderCapability::from_str(option)?);
+            }
+        }
+        Some(options)
+    }
+}
+
+fn parse_ethereum_network_and_node(
+    s: &str,
+) -> Result<(&str, EthereumProviderOptions, &str), Error> {
     // Check for common Ethereum node mistakes
     if s.starts_with("wss://") || s.starts_with("http://") || s.starts_with("https://") {
         return Err(format_err!(
@@ -567,7 +1996,7 @@ fn parse_ethereum_network_and_node(s: &str) -> Result<(&str, &str), Error> {
         ));
     }
 
-    // Parse string (format is "NETWORK_NAME:URL")
+    // Parse string (format is "NETWORK_NAME:[OPTIONS:]URL")
     let split_at = s.find(':').ok_or_else(|| {
         format_err!(
             "A network name must be provided alongside the \
@@ -575,7 +2004,7 @@ fn parse_ethereum_network_and_node(s: &str) -> Result<(&str, &str), Error> {
         )
     })?;
     let (name, loc_with_delim) = s.split_at(split_at);
-    let loc = &loc_with_delim[1..];
+    let mut loc = &loc_with_delim[1..];
 
     if name.is_empty() {
         return Err(format_err!(
@@ -583,9 +2012,19 @@ fn parse_ethereum_network_and_node(s: &str) -> Result<(&str, &str), Error> {
         ));
     }
 
+    // Provider options are optional; the scheme of a URL is never a valid
+    // list of options
+    let mut options = EthereumProviderOptions::default();
+    if let Some(split_at) = loc.find(':') {
+        if let Some(parsed) = EthereumProviderOptions::parse(&loc[..split_at]) {
+            options = parsed;
+            loc = &loc[split_at + 1..];
+        }
+    }
+
     if loc.is_empty() {
         return Err(format_err!("Ethereum node URL cannot be an empty string"));
     }
 
-    Ok((name, loc))
+    Ok((name, options, loc))
 }
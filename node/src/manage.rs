@@ -0,0 +1,191 @@
+use clap::ArgMatches;
+
+use graph::data::subgraph::schema::*;
+use graph::prelude::*;
+use graph_core::{reassign_subgraph, remove_subgraph, unassign_subgraph};
+use graph_store_postgres::Store as DieselStore;
+
+/// Runs a `graph-node manage` subcommand against the store and prints its
+/// results.
+pub fn run(logger: &Logger, store: Arc<DieselStore>, matches: &ArgMatches) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("info", Some(matches)) => {
+            for deployment in deployments(&store, matches.value_of("deployment").unwrap())? {
+                print_info(&store, &deployment)?;
+            }
+        }
+        ("stats", Some(matches)) => {
+            let deployment = deployment_id(matches.value_of("deployment").unwrap())?;
+            let counts = store.entity_counts(&deployment)?;
+            for (entity_type, count) in counts.iter() {
+                println!("{:<24} {}", entity_type, count);
+            }
+            println!(
+                "{:<24} {}",
+                "total",
+                counts.iter().map(|(_, count)| count).sum::<u64>()
+            );
+            println!(
+                "{:<24} {}",
+                "history",
+                store.entity_history_count(&deployment)?
+            );
+        }
+        ("unassign", Some(matches)) => {
+            let deployment = deployment_id(matches.value_of("deployment").unwrap())?;
+            unassign_subgraph(logger, store, deployment.clone())?;
+            println!("unassigned {}", deployment);
+        }
+        ("reassign", Some(matches)) => {
+            let deployment = deployment_id(matches.value_of("deployment").unwrap())?;
+            let node = matches.value_of("node").unwrap();
+            let node_id =
+                NodeId::new(node).map_err(|()| format_err!("invalid node ID: {}", node))?;
+            reassign_subgraph(logger, store, deployment.clone(), node_id.clone())?;
+            println!("reassigned {} to {}", deployment, node_id);
+        }
+        ("remove", Some(matches)) => {
+            let name = matches.value_of("name").unwrap();
+            let name = SubgraphName::new(name)
+                .map_err(|()| format_err!("invalid subgraph name: {}", name))?;
+            remove_subgraph(logger, store, name.clone())?;
+            println!("removed {}", name);
+        }
+        ("unused", Some(matches)) => match matches.subcommand() {
+            ("record", Some(_)) => {
+                store.record_unused_deployments()?;
+                for (deployment, entity_count) in store.unused_deployments()? {
+                    println!("{} ({} entities)", deployment, entity_count);
+                }
+            }
+            ("remove", Some(matches)) => {
+                let deployments = match matches.values_of("deployment") {
+                    Some(deployments) => deployments
+                        .map(deployment_id)
+                        .collect::<Result<Vec<_>, _>>()?,
+                    None => store
+                        .unused_deployments()?
+                        .into_iter()
+                        .map(|(deployment, _)| deployment)
+                        .collect(),
+                };
+                for deployment in deployments {
+                    if store.remove_unused_deployment(&deployment)? {
+                        println!("removed {}", deployment);
+                    } else {
+                        println!("kept {}, it is in use again", deployment);
+                    }
+                }
+            }
+            _ => unreachable!("clap requires an `unused` subcommand"),
+        },
+        _ => unreachable!("clap requires a `manage` subcommand"),
+    }
+    Ok(())
+}
+
+fn deployment_id(deployment: &str) -> Result<SubgraphDeploymentId, Error> {
+    SubgraphDeploymentId::new(deployment)
+        .map_err(|()| format_err!("invalid subgraph deployment ID: {}", deployment))
+}
+
+/// Returns the deployment with the ID `deployment`, or the current and
+/// pending deployments of the subgraph with the name `deployment`.
+fn deployments(store: &DieselStore, deployment: &str) -> Result<Vec<SubgraphDeploymentId>, Error> {
+    if let Ok(name) = SubgraphName::new(deployment) {
+        if let Some(subgraph) = store.find_one(
+            SubgraphEntity::query().filter(EntityFilter::new_equal("name", name.to_string())),
+        )? {
+            let mut deployments = vec![];
+            for field in &["currentVersion", "pendingVersion"] {
+                let version_id = match subgraph.get(*field) {
+                    Some(Value::String(version_id)) => version_id.to_owned(),
+                    _ => continue,
+                };
+                if let Some(Value::String(deployment)) = store
+                    .get(SubgraphVersionEntity::key(version_id))?
+                    .as_ref()
+                    .and_then(|version| version.get("deployment"))
+                {
+                    deployments.push(deployment_id(deployment)?);
+                }
+            }
+            return Ok(deployments);
+        }
+    }
+
+    let deployment = deployment_id(deployment)?;
+    if store
+        .get(SubgraphDeploymentEntity::key(deployment.clone()))?
+        .is_none()
+    {
+        bail!("subgraph deployment not found: {}", deployment);
+    }
+    Ok(vec![deployment])
+}
+
+fn print_info(store: &DieselStore, deployment: &SubgraphDeploymentId) -> Result<(), Error> {
+    let entity = store
+        .get(SubgraphDeploymentEntity::key(deployment.clone()))?
+        .ok_or_else(|| format_err!("subgraph deployment not found: {}", deployment))?;
+    let field = |name: &str| {
+        entity
+            .get(name)
+            .map_or("-".to_owned(), |value| value.to_string())
+    };
+
+    // The names of the subgraphs the deployment is a version of
+    let mut names = vec![];
+    let versions = store.find(
+        SubgraphVersionEntity::query().filter(EntityFilter::new_equal(
+            "deployment",
+            deployment.to_string(),
+        )),
+    )?;
+    for version in versions {
+        let version_id = version.id()?;
+        let subgraph = match version.get("subgraph") {
+            Some(Value::String(subgraph_id)) => {
+                store.get(SubgraphEntity::key(subgraph_id.to_owned()))?
+            }
+            _ => None,
+        };
+        if let Some(subgraph) = subgraph {
+            let status = if subgraph.get("currentVersion") == Some(&Value::from(version_id.clone()))
+            {
+                "current"
+            } else if subgraph.get("pendingVersion") == Some(&Value::from(version_id)) {
+                "pending"
+            } else {
+                "previous"
+            };
+            if let Some(Value::String(name)) = subgraph.get("name") {
+                names.push(format!("{} ({})", name, status));
+            }
+        }
+    }
+
+    let node = store
+        .get(SubgraphDeploymentAssignmentEntity::key(deployment.clone()))?
+        .and_then(|assignment| assignment.get("nodeId").map(|node| node.to_string()))
+        .unwrap_or_else(|| "unassigned".to_owned());
+
+    println!("{:<14} {}", "deployment", deployment);
+    println!("{:<14} {}", "names", names.join(", "));
+    println!("{:<14} {}", "node", node);
+    println!("{:<14} {}", "synced", field("synced"));
+    println!("{:<14} {}", "failed", field("failed"));
+    println!(
+        "{:<14} #{} (0x{})",
+        "latest block",
+        field("latestEthereumBlockNumber"),
+        field("latestEthereumBlockHash")
+    );
+    println!(
+        "{:<14} {}",
+        "entities",
+        store.count_entities(deployment.clone())?
+    );
+    println!();
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use graph::data::subgraph::schema::{SubgraphEntity, SubgraphVersionEntity};
+use graph::prelude::*;
+
+/// Deploys the subgraphs given with `--subgraph` to this node, creating
+/// their names if needed. Subgraphs whose current or pending version is the
+/// given deployment already are left alone, so that restarting the node
+/// doesn't add new versions. Fails the node if a subgraph can't be deployed.
+pub fn deploy<R, S>(
+    logger: Logger,
+    registrar: Arc<R>,
+    store: Arc<S>,
+    subgraphs: Vec<(SubgraphName, SubgraphDeploymentId)>,
+    node_id: NodeId,
+) -> impl Future<Item = (), Error = ()> + Send
+where
+    R: SubgraphRegistrar,
+    S: Store,
+{
+    stream::iter_ok(subgraphs).for_each(move |(name, subgraph_id)| {
+        let logger = logger.new(o!(
+            "subgraph_name" => name.to_string(),
+            "subgraph_id" => subgraph_id.to_string(),
+        ));
+        let deployments = subgraph_deployments(&*store, &name)
+            .expect("Failed to look up subgraph from `--subgraph` flag");
+
+        let create = match deployments {
+            Some(ref deployments) if deployments.contains(&subgraph_id) => {
+                info!(
+                    logger,
+                    "Subgraph from `--subgraph` flag is deployed already"
+                );
+                return future::Either::A(future::ok(()));
+            }
+            Some(_) => future::Either::A(future::ok(())),
+            None => future::Either::B(registrar.create_subgraph(name.clone()).then(|result| {
+                result.expect("Failed to create subgraph from `--subgraph` flag");
+                Ok(())
+            })),
+        };
+
+        let registrar = registrar.clone();
+        let node_id = node_id.clone();
+        future::Either::B(
+            create
+                .and_then(move |()| {
+                    registrar
+                        .create_subgraph_version(name, subgraph_id, node_id)
+                        .then(|result| {
+                            Ok(result.expect("Failed to deploy subgraph from `--subgraph` flag"))
+                        })
+                })
+                .map(move |()| info!(logger, "Deployed subgraph from `--subgraph` flag")),
+        )
+    })
+}
+
+/// Returns the deployments of the current and pending version of the
+/// subgraph `name`, or `None` if there is no subgraph with that name.
+fn subgraph_deployments<S: Store>(
+    store: &S,
+    name: &SubgraphName,
+) -> Result<Option<Vec<SubgraphDeploymentId>>, Error> {
+    let subgraph = match store.find_one(
+        SubgraphEntity::query().filter(EntityFilter::new_equal("name", name.to_string())),
+    )? {
+        Some(subgraph) => subgraph,
+        None => return Ok(None),
+    };
+
+    let mut deployments = vec![];
+    for field in &["currentVersion", "pendingVersion"] {
+        let version_id = match subgraph.get(*field) {
+            Some(Value::String(version_id)) => version_id.to_owned(),
+            _ => continue,
+        };
+        if let Some(Value::String(deployment)) = store
+            .get(SubgraphVersionEntity::key(version_id))?
+            .as_ref()
+            .and_then(|version| version.get("deployment"))
+        {
+            deployments.push(
+                SubgraphDeploymentId::new(deployment.as_str())
+                    .map_err(|()| format_err!("invalid subgraph deployment ID: {}", deployment))?,
+            );
+        }
+    }
+    Ok(Some(deployments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_mock::MockStore;
+    use std::sync::Mutex;
+
+    /// A registrar that records subgraphs and versions in a store like the
+    /// real one does, and remembers what it was asked to do.
+    struct TestRegistrar {
+        store: Arc<MockStore>,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl TestRegistrar {
+        fn write(&self, operations: Vec<EntityOperation>) {
+            self.store
+                .apply_entity_operations(operations, EventSource::None)
+                .unwrap();
+        }
+    }
+
+    type RegistrarFuture<T> = Box<Future<Item = T, Error = SubgraphRegistrarError> + Send>;
+
+    impl SubgraphRegistrar for TestRegistrar {
+        fn create_subgraph(&self, name: SubgraphName) -> RegistrarFuture<CreateSubgraphResult> {
+            self.calls.lock().unwrap().push(format!("create {}", name));
+            let id = name.to_string();
+            self.write(SubgraphEntity::new(name, None, None, 0).write_operations(&id));
+            Box::new(future::ok(CreateSubgraphResult { id }))
+        }
+
+        fn create_subgraph_version(
+            &self,
+            name: SubgraphName,
+            hash: SubgraphDeploymentId,
+            _: NodeId,
+        ) -> RegistrarFuture<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("deploy {} to {}", hash, name));
+            let id = name.to_string();
+            let version_id = format!("{}-{}", id, hash);
+            self.write(
+                SubgraphVersionEntity::new(id.clone(), hash, 0).write_operations(&version_id),
+            );
+            self.write(SubgraphEntity::new(name, Some(version_id), None, 0).write_operations(&id));
+            Box::new(future::ok(()))
+        }
+
+        fn validate_subgraph_version(&self, _: SubgraphDeploymentId) -> RegistrarFuture<()> {
+            unimplemented!()
+        }
+
+        fn remove_subgraph(&self, _: SubgraphName) -> RegistrarFuture<()> {
+            unimplemented!()
+        }
+
+        fn list_subgraphs(&self) -> RegistrarFuture<Vec<SubgraphName>> {
+            unimplemented!()
+        }
+
+        fn rewind_subgraph(
+            &self,
+            _: SubgraphDeploymentId,
+            _: EthereumBlockPointer,
+            _: Option<IdempotencyKey>,
+        ) -> RegistrarFuture<()> {
+            unimplemented!()
+        }
+
+        fn list_assignments(
+            &self,
+            _: Option<NodeId>,
+        ) -> RegistrarFuture<Vec<(SubgraphDeploymentId, NodeId)>> {
+            unimplemented!()
+        }
+
+        fn set_deployment_enabled(
+            &self,
+            _: SubgraphDeploymentId,
+            _: Option<bool>,
+            _: Option<bool>,
+        ) -> RegistrarFuture<()> {
+            unimplemented!()
+        }
+
+        fn reassign_subgraph(&self, _: SubgraphDeploymentId, _: NodeId) -> RegistrarFuture<()> {
+            unimplemented!()
+        }
+
+        fn apply_operations(
+            &self,
+            _: Vec<SubgraphRegistrarOperation>,
+            _: Option<IdempotencyKey>,
+        ) -> RegistrarFuture<Vec<Option<CreateSubgraphResult>>> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn redeploying_static_subgraphs_is_idempotent() {
+        let store = Arc::new(MockStore::new(vec![]));
+        let registrar = Arc::new(TestRegistrar {
+            store: store.clone(),
+            calls: Mutex::new(vec![]),
+        });
+        let deploy_subgraphs = |subgraphs: Vec<(&str, &str)>| {
+            let subgraphs = subgraphs
+                .into_iter()
+                .map(|(name, id)| {
+                    (
+                        SubgraphName::new(name).unwrap(),
+                        SubgraphDeploymentId::new(id).unwrap(),
+                    )
+                })
+                .collect();
+            deploy(
+                Logger::root(slog::Discard, o!()),
+                registrar.clone(),
+                store.clone(),
+                subgraphs,
+                NodeId::new("test").unwrap(),
+            )
+            .wait()
+            .unwrap();
+            registrar
+                .calls
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            deploy_subgraphs(vec![("static", "QmFirst")]),
+            vec!["create static", "deploy QmFirst to static"]
+        );
+
+        // Restarting the node with the same subgraphs deploys nothing
+        assert!(deploy_subgraphs(vec![("static", "QmFirst")]).is_empty());
+
+        // A new deployment of a subgraph becomes a new version of it
+        assert_eq!(
+            deploy_subgraphs(vec![("static", "QmSecond")]),
+            vec!["deploy QmSecond to static"]
+        );
+        assert!(deploy_subgraphs(vec![("static", "QmSecond")]).is_empty());
+    }
+}
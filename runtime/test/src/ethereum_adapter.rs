@@ -0,0 +1,194 @@
+use std::sync::Mutex;
+
+use graph::components::ethereum::{EthereumContractCall, EthereumContractCallError};
+use graph::ethabi::Token;
+use graph::prelude::*;
+use graph::web3::types::{Address, Block, Log, Transaction, H256};
+
+/// A response to an `ethereum.call` of a mapping.
+struct MockCall {
+    address: Address,
+    function: String,
+    args: Vec<Token>,
+    result: Vec<Token>,
+}
+
+/// An Ethereum adapter that answers the contract calls of mappings with
+/// the responses they were mocked with. Mappings have no other way to
+/// reach Ethereum, so all other methods panic.
+#[derive(Default)]
+pub struct MockEthereumAdapter {
+    calls: Mutex<Vec<MockCall>>,
+}
+
+impl MockEthereumAdapter {
+    /// Answers calls of `function` of the contract at `address` with
+    /// `args` with `result`. Mocking the same call again replaces the
+    /// previous response.
+    pub fn mock_call(
+        &self,
+        address: Address,
+        function: &str,
+        args: Vec<Token>,
+        result: Vec<Token>,
+    ) {
+        let mut calls = self.calls.lock().unwrap();
+        calls.retain(|call| {
+            !(call.address == address && call.function == function && call.args == args)
+        });
+        calls.push(MockCall {
+            address,
+            function: function.to_owned(),
+            args,
+            result,
+        });
+    }
+}
+
+impl EthereumAdapter for MockEthereumAdapter {
+    fn net_identifiers(
+        &self,
+        _: &Logger,
+    ) -> Box<Future<Item = EthereumNetworkIdentifier, Error = Error> + Send> {
+        unimplemented!();
+    }
+
+    fn latest_block(
+        &self,
+        _: &Logger,
+    ) -> Box<Future<Item = Block<Transaction>, Error = EthereumAdapterError> + Send> {
+        unimplemented!();
+    }
+
+    fn block_by_hash(
+        &self,
+        _: &Logger,
+        _: H256,
+    ) -> Box<Future<Item = Option<Block<Transaction>>, Error = Error> + Send> {
+        unimplemented!();
+    }
+
+    fn block_header_by_hash(
+        &self,
+        _: &Logger,
+        _: H256,
+    ) -> Box<Future<Item = Option<Block<Transaction>>, Error = Error> + Send> {
+        unimplemented!();
+    }
+
+    fn load_full_block(
+        &self,
+        _: &Logger,
+        _: Block<Transaction>,
+    ) -> Box<Future<Item = EthereumBlock, Error = EthereumAdapterError> + Send> {
+        unimplemented!();
+    }
+
+    fn block_parent_hash_by_block_hash(
+        &self,
+        _: &Logger,
+        _: H256,
+    ) -> Box<Future<Item = Option<H256>, Error = Error> + Send> {
+        unimplemented!();
+    }
+
+    fn block_hash_by_block_number(
+        &self,
+        _: &Logger,
+        _: u64,
+    ) -> Box<Future<Item = Option<H256>, Error = Error> + Send> {
+        unimplemented!();
+    }
+
+    fn is_on_main_chain(
+        &self,
+        _: &Logger,
+        _: EthereumBlockPointer,
+    ) -> Box<Future<Item = bool, Error = Error> + Send> {
+        unimplemented!();
+    }
+
+    fn calls_in_block(
+        &self,
+        _: &Logger,
+        _: u64,
+        _: H256,
+    ) -> Box<Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
+        unimplemented!();
+    }
+
+    fn logs_in_block(
+        &self,
+        _: &Logger,
+        _: u64,
+        _: H256,
+    ) -> Box<Future<Item = Vec<Log>, Error = Error> + Send> {
+        unimplemented!();
+    }
+
+    fn blocks_with_triggers(
+        &self,
+        _: &Logger,
+        _: u64,
+        _: u64,
+        _: Option<EthereumLogFilter>,
+        _: Option<EthereumCallFilter>,
+        _: Option<EthereumBlockFilter>,
+    ) -> Box<Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        unimplemented!();
+    }
+
+    fn blocks_with_logs(
+        &self,
+        _: &Logger,
+        _: u64,
+        _: u64,
+        _: EthereumLogFilter,
+    ) -> Box<Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        unimplemented!();
+    }
+
+    fn blocks_with_calls(
+        &self,
+        _: &Logger,
+        _: u64,
+        _: u64,
+        _: EthereumCallFilter,
+    ) -> Box<Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        unimplemented!();
+    }
+
+    fn blocks(
+        &self,
+        _: &Logger,
+        _: u64,
+        _: u64,
+    ) -> Box<Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        unimplemented!();
+    }
+
+    fn contract_call(
+        &self,
+        _: &Logger,
+        call: EthereumContractCall,
+    ) -> Box<Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send> {
+        let calls = self.calls.lock().unwrap();
+        let result = calls
+            .iter()
+            .find(|mock| {
+                mock.address == call.address
+                    && mock.function == call.function.name
+                    && mock.args == call.args
+            })
+            .map(|mock| mock.result.clone())
+            .ok_or_else(|| {
+                EthereumContractCallError::CallError(format_err!(
+                    "no response mocked for call of `{}` with {:?} on contract {:x}",
+                    call.function.name,
+                    call.args,
+                    call.address
+                ))
+            });
+        Box::new(future::result(result))
+    }
+}
@@ -0,0 +1,458 @@
+//! Unit tests for the mappings of a subgraph, without a node, a database or
+//! an Ethereum node.
+//!
+//! A `MappingTest` loads a data source of a subgraph manifest on disk and
+//! runs its handlers in the WASM runtime of Graph Node, against an
+//! in-memory store and Ethereum calls that respond with mocked values:
+//!
+//! ```ignore
+//! let mut test = MappingTest::new("./subgraph.yaml", "Gravity")?;
+//! test.mock_call(gravity, "getGravatar", vec![id.clone()], vec![name, url]);
+//! let operations = test.handle_event("NewGravatar(uint256,address,string,string)", params)?;
+//! assert_eq!(test.get_entity("Gravatar", "0x1")?.unwrap().get("displayName"), Some(&name));
+//! ```
+
+extern crate graph;
+extern crate graph_mock;
+extern crate graph_runtime_wasm;
+extern crate tiny_keccak;
+
+mod ethereum_adapter;
+
+use std::path::Path;
+
+use graph::components::link_resolver::StreamValue;
+use graph::ethabi::{self, Event, ParamType, Token};
+use graph::prelude::{
+    RuntimeHost as RuntimeHostTrait, RuntimeHostBuilder as RuntimeHostBuilderTrait, *,
+};
+use graph::tokio::runtime::Runtime;
+use graph::util::ethereum::{contract_event_with_signature, contract_function_with_signature};
+use graph::web3::types::{Address, Bytes, Log, Transaction, H256, U256};
+use graph_mock::MockStore;
+use graph_runtime_wasm::{RuntimeHost, RuntimeHostBuilder};
+use tiny_keccak::keccak256;
+
+pub use self::ethereum_adapter::MockEthereumAdapter;
+
+/// The deployment ID the subgraph under test is loaded as.
+const TEST_SUBGRAPH_ID: &str = "mappingTest";
+
+/// Resolves no links, so that a subgraph under test can only read files
+/// from disk.
+struct NoLinkResolver;
+
+impl LinkResolver for NoLinkResolver {
+    fn cat(&self, link: &Link) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+        Box::new(future::err(format_err!(
+            "only `file:` links can be resolved in mapping tests: {}",
+            link.link
+        )))
+    }
+
+    fn json_stream(
+        &self,
+        link: &Link,
+    ) -> Box<
+        Future<
+                Item = Box<Stream<Item = StreamValue, Error = failure::Error> + Send + 'static>,
+                Error = failure::Error,
+            > + Send
+            + 'static,
+    > {
+        Box::new(self.cat(link).map(|_| unreachable!()))
+    }
+}
+
+/// Runs the handlers of one data source of a subgraph against an
+/// in-memory store. The entity operations of each handler are applied to
+/// the store, so later handlers see the entities earlier handlers wrote.
+pub struct MappingTest {
+    runtime: Runtime,
+    logger: Logger,
+    subgraph_id: SubgraphDeploymentId,
+    data_source: DataSource,
+    store: Arc<MockStore>,
+    ethereum_adapter: Arc<MockEthereumAdapter>,
+    host: RuntimeHost,
+    block: Arc<EthereumBlock>,
+    transaction: Arc<Transaction>,
+}
+
+impl MappingTest {
+    /// Loads the data source `data_source` of the subgraph manifest at
+    /// `manifest`. The manifest refers to its files with links of the form
+    /// `{ /: "file:./mapping.wasm" }`, where relative paths are taken
+    /// relative to the working directory.
+    pub fn new(manifest: impl AsRef<Path>, data_source: &str) -> Result<Self, Error> {
+        let mut runtime = Runtime::new()?;
+        let logger = Logger::root(slog::Discard, o!());
+        let subgraph_id = SubgraphDeploymentId::new(TEST_SUBGRAPH_ID).unwrap();
+
+        let link_resolver = Arc::new(
+            FileLinkResolver::new(Arc::new(NoLinkResolver))
+                .with_local_subgraph(TEST_SUBGRAPH_ID.to_owned(), manifest.as_ref().to_owned()),
+        );
+        let manifest = runtime.block_on(SubgraphManifest::resolve(
+            Link {
+                link: TEST_SUBGRAPH_ID.to_owned(),
+            },
+            link_resolver.clone(),
+        ))?;
+        let external_hosts = Arc::new(ExternalHosts::from_manifest(&manifest)?);
+        let templates = Arc::new(manifest.templates.clone());
+        let data_source = manifest
+            .data_sources
+            .into_iter()
+            .find(|candidate| candidate.name == data_source)
+            .ok_or_else(|| format_err!("no data source named `{}` in the manifest", data_source))?;
+
+        let store = Arc::new(MockStore::new(vec![(subgraph_id.clone(), manifest.schema)]));
+        let ethereum_adapter = Arc::new(MockEthereumAdapter::default());
+        let mut ethereum_adapters = EthereumNetworks::new();
+        ethereum_adapters.insert(
+            data_source
+                .network
+                .clone()
+                .unwrap_or_else(|| "mainnet".to_owned()),
+            ethereum_adapter.clone(),
+        );
+
+        // Runtime hosts spawn the threads their handlers run on with Tokio
+        let host_builder = RuntimeHostBuilder::new(ethereum_adapters, link_resolver, store.clone());
+        let host_logger = logger.clone();
+        let host_subgraph_id = subgraph_id.clone();
+        let host_data_source = data_source.clone();
+        let host = runtime.block_on(future::lazy(move || {
+            host_builder.build(
+                &host_logger,
+                host_subgraph_id,
+                host_data_source,
+                templates,
+                external_hosts,
+                Arc::new(DeploymentQuotas::default()),
+            )
+        }))?;
+
+        let mut block = EthereumBlock::default();
+        block.block.number = Some(1u64.into());
+        let transaction = Transaction {
+            hash: H256::zero(),
+            nonce: U256::zero(),
+            block_hash: block.block.hash,
+            block_number: Some(1u64.into()),
+            transaction_index: Some(0u64.into()),
+            from: Address::zero(),
+            to: data_source.source.address,
+            value: U256::zero(),
+            gas_price: U256::zero(),
+            gas: U256::zero(),
+            input: Bytes::default(),
+        };
+
+        Ok(MappingTest {
+            runtime,
+            logger,
+            subgraph_id,
+            data_source,
+            store,
+            ethereum_adapter,
+            host,
+            block: Arc::new(block),
+            transaction: Arc::new(transaction),
+        })
+    }
+
+    /// Sets the block that the following triggers happen in. Without this,
+    /// triggers happen in block #1.
+    pub fn set_block(&mut self, block: EthereumBlock) {
+        self.block = Arc::new(block);
+    }
+
+    /// Sets the transaction that the following events and calls are part
+    /// of.
+    pub fn set_transaction(&mut self, transaction: Transaction) {
+        self.transaction = Arc::new(transaction);
+    }
+
+    /// Writes `entity` to the store, e.g. to set up the entities that a
+    /// handler updates.
+    pub fn set_entity(&self, entity_type: &str, id: &str, entity: Entity) -> Result<(), Error> {
+        self.store.apply_entity_operations(
+            vec![EntityOperation::Set {
+                key: self.key(entity_type, id),
+                data: entity,
+            }],
+            EventSource::None,
+        )?;
+        Ok(())
+    }
+
+    /// Returns the entity of type `entity_type` with ID `id`, with all
+    /// changes the handlers made so far.
+    pub fn get_entity(&self, entity_type: &str, id: &str) -> Result<Option<Entity>, Error> {
+        Ok(self.store.get(self.key(entity_type, id))?)
+    }
+
+    /// Answers calls of `function` of the contract at `address` with `args`
+    /// with `result`. Calls that weren't mocked fail.
+    pub fn mock_call(
+        &self,
+        address: Address,
+        function: &str,
+        args: Vec<Token>,
+        result: Vec<Token>,
+    ) {
+        self.ethereum_adapter
+            .mock_call(address, function, args, result)
+    }
+
+    /// Builds the log that the data source contract emits for the event
+    /// with the signature `event`, e.g. `Transfer(address,address,uint256)`,
+    /// with the parameters `params`.
+    pub fn event_log(&self, event: &str, params: Vec<Token>) -> Result<Log, Error> {
+        let event_abi = contract_event_with_signature(&self.contract_abi()?.contract, event)
+            .ok_or_else(|| format_err!("no event `{}` in the contract ABI", event))?;
+        let address = self.data_source.source.address.unwrap_or_default();
+        let mut log = encode_log(event_abi, address, params)?;
+        log.block_hash = self.block.block.hash;
+        log.block_number = Some(self.block_number().into());
+        log.transaction_hash = Some(self.transaction.hash);
+        log.transaction_index = self.transaction.transaction_index;
+        Ok(log)
+    }
+
+    /// Runs the handler for the event with the signature `event` and the
+    /// parameters `params`, and returns the entity operations it made.
+    pub fn handle_event(
+        &mut self,
+        event: &str,
+        params: Vec<Token>,
+    ) -> Result<Vec<EntityOperation>, Error> {
+        let log = self.event_log(event, params)?;
+        self.handle_log(log)
+    }
+
+    /// Runs the handler for `log` and returns the entity operations it
+    /// made.
+    pub fn handle_log(&mut self, log: Log) -> Result<Vec<EntityOperation>, Error> {
+        if !self.host.matches_log(&log) {
+            bail!("no handler of the data source matches the log");
+        }
+        let future = self.host.process_log(
+            self.logger.clone(),
+            self.block.clone(),
+            self.transaction.clone(),
+            Arc::new(log),
+            vec![],
+        );
+        self.apply(future)
+    }
+
+    /// Runs the handler for a call of the function with the signature
+    /// `function` of the data source contract, e.g.
+    /// `transfer(address,uint256)`, and returns the entity operations it
+    /// made.
+    pub fn handle_call(
+        &mut self,
+        function: &str,
+        inputs: Vec<Token>,
+        outputs: Vec<Token>,
+    ) -> Result<Vec<EntityOperation>, Error> {
+        let function_abi =
+            contract_function_with_signature(&self.contract_abi()?.contract, function)
+                .ok_or_else(|| format_err!("no function `{}` in the contract ABI", function))?;
+        let call = EthereumCall {
+            from: self.transaction.from,
+            to: self.data_source.source.address.unwrap_or_default(),
+            value: U256::zero(),
+            gas_used: U256::zero(),
+            input: Bytes(
+                function_abi
+                    .encode_input(&inputs)
+                    .map_err(|e| format_err!("invalid inputs for `{}`: {}", function, e))?,
+            ),
+            output: Bytes(ethabi::encode(&outputs)),
+            block_number: self.block_number(),
+            block_hash: self.block.block.hash.unwrap_or_default(),
+            transaction_hash: Some(self.transaction.hash),
+        };
+        if !self.host.matches_call(&call) {
+            bail!(
+                "no handler of the data source matches the call of `{}`",
+                function
+            );
+        }
+        let future = self.host.process_call(
+            self.logger.clone(),
+            self.block.clone(),
+            self.transaction.clone(),
+            Arc::new(call),
+            vec![],
+        );
+        self.apply(future)
+    }
+
+    /// Runs the block handler for `trigger_type` and returns the entity
+    /// operations it made.
+    pub fn handle_block(
+        &mut self,
+        trigger_type: EthereumBlockTriggerType,
+    ) -> Result<Vec<EntityOperation>, Error> {
+        let future = self.host.process_block(
+            self.logger.clone(),
+            self.block.clone(),
+            trigger_type,
+            vec![],
+        );
+        self.apply(future)
+    }
+
+    /// Waits for a handler and applies its entity operations to the store.
+    fn apply(
+        &mut self,
+        future: Box<Future<Item = Vec<EntityOperation>, Error = Error> + Send>,
+    ) -> Result<Vec<EntityOperation>, Error> {
+        let operations = self.runtime.block_on(future)?;
+        self.store
+            .apply_entity_operations(operations.clone(), EventSource::None)?;
+        Ok(operations)
+    }
+
+    fn key(&self, entity_type: &str, id: &str) -> EntityKey {
+        EntityKey {
+            subgraph_id: self.subgraph_id.clone(),
+            entity_type: entity_type.to_owned(),
+            entity_id: id.to_owned(),
+        }
+    }
+
+    fn contract_abi(&self) -> Result<&MappingABI, Error> {
+        self.data_source
+            .mapping
+            .abis
+            .iter()
+            .find(|abi| abi.name == self.data_source.source.abi)
+            .ok_or_else(|| {
+                format_err!(
+                    "no ABI for the contract `{}` of the data source",
+                    self.data_source.source.abi
+                )
+            })
+    }
+
+    fn block_number(&self) -> u64 {
+        self.block.block.number.map_or(0, |number| number.as_u64())
+    }
+}
+
+/// Encodes an event the way the contract at `address` logs it: indexed
+/// parameters are topics, following the event signature, and all other
+/// parameters make up the data of the log.
+fn encode_log(event: &Event, address: Address, params: Vec<Token>) -> Result<Log, Error> {
+    if params.len() != event.inputs.len() {
+        bail!(
+            "event `{}` has {} parameters, not {}",
+            event.name,
+            event.inputs.len(),
+            params.len()
+        );
+    }
+
+    let mut topics = vec![];
+    if !event.anonymous {
+        topics.push(event.signature());
+    }
+    let mut data = vec![];
+    for (input, param) in event.inputs.iter().zip(params) {
+        if !param.type_check(&input.kind) {
+            bail!(
+                "parameter `{}` of event `{}` must be of type {}",
+                input.name,
+                event.name,
+                input.kind
+            );
+        }
+        if !input.indexed {
+            data.push(param);
+            continue;
+        }
+        // Indexed parameters of dynamic types are logged as their hash
+        let topic = match (&input.kind, param) {
+            (ParamType::String, Token::String(s)) => H256::from(keccak256(s.as_bytes())),
+            (ParamType::Bytes, Token::Bytes(bytes)) => H256::from(keccak256(&bytes)),
+            (ParamType::Array(_), _) | (ParamType::FixedArray(_, _), _) => bail!(
+                "indexed array parameter `{}` of event `{}` is not supported",
+                input.name,
+                event.name
+            ),
+            (_, param) => H256::from_slice(&ethabi::encode(&[param])),
+        };
+        topics.push(topic);
+    }
+
+    Ok(Log {
+        address,
+        topics,
+        data: Bytes(ethabi::encode(&data)),
+        block_hash: None,
+        block_number: None,
+        transaction_hash: None,
+        transaction_index: None,
+        log_index: Some(U256::zero()),
+        transaction_log_index: Some(U256::zero()),
+        log_type: None,
+        removed: None,
+    })
+}
+
+#[test]
+fn encoded_logs_parse_as_their_event() {
+    use graph::ethabi::{EventParam, RawLog};
+
+    let event = Event {
+        name: "Transfer".to_owned(),
+        inputs: vec![
+            EventParam {
+                name: "from".to_owned(),
+                kind: ParamType::Address,
+                indexed: true,
+            },
+            EventParam {
+                name: "to".to_owned(),
+                kind: ParamType::Address,
+                indexed: true,
+            },
+            EventParam {
+                name: "value".to_owned(),
+                kind: ParamType::Uint(256),
+                indexed: false,
+            },
+        ],
+        anonymous: false,
+    };
+    let params = vec![
+        Token::Address(Address::from(1)),
+        Token::Address(Address::from(2)),
+        Token::Uint(U256::from(100)),
+    ];
+
+    let log = encode_log(&event, Address::from(3), params.clone()).unwrap();
+    assert_eq!(log.topics.len(), 3);
+    let parsed = event
+        .parse_log(RawLog {
+            topics: log.topics,
+            data: log.data.0,
+        })
+        .unwrap();
+    assert_eq!(
+        parsed
+            .params
+            .into_iter()
+            .map(|param| param.value)
+            .collect::<Vec<_>>(),
+        params
+    );
+
+    assert!(encode_log(&event, Address::from(3), vec![]).is_err());
+}
@@ -455,14 +455,46 @@ where
     pub block: AscPtr<AscEthereumBlock>,
     pub transaction: AscPtr<T>,
     pub params: AscPtr<AscLogParamArray>,
+    /// Null unless the event handler sets `receipt: true`.
+    pub receipt: AscPtr<AscEthereumTransactionReceipt>,
 }
 
+pub(crate) type AscH256Array = Array<AscPtr<AscH256>>;
+
+#[repr(C)]
+#[derive(AscType)]
+pub(crate) struct AscEthereumLog {
+    pub address: AscPtr<AscAddress>,
+    pub topics: AscPtr<AscH256Array>,
+    pub data: AscPtr<Bytes>,
+    pub log_index: AscPtr<AscBigInt>,
+    pub transaction_log_index: AscPtr<AscBigInt>,
+    pub log_type: AscPtr<AscString>,
+}
+
+pub(crate) type AscEthereumLogArray = Array<AscPtr<AscEthereumLog>>;
+
 #[repr(C)]
 #[derive(AscType)]
-pub(crate) struct AscEthereumCall {
+pub(crate) struct AscEthereumTransactionReceipt {
+    pub transaction_hash: AscPtr<AscH256>,
+    pub transaction_index: AscPtr<AscBigInt>,
+    pub cumulative_gas_used: AscPtr<AscBigInt>,
+    pub gas_used: AscPtr<AscBigInt>,
+    pub contract_address: AscPtr<AscAddress>,
+    pub status: AscPtr<AscBigInt>,
+    pub logs: AscPtr<AscEthereumLogArray>,
+}
+
+#[repr(C)]
+#[derive(AscType)]
+pub(crate) struct AscEthereumCall<T>
+where
+    T: AscType,
+{
     pub address: AscPtr<AscAddress>,
     pub block: AscPtr<AscEthereumBlock>,
-    pub transaction: AscPtr<AscEthereumTransaction>,
+    pub transaction: AscPtr<T>,
     pub inputs: AscPtr<AscLogParamArray>,
     pub outputs: AscPtr<AscLogParamArray>,
 }
@@ -1,13 +1,21 @@
 use futures::sync::mpsc::{channel, Sender};
 use futures::sync::oneshot;
 use semver::{Version, VersionReq};
-use std::thread;
+use std::any::Any;
+use std::collections::HashSet;
+use std::env;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::Mutex;
 use std::time::Instant;
 
 use super::MappingContext;
-use crate::module::{ValidModule, WasmiModule, WasmiModuleConfig};
+use crate::module::{ValidModule, WasmiModule, WasmiModuleConfig, SUPPORTED_API_VERSIONS};
+use crate::workers::{MappingRunner, MappingWorkers, StartRunner};
 use graph::components::ethereum::*;
 use graph::components::store::Store;
+use graph::data::subgraph::schema::SubgraphDeploymentFileEntity;
 use graph::data::subgraph::{DataSource, Source};
 use graph::ethabi::RawLog;
 use graph::ethabi::{LogParam, Param, Token};
@@ -15,19 +23,26 @@ use graph::prelude::{
     RuntimeHost as RuntimeHostTrait, RuntimeHostBuilder as RuntimeHostBuilderTrait, *,
 };
 use graph::util;
-use graph::web3::types::{Log, Transaction};
+use graph::web3::types::{Log, Transaction, U256};
 
 use tiny_keccak::keccak256;
 
 pub struct RuntimeHostConfig {
     subgraph_id: SubgraphDeploymentId,
     data_source: DataSource,
+    templates: Arc<Vec<DataSourceTemplate>>,
+    external_hosts: Arc<ExternalHosts>,
+    quotas: Arc<DeploymentQuotas>,
+    handler_stats: Arc<HandlerStats>,
+    workers: Arc<MappingWorkers>,
 }
 
 pub struct RuntimeHostBuilder<T, L, S> {
-    ethereum_adapter: Arc<T>,
+    ethereum_adapters: EthereumNetworks<T>,
     link_resolver: Arc<L>,
     store: Arc<S>,
+    handler_stats: Arc<HandlerStats>,
+    workers: Arc<MappingWorkers>,
 }
 
 impl<T, L, S> Clone for RuntimeHostBuilder<T, L, S>
@@ -38,9 +53,11 @@ where
 {
     fn clone(&self) -> Self {
         RuntimeHostBuilder {
-            ethereum_adapter: self.ethereum_adapter.clone(),
+            ethereum_adapters: self.ethereum_adapters.clone(),
             link_resolver: self.link_resolver.clone(),
             store: self.store.clone(),
+            handler_stats: self.handler_stats.clone(),
+            workers: self.workers.clone(),
         }
     }
 }
@@ -51,13 +68,26 @@ where
     L: LinkResolver,
     S: Store,
 {
-    pub fn new(ethereum_adapter: Arc<T>, link_resolver: Arc<L>, store: Arc<S>) -> Self {
+    pub fn new(
+        ethereum_adapters: EthereumNetworks<T>,
+        link_resolver: Arc<L>,
+        store: Arc<S>,
+    ) -> Self {
         RuntimeHostBuilder {
-            ethereum_adapter,
+            ethereum_adapters,
             link_resolver,
             store,
+            handler_stats: Arc::new(HandlerStats::default()),
+            workers: Arc::new(MappingWorkers::from_env()),
         }
     }
+
+    /// Records the invocations and execution times of the handlers of the
+    /// hosts this builder builds in `handler_stats`.
+    pub fn with_handler_stats(mut self, handler_stats: Arc<HandlerStats>) -> Self {
+        self.handler_stats = handler_stats;
+        self
+    }
 }
 
 impl<T, L, S> RuntimeHostBuilderTrait for RuntimeHostBuilder<T, L, S>
@@ -73,37 +103,147 @@ where
         logger: &Logger,
         subgraph_id: SubgraphDeploymentId,
         data_source: DataSource,
+        templates: Arc<Vec<DataSourceTemplate>>,
+        external_hosts: Arc<ExternalHosts>,
+        quotas: Arc<DeploymentQuotas>,
     ) -> Result<Self::Host, Error> {
+        // Contract calls of the mappings go to the network of the data source
+        let ethereum_adapter = self
+            .ethereum_adapters
+            .get(data_source.network.as_ref().map(String::as_str))?;
+
         RuntimeHost::new(
             logger,
-            self.ethereum_adapter.clone(),
+            ethereum_adapter,
             self.link_resolver.clone(),
             self.store.clone(),
             RuntimeHostConfig {
                 subgraph_id,
                 data_source,
+                templates,
+                external_hosts,
+                quotas,
+                handler_stats: self.handler_stats.clone(),
+                workers: self.workers.clone(),
             },
         )
     }
 }
 
+/// How often the file of a file data source is requested before giving up.
+const FILE_ATTEMPTS_ENV_VAR: &str = "GRAPH_FILE_DATA_SOURCE_ATTEMPTS";
+
+fn file_attempts() -> usize {
+    env::var(FILE_ATTEMPTS_ENV_VAR)
+        .ok()
+        .and_then(|s| usize::from_str(&s).ok())
+        .filter(|attempts| *attempts > 0)
+        .unwrap_or(10)
+}
+
+/// The message a thread panicked with, if it is a string.
+pub(crate) fn panic_message(panic: &(Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| *s)
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
 type MappingResponse = Result<Vec<EntityOperation>, Error>;
 
+/// Runs the handler `request` asks for and sends its result back. A panic
+/// in the handler only fails this request.
+fn run_mapping_request<T, L, S, U>(
+    logger: &Logger,
+    valid_module: Arc<ValidModule<T, L, S, U>>,
+    request: MappingRequest,
+) where
+    T: EthereumAdapter,
+    L: LinkResolver,
+    S: Store + Send + Sync + 'static,
+    U: Sink<SinkItem = Box<Future<Item = (), Error = ()> + Send>> + Clone + Send + Sync + 'static,
+{
+    let MappingRequest {
+        logger: request_logger,
+        block,
+        entity_operations,
+        trigger,
+        result_sender,
+    } = request;
+    let ctx = MappingContext {
+        logger: request_logger,
+        block,
+        entity_operations,
+        eth_calls: 0,
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(move || {
+        let module = WasmiModule::from_valid_module_with_ctx(valid_module, ctx)?;
+        match trigger {
+            MappingTrigger::Log {
+                transaction,
+                log,
+                params,
+                receipt,
+                handler,
+            } => module.handle_ethereum_log(
+                handler.handler.as_str(),
+                transaction,
+                log,
+                params,
+                receipt,
+            ),
+            MappingTrigger::Call {
+                transaction,
+                call,
+                inputs,
+                outputs,
+                handler,
+            } => module.handle_ethereum_call(
+                handler.handler.as_str(),
+                transaction,
+                call,
+                inputs,
+                outputs,
+            ),
+            MappingTrigger::Block { handler } => {
+                module.handle_ethereum_block(handler.handler.as_str())
+            }
+            MappingTrigger::File { handler, data } => module.handle_file(handler.as_str(), data),
+            MappingTrigger::Entity {
+                handler,
+                entity_id,
+                entity,
+            } => module.handle_entity(handler.as_str(), entity_id.as_str(), entity),
+        }
+    }))
+    .unwrap_or_else(|panic| {
+        Err(format_err!(
+            "Mapping handler panicked: {}",
+            panic_message(&*panic)
+        ))
+    });
+    if result_sender.send(result).is_err() {
+        debug!(logger, "WASM module result receiver dropped");
+    }
+}
+
 #[derive(Debug)]
-struct MappingRequest {
-    logger: Logger,
-    block: Arc<EthereumBlock>,
-    trigger: MappingTrigger,
-    entity_operations: Vec<EntityOperation>,
-    result_sender: oneshot::Sender<MappingResponse>,
+pub(crate) struct MappingRequest {
+    pub(crate) logger: Logger,
+    pub(crate) block: Arc<EthereumBlock>,
+    pub(crate) trigger: MappingTrigger,
+    pub(crate) entity_operations: Vec<EntityOperation>,
+    pub(crate) result_sender: oneshot::Sender<MappingResponse>,
 }
 
 #[derive(Debug)]
-enum MappingTrigger {
+pub(crate) enum MappingTrigger {
     Log {
         transaction: Arc<Transaction>,
         log: Arc<Log>,
         params: Vec<LogParam>,
+        receipt: Option<EthereumTransactionReceiptData>,
         handler: MappingEventHandler,
     },
     Call {
@@ -116,18 +256,30 @@ enum MappingTrigger {
     Block {
         handler: MappingBlockHandler,
     },
+    File {
+        handler: String,
+        data: Vec<u8>,
+    },
+    Entity {
+        handler: String,
+        entity_id: String,
+        entity: Option<Entity>,
+    },
 }
 
 #[derive(Debug)]
 pub struct RuntimeHost {
     data_source_name: String,
     data_source_contract: Source,
-    data_source_contract_abi: MappingABI,
+    data_source_contract_abi: Option<MappingABI>,
     data_source_event_handlers: Vec<MappingEventHandler>,
     data_source_call_handlers: Vec<MappingCallHandler>,
     data_source_block_handlers: Vec<MappingBlockHandler>,
+    data_source_entity_handlers: Vec<MappingEntityHandler>,
+    data_source_entities: Arc<Vec<String>>,
+    entity_reads: Arc<Mutex<HashSet<String>>>,
     mapping_request_sender: Sender<MappingRequest>,
-    _guard: oneshot::Sender<()>,
+    _offchain_guard: CancelGuard,
 }
 
 impl RuntimeHost {
@@ -146,27 +298,29 @@ impl RuntimeHost {
         let logger = logger.new(o!(
             "component" => "RuntimeHost",
             "data_source" => config.data_source.name.clone(),
+            "api_version" => config.data_source.mapping.api_version.clone(),
         ));
 
         let api_version = Version::parse(&config.data_source.mapping.api_version)?;
-        if !VersionReq::parse("<= 0.0.2").unwrap().matches(&api_version) {
+        if !VersionReq::parse(SUPPORTED_API_VERSIONS)
+            .unwrap()
+            .matches(&api_version)
+        {
             return Err(format_err!(
-                "This Graph Node only supports mapping API versions <= 0.0.2, but subgraph `{}` uses `{}`",
+                "This Graph Node only supports mapping API versions {}, but subgraph `{}` uses `{}`",
+                SUPPORTED_API_VERSIONS,
                 config.subgraph_id,
                 api_version
             ));
         }
 
-        // Create channel for canceling the module
-        let (cancel_sender, cancel_receiver) = oneshot::channel();
-
         // Create channel for event handling requests
         let (mapping_request_sender, mapping_request_receiver) = channel(100);
 
         // wasmi modules are not `Send` therefore they cannot be scheduled by
-        // the regular tokio executor, so we create a dedicated thread.
+        // the regular tokio executor, so they run on the mapping workers.
         //
-        // This thread can spawn tasks on the runtime by sending them to
+        // The module can spawn tasks on the runtime by sending them to
         // `task_receiver`.
         let (task_sender, task_receiver) = channel(100);
         tokio::spawn(task_receiver.for_each(tokio::spawn));
@@ -178,110 +332,95 @@ impl RuntimeHost {
         let data_source_event_handlers = config.data_source.mapping.event_handlers.clone();
         let data_source_call_handlers = config.data_source.mapping.call_handlers.clone();
         let data_source_block_handlers = config.data_source.mapping.block_handlers.clone();
-        let data_source_contract_abi = config
-            .data_source
-            .mapping
-            .abis
-            .iter()
-            .find(|abi| abi.name == config.data_source.source.abi)
-            .ok_or_else(|| {
-                format_err!(
-                    "No ABI entry found for the main contract of data source \"{}\": {}",
-                    data_source_name,
-                    config.data_source.source.abi,
+        let data_source_entity_handlers = config.data_source.mapping.entity_handlers.clone();
+        let data_source_entities = Arc::new(config.data_source.mapping.entities.clone());
+        let data_source_contract_abi = if !config.data_source.has_contract() {
+            // File and subgraph data sources are not backed by a contract
+            None
+        } else {
+            Some(
+                config
+                    .data_source
+                    .mapping
+                    .abis
+                    .iter()
+                    .find(|abi| abi.name == config.data_source.source.abi)
+                    .ok_or_else(|| {
+                        format_err!(
+                            "No ABI entry found for the main contract of data source \"{}\": {}",
+                            data_source_name,
+                            config.data_source.source.abi,
+                        )
+                    })?
+                    .clone(),
+            )
+        };
+
+        // Handle the file of a file data source once it becomes available,
+        // independently of the blocks processed by the subgraph
+        let offchain_guard = CancelGuard::new();
+        if config.data_source.is_file() {
+            let file = config.data_source.source.file.clone().ok_or_else(|| {
+                format_err!("File data source \"{}\" has no file", data_source_name)
+            })?;
+            let handler = config
+                .data_source
+                .mapping
+                .file_handler
+                .clone()
+                .ok_or_else(|| {
+                    format_err!(
+                        "File data source \"{}\" has no file handler",
+                        data_source_name
+                    )
+                })?;
+            tokio::spawn(
+                process_file(
+                    logger.clone(),
+                    link_resolver.clone(),
+                    store.clone(),
+                    config.subgraph_id.clone(),
+                    mapping_request_sender.clone(),
+                    file,
+                    handler,
+                    config.data_source.mapping.entities.clone(),
                 )
-            })?
-            .clone();
+                .cancelable(&offchain_guard, || ()),
+            );
+        }
 
-        // Spawn a dedicated thread for the runtime.
-        //
-        // In case of failure, this thread may panic or simply terminate,
-        // dropping the `mapping_request_receiver` which ultimately causes the
-        // subgraph to fail the next time it tries to handle an event.
-        let conf = thread::Builder::new().name(format!(
-            "{}-{}-{}",
-            util::log::MAPPING_THREAD_PREFIX,
-            config.subgraph_id,
-            data_source_name
-        ));
-        conf.spawn(move || {
+        // Run the mapping on one of the mapping workers, which creates the
+        // WASM module there and runs the requests of this host one after the
+        // other. The worker drops the module when the host is dropped,
+        // together with the sender of the requests.
+        let entity_reads = Arc::new(Mutex::new(HashSet::new()));
+        let wasmi_config = WasmiModuleConfig {
+            subgraph_id: config.subgraph_id.clone(),
+            data_source: config.data_source,
+            templates: config.templates,
+            external_hosts: config.external_hosts,
+            quotas: config.quotas,
+            handler_stats: config.handler_stats,
+            ethereum_adapter: ethereum_adapter.clone(),
+            link_resolver: link_resolver.clone(),
+            store: store.clone(),
+            entity_reads: entity_reads.clone(),
+        };
+        let mut start_args = Some((wasmi_config, task_sender));
+        let start: StartRunner = Box::new(move || -> Result<MappingRunner, Error> {
+            let (wasmi_config, task_sender) = start_args
+                .take()
+                .expect("WASM runtime is only started once");
             debug!(module_logger, "Start WASM runtime");
-            let wasmi_config = WasmiModuleConfig {
-                subgraph_id: config.subgraph_id,
-                data_source: config.data_source,
-                ethereum_adapter: ethereum_adapter.clone(),
-                link_resolver: link_resolver.clone(),
-                store: store.clone(),
-            };
-            let valid_module = ValidModule::new(&module_logger, wasmi_config, task_sender)
-                .expect("Failed to validate module");
-            let valid_module = Arc::new(valid_module);
-
-            // Pass incoming triggers to the WASM module and return entity changes;
-            // Stop when cancelled.
-            let canceler = cancel_receiver.into_stream();
-            mapping_request_receiver
-                .select(
-                    canceler
-                        .map(|_| panic!("WASM module thread cancelled"))
-                        .map_err(|_| ()),
-                )
-                .map_err(|()| err_msg("Cancelled"))
-                .for_each(move |request: MappingRequest| -> Result<(), Error> {
-                    let MappingRequest {
-                        logger,
-                        block,
-                        entity_operations,
-                        trigger,
-                        result_sender,
-                    } = request;
-                    let ctx = MappingContext {
-                        logger,
-                        block,
-                        entity_operations,
-                    };
-                    let module =
-                        WasmiModule::from_valid_module_with_ctx(valid_module.clone(), ctx)?;
-                    let result = match trigger {
-                        MappingTrigger::Log {
-                            transaction,
-                            log,
-                            params,
-                            handler,
-                        } => module.handle_ethereum_log(
-                            handler.handler.as_str(),
-                            transaction,
-                            log,
-                            params,
-                        ),
-                        MappingTrigger::Call {
-                            transaction,
-                            call,
-                            inputs,
-                            outputs,
-                            handler,
-                        } => module.handle_ethereum_call(
-                            handler.handler.as_str(),
-                            transaction,
-                            call,
-                            inputs,
-                            outputs,
-                        ),
-                        MappingTrigger::Block { handler } => {
-                            module.handle_ethereum_block(handler.handler.as_str())
-                        }
-                    };
-                    result_sender
-                        .send(result)
-                        .map_err(|_| err_msg("WASM module result receiver dropped."))
-                })
-                .wait()
-                .unwrap_or_else(|e| {
-                    debug!(module_logger, "WASM runtime thread terminating"; 
-                           "reason" => e.to_string())
-                });
-        })
-        .expect("Spawning WASM runtime thread failed.");
+            let valid_module =
+                Arc::new(ValidModule::new(&module_logger, wasmi_config, task_sender)?);
+            let module_logger = module_logger.clone();
+            Ok(Box::new(move |request: MappingRequest| {
+                run_mapping_request(&module_logger, valid_module.clone(), request)
+            }) as MappingRunner)
+        });
+        config.workers.run(start, mapping_request_receiver);
+
         Ok(RuntimeHost {
             data_source_name,
             data_source_contract,
@@ -289,16 +428,35 @@ impl RuntimeHost {
             data_source_event_handlers,
             data_source_call_handlers,
             data_source_block_handlers,
+            data_source_entity_handlers,
+            data_source_entities,
+            entity_reads,
             mapping_request_sender,
-            _guard: cancel_sender,
+            _offchain_guard: offchain_guard,
         })
     }
 
+    fn no_contract_error(&self) -> Error {
+        format_err!(
+            "Data source \"{}\" has no contract to handle Ethereum triggers for",
+            self.data_source_name
+        )
+    }
+
     fn matches_call_address(&self, call: &EthereumCall) -> bool {
         self.data_source_contract.address.unwrap_or_default() == call.to
     }
 
+    fn matches_call_block(&self, call: &EthereumCall) -> bool {
+        call.block_number >= self.data_source_contract.start_block
+    }
+
     fn matches_call_function(&self, call: &EthereumCall) -> bool {
+        // Calls without a function selector (e.g. plain Ether transfers)
+        // cannot match any call handler
+        if call.input.0.len() < 4 {
+            return false;
+        }
         let target_method_id = &call.input.0[..4];
         self.data_source_call_handlers.iter().any(|handler| {
             let fhash = keccak256(handler.function.as_bytes());
@@ -316,6 +474,14 @@ impl RuntimeHost {
             .map_or(true, |addr| addr == log.address)
     }
 
+    fn matches_log_block(&self, log: &Log) -> bool {
+        // Logs without a block number are pending and therefore always
+        // after the start block
+        log.block_number.map_or(true, |number| {
+            number >= U256::from(self.data_source_contract.start_block)
+        })
+    }
+
     fn matches_log_signature(&self, log: &Log) -> bool {
         if log.topics.is_empty() {
             return false;
@@ -335,11 +501,31 @@ impl RuntimeHost {
                     // Do not match if this datasource has no address
                     .map_or(false, |addr| addr == address)
             }
-            EthereumBlockTriggerType::Every => true,
+            EthereumBlockTriggerType::Every | EthereumBlockTriggerType::Polling(_) => true,
         };
         source_address_matches && self.handler_for_block(block_trigger_type).is_ok()
     }
 
+    fn handlers_for_entity_change(
+        &self,
+        trigger: &EntityChangeTrigger,
+    ) -> Result<Vec<MappingEntityHandler>, Error> {
+        let handlers = self
+            .data_source_entity_handlers
+            .iter()
+            .filter(|handler| handler.entity == trigger.entity_type)
+            .cloned()
+            .collect::<Vec<_>>();
+        if handlers.is_empty() {
+            return Err(format_err!(
+                "No entity handler found for entity type `{}` in data source \"{}\"",
+                trigger.entity_type,
+                self.data_source_name,
+            ));
+        }
+        Ok(handlers)
+    }
+
     fn handler_for_log(&self, log: &Arc<Log>) -> Result<MappingEventHandler, Error> {
         // Get signature from the log
         if log.topics.is_empty() {
@@ -402,6 +588,22 @@ impl RuntimeHost {
                         )
                     })
             }
+            EthereumBlockTriggerType::Polling(every) => {
+                self
+                    .data_source_block_handlers
+                    .iter()
+                    .find(move |handler| {
+                        handler.filter == Some(BlockHandlerFilter::Polling { every })
+                    })
+                    .cloned()
+                    .ok_or_else(|| {
+                        format_err!(
+                            "No block handler for `Polling` block trigger type every {} blocks found in data source \"{}\"",
+                            every,
+                            self.data_source_name,
+                        )
+                    })
+            }
             EthereumBlockTriggerType::WithCallTo(address) => {
                 self
                     .data_source_block_handlers
@@ -423,17 +625,29 @@ impl RuntimeHost {
 
 impl RuntimeHostTrait for RuntimeHost {
     fn matches_log(&self, log: &Log) -> bool {
-        self.matches_log_address(log) && self.matches_log_signature(log)
+        self.matches_log_address(log)
+            && self.matches_log_signature(log)
+            && self.matches_log_block(log)
     }
 
     fn matches_call(&self, call: &EthereumCall) -> bool {
-        self.matches_call_address(call) && self.matches_call_function(call)
+        self.matches_call_address(call)
+            && self.matches_call_function(call)
+            && self.matches_call_block(call)
     }
 
     fn matches_block(&self, block_trigger_type: EthereumBlockTriggerType) -> bool {
         self.matches_block_trigger(block_trigger_type)
     }
 
+    fn matches_entity_change(&self, trigger: &EntityChangeTrigger) -> bool {
+        self.data_source_contract.subgraph.as_ref() == Some(&trigger.subgraph_id)
+            && self
+                .data_source_entity_handlers
+                .iter()
+                .any(|handler| handler.entity == trigger.entity_type)
+    }
+
     fn process_call(
         &self,
         logger: Logger,
@@ -448,9 +662,14 @@ impl RuntimeHostTrait for RuntimeHost {
             Err(e) => return Box::new(future::err(e)),
         };
 
+        let contract_abi = match self.data_source_contract_abi {
+            Some(ref abi) => abi,
+            None => return Box::new(future::err(self.no_contract_error())),
+        };
+
         // Identify the function ABI in the contract
         let function_abi = match util::ethereum::contract_function_with_signature(
-            &self.data_source_contract_abi.contract,
+            &contract_abi.contract,
             call_handler.function.as_str(),
         ) {
             Some(function_abi) => function_abi,
@@ -459,7 +678,7 @@ impl RuntimeHostTrait for RuntimeHost {
                     "Function with the signature \"{}\" not found in \
                      contract \"{}\" of data source \"{}\"",
                     call_handler.function,
-                    self.data_source_contract_abi.name,
+                    contract_abi.name,
                     self.data_source_name
                 )));
             }
@@ -625,9 +844,14 @@ impl RuntimeHostTrait for RuntimeHost {
             Err(e) => return Box::new(future::err(e)),
         };
 
+        let contract_abi = match self.data_source_contract_abi {
+            Some(ref abi) => abi,
+            None => return Box::new(future::err(self.no_contract_error())),
+        };
+
         // Identify the event ABI in the contract
         let event_abi = match util::ethereum::contract_event_with_signature(
-            &self.data_source_contract_abi.contract,
+            &contract_abi.contract,
             event_handler.event.as_str(),
         ) {
             Some(event_abi) => event_abi,
@@ -636,7 +860,7 @@ impl RuntimeHostTrait for RuntimeHost {
                     "Event with the signature \"{}\" not found in \
                      contract \"{}\" of data source \"{}\"",
                     event_handler.event,
-                    self.data_source_contract_abi.name,
+                    contract_abi.name,
                     self.data_source_name
                 )));
             }
@@ -657,6 +881,27 @@ impl RuntimeHostTrait for RuntimeHost {
             }
         };
 
+        // Receipts are loaded together with the blocks that contain triggers,
+        // so they only need to be looked up for the handlers that want them
+        let receipt = if event_handler.receipt {
+            match block
+                .transaction_receipts
+                .iter()
+                .find(|receipt| receipt.transaction_hash == transaction.hash)
+            {
+                Some(receipt) => Some(EthereumTransactionReceiptData::from(receipt)),
+                None => {
+                    return Box::new(future::err(format_err!(
+                        "No receipt found for transaction {:x} of event: {}",
+                        transaction.hash,
+                        event_handler.event
+                    )));
+                }
+            }
+        } else {
+            None
+        };
+
         debug!(
             logger, "Start processing Ethereum event";
             "signature" => &event_handler.event,
@@ -680,6 +925,7 @@ impl RuntimeHostTrait for RuntimeHost {
                     transaction: transaction.clone(),
                     log: log.clone(),
                     params,
+                    receipt,
                     handler: event_handler.clone(),
                 },
                 entity_operations,
@@ -714,4 +960,457 @@ impl RuntimeHostTrait for RuntimeHost {
             });
         Box::new(eops)
     }
+
+    fn process_entity_change(
+        &self,
+        logger: Logger,
+        block: Arc<EthereumBlock>,
+        trigger: Arc<EntityChangeTrigger>,
+        entity_operations: Vec<EntityOperation>,
+    ) -> Box<Future<Item = Vec<EntityOperation>, Error = Error> + Send> {
+        let handlers = match self.handlers_for_entity_change(&trigger) {
+            Ok(handlers) => handlers,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let logger = logger.new(o!(
+            "entity_type" => trigger.entity_type.clone(),
+            "entity_id" => trigger.entity_id.clone(),
+        ));
+        let done_logger = logger.clone();
+        let mapping_request_sender = self.mapping_request_sender.clone();
+        let entities = self.data_source_entities.clone();
+        let start_time = Instant::now();
+
+        // Every handler for the entity type runs, in manifest order, and sees
+        // the operations of the handlers before it
+        let eops = stream::iter_ok(handlers)
+            .fold(entity_operations, move |entity_operations, handler| {
+                let (result_sender, result_receiver) = oneshot::channel();
+                let previous_count = entity_operations.len();
+                let entities = entities.clone();
+                mapping_request_sender
+                    .clone()
+                    .send(MappingRequest {
+                        logger: logger.clone(),
+                        block: block.clone(),
+                        trigger: MappingTrigger::Entity {
+                            handler: handler.handler,
+                            entity_id: trigger.entity_id.clone(),
+                            entity: trigger.entity.clone(),
+                        },
+                        entity_operations,
+                        result_sender,
+                    })
+                    .map_err(|_| format_err!("Mapping terminated before passing in entity change"))
+                    .and_then(|_| {
+                        result_receiver.map_err(|_| {
+                            format_err!(
+                                "Mapping terminated before finishing to handle entity change"
+                            )
+                        })
+                    })
+                    .and_then(move |result| {
+                        let operations = result?;
+                        check_isolated_operations(
+                            &entities,
+                            operations.iter().skip(previous_count),
+                        )?;
+                        Ok(operations)
+                    })
+            })
+            .map(move |entity_operations| {
+                info!(
+                    done_logger, "Done processing entity change";
+                    // Replace this when `as_millis` is stable.
+                    "secs" => start_time.elapsed().as_secs(),
+                    "ms" => start_time.elapsed().subsec_millis()
+                );
+                entity_operations
+            });
+        Box::new(eops)
+    }
+
+    fn take_entity_reads(&self) -> HashSet<String> {
+        mem::replace(&mut *self.entity_reads.lock().unwrap(), HashSet::new())
+    }
+}
+
+/// Waits until the file of a file data source is available, runs the file
+/// handler on it and applies the resulting entity operations to the store.
+///
+/// The operations are recorded under the file's own event source, together
+/// with a marker that keeps the handler from running again for the file when
+/// the deployment restarts. The file is requested a limited number of times.
+fn process_file<L, S>(
+    logger: Logger,
+    link_resolver: Arc<L>,
+    store: Arc<S>,
+    subgraph_id: SubgraphDeploymentId,
+    mapping_request_sender: Sender<MappingRequest>,
+    file: Link,
+    handler: String,
+    entities: Vec<String>,
+) -> impl Future<Item = (), Error = ()> + Send
+where
+    L: LinkResolver,
+    S: Store,
+{
+    let logger = logger.new(o!("file" => file.link.clone()));
+    let retry_logger = logger.clone();
+    let error_logger = logger.clone();
+    let cat_link = file.clone();
+    let marker_key = SubgraphDeploymentFileEntity::key(SubgraphDeploymentFileEntity::id(
+        &subgraph_id,
+        &file.link,
+    ));
+
+    future::result(store.get(marker_key).map_err(Error::from))
+        .and_then(
+            move |marker| -> Box<Future<Item = (), Error = Error> + Send> {
+                if marker.is_some() {
+                    debug!(logger, "File has already been processed");
+                    return Box::new(future::ok(()));
+                }
+
+                let link = file.link.clone();
+                Box::new(
+                    retry("cat file of file data source", &retry_logger)
+                        .limit(file_attempts())
+                        .timeout_secs(60)
+                        .run(move || link_resolver.cat(&cat_link))
+                        .map_err(move |e| {
+                            e.into_inner().unwrap_or_else(move || {
+                                format_err!(
+                                    "File {} of file data source timed out or could not be found",
+                                    file.link
+                                )
+                            })
+                        })
+                        .and_then(move |data| {
+                            debug!(logger, "Start processing file"; "bytes" => data.len());
+                            process_offchain_trigger(
+                                logger,
+                                store,
+                                mapping_request_sender,
+                                MappingTrigger::File { handler, data },
+                                Arc::new(entities),
+                                EventSource::File(link.clone()),
+                                SubgraphDeploymentFileEntity::new(subgraph_id, link)
+                                    .write_operations(),
+                            )
+                        }),
+                )
+            },
+        )
+        .map_err(move |e| error!(error_logger, "Failed to process file"; "error" => e.to_string()))
+}
+
+/// Runs a handler for a trigger that is not tied to an Ethereum block and
+/// applies the resulting entity operations, followed by `extra_operations`,
+/// to the store in one transaction.
+///
+/// The entities written by the handler must be listed in the data source,
+/// which keeps them apart from the entities derived from Ethereum triggers.
+fn process_offchain_trigger<S>(
+    logger: Logger,
+    store: Arc<S>,
+    mapping_request_sender: Sender<MappingRequest>,
+    trigger: MappingTrigger,
+    entities: Arc<Vec<String>>,
+    event_source: EventSource,
+    mut extra_operations: Vec<EntityOperation>,
+) -> impl Future<Item = (), Error = Error> + Send
+where
+    S: Store,
+{
+    let (result_sender, result_receiver) = oneshot::channel();
+    let start_time = Instant::now();
+    mapping_request_sender
+        .send(MappingRequest {
+            logger: logger.clone(),
+            block: Arc::new(EthereumBlock::default()),
+            trigger,
+            entity_operations: vec![],
+            result_sender,
+        })
+        .map_err(|_| format_err!("Mapping terminated before passing in trigger"))
+        .and_then(|_| {
+            result_receiver
+                .map_err(|_| format_err!("Mapping terminated before finishing to handle trigger"))
+        })
+        .and_then(move |result| {
+            let mut operations = result?;
+            check_isolated_operations(&entities, operations.iter())?;
+            operations.append(&mut extra_operations);
+            store.apply_entity_operations(operations, event_source)?;
+            debug!(
+                logger, "Done processing trigger";
+                // Replace this when `as_millis` is stable.
+                "secs" => start_time.elapsed().as_secs(),
+                "ms" => start_time.elapsed().subsec_millis()
+            );
+            Ok(())
+        })
+}
+
+/// File and subgraph data sources write entity types of their own, so that
+/// their results never affect the entities derived from Ethereum triggers.
+/// Fails if one of `operations` doesn't write one of `entities`.
+fn check_isolated_operations<'a>(
+    entities: &[String],
+    mut operations: impl Iterator<Item = &'a EntityOperation>,
+) -> Result<(), Error> {
+    match operations.find(|op| match op {
+        EntityOperation::AbortUnless { .. } => true,
+        op => !entities.contains(&op.entity_key().entity_type),
+    }) {
+        None => Ok(()),
+        Some(EntityOperation::AbortUnless { .. }) => Err(format_err!(
+            "Handlers of this data source cannot abort entity operations"
+        )),
+        Some(op) => Err(format_err!(
+            "Handler wrote entity type `{}`, which is not listed \
+             in the entities of the data source",
+            op.entity_key().entity_type
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate graph_mock;
+
+    use self::graph_mock::MockStore;
+    use super::*;
+    use graph::components::link_resolver::StreamValue;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Resolves every link to the same file, or to an error if there is no
+    /// file, and counts how often it is asked to.
+    struct CountingLinkResolver {
+        file: Option<Vec<u8>>,
+        cats: AtomicUsize,
+    }
+
+    impl CountingLinkResolver {
+        fn new(file: Option<Vec<u8>>) -> Self {
+            CountingLinkResolver {
+                file,
+                cats: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl LinkResolver for CountingLinkResolver {
+        fn cat(&self, link: &Link) -> Box<Future<Item = Vec<u8>, Error = Error> + Send> {
+            self.cats.fetch_add(1, Ordering::SeqCst);
+            Box::new(future::result(
+                self.file
+                    .clone()
+                    .ok_or_else(|| format_err!("file not found: {}", link.link)),
+            ))
+        }
+
+        fn json_stream(
+            &self,
+            _: &Link,
+        ) -> Box<
+            Future<
+                    Item = Box<Stream<Item = StreamValue, Error = Error> + Send + 'static>,
+                    Error = Error,
+                > + Send
+                + 'static,
+        > {
+            unimplemented!()
+        }
+    }
+
+    fn token_key() -> EntityKey {
+        EntityKey {
+            subgraph_id: SubgraphDeploymentId::new("fileTest").unwrap(),
+            entity_type: "Token".to_owned(),
+            entity_id: "1".to_owned(),
+        }
+    }
+
+    /// Processes `file` like a file data source whose handler sets a
+    /// `Token`, with a mapping that answers requests without running any
+    /// WASM.
+    fn run_process_file(
+        runtime: &mut tokio::runtime::Runtime,
+        link_resolver: Arc<CountingLinkResolver>,
+        store: Arc<MockStore>,
+    ) {
+        let (sender, receiver) = channel::<MappingRequest>(1);
+        runtime.spawn(receiver.for_each(|request| {
+            let mut data = Entity::new();
+            data.set("name", "file");
+            let _ = request.result_sender.send(Ok(vec![EntityOperation::Set {
+                key: token_key(),
+                data,
+            }]));
+            Ok(())
+        }));
+        runtime
+            .block_on(process_file(
+                Logger::root(slog::Discard, o!()),
+                link_resolver,
+                store,
+                token_key().subgraph_id,
+                sender,
+                Link {
+                    link: "/ipfs/QmFile".to_owned(),
+                },
+                "handleFile".to_owned(),
+                vec!["Token".to_owned()],
+            ))
+            .unwrap();
+    }
+
+    #[test]
+    fn files_are_handled_once() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let link_resolver = Arc::new(CountingLinkResolver::new(Some(b"data".to_vec())));
+        let store = Arc::new(MockStore::new(vec![]));
+
+        run_process_file(&mut runtime, link_resolver.clone(), store.clone());
+        assert!(store.get(token_key()).unwrap().is_some());
+        let marker_key = SubgraphDeploymentFileEntity::key(SubgraphDeploymentFileEntity::id(
+            &token_key().subgraph_id,
+            "/ipfs/QmFile",
+        ));
+        assert!(store.get(marker_key).unwrap().is_some());
+
+        // As when the deployment restarts
+        store
+            .apply_entity_operations(
+                vec![EntityOperation::Remove { key: token_key() }],
+                EventSource::None,
+            )
+            .unwrap();
+        run_process_file(&mut runtime, link_resolver.clone(), store.clone());
+        assert_eq!(link_resolver.cats.load(Ordering::SeqCst), 1);
+        assert!(store.get(token_key()).unwrap().is_none());
+    }
+
+    #[test]
+    fn missing_files_are_requested_a_limited_number_of_times() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let link_resolver = Arc::new(CountingLinkResolver::new(None));
+        let store = Arc::new(MockStore::new(vec![]));
+
+        run_process_file(&mut runtime, link_resolver.clone(), store.clone());
+        assert_eq!(link_resolver.cats.load(Ordering::SeqCst), file_attempts());
+        assert!(store.get(token_key()).unwrap().is_none());
+    }
+
+    /// A host for a subgraph data source that consumes `baseTest`, with a
+    /// mapping that answers requests without running any WASM: each handler
+    /// sets an entity of the type named like the handler.
+    fn entity_change_host(
+        runtime: &mut tokio::runtime::Runtime,
+        handlers: Vec<(&str, &str)>,
+        entities: Vec<&str>,
+    ) -> RuntimeHost {
+        let (sender, receiver) = channel::<MappingRequest>(1);
+        runtime.spawn(receiver.for_each(|request| {
+            let mut entity_operations = request.entity_operations;
+            if let MappingTrigger::Entity {
+                handler, entity_id, ..
+            } = request.trigger
+            {
+                entity_operations.push(EntityOperation::Set {
+                    key: EntityKey {
+                        subgraph_id: SubgraphDeploymentId::new("dependentTest").unwrap(),
+                        entity_type: handler,
+                        entity_id,
+                    },
+                    data: Entity::new(),
+                });
+            }
+            let _ = request.result_sender.send(Ok(entity_operations));
+            Ok(())
+        }));
+
+        RuntimeHost {
+            data_source_name: "Base".to_owned(),
+            data_source_contract: Source {
+                address: None,
+                abi: String::new(),
+                file: None,
+                subgraph: Some(SubgraphDeploymentId::new("baseTest").unwrap()),
+                start_block: 0,
+            },
+            data_source_contract_abi: None,
+            data_source_event_handlers: vec![],
+            data_source_call_handlers: vec![],
+            data_source_block_handlers: vec![],
+            data_source_entity_handlers: handlers
+                .into_iter()
+                .map(|(entity, handler)| MappingEntityHandler {
+                    entity: entity.to_owned(),
+                    handler: handler.to_owned(),
+                })
+                .collect(),
+            data_source_entities: Arc::new(entities.into_iter().map(str::to_owned).collect()),
+            mapping_request_sender: sender,
+            _offchain_guard: CancelGuard::new(),
+        }
+    }
+
+    fn token_change(subgraph_id: &str) -> EntityChangeTrigger {
+        EntityChangeTrigger {
+            subgraph_id: SubgraphDeploymentId::new(subgraph_id).unwrap(),
+            entity_type: "Token".to_owned(),
+            entity_id: "1".to_owned(),
+            entity: None,
+        }
+    }
+
+    #[test]
+    fn every_handler_for_an_entity_change_runs() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let host = entity_change_host(
+            &mut runtime,
+            vec![("Token", "Total"), ("Account", "Owner"), ("Token", "Count")],
+            vec!["Total", "Owner", "Count"],
+        );
+        assert!(host.matches_entity_change(&token_change("baseTest")));
+        assert!(!host.matches_entity_change(&token_change("otherTest")));
+
+        // Operations of earlier triggers are passed along
+        let operations = runtime
+            .block_on(host.process_entity_change(
+                Logger::root(slog::Discard, o!()),
+                Arc::new(EthereumBlock::default()),
+                Arc::new(token_change("baseTest")),
+                vec![EntityOperation::Remove { key: token_key() }],
+            ))
+            .unwrap();
+        let entity_types = operations
+            .iter()
+            .map(|op| op.entity_key().entity_type.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(entity_types, vec!["Token", "Total", "Count"]);
+    }
+
+    #[test]
+    fn entity_handlers_only_write_the_entities_of_their_data_source() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let host = entity_change_host(
+            &mut runtime,
+            vec![("Token", "Total"), ("Token", "Count")],
+            vec!["Total"],
+        );
+        let err = runtime
+            .block_on(host.process_entity_change(
+                Logger::root(slog::Discard, o!()),
+                Arc::new(EthereumBlock::default()),
+                Arc::new(token_change("baseTest")),
+                vec![],
+            ))
+            .unwrap_err();
+        assert!(err.to_string().contains("`Count`"));
+    }
 }
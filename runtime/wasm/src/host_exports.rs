@@ -4,15 +4,18 @@ use ethabi::Token;
 use futures::sync::oneshot;
 use graph::components::ethereum::*;
 use graph::components::store::EntityKey;
+use graph::components::subgraph::is_http_url;
 use graph::data::store;
+use graph::data::subgraph::schema::DynamicEthereumContractDataSourceEntity;
 use graph::prelude::*;
 use graph::serde_json;
-use graph::web3::types::H160;
+use graph::web3::types::{Address, H160};
 use semver::Version;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::ops::Deref;
 use std::str::FromStr;
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
 use crate::module::WasmiModule;
@@ -39,12 +42,19 @@ impl From<graph::prelude::Error> for HostExportError<String> {
 }
 
 pub(crate) struct HostExports<E, L, S, U> {
-    subgraph_id: SubgraphDeploymentId,
+    pub subgraph_id: SubgraphDeploymentId,
     pub api_version: Version,
+    data_source_address: Option<Address>,
+    data_source_network: Option<String>,
+    data_source_context: Option<DataSourceContext>,
     abis: Vec<MappingABI>,
+    templates: Arc<Vec<DataSourceTemplate>>,
+    external_hosts: Arc<ExternalHosts>,
+    quotas: Arc<DeploymentQuotas>,
     ethereum_adapter: Arc<E>,
     link_resolver: Arc<L>,
     store: Arc<S>,
+    entity_reads: Arc<Mutex<HashSet<String>>>,
     task_sink: U,
 }
 
@@ -58,19 +68,33 @@ where
     pub(crate) fn new(
         subgraph_id: SubgraphDeploymentId,
         api_version: Version,
+        data_source_address: Option<Address>,
+        data_source_network: Option<String>,
+        data_source_context: Option<DataSourceContext>,
         abis: Vec<MappingABI>,
+        templates: Arc<Vec<DataSourceTemplate>>,
+        external_hosts: Arc<ExternalHosts>,
+        quotas: Arc<DeploymentQuotas>,
         ethereum_adapter: Arc<E>,
         link_resolver: Arc<L>,
         store: Arc<S>,
+        entity_reads: Arc<Mutex<HashSet<String>>>,
         task_sink: U,
     ) -> Self {
         HostExports {
             subgraph_id,
             api_version,
+            data_source_address,
+            data_source_network,
+            data_source_context,
             abis,
+            templates,
+            external_hosts,
+            quotas,
             ethereum_adapter,
             link_resolver,
             store,
+            entity_reads,
             task_sink,
         }
     }
@@ -155,6 +179,13 @@ where
         entity_type: String,
         entity_id: String,
     ) -> Result<Option<Entity>, HostExportError<impl ExportError>> {
+        // Handlers of different data sources only run in parallel if they
+        // don't load the entity types that others write
+        self.entity_reads
+            .lock()
+            .unwrap()
+            .insert(entity_type.clone());
+
         let store_key = EntityKey {
             subgraph_id: self.subgraph_id.clone(),
             entity_type,
@@ -202,7 +233,7 @@ where
 
     pub(crate) fn ethereum_call(
         &self,
-        ctx: &MappingContext,
+        ctx: &mut MappingContext,
         unresolved_call: UnresolvedContractCall,
     ) -> Result<Vec<Token>, HostExportError<impl ExportError>> {
         debug!(ctx.logger, "Call smart contract";
@@ -210,6 +241,11 @@ where
               "contract" => &unresolved_call.contract_name,
               "function" => &unresolved_call.function_name);
 
+        ctx.eth_calls += 1;
+        self.quotas
+            .eth_call(ctx.eth_calls)
+            .map_err(|e| HostExportError(e.to_string()))?;
+
         // Obtain the path to the contract ABI
         let contract = self
             .abis
@@ -218,8 +254,13 @@ where
             .ok_or_else(|| {
                 HostExportError(format!(
                     "Could not find ABI for contract \"{}\", try adding it to the 'abis' section \
-                     of the subgraph manifest",
-                    unresolved_call.contract_name
+                     of the subgraph manifest (ABIs of this data source: {})",
+                    unresolved_call.contract_name,
+                    self.abis
+                        .iter()
+                        .map(|abi| abi.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
                 ))
             })?
             .contract
@@ -332,15 +373,47 @@ where
         })
     }
 
+    /// Checks that the mapping may fetch `link` if it is an HTTP(S) URL and
+    /// waits until the request is within the rate limit of its host. Host
+    /// exports that fetch links must call this before fetching them.
+    pub(crate) fn check_external_request(
+        &self,
+        link: &str,
+    ) -> Result<(), HostExportError<impl ExportError>> {
+        if !is_http_url(link) {
+            return Ok(());
+        }
+        self.external_hosts
+            .acquire(link)
+            .map_err(|e| HostExportError(e.to_string()))
+    }
+
+    /// Reads the file at `link`. If the deployment fails when it goes over
+    /// its IPFS quota, files are only read up to the bytes that are left of
+    /// the quota of the block, and larger files fail with `FileTooLarge`.
     pub(crate) fn ipfs_cat(
         &self,
+        ctx: &MappingContext,
         link: String,
-    ) -> Result<Vec<u8>, HostExportError<impl ExportError>> {
-        self.block_on(
-            self.link_resolver
-                .cat(&Link { link })
-                .map_err(HostExportError),
-        )
+    ) -> Result<Vec<u8>, HostExportError<failure::Error>> {
+        let link = Link { link };
+        let cat = match self.quotas.ipfs_bytes_left(&ctx.block) {
+            Some(max_bytes) => self.link_resolver.cat_at_most(&link, max_bytes),
+            None => self.link_resolver.cat(&link),
+        };
+        self.block_on(cat.map_err(HostExportError))
+    }
+
+    /// Counts `bytes` read from IPFS towards the quota of the block that is
+    /// being processed.
+    pub(crate) fn check_ipfs_quota(
+        &self,
+        ctx: &MappingContext,
+        bytes: u64,
+    ) -> Result<(), HostExportError<impl ExportError>> {
+        self.quotas
+            .ipfs_read(&ctx.block, bytes)
+            .map_err(HostExportError)
     }
 
     // Read the IPFS file `link`, split it into JSON objects, and invoke
@@ -376,12 +449,16 @@ where
         let start = Instant::now();
         let mut last_log = Instant::now();
         let logger = ctx.logger.new(o!("ipfs_map" => link.clone()));
+        let quotas = self.quotas.clone();
         let operations = self.block_on(
             self.link_resolver
                 .json_stream(&Link { link })
                 .and_then(move |stream| {
                     stream
                         .and_then(move |sv| {
+                            // Every line counts towards the IPFS quota, so
+                            // that going over it stops reading the file
+                            quotas.ipfs_read(&ctx.block, sv.bytes as u64)?;
                             let module = WasmiModule::from_valid_module_with_ctx(
                                 valid_module.clone(),
                                 ctx.clone(),
@@ -542,6 +619,104 @@ where
         x.to_string()
     }
 
+    /// Writes a message logged by the mapping to the subgraph's logs. Levels
+    /// are those of `log.Level` in `graph-ts`; a critical message fails the
+    /// handler.
+    pub(crate) fn log_log(
+        &self,
+        logger: &Logger,
+        level: i32,
+        message: String,
+    ) -> Result<(), HostExportError<impl ExportError>> {
+        let logger = logger.new(o!("component" => "UserMapping"));
+        match level {
+            0 => {
+                crit!(logger, "{}", message);
+                return Err(HostExportError(format!(
+                    "Critical error logged in mapping: {}",
+                    message
+                )));
+            }
+            1 => error!(logger, "{}", message),
+            2 => warn!(logger, "{}", message),
+            3 => info!(logger, "{}", message),
+            4 => debug!(logger, "{}", message),
+            _ => return Err(HostExportError(format!("Invalid log level: {}", level))),
+        }
+        Ok(())
+    }
+
+    pub(crate) fn data_source_address(&self) -> Result<Address, HostExportError<impl ExportError>> {
+        self.data_source_address.ok_or_else(|| {
+            HostExportError("`dataSource.address()` called on a data source without an address")
+        })
+    }
+
+    pub(crate) fn data_source_network(&self) -> Result<String, HostExportError<impl ExportError>> {
+        self.data_source_network.clone().ok_or_else(|| {
+            HostExportError("`dataSource.network()` called on a data source without a network")
+        })
+    }
+
+    pub(crate) fn data_source_context(&self) -> DataSourceContext {
+        self.data_source_context.clone().unwrap_or_default()
+    }
+
+    /// Creates a data source from the template `name` for the contract at
+    /// the address in `params`, whose mappings read `context` through
+    /// `dataSource.context()`. The data source is written together with the
+    /// entities of the block and processes blocks from the next block on.
+    pub(crate) fn data_source_create(
+        &self,
+        ctx: &mut MappingContext,
+        name: String,
+        params: Vec<String>,
+        context: Option<DataSourceContext>,
+    ) -> Result<(), HostExportError<impl ExportError>> {
+        if !self.templates.iter().any(|template| template.name == name) {
+            return Err(HostExportError(format!(
+                "Failed to create data source: there is no template named `{}`, \
+                 the templates are: {}",
+                name,
+                self.templates
+                    .iter()
+                    .map(|template| template.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+        let address = match params.as_slice() {
+            [address] => H160::from_str(address.trim_start_matches("0x")).map_err(|e| {
+                HostExportError(format!(
+                    "Failed to create data source from template `{}`: \
+                     invalid address `{}`: {}",
+                    name, address, e
+                ))
+            })?,
+            _ => {
+                return Err(HostExportError(format!(
+                    "Failed to create data source from template `{}`: \
+                     expected the contract address as the only parameter, got {} parameters",
+                    name,
+                    params.len()
+                )));
+            }
+        };
+
+        let data_source = DynamicEthereumContractDataSourceEntity::new(
+            self.subgraph_id.clone(),
+            name,
+            address,
+            context,
+            EthereumBlockPointer::from(&*ctx.block),
+        );
+        let operations = data_source
+            .write_operations()
+            .map_err(|e| HostExportError(e.to_string()))?;
+        ctx.entity_operations.extend(operations);
+        Ok(())
+    }
+
     pub(crate) fn big_decimal_from_string(
         &self,
         s: String,
@@ -558,6 +733,56 @@ pub(crate) fn string_to_h160(string: &str) -> Result<H160, HostExportError<impl
         .map_err(|e| HostExportError(format!("Failed to convert string to Address/H160: {}", e)))
 }
 
+/// Formats an address as a `0x`-prefixed hex string with the mixed-case
+/// checksum defined in EIP-55.
+pub(crate) fn address_to_checksum_string(address: &H160) -> String {
+    let hex_address = hex::encode(address.0);
+    let hash = ::tiny_keccak::keccak256(hex_address.as_bytes());
+
+    let mut checksummed = String::with_capacity(42);
+    checksummed.push_str("0x");
+    for (i, c) in hex_address.chars().enumerate() {
+        let nibble = if i % 2 == 0 {
+            hash[i / 2] >> 4
+        } else {
+            hash[i / 2] & 0x0f
+        };
+        if nibble >= 8 {
+            checksummed.push(c.to_ascii_uppercase());
+        } else {
+            checksummed.push(c);
+        }
+    }
+    checksummed
+}
+
+/// Parses an address string, with or without a `0x` prefix. Mixed-case
+/// strings must carry a valid EIP-55 checksum; all-lowercase and
+/// all-uppercase strings are accepted as they are.
+pub(crate) fn checked_string_to_address(
+    string: &str,
+) -> Result<H160, HostExportError<impl ExportError>> {
+    let hex_address = string.trim_start_matches("0x");
+    if hex_address.len() != 40 {
+        return Err(HostExportError(format!(
+            "Invalid address `{}`: expected 40 hex digits",
+            string
+        )));
+    }
+    let address = H160::from_str(hex_address)
+        .map_err(|e| HostExportError(format!("Invalid address `{}`: {}", string, e)))?;
+
+    let has_lowercase = hex_address.chars().any(|c| c.is_ascii_lowercase());
+    let has_uppercase = hex_address.chars().any(|c| c.is_ascii_uppercase());
+    if has_lowercase && has_uppercase && address_to_checksum_string(&address)[2..] != *hex_address {
+        return Err(HostExportError(format!(
+            "Invalid address `{}`: checksum mismatch",
+            string
+        )));
+    }
+    Ok(address)
+}
+
 #[test]
 fn test_string_to_h160_with_0x() {
     assert_eq!(
@@ -565,3 +790,35 @@ fn test_string_to_h160_with_0x() {
         string_to_h160("0xA16081F360e3847006dB660bae1c6d1b2e17eC2A").unwrap()
     )
 }
+
+#[test]
+fn test_address_to_checksum_string() {
+    for expected in &[
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+        "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+        "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+    ] {
+        let address = string_to_h160(expected).unwrap();
+        assert_eq!(*expected, address_to_checksum_string(&address));
+    }
+}
+
+#[test]
+fn test_checked_string_to_address() {
+    let address = string_to_h160("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap();
+    assert_eq!(
+        address,
+        checked_string_to_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").unwrap()
+    );
+    assert_eq!(
+        address,
+        checked_string_to_address("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap()
+    );
+    assert_eq!(
+        address,
+        checked_string_to_address("0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED").unwrap()
+    );
+    assert!(checked_string_to_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAEd").is_err());
+    assert!(checked_string_to_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA").is_err());
+}
@@ -12,7 +12,9 @@ extern crate wasmi;
 mod asc_abi;
 mod host;
 mod module;
+mod profiler;
 mod to_from;
+mod workers;
 
 /// Runtime-agnostic implementation of exports to WASM.
 mod host_exports;
@@ -21,6 +23,7 @@ use graph::prelude::*;
 use graph::web3::types::{Address, Transaction};
 
 pub use self::host::{RuntimeHost, RuntimeHostBuilder, RuntimeHostConfig};
+pub use self::module::validate_mapping;
 
 #[derive(Clone, Debug)]
 pub(crate) struct UnresolvedContractCall {
@@ -35,6 +38,8 @@ pub(crate) struct MappingContext {
     logger: Logger,
     block: Arc<EthereumBlock>,
     entity_operations: Vec<EntityOperation>,
+    /// The number of Ethereum calls the handler has made.
+    eth_calls: u64,
 }
 
 /// Cloning an `EventHandlerContext` clones all its fields,
@@ -46,6 +51,7 @@ impl Clone for MappingContext {
             logger: self.logger.clone(),
             block: self.block.clone(),
             entity_operations: Vec::new(),
+            eth_calls: self.eth_calls,
         }
     }
 }
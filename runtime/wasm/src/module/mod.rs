@@ -1,8 +1,10 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::ops::Deref;
+use std::sync::Mutex;
 use std::time::Instant;
 
-use semver::Version;
+use semver::{Version, VersionReq};
 use wasmi::{
     nan_preserving_float::F64, Error, Externals, FuncInstance, FuncRef, HostError, ImportsBuilder,
     MemoryRef, Module, ModuleImportResolver, ModuleInstance, ModuleRef, RuntimeArgs, RuntimeValue,
@@ -10,10 +12,11 @@ use wasmi::{
 };
 
 use crate::host_exports::{self, HostExportError, HostExports};
+use crate::profiler::{HandlerProfile, MappingProfiler};
 use crate::MappingContext;
 use graph::components::ethereum::*;
 use graph::data::store;
-use graph::data::subgraph::DataSource;
+use graph::data::subgraph::{DataSource, Mapping};
 use graph::ethabi::{LogParam, Param};
 use graph::prelude::{Error as FailureError, *};
 use graph::web3::types::{Log, Transaction, U256};
@@ -61,13 +64,32 @@ const BIG_DECIMAL_EQUALS: usize = 31;
 const BIG_DECIMAL_TO_STRING: usize = 32;
 const BIG_DECIMAL_FROM_STRING: usize = 33;
 const IPFS_MAP_FUNC_INDEX: usize = 34;
+const DATA_SOURCE_ADDRESS: usize = 35;
+const DATA_SOURCE_NETWORK: usize = 36;
+const DATA_SOURCE_CONTEXT: usize = 37;
+const TYPE_CONVERSION_ADDRESS_TO_CHECKSUM_STRING_INDEX: usize = 38;
+const TYPE_CONVERSION_CHECKED_STRING_TO_ADDRESS_INDEX: usize = 39;
+const LOG_FUNC_INDEX: usize = 40;
+const DATA_SOURCE_CREATE: usize = 41;
+const DATA_SOURCE_CREATE_WITH_CONTEXT: usize = 42;
+
+/// Mapping API versions this node can run. Handler arguments are laid out
+/// according to the `apiVersion` declared by each mapping, so subgraphs built
+/// against different versions of the AssemblyScript library can run side by side.
+pub(crate) const SUPPORTED_API_VERSIONS: &str = "<= 0.0.2";
 
 pub struct WasmiModuleConfig<T, L, S> {
     pub subgraph_id: SubgraphDeploymentId,
     pub data_source: DataSource,
+    pub templates: Arc<Vec<DataSourceTemplate>>,
+    pub external_hosts: Arc<ExternalHosts>,
+    pub quotas: Arc<DeploymentQuotas>,
+    pub handler_stats: Arc<HandlerStats>,
     pub ethereum_adapter: Arc<T>,
     pub link_resolver: Arc<L>,
     pub store: Arc<S>,
+    /// The entity types that handlers load, for the host to report.
+    pub entity_reads: Arc<Mutex<HashSet<String>>>,
 }
 
 /// A pre-processed and valid WASM module, ready to be started as a WasmiModule.
@@ -76,6 +98,9 @@ pub(crate) struct ValidModule<T, L, S, U> {
     pub module: Module,
     host_exports: HostExports<T, L, S, U>,
     user_module: Option<String>,
+    profiler: Option<MappingProfiler>,
+    data_source_name: String,
+    handler_stats: Arc<HandlerStats>,
 }
 
 impl<T, L, S, U> ValidModule<T, L, S, U>
@@ -119,18 +144,49 @@ where
             _ => return Err(err_msg("WASM module has multiple import sections")),
         };
 
-        let name = config.data_source.name;
-        let module = Module::from_parity_wasm_module(parsed_module)
-            .map_err(|e| format_err!("Invalid module of data source `{}`: {}", name, e))?;
+        // Remember the names of the imported host exports, for profiling
+        let host_export_names: HashMap<_, _> = import_section
+            .entries()
+            .iter()
+            .filter_map(|import| {
+                let index = if import.module() == "env" {
+                    env_export_index(import.field())
+                } else {
+                    host_export_index(import.field())
+                };
+                index.map(|index| (index, import.field().to_owned()))
+            })
+            .collect();
+        let profiler = MappingProfiler::from_env(
+            &config.subgraph_id,
+            &config.data_source.name,
+            host_export_names,
+        )?;
+
+        let data_source_name = config.data_source.name;
+        let module = Module::from_parity_wasm_module(parsed_module).map_err(|e| {
+            format_err!(
+                "Invalid module of data source `{}`: {}",
+                data_source_name,
+                e
+            )
+        })?;
 
         // Create new instance of externally hosted functions invoker
         let host_exports = HostExports::new(
             config.subgraph_id,
             Version::parse(&config.data_source.mapping.api_version)?,
+            config.data_source.source.address,
+            config.data_source.network,
+            config.data_source.context,
             config.data_source.mapping.abis,
+            config.templates,
+            config.external_hosts,
+            config.quotas,
             config.ethereum_adapter.clone(),
             config.link_resolver.clone(),
             config.store.clone(),
+            config.entity_reads,
             task_sink,
         );
 
@@ -139,8 +195,101 @@ where
             module,
             host_exports,
             user_module,
+            profiler,
+            data_source_name,
+            handler_stats: config.handler_stats,
+        })
+    }
+}
+
+/// Checks that this node can run the WASM module of `mapping`: that its
+/// API version is supported, that it only imports host exports this node
+/// provides and that it exports memory and every handler of the mapping.
+/// The module is instantiated but not started, so nothing in it runs.
+/// Returns a description of each problem that was found.
+pub fn validate_mapping(mapping: &Mapping) -> Vec<String> {
+    let mut problems = vec![];
+
+    let supported_api_versions = VersionReq::parse(SUPPORTED_API_VERSIONS).unwrap();
+    match Version::parse(&mapping.api_version) {
+        Ok(version) => {
+            if !supported_api_versions.matches(&version) {
+                problems.push(format!(
+                    "apiVersion {} is not supported, supported versions are {}",
+                    version, SUPPORTED_API_VERSIONS
+                ))
+            }
+        }
+        Err(e) => problems.push(format!(
+            "apiVersion {} is invalid: {}",
+            mapping.api_version, e
+        )),
+    }
+
+    let module = match Module::from_parity_wasm_module(mapping.runtime.clone()) {
+        Ok(module) => module,
+        Err(e) => {
+            problems.push(format!("invalid WASM module: {}", e));
+            return problems;
+        }
+    };
+
+    // Resolve the imports like `WasmiModule` does
+    let user_modules = mapping
+        .runtime
+        .import_section()
+        .map(|section| {
+            section
+                .entries()
+                .iter()
+                .map(|import| import.module().to_owned())
+                .filter(|module| module != "env")
+                .collect::<BTreeSet<_>>()
         })
+        .unwrap_or_default();
+    if user_modules.len() > 1 {
+        problems.push("WASM module has multiple import sections".to_owned());
+    }
+    let mut imports = ImportsBuilder::new();
+    imports.push_resolver("env", &EnvModuleResolver);
+    for user_module in user_modules {
+        imports.push_resolver(user_module, &ModuleResolver);
+    }
+    let instance = match ModuleInstance::new(&module, &imports) {
+        Ok(instance) => instance.not_started_instance().clone(),
+        Err(e) => {
+            problems.push(format!("failed to instantiate WASM module: {}", e));
+            return problems;
+        }
+    };
+
+    if instance
+        .export_by_name("memory")
+        .and_then(|export| export.as_memory().cloned())
+        .is_none()
+    {
+        problems.push("WASM module does not export its memory".to_owned());
+    }
+    let mut handlers = BTreeSet::new();
+    handlers.extend(mapping.event_handlers.iter().map(|h| &h.handler));
+    handlers.extend(mapping.call_handlers.iter().map(|h| &h.handler));
+    handlers.extend(mapping.block_handlers.iter().map(|h| &h.handler));
+    handlers.extend(mapping.entity_handlers.iter().map(|h| &h.handler));
+    handlers.extend(mapping.file_handler.iter());
+    for handler in handlers {
+        if instance
+            .export_by_name(handler)
+            .and_then(|export| export.as_func().cloned())
+            .is_none()
+        {
+            problems.push(format!(
+                "handler {} is not exported by the WASM module",
+                handler
+            ));
+        }
     }
+
+    problems
 }
 
 /// A WASM module based on wasmi that powers a subgraph runtime.
@@ -151,9 +300,20 @@ pub(crate) struct WasmiModule<T, L, S, U> {
     pub ctx: MappingContext,
     pub valid_module: Arc<ValidModule<T, L, S, U>>,
 
-    // Time when the current handler began processing.
+    // Time when the current handler began processing, for the handler
+    // timeout. `ipfs.map` moves it forward by the time it spends.
     start_time: Instant,
 
+    // Time when the current handler began processing, for the handler
+    // statistics and the profile.
+    handler_start_time: Instant,
+
+    // Samples of the current handler, if profiling is enabled.
+    profile: Option<HandlerProfile>,
+
+    // Counts the current handler as running in the handler statistics.
+    running_handler: Option<RunningHandler>,
+
     // True if `run_start` has not yet been called on the module.
     // This is used to prevent mutating store state in start.
     running_start: bool,
@@ -200,6 +360,9 @@ where
             ctx,
             valid_module: valid_module.clone(),
             start_time: Instant::now(),
+            handler_start_time: Instant::now(),
+            profile: None,
+            running_handler: None,
             running_start: true,
         };
 
@@ -215,20 +378,66 @@ where
         &self.valid_module.host_exports
     }
 
+    /// Resets the handler timeout and, if profiling is enabled, starts
+    /// collecting samples for the handler about to run.
+    fn start_handler(&mut self) {
+        self.start_time = Instant::now();
+        self.handler_start_time = self.start_time;
+        self.running_handler = Some(HandlerStats::start(
+            &self.valid_module.handler_stats,
+            &self.host_exports().subgraph_id,
+        ));
+        if self.valid_module.profiler.is_some() {
+            self.profile = Some(HandlerProfile::new());
+        }
+    }
+
+    /// Records how long the handler that just ran took and writes the
+    /// samples collected for it.
+    fn finish_handler(&mut self, handler_name: &str) {
+        let time = self.handler_start_time.elapsed();
+        self.running_handler = None;
+        self.valid_module.handler_stats.record(
+            &self.host_exports().subgraph_id,
+            &self.valid_module.data_source_name,
+            handler_name,
+            time,
+        );
+
+        let profile = match self.profile.take() {
+            Some(profile) => profile,
+            None => return,
+        };
+        if let Some(profiler) = self.valid_module.profiler.as_ref() {
+            if let Err(e) = profiler.write(handler_name, time, profile) {
+                warn!(self.logger, "Failed to write mapping profile";
+                      "handler" => handler_name,
+                      "error" => e.to_string());
+            }
+        }
+    }
+
+    /// Starting with API version 0.0.2, transactions passed to mappings
+    /// include the transaction `input`.
+    fn transaction_has_input(&self) -> bool {
+        self.host_exports().api_version >= Version::new(0, 0, 2)
+    }
+
     pub(crate) fn handle_ethereum_log(
         mut self,
         handler_name: &str,
         transaction: Arc<Transaction>,
         log: Arc<Log>,
         params: Vec<LogParam>,
+        receipt: Option<EthereumTransactionReceiptData>,
     ) -> Result<Vec<EntityOperation>, FailureError> {
-        self.start_time = Instant::now();
+        self.start_handler();
         let block = self.ctx.block.block.clone();
 
         // Prepare an EthereumEvent for the WASM runtime
         // Decide on the destination type using the mapping
         // api version provided in the subgraph manifest
-        let event = if self.valid_module.host_exports.api_version >= Version::new(0, 0, 2) {
+        let event = if self.transaction_has_input() {
             RuntimeValue::from(
                 self.asc_new::<AscEthereumEvent<AscEthereumTransaction_0_0_2>, _>(
                     &EthereumEventData {
@@ -239,6 +448,7 @@ where
                         transaction_log_index: log.transaction_log_index.unwrap_or(U256::zero()),
                         log_type: log.log_type.clone(),
                         params,
+                        receipt,
                     },
                 ),
             )
@@ -252,6 +462,7 @@ where
                     transaction_log_index: log.transaction_log_index.unwrap_or(U256::zero()),
                     log_type: log.log_type.clone(),
                     params,
+                    receipt,
                 },
             ))
         };
@@ -259,12 +470,14 @@ where
             .module
             .clone()
             .invoke_export(handler_name, &[event], &mut self);
+        self.finish_handler(handler_name);
         result.map(|_| self.ctx.entity_operations).map_err(|e| {
-            format_err!(
-                "Failed to handle Ethereum event with handler \"{}\": {}",
-                handler_name,
-                e
-            )
+            HandlerError {
+                trigger: "Ethereum event".to_owned(),
+                handler: handler_name.to_owned(),
+                error: e.to_string(),
+            }
+            .into()
         })
     }
 
@@ -276,7 +489,7 @@ where
         inputs: Vec<LogParam>,
         outputs: Vec<LogParam>,
     ) -> Result<Vec<EntityOperation>, FailureError> {
-        self.start_time = Instant::now();
+        self.start_handler();
         // Prepare an EthereumCall for the WASM runtime
         let arg = EthereumCallData {
             address: call.to,
@@ -285,17 +498,25 @@ where
             inputs,
             outputs,
         };
-        let result = self.module.clone().invoke_export(
-            handler_name,
-            &[RuntimeValue::from(self.asc_new(&arg))],
-            &mut self,
-        );
-        result.map(|_| self.ctx.entity_operations).map_err(|err| {
-            format_err!(
-                "Failed to handle Ethereum call with handler \"{}\": {}",
-                handler_name,
-                err
+        let arg = if self.transaction_has_input() {
+            RuntimeValue::from(
+                self.asc_new::<AscEthereumCall<AscEthereumTransaction_0_0_2>, _>(&arg),
             )
+        } else {
+            RuntimeValue::from(self.asc_new::<AscEthereumCall<AscEthereumTransaction>, _>(&arg))
+        };
+        let result = self
+            .module
+            .clone()
+            .invoke_export(handler_name, &[arg], &mut self);
+        self.finish_handler(handler_name);
+        result.map(|_| self.ctx.entity_operations).map_err(|err| {
+            HandlerError {
+                trigger: "Ethereum call".to_owned(),
+                handler: handler_name.to_owned(),
+                error: err.to_string(),
+            }
+            .into()
         })
     }
 
@@ -303,7 +524,7 @@ where
         mut self,
         handler_name: &str,
     ) -> Result<Vec<EntityOperation>, FailureError> {
-        self.start_time = Instant::now();
+        self.start_handler();
         // Prepare an EthereumBlock for the WASM runtime
         let arg = EthereumBlockData::from(&self.ctx.block.block);
         let result = self.module.clone().invoke_export(
@@ -311,12 +532,65 @@ where
             &[RuntimeValue::from(self.asc_new(&arg))],
             &mut self,
         );
+        self.finish_handler(handler_name);
         result.map(|_| self.ctx.entity_operations).map_err(|err| {
-            format_err!(
-                "Failed to handle Ethereum block with handler \"{}\": {}",
-                handler_name,
-                err
-            )
+            HandlerError {
+                trigger: "Ethereum block".to_owned(),
+                handler: handler_name.to_owned(),
+                error: err.to_string(),
+            }
+            .into()
+        })
+    }
+
+    pub(crate) fn handle_file(
+        mut self,
+        handler_name: &str,
+        data: Vec<u8>,
+    ) -> Result<Vec<EntityOperation>, FailureError> {
+        self.start_handler();
+        // Pass the contents of the file as bytes to the WASM runtime
+        let arg: AscPtr<Uint8Array> = self.asc_new(&*data);
+        let result =
+            self.module
+                .clone()
+                .invoke_export(handler_name, &[RuntimeValue::from(arg)], &mut self);
+        self.finish_handler(handler_name);
+        result.map(|_| self.ctx.entity_operations).map_err(|err| {
+            HandlerError {
+                trigger: "file".to_owned(),
+                handler: handler_name.to_owned(),
+                error: err.to_string(),
+            }
+            .into()
+        })
+    }
+
+    pub(crate) fn handle_entity(
+        mut self,
+        handler_name: &str,
+        entity_id: &str,
+        entity: Option<Entity>,
+    ) -> Result<Vec<EntityOperation>, FailureError> {
+        self.start_handler();
+        // Removed entities are passed as `null`
+        let id = RuntimeValue::from(self.asc_new(entity_id));
+        let entity = match entity {
+            Some(entity) => RuntimeValue::from(self.asc_new(&entity)),
+            None => RuntimeValue::from(0),
+        };
+        let result = self
+            .module
+            .clone()
+            .invoke_export(handler_name, &[id, entity], &mut self);
+        self.finish_handler(handler_name);
+        result.map(|_| self.ctx.entity_operations).map_err(|err| {
+            HandlerError {
+                trigger: format!("entity `{}`", entity_id),
+                handler: handler_name.to_owned(),
+                error: err.to_string(),
+            }
+            .into()
         })
     }
 
@@ -387,7 +661,10 @@ where
     S: Store + Send + Sync + 'static,
     U: Sink<SinkItem = Box<Future<Item = (), Error = ()> + Send>> + Clone + Send + Sync + 'static,
 {
-    fn gas(&mut self, _gas_spent: u32) -> Result<Option<RuntimeValue>, Trap> {
+    fn gas(&mut self, gas_spent: u32) -> Result<Option<RuntimeValue>, Trap> {
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record_gas(gas_spent);
+        }
         self.host_exports().check_timeout(self.start_time)?;
         Ok(None)
     }
@@ -541,6 +818,27 @@ where
         Ok(Some(RuntimeValue::from(h160_obj)))
     }
 
+    /// function typeConversion.addressToChecksumString(address: Address): string
+    fn address_to_checksum_string(
+        &mut self,
+        address_ptr: AscPtr<AscAddress>,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let address = self.asc_get(address_ptr);
+        let result = host_exports::address_to_checksum_string(&address);
+        Ok(Some(RuntimeValue::from(self.asc_new(&result))))
+    }
+
+    /// function typeConversion.checkedStringToAddress(s: string): Address
+    fn checked_string_to_address(
+        &mut self,
+        str_ptr: AscPtr<AscString>,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let s: String = self.asc_get(str_ptr);
+        let address = host_exports::checked_string_to_address(&s)?;
+        let address_ptr: AscPtr<AscAddress> = self.asc_new(&address);
+        Ok(Some(RuntimeValue::from(address_ptr)))
+    }
+
     /// function typeConversion.i32ToBigInt(i: i32): Uint64Array
     fn i32_to_big_int(&mut self, i: i32) -> Result<Option<RuntimeValue>, Trap> {
         let bytes = BigInt::from(i).to_signed_bytes_le();
@@ -567,16 +865,29 @@ where
 
     /// function ipfs.cat(link: String): Bytes
     fn ipfs_cat(&mut self, link_ptr: AscPtr<AscString>) -> Result<Option<RuntimeValue>, Trap> {
-        let link = self.asc_get(link_ptr);
-        let ipfs_res = self.host_exports().ipfs_cat(link);
+        let link: String = self.asc_get(link_ptr);
+
+        // Fetching from a host that the manifest doesn't allow fails the
+        // handler instead of returning `null`
+        self.host_exports().check_external_request(&link)?;
+        let ipfs_res = self.host_exports().ipfs_cat(&self.ctx, link);
         match ipfs_res {
             Ok(bytes) => {
+                // Exceeding the quota fails the handler instead of returning `null`
+                self.host_exports()
+                    .check_ipfs_quota(&self.ctx, bytes.len() as u64)?;
                 let bytes_obj: AscPtr<Uint8Array> = self.asc_new(&*bytes);
                 Ok(Some(RuntimeValue::from(bytes_obj)))
             }
 
             // Return null in case of error.
             Err(e) => {
+                // A file that is larger than what is left of the quota
+                // exceeds it, even though it wasn't read completely
+                if let Some(too_large) = e.0.downcast_ref::<FileTooLarge>() {
+                    self.host_exports()
+                        .check_ipfs_quota(&self.ctx, too_large.1 + 1)?;
+                }
                 info!(self.logger, "Failed ipfs.cat, returning `null`";
                                     "link" => self.asc_get::<String, _>(link_ptr),
                                     "error" => e.to_string());
@@ -598,6 +909,7 @@ where
         let user_data: store::Value = self.asc_get(user_data);
 
         let flags = self.asc_get(flags);
+        self.host_exports().check_external_request(&link)?;
         let start_time = Instant::now();
         let result =
             match self
@@ -840,16 +1152,85 @@ where
             .big_decimal_equals(self.asc_get(x_ptr), self.asc_get(y_ptr));
         Ok(Some(RuntimeValue::I32(if equals { 1 } else { 0 })))
     }
-}
 
-impl<T, L, S, U> Externals for WasmiModule<T, L, S, U>
-where
-    T: EthereumAdapter,
-    L: LinkResolver,
-    S: Store + Send + Sync + 'static,
-    U: Sink<SinkItem = Box<Future<Item = (), Error = ()> + Send>> + Clone + Send + Sync + 'static,
-{
-    fn invoke_index(
+    /// function log.log(level: i32, msg: string): void
+    fn log_log(
+        &mut self,
+        level: i32,
+        msg_ptr: AscPtr<AscString>,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let msg: String = self.asc_get(msg_ptr);
+        self.host_exports().log_log(&self.ctx.logger, level, msg)?;
+        Ok(None)
+    }
+
+    /// function dataSource.address(): Address
+    fn data_source_address(&mut self) -> Result<Option<RuntimeValue>, Trap> {
+        let address = self.host_exports().data_source_address()?;
+        let address_ptr: AscPtr<AscAddress> = self.asc_new(&address);
+        Ok(Some(RuntimeValue::from(address_ptr)))
+    }
+
+    /// function dataSource.network(): String
+    fn data_source_network(&mut self) -> Result<Option<RuntimeValue>, Trap> {
+        let network = self.host_exports().data_source_network()?;
+        Ok(Some(RuntimeValue::from(self.asc_new(&network))))
+    }
+
+    /// function dataSource.context(): DataSourceContext
+    fn data_source_context(&mut self) -> Result<Option<RuntimeValue>, Trap> {
+        let context = self.host_exports().data_source_context();
+        Ok(Some(RuntimeValue::from(self.asc_new(&context))))
+    }
+
+    /// function dataSource.create(name: string, params: Array<string>): void
+    fn data_source_create(
+        &mut self,
+        name_ptr: AscPtr<AscString>,
+        params_ptr: AscPtr<Array<AscPtr<AscString>>>,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        if self.running_start {
+            return Err(
+                HostExportError("dataSource.create may not be called in start function").into(),
+            );
+        }
+        let name: String = self.asc_get(name_ptr);
+        let params: Vec<String> = self.asc_get(params_ptr);
+        self.valid_module
+            .host_exports
+            .data_source_create(&mut self.ctx, name, params, None)?;
+        Ok(None)
+    }
+
+    /// function dataSource.createWithContext(
+    ///   name: string, params: Array<string>, context: DataSourceContext
+    /// ): void
+    fn data_source_create_with_context(
+        &mut self,
+        name_ptr: AscPtr<AscString>,
+        params_ptr: AscPtr<Array<AscPtr<AscString>>>,
+        context_ptr: AscPtr<AscEntity>,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        if self.running_start {
+            return Err(HostExportError(
+                "dataSource.createWithContext may not be called in start function",
+            )
+            .into());
+        }
+        let name: String = self.asc_get(name_ptr);
+        let params: Vec<String> = self.asc_get(params_ptr);
+        let context: HashMap<String, Value> = self.asc_get(context_ptr);
+        self.valid_module.host_exports.data_source_create(
+            &mut self.ctx,
+            name,
+            params,
+            Some(Entity::from(context)),
+        )?;
+        Ok(None)
+    }
+
+    /// Calls the host export with the given index.
+    fn invoke_host_export(
         &mut self,
         index: usize,
         args: RuntimeArgs,
@@ -918,26 +1299,145 @@ where
                 args.nth_checked(2)?,
                 args.nth_checked(3)?,
             ),
+            DATA_SOURCE_ADDRESS => self.data_source_address(),
+            DATA_SOURCE_NETWORK => self.data_source_network(),
+            DATA_SOURCE_CONTEXT => self.data_source_context(),
+            TYPE_CONVERSION_ADDRESS_TO_CHECKSUM_STRING_INDEX => {
+                self.address_to_checksum_string(args.nth_checked(0)?)
+            }
+            TYPE_CONVERSION_CHECKED_STRING_TO_ADDRESS_INDEX => {
+                self.checked_string_to_address(args.nth_checked(0)?)
+            }
+            LOG_FUNC_INDEX => self.log_log(args.nth_checked(0)?, args.nth_checked(1)?),
+            DATA_SOURCE_CREATE => {
+                self.data_source_create(args.nth_checked(0)?, args.nth_checked(1)?)
+            }
+            DATA_SOURCE_CREATE_WITH_CONTEXT => self.data_source_create_with_context(
+                args.nth_checked(0)?,
+                args.nth_checked(1)?,
+                args.nth_checked(2)?,
+            ),
             _ => panic!("Unimplemented function at {}", index),
         }
     }
 }
 
+impl<T, L, S, U> Externals for WasmiModule<T, L, S, U>
+where
+    T: EthereumAdapter,
+    L: LinkResolver,
+    S: Store + Send + Sync + 'static,
+    U: Sink<SinkItem = Box<Future<Item = (), Error = ()> + Send>> + Clone + Send + Sync + 'static,
+{
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        // Gas calls are instrumentation and are counted separately
+        let start_time = match self.profile {
+            Some(_) if index != GAS_FUNC_INDEX => Some(Instant::now()),
+            _ => None,
+        };
+
+        // Calls that fail are recorded, too, since they can be just as slow
+        let result = self.invoke_host_export(index, args);
+
+        if let (Some(profile), Some(start_time)) = (self.profile.as_mut(), start_time) {
+            profile.record_host_export(index, start_time.elapsed());
+        }
+
+        result
+    }
+}
+
+/// Returns the index of a function exported to the "env" module.
+fn env_export_index(field_name: &str) -> Option<usize> {
+    Some(match field_name {
+        "gas" => GAS_FUNC_INDEX,
+        "abort" => ABORT_FUNC_INDEX,
+        _ => return None,
+    })
+}
+
+/// Returns the index of a function exported to the user module.
+fn host_export_index(field_name: &str) -> Option<usize> {
+    Some(match field_name {
+        // store
+        "store.set" => STORE_SET_FUNC_INDEX,
+        "store.remove" => STORE_REMOVE_FUNC_INDEX,
+        "store.get" => STORE_GET_FUNC_INDEX,
+
+        // ethereum
+        "ethereum.call" => ETHEREUM_CALL_FUNC_INDEX,
+
+        // typeConversion
+        "typeConversion.bytesToString" => TYPE_CONVERSION_BYTES_TO_STRING_FUNC_INDEX,
+        "typeConversion.bytesToHex" => TYPE_CONVERSION_BYTES_TO_HEX_FUNC_INDEX,
+        "typeConversion.bigIntToString" => TYPE_CONVERSION_BIG_INT_TO_STRING_FUNC_INDEX,
+        "typeConversion.bigIntToHex" => TYPE_CONVERSION_BIG_INT_TO_HEX_FUNC_INDEX,
+        "typeConversion.stringToH160" => TYPE_CONVERSION_STRING_TO_H160_FUNC_INDEX,
+        "typeConversion.i32ToBigInt" => TYPE_CONVERSION_I32_TO_BIG_INT_FUNC_INDEX,
+        "typeConversion.bigIntToI32" => TYPE_CONVERSION_BIG_INT_TO_I32_FUNC_INDEX,
+        "typeConversion.bytesToBase58" => TYPE_CONVERSION_BYTES_TO_BASE_58_INDEX,
+        "typeConversion.addressToChecksumString" => {
+            TYPE_CONVERSION_ADDRESS_TO_CHECKSUM_STRING_INDEX
+        }
+        "typeConversion.checkedStringToAddress" => TYPE_CONVERSION_CHECKED_STRING_TO_ADDRESS_INDEX,
+
+        // json
+        "json.fromBytes" => JSON_FROM_BYTES_FUNC_INDEX,
+        "json.toI64" => JSON_TO_I64_FUNC_INDEX,
+        "json.toU64" => JSON_TO_U64_FUNC_INDEX,
+        "json.toF64" => JSON_TO_F64_FUNC_INDEX,
+        "json.toBigInt" => JSON_TO_BIG_INT_FUNC_INDEX,
+
+        // ipfs
+        "ipfs.cat" => IPFS_CAT_FUNC_INDEX,
+        "ipfs.map" => IPFS_MAP_FUNC_INDEX,
+
+        // crypto
+        "crypto.keccak256" => CRYPTO_KECCAK_256_INDEX,
+
+        // bigInt
+        "bigInt.plus" => BIG_INT_PLUS,
+        "bigInt.minus" => BIG_INT_MINUS,
+        "bigInt.times" => BIG_INT_TIMES,
+        "bigInt.dividedBy" => BIG_INT_DIVIDED_BY,
+        "bigInt.dividedByDecimal" => BIG_INT_DIVIDED_BY_DECIMAL,
+        "bigInt.mod" => BIG_INT_MOD,
+
+        // bigDecimal
+        "bigDecimal.plus" => BIG_DECIMAL_PLUS,
+        "bigDecimal.minus" => BIG_DECIMAL_MINUS,
+        "bigDecimal.times" => BIG_DECIMAL_TIMES,
+        "bigDecimal.dividedBy" => BIG_DECIMAL_DIVIDED_BY,
+        "bigDecimal.equals" => BIG_DECIMAL_EQUALS,
+        "bigDecimal.toString" => BIG_DECIMAL_TO_STRING,
+        "bigDecimal.fromString" => BIG_DECIMAL_FROM_STRING,
+
+        // dataSource
+        "dataSource.address" => DATA_SOURCE_ADDRESS,
+        "dataSource.network" => DATA_SOURCE_NETWORK,
+        "dataSource.context" => DATA_SOURCE_CONTEXT,
+        "dataSource.create" => DATA_SOURCE_CREATE,
+        "dataSource.createWithContext" => DATA_SOURCE_CREATE_WITH_CONTEXT,
+
+        // log
+        "log.log" => LOG_FUNC_INDEX,
+
+        _ => return None,
+    })
+}
+
 /// Env module resolver
 pub struct EnvModuleResolver;
 
 impl ModuleImportResolver for EnvModuleResolver {
     fn resolve_func(&self, field_name: &str, signature: &Signature) -> Result<FuncRef, Error> {
-        Ok(match field_name {
-            "gas" => FuncInstance::alloc_host(signature.clone(), GAS_FUNC_INDEX),
-            "abort" => FuncInstance::alloc_host(signature.clone(), ABORT_FUNC_INDEX),
-            _ => {
-                return Err(Error::Instantiation(format!(
-                    "Export '{}' not found",
-                    field_name
-                )));
-            }
-        })
+        env_export_index(field_name)
+            .map(|index| FuncInstance::alloc_host(signature.clone(), index))
+            .ok_or_else(|| Error::Instantiation(format!("Export '{}' not found", field_name)))
     }
 }
 
@@ -945,81 +1445,8 @@ pub struct ModuleResolver;
 
 impl ModuleImportResolver for ModuleResolver {
     fn resolve_func(&self, field_name: &str, signature: &Signature) -> Result<FuncRef, Error> {
-        let signature = signature.clone();
-        Ok(match field_name {
-            // store
-            "store.set" => FuncInstance::alloc_host(signature, STORE_SET_FUNC_INDEX),
-            "store.remove" => FuncInstance::alloc_host(signature, STORE_REMOVE_FUNC_INDEX),
-            "store.get" => FuncInstance::alloc_host(signature, STORE_GET_FUNC_INDEX),
-
-            // ethereum
-            "ethereum.call" => FuncInstance::alloc_host(signature, ETHEREUM_CALL_FUNC_INDEX),
-
-            // typeConversion
-            "typeConversion.bytesToString" => {
-                FuncInstance::alloc_host(signature, TYPE_CONVERSION_BYTES_TO_STRING_FUNC_INDEX)
-            }
-            "typeConversion.bytesToHex" => {
-                FuncInstance::alloc_host(signature, TYPE_CONVERSION_BYTES_TO_HEX_FUNC_INDEX)
-            }
-            "typeConversion.bigIntToString" => {
-                FuncInstance::alloc_host(signature, TYPE_CONVERSION_BIG_INT_TO_STRING_FUNC_INDEX)
-            }
-            "typeConversion.bigIntToHex" => {
-                FuncInstance::alloc_host(signature, TYPE_CONVERSION_BIG_INT_TO_HEX_FUNC_INDEX)
-            }
-            "typeConversion.stringToH160" => {
-                FuncInstance::alloc_host(signature, TYPE_CONVERSION_STRING_TO_H160_FUNC_INDEX)
-            }
-            "typeConversion.i32ToBigInt" => {
-                FuncInstance::alloc_host(signature, TYPE_CONVERSION_I32_TO_BIG_INT_FUNC_INDEX)
-            }
-            "typeConversion.bigIntToI32" => {
-                FuncInstance::alloc_host(signature, TYPE_CONVERSION_BIG_INT_TO_I32_FUNC_INDEX)
-            }
-            "typeConversion.bytesToBase58" => {
-                FuncInstance::alloc_host(signature, TYPE_CONVERSION_BYTES_TO_BASE_58_INDEX)
-            }
-
-            // json
-            "json.fromBytes" => FuncInstance::alloc_host(signature, JSON_FROM_BYTES_FUNC_INDEX),
-            "json.toI64" => FuncInstance::alloc_host(signature, JSON_TO_I64_FUNC_INDEX),
-            "json.toU64" => FuncInstance::alloc_host(signature, JSON_TO_U64_FUNC_INDEX),
-            "json.toF64" => FuncInstance::alloc_host(signature, JSON_TO_F64_FUNC_INDEX),
-            "json.toBigInt" => FuncInstance::alloc_host(signature, JSON_TO_BIG_INT_FUNC_INDEX),
-
-            // ipfs
-            "ipfs.cat" => FuncInstance::alloc_host(signature, IPFS_CAT_FUNC_INDEX),
-            "ipfs.map" => FuncInstance::alloc_host(signature, IPFS_MAP_FUNC_INDEX),
-
-            // crypto
-            "crypto.keccak256" => FuncInstance::alloc_host(signature, CRYPTO_KECCAK_256_INDEX),
-
-            // bigInt
-            "bigInt.plus" => FuncInstance::alloc_host(signature, BIG_INT_PLUS),
-            "bigInt.minus" => FuncInstance::alloc_host(signature, BIG_INT_MINUS),
-            "bigInt.times" => FuncInstance::alloc_host(signature, BIG_INT_TIMES),
-            "bigInt.dividedBy" => FuncInstance::alloc_host(signature, BIG_INT_DIVIDED_BY),
-            "bigInt.dividedByDecimal" => {
-                FuncInstance::alloc_host(signature, BIG_INT_DIVIDED_BY_DECIMAL)
-            }
-            "bigInt.mod" => FuncInstance::alloc_host(signature, BIG_INT_MOD),
-
-            // bigDecimal
-            "bigDecimal.plus" => FuncInstance::alloc_host(signature, BIG_DECIMAL_PLUS),
-            "bigDecimal.minus" => FuncInstance::alloc_host(signature, BIG_DECIMAL_MINUS),
-            "bigDecimal.times" => FuncInstance::alloc_host(signature, BIG_DECIMAL_TIMES),
-            "bigDecimal.dividedBy" => FuncInstance::alloc_host(signature, BIG_DECIMAL_DIVIDED_BY),
-            "bigDecimal.equals" => FuncInstance::alloc_host(signature, BIG_DECIMAL_EQUALS),
-            "bigDecimal.toString" => FuncInstance::alloc_host(signature, BIG_DECIMAL_TO_STRING),
-            "bigDecimal.fromString" => FuncInstance::alloc_host(signature, BIG_DECIMAL_FROM_STRING),
-
-            _ => {
-                return Err(Error::Instantiation(format!(
-                    "Export '{}' not found",
-                    field_name
-                )));
-            }
-        })
+        host_export_index(field_name)
+            .map(|index| FuncInstance::alloc_host(signature.clone(), index))
+            .ok_or_else(|| Error::Instantiation(format!("Export '{}' not found", field_name)))
     }
 }
@@ -95,7 +95,36 @@ fn test_valid_module(
 ) -> Arc<
     ValidModule<
         MockEthereumAdapter,
-        ipfs_api::IpfsClient,
+        IpfsResolver,
+        FakeStore,
+        Sender<Box<Future<Item = (), Error = ()> + Send>>,
+    >,
+> {
+    test_valid_module_with_templates(data_source, vec![])
+}
+
+fn test_valid_module_with_templates(
+    data_source: DataSource,
+    templates: Vec<DataSourceTemplate>,
+) -> Arc<
+    ValidModule<
+        MockEthereumAdapter,
+        IpfsResolver,
+        FakeStore,
+        Sender<Box<Future<Item = (), Error = ()> + Send>>,
+    >,
+> {
+    test_valid_module_with(data_source, templates, ResourceQuota::default())
+}
+
+fn test_valid_module_with(
+    data_source: DataSource,
+    templates: Vec<DataSourceTemplate>,
+    quota: ResourceQuota,
+) -> Arc<
+    ValidModule<
+        MockEthereumAdapter,
+        IpfsResolver,
         FakeStore,
         Sender<Box<Future<Item = (), Error = ()> + Send>>,
     >,
@@ -112,9 +141,17 @@ fn test_valid_module(
             WasmiModuleConfig {
                 subgraph_id: SubgraphDeploymentId::new("wasmModuleTest").unwrap(),
                 data_source,
+                templates: Arc::new(templates),
+                external_hosts: Arc::new(ExternalHosts::new(vec![], 1)),
+                quotas: Arc::new(DeploymentQuotas::new(quota)),
+                handler_stats: Arc::new(HandlerStats::default()),
                 ethereum_adapter: mock_ethereum_adapter,
-                link_resolver: Arc::new(ipfs_api::IpfsClient::default()),
+                link_resolver: Arc::new(IpfsResolver::new(
+                    &logger,
+                    vec![Arc::new(ipfs_api::IpfsClient::default())],
+                )),
                 store: Arc::new(FakeStore),
+                entity_reads: Default::default(),
             },
             task_sender,
         )
@@ -132,28 +169,35 @@ fn mock_data_source(path: &str) -> DataSource {
         source: Source {
             address: Some(Address::from_str("0123123123012312312301231231230123123123").unwrap()),
             abi: String::from("123123"),
+            file: None,
+            subgraph: None,
+            start_block: 0,
         },
         mapping: Mapping {
             kind: String::from("ethereum/events"),
             api_version: String::from("0.1.0"),
             language: String::from("wasm/assemblyscript"),
             entities: vec![],
+            reads: vec![],
             abis: vec![],
             event_handlers: vec![],
+            entity_handlers: vec![],
+            file_handler: None,
             link: Link {
                 link: "link".to_owned(),
             },
             runtime,
         },
+        context: None,
     }
 }
 
-fn mock_context() -> EventHandlerContext {
-    EventHandlerContext {
+fn mock_context() -> MappingContext {
+    MappingContext {
         logger: Logger::root(slog::Discard, o!()),
         block: Default::default(),
-        transaction: Default::default(),
         entity_operations: Vec::new(),
+        eth_calls: 0,
     }
 }
 
@@ -380,6 +424,80 @@ fn ipfs_map() {
     assert!(errmsg.contains("api returned error \\'invalid \\'ipfs ref\\' path\\'"))
 }
 
+#[test]
+fn ipfs_quota() {
+    let quota = ResourceQuota {
+        max_ipfs_bytes_per_block: Some(10),
+        ..ResourceQuota::default()
+    };
+    let cat_module = test_valid_module_with(
+        mock_data_source("wasm_test/ipfs_cat.wasm"),
+        vec![],
+        quota.clone(),
+    );
+    let map_module =
+        test_valid_module_with(mock_data_source("wasm_test/ipfs_map.wasm"), vec![], quota);
+    let ipfs = Arc::new(ipfs_api::IpfsClient::default());
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut add = |contents: String| {
+        runtime
+            .block_on(ipfs.add(Cursor::new(contents)))
+            .unwrap()
+            .hash
+    };
+    let small = add("4242".to_owned());
+    let large = add("x".repeat(1000));
+    let (thing, _) = make_thing("one", "eins");
+    let lines = add(format!("{}\n{}", thing, thing));
+
+    // Each module processes a block of its own, since the quota is shared
+    // by all modules of a deployment
+    let context = |number: u64| {
+        let mut block = EthereumBlock::default();
+        block.block.hash = Some(H256::from(number));
+        MappingContext {
+            block: Arc::new(block),
+            ..mock_context()
+        }
+    };
+    let cat = |block: u64, hash: &str| -> Result<(), Error> {
+        let mut module =
+            WasmiModule::from_valid_module_with_ctx(cat_module.clone(), context(block)).unwrap();
+        let hash = RuntimeValue::from(module.asc_new(hash));
+        module
+            .module
+            .clone()
+            .invoke_export("ipfsCat", &[hash], &mut module)?;
+        Ok(())
+    };
+
+    // Files are counted until the quota of the block is used up
+    assert!(cat(1, &small).is_ok());
+    assert!(cat(1, &small).is_ok());
+    let err = cat(1, &small).unwrap_err().to_string();
+    assert!(err.contains("quota of 10 bytes"), "{}", err);
+
+    // Files larger than the quota fail without being read completely
+    let err = cat(2, &large).unwrap_err().to_string();
+    assert!(err.contains("quota of 10 bytes"), "{}", err);
+
+    // Lines that `ipfs.map` reads count, too
+    let mut module =
+        WasmiModule::from_valid_module_with_ctx(map_module.clone(), context(3)).unwrap();
+    let user_data = RuntimeValue::from(module.asc_new(USER_DATA));
+    let err = module
+        .module
+        .clone()
+        .invoke_export(
+            "ipfsMap",
+            &[RuntimeValue::from(module.asc_new(&lines)), user_data],
+            &mut module,
+        )
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("quota of 10 bytes"), "{}", err);
+}
+
 #[test]
 fn ipfs_fail() {
     let valid_module = test_valid_module(mock_data_source("wasm_test/ipfs_cat.wasm"));
@@ -391,6 +509,22 @@ fn ipfs_fail() {
         .is_null());
 }
 
+#[test]
+fn ipfs_cat_from_undeclared_host_fails() {
+    let valid_module = test_valid_module(mock_data_source("wasm_test/ipfs_cat.wasm"));
+    let mut module = WasmiModule::from_valid_module_with_ctx(valid_module, mock_context()).unwrap();
+
+    let link = module.asc_new("https://files.example.com/42.json#sha256=00");
+    let err = module
+        .module
+        .clone()
+        .invoke_export("ipfsCat", &[RuntimeValue::from(link)], &mut module)
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("host files.example.com is not declared in the externalHosts"));
+}
+
 #[test]
 fn crypto_keccak256() {
     let valid_module = test_valid_module(mock_data_source("wasm_test/crypto.wasm"));
@@ -677,3 +811,102 @@ fn bytes_to_base58() {
     let base58: String = module.asc_get(result_ptr);
     assert_eq!(base58, "QmWmyoMoctfbAaiEs2G46gpeUmhqFRDW6KWo64y5r581Vz");
 }
+
+#[test]
+fn validate_mapping_reports_missing_handlers() {
+    let mut data_source = mock_data_source("wasm_test/abort.wasm");
+    data_source.mapping.api_version = String::from("0.0.1");
+    data_source.mapping.event_handlers = vec![
+        MappingEventHandler {
+            event: String::from("Abort()"),
+            handler: String::from("abort"),
+            receipt: false,
+        },
+        MappingEventHandler {
+            event: String::from("Missing()"),
+            handler: String::from("handleMissing"),
+            receipt: false,
+        },
+    ];
+    assert_eq!(
+        validate_mapping(&data_source.mapping),
+        vec![String::from(
+            "handler handleMissing is not exported by the WASM module"
+        )]
+    );
+
+    data_source.mapping.api_version = String::from("0.1.0");
+    data_source.mapping.event_handlers.pop();
+    assert_eq!(
+        validate_mapping(&data_source.mapping),
+        vec![format!(
+            "apiVersion 0.1.0 is not supported, supported versions are {}",
+            SUPPORTED_API_VERSIONS
+        )]
+    );
+}
+
+#[test]
+fn data_source_create_with_context() {
+    let data_source = mock_data_source("wasm_test/abort.wasm");
+    let template = DataSourceTemplate {
+        kind: data_source.kind.clone(),
+        network: data_source.network.clone(),
+        name: String::from("Exchange"),
+        source: TemplateSource {
+            abi: data_source.source.abi.clone(),
+        },
+        mapping: data_source.mapping.clone(),
+    };
+    let valid_module = test_valid_module_with_templates(data_source, vec![template.clone()]);
+    let mut module = WasmiModule::from_valid_module_with_ctx(valid_module, mock_context()).unwrap();
+    let host_exports = &module.valid_module.host_exports;
+
+    let mut context = Entity::new();
+    context.set("token", "0x0123");
+    host_exports
+        .data_source_create(
+            &mut module.ctx,
+            String::from("Exchange"),
+            vec![String::from("0x0123123123012312312301231231230123123124")],
+            Some(context.clone()),
+        )
+        .unwrap();
+    assert!(host_exports
+        .data_source_create(
+            &mut module.ctx,
+            String::from("Missing"),
+            vec![String::from("0x0123123123012312312301231231230123123124")],
+            None,
+        )
+        .is_err());
+    assert!(host_exports
+        .data_source_create(&mut module.ctx, String::from("Exchange"), vec![], None)
+        .is_err());
+
+    // The data source is written with the entities of the block and its
+    // mappings see the context once it is loaded
+    let created = match &module.ctx.entity_operations[..] {
+        [EntityOperation::Set { data, .. }] => {
+            schema::DynamicEthereumContractDataSourceEntity::from_entity(data).unwrap()
+        }
+        ops => panic!("expected the data source to be written, got {:?}", ops),
+    };
+    let data_source = DataSource::from_template(
+        &template,
+        created.address(),
+        created.context().cloned(),
+        created.block_ptr().number + 1,
+    );
+    assert_eq!(
+        data_source.source.address,
+        Some(Address::from_str("0123123123012312312301231231230123123124").unwrap())
+    );
+    let module =
+        WasmiModule::from_valid_module_with_ctx(test_valid_module(data_source), mock_context())
+            .unwrap();
+    assert_eq!(
+        module.valid_module.host_exports.data_source_context(),
+        context
+    );
+}
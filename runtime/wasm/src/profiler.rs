@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use graph::prelude::*;
+
+/// Environment variable that enables mapping profiling. Profiles are written
+/// to the directory it points to.
+const PROFILE_DIR_ENV_VAR: &str = "GRAPH_MAPPING_PROFILE_DIR";
+
+/// Samples are buffered until there are this many bytes of them...
+const PROFILE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// ...or until they are this old.
+const PROFILE_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Writes profiles of handler executions as folded stacks, the input format
+/// of flamegraph tools such as `flamegraph.pl` or `inferno`.
+///
+/// Every handler execution adds its samples to two files per subgraph
+/// deployment:
+///
+/// - `<deployment>.folded` attributes wall-clock time in microseconds to
+///   `dataSource;handler` for time spent in WASM code and to
+///   `dataSource;handler;hostExport` for time spent in host exports.
+/// - `<deployment>.gas.folded` attributes the instruction counts reported
+///   by the injected gas counter to `dataSource;handler`.
+///
+/// Flamegraph tools merge repeated stacks, so the files can simply grow
+/// while the subgraph is indexed. Samples are buffered and appended to the
+/// files in batches, and when the profiler is dropped.
+pub(crate) struct MappingProfiler {
+    time_samples: ProfileFile,
+    gas_samples: ProfileFile,
+    data_source: String,
+    host_export_names: HashMap<usize, String>,
+}
+
+impl MappingProfiler {
+    /// Creates a profiler if profiling is enabled via `GRAPH_MAPPING_PROFILE_DIR`.
+    pub fn from_env(
+        subgraph_id: &SubgraphDeploymentId,
+        data_source: &str,
+        host_export_names: HashMap<usize, String>,
+    ) -> Result<Option<Self>, Error> {
+        let dir = match env::var_os(PROFILE_DIR_ENV_VAR) {
+            Some(dir) => PathBuf::from(dir),
+            None => return Ok(None),
+        };
+        fs::create_dir_all(&dir)?;
+
+        Ok(Some(MappingProfiler {
+            time_samples: ProfileFile::new(dir.join(format!("{}.folded", subgraph_id))),
+            gas_samples: ProfileFile::new(dir.join(format!("{}.gas.folded", subgraph_id))),
+            data_source: data_source.to_owned(),
+            host_export_names,
+        }))
+    }
+
+    /// Adds the samples of one handler execution, which took `total_time`,
+    /// to the profile files.
+    pub fn write(
+        &self,
+        handler: &str,
+        total_time: Duration,
+        profile: HandlerProfile,
+    ) -> Result<(), Error> {
+        let stack = format!("{};{}", self.data_source, handler);
+        let host_time = profile
+            .host_time
+            .values()
+            .fold(Duration::from_secs(0), |sum, time| sum + *time);
+
+        let mut time_samples = format!(
+            "{} {}\n",
+            stack,
+            micros(total_time.checked_sub(host_time).unwrap_or_default())
+        );
+        for (index, time) in profile.host_time {
+            let name = self
+                .host_export_names
+                .get(&index)
+                .cloned()
+                .unwrap_or_else(|| format!("hostExport{}", index));
+            time_samples.push_str(&format!("{};{} {}\n", stack, name, micros(time)));
+        }
+
+        self.time_samples.write(&time_samples)?;
+        self.gas_samples
+            .write(&format!("{} {}\n", stack, profile.gas))
+    }
+}
+
+/// Samples collected while a single handler runs.
+pub(crate) struct HandlerProfile {
+    host_time: HashMap<usize, Duration>,
+    gas: u64,
+}
+
+impl HandlerProfile {
+    pub fn new() -> Self {
+        HandlerProfile {
+            host_time: HashMap::new(),
+            gas: 0,
+        }
+    }
+
+    pub fn record_host_export(&mut self, index: usize, time: Duration) {
+        *self
+            .host_time
+            .entry(index)
+            .or_insert(Duration::from_secs(0)) += time;
+    }
+
+    pub fn record_gas(&mut self, gas: u32) {
+        self.gas += gas as u64;
+    }
+}
+
+/// A profile file and the samples that haven't been written to it yet.
+struct ProfileFile {
+    path: PathBuf,
+    buffer: Mutex<SampleBuffer>,
+}
+
+struct SampleBuffer {
+    samples: String,
+    flushed_at: Instant,
+}
+
+impl ProfileFile {
+    fn new(path: PathBuf) -> Self {
+        ProfileFile {
+            path,
+            buffer: Mutex::new(SampleBuffer {
+                samples: String::new(),
+                flushed_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Buffers `samples` and appends the buffer to the file once it is
+    /// large or old enough.
+    fn write(&self, samples: &str) -> Result<(), Error> {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.samples.push_str(samples);
+        if buffer.samples.len() >= PROFILE_BUFFER_SIZE
+            || buffer.flushed_at.elapsed() >= PROFILE_FLUSH_INTERVAL
+        {
+            flush(&self.path, &mut buffer)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ProfileFile {
+    fn drop(&mut self) {
+        let buffer = self.buffer.get_mut().unwrap();
+        if !buffer.samples.is_empty() {
+            // There is nobody to report the error to
+            let _ = flush(&self.path, buffer);
+        }
+    }
+}
+
+/// Appends the buffered samples to the file at `path` with a single call,
+/// so that they don't get interleaved with the samples of data sources
+/// running on other threads.
+fn flush(path: &PathBuf, buffer: &mut SampleBuffer) -> Result<(), Error> {
+    let samples = std::mem::replace(&mut buffer.samples, String::new());
+    buffer.flushed_at = Instant::now();
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(samples.as_bytes())?;
+    Ok(())
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + duration.subsec_micros() as u64
+}
+
+#[test]
+fn samples_are_buffered_until_the_profile_is_dropped() {
+    let path = env::temp_dir().join(format!("graph-profile-{}.folded", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let file = ProfileFile::new(path.clone());
+    file.write("dataSource;handleEvent 10\n").unwrap();
+    file.write("dataSource;handleEvent;store.set 5\n").unwrap();
+    assert!(!path.exists());
+
+    drop(file);
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "dataSource;handleEvent 10\ndataSource;handleEvent;store.set 5\n"
+    );
+    fs::remove_file(&path).unwrap();
+}
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 
 use graph::components::ethereum::{
     EthereumBlockData, EthereumCallData, EthereumEventData, EthereumTransactionData,
+    EthereumTransactionReceiptData,
 };
 use graph::data::store;
 use graph::prelude::{BigDecimal, BigInt};
@@ -355,16 +356,68 @@ where
             block: heap.asc_new(&self.block),
             transaction: heap.asc_new::<T, EthereumTransactionData>(&self.transaction),
             params: heap.asc_new(self.params.as_slice()),
+            receipt: self
+                .receipt
+                .as_ref()
+                .map(|receipt| heap.asc_new(receipt))
+                .unwrap_or_else(|| AscPtr::null()),
+        }
+    }
+}
+
+impl ToAscObj<AscEthereumLog> for web3::Log {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumLog {
+        AscEthereumLog {
+            address: heap.asc_new(&self.address),
+            topics: heap.asc_new(self.topics.as_slice()),
+            data: heap.asc_new(&*self.data.0),
+            log_index: self
+                .log_index
+                .map(|log_index| heap.asc_new(&BigInt::from_unsigned_u256(&log_index)))
+                .unwrap_or_else(|| AscPtr::null()),
+            transaction_log_index: self
+                .transaction_log_index
+                .map(|index| heap.asc_new(&BigInt::from_unsigned_u256(&index)))
+                .unwrap_or_else(|| AscPtr::null()),
+            log_type: self
+                .log_type
+                .clone()
+                .map(|log_type| heap.asc_new(&log_type))
+                .unwrap_or_else(|| AscPtr::null()),
+        }
+    }
+}
+
+impl ToAscObj<AscEthereumTransactionReceipt> for EthereumTransactionReceiptData {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumTransactionReceipt {
+        AscEthereumTransactionReceipt {
+            transaction_hash: heap.asc_new(&self.transaction_hash),
+            transaction_index: heap.asc_new(&BigInt::from(self.transaction_index)),
+            cumulative_gas_used: heap
+                .asc_new(&BigInt::from_unsigned_u256(&self.cumulative_gas_used)),
+            gas_used: heap.asc_new(&BigInt::from_unsigned_u256(&self.gas_used)),
+            contract_address: self
+                .contract_address
+                .map(|address| heap.asc_new(&address))
+                .unwrap_or_else(|| AscPtr::null()),
+            status: self
+                .status
+                .map(|status| heap.asc_new(&BigInt::from_unsigned_u256(&status)))
+                .unwrap_or_else(|| AscPtr::null()),
+            logs: heap.asc_new(self.logs.as_slice()),
         }
     }
 }
 
-impl ToAscObj<AscEthereumCall> for EthereumCallData {
-    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumCall {
+impl<T: AscType> ToAscObj<AscEthereumCall<T>> for EthereumCallData
+where
+    EthereumTransactionData: ToAscObj<T>,
+{
+    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumCall<T> {
         AscEthereumCall {
             address: heap.asc_new(&self.address),
             block: heap.asc_new(&self.block),
-            transaction: heap.asc_new(&self.transaction),
+            transaction: heap.asc_new::<T, EthereumTransactionData>(&self.transaction),
             inputs: heap.asc_new(self.inputs.as_slice()),
             outputs: heap.asc_new(self.outputs.as_slice()),
         }
@@ -0,0 +1,248 @@
+use futures::sync::mpsc::{unbounded, Receiver, UnboundedReceiver, UnboundedSender};
+use std::collections::HashMap;
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::host::{panic_message, MappingRequest};
+use graph::prelude::*;
+use graph::util;
+
+/// How many threads run the handlers of all data sources on this node.
+const WORKERS_ENV_VAR: &str = "GRAPH_MAPPING_WORKERS";
+
+fn mapping_workers() -> usize {
+    env::var(WORKERS_ENV_VAR)
+        .ok()
+        .and_then(|s| usize::from_str(&s).ok())
+        .filter(|workers| *workers > 0)
+        .unwrap_or(8)
+}
+
+/// Runs the mapping requests of one host on the worker the host is pinned to.
+pub(crate) type MappingRunner = Box<FnMut(MappingRequest)>;
+
+/// Creates the runner of a host. It is called once, on the worker, because
+/// WASM modules can't be moved between threads.
+pub(crate) type StartRunner = Box<FnMut() -> Result<MappingRunner, Error> + Send>;
+
+enum WorkerJob {
+    Start(usize, StartRunner),
+    Run(usize, MappingRequest),
+    Stop(usize),
+}
+
+/// A fixed number of threads that run the mappings of all hosts built by a
+/// `RuntimeHostBuilder`.
+///
+/// Every host is pinned to one worker, which keeps the WASM module of the
+/// host and runs its requests one after the other, in the order in which
+/// they were sent. Handlers of the same data source therefore run exactly
+/// like they would on a thread of their own, while the handlers of hosts
+/// that are pinned to different workers run in parallel.
+pub(crate) struct MappingWorkers {
+    workers: Vec<UnboundedSender<WorkerJob>>,
+    next_host: AtomicUsize,
+}
+
+impl MappingWorkers {
+    /// Starts as many workers as `GRAPH_MAPPING_WORKERS` asks for.
+    pub fn from_env() -> Self {
+        Self::new(mapping_workers())
+    }
+
+    pub fn new(count: usize) -> Self {
+        let workers = (0..count)
+            .map(|worker| {
+                let (job_sender, job_receiver) = unbounded();
+                thread::Builder::new()
+                    .name(format!("{}-{}", util::log::MAPPING_THREAD_PREFIX, worker))
+                    .spawn(move || work(job_receiver))
+                    .expect("Spawning WASM runtime thread failed.");
+                job_sender
+            })
+            .collect();
+        MappingWorkers {
+            workers,
+            next_host: AtomicUsize::new(0),
+        }
+    }
+
+    /// Pins a new host to one of the workers. The worker creates the runner
+    /// of the host with `start` and passes it the requests from `requests`
+    /// until all senders of requests are dropped.
+    pub fn run(&self, start: StartRunner, requests: Receiver<MappingRequest>) {
+        let host = self.next_host.fetch_add(1, Ordering::SeqCst);
+        let worker = self.workers[host % self.workers.len()].clone();
+
+        // A worker handles its jobs in order, so the runner of the host
+        // exists before the first request arrives
+        if worker
+            .unbounded_send(WorkerJob::Start(host, start))
+            .is_err()
+        {
+            return;
+        }
+        tokio::spawn(
+            requests
+                .map(move |request| WorkerJob::Run(host, request))
+                .chain(stream::once(Ok(WorkerJob::Stop(host))))
+                .forward(worker.sink_map_err(|_| ()))
+                .map(|_| ()),
+        );
+    }
+}
+
+/// Handles the jobs of one worker until the `MappingWorkers` are dropped.
+fn work(jobs: UnboundedReceiver<WorkerJob>) {
+    let mut runners: HashMap<usize, Result<MappingRunner, String>> = HashMap::new();
+
+    for job in jobs.wait() {
+        match job {
+            Ok(WorkerJob::Start(host, mut start)) => {
+                let runner = panic::catch_unwind(AssertUnwindSafe(|| start()))
+                    .unwrap_or_else(|panic| {
+                        Err(format_err!(
+                            "WASM runtime panicked while starting: {}",
+                            panic_message(&*panic)
+                        ))
+                    })
+                    .map_err(|e| e.to_string());
+                runners.insert(host, runner);
+            }
+            Ok(WorkerJob::Run(host, request)) => match runners.get_mut(&host) {
+                Some(Ok(runner)) => runner(request),
+                Some(Err(e)) => {
+                    let _ = request
+                        .result_sender
+                        .send(Err(format_err!("Failed to start WASM runtime: {}", e)));
+                }
+                None => unreachable!("requests are only sent to started hosts"),
+            },
+            Ok(WorkerJob::Stop(host)) => {
+                runners.remove(&host);
+            }
+            Err(()) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::MappingTrigger;
+    use futures::sync::mpsc::channel;
+    use futures::sync::oneshot;
+
+    fn block_request() -> (
+        MappingRequest,
+        oneshot::Receiver<Result<Vec<EntityOperation>, Error>>,
+    ) {
+        let (result_sender, result_receiver) = oneshot::channel();
+        let request = MappingRequest {
+            logger: Logger::root(slog::Discard, o!()),
+            block: Arc::new(EthereumBlock::default()),
+            trigger: MappingTrigger::Block {
+                handler: MappingBlockHandler {
+                    handler: "handleBlock".to_owned(),
+                    filter: None,
+                },
+            },
+            entity_operations: vec![],
+            result_sender,
+        };
+        (request, result_receiver)
+    }
+
+    /// Answers every request with an error naming the thread it ran on and
+    /// how many requests the host handled before.
+    fn thread_runner() -> StartRunner {
+        Box::new(|| -> Result<MappingRunner, Error> {
+            let mut handled = 0;
+            Ok(Box::new(move |request: MappingRequest| {
+                let thread = thread::current().name().unwrap().to_owned();
+                let _ = request
+                    .result_sender
+                    .send(Err(format_err!("{} {}", thread, handled)));
+                handled += 1;
+            }) as MappingRunner)
+        })
+    }
+
+    /// Sends `count` requests to a host and returns the answers.
+    fn run_requests(
+        runtime: &mut tokio::runtime::Runtime,
+        workers: &Arc<MappingWorkers>,
+        start: StartRunner,
+        count: usize,
+    ) -> Vec<String> {
+        let (sender, receiver) = channel(100);
+        let workers = workers.clone();
+        runtime
+            .block_on(future::lazy(move || {
+                workers.run(start, receiver);
+                stream::iter_ok::<_, Error>(0..count)
+                    .and_then(move |_| {
+                        let (request, result_receiver) = block_request();
+                        sender
+                            .clone()
+                            .send(request)
+                            .map_err(|_| format_err!("request not sent"))
+                            .and_then(|_| {
+                                result_receiver.map_err(|_| format_err!("request dropped"))
+                            })
+                    })
+                    .map(|result| result.unwrap_err().to_string())
+                    .collect()
+            }))
+            .unwrap()
+    }
+
+    #[test]
+    fn hosts_are_pinned_to_one_worker() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let workers = Arc::new(MappingWorkers::new(2));
+
+        let first = run_requests(&mut runtime, &workers, thread_runner(), 3);
+        let second = run_requests(&mut runtime, &workers, thread_runner(), 2);
+        let third = run_requests(&mut runtime, &workers, thread_runner(), 1);
+
+        assert_eq!(
+            first,
+            vec![
+                "mapping-thread-0 0",
+                "mapping-thread-0 1",
+                "mapping-thread-0 2"
+            ]
+        );
+        assert_eq!(second, vec!["mapping-thread-1 0", "mapping-thread-1 1"]);
+        assert_eq!(third, vec!["mapping-thread-0 0"]);
+    }
+
+    #[test]
+    fn hosts_that_fail_to_start_fail_their_requests() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let workers = Arc::new(MappingWorkers::new(1));
+
+        let failed = run_requests(
+            &mut runtime,
+            &workers,
+            Box::new(|| -> Result<MappingRunner, Error> { panic!("invalid module") }),
+            2,
+        );
+        assert_eq!(
+            failed,
+            vec![
+                "Failed to start WASM runtime: \
+                 WASM runtime panicked while starting: invalid module";
+                2
+            ]
+        );
+
+        // The worker keeps running the requests of other hosts
+        let succeeded = run_requests(&mut runtime, &workers, thread_runner(), 1);
+        assert_eq!(succeeded, vec!["mapping-thread-0 0"]);
+    }
+}
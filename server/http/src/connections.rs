@@ -0,0 +1,412 @@
+use futures::future::Either;
+use hyper::server::conn::Http;
+use hyper::service::Service;
+use hyper::{Body, Chunk, Request, Response};
+use std::env;
+use std::error::Error as StdError;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use graph::prelude::*;
+use graph::tokio;
+use graph::tokio::io::{AsyncRead, AsyncWrite};
+use graph::tokio::net::{TcpListener, TcpStream};
+use graph::tokio::timer::{Delay, Interval};
+
+/// How often the timeouts of a connection are checked.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait before accepting connections again after accepting one
+/// failed, e.g. because the process ran out of file descriptors.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+/// Limits on the connections of an HTTP server, which keep slow or idle
+/// clients from holding on to the sockets of the node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionLimits {
+    /// Connections beyond this many are closed right after they are
+    /// accepted.
+    pub max_connections: usize,
+    /// How long a kept-alive connection may wait for its next request. Zero
+    /// disables keep-alive.
+    pub idle_timeout: Duration,
+    /// How long a client may take to send the headers of a request, from
+    /// connecting or from the first byte of a request on a kept-alive
+    /// connection.
+    pub header_timeout: Duration,
+    /// How long a client may take to send the body of a request once its
+    /// headers have arrived.
+    pub read_timeout: Duration,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        ConnectionLimits {
+            max_connections: 10000,
+            idle_timeout: Duration::from_secs(60),
+            header_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ConnectionLimits {
+    /// The limits set with the `GRAPH_HTTP_*` environment variables, and
+    /// the defaults for those that aren't set.
+    pub fn from_env() -> Self {
+        let defaults = ConnectionLimits::default();
+        ConnectionLimits {
+            max_connections: env_number("GRAPH_HTTP_MAX_CONNECTIONS")
+                .map_or(defaults.max_connections, |n| n as usize),
+            idle_timeout: env_number("GRAPH_HTTP_IDLE_TIMEOUT")
+                .map_or(defaults.idle_timeout, Duration::from_secs),
+            header_timeout: env_number("GRAPH_HTTP_HEADER_TIMEOUT")
+                .map_or(defaults.header_timeout, Duration::from_secs),
+            read_timeout: env_number("GRAPH_HTTP_READ_TIMEOUT")
+                .map_or(defaults.read_timeout, Duration::from_secs),
+        }
+    }
+}
+
+fn env_number(name: &str) -> Option<u64> {
+    env::var(name)
+        .ok()
+        .map(|s| u64::from_str(&s).unwrap_or_else(|_| panic!("failed to parse env var {}", name)))
+}
+
+/// Serves HTTP on `addr`, with a service created by `new_service` for each
+/// connection from the address of the client, and closes connections that
+/// exceed `limits`.
+pub fn serve<N, S>(
+    logger: Logger,
+    addr: SocketAddr,
+    limits: ConnectionLimits,
+    new_service: N,
+) -> Result<Box<Future<Item = (), Error = ()> + Send>, io::Error>
+where
+    N: Fn(SocketAddr) -> S + Send + 'static,
+    S: Service<ReqBody = Body, ResBody = Body> + Send + 'static,
+    S::Error: Into<Box<StdError + Send + Sync>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    let listener = TcpListener::bind(&addr)?;
+    Ok(serve_listener(logger, listener, limits, new_service))
+}
+
+/// Like `serve`, but accepts connections on a listener that is already
+/// bound, e.g. to a port picked by the operating system.
+pub fn serve_listener<N, S>(
+    logger: Logger,
+    listener: TcpListener,
+    limits: ConnectionLimits,
+    new_service: N,
+) -> Box<Future<Item = (), Error = ()> + Send>
+where
+    N: Fn(SocketAddr) -> S + Send + 'static,
+    S: Service<ReqBody = Body, ResBody = Body> + Send + 'static,
+    S::Error: Into<Box<StdError + Send + Sync>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    let mut http = Http::new();
+    http.keep_alive(limits.idle_timeout > Duration::from_secs(0));
+    let active = Arc::new(AtomicUsize::new(0));
+
+    let task = listener
+        .incoming()
+        .then(Ok::<_, ()>)
+        .for_each(move |result| {
+            let stream = match result {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(logger, "Failed to accept HTTP connection"; "error" => e.to_string());
+                    return Either::B(
+                        Delay::new(Instant::now() + ACCEPT_ERROR_DELAY).map_err(|_| ()),
+                    );
+                }
+            };
+
+            let peer_addr = match stream.peer_addr() {
+                Ok(peer_addr) => peer_addr,
+                Err(e) => {
+                    debug!(
+                        logger,
+                        "Closing HTTP connection without a peer address";
+                        "error" => e.to_string()
+                    );
+                    return Either::A(future::ok(()));
+                }
+            };
+
+            if active.load(Ordering::SeqCst) >= limits.max_connections {
+                warn!(
+                    logger,
+                    "Too many HTTP connections, closing new connection";
+                    "max_connections" => limits.max_connections,
+                );
+                return Either::A(future::ok(()));
+            }
+            let guard = ActiveConnection::new(active.clone());
+
+            // A new connection has until the header timeout to send its
+            // first request
+            let state = Arc::new(Mutex::new(ConnectionState {
+                idle_since: None,
+                request_since: Some(Instant::now()),
+            }));
+            let stream = TrackedStream {
+                stream,
+                state: state.clone(),
+            };
+            let service = TrackedService {
+                service: new_service(peer_addr),
+                state: state.clone(),
+                read_timeout: limits.read_timeout,
+            };
+            let watchdog = Watchdog {
+                state,
+                idle_timeout: limits.idle_timeout,
+                header_timeout: limits.header_timeout,
+                interval: Interval::new(Instant::now() + WATCHDOG_INTERVAL, WATCHDOG_INTERVAL),
+            };
+
+            let logger = logger.clone();
+            let connection = http
+                .serve_connection(stream, service)
+                .select2(watchdog)
+                .then(move |result| {
+                    let _guard = guard;
+                    match result {
+                        Ok(Either::A(_)) => {}
+                        Ok(Either::B((reason, _))) => {
+                            debug!(logger, "Closing HTTP connection"; "reason" => reason)
+                        }
+                        Err(Either::A((e, _))) => {
+                            debug!(logger, "HTTP connection failed"; "error" => e.to_string())
+                        }
+                        Err(Either::B(_)) => {
+                            debug!(logger, "Closing HTTP connection, its timer failed")
+                        }
+                    }
+                    Ok(())
+                });
+            tokio::spawn(connection);
+            Either::A(future::ok(()))
+        });
+
+    Box::new(task)
+}
+
+/// Counts a connection as active until it is dropped.
+struct ActiveConnection(Arc<AtomicUsize>);
+
+impl ActiveConnection {
+    fn new(active: Arc<AtomicUsize>) -> Self {
+        active.fetch_add(1, Ordering::SeqCst);
+        ActiveConnection(active)
+    }
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// What a connection is doing. While a request is being handled, neither
+/// is set.
+#[derive(Debug)]
+struct ConnectionState {
+    /// Since when the connection has been waiting for its next request.
+    idle_since: Option<Instant>,
+    /// Since when the connection has been receiving the headers of a
+    /// request.
+    request_since: Option<Instant>,
+}
+
+/// A TCP stream that notes when the next request on an idle connection
+/// starts arriving.
+struct TrackedStream {
+    stream: TcpStream,
+    state: Arc<Mutex<ConnectionState>>,
+}
+
+impl Read for TrackedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stream.read(buf)?;
+        if n > 0 {
+            let mut state = self.state.lock().unwrap();
+            if state.idle_since.is_some() {
+                state.idle_since = None;
+                state.request_since = Some(Instant::now());
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Write for TrackedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl AsyncRead for TrackedStream {}
+
+impl AsyncWrite for TrackedStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        AsyncWrite::shutdown(&mut self.stream)
+    }
+}
+
+/// A service that notes when a connection starts and stops handling a
+/// request, and limits how long reading the request body may take.
+struct TrackedService<S> {
+    service: S,
+    state: Arc<Mutex<ConnectionState>>,
+    read_timeout: Duration,
+}
+
+impl<S> Service for TrackedService<S>
+where
+    S: Service<ReqBody = Body, ResBody = Body>,
+    S::Error: Into<Box<StdError + Send + Sync>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = S::Error;
+    type Future = Box<Future<Item = Response<Body>, Error = S::Error> + Send>;
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.idle_since = None;
+            state.request_since = None;
+        }
+
+        let (parts, body) = req.into_parts();
+        let body = Body::wrap_stream(DeadlineBody {
+            body,
+            deadline: Delay::new(Instant::now() + self.read_timeout),
+        });
+
+        let state = self.state.clone();
+        Box::new(
+            self.service
+                .call(Request::from_parts(parts, body))
+                .then(move |result| {
+                    state.lock().unwrap().idle_since = Some(Instant::now());
+                    result
+                }),
+        )
+    }
+}
+
+/// A request body that fails if it hasn't been read completely by the
+/// deadline.
+struct DeadlineBody {
+    body: Body,
+    deadline: Delay,
+}
+
+impl Stream for DeadlineBody {
+    type Item = Chunk;
+    type Error = Box<StdError + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, Self::Error> {
+        match self.body.poll() {
+            Ok(Async::NotReady) => {}
+            result => return result.map_err(Into::into),
+        }
+        match self.deadline.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            _ => Err("timed out reading the request body".into()),
+        }
+    }
+}
+
+/// Resolves with the reason for closing a connection once the connection
+/// exceeds its header or idle timeout.
+struct Watchdog {
+    state: Arc<Mutex<ConnectionState>>,
+    idle_timeout: Duration,
+    header_timeout: Duration,
+    interval: Interval,
+}
+
+impl Future for Watchdog {
+    type Item = &'static str;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<&'static str, ()> {
+        loop {
+            match self.interval.poll() {
+                Ok(Async::Ready(Some(_))) => {}
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(None)) | Err(_) => return Err(()),
+            }
+
+            let state = self.state.lock().unwrap();
+            if let Some(since) = state.request_since {
+                if since.elapsed() > self.header_timeout {
+                    return Ok(Async::Ready("timed out waiting for the request headers"));
+                }
+            }
+            if let Some(since) = state.idle_since {
+                if since.elapsed() > self.idle_timeout {
+                    return Ok(Async::Ready("idle timeout"));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn connections_exceeding_limits_are_closed() {
+    use hyper::service::service_fn_ok;
+    use std::net::TcpStream as StdTcpStream;
+
+    let limits = ConnectionLimits {
+        max_connections: 1,
+        header_timeout: Duration::from_secs(1),
+        ..ConnectionLimits::default()
+    };
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let logger = Logger::root(slog::Discard, o!());
+    let server = serve_listener(logger, listener, limits, |_| {
+        service_fn_ok(|_| Response::new(Body::from("ok")))
+    });
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.spawn(server);
+
+    // A client that never sends its request is disconnected after the
+    // header timeout
+    let mut silent = StdTcpStream::connect(addr).unwrap();
+    silent
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+
+    // Connections beyond the limit are closed right away
+    let mut extra = StdTcpStream::connect(addr).unwrap();
+    extra
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    extra.write_all(b"GET / HTTP/1.1\r\n\r\n").ok();
+    let mut buf = [0u8; 64];
+    assert_eq!(extra.read(&mut buf).unwrap_or(0), 0);
+
+    let start = Instant::now();
+    assert_eq!(silent.read(&mut buf).unwrap(), 0);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
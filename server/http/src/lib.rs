@@ -9,13 +9,16 @@ extern crate hyper;
 extern crate itertools;
 extern crate serde;
 
+mod connections;
+mod multi;
 mod request;
 mod response;
 mod server;
 mod service;
 
+pub use self::connections::{serve, serve_listener, ConnectionLimits};
 pub use self::request::GraphQLRequest;
-pub use self::response::GraphQLResponse;
+pub use self::response::{GraphQLResponse, CONSISTENCY_TOKEN_HEADER};
 pub use self::server::GraphQLServer;
 pub use self::service::{GraphQLService, GraphQLServiceResponse};
 
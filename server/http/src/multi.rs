@@ -0,0 +1,365 @@
+use graphql_parser::query as q;
+use std::collections::{BTreeMap, HashSet};
+use std::iter;
+
+use graph::components::server::query::GraphQLServerError;
+use graph::data::subgraph::schema::{SubgraphManifestEntity, SUBGRAPHS_ID};
+use graph::prelude::*;
+
+/// The root field that addresses a subgraph in a cross-subgraph query.
+const SUBGRAPH_FIELD: &str = "subgraph";
+
+/// The subgraph that a root field of a cross-subgraph query addresses.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SubgraphTarget {
+    Name(String),
+    Id(String),
+}
+
+/// The part of a cross-subgraph query that addresses one subgraph: the
+/// selections of one `subgraph` root field, as a query of their own.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubgraphSelection {
+    /// The key of the results in the response, i.e. the alias of the root
+    /// field.
+    pub key: String,
+    pub target: SubgraphTarget,
+    pub document: q::Document,
+}
+
+fn client_error(message: impl Into<String>) -> GraphQLServerError {
+    GraphQLServerError::ClientError(message.into())
+}
+
+/// Splits a cross-subgraph query into one query per root field. The root
+/// fields must all be `subgraph(name: ...)` or `subgraph(id: ...)` fields,
+/// with different aliases; their selections are run against the subgraph
+/// they address, with the fragments and variables of the whole query.
+pub fn split_query(
+    document: &q::Document,
+    variables: Option<&QueryVariables>,
+) -> Result<Vec<SubgraphSelection>, GraphQLServerError> {
+    let mut operations = document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            q::Definition::Operation(operation) => Some(operation),
+            q::Definition::Fragment(_) => None,
+        });
+    let operation = match (operations.next(), operations.next()) {
+        (Some(operation), None) => operation,
+        _ => {
+            return Err(client_error(
+                "A cross-subgraph query must contain exactly one operation",
+            ))
+        }
+    };
+    let (name, variable_definitions, selection_set) = match operation {
+        q::OperationDefinition::SelectionSet(selection_set) => (None, vec![], selection_set),
+        q::OperationDefinition::Query(query) => (
+            query.name.clone(),
+            query.variable_definitions.clone(),
+            &query.selection_set,
+        ),
+        _ => {
+            return Err(client_error(
+                "Cross-subgraph requests only support queries, \
+                 not mutations or subscriptions",
+            ))
+        }
+    };
+    if selection_set.items.is_empty() {
+        return Err(client_error(
+            "A cross-subgraph query must address at least one subgraph",
+        ));
+    }
+    let fragments = document
+        .definitions
+        .iter()
+        .filter(|definition| match definition {
+            q::Definition::Fragment(_) => true,
+            q::Definition::Operation(_) => false,
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut keys = HashSet::new();
+    selection_set
+        .items
+        .iter()
+        .map(|selection| {
+            let field = match selection {
+                q::Selection::Field(field)
+                    if field.name == SUBGRAPH_FIELD && field.directives.is_empty() =>
+                {
+                    field
+                }
+                _ => {
+                    return Err(client_error(
+                        "The root fields of a cross-subgraph query must be \
+                         `subgraph(name: ...)` or `subgraph(id: ...)` fields without directives",
+                    ))
+                }
+            };
+
+            let key = field.alias.clone().unwrap_or_else(|| field.name.clone());
+            if !keys.insert(key.clone()) {
+                return Err(client_error(format!(
+                    "More than one root field is called `{}`; give them different aliases",
+                    key
+                )));
+            }
+
+            let target = match field.arguments.as_slice() {
+                [(argument, value)] => {
+                    let value = string_argument(value, variables)?;
+                    match argument.as_str() {
+                        "name" => SubgraphTarget::Name(value),
+                        "id" => SubgraphTarget::Id(value),
+                        _ => return Err(subgraph_argument_error(&key)),
+                    }
+                }
+                _ => return Err(subgraph_argument_error(&key)),
+            };
+
+            let query = q::Definition::Operation(q::OperationDefinition::Query(q::Query {
+                position: field.position,
+                name: name.clone(),
+                variable_definitions: variable_definitions.clone(),
+                directives: vec![],
+                selection_set: field.selection_set.clone(),
+            }));
+            Ok(SubgraphSelection {
+                key,
+                target,
+                document: q::Document {
+                    definitions: iter::once(query).chain(fragments.iter().cloned()).collect(),
+                },
+            })
+        })
+        .collect()
+}
+
+fn subgraph_argument_error(key: &str) -> GraphQLServerError {
+    client_error(format!(
+        "The root field `{}` must have either a `name` or an `id` argument",
+        key
+    ))
+}
+
+/// The value of a `name` or `id` argument, which is either a string or a
+/// variable with a string value.
+fn string_argument(
+    value: &q::Value,
+    variables: Option<&QueryVariables>,
+) -> Result<String, GraphQLServerError> {
+    let value = match value {
+        q::Value::Variable(name) => variables
+            .and_then(|variables| variables.get(name))
+            .ok_or_else(|| client_error(format!("No value provided for variable `{}`", name)))?,
+        value => value,
+    };
+    match value {
+        q::Value::String(s) => Ok(s.to_owned()),
+        _ => Err(client_error(
+            "The subgraph name or ID in a cross-subgraph query must be a string",
+        )),
+    }
+}
+
+/// The query of one part of a cross-subgraph query, ready to run against
+/// the deployment it addresses.
+pub struct MultiQueryPart {
+    pub key: String,
+    pub subgraph_id: SubgraphDeploymentId,
+    /// The network the deployment indexes, if any.
+    pub network: Option<String>,
+    pub query: Query,
+}
+
+/// The block that a deployment addressed by a cross-subgraph query has
+/// been indexed up to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeploymentBlock {
+    pub key: String,
+    pub network: String,
+    pub block_ptr: EthereumBlockPointer,
+}
+
+/// The block that the deployments on each network are queried at: the
+/// earliest block any of them is at, which all of them have processed.
+pub fn pinned_blocks(blocks: &[DeploymentBlock]) -> BTreeMap<String, EthereumBlockPointer> {
+    let mut pinned: BTreeMap<String, EthereumBlockPointer> = BTreeMap::new();
+    for block in blocks {
+        let earliest = pinned
+            .entry(block.network.clone())
+            .or_insert(block.block_ptr);
+        if block.block_ptr.number < earliest.number {
+            *earliest = block.block_ptr;
+        }
+    }
+    pinned
+}
+
+/// Describes the deployments that are now before the block they were
+/// queried at, because that block was reverted in a chain reorganization,
+/// or returns `None` if there are none.
+pub fn reverted_deployments(
+    pinned: &BTreeMap<String, EthereumBlockPointer>,
+    blocks: &[DeploymentBlock],
+) -> Option<String> {
+    let descriptions = blocks
+        .iter()
+        .filter(|block| block.block_ptr.number < pinned[&block.network].number)
+        .map(|block| {
+            format!(
+                "`{}` was reverted to block {} while it was queried at block {}",
+                block.key, block.block_ptr.number, pinned[&block.network].number
+            )
+        })
+        .collect::<Vec<_>>();
+    if descriptions.is_empty() {
+        None
+    } else {
+        Some(descriptions.join(", "))
+    }
+}
+
+/// Combines the results of the parts of a cross-subgraph query into one
+/// result, with the data of each part under its key.
+pub fn combine_results(results: Vec<(String, QueryResult)>) -> QueryResult {
+    let mut data = BTreeMap::new();
+    let mut errors = vec![];
+    for (key, result) in results {
+        data.insert(key, result.data.unwrap_or(q::Value::Null));
+        errors.extend(result.errors.unwrap_or_default());
+    }
+    QueryResult {
+        data: Some(q::Value::Object(data)),
+        errors: if errors.is_empty() {
+            None
+        } else {
+            Some(errors)
+        },
+    }
+}
+
+/// The Ethereum network a deployment indexes, as declared by its first
+/// data source, or `None` for the subgraph of subgraphs.
+pub fn deployment_network<S: Store>(
+    store: &S,
+    subgraph_id: &SubgraphDeploymentId,
+) -> Result<Option<String>, Error> {
+    if *subgraph_id == *SUBGRAPHS_ID {
+        return Ok(None);
+    }
+
+    let manifest = store.get(SubgraphManifestEntity::key(SubgraphManifestEntity::id(
+        subgraph_id,
+    )))?;
+    let data_source_id = match manifest.as_ref().and_then(|m| m.get("dataSources")) {
+        Some(Value::List(data_sources)) => match data_sources.first() {
+            Some(Value::String(data_source_id)) => data_source_id.to_owned(),
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+
+    let data_source = store.get(EntityKey {
+        subgraph_id: SUBGRAPHS_ID.clone(),
+        entity_type: "EthereumContractDataSource".to_owned(),
+        entity_id: data_source_id,
+    })?;
+    let network = match data_source.as_ref().and_then(|ds| ds.get("network")) {
+        Some(Value::String(network)) => Some(network.to_owned()),
+        _ => None,
+    };
+    Ok(network)
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::web3::types::H256;
+    use graphql_parser::parse_query;
+
+    use super::*;
+
+    #[test]
+    fn splits_queries_by_subgraph() {
+        let document = parse_query(
+            "query Dashboard($ens: String!) {
+               tokens: subgraph(name: \"org/tokens\") { tokens { ...TokenFields } }
+               ens: subgraph(id: $ens) { domains { id } }
+             }
+             fragment TokenFields on Token { id }",
+        )
+        .unwrap();
+        let mut variables = QueryVariables::default();
+        variables.insert("ens".to_owned(), q::Value::String("QmEns".to_owned()));
+
+        let selections = split_query(&document, Some(&variables)).unwrap();
+        assert_eq!(selections.len(), 2);
+        assert_eq!(selections[0].key, "tokens");
+        assert_eq!(
+            selections[0].target,
+            SubgraphTarget::Name("org/tokens".to_owned())
+        );
+        assert_eq!(selections[1].target, SubgraphTarget::Id("QmEns".to_owned()));
+
+        // Each part is a query of its own, with all fragments
+        let tokens = selections[0].document.to_string();
+        assert!(tokens.contains("query Dashboard($ens: String!)"));
+        assert!(tokens.contains("tokens {"));
+        assert!(tokens.contains("fragment TokenFields on Token"));
+        assert!(!tokens.contains("domains"));
+
+        // Root fields must address subgraphs under different keys
+        let other_fields = parse_query("{ tokens { id } }").unwrap();
+        assert!(split_query(&other_fields, None).is_err());
+        let same_key = parse_query(
+            "{ subgraph(name: \"org/a\") { a { id } } subgraph(name: \"org/b\") { b { id } } }",
+        )
+        .unwrap();
+        assert!(split_query(&same_key, None).is_err());
+    }
+
+    #[test]
+    fn pins_networks_to_their_earliest_block() {
+        let block = |key: &str, network: &str, number: u64| DeploymentBlock {
+            key: key.to_owned(),
+            network: network.to_owned(),
+            block_ptr: EthereumBlockPointer {
+                hash: H256::from(number),
+                number,
+            },
+        };
+
+        let before = [
+            block("a", "mainnet", 10),
+            block("b", "mainnet", 9),
+            block("c", "ropsten", 5),
+        ];
+        let pinned = pinned_blocks(&before);
+        assert_eq!(pinned.len(), 2);
+        assert_eq!(pinned["mainnet"].number, 9);
+        assert_eq!(pinned["ropsten"].number, 5);
+
+        // Deployments that advanced while they were queried are fine
+        let after = [
+            block("a", "mainnet", 11),
+            block("b", "mainnet", 9),
+            block("c", "ropsten", 5),
+        ];
+        assert_eq!(reverted_deployments(&pinned, &after), None);
+
+        let after = [
+            block("a", "mainnet", 8),
+            block("b", "mainnet", 9),
+            block("c", "ropsten", 5),
+        ];
+        assert_eq!(
+            reverted_deployments(&pinned, &after),
+            Some("`a` was reverted to block 8 while it was queried at block 9".to_owned())
+        );
+    }
+}
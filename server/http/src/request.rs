@@ -1,5 +1,6 @@
 use graph::serde_json;
 use graphql_parser;
+use graphql_parser::query as q;
 use hyper::Chunk;
 
 use graph::components::server::query::GraphQLServerError;
@@ -23,55 +24,58 @@ impl Future for GraphQLRequest {
     type Error = GraphQLServerError;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        // Fail if no schema is available
-        let schema = self.schema.clone();
-
-        // Parse request body as JSON
-        let json: serde_json::Value = serde_json::from_slice(&self.body)
-            .map_err(|e| GraphQLServerError::ClientError(format!("{}", e)))?;
-
-        // Ensure the JSON data is an object
-        let obj = json.as_object().ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from("Request data is not an object"))
-        })?;
-
-        // Ensure the JSON data has a "query" field
-        let query_value = obj.get("query").ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from(
-                "The \"query\" field missing in request data",
-            ))
-        })?;
-
-        // Ensure the "query" field is a string
-        let query_string = query_value.as_str().ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from("The\"query\" field is not a string"))
-        })?;
-
-        // Parse the "query" field of the JSON body
-        let document = graphql_parser::parse_query(query_string)
-            .map_err(|e| GraphQLServerError::from(QueryError::from(e)))?;
-
-        // Parse the "variables" field of the JSON body, if present
-        let variables = match obj.get("variables") {
-            None | Some(serde_json::Value::Null) => Ok(None),
-            Some(variables @ serde_json::Value::Object(_)) => {
-                serde_json::from_value(variables.clone())
-                    .map_err(|e| GraphQLServerError::ClientError(e.to_string()))
-                    .map(Some)
-            }
-            _ => Err(GraphQLServerError::ClientError(
-                "Invalid query variables provided".to_string(),
-            )),
-        }?;
-
+        let (document, variables) = parse_request_body(&self.body)?;
         Ok(Async::Ready(Query {
             document,
             variables,
-            schema,
+            schema: self.schema.clone(),
+            block: None,
         }))
     }
 }
 
+/// Parses the query and the variables in the JSON body of a GraphQL
+/// request.
+pub fn parse_request_body(
+    body: &[u8],
+) -> Result<(q::Document, Option<QueryVariables>), GraphQLServerError> {
+    // Parse request body as JSON
+    let json: serde_json::Value = serde_json::from_slice(body)
+        .map_err(|e| GraphQLServerError::ClientError(format!("{}", e)))?;
+
+    // Ensure the JSON data is an object
+    let obj = json.as_object().ok_or_else(|| {
+        GraphQLServerError::ClientError(String::from("Request data is not an object"))
+    })?;
+
+    // Ensure the JSON data has a "query" field
+    let query_value = obj.get("query").ok_or_else(|| {
+        GraphQLServerError::ClientError(String::from("The \"query\" field missing in request data"))
+    })?;
+
+    // Ensure the "query" field is a string
+    let query_string = query_value.as_str().ok_or_else(|| {
+        GraphQLServerError::ClientError(String::from("The\"query\" field is not a string"))
+    })?;
+
+    // Parse the "query" field of the JSON body
+    let document = graphql_parser::parse_query(query_string)
+        .map_err(|e| GraphQLServerError::from(QueryError::from(e)))?;
+
+    // Parse the "variables" field of the JSON body, if present
+    let variables = match obj.get("variables") {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(variables @ serde_json::Value::Object(_)) => serde_json::from_value(variables.clone())
+            .map_err(|e| GraphQLServerError::ClientError(e.to_string()))
+            .map(Some),
+        _ => Err(GraphQLServerError::ClientError(
+            "Invalid query variables provided".to_string(),
+        )),
+    }?;
+
+    Ok((document, variables))
+}
+
 #[cfg(test)]
 mod tests {
     use graphql_parser;
@@ -3,20 +3,34 @@ use hyper::{Body, Response};
 use serde::ser::*;
 
 use graph::components::server::query::GraphQLServerError;
-use graph::data::query::QueryResult;
+use graph::data::query::{ConsistencyToken, QueryResult};
 use graph::serde_json;
 use graph::tokio::prelude::*;
 
+/// HTTP header used to pass consistency tokens between clients and the server.
+pub const CONSISTENCY_TOKEN_HEADER: &str = "Graph-Consistency-Token";
+
 /// Future for HTTP responses to GraphQL query requests.
 pub struct GraphQLResponse {
     result: Result<QueryResult, GraphQLServerError>,
+    consistency_token: Option<ConsistencyToken>,
 }
 
 impl GraphQLResponse {
     /// Creates a new GraphQLResponse future based on the result generated by
     /// running a query.
     pub fn new(result: Result<QueryResult, GraphQLServerError>) -> Self {
-        GraphQLResponse { result }
+        GraphQLResponse {
+            result,
+            consistency_token: None,
+        }
+    }
+
+    /// Attaches a consistency token to the response, which is returned to
+    /// the client in the `Graph-Consistency-Token` header.
+    pub fn with_consistency_token(mut self, token: Option<ConsistencyToken>) -> Self {
+        self.consistency_token = token;
+        self
     }
 
     fn status_code_from_result(&self) -> StatusCode {
@@ -57,14 +71,20 @@ impl Future for GraphQLResponse {
         let status_code = self.status_code_from_result();
         let json =
             serde_json::to_string(self).expect("Failed to serialize GraphQL response to JSON");
-        let response = Response::builder()
+        let mut response = Response::builder();
+        response
             .status(status_code)
             .header("Access-Control-Allow-Origin", "*")
-            .header("Access-Control-Allow-Headers", "Content-Type")
+            .header(
+                "Access-Control-Allow-Headers",
+                format!("Content-Type, {}", CONSISTENCY_TOKEN_HEADER).as_str(),
+            )
             .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
-            .body(Body::from(json))
-            .unwrap();
-        Ok(Async::Ready(response))
+            .header("Access-Control-Expose-Headers", CONSISTENCY_TOKEN_HEADER);
+        if let (Ok(_), Some(token)) = (&self.result, &self.consistency_token) {
+            response.header(CONSISTENCY_TOKEN_HEADER, token.to_string().as_str());
+        }
+        Ok(Async::Ready(response.body(Body::from(json)).unwrap()))
     }
 }
 
@@ -1,17 +1,16 @@
-use hyper;
-use hyper::Server;
-
 use std::error::Error;
 use std::fmt;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 
+use crate::connections::{serve, ConnectionLimits};
 use crate::service::GraphQLService;
 use graph::prelude::{GraphQLServer as GraphQLServerTrait, *};
 
 /// Errors that may occur when starting the server.
 #[derive(Debug)]
 pub enum GraphQLServeError {
-    BindError(hyper::Error),
+    BindError(io::Error),
 }
 
 impl Error for GraphQLServeError {
@@ -32,8 +31,8 @@ impl fmt::Display for GraphQLServeError {
     }
 }
 
-impl From<hyper::Error> for GraphQLServeError {
-    fn from(err: hyper::Error) -> Self {
+impl From<io::Error> for GraphQLServeError {
+    fn from(err: io::Error) -> Self {
         GraphQLServeError::BindError(err)
     }
 }
@@ -61,7 +60,7 @@ impl<Q, S> GraphQLServer<Q, S> {
 impl<Q, S> GraphQLServerTrait for GraphQLServer<Q, S>
 where
     Q: GraphQlRunner,
-    S: SubgraphDeploymentStore + Store,
+    S: SubgraphDeploymentStore + Store + ChainStore,
 {
     type ServeError = GraphQLServeError;
 
@@ -85,22 +84,24 @@ where
         let graphql_runner = self.graphql_runner.clone();
         let store = self.store.clone();
         let node_id = self.node_id.clone();
-        let new_service = move || {
-            let service = GraphQLService::new(
+        let new_service = move |_: SocketAddr| {
+            GraphQLService::new(
                 logger_for_service.clone(),
                 graphql_runner.clone(),
                 store.clone(),
                 ws_port,
                 node_id.clone(),
-            );
-            future::ok::<GraphQLService<Q, S>, hyper::Error>(service)
+            )
         };
 
         // Create a task to run the server and handle HTTP requests
-        let task = Server::try_bind(&addr.into())?
-            .serve(new_service)
-            .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
+        let task = serve(
+            logger,
+            addr.into(),
+            ConnectionLimits::from_env(),
+            new_service,
+        )?;
 
-        Ok(Box::new(task))
+        Ok(task)
     }
 }
@@ -1,12 +1,24 @@
 use graph::components::server::query::GraphQLServerError;
 use graph::data::subgraph::schema::{SubgraphEntity, SUBGRAPHS_ID};
 use graph::prelude::*;
+use graph::tokio::timer::Delay;
+use graph::util::tracing;
 use http::header;
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
+use std::time::{Duration, Instant};
 
-use crate::request::GraphQLRequest;
-use crate::response::GraphQLResponse;
+use crate::multi::*;
+use crate::request::{parse_request_body, GraphQLRequest};
+use crate::response::{GraphQLResponse, CONSISTENCY_TOKEN_HEADER};
+
+/// How often a cross-subgraph query is run before it fails because the
+/// block it was run at was reverted.
+const MULTI_QUERY_ATTEMPTS: u32 = 5;
+
+/// How long to wait before running a cross-subgraph query again after the
+/// block it was run at was reverted.
+const MULTI_QUERY_RETRY_DELAY: Duration = Duration::from_millis(500);
 
 /// An asynchronous response to a GraphQL request.
 pub type GraphQLServiceResponse =
@@ -37,7 +49,7 @@ impl<Q, S> Clone for GraphQLService<Q, S> {
 impl<Q, S> GraphQLService<Q, S>
 where
     Q: GraphQlRunner,
-    S: SubgraphDeploymentStore + Store,
+    S: SubgraphDeploymentStore + Store + ChainStore,
 {
     /// Creates a new GraphQL service.
     pub fn new(
@@ -136,35 +148,27 @@ where
         let service = self.clone();
 
         Box::new(
-            SubgraphName::new(subgraph_name.as_str())
-                .map_err(|()| {
-                    GraphQLServerError::ClientError(format!(
-                        "Invalid subgraph name {:?}",
-                        subgraph_name
-                    ))
-                })
-                .and_then(|subgraph_name| {
-                    self.store
-                        .resolve_subgraph_name_to_id(subgraph_name)
-                        .map_err(|e| {
-                            GraphQLServerError::InternalError(format!(
-                                "Error resolving subgraph name: {}",
-                                e
-                            ))
-                        })
-                })
+            self.resolve_subgraph_name(&subgraph_name)
                 .into_future()
-                .and_then(|subgraph_id_opt| {
-                    subgraph_id_opt.ok_or(GraphQLServerError::ClientError(
-                        "Subgraph name not found".to_owned(),
-                    ))
-                })
-                .and_then(move |subgraph_id| {
-                    service.handle_graphql_query(&subgraph_id, request.into_body())
-                }),
+                .and_then(move |subgraph_id| service.handle_graphql_query(&subgraph_id, request)),
         )
     }
 
+    fn resolve_subgraph_name(
+        &self,
+        subgraph_name: &str,
+    ) -> Result<SubgraphDeploymentId, GraphQLServerError> {
+        let subgraph_name = SubgraphName::new(subgraph_name).map_err(|()| {
+            GraphQLServerError::ClientError(format!("Invalid subgraph name {:?}", subgraph_name))
+        })?;
+        self.store
+            .resolve_subgraph_name_to_id(subgraph_name)
+            .map_err(|e| {
+                GraphQLServerError::InternalError(format!("Error resolving subgraph name: {}", e))
+            })?
+            .ok_or_else(|| GraphQLServerError::ClientError("Subgraph name not found".to_owned()))
+    }
+
     fn handle_graphql_query_by_id(
         &self,
         id: String,
@@ -172,64 +176,344 @@ where
     ) -> GraphQLServiceResponse {
         match SubgraphDeploymentId::new(id) {
             Err(()) => self.handle_not_found(),
-            Ok(id) => self.handle_graphql_query(&id, request.into_body()),
+            Ok(id) => self.handle_graphql_query(&id, request),
         }
     }
 
-    fn handle_graphql_query(
+    /// Returns the consistency token for the block the given subgraph is at
+    /// before a query runs, and fails if the client asked for a state that
+    /// this node hasn't indexed: a later block, or a block on another chain.
+    fn check_consistency_token(
         &self,
         id: &SubgraphDeploymentId,
-        request_body: Body,
-    ) -> GraphQLServiceResponse {
+        request: &Request<Body>,
+    ) -> Result<Option<ConsistencyToken>, GraphQLServerError> {
+        // The subgraph of subgraphs is not indexed from Ethereum
+        if *id == *SUBGRAPHS_ID {
+            return Ok(None);
+        }
+
+        let required = match request.headers().get(CONSISTENCY_TOKEN_HEADER) {
+            None => None,
+            Some(value) => Some(
+                value
+                    .to_str()
+                    .map_err(|e| e.to_string())
+                    .and_then(|s| s.parse::<ConsistencyToken>().map_err(|e| e.to_string()))
+                    .map_err(GraphQLServerError::ClientError)?,
+            ),
+        };
+
+        let current = self.consistency_token(id)?;
+        let required = match required {
+            Some(required) => required,
+            None => return Ok(Some(current)),
+        };
+
+        let satisfied = required
+            .is_satisfied_by(&current.0, |block_ptr, offset| {
+                Ok(self
+                    .store
+                    .ancestor_block(block_ptr, offset)?
+                    .map(|block| EthereumBlockPointer::from(&block).hash))
+            })
+            .map_err(|e| GraphQLServerError::InternalError(e.to_string()))?;
+        if satisfied {
+            Ok(Some(current))
+        } else if current.0.number < required.0.number {
+            Err(GraphQLServerError::ClientError(format!(
+                "Subgraph {} has only been indexed up to block {}, \
+                 but the consistency token requires block {}",
+                id, current.0.number, required.0.number
+            )))
+        } else {
+            Err(GraphQLServerError::ClientError(format!(
+                "Subgraph {} has been indexed up to block {} ({}), which does not \
+                 descend from block {} ({}) that the consistency token requires",
+                id,
+                current.0.number,
+                current.0.hash_hex(),
+                required.0.number,
+                required.0.hash_hex()
+            )))
+        }
+    }
+
+    /// The consistency token for the block the given subgraph is at.
+    fn consistency_token(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<ConsistencyToken, GraphQLServerError> {
+        self.store
+            .block_ptr(id.clone())
+            .map(ConsistencyToken::from)
+            .map_err(|e| GraphQLServerError::InternalError(e.to_string()))
+    }
+
+    /// Runs a query and returns the consistency token for the block it read
+    /// at. `before` is the token for the block the subgraph was at before
+    /// the query ran. If the subgraph processed or reverted blocks while
+    /// the query ran, the results may mix several blocks, so the query is
+    /// run again at the block the subgraph is at afterwards.
+    fn run_query_with_consistency_token(
+        &self,
+        id: &SubgraphDeploymentId,
+        query: Query,
+        before: Option<ConsistencyToken>,
+    ) -> Box<
+        Future<Item = (QueryResult, Option<ConsistencyToken>), Error = GraphQLServerError> + Send,
+    > {
+        let before = match before {
+            Some(before) => before,
+            None => {
+                return Box::new(
+                    self.graphql_runner
+                        .run_query(query)
+                        .map_err(GraphQLServerError::from)
+                        .map(|result| (result, None)),
+                );
+            }
+        };
+
         let service = self.clone();
+        let id = id.clone();
+        Box::new(
+            self.graphql_runner
+                .run_query(query.clone())
+                .map_err(GraphQLServerError::from)
+                .and_then(move |result| -> Box<Future<Item = _, Error = _> + Send> {
+                    let after = match service.consistency_token(&id) {
+                        Ok(after) => after,
+                        Err(e) => return Box::new(future::err(e)),
+                    };
+                    if after == before {
+                        return Box::new(future::ok((result, Some(after))));
+                    }
+
+                    let mut query = query;
+                    query.block = Some(after.0);
+                    Box::new(
+                        service
+                            .graphql_runner
+                            .run_query(query)
+                            .map_err(GraphQLServerError::from)
+                            .map(move |result| (result, Some(after))),
+                    )
+                }),
+        )
+    }
 
+    /// Returns the schema to run queries against a deployment with, and
+    /// fails if the deployment can't be queried.
+    fn deployment_schema(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Arc<Schema>, GraphQLServerError> {
         match self.store.is_deployed(id) {
-            Err(e) => {
-                return Box::new(future::err(GraphQLServerError::InternalError(
-                    e.to_string(),
-                )));
-            }
+            Err(e) => return Err(GraphQLServerError::InternalError(e.to_string())),
             Ok(false) => {
-                return Box::new(future::err(GraphQLServerError::ClientError(format!(
+                return Err(GraphQLServerError::ClientError(format!(
                     "No data found for subgraph {}",
                     id
-                ))));
+                )));
             }
             Ok(true) => (),
         }
 
-        let schema = match self.store.subgraph_schema(id) {
-            Ok(schema) => schema,
-            Err(e) => {
-                return Box::new(future::err(GraphQLServerError::InternalError(
-                    e.to_string(),
+        match self.store.is_deployment_query_disabled(id) {
+            Err(e) => return Err(GraphQLServerError::InternalError(e.to_string())),
+            Ok(true) => {
+                return Err(GraphQLServerError::ClientError(format!(
+                    "Queries are disabled for subgraph {}",
+                    id
                 )));
             }
+            Ok(false) => (),
+        }
+
+        self.store
+            .subgraph_schema(id)
+            .map_err(|e| GraphQLServerError::InternalError(e.to_string()))
+    }
+
+    fn handle_graphql_query(
+        &self,
+        id: &SubgraphDeploymentId,
+        request: Request<Body>,
+    ) -> GraphQLServiceResponse {
+        let service = self.clone();
+
+        let schema = match self.deployment_schema(id) {
+            Ok(schema) => schema,
+            Err(e) => return Box::new(future::err(e)),
         };
 
+        let consistency_token = match self.check_consistency_token(id, &request) {
+            Ok(token) => token,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let mut span = tracing::root_span("http_query");
+        tracing::tag(&mut span, "subgraph_id", id);
+        let id = id.clone();
+
         Box::new(
-            request_body
+            request
+                .into_body()
                 .concat2()
                 .map_err(|_| GraphQLServerError::from("Failed to read request body"))
                 .and_then(move |body| GraphQLRequest::new(body, schema))
                 .and_then(move |query| {
-                    // Run the query using the query runner
-                    service
-                        .graphql_runner
-                        .run_query(query)
-                        .map_err(|e| GraphQLServerError::from(e))
+                    // Run the query using the query runner; the runner executes
+                    // the query right away, so the spans of the execution and
+                    // the store become part of this trace
+                    tracing::enter(&span, || {
+                        service.run_query_with_consistency_token(&id, query, consistency_token)
+                    })
+                })
+                .then(|result| match result {
+                    Ok((result, consistency_token)) => {
+                        GraphQLResponse::new(Ok(result)).with_consistency_token(consistency_token)
+                    }
+                    Err(e) => GraphQLResponse::new(Err(e)),
+                }),
+        )
+    }
+
+    /// Runs a query whose root fields address several subgraphs, see
+    /// `split_query`. The subgraphs that index the same network are queried
+    /// at the same block, the earliest block that any of them is at. If
+    /// that block is reverted while the query runs, the query is run again
+    /// a few times before it fails.
+    fn handle_multi_query(&self, request: Request<Body>) -> GraphQLServiceResponse {
+        let service = self.clone();
+
+        Box::new(
+            request
+                .into_body()
+                .concat2()
+                .map_err(|_| GraphQLServerError::from("Failed to read request body"))
+                .and_then(move |body| -> Result<_, GraphQLServerError> {
+                    let (document, variables) = parse_request_body(&body)?;
+                    let parts = split_query(&document, variables.as_ref())?
+                        .into_iter()
+                        .map(|selection| service.multi_query_part(selection, variables.clone()))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Ok((service, Arc::new(parts)))
+                })
+                .and_then(|(service, parts)| {
+                    future::loop_fn(1, move |attempt| {
+                        service
+                            .run_multi_query(parts.clone())
+                            .and_then(move |result| next_multi_query_attempt(attempt, result))
+                    })
                 })
-                .then(|result| GraphQLResponse::new(result)),
+                .then(GraphQLResponse::new),
         )
     }
 
+    fn multi_query_part(
+        &self,
+        selection: SubgraphSelection,
+        variables: Option<QueryVariables>,
+    ) -> Result<MultiQueryPart, GraphQLServerError> {
+        let subgraph_id = match selection.target {
+            SubgraphTarget::Name(name) => self.resolve_subgraph_name(&name)?,
+            SubgraphTarget::Id(id) => SubgraphDeploymentId::new(id.as_str()).map_err(|()| {
+                GraphQLServerError::ClientError(format!("Invalid subgraph ID {:?}", id))
+            })?,
+        };
+        let schema = self.deployment_schema(&subgraph_id)?;
+        let network = deployment_network(&*self.store, &subgraph_id)
+            .map_err(|e| GraphQLServerError::InternalError(e.to_string()))?;
+        Ok(MultiQueryPart {
+            key: selection.key,
+            subgraph_id,
+            network,
+            query: Query {
+                schema,
+                document: selection.document,
+                variables,
+                block: None,
+            },
+        })
+    }
+
+    /// Runs the parts of a cross-subgraph query once, each at the block its
+    /// network is pinned to. Returns the combined result, or a description
+    /// of the deployments whose block was reverted while the query ran.
+    fn run_multi_query(
+        &self,
+        parts: Arc<Vec<MultiQueryPart>>,
+    ) -> Box<Future<Item = Result<QueryResult, String>, Error = GraphQLServerError> + Send> {
+        let pinned = match self.deployment_blocks(&parts) {
+            Ok(blocks) => pinned_blocks(&blocks),
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let runs = parts
+            .iter()
+            .map(|part| {
+                let key = part.key.clone();
+                let mut query = part.query.clone();
+                query.block = part
+                    .network
+                    .as_ref()
+                    .map(|network| pinned[network.as_str()]);
+                self.graphql_runner.run_query(query).then(move |result| {
+                    let result = result.unwrap_or_else(|e| QueryResult {
+                        data: None,
+                        errors: Some(vec![e]),
+                    });
+                    Ok::<_, GraphQLServerError>((key, result))
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let service = self.clone();
+        Box::new(
+            future::join_all(runs).and_then(move |results| -> Result<_, GraphQLServerError> {
+                let after = service.deployment_blocks(&parts)?;
+                if let Some(reverted) = reverted_deployments(&pinned, &after) {
+                    return Ok(Err(reverted));
+                }
+                Ok(Ok(combine_results(results)))
+            }),
+        )
+    }
+
+    /// The blocks that the deployments of a cross-subgraph query that
+    /// index a network are at.
+    fn deployment_blocks(
+        &self,
+        parts: &[MultiQueryPart],
+    ) -> Result<Vec<DeploymentBlock>, GraphQLServerError> {
+        parts
+            .iter()
+            .filter_map(|part| part.network.as_ref().map(|network| (part, network)))
+            .map(|(part, network)| {
+                Ok(DeploymentBlock {
+                    key: part.key.clone(),
+                    network: network.clone(),
+                    block_ptr: self
+                        .store
+                        .block_ptr(part.subgraph_id.clone())
+                        .map_err(|e| GraphQLServerError::InternalError(e.to_string()))?,
+                })
+            })
+            .collect()
+    }
+
     // Handles OPTIONS requests
     fn handle_graphql_options(&self, _request: Request<Body>) -> GraphQLServiceResponse {
         Box::new(future::ok(
             Response::builder()
                 .status(200)
                 .header("Access-Control-Allow-Origin", "*")
-                .header("Access-Control-Allow-Headers", "Content-Type")
+                .header(
+                    "Access-Control-Allow-Headers",
+                    format!("Content-Type, {}", CONSISTENCY_TOKEN_HEADER).as_str(),
+                )
                 .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
                 .body(Body::from(""))
                 .unwrap(),
@@ -310,6 +594,10 @@ where
             (Method::OPTIONS, ["subgraphs", "name", _])
             | (Method::OPTIONS, ["subgraphs", "name", _, _]) => self.handle_graphql_options(req),
 
+            // Queries whose root fields address several subgraphs
+            (Method::POST, &["subgraphs", "multi"]) => self.handle_multi_query(req),
+            (Method::OPTIONS, ["subgraphs", "multi"]) => self.handle_graphql_options(req),
+
             // `/subgraphs` acts as an alias to `/subgraphs/id/SUBGRAPHS_ID`
             (Method::POST, &["subgraphs"]) => {
                 self.handle_graphql_query_by_id(SUBGRAPHS_ID.to_string(), req)
@@ -321,10 +609,30 @@ where
     }
 }
 
+/// Finishes a cross-subgraph query with the result of its `attempt`-th run,
+/// or runs it again after a delay if the block it ran at was reverted.
+fn next_multi_query_attempt(
+    attempt: u32,
+    result: Result<QueryResult, String>,
+) -> Box<Future<Item = future::Loop<QueryResult, u32>, Error = GraphQLServerError> + Send> {
+    match result {
+        Ok(result) => Box::new(future::ok(future::Loop::Break(result))),
+        Err(_) if attempt < MULTI_QUERY_ATTEMPTS => Box::new(
+            Delay::new(Instant::now() + MULTI_QUERY_RETRY_DELAY)
+                .map_err(|e| GraphQLServerError::InternalError(e.to_string()))
+                .map(move |()| future::Loop::Continue(attempt + 1)),
+        ),
+        Err(reverted) => Box::new(future::err(GraphQLServerError::InternalError(format!(
+            "The blocks a cross-subgraph query ran at kept being reverted: {}",
+            reverted
+        )))),
+    }
+}
+
 impl<Q, S> Service for GraphQLService<Q, S>
 where
     Q: GraphQlRunner,
-    S: SubgraphDeploymentStore + Store,
+    S: SubgraphDeploymentStore + Store + ChainStore,
 {
     type ReqBody = Body;
     type ResBody = Body;
@@ -392,6 +700,7 @@ mod tests {
     use graph::prelude::*;
 
     use super::GraphQLService;
+    use crate::response::CONSISTENCY_TOKEN_HEADER;
     use crate::test_utils;
 
     /// A simple stupid query runner for testing.
@@ -433,8 +742,12 @@ mod tests {
             spec_version: "".to_owned(),
             description: None,
             repository: None,
+            author: None,
             schema: schema.clone(),
             data_sources: vec![],
+            templates: vec![],
+            external_hosts: vec![],
+            features: vec![],
         };
 
         let graphql_runner = Arc::new(TestGraphQlRunner);
@@ -503,8 +816,12 @@ mod tests {
             spec_version: "".to_owned(),
             description: None,
             repository: None,
+            author: None,
             schema: schema.clone(),
             data_sources: vec![],
+            templates: vec![],
+            external_hosts: vec![],
+            features: vec![],
         };
         let graphql_runner = Arc::new(TestGraphQlRunner);
         let store = Arc::new(MockStore::new(vec![(id.clone(), schema)]));
@@ -559,4 +876,239 @@ mod tests {
             }))
             .unwrap()
     }
+
+    #[test]
+    fn stale_consistency_token_yields_error_response() {
+        let logger = Logger::root(slog::Discard, o!());
+        let id = SubgraphDeploymentId::new("testschema").unwrap();
+        let schema = Schema::parse(
+            "\
+             scalar String \
+             type Query @entity { name: String } \
+             ",
+            id.clone(),
+        )
+        .unwrap();
+        let manifest = SubgraphManifest {
+            id: id.clone(),
+            location: "".to_owned(),
+            spec_version: "".to_owned(),
+            description: None,
+            repository: None,
+            author: None,
+            schema: schema.clone(),
+            data_sources: vec![],
+            templates: vec![],
+            external_hosts: vec![],
+            features: vec![],
+        };
+
+        let graphql_runner = Arc::new(TestGraphQlRunner);
+        let store = Arc::new(MockStore::new(vec![(id.clone(), schema)]));
+        store
+            .apply_entity_operations(
+                SubgraphDeploymentEntity::new(
+                    &manifest,
+                    false,
+                    false,
+                    EthereumBlockPointer {
+                        hash: H256::zero(),
+                        number: 10,
+                    },
+                    0,
+                )
+                .create_operations(&id),
+                EventSource::None,
+            )
+            .unwrap();
+
+        let node_id = NodeId::new("test").unwrap();
+        let mut service = GraphQLService::new(logger, graphql_runner, store, 8001, node_id);
+
+        let token = ConsistencyToken(EthereumBlockPointer {
+            hash: H256::zero(),
+            number: 11,
+        });
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://localhost:8000/subgraphs/id/{}", id))
+            .header(CONSISTENCY_TOKEN_HEADER, token.to_string().as_str())
+            .body(Body::from("{\"query\": \"{ name }\"}"))
+            .unwrap();
+
+        let response = service
+            .call(request)
+            .wait()
+            .expect("Should return a response");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn disabled_queries_yield_error_response() {
+        let logger = Logger::root(slog::Discard, o!());
+        let id = SubgraphDeploymentId::new("testschema").unwrap();
+        let schema = Schema::parse(
+            "\
+             scalar String \
+             type Query @entity { name: String } \
+             ",
+            id.clone(),
+        )
+        .unwrap();
+        let manifest = SubgraphManifest {
+            id: id.clone(),
+            location: "".to_owned(),
+            spec_version: "".to_owned(),
+            description: None,
+            repository: None,
+            author: None,
+            schema: schema.clone(),
+            data_sources: vec![],
+            templates: vec![],
+            external_hosts: vec![],
+            features: vec![],
+        };
+
+        let graphql_runner = Arc::new(TestGraphQlRunner);
+        let store = Arc::new(MockStore::new(vec![(id.clone(), schema)]));
+        store
+            .apply_entity_operations(
+                SubgraphDeploymentEntity::new(&manifest, false, false, Default::default(), 0)
+                    .create_operations(&id)
+                    .into_iter()
+                    .chain(SubgraphDeploymentEntity::update_queries_disabled_operations(&id, true))
+                    .collect(),
+                EventSource::None,
+            )
+            .unwrap();
+
+        let node_id = NodeId::new("test").unwrap();
+        let mut service = GraphQLService::new(logger, graphql_runner, store, 8001, node_id);
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://localhost:8000/subgraphs/id/{}", id))
+            .body(Body::from("{\"query\": \"{ name }\"}"))
+            .unwrap();
+
+        let response = service
+            .call(request)
+            .wait()
+            .expect("Should return a response");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// Records the block each query is run at, by the subgraph it is for.
+    struct BlockRecordingRunner(std::sync::Mutex<Vec<(String, Option<u64>)>>);
+
+    impl GraphQlRunner for BlockRecordingRunner {
+        fn run_query(&self, query: Query) -> QueryResultFuture {
+            self.0.lock().unwrap().push((
+                query.schema.id.to_string(),
+                query.block.map(|block| block.number),
+            ));
+            TestGraphQlRunner.run_query(query)
+        }
+
+        fn run_subscription(&self, _subscription: Subscription) -> SubscriptionResultFuture {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn multi_queries_run_at_the_earliest_block_of_each_network() {
+        let logger = Logger::root(slog::Discard, o!());
+        let deployments = vec![
+            ("tokens", "mainnet", 10),
+            ("ens", "mainnet", 8),
+            ("test", "ropsten", 3),
+        ];
+        let schemas = deployments
+            .iter()
+            .map(|(id, _, _)| {
+                let id = SubgraphDeploymentId::new(*id).unwrap();
+                let schema =
+                    Schema::parse("type Query @entity { name: String }", id.clone()).unwrap();
+                (id, schema)
+            })
+            .collect::<Vec<_>>();
+        let store = Arc::new(MockStore::new(schemas.clone()));
+
+        for ((id, schema), (_, network, block)) in schemas.into_iter().zip(deployments) {
+            let manifest = SubgraphManifest {
+                id: id.clone(),
+                location: "".to_owned(),
+                spec_version: "".to_owned(),
+                description: None,
+                repository: None,
+                author: None,
+                schema,
+                data_sources: vec![],
+                templates: vec![],
+                external_hosts: vec![],
+                features: vec![],
+            };
+            let block_ptr = EthereumBlockPointer {
+                hash: H256::from(block),
+                number: block,
+            };
+
+            // The network of a deployment is that of its first data source
+            let data_source_id = format!("{}-data-source", id);
+            let mut manifest_entity = Entity::new();
+            manifest_entity.set("dataSources", vec![Value::from(data_source_id.as_str())]);
+            let mut data_source = Entity::new();
+            data_source.set("network", network);
+
+            let mut ops = SubgraphDeploymentEntity::new(&manifest, false, false, block_ptr, 0)
+                .create_operations(&id);
+            ops.push(EntityOperation::Set {
+                key: SubgraphManifestEntity::key(SubgraphManifestEntity::id(&id)),
+                data: manifest_entity,
+            });
+            ops.push(EntityOperation::Set {
+                key: EthereumContractDataSourceEntity::key(data_source_id),
+                data: data_source,
+            });
+            store
+                .apply_entity_operations(ops, EventSource::None)
+                .unwrap();
+        }
+
+        let graphql_runner = Arc::new(BlockRecordingRunner(Default::default()));
+        let node_id = NodeId::new("test").unwrap();
+        let mut service = GraphQLService::new(logger, graphql_runner.clone(), store, 8001, node_id);
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("http://localhost:8000/subgraphs/multi")
+            .body(Body::from(
+                "{\"query\": \"{ \
+                   tokens: subgraph(id: \\\"tokens\\\") { name } \
+                   ens: subgraph(id: \\\"ens\\\") { name } \
+                   test: subgraph(id: \\\"test\\\") { name } \
+                 }\"}",
+            ))
+            .unwrap();
+        let response = service
+            .call(request)
+            .wait()
+            .expect("Should return a response");
+        let data = test_utils::assert_successful_response(response);
+        assert_eq!(data["tokens"]["name"], "Jordi");
+        assert_eq!(data["ens"]["name"], "Jordi");
+
+        // The subgraphs on mainnet are queried at the block the one that is
+        // behind is at
+        let mut blocks = graphql_runner.0.lock().unwrap().clone();
+        blocks.sort();
+        assert_eq!(
+            blocks,
+            vec![
+                ("ens".to_owned(), Some(8)),
+                ("test".to_owned(), Some(3)),
+                ("tokens".to_owned(), Some(8)),
+            ]
+        );
+    }
 }
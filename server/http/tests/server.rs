@@ -75,8 +75,12 @@ mod test {
             spec_version: "".to_owned(),
             description: None,
             repository: None,
+            author: None,
             schema: schema.clone(),
             data_sources: vec![],
+            templates: vec![],
+            external_hosts: vec![],
+            features: vec![],
         };
 
         let store = Arc::new(MockStore::new(vec![(id, schema)]));
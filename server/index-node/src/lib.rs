@@ -0,0 +1,15 @@
+extern crate futures;
+extern crate graph;
+extern crate graph_graphql;
+extern crate graph_server_http;
+extern crate graphql_parser;
+extern crate hyper;
+extern crate lazy_static;
+
+mod resolver;
+mod schema;
+mod server;
+mod service;
+
+pub use self::server::IndexNodeServer;
+pub use self::service::{IndexNodeService, IndexNodeServiceResponse};
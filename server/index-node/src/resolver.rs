@@ -0,0 +1,473 @@
+use graphql_parser::{query as q, schema as s, Pos};
+use std::collections::HashMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+use graph::data::subgraph::schema::*;
+use graph::data::subgraph::{SUPPORTED_FEATURES, SUPPORTED_SPEC_VERSIONS};
+use graph::prelude::*;
+use graph_graphql::prelude::{object_value, ObjectOrInterface, Resolver};
+
+/// Resolver for the index node API, which reports the indexing status of
+/// subgraph deployments based on the subgraph of subgraphs and the chain
+/// stores, and the statistics of the queries they received.
+pub struct IndexNodeResolver<S, C> {
+    logger: Logger,
+    store: Arc<S>,
+    chain_stores: EthereumNetworks<C>,
+    query_stats: Arc<QueryStats>,
+    handler_stats: Arc<HandlerStats>,
+}
+
+impl<S, C> Clone for IndexNodeResolver<S, C> {
+    fn clone(&self) -> Self {
+        IndexNodeResolver {
+            logger: self.logger.clone(),
+            store: self.store.clone(),
+            chain_stores: self.chain_stores.clone(),
+            query_stats: self.query_stats.clone(),
+            handler_stats: self.handler_stats.clone(),
+        }
+    }
+}
+
+impl<S, C> IndexNodeResolver<S, C>
+where
+    S: Store,
+    C: ChainStore,
+{
+    pub fn new(
+        logger: &Logger,
+        store: Arc<S>,
+        chain_stores: EthereumNetworks<C>,
+        query_stats: Arc<QueryStats>,
+        handler_stats: Arc<HandlerStats>,
+    ) -> Self {
+        IndexNodeResolver {
+            logger: logger.new(o!("component" => "IndexNodeResolver")),
+            store,
+            chain_stores,
+            query_stats,
+            handler_stats,
+        }
+    }
+
+    /// Returns the statuses of the given deployments, or of all
+    /// deployments if `deployment_ids` is `None`.
+    fn indexing_statuses(
+        &self,
+        deployment_ids: Option<Vec<String>>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let mut query = SubgraphDeploymentEntity::query();
+        if let Some(deployment_ids) = deployment_ids {
+            query = query.filter(EntityFilter::In(
+                "id".to_owned(),
+                deployment_ids.into_iter().map(Value::from).collect(),
+            ));
+        }
+
+        let statuses = self
+            .store
+            .find(query)?
+            .into_iter()
+            .map(|deployment| self.indexing_status(deployment))
+            .collect::<Result<_, _>>()?;
+        Ok(q::Value::List(statuses))
+    }
+
+    /// Returns the statuses of the current and the pending version of a
+    /// subgraph, if there are any.
+    fn indexing_statuses_for_subgraph_name(
+        &self,
+        subgraph_name: &str,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let subgraphs = self
+            .store
+            .find(SubgraphEntity::query().filter(EntityFilter::new_equal("name", subgraph_name)))?;
+
+        let mut deployment_ids = vec![];
+        for subgraph in subgraphs {
+            for field in &["currentVersion", "pendingVersion"] {
+                let version_id = match subgraph.get(*field) {
+                    Some(Value::String(version_id)) => version_id.to_owned(),
+                    _ => continue,
+                };
+                let version = self.store.get(SubgraphVersionEntity::key(version_id))?;
+                if let Some(Value::String(deployment_id)) = version
+                    .as_ref()
+                    .and_then(|version| version.get("deployment"))
+                {
+                    deployment_ids.push(deployment_id.to_owned());
+                }
+            }
+        }
+
+        self.indexing_statuses(Some(deployment_ids))
+    }
+
+    fn indexing_status(&self, deployment: Entity) -> Result<q::Value, QueryExecutionError> {
+        let id = deployment.id().map_err(QueryExecutionError::StoreError)?;
+        let deployment_id = SubgraphDeploymentId::new(id.clone())
+            .map_err(|()| QueryExecutionError::SubgraphDeploymentIdError(id.clone()))?;
+
+        let failed = match deployment.get("failed") {
+            Some(Value::Bool(failed)) => *failed,
+            _ => false,
+        };
+        let synced = match deployment.get("synced") {
+            Some(Value::Bool(synced)) => *synced,
+            _ => false,
+        };
+
+        let last_error = match deployment.get("lastErrorMessage") {
+            Some(Value::String(message)) => object_value(vec![
+                ("message", q::Value::String(message.to_owned())),
+                (
+                    "block",
+                    block_object(
+                        deployment.get("lastErrorBlockHash"),
+                        deployment.get("lastErrorBlockNumber"),
+                    ),
+                ),
+                ("handler", field_value(&deployment, "lastErrorHandler")),
+            ]),
+            _ => q::Value::Null,
+        };
+
+        let node = self
+            .store
+            .get(SubgraphDeploymentAssignmentEntity::key(
+                deployment_id.clone(),
+            ))?
+            .map_or(q::Value::Null, |assignment| {
+                field_value(&assignment, "nodeId")
+            });
+
+        let manifest = self
+            .store
+            .get(SubgraphManifestEntity::key(SubgraphManifestEntity::id(
+                &deployment_id,
+            )))?;
+        let manifest_value = |field: &str| {
+            manifest
+                .as_ref()
+                .map_or(q::Value::Null, |manifest| field_value(manifest, field))
+        };
+
+        let network = self.network(manifest.as_ref())?;
+        let chain_head_block = match self.chain_stores.get(network.as_ref().map(String::as_str)) {
+            Ok(chain_store) => chain_store
+                .chain_head_ptr()
+                .map_err(QueryExecutionError::StoreError)?
+                .map_or(q::Value::Null, |block_ptr| {
+                    block_object(
+                        Some(&Value::from(block_ptr.hash_hex())),
+                        Some(&Value::from(block_ptr.number)),
+                    )
+                }),
+            Err(e) => {
+                debug!(
+                    self.logger,
+                    "No chain head block for subgraph";
+                    "subgraph" => &id,
+                    "error" => e.to_string(),
+                );
+                q::Value::Null
+            }
+        };
+
+        let entity_count = self
+            .store
+            .count_entities(deployment_id.clone())
+            .map_err(QueryExecutionError::StoreError)?;
+
+        let query_stats = match self.query_stats.summary(&deployment_id) {
+            Some(summary) => object_value(vec![
+                ("count", q::Value::String(summary.count.to_string())),
+                ("p50Ms", millis_value(summary.p50)),
+                ("p95Ms", millis_value(summary.p95)),
+            ]),
+            None => object_value(vec![
+                ("count", q::Value::String("0".to_owned())),
+                ("p50Ms", q::Value::Null),
+                ("p95Ms", q::Value::Null),
+            ]),
+        };
+
+        let handlers = self
+            .handler_stats
+            .summary(&deployment_id)
+            .into_iter()
+            .map(|handler| {
+                object_value(vec![
+                    ("dataSource", q::Value::String(handler.data_source)),
+                    ("handler", q::Value::String(handler.handler)),
+                    (
+                        "invocations",
+                        q::Value::String(handler.invocations.to_string()),
+                    ),
+                    (
+                        "totalTimeMs",
+                        q::Value::String(handler.total_time.as_millis().to_string()),
+                    ),
+                ])
+            })
+            .collect();
+
+        let concurrency = self.handler_stats.concurrency(&deployment_id);
+        let handler_concurrency = object_value(vec![
+            (
+                "running",
+                q::Value::Int(q::Number::from(concurrency.running as i32)),
+            ),
+            (
+                "peak",
+                q::Value::Int(q::Number::from(concurrency.peak as i32)),
+            ),
+        ]);
+
+        Ok(object_value(vec![
+            ("subgraph", q::Value::String(id)),
+            ("description", manifest_value("description")),
+            ("repository", manifest_value("repository")),
+            ("author", manifest_value("author")),
+            ("synced", q::Value::Boolean(synced)),
+            (
+                "health",
+                q::Value::Enum(if failed { "failed" } else { "healthy" }.to_owned()),
+            ),
+            ("lastError", last_error),
+            ("node", node),
+            ("network", network.map_or(q::Value::Null, q::Value::String)),
+            (
+                "latestBlock",
+                block_object(
+                    deployment.get("latestEthereumBlockHash"),
+                    deployment.get("latestEthereumBlockNumber"),
+                ),
+            ),
+            ("chainHeadBlock", chain_head_block),
+            ("entityCount", q::Value::String(entity_count.to_string())),
+            ("queryStats", query_stats),
+            ("handlers", q::Value::List(handlers)),
+            ("handlerConcurrency", handler_concurrency),
+        ]))
+    }
+
+    /// Returns the slow queries of the given deployments, or of all
+    /// deployments if `deployment_ids` is `None`, oldest first.
+    fn slow_queries(&self, deployment_ids: Option<Vec<String>>) -> q::Value {
+        let slow_queries = self
+            .query_stats
+            .slow_queries(None)
+            .into_iter()
+            .filter(|slow_query| {
+                deployment_ids.as_ref().map_or(true, |ids| {
+                    ids.iter()
+                        .any(|id| id.as_str() == slow_query.subgraph_id.as_str())
+                })
+            })
+            .map(|slow_query| {
+                let timestamp = slow_query
+                    .received_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                object_value(vec![
+                    (
+                        "subgraph",
+                        q::Value::String(slow_query.subgraph_id.to_string()),
+                    ),
+                    ("query", q::Value::String(slow_query.query)),
+                    (
+                        "variables",
+                        slow_query
+                            .variables
+                            .map_or(q::Value::Null, q::Value::String),
+                    ),
+                    ("durationMs", millis_value(slow_query.duration)),
+                    ("storeTimeMs", millis_value(slow_query.store_time)),
+                    ("timestamp", q::Value::String(timestamp.to_string())),
+                ])
+            })
+            .collect();
+        q::Value::List(slow_queries)
+    }
+
+    /// Returns the jobs in the background job queue for the given
+    /// deployments, or for all deployments if `deployment_ids` is `None`,
+    /// oldest first. The arguments of jobs are left out, since they may
+    /// contain database passwords.
+    fn background_jobs(
+        &self,
+        deployment_ids: Option<Vec<String>>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let jobs = self
+            .store
+            .background_jobs()
+            .map_err(QueryExecutionError::StoreError)?
+            .into_iter()
+            .filter(|job| {
+                deployment_ids.as_ref().map_or(true, |ids| {
+                    ids.iter().any(|id| id.as_str() == job.deployment.as_str())
+                })
+            })
+            .map(|job| {
+                object_value(vec![
+                    ("id", q::Value::Int(q::Number::from(job.id))),
+                    ("kind", q::Value::String(job.kind)),
+                    ("subgraph", q::Value::String(job.deployment.to_string())),
+                    ("status", q::Value::Enum(job.status.as_str().to_owned())),
+                    ("progress", q::Value::String(job.progress.to_string())),
+                    ("error", job.error.map_or(q::Value::Null, q::Value::String)),
+                    (
+                        "node",
+                        job.node_id.map_or(q::Value::Null, |node_id| {
+                            q::Value::String(node_id.to_string())
+                        }),
+                    ),
+                    ("createdAt", q::Value::String(job.created_at.to_string())),
+                    ("updatedAt", q::Value::String(job.updated_at.to_string())),
+                ])
+            })
+            .collect();
+        Ok(q::Value::List(jobs))
+    }
+
+    /// Returns the Ethereum network the deployment with the manifest
+    /// `manifest` indexes, as declared by its first data source.
+    fn network(&self, manifest: Option<&Entity>) -> Result<Option<String>, QueryExecutionError> {
+        let data_source_id = match manifest.and_then(|m| m.get("dataSources")) {
+            Some(Value::List(data_sources)) => match data_sources.first() {
+                Some(Value::String(data_source_id)) => data_source_id.to_owned(),
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+
+        let data_source = self.store.get(EntityKey {
+            subgraph_id: SUBGRAPHS_ID.clone(),
+            entity_type: "EthereumContractDataSource".to_owned(),
+            entity_id: data_source_id,
+        })?;
+        let network = match data_source.as_ref().and_then(|ds| ds.get("network")) {
+            Some(Value::String(network)) => Some(network.to_owned()),
+            _ => None,
+        };
+        Ok(network)
+    }
+}
+
+fn field_value(entity: &Entity, field: &str) -> q::Value {
+    entity
+        .get(field)
+        .cloned()
+        .map_or(q::Value::Null, q::Value::from)
+}
+
+fn block_object(hash: Option<&Value>, number: Option<&Value>) -> q::Value {
+    match (hash, number) {
+        (Some(Value::String(hash)), Some(number)) => object_value(vec![
+            ("hash", q::Value::String(hash.to_owned())),
+            ("number", q::Value::from(number.clone())),
+        ]),
+        _ => q::Value::Null,
+    }
+}
+
+/// The deployment IDs in the optional `subgraphs` argument.
+fn subgraphs_argument(arguments: &HashMap<&q::Name, q::Value>) -> Option<Vec<String>> {
+    match arguments.get(&String::from("subgraphs")) {
+        Some(q::Value::List(ids)) => Some(
+            ids.iter()
+                .filter_map(|id| match id {
+                    q::Value::String(id) => Some(id.to_owned()),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn millis_value(duration: Duration) -> q::Value {
+    let millis = duration.as_millis().min(i32::max_value() as u128) as i32;
+    q::Value::Int(q::Number::from(millis))
+}
+
+/// The spec versions and features of subgraph manifests this node supports.
+fn manifest_support() -> q::Value {
+    let strings = |values: &[&str]| {
+        q::Value::List(
+            values
+                .iter()
+                .map(|value| q::Value::String(value.to_string()))
+                .collect(),
+        )
+    };
+    object_value(vec![
+        ("specVersions", strings(SUPPORTED_SPEC_VERSIONS)),
+        ("features", strings(SUPPORTED_FEATURES)),
+    ])
+}
+
+fn object_field<'a>(object: &'a Option<q::Value>, field: &str) -> Option<&'a q::Value> {
+    match object {
+        Some(q::Value::Object(data)) => data.get(field),
+        _ => None,
+    }
+}
+
+impl<S, C> Resolver for IndexNodeResolver<S, C>
+where
+    S: Store,
+    C: ChainStore,
+{
+    fn resolve_objects(
+        &self,
+        parent: &Option<q::Value>,
+        field: &q::Name,
+        _field_definition: &s::Field,
+        _object_type: ObjectOrInterface<'_>,
+        arguments: &HashMap<&q::Name, q::Value>,
+        _schema: &Schema,
+    ) -> Result<q::Value, QueryExecutionError> {
+        match (parent, field.as_str()) {
+            (None, "indexingStatuses") => self.indexing_statuses(subgraphs_argument(arguments)),
+            (None, "slowQueries") => Ok(self.slow_queries(subgraphs_argument(arguments))),
+            (None, "backgroundJobs") => self.background_jobs(subgraphs_argument(arguments)),
+            (None, "indexingStatusesForSubgraphName") => {
+                match arguments.get(&String::from("subgraphName")) {
+                    Some(q::Value::String(subgraph_name)) => {
+                        self.indexing_statuses_for_subgraph_name(subgraph_name)
+                    }
+                    _ => Err(QueryExecutionError::MissingArgumentError(
+                        Pos::default(),
+                        "missing argument `subgraphName` in \
+                         `indexingStatusesForSubgraphName(subgraphName: String!)`"
+                            .to_owned(),
+                    )),
+                }
+            }
+            _ => Ok(object_field(parent, field.as_str())
+                .cloned()
+                .unwrap_or(q::Value::Null)),
+        }
+    }
+
+    fn resolve_object(
+        &self,
+        parent: &Option<q::Value>,
+        field: &q::Field,
+        _field_definition: &s::Field,
+        _object_type: ObjectOrInterface<'_>,
+        _arguments: &HashMap<&q::Name, q::Value>,
+        _schema: &Schema,
+    ) -> Result<q::Value, QueryExecutionError> {
+        match (parent, field.name.as_str()) {
+            (None, "manifestSupport") => Ok(manifest_support()),
+            _ => Ok(object_field(parent, field.name.as_str())
+                .cloned()
+                .unwrap_or(q::Value::Null)),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use graphql_parser;
+use lazy_static::lazy_static;
+
+use graph::prelude::*;
+
+lazy_static! {
+    /// The schema of the index node API.
+    pub static ref SCHEMA: Arc<Schema> = Arc::new(Schema::new(
+        SubgraphDeploymentId::new("indexnode").unwrap(),
+        graphql_parser::parse_schema(include_str!("./schema.graphql"))
+            .expect("invalid index node schema"),
+    ));
+}
+
+#[test]
+fn index_node_schema_has_query_type() {
+    use graph_graphql::schema::ast::get_root_query_type;
+
+    assert!(get_root_query_type(&SCHEMA.document).is_some());
+}
@@ -0,0 +1,118 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use crate::service::IndexNodeService;
+use graph::prelude::{IndexNodeServer as IndexNodeServerTrait, *};
+use graph_server_http::{serve, ConnectionLimits};
+
+/// Errors that may occur when starting the server.
+#[derive(Debug)]
+pub enum IndexNodeServeError {
+    BindError(io::Error),
+}
+
+impl Error for IndexNodeServeError {
+    fn description(&self) -> &str {
+        "Failed to start the server"
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        None
+    }
+}
+
+impl fmt::Display for IndexNodeServeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IndexNodeServeError::BindError(e) => {
+                write!(f, "Failed to bind index node server: {}", e)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for IndexNodeServeError {
+    fn from(err: io::Error) -> Self {
+        IndexNodeServeError::BindError(err)
+    }
+}
+
+/// A GraphQL server that reports the indexing status of subgraphs.
+pub struct IndexNodeServer<S, C> {
+    logger: Logger,
+    store: Arc<S>,
+    chain_stores: EthereumNetworks<C>,
+    query_stats: Arc<QueryStats>,
+    handler_stats: Arc<HandlerStats>,
+}
+
+impl<S, C> IndexNodeServer<S, C> {
+    /// Creates a new index node server that reports the query statistics
+    /// in `query_stats` and the handler statistics in `handler_stats`.
+    pub fn new(
+        logger: &Logger,
+        store: Arc<S>,
+        chain_stores: EthereumNetworks<C>,
+        query_stats: Arc<QueryStats>,
+        handler_stats: Arc<HandlerStats>,
+    ) -> Self {
+        IndexNodeServer {
+            logger: logger.new(o!("component" => "IndexNodeServer")),
+            store,
+            chain_stores,
+            query_stats,
+            handler_stats,
+        }
+    }
+}
+
+impl<S, C> IndexNodeServerTrait for IndexNodeServer<S, C>
+where
+    S: Store,
+    C: ChainStore,
+{
+    type ServeError = IndexNodeServeError;
+
+    fn serve(
+        &mut self,
+        port: u16,
+    ) -> Result<Box<Future<Item = (), Error = ()> + Send>, Self::ServeError> {
+        let logger = self.logger.clone();
+
+        info!(
+            logger,
+            "Starting index node server at: http://localhost:{}/graphql", port
+        );
+
+        let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
+
+        // On every incoming request, launch a new service that answers
+        // queries about the indexing status of subgraphs
+        let logger_for_service = self.logger.clone();
+        let store = self.store.clone();
+        let chain_stores = self.chain_stores.clone();
+        let query_stats = self.query_stats.clone();
+        let handler_stats = self.handler_stats.clone();
+        let new_service = move |_: SocketAddr| {
+            IndexNodeService::new(
+                logger_for_service.clone(),
+                store.clone(),
+                chain_stores.clone(),
+                query_stats.clone(),
+                handler_stats.clone(),
+            )
+        };
+
+        // Create a task to run the server and handle HTTP requests
+        let task = serve(
+            logger,
+            addr.into(),
+            ConnectionLimits::from_env(),
+            new_service,
+        )?;
+
+        Ok(task)
+    }
+}
@@ -0,0 +1,177 @@
+use graph::components::server::query::GraphQLServerError;
+use graph::prelude::*;
+use graph_graphql::prelude::{execute_query, QueryExecutionOptions};
+use graph_server_http::{GraphQLRequest, GraphQLResponse};
+use hyper::service::Service;
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+use crate::resolver::IndexNodeResolver;
+use crate::schema::SCHEMA;
+
+/// An asynchronous response to a GraphQL request.
+pub type IndexNodeServiceResponse =
+    Box<Future<Item = Response<Body>, Error = GraphQLServerError> + Send>;
+
+/// A Hyper Service that serves the index node API over a POST /graphql endpoint.
+#[derive(Debug)]
+pub struct IndexNodeService<S, C> {
+    logger: Logger,
+    store: Arc<S>,
+    chain_stores: EthereumNetworks<C>,
+    query_stats: Arc<QueryStats>,
+    handler_stats: Arc<HandlerStats>,
+}
+
+impl<S, C> Clone for IndexNodeService<S, C> {
+    fn clone(&self) -> Self {
+        Self {
+            logger: self.logger.clone(),
+            store: self.store.clone(),
+            chain_stores: self.chain_stores.clone(),
+            query_stats: self.query_stats.clone(),
+            handler_stats: self.handler_stats.clone(),
+        }
+    }
+}
+
+impl<S, C> IndexNodeService<S, C>
+where
+    S: Store,
+    C: ChainStore,
+{
+    /// Creates a new index node service.
+    pub fn new(
+        logger: Logger,
+        store: Arc<S>,
+        chain_stores: EthereumNetworks<C>,
+        query_stats: Arc<QueryStats>,
+        handler_stats: Arc<HandlerStats>,
+    ) -> Self {
+        IndexNodeService {
+            logger,
+            store,
+            chain_stores,
+            query_stats,
+            handler_stats,
+        }
+    }
+
+    fn handle_graphql_query(&self, request: Request<Body>) -> IndexNodeServiceResponse {
+        let logger = self.logger.clone();
+        let resolver = IndexNodeResolver::new(
+            &self.logger,
+            self.store.clone(),
+            self.chain_stores.clone(),
+            self.query_stats.clone(),
+            self.handler_stats.clone(),
+        );
+
+        Box::new(
+            request
+                .into_body()
+                .concat2()
+                .map_err(|_| GraphQLServerError::from("Failed to read request body"))
+                .and_then(move |body| GraphQLRequest::new(body, SCHEMA.clone()))
+                .map(move |query| {
+                    let options = QueryExecutionOptions {
+                        logger,
+                        resolver,
+                        deadline: None,
+                    };
+                    execute_query(&query, options)
+                })
+                .then(GraphQLResponse::new),
+        )
+    }
+
+    // Handles OPTIONS requests
+    fn handle_graphql_options(&self, _request: Request<Body>) -> IndexNodeServiceResponse {
+        Box::new(future::ok(
+            Response::builder()
+                .status(200)
+                .header("Access-Control-Allow-Origin", "*")
+                .header("Access-Control-Allow-Headers", "Content-Type")
+                .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
+                .body(Body::from(""))
+                .unwrap(),
+        ))
+    }
+
+    /// Handles 404s.
+    fn handle_not_found(&self) -> IndexNodeServiceResponse {
+        Box::new(future::ok(
+            Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Not found"))
+                .unwrap(),
+        ))
+    }
+
+    fn handle_call(&mut self, req: Request<Body>) -> IndexNodeServiceResponse {
+        let method = req.method().clone();
+        let path = req.uri().path().to_owned();
+
+        match (method, path.as_str()) {
+            (Method::POST, "/graphql") => self.handle_graphql_query(req),
+            (Method::OPTIONS, "/graphql") => self.handle_graphql_options(req),
+            _ => self.handle_not_found(),
+        }
+    }
+}
+
+impl<S, C> Service for IndexNodeService<S, C>
+where
+    S: Store,
+    C: ChainStore,
+{
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = GraphQLServerError;
+    type Future = IndexNodeServiceResponse;
+
+    fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
+        let logger = self.logger.clone();
+
+        // Returning Err here will prevent the client from receiving any response.
+        // Instead, we generate a Response with an error code and return Ok
+        Box::new(self.handle_call(req).then(move |result| match result {
+            Ok(response) => Ok(response),
+            Err(err @ GraphQLServerError::Canceled(_)) => {
+                error!(logger, "IndexNodeService call failed: {}", err);
+
+                Ok(Response::builder()
+                    .status(500)
+                    .header("Content-Type", "text/plain")
+                    .body(Body::from("Internal server error (operation canceled)"))
+                    .unwrap())
+            }
+            Err(err @ GraphQLServerError::ClientError(_)) => {
+                debug!(logger, "IndexNodeService call failed: {}", err);
+
+                Ok(Response::builder()
+                    .status(400)
+                    .header("Content-Type", "text/plain")
+                    .body(Body::from(format!("Invalid request: {}", err)))
+                    .unwrap())
+            }
+            Err(err @ GraphQLServerError::QueryError(_)) => {
+                error!(logger, "IndexNodeService call failed: {}", err);
+
+                Ok(Response::builder()
+                    .status(500)
+                    .header("Content-Type", "text/plain")
+                    .body(Body::from(format!("Query error: {}", err)))
+                    .unwrap())
+            }
+            Err(err @ GraphQLServerError::InternalError(_)) => {
+                error!(logger, "IndexNodeService call failed: {}", err);
+
+                Ok(Response::builder()
+                    .status(500)
+                    .header("Content-Type", "text/plain")
+                    .body(Body::from(format!("Internal server error: {}", err)))
+                    .unwrap())
+            }
+        }))
+    }
+}
@@ -1,20 +1,29 @@
 extern crate graph;
+extern crate graph_server_http;
 extern crate jsonrpc_http_server;
 extern crate lazy_static;
 extern crate serde;
 
 use graph::prelude::{JsonRpcServer as JsonRpcServerTrait, *};
 use graph::serde_json;
+use graph::web3::types::H256;
+use graph_server_http::{serve, ConnectionLimits};
 use jsonrpc_http_server::{
+    hyper::{
+        self,
+        header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+        service::service_fn,
+        Body, Method, Request, Response, StatusCode,
+    },
     jsonrpc_core::{self, Compatibility, IoHandler, Params, Value},
-    RestApi, Server, ServerBuilder,
 };
 use lazy_static::lazy_static;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::io;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
 
 lazy_static! {
     static ref EXTERNAL_HTTP_BASE_URL: Option<String> = env::var_os("EXTERNAL_HTTP_BASE_URL")
@@ -27,10 +36,23 @@ const JSON_RPC_DEPLOY_ERROR: i64 = 0;
 const JSON_RPC_REMOVE_ERROR: i64 = 1;
 const JSON_RPC_CREATE_ERROR: i64 = 2;
 const JSON_RPC_INTERNAL_ERROR: i64 = 3;
+const JSON_RPC_SET_ENABLED_ERROR: i64 = 4;
+const JSON_RPC_BATCH_ERROR: i64 = 5;
+const JSON_RPC_IDEMPOTENCY_ERROR: i64 = 6;
+const JSON_RPC_REASSIGN_ERROR: i64 = 7;
+const JSON_RPC_REWIND_ERROR: i64 = 8;
+const JSON_RPC_VALIDATE_ERROR: i64 = 9;
+
+/// The largest request body the admin server accepts.
+const MAX_REQUEST_BODY_SIZE: usize = 5 * 1024 * 1024;
+
+type ResponseFuture = Box<Future<Item = Response<Body>, Error = hyper::Error> + Send>;
 
 #[derive(Debug, Deserialize)]
 struct SubgraphCreateParams {
     name: SubgraphName,
+    #[serde(default)]
+    idempotency_key: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -38,19 +60,108 @@ struct SubgraphDeployParams {
     name: SubgraphName,
     ipfs_hash: SubgraphDeploymentId,
     node_id: Option<NodeId>,
+    #[serde(default)]
+    idempotency_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphValidateParams {
+    ipfs_hash: SubgraphDeploymentId,
 }
 
 #[derive(Debug, Deserialize)]
 struct SubgraphRemoveParams {
     name: SubgraphName,
+    #[serde(default)]
+    idempotency_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphSetEnabledParams {
+    ipfs_hash: SubgraphDeploymentId,
+    queries: Option<bool>,
+    indexing: Option<bool>,
+    #[serde(default)]
+    idempotency_key: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SubgraphAssignmentsParams {
+    #[serde(default)]
+    node_id: Option<NodeId>,
+}
+
+/// Parameters of `subgraph_pause` and `subgraph_resume`.
+#[derive(Debug, Deserialize)]
+struct SubgraphPauseParams {
+    ipfs_hash: SubgraphDeploymentId,
+    #[serde(default)]
+    idempotency_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphReassignParams {
+    ipfs_hash: SubgraphDeploymentId,
+    node_id: NodeId,
+    #[serde(default)]
+    idempotency_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphRewindParams {
+    ipfs_hash: SubgraphDeploymentId,
+    block_hash: H256,
+    block_number: u64,
+    #[serde(default)]
+    idempotency_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphBatchParams {
+    operations: Vec<SubgraphBatchOperation>,
+    #[serde(default)]
+    idempotency_key: Option<String>,
+}
+
+/// An operation in a `subgraph_batch` request, with the same method name
+/// and parameters as the corresponding individual request.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", content = "params")]
+enum SubgraphBatchOperation {
+    #[serde(rename = "subgraph_create")]
+    Create(SubgraphCreateParams),
+    #[serde(rename = "subgraph_deploy")]
+    Deploy(SubgraphDeployParams),
+    #[serde(rename = "subgraph_remove")]
+    Remove(SubgraphRemoveParams),
+    #[serde(rename = "subgraph_set_enabled")]
+    SetEnabled(SubgraphSetEnabledParams),
+    #[serde(rename = "subgraph_pause")]
+    Pause(SubgraphPauseParams),
+    #[serde(rename = "subgraph_resume")]
+    Resume(SubgraphPauseParams),
+    #[serde(rename = "subgraph_reassign")]
+    Reassign(SubgraphReassignParams),
+}
+
+type JsonRpcResponse = Box<Future<Item = Value, Error = jsonrpc_core::Error> + Send>;
+
+/// A request with an idempotency key that this node is still handling.
+struct IdempotentRequest {
+    /// Method and parameters of the request, to detect keys that are
+    /// reused for different requests.
+    request: String,
+    response: future::Shared<JsonRpcResponse>,
 }
 
 pub struct JsonRpcServer<R> {
     registrar: Arc<R>,
+    network_capabilities: EthereumNetworks<EthereumNetworkCapabilities>,
     http_port: u16,
     ws_port: u16,
     node_id: NodeId,
     logger: Logger,
+    idempotent_requests: Arc<Mutex<HashMap<String, IdempotentRequest>>>,
 }
 
 impl<R> JsonRpcServer<R>
@@ -61,11 +172,21 @@ where
     fn create_handler(
         &self,
         params: SubgraphCreateParams,
+        idempotency_key: Option<IdempotencyKey>,
     ) -> Box<Future<Item = Value, Error = jsonrpc_core::Error> + Send> {
         let logger = self.logger.clone();
 
         info!(logger, "Received subgraph_create request"; "params" => format!("{:?}", params));
 
+        if let Some(idempotency_key) = idempotency_key {
+            return self.operation_handler(
+                "subgraph_create",
+                JSON_RPC_CREATE_ERROR,
+                SubgraphBatchOperation::Create(params),
+                idempotency_key,
+            );
+        }
+
         Box::new(
             self.registrar
                 .create_subgraph(params.name)
@@ -87,11 +208,21 @@ where
     fn deploy_handler(
         &self,
         params: SubgraphDeployParams,
+        idempotency_key: Option<IdempotencyKey>,
     ) -> Box<Future<Item = Value, Error = jsonrpc_core::Error> + Send> {
         let logger = self.logger.clone();
 
         info!(logger, "Received subgraph_deploy request"; "params" => format!("{:?}", params));
 
+        if let Some(idempotency_key) = idempotency_key {
+            return self.operation_handler(
+                "subgraph_deploy",
+                JSON_RPC_DEPLOY_ERROR,
+                SubgraphBatchOperation::Deploy(params),
+                idempotency_key,
+            );
+        }
+
         let node_id = params.node_id.clone().unwrap_or(self.node_id.clone());
         let routes = subgraph_routes(&params.name, self.http_port, self.ws_port);
 
@@ -110,15 +241,69 @@ where
         )
     }
 
+    /// Handler for the `subgraph_validate` endpoint.
+    ///
+    /// Checks a deployment like `subgraph_deploy` would, and also that its
+    /// mappings can run on this node, without deploying it. Problems with
+    /// the deployment are returned as a list rather than as an error.
+    fn validate_handler(
+        &self,
+        params: SubgraphValidateParams,
+    ) -> Box<Future<Item = Value, Error = jsonrpc_core::Error> + Send> {
+        let logger = self.logger.clone();
+
+        info!(logger, "Received subgraph_validate request"; "params" => format!("{:?}", params));
+
+        Box::new(
+            self.registrar
+                .validate_subgraph_version(params.ipfs_hash)
+                .then(move |result| {
+                    let errors = match result {
+                        Ok(()) => vec![],
+                        Err(SubgraphRegistrarError::ManifestValidationError(errors)) => {
+                            errors.0.into_iter().map(|e| e.to_string()).collect()
+                        }
+                        Err(SubgraphRegistrarError::ResolveError(
+                            SubgraphManifestResolveError::ResolveError(ref e),
+                        )) if e.downcast_ref::<ManifestResolveErrors>().is_some() => {
+                            e.downcast_ref::<ManifestResolveErrors>().unwrap().0.clone()
+                        }
+                        Err(SubgraphRegistrarError::Unknown(e)) => {
+                            error!(logger, "subgraph_validate failed: {}", e);
+                            return Err(json_rpc_error(
+                                JSON_RPC_VALIDATE_ERROR,
+                                "internal error".to_owned(),
+                            ));
+                        }
+                        Err(e) => vec![e.to_string()],
+                    };
+                    let mut response = serde_json::Map::new();
+                    response.insert("valid".to_owned(), errors.is_empty().into());
+                    response.insert("errors".to_owned(), errors.into());
+                    Ok(Value::Object(response))
+                }),
+        )
+    }
+
     /// Handler for the `subgraph_remove` endpoint.
     fn remove_handler(
         &self,
         params: SubgraphRemoveParams,
+        idempotency_key: Option<IdempotencyKey>,
     ) -> Box<Future<Item = Value, Error = jsonrpc_core::Error> + Send> {
         let logger = self.logger.clone();
 
         info!(logger, "Received subgraph_remove request"; "params" => format!("{:?}", params));
 
+        if let Some(idempotency_key) = idempotency_key {
+            return self.operation_handler(
+                "subgraph_remove",
+                JSON_RPC_REMOVE_ERROR,
+                SubgraphBatchOperation::Remove(params),
+                idempotency_key,
+            );
+        }
+
         Box::new(
             self.registrar
                 .remove_subgraph(params.name)
@@ -135,6 +320,350 @@ where
         )
     }
 
+    /// Handler for the `subgraph_set_enabled` endpoint.
+    ///
+    /// Enables or disables query serving and indexing for a deployment.
+    fn set_enabled_handler(
+        &self,
+        params: SubgraphSetEnabledParams,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Box<Future<Item = Value, Error = jsonrpc_core::Error> + Send> {
+        let logger = self.logger.clone();
+
+        info!(logger, "Received subgraph_set_enabled request"; "params" => format!("{:?}", params));
+
+        if let Some(idempotency_key) = idempotency_key {
+            return self.operation_handler(
+                "subgraph_set_enabled",
+                JSON_RPC_SET_ENABLED_ERROR,
+                SubgraphBatchOperation::SetEnabled(params),
+                idempotency_key,
+            );
+        }
+
+        Box::new(
+            self.registrar
+                .set_deployment_enabled(params.ipfs_hash, params.queries, params.indexing)
+                .map_err(move |e| {
+                    if let SubgraphRegistrarError::Unknown(e) = e {
+                        error!(logger, "subgraph_set_enabled failed: {}", e);
+                        json_rpc_error(JSON_RPC_SET_ENABLED_ERROR, "internal error".to_owned())
+                    } else {
+                        json_rpc_error(JSON_RPC_SET_ENABLED_ERROR, e.to_string())
+                    }
+                })
+                .map(|_| Value::Null),
+        )
+    }
+
+    /// Handler for the `subgraph_pause` and `subgraph_resume` endpoints.
+    ///
+    /// Stops or restarts indexing a deployment, which keeps serving
+    /// queries while it is paused. The paused state is kept in the store, so
+    /// it survives restarts.
+    fn pause_handler(
+        &self,
+        method: &'static str,
+        params: SubgraphPauseParams,
+        paused: bool,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Box<Future<Item = Value, Error = jsonrpc_core::Error> + Send> {
+        let logger = self.logger.clone();
+
+        info!(logger, "Received {} request", method; "params" => format!("{:?}", params));
+
+        if let Some(idempotency_key) = idempotency_key {
+            let operation = if paused {
+                SubgraphBatchOperation::Pause(params)
+            } else {
+                SubgraphBatchOperation::Resume(params)
+            };
+            return self.operation_handler(
+                method,
+                JSON_RPC_SET_ENABLED_ERROR,
+                operation,
+                idempotency_key,
+            );
+        }
+
+        Box::new(
+            self.registrar
+                .set_deployment_enabled(params.ipfs_hash, None, Some(!paused))
+                .map_err(move |e| {
+                    if let SubgraphRegistrarError::Unknown(e) = e {
+                        error!(logger, "{} failed: {}", method, e);
+                        json_rpc_error(JSON_RPC_SET_ENABLED_ERROR, "internal error".to_owned())
+                    } else {
+                        json_rpc_error(JSON_RPC_SET_ENABLED_ERROR, e.to_string())
+                    }
+                })
+                .map(|_| Value::Null),
+        )
+    }
+
+    /// Handler for the `subgraph_reassign` endpoint.
+    ///
+    /// Moves a deployment to the node with the given ID.
+    fn reassign_handler(
+        &self,
+        params: SubgraphReassignParams,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Box<Future<Item = Value, Error = jsonrpc_core::Error> + Send> {
+        let logger = self.logger.clone();
+
+        info!(logger, "Received subgraph_reassign request"; "params" => format!("{:?}", params));
+
+        if let Some(idempotency_key) = idempotency_key {
+            return self.operation_handler(
+                "subgraph_reassign",
+                JSON_RPC_REASSIGN_ERROR,
+                SubgraphBatchOperation::Reassign(params),
+                idempotency_key,
+            );
+        }
+
+        Box::new(
+            self.registrar
+                .reassign_subgraph(params.ipfs_hash, params.node_id)
+                .map_err(move |e| {
+                    if let SubgraphRegistrarError::Unknown(e) = e {
+                        error!(logger, "subgraph_reassign failed: {}", e);
+                        json_rpc_error(JSON_RPC_REASSIGN_ERROR, "internal error".to_owned())
+                    } else {
+                        json_rpc_error(JSON_RPC_REASSIGN_ERROR, e.to_string())
+                    }
+                })
+                .map(|_| Value::Null),
+        )
+    }
+
+    /// Handler for the `subgraph_rewind` endpoint.
+    ///
+    /// Reverts a paused deployment to an earlier block, e.g. after an
+    /// Ethereum node served bad data.
+    fn rewind_handler(
+        &self,
+        params: SubgraphRewindParams,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Box<Future<Item = Value, Error = jsonrpc_core::Error> + Send> {
+        let logger = self.logger.clone();
+
+        info!(logger, "Received subgraph_rewind request"; "params" => format!("{:?}", params));
+
+        let block_ptr = EthereumBlockPointer {
+            hash: params.block_hash,
+            number: params.block_number,
+        };
+
+        Box::new(
+            self.registrar
+                .rewind_subgraph(params.ipfs_hash, block_ptr, idempotency_key)
+                .map_err(move |e| match e {
+                    SubgraphRegistrarError::Unknown(e) => {
+                        error!(logger, "subgraph_rewind failed: {}", e);
+                        json_rpc_error(JSON_RPC_REWIND_ERROR, "internal error".to_owned())
+                    }
+                    e @ SubgraphRegistrarError::IdempotencyKeyReused(_) => {
+                        json_rpc_error(JSON_RPC_IDEMPOTENCY_ERROR, e.to_string())
+                    }
+                    e => json_rpc_error(JSON_RPC_REWIND_ERROR, e.to_string()),
+                })
+                .map(|_| Value::Null),
+        )
+    }
+
+    /// Handler for the `subgraph_batch` endpoint.
+    ///
+    /// Applies several operations atomically and returns the result of each
+    /// operation, in the order of the operations.
+    fn batch_handler(
+        &self,
+        params: SubgraphBatchParams,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Box<Future<Item = Value, Error = jsonrpc_core::Error> + Send> {
+        info!(self.logger, "Received subgraph_batch request"; "params" => format!("{:?}", params));
+
+        Box::new(
+            self.apply_batch(
+                "subgraph_batch",
+                JSON_RPC_BATCH_ERROR,
+                params.operations,
+                idempotency_key,
+            )
+            .map(Value::from),
+        )
+    }
+
+    /// Applies a single operation that was sent with an idempotency key as
+    /// a batch, because the registrar only records the keys of batches. The
+    /// response is the same as without a key.
+    fn operation_handler(
+        &self,
+        method: &'static str,
+        error_code: i64,
+        operation: SubgraphBatchOperation,
+        idempotency_key: IdempotencyKey,
+    ) -> Box<Future<Item = Value, Error = jsonrpc_core::Error> + Send> {
+        Box::new(
+            self.apply_batch(method, error_code, vec![operation], Some(idempotency_key))
+                .map(|mut results| results.remove(0)),
+        )
+    }
+
+    /// Applies the operations of a batch atomically and returns the result
+    /// of each operation, in the order of the operations.
+    fn apply_batch(
+        &self,
+        method: &'static str,
+        error_code: i64,
+        operations: Vec<SubgraphBatchOperation>,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Box<Future<Item = Vec<Value>, Error = jsonrpc_core::Error> + Send> {
+        let logger = self.logger.clone();
+
+        let mut routes = vec![];
+        let operations = operations
+            .into_iter()
+            .map(|operation| match operation {
+                SubgraphBatchOperation::Create(params) => {
+                    routes.push(None);
+                    SubgraphRegistrarOperation::Create { name: params.name }
+                }
+                SubgraphBatchOperation::Deploy(params) => {
+                    routes.push(Some(subgraph_routes(
+                        &params.name,
+                        self.http_port,
+                        self.ws_port,
+                    )));
+                    SubgraphRegistrarOperation::Deploy {
+                        name: params.name,
+                        hash: params.ipfs_hash,
+                        node_id: params.node_id.unwrap_or(self.node_id.clone()),
+                    }
+                }
+                SubgraphBatchOperation::Remove(params) => {
+                    routes.push(None);
+                    SubgraphRegistrarOperation::Remove { name: params.name }
+                }
+                SubgraphBatchOperation::SetEnabled(params) => {
+                    routes.push(None);
+                    SubgraphRegistrarOperation::SetEnabled {
+                        hash: params.ipfs_hash,
+                        queries_enabled: params.queries,
+                        indexing_enabled: params.indexing,
+                    }
+                }
+                SubgraphBatchOperation::Pause(params) => {
+                    routes.push(None);
+                    SubgraphRegistrarOperation::SetEnabled {
+                        hash: params.ipfs_hash,
+                        queries_enabled: None,
+                        indexing_enabled: Some(false),
+                    }
+                }
+                SubgraphBatchOperation::Resume(params) => {
+                    routes.push(None);
+                    SubgraphRegistrarOperation::SetEnabled {
+                        hash: params.ipfs_hash,
+                        queries_enabled: None,
+                        indexing_enabled: Some(true),
+                    }
+                }
+                SubgraphBatchOperation::Reassign(params) => {
+                    routes.push(None);
+                    SubgraphRegistrarOperation::Reassign {
+                        hash: params.ipfs_hash,
+                        node_id: params.node_id,
+                    }
+                }
+            })
+            .collect();
+
+        Box::new(
+            self.registrar
+                .apply_operations(operations, idempotency_key)
+                .map_err(move |e| match e {
+                    SubgraphRegistrarError::Unknown(e) => {
+                        error!(logger, "{} failed: {}", method, e);
+                        json_rpc_error(error_code, "internal error".to_owned())
+                    }
+                    e @ SubgraphRegistrarError::IdempotencyKeyReused(_) => {
+                        json_rpc_error(JSON_RPC_IDEMPOTENCY_ERROR, e.to_string())
+                    }
+                    e => json_rpc_error(error_code, e.to_string()),
+                })
+                .map(move |results| {
+                    results
+                        .into_iter()
+                        .zip(routes)
+                        .map(|(result, routes)| match (result, routes) {
+                            (Some(result), _) => serde_json::to_value(result)
+                                .expect("invalid subgraph creation result"),
+                            (None, Some(routes)) => routes,
+                            (None, None) => Value::Null,
+                        })
+                        .collect()
+                }),
+        )
+    }
+
+    /// Passes the idempotency key of a request to `handler`, which has the
+    /// registrar record the key with the result of the request in the same
+    /// transaction as its changes. A request that repeats the key gets the
+    /// recorded result instead of being applied again, on any node, until
+    /// the key expires. This allows clients to retry requests safely.
+    ///
+    /// Requests that repeat a key while this node is still handling the
+    /// first one share its response. Failed requests don't record their
+    /// key, so they can be retried with it.
+    fn idempotent<F>(&self, key: Option<String>, request: String, handler: F) -> JsonRpcResponse
+    where
+        F: FnOnce(Option<IdempotencyKey>) -> JsonRpcResponse,
+    {
+        let key = match key {
+            Some(key) => key,
+            None => return handler(None),
+        };
+
+        let mut idempotent_requests = self.idempotent_requests.lock().unwrap();
+        let response = match idempotent_requests.get(&key) {
+            Some(previous) if previous.request != request => {
+                return Box::new(future::err(json_rpc_error(
+                    JSON_RPC_IDEMPOTENCY_ERROR,
+                    format!("idempotency key was used for a different request: {}", key),
+                )));
+            }
+            Some(previous) => previous.response.clone(),
+            None => {
+                let idempotency_key = IdempotencyKey {
+                    key: key.clone(),
+                    request: request.clone(),
+                };
+                let idempotent_requests = self.idempotent_requests.clone();
+                let handled_key = key.clone();
+                let response = (Box::new(handler(Some(idempotency_key)).then(move |result| {
+                    idempotent_requests.lock().unwrap().remove(&handled_key);
+                    result
+                })) as JsonRpcResponse)
+                    .shared();
+                idempotent_requests.insert(
+                    key,
+                    IdempotentRequest {
+                        request,
+                        response: response.clone(),
+                    },
+                );
+                response
+            }
+        };
+
+        Box::new(
+            response
+                .map(|value| (*value).clone())
+                .map_err(|e| (*e).clone()),
+        )
+    }
+
     /// Handler for the `subgraph_list` endpoint.
     ///
     /// Returns the names of deployed subgraphs.
@@ -160,6 +689,221 @@ where
                 }),
         )
     }
+
+    /// Handler for the `subgraph_assignments` endpoint.
+    ///
+    /// Returns the node that each subgraph deployment is assigned to,
+    /// optionally only for one node.
+    fn assignments_handler(
+        &self,
+        params: SubgraphAssignmentsParams,
+    ) -> Box<Future<Item = Value, Error = jsonrpc_core::Error> + Send> {
+        let logger = self.logger.clone();
+
+        info!(logger, "Received subgraph_assignments request"; "params" => format!("{:?}", params));
+
+        Box::new(
+            self.registrar
+                .list_assignments(params.node_id)
+                .map_err(move |e| {
+                    error!(logger, "Failed to list subgraph assignments: {}", e);
+                    json_rpc_error(JSON_RPC_INTERNAL_ERROR, "database error".to_owned())
+                })
+                .map(|assignments| {
+                    Value::from(
+                        assignments
+                            .into_iter()
+                            .map(|(hash, node_id)| {
+                                let mut assignment = serde_json::Map::new();
+                                assignment.insert("ipfs_hash".to_owned(), hash.to_string().into());
+                                assignment.insert("node_id".to_owned(), node_id.to_string().into());
+                                Value::Object(assignment)
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                }),
+        )
+    }
+
+    /// Handler for the `ethereum_networks` endpoint.
+    ///
+    /// Returns what the Ethereum nodes of each network can do, as probed
+    /// on startup.
+    fn ethereum_networks_handler(
+        &self,
+    ) -> Box<Future<Item = Value, Error = jsonrpc_core::Error> + Send> {
+        info!(self.logger, "Received ethereum_networks request");
+
+        let networks = self
+            .network_capabilities
+            .iter()
+            .map(|(_, capabilities)| {
+                let mut network = serde_json::to_value(&**capabilities)
+                    .expect("failed to serialize Ethereum network capabilities");
+                if let Value::Object(ref mut network) = network {
+                    network.insert("archive".to_owned(), Value::from(capabilities.archive()));
+                    network.insert("traces".to_owned(), Value::from(capabilities.traces()));
+                }
+                network
+            })
+            .collect::<Vec<_>>();
+        Box::new(future::ok(Value::from(networks)))
+    }
+
+    /// Registers the JSON-RPC methods of the admin server.
+    fn io_handler(arc_self: Arc<Self>) -> IoHandler {
+        let mut handler = IoHandler::with_compatibility(Compatibility::Both);
+
+        let me = arc_self.clone();
+        handler.add_method("subgraph_create", move |params: Params| {
+            let me = me.clone();
+            params
+                .parse()
+                .into_future()
+                .and_then(move |params: SubgraphCreateParams| {
+                    let key = params.idempotency_key.clone();
+                    let request = format!("subgraph_create {:?}", params);
+                    me.idempotent(key, request, |key| me.create_handler(params, key))
+                })
+        });
+
+        let me = arc_self.clone();
+        handler.add_method("subgraph_deploy", move |params: Params| {
+            let me = me.clone();
+            params
+                .parse()
+                .into_future()
+                .and_then(move |params: SubgraphDeployParams| {
+                    let key = params.idempotency_key.clone();
+                    let request = format!("subgraph_deploy {:?}", params);
+                    me.idempotent(key, request, |key| me.deploy_handler(params, key))
+                })
+        });
+
+        let me = arc_self.clone();
+        handler.add_method("subgraph_validate", move |params: Params| {
+            let me = me.clone();
+            params
+                .parse()
+                .into_future()
+                .and_then(move |params: SubgraphValidateParams| me.validate_handler(params))
+        });
+
+        let me = arc_self.clone();
+        handler.add_method("subgraph_remove", move |params: Params| {
+            let me = me.clone();
+            params
+                .parse()
+                .into_future()
+                .and_then(move |params: SubgraphRemoveParams| {
+                    let key = params.idempotency_key.clone();
+                    let request = format!("subgraph_remove {:?}", params);
+                    me.idempotent(key, request, |key| me.remove_handler(params, key))
+                })
+        });
+
+        let me = arc_self.clone();
+        handler.add_method("subgraph_set_enabled", move |params: Params| {
+            let me = me.clone();
+            params
+                .parse()
+                .into_future()
+                .and_then(move |params: SubgraphSetEnabledParams| {
+                    let key = params.idempotency_key.clone();
+                    let request = format!("subgraph_set_enabled {:?}", params);
+                    me.idempotent(key, request, |key| me.set_enabled_handler(params, key))
+                })
+        });
+
+        let me = arc_self.clone();
+        handler.add_method("subgraph_pause", move |params: Params| {
+            let me = me.clone();
+            params
+                .parse()
+                .into_future()
+                .and_then(move |params: SubgraphPauseParams| {
+                    let key = params.idempotency_key.clone();
+                    let request = format!("subgraph_pause {:?}", params);
+                    me.idempotent(key, request, |key| {
+                        me.pause_handler("subgraph_pause", params, true, key)
+                    })
+                })
+        });
+
+        let me = arc_self.clone();
+        handler.add_method("subgraph_resume", move |params: Params| {
+            let me = me.clone();
+            params
+                .parse()
+                .into_future()
+                .and_then(move |params: SubgraphPauseParams| {
+                    let key = params.idempotency_key.clone();
+                    let request = format!("subgraph_resume {:?}", params);
+                    me.idempotent(key, request, |key| {
+                        me.pause_handler("subgraph_resume", params, false, key)
+                    })
+                })
+        });
+
+        let me = arc_self.clone();
+        handler.add_method("subgraph_reassign", move |params: Params| {
+            let me = me.clone();
+            params
+                .parse()
+                .into_future()
+                .and_then(move |params: SubgraphReassignParams| {
+                    let key = params.idempotency_key.clone();
+                    let request = format!("subgraph_reassign {:?}", params);
+                    me.idempotent(key, request, |key| me.reassign_handler(params, key))
+                })
+        });
+
+        let me = arc_self.clone();
+        handler.add_method("subgraph_rewind", move |params: Params| {
+            let me = me.clone();
+            params
+                .parse()
+                .into_future()
+                .and_then(move |params: SubgraphRewindParams| {
+                    let key = params.idempotency_key.clone();
+                    let request = format!("subgraph_rewind {:?}", params);
+                    me.idempotent(key, request, |key| me.rewind_handler(params, key))
+                })
+        });
+
+        let me = arc_self.clone();
+        handler.add_method("subgraph_batch", move |params: Params| {
+            let me = me.clone();
+            params
+                .parse()
+                .into_future()
+                .and_then(move |params: SubgraphBatchParams| {
+                    let key = params.idempotency_key.clone();
+                    let request = format!("subgraph_batch {:?}", params);
+                    me.idempotent(key, request, |key| me.batch_handler(params, key))
+                })
+        });
+
+        let me = arc_self.clone();
+        handler.add_method("subgraph_list", move |_| me.list_handler());
+
+        let me = arc_self.clone();
+        handler.add_method("subgraph_assignments", move |params: Params| {
+            let me = me.clone();
+            let params = match params {
+                Params::None => Ok(SubgraphAssignmentsParams::default()),
+                params => params.parse(),
+            };
+            params
+                .into_future()
+                .and_then(move |params| me.assignments_handler(params))
+        });
+
+        let me = arc_self.clone();
+        handler.add_method("ethereum_networks", move |_| me.ethereum_networks_handler());
+
+        handler
+    }
 }
 
 impl<R> JsonRpcServerTrait<R> for JsonRpcServer<R>
@@ -169,68 +913,245 @@ where
     type Server = Server;
 
     fn serve(
+        listen_address: IpAddr,
         port: u16,
+        auth_token: Option<String>,
+        allowed_ips: Vec<IpNetwork>,
         http_port: u16,
         ws_port: u16,
         registrar: Arc<R>,
+        network_capabilities: EthereumNetworks<EthereumNetworkCapabilities>,
         node_id: NodeId,
         logger: Logger,
     ) -> Result<Self::Server, io::Error> {
         let logger = logger.new(o!("component" => "JsonRpcServer"));
 
+        let allowed_networks = allowed_ips
+            .iter()
+            .map(|network| network.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
         info!(
             logger,
-            "Starting JSON-RPC admin server at: http://localhost:{}", port
+            "Starting JSON-RPC admin server at: http://{}:{}", listen_address, port;
+            "authenticated" => auth_token.is_some(),
+            "allowed_ips" => allowed_networks,
         );
 
-        let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
-
-        let mut handler = IoHandler::with_compatibility(Compatibility::Both);
+        let addr = SocketAddr::new(listen_address, port);
+        let server_logger = logger.clone();
 
         let arc_self = Arc::new(JsonRpcServer {
             registrar,
+            network_capabilities,
             http_port,
             ws_port,
             node_id,
             logger,
+            idempotent_requests: Arc::new(Mutex::new(HashMap::new())),
         });
 
-        let me = arc_self.clone();
-        handler.add_method("subgraph_create", move |params: Params| {
-            let me = me.clone();
-            params
-                .parse()
-                .into_future()
-                .and_then(move |params| me.create_handler(params))
-        });
+        // Serve the methods over HTTP, with the same connection limits as
+        // the other HTTP servers
+        let io = Arc::new(JsonRpcServer::io_handler(arc_self));
+        let auth_token = auth_token.map(Arc::new);
+        let new_service = move |remote_addr: SocketAddr| {
+            let io = io.clone();
+            let auth_token = auth_token.clone();
+            let allowed = is_allowed(&allowed_ips, remote_addr.ip());
+            service_fn(move |request| -> ResponseFuture {
+                if !allowed {
+                    return Box::new(future::ok(text_response(
+                        StatusCode::FORBIDDEN,
+                        "Requests from this address are not allowed\n",
+                    )));
+                }
+                handle_request(
+                    &io,
+                    auth_token.as_ref().map(|token| token.as_str()),
+                    request,
+                )
+            })
+        };
+        let canceler = CancelGuard::new();
+        let task = serve(
+            server_logger,
+            addr,
+            ConnectionLimits::from_env(),
+            new_service,
+        )?;
+        tokio::spawn(task.cancelable(&canceler, || ()));
 
-        let me = arc_self.clone();
-        handler.add_method("subgraph_deploy", move |params: Params| {
-            let me = me.clone();
-            params
-                .parse()
-                .into_future()
-                .and_then(move |params| me.deploy_handler(params))
-        });
+        Ok(Server { canceler })
+    }
+}
 
-        let me = arc_self.clone();
-        handler.add_method("subgraph_remove", move |params: Params| {
-            let me = me.clone();
-            params
-                .parse()
-                .into_future()
-                .and_then(move |params| me.remove_handler(params))
-        });
+/// A running JSON-RPC admin server, which stops accepting requests once it
+/// is closed or dropped.
+pub struct Server {
+    canceler: CancelGuard,
+}
 
-        let me = arc_self.clone();
-        handler.add_method("subgraph_list", move |_| me.list_handler());
+impl Server {
+    pub fn close(self) {
+        drop(self.canceler)
+    }
+}
 
-        ServerBuilder::new(handler)
-            // Enable REST API:
-            // POST /<method>/<param1>/<param2>
-            .rest_api(RestApi::Secure)
-            .start_http(&addr.into())
+/// Handles a request to the admin server. JSON-RPC requests are POSTed to
+/// `/`; `POST /<method>/<param1>/<param2>` with a JSON content type calls
+/// a method with the parameters in the path. Requests without the auth
+/// token are rejected before they are read.
+fn handle_request(
+    io: &Arc<IoHandler>,
+    auth_token: Option<&str>,
+    request: Request<Body>,
+) -> ResponseFuture {
+    if let Some(auth_token) = auth_token {
+        let authorization = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        if !is_authorized(authorization, auth_token) {
+            return Box::new(future::ok(text_response(
+                StatusCode::UNAUTHORIZED,
+                "Missing or invalid admin auth token\n",
+            )));
+        }
     }
+
+    if *request.method() != Method::POST {
+        return Box::new(future::ok(text_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Only POST requests are supported\n",
+        )));
+    }
+
+    // Requests in the path must be JSON, so that browsers can't send them
+    // from HTML forms
+    let rest_path = match request.uri().path().trim_matches('/') {
+        "" => None,
+        path => {
+            let is_json = request
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map_or(false, |value| value.starts_with("application/json"));
+            if !is_json {
+                return Box::new(future::ok(text_response(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "Supported Content-Type: application/json\n",
+                )));
+            }
+            Some(path.to_owned())
+        }
+    };
+
+    let io = io.clone();
+    Box::new(
+        request
+            .into_body()
+            .fold((Vec::new(), false), |(mut body, too_large), chunk| {
+                let too_large = too_large || body.len() + chunk.len() > MAX_REQUEST_BODY_SIZE;
+                if !too_large {
+                    body.extend_from_slice(&chunk);
+                }
+                Ok::<_, hyper::Error>((body, too_large))
+            })
+            .and_then(move |(body, too_large)| -> ResponseFuture {
+                match too_large {
+                    false => call_method(&io, rest_path, body),
+                    true => Box::new(future::ok(text_response(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        "Request body is too large\n",
+                    ))),
+                }
+            }),
+    )
+}
+
+/// Calls the method of the request in `rest_path`, or in `body` if the
+/// request was sent to `/`.
+fn call_method(io: &IoHandler, rest_path: Option<String>, body: Vec<u8>) -> ResponseFuture {
+    let request = match rest_path {
+        Some(path) => rest_request(&path),
+        None => match String::from_utf8(body) {
+            Ok(request) => request,
+            Err(_) => {
+                return Box::new(future::ok(text_response(
+                    StatusCode::BAD_REQUEST,
+                    "Request body is not UTF-8\n",
+                )));
+            }
+        },
+    };
+    Box::new(io.handle_request(&request).then(|response| {
+        // Notifications don't have a response
+        let body = match response {
+            Ok(Some(response)) => format!("{}\n", response),
+            Ok(None) | Err(()) => String::new(),
+        };
+        let mut response = Response::new(Body::from(body));
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/json; charset=utf-8"),
+        );
+        Ok::<_, hyper::Error>(response)
+    }))
+}
+
+/// Turns the path of a request, `<method>/<param1>/<param2>`, into a
+/// JSON-RPC request. Parameters that are JSON values are passed as such,
+/// all others as strings.
+fn rest_request(path: &str) -> String {
+    let mut segments = path.split('/');
+    let method = segments.next().unwrap_or("");
+    let params = segments
+        .map(|param| {
+            serde_json::from_str(param).unwrap_or_else(|_| Value::String(param.to_owned()))
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    })
+    .to_string()
+}
+
+fn text_response(status: StatusCode, text: &'static str) -> Response<Body> {
+    let mut response = Response::new(Body::from(text));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response
+}
+
+/// Whether the `Authorization` header of a request carries `auth_token` as
+/// a bearer token. The token is compared in constant time, so that response
+/// times don't reveal how much of a guessed token is right.
+fn is_authorized(authorization: Option<&str>, auth_token: &str) -> bool {
+    let token = match authorization {
+        Some(authorization) if authorization.starts_with("Bearer ") => {
+            authorization["Bearer ".len()..].trim()
+        }
+        _ => return false,
+    };
+    token.len() == auth_token.len()
+        && token
+            .bytes()
+            .zip(auth_token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Whether requests from `ip` may be served. An empty allowlist allows
+/// requests from all addresses.
+fn is_allowed(allowed_ips: &[IpNetwork], ip: IpAddr) -> bool {
+    allowed_ips.is_empty() || allowed_ips.iter().any(|network| network.contains(ip))
 }
 
 fn json_rpc_error(code: i64, message: String) -> jsonrpc_core::Error {
@@ -276,3 +1197,297 @@ fn subgraph_routes(name: &SubgraphName, http_port: u16, ws_port: u16) -> Value {
     );
     jsonrpc_core::to_value(map).unwrap()
 }
+
+#[test]
+fn bearer_token_authorization() {
+    assert!(is_authorized(Some("Bearer secret"), "secret"));
+    assert!(!is_authorized(Some("Bearer secreT"), "secret"));
+    assert!(!is_authorized(Some("Bearer secret2"), "secret"));
+    assert!(!is_authorized(Some("Basic secret"), "secret"));
+    assert!(!is_authorized(None, "secret"));
+}
+
+#[test]
+fn ip_allowlist() {
+    let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+    let allowed_ips = vec![
+        "10.0.0.0/8".parse::<IpNetwork>().unwrap(),
+        "::1".parse::<IpNetwork>().unwrap(),
+    ];
+    assert!(is_allowed(&[], ip("192.168.1.1")));
+    assert!(is_allowed(&allowed_ips, ip("10.1.2.3")));
+    assert!(is_allowed(&allowed_ips, ip("::1")));
+    assert!(!is_allowed(&allowed_ips, ip("11.0.0.1")));
+    assert!(!is_allowed(&allowed_ips, ip("127.0.0.1")));
+}
+
+#[test]
+fn requests_are_served_over_http() {
+    let mut io = IoHandler::new();
+    io.add_method("echo", |params: Params| match params {
+        Params::Array(params) => Ok(Value::Array(params)),
+        _ => Ok(Value::Null),
+    });
+    let io = Arc::new(io);
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut send = |auth_token: Option<&str>, request: Request<Body>| {
+        let response = runtime
+            .block_on(handle_request(&io, auth_token, request))
+            .unwrap();
+        let status = response.status();
+        let body = runtime.block_on(response.into_body().concat2()).unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    };
+    let post = |path: &str, content_type: &str, body: &str| {
+        Request::post(path)
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body.to_owned()))
+            .unwrap()
+    };
+
+    let (status, body) = send(
+        None,
+        post(
+            "/",
+            "application/json",
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "echo", "params": [1]}"#,
+        ),
+    );
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        serde_json::from_str::<Value>(&body).unwrap()["result"],
+        serde_json::json!([1])
+    );
+
+    // Methods can be called with the parameters in the path, but only
+    // with a JSON content type
+    let (status, body) = send(None, post("/echo/1/two", "application/json", ""));
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        serde_json::from_str::<Value>(&body).unwrap()["result"],
+        serde_json::json!([1, "two"])
+    );
+    let (status, _) = send(None, post("/echo/1", "text/plain", ""));
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let get = Request::get("/").body(Body::empty()).unwrap();
+    assert_eq!(send(None, get).0, StatusCode::METHOD_NOT_ALLOWED);
+
+    // Requests without the auth token are rejected
+    let (status, _) = send(Some("secret"), post("/echo", "application/json", ""));
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let mut request = post("/echo", "application/json", "");
+    request
+        .headers_mut()
+        .insert(AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+    assert_eq!(send(Some("secret"), request).0, StatusCode::OK);
+}
+
+/// A registrar that records the batches it applies, and fails them while
+/// `fail` is set. Like the store, it keeps the idempotency keys of the
+/// batches it applied, with their results as JSON.
+#[cfg(test)]
+#[derive(Default)]
+struct TestRegistrar {
+    batches: Mutex<Vec<Vec<SubgraphRegistrarOperation>>>,
+    fail: std::sync::atomic::AtomicBool,
+    idempotency_keys: Mutex<HashMap<String, (String, String)>>,
+}
+
+#[cfg(test)]
+type RegistrarFuture<T> = Box<Future<Item = T, Error = SubgraphRegistrarError> + Send>;
+
+#[cfg(test)]
+impl SubgraphRegistrar for TestRegistrar {
+    fn create_subgraph(&self, _: SubgraphName) -> RegistrarFuture<CreateSubgraphResult> {
+        unimplemented!()
+    }
+
+    fn create_subgraph_version(
+        &self,
+        _: SubgraphName,
+        _: SubgraphDeploymentId,
+        _: NodeId,
+    ) -> RegistrarFuture<()> {
+        unimplemented!()
+    }
+
+    fn validate_subgraph_version(&self, _: SubgraphDeploymentId) -> RegistrarFuture<()> {
+        unimplemented!()
+    }
+
+    fn remove_subgraph(&self, _: SubgraphName) -> RegistrarFuture<()> {
+        unimplemented!()
+    }
+
+    fn list_subgraphs(&self) -> RegistrarFuture<Vec<SubgraphName>> {
+        unimplemented!()
+    }
+
+    fn rewind_subgraph(
+        &self,
+        _: SubgraphDeploymentId,
+        _: EthereumBlockPointer,
+        _: Option<IdempotencyKey>,
+    ) -> RegistrarFuture<()> {
+        unimplemented!()
+    }
+
+    fn list_assignments(
+        &self,
+        _: Option<NodeId>,
+    ) -> RegistrarFuture<Vec<(SubgraphDeploymentId, NodeId)>> {
+        unimplemented!()
+    }
+
+    fn set_deployment_enabled(
+        &self,
+        _: SubgraphDeploymentId,
+        _: Option<bool>,
+        _: Option<bool>,
+    ) -> RegistrarFuture<()> {
+        unimplemented!()
+    }
+
+    fn reassign_subgraph(&self, _: SubgraphDeploymentId, _: NodeId) -> RegistrarFuture<()> {
+        unimplemented!()
+    }
+
+    fn apply_operations(
+        &self,
+        operations: Vec<SubgraphRegistrarOperation>,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> RegistrarFuture<Vec<Option<CreateSubgraphResult>>> {
+        if let Some(idempotency_key) = &idempotency_key {
+            match self
+                .idempotency_keys
+                .lock()
+                .unwrap()
+                .get(&idempotency_key.key)
+            {
+                Some((request, _)) if *request != idempotency_key.request => {
+                    return Box::new(future::err(SubgraphRegistrarError::IdempotencyKeyReused(
+                        idempotency_key.key.clone(),
+                    )));
+                }
+                Some((_, results)) => {
+                    return Box::new(future::ok(serde_json::from_str(results).unwrap()));
+                }
+                None => (),
+            }
+        }
+
+        self.batches.lock().unwrap().push(operations.clone());
+        if self.fail.load(std::sync::atomic::Ordering::SeqCst) {
+            return Box::new(future::err(SubgraphRegistrarError::InvalidBatch(
+                "failed".to_owned(),
+            )));
+        }
+        let results: Vec<_> = operations
+            .into_iter()
+            .map(|operation| match operation {
+                SubgraphRegistrarOperation::Create { name } => Some(CreateSubgraphResult {
+                    id: name.to_string(),
+                }),
+                _ => None,
+            })
+            .collect();
+        if let Some(idempotency_key) = idempotency_key {
+            self.idempotency_keys.lock().unwrap().insert(
+                idempotency_key.key,
+                (
+                    idempotency_key.request,
+                    serde_json::to_string(&results).unwrap(),
+                ),
+            );
+        }
+        Box::new(future::ok(results))
+    }
+}
+
+#[test]
+fn batches_with_an_idempotency_key_are_applied_once() {
+    use std::sync::atomic::Ordering;
+
+    let registrar = Arc::new(TestRegistrar::default());
+    let server = || {
+        JsonRpcServer::io_handler(Arc::new(JsonRpcServer {
+            registrar: registrar.clone(),
+            network_capabilities: EthereumNetworks::new(),
+            http_port: 8000,
+            ws_port: 8001,
+            node_id: NodeId::new("test").unwrap(),
+            logger: Logger::root(slog::Discard, o!()),
+            idempotent_requests: Arc::new(Mutex::new(HashMap::new())),
+        }))
+    };
+    let send = |io: &IoHandler, method: &str, params: Value| {
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+        let response = io.handle_request_sync(&request.to_string()).unwrap();
+        serde_json::from_str::<Value>(&response).unwrap()
+    };
+    let io = server();
+    let batch = |name: &str, key: &str| {
+        let params = serde_json::json!({
+            "operations": [{ "method": "subgraph_create", "params": { "name": name } }],
+            "idempotency_key": key,
+        });
+        send(&io, "subgraph_batch", params)
+    };
+    let batches = || registrar.batches.lock().unwrap().len();
+
+    let response = batch("first", "key");
+    assert_eq!(response["result"], serde_json::json!([{ "id": "first" }]));
+    assert_eq!(batches(), 1);
+
+    // Replaying the request returns the first response without applying
+    // the batch again
+    assert_eq!(batch("first", "key"), response);
+    assert_eq!(batches(), 1);
+
+    // The key can't be reused for a different batch
+    let response = batch("second", "key");
+    assert_eq!(
+        response["error"]["code"],
+        Value::from(JSON_RPC_IDEMPOTENCY_ERROR)
+    );
+    assert_eq!(batches(), 1);
+
+    // Failed batches are forgotten, so they can be retried with their key
+    registrar.fail.store(true, Ordering::SeqCst);
+    let response = batch("second", "other key");
+    assert_eq!(response["error"]["code"], Value::from(JSON_RPC_BATCH_ERROR));
+    assert_eq!(batches(), 2);
+    registrar.fail.store(false, Ordering::SeqCst);
+    let response = batch("second", "other key");
+    assert_eq!(response["result"], serde_json::json!([{ "id": "second" }]));
+    assert_eq!(batches(), 3);
+
+    // The keys are kept by the registrar, so another server, e.g. on
+    // another node or after a restart, also knows them
+    let other_io = server();
+    let params = serde_json::json!({
+        "operations": [{ "method": "subgraph_create", "params": { "name": "first" } }],
+        "idempotency_key": "key",
+    });
+    assert_eq!(
+        send(&other_io, "subgraph_batch", params),
+        batch("first", "key")
+    );
+    assert_eq!(batches(), 3);
+
+    // Single operations with a key are applied once as well, with the
+    // response they have without a key
+    let params = serde_json::json!({ "name": "third", "idempotency_key": "create key" });
+    let response = send(&io, "subgraph_create", params.clone());
+    assert_eq!(response["result"], serde_json::json!({ "id": "third" }));
+    assert_eq!(batches(), 4);
+    assert_eq!(send(&other_io, "subgraph_create", params), response);
+    assert_eq!(batches(), 4);
+}
@@ -1,14 +1,33 @@
 use futures::future::IntoFuture;
-use futures::stream::SplitStream;
+use futures::stream::{poll_fn, SplitStream};
 use futures::sync::mpsc;
 use graphql_parser::parse_query;
+use lazy_static::lazy_static;
 use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
 use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
 use tokio_tungstenite::WebSocketStream;
 use uuid::Uuid;
 
 use graph::prelude::*;
 use graph::serde_json;
+use graph::tokio_timer::Delay;
+
+lazy_static! {
+    /// The shortest time between two results that are sent for the same
+    /// subscription. Zero turns throttling off.
+    static ref SUBSCRIPTION_MIN_PUSH_INTERVAL: Duration =
+        env::var("SUBSCRIPTION_MIN_PUSH_INTERVAL")
+            .ok()
+            .map(|s| {
+                s.parse::<u64>().unwrap_or_else(|_| {
+                    panic!("failed to parse env var SUBSCRIPTION_MIN_PUSH_INTERVAL")
+                })
+            })
+            .map(Duration::from_millis)
+            .unwrap_or(Duration::from_millis(0));
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -87,6 +106,61 @@ fn send_error_string(
         .map_err(|_| WsError::Http(500))
 }
 
+/// Skips results that are identical to the result that was sent last. If
+/// `min_interval` is not zero, results are sent at most every `min_interval`
+/// and of the results that arrive in the meantime, only the newest is sent
+/// once the interval has passed; each result is the complete, current
+/// result of the subscription, so the older ones are outdated by then.
+fn throttle_results(
+    results: QueryResultStream,
+    min_interval: Duration,
+) -> impl Stream<Item = QueryResult, Error = ()> {
+    let coalesce = min_interval > Duration::from_millis(0);
+    let mut results = results.fuse();
+    let mut results_done = false;
+    let mut pending: Option<QueryResult> = None;
+    let mut last_sent: Option<String> = None;
+    let mut delay: Option<Delay> = None;
+
+    poll_fn(move || loop {
+        // With an interval, keep only the newest of the results that are
+        // ready; without one, take them one at a time
+        while !results_done && (coalesce || pending.is_none()) {
+            match results.poll()? {
+                Async::Ready(Some(result)) => pending = Some(result),
+                Async::Ready(None) => results_done = true,
+                Async::NotReady => break,
+            }
+        }
+
+        if pending.is_none() {
+            return Ok(if results_done {
+                Async::Ready(None)
+            } else {
+                Async::NotReady
+            });
+        }
+
+        // Wait until the interval since the last result has passed; timer
+        // errors are harmless, so treat them as if the timer was ready
+        if let Some(Ok(Async::NotReady)) = delay.as_mut().map(|delay| delay.poll()) {
+            return Ok(Async::NotReady);
+        }
+        delay = None;
+
+        let result = pending.take().unwrap();
+        let serialized = serde_json::to_string(&result).ok();
+        if serialized.is_some() && serialized == last_sent {
+            continue;
+        }
+        last_sent = serialized;
+        if coalesce {
+            delay = Some(Delay::new(Instant::now() + min_interval));
+        }
+        return Ok(Async::Ready(Some(result)));
+    })
+}
+
 /// Responsible for recording operation ids and stopping them.
 /// On drop, cancels all operations.
 struct Operations {
@@ -269,6 +343,7 @@ where
                             schema: schema.clone(),
                             document: query,
                             variables,
+                            block: None,
                         },
                     };
 
@@ -297,7 +372,7 @@ where
                         })
                         .and_then(move |result_stream| {
                             // Send results back to the client as GQL_DATA
-                            result_stream
+                            throttle_results(result_stream, *SUBSCRIPTION_MIN_PUSH_INTERVAL)
                                 .map(move |result| {
                                     OutgoingMessage::from_query_result(result_id.clone(), result)
                                 })
@@ -374,3 +449,44 @@ where
         }))
     }
 }
+
+#[test]
+fn throttle_results_skips_identical_results() {
+    use graphql_parser::query as q;
+
+    let result = |n| QueryResult::new(Some(q::Value::Int(q::Number::from(n))));
+    let results: QueryResultStream = Box::new(stream::iter_ok(vec![
+        result(1),
+        result(1),
+        result(2),
+        result(1),
+    ]));
+
+    let sent = throttle_results(results, Duration::from_millis(0))
+        .collect()
+        .wait()
+        .unwrap()
+        .into_iter()
+        .map(|result| serde_json::to_string(&result).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(sent, vec!["{\"data\":1}", "{\"data\":2}", "{\"data\":1}"]);
+}
+
+#[test]
+fn throttle_results_coalesces_results_within_the_interval() {
+    use graphql_parser::query as q;
+
+    let result = |n| QueryResult::new(Some(q::Value::Int(q::Number::from(n))));
+    let results: QueryResultStream =
+        Box::new(stream::iter_ok(vec![result(1), result(2), result(3)]));
+
+    // All results are ready at once, so only the newest of them is sent
+    let sent = throttle_results(results, Duration::from_millis(10))
+        .collect()
+        .wait()
+        .unwrap()
+        .into_iter()
+        .map(|result| serde_json::to_string(&result).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(sent, vec!["{\"data\":3}"]);
+}
@@ -151,6 +151,17 @@ where
                         Ok(true) => (),
                     }
 
+                    // Check if queries against the subgraph have been disabled
+                    match store.is_deployment_query_disabled(&subgraph_id) {
+                        Err(_) | Ok(true) => {
+                            error!(logger, "Failed to establish WS connection, queries are disabled for subgraph";
+                                            "subgraph_id" => subgraph_id.to_string(),
+                            );
+                            return Err(WsError::Http(403));
+                        }
+                        Ok(false) => (),
+                    }
+
                     *accept_subgraph_id.lock().unwrap() = Some(subgraph_id);
 
                     Ok(Some(vec![(
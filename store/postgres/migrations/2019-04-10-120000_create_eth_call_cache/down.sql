@@ -0,0 +1,4 @@
+/**************************************************************
+* DROP TABLES
+**************************************************************/
+DROP TABLE eth_call_cache;
@@ -0,0 +1,12 @@
+/**************************************************************
+* CREATE TABLES
+**************************************************************/
+-- Caches the results of contract calls. The id is the Keccak-256 hash of
+-- the contract address, the call data and the hash of the block the call
+-- was made at
+CREATE TABLE IF NOT EXISTS eth_call_cache (
+    id VARCHAR PRIMARY KEY,
+    return_value BYTEA NOT NULL,
+    contract_address VARCHAR NOT NULL,
+    block_number BIGINT NOT NULL
+);
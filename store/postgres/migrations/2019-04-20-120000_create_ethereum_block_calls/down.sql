@@ -0,0 +1,4 @@
+/**************************************************************
+* DROP TABLES
+**************************************************************/
+DROP TABLE ethereum_block_calls;
@@ -0,0 +1,11 @@
+/**************************************************************
+* CREATE TABLES
+**************************************************************/
+-- Caches the calls traced in a block, so that they are only fetched from
+-- the Ethereum node once, no matter how many subgraphs need them
+CREATE TABLE IF NOT EXISTS ethereum_block_calls (
+    block_hash VARCHAR PRIMARY KEY,
+    block_number BIGINT NOT NULL,
+    network_name VARCHAR NOT NULL,
+    calls JSONB NOT NULL
+);
@@ -0,0 +1,11 @@
+/**************************************************************
+* CREATE TABLES
+**************************************************************/
+-- Deployments that no subgraph version refers to and that are not assigned
+-- to a node, as recorded by `graph-node manage unused record`, so that they
+-- can be reviewed before they are removed
+CREATE TABLE IF NOT EXISTS unused_deployments (
+    deployment VARCHAR PRIMARY KEY,
+    entity_count BIGINT NOT NULL,
+    recorded_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,6 @@
+DROP FUNCTION build_lower_attribute_index(
+  subgraph_id Text,
+  index_name Text,
+  attribute_name Text,
+  entity_name Text
+);
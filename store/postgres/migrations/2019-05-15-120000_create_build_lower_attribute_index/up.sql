@@ -0,0 +1,20 @@
+-- Build a partial index on the lowercased values of a string attribute,
+-- for filters like `name_starts_with_nocase` that ignore case
+CREATE OR REPLACE FUNCTION build_lower_attribute_index(subgraph_id Text, index_name Text,
+        attribute_name Text, entity_name Text) RETURNS void
+LANGUAGE plpgsql
+AS $$
+BEGIN
+    EXECUTE 'CREATE INDEX ' || index_name
+      || ' ON entities USING btree(lower(data -> '
+      || quote_literal(attribute_name)
+      || ' ->> ''data'') text_pattern_ops) where subgraph='
+      || quote_literal(subgraph_id)
+      || ' and entity='
+      || quote_literal(entity_name);
+  RETURN ;
+EXCEPTION
+  WHEN duplicate_table THEN
+      -- do nothing if index already exists
+END;
+$$;
@@ -0,0 +1,7 @@
+DROP FUNCTION build_composite_attribute_index(
+  subgraph_id Text,
+  index_name Text,
+  attribute_names Text[],
+  attribute_casts Text[],
+  entity_name Text
+);
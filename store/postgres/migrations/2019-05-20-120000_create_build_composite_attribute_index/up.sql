@@ -0,0 +1,30 @@
+-- Build a partial index on several attributes of an entity type, declared
+-- with `@index(fields: [...])` in the subgraph schema. Each attribute is
+-- cast the same way as when queries order by it
+CREATE OR REPLACE FUNCTION build_composite_attribute_index(subgraph_id Text, index_name Text,
+        attribute_names Text[], attribute_casts Text[], entity_name Text) RETURNS void
+LANGUAGE plpgsql
+AS $$
+DECLARE
+    index_columns Text[] := '{}';
+BEGIN
+    FOR i IN 1 .. array_length(attribute_names, 1) LOOP
+        index_columns := index_columns || ('((data -> '
+          || quote_literal(attribute_names[i])
+          || ' ->> ''data'')'
+          || attribute_casts[i]
+          || ')');
+    END LOOP;
+    EXECUTE 'CREATE INDEX ' || index_name
+      || ' ON entities USING btree('
+      || array_to_string(index_columns, ', ')
+      || ') where subgraph='
+      || quote_literal(subgraph_id)
+      || ' and entity='
+      || quote_literal(entity_name);
+  RETURN ;
+EXCEPTION
+  WHEN duplicate_table THEN
+      -- do nothing if index already exists
+END;
+$$;
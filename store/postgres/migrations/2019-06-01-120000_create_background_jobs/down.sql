@@ -0,0 +1,4 @@
+/**************************************************************
+* DROP TABLES
+**************************************************************/
+DROP TABLE background_jobs;
@@ -0,0 +1,21 @@
+/**************************************************************
+* CREATE TABLES
+**************************************************************/
+-- Long-running operations on deployments, e.g. copying or exporting them,
+-- that index nodes run in the background. A node that restarts resumes the
+-- jobs it was running, from the progress they recorded
+CREATE TABLE IF NOT EXISTS background_jobs (
+    id SERIAL PRIMARY KEY,
+    kind VARCHAR NOT NULL,
+    deployment VARCHAR NOT NULL,
+    args JSONB NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'queued',
+    progress JSONB NOT NULL DEFAULT '{}',
+    error VARCHAR,
+    node_id VARCHAR,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS background_jobs_status_idx
+    ON background_jobs (status);
@@ -0,0 +1,5 @@
+/**************************************************************
+* DROP COLUMNS
+**************************************************************/
+ALTER TABLE background_jobs
+    DROP COLUMN IF EXISTS lease_expires_at;
@@ -0,0 +1,8 @@
+/**************************************************************
+* ADD COLUMNS
+**************************************************************/
+-- Running jobs are leased to the node that runs them, which renews the
+-- lease while the job runs. Another node takes over a job whose lease has
+-- expired, e.g. because the node that ran it was shut down for good
+ALTER TABLE background_jobs
+    ADD COLUMN IF NOT EXISTS lease_expires_at TIMESTAMPTZ;
@@ -0,0 +1,4 @@
+/**************************************************************
+* DROP TABLES
+**************************************************************/
+DROP TABLE entity_change_outbox;
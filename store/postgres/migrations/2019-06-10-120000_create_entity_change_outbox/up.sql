@@ -0,0 +1,11 @@
+/**************************************************************
+* CREATE TABLES
+**************************************************************/
+-- Entity changes that were committed but not yet published to the entity
+-- change sink. Changes are recorded in the transaction that makes them and
+-- removed once the sink accepted them, so that none are lost when a node
+-- stops before publishing them
+CREATE TABLE IF NOT EXISTS entity_change_outbox (
+    id BIGSERIAL PRIMARY KEY,
+    change JSONB NOT NULL
+);
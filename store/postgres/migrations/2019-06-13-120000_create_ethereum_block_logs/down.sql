@@ -0,0 +1,10 @@
+/**************************************************************
+* DROP TABLES
+**************************************************************/
+DROP TABLE ethereum_block_logs;
+
+/**************************************************************
+* CHANGE PRIMARY KEYS
+**************************************************************/
+ALTER TABLE ethereum_block_calls DROP CONSTRAINT ethereum_block_calls_pkey;
+ALTER TABLE ethereum_block_calls ADD PRIMARY KEY (block_hash);
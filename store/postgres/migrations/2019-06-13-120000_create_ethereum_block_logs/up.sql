@@ -0,0 +1,19 @@
+/**************************************************************
+* CHANGE PRIMARY KEYS
+**************************************************************/
+-- Cached calls are looked up by network and block hash
+ALTER TABLE ethereum_block_calls DROP CONSTRAINT ethereum_block_calls_pkey;
+ALTER TABLE ethereum_block_calls ADD PRIMARY KEY (network_name, block_hash);
+
+/**************************************************************
+* CREATE TABLES
+**************************************************************/
+-- Caches the logs of a block, so that they are only fetched from the
+-- Ethereum node once, no matter how many subgraphs need them
+CREATE TABLE IF NOT EXISTS ethereum_block_logs (
+    block_hash VARCHAR NOT NULL,
+    block_number BIGINT NOT NULL,
+    network_name VARCHAR NOT NULL,
+    logs JSONB NOT NULL,
+    PRIMARY KEY (network_name, block_hash)
+);
@@ -0,0 +1,23 @@
+drop trigger if exists entity_change_insert_trigger on entities;
+drop trigger if exists entity_change_update_trigger on entities;
+drop trigger if exists entity_change_delete_trigger on entities;
+
+create trigger entity_change_insert_trigger
+  after insert on entities
+  for each row
+  when (new.subgraph != 'subgraphs')
+  execute procedure log_entity_event();
+
+create trigger entity_change_update_trigger
+  after update on entities
+  for each row
+  when (old.subgraph != 'subgraphs' and old.data != new.data)
+  execute procedure log_entity_event();
+
+create trigger entity_change_delete_trigger
+  after delete on entities
+  for each row
+  when (old.subgraph != 'subgraphs')
+  execute procedure log_entity_event();
+
+drop function if exists is_immutable_entity(VARCHAR, VARCHAR);
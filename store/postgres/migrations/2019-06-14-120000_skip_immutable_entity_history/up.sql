@@ -0,0 +1,42 @@
+/**************************************************************
+* SKIP THE HISTORY OF IMMUTABLE ENTITIES
+*
+* Entities of immutable types are only ever inserted, so there is
+* nothing for their history to record that their event source does
+* not already tell; reverting a block deletes the immutable entities
+* whose event source is the block. The store passes the entity types
+* whose history to skip in the transaction-local setting
+* vars.immutable_entity_types, as a comma separated list of
+* 'subgraph/EntityType'
+**************************************************************/
+create or replace function is_immutable_entity(subgraph VARCHAR, entity VARCHAR)
+    returns boolean as
+$$
+    select (subgraph || '/' || entity) = any(string_to_array(
+        coalesce(current_setting('vars.immutable_entity_types', true), ''), ','))
+$$ language sql stable;
+
+drop trigger if exists entity_change_insert_trigger on entities;
+drop trigger if exists entity_change_update_trigger on entities;
+drop trigger if exists entity_change_delete_trigger on entities;
+
+create trigger entity_change_insert_trigger
+  after insert on entities
+  for each row
+  when (new.subgraph != 'subgraphs'
+        and not is_immutable_entity(new.subgraph, new.entity))
+  execute procedure log_entity_event();
+
+create trigger entity_change_update_trigger
+  after update on entities
+  for each row
+  when (old.subgraph != 'subgraphs' and old.data != new.data
+        and not is_immutable_entity(old.subgraph, old.entity))
+  execute procedure log_entity_event();
+
+create trigger entity_change_delete_trigger
+  after delete on entities
+  for each row
+  when (old.subgraph != 'subgraphs'
+        and not is_immutable_entity(old.subgraph, old.entity))
+  execute procedure log_entity_event();
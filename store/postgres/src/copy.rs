@@ -0,0 +1,151 @@
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Jsonb, Text};
+
+use graph::components::store::Store as StoreTrait;
+use graph::data::subgraph::schema::*;
+use graph::prelude::*;
+use graph::serde_json;
+
+use crate::store::Store;
+
+/// How many entities to copy per transaction.
+const COPY_BATCH_SIZE: i64 = 1000;
+
+#[derive(QueryableByName)]
+struct EntityRow {
+    #[sql_type = "Text"]
+    entity: String,
+    #[sql_type = "Text"]
+    id: String,
+    #[sql_type = "Jsonb"]
+    data: serde_json::Value,
+}
+
+impl EntityRow {
+    fn into_operation(self, subgraph_id: &SubgraphDeploymentId) -> Result<EntityOperation, Error> {
+        Ok(EntityOperation::Set {
+            key: EntityKey {
+                subgraph_id: subgraph_id.clone(),
+                entity_type: self.entity,
+                entity_id: self.id,
+            },
+            data: serde_json::from_value::<Entity>(self.data)?,
+        })
+    }
+}
+
+/// Copies a subgraph deployment, its entities and its metadata, from the
+/// database at `source_url` into `store`, so that it continues indexing
+/// from the block it was copied at instead of from the start. The copy is
+/// read from a snapshot of the source database, which may keep indexing
+/// the deployment in the meantime.
+///
+/// The deployment entity is written last, so an interrupted copy is not
+/// visible as a deployment and can simply be started again. `on_progress`
+/// is called with the number of entities copied so far after every batch;
+/// if it fails, the copy stops with its error. Returns the number of
+/// entities that were copied.
+pub fn copy_deployment(
+    logger: &Logger,
+    source_url: &str,
+    store: &Store,
+    subgraph_id: &SubgraphDeploymentId,
+    mut on_progress: impl FnMut(u64) -> Result<(), Error>,
+) -> Result<u64, Error> {
+    if store
+        .get(SubgraphDeploymentEntity::key(subgraph_id.clone()))?
+        .is_some()
+    {
+        bail!(
+            "subgraph deployment `{}` already exists in this database",
+            subgraph_id
+        );
+    }
+
+    let source = PgConnection::establish(source_url)?;
+    source
+        .build_transaction()
+        .read_only()
+        .repeatable_read()
+        .run(|| {
+            // The deployment entity and the manifest, with everything the
+            // manifest refers to, whose IDs all start with the manifest ID
+            let manifest_id = SubgraphManifestEntity::id(subgraph_id);
+            let metadata = diesel::sql_query(
+                "select entity, id, data
+                   from entities
+                  where subgraph = $1
+                    and ((entity = $2 and id = $3) or id = $4 or id like $4 || '-%')",
+            )
+            .bind::<Text, _>(SUBGRAPHS_ID.to_string())
+            .bind::<Text, _>(SubgraphDeploymentEntity::TYPENAME)
+            .bind::<Text, _>(subgraph_id.to_string())
+            .bind::<Text, _>(&manifest_id)
+            .load::<EntityRow>(&source)?;
+
+            let (deployment, manifest): (Vec<_>, Vec<_>) = metadata
+                .into_iter()
+                .partition(|row| row.entity == SubgraphDeploymentEntity::TYPENAME);
+            let deployment = match deployment.into_iter().next() {
+                Some(deployment) => deployment.into_operation(&SUBGRAPHS_ID)?,
+                None => bail!(
+                    "subgraph deployment `{}` does not exist in the source database",
+                    subgraph_id
+                ),
+            };
+            if let EntityOperation::Set { data, .. } = &deployment {
+                if data.get("failed") == Some(&Value::Bool(true)) {
+                    warn!(logger, "Copying a subgraph deployment that has failed");
+                }
+            }
+
+            // Writing entities needs the schema from the manifest
+            store.apply_entity_operations(
+                manifest
+                    .into_iter()
+                    .map(|row| row.into_operation(&SUBGRAPHS_ID))
+                    .collect::<Result<_, _>>()?,
+                EventSource::None,
+            )?;
+
+            let mut count = 0;
+            let mut last = (String::new(), String::new());
+            loop {
+                let rows = diesel::sql_query(
+                    "select entity, id, data
+                       from entities
+                      where subgraph = $1
+                        and (entity, id) > ($2, $3)
+                      order by entity, id
+                      limit $4",
+                )
+                .bind::<Text, _>(subgraph_id.to_string())
+                .bind::<Text, _>(&last.0)
+                .bind::<Text, _>(&last.1)
+                .bind::<BigInt, _>(COPY_BATCH_SIZE)
+                .load::<EntityRow>(&source)?;
+
+                let batch_size = rows.len();
+                if let Some(row) = rows.last() {
+                    last = (row.entity.clone(), row.id.clone());
+                }
+                store.apply_entity_operations(
+                    rows.into_iter()
+                        .map(|row| row.into_operation(subgraph_id))
+                        .collect::<Result<_, _>>()?,
+                    EventSource::None,
+                )?;
+                count += batch_size as u64;
+                debug!(logger, "Copied entities"; "entities" => count);
+                on_progress(count)?;
+
+                if (batch_size as i64) < COPY_BATCH_SIZE {
+                    break;
+                }
+            }
+
+            store.apply_entity_operations(vec![deployment], EventSource::None)?;
+            Ok(count)
+        })
+}
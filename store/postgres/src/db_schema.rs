@@ -51,6 +51,33 @@ table! {
     }
 }
 
+table! {
+    ethereum_block_calls (network_name, block_hash) {
+        block_hash -> Varchar,
+        block_number -> BigInt,
+        network_name -> Varchar,
+        calls -> Jsonb,
+    }
+}
+
+table! {
+    ethereum_block_logs (network_name, block_hash) {
+        block_hash -> Varchar,
+        block_number -> BigInt,
+        network_name -> Varchar,
+        logs -> Jsonb,
+    }
+}
+
+table! {
+    eth_call_cache (id) {
+        id -> Varchar,
+        return_value -> Binary,
+        contract_address -> Varchar,
+        block_number -> BigInt,
+    }
+}
+
 table! {
     large_notifications(id) {
         id -> Integer,
@@ -58,3 +85,34 @@ table! {
         created_at -> Timestamp,
     }
 }
+
+table! {
+    unused_deployments (deployment) {
+        deployment -> Varchar,
+        entity_count -> BigInt,
+        recorded_at -> Timestamp,
+    }
+}
+
+table! {
+    background_jobs (id) {
+        id -> Integer,
+        kind -> Varchar,
+        deployment -> Varchar,
+        args -> Jsonb,
+        status -> Varchar,
+        progress -> Jsonb,
+        error -> Nullable<Varchar>,
+        node_id -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        lease_expires_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    entity_change_outbox (id) {
+        id -> BigInt,
+        change -> Jsonb,
+    }
+}
@@ -0,0 +1,65 @@
+use diesel::dsl::sql;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::sql_types::BigInt;
+use diesel::{delete, insert_into};
+
+use graph::prelude::{EntityChangeOutbox as EntityChangeOutboxTrait, *};
+use graph::serde_json;
+
+use crate::db_schema::entity_change_outbox::dsl::*;
+
+/// The entity changes that a `Store` committed but that were not published
+/// to the entity change sink yet, in the `entity_change_outbox` table.
+pub struct EntityChangeOutbox {
+    conn: Pool<ConnectionManager<PgConnection>>,
+}
+
+impl EntityChangeOutbox {
+    pub(crate) fn new(conn: Pool<ConnectionManager<PgConnection>>) -> Self {
+        EntityChangeOutbox { conn }
+    }
+}
+
+impl EntityChangeOutboxTrait for EntityChangeOutbox {
+    fn pending(&self, limit: usize) -> Result<Vec<(i64, CommittedEntityChange)>, Error> {
+        entity_change_outbox
+            .select((id, change))
+            .order(id.asc())
+            .limit(limit as i64)
+            .load::<(i64, serde_json::Value)>(&*self.conn.get()?)?
+            .into_iter()
+            .map(|(position, value)| Ok((position, serde_json::from_value(value)?)))
+            .collect()
+    }
+
+    fn acknowledge(&self, position: i64) -> Result<(), Error> {
+        delete(entity_change_outbox.filter(id.le(position))).execute(&*self.conn.get()?)?;
+        Ok(())
+    }
+}
+
+/// Records `changes` in the outbox, as part of the transaction of `conn`.
+pub(crate) fn record(conn: &PgConnection, changes: &[CommittedEntityChange]) -> Result<(), Error> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let rows = changes
+        .iter()
+        .map(|c| serde_json::to_value(c).map(|value| change.eq(value)))
+        .collect::<Result<Vec<_>, _>>()?;
+    insert_into(entity_change_outbox)
+        .values(&rows)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Returns the number of changes in the outbox. Counting the range of
+/// positions is cheap and good enough since changes are removed in order.
+pub(crate) fn len(conn: &PgConnection) -> Result<u64, Error> {
+    let len = entity_change_outbox
+        .select(sql::<BigInt>("coalesce(max(id) - min(id) + 1, 0)"))
+        .get_result::<i64>(conn)?;
+    Ok(len as u64)
+}
@@ -1,12 +1,13 @@
 use diesel::dsl::{self, sql};
+use diesel::expression::NonAggregate;
 use diesel::pg::Pg;
 use diesel::prelude::*;
-use diesel::query_builder::BoxedSelectStatement;
+use diesel::query_builder::{AstPass, BoxedSelectStatement, QueryFragment, QueryId};
 use diesel::serialize::ToSql;
 use diesel::sql_types::{Array, Bool, HasSqlType, Integer, Numeric, Text};
 use std::str::FromStr;
 
-use graph::components::store::EntityFilter;
+use graph::components::store::{ChildLink, EntityFilter};
 use graph::data::store::*;
 use graph::prelude::{BigDecimal, BigInt};
 use graph::serde_json;
@@ -40,6 +41,29 @@ impl IntoFilter for String {
     }
 }
 
+/// Matches the lowercased value of a string attribute against a lowercased
+/// `LIKE` pattern, in the form that the `lower()` attribute indexes cover.
+fn lower_like_filter(attribute: String, op: &str, pattern: String) -> FilterExpression {
+    match &*attribute {
+        "id" => Box::new(
+            sql("lower(id)")
+                .sql(op)
+                .sql("lower(")
+                .bind::<Text, _>(pattern)
+                .sql(")"),
+        ) as FilterExpression,
+        _ => Box::new(
+            sql("lower(data -> ")
+                .bind::<Text, _>(attribute)
+                .sql(" ->> 'data')")
+                .sql(op)
+                .sql("lower(")
+                .bind::<Text, _>(pattern)
+                .sql(")"),
+        ) as FilterExpression,
+    }
+}
+
 impl IntoFilter for i32 {
     fn into_filter(self, attribute: String, op: &str) -> FilterExpression {
         Box::new(
@@ -122,6 +146,71 @@ impl IntoArrayFilter<SqlValue> for Vec<SqlValue> {
     }
 }
 
+/// Matches entities that are linked through `link` to an entity of one of
+/// `entity_types` that matches `filter`. The children are looked up in a
+/// subquery on `entities`; unqualified column names inside of it refer to
+/// the children, which is what `filter` expects. The parent is only
+/// referred to outside of the subquery.
+struct ChildFilter {
+    link: ChildLink,
+    entity_types: Vec<String>,
+    filter: FilterExpression,
+}
+
+impl Expression for ChildFilter {
+    type SqlType = Bool;
+}
+
+impl NonAggregate for ChildFilter {}
+
+impl AppearsOnTable<entities::table> for ChildFilter {}
+
+impl SelectableExpression<entities::table> for ChildFilter {}
+
+impl QueryId for ChildFilter {
+    type QueryId = ();
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl QueryFragment<Pg> for ChildFilter {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        // Children have to be in the same subgraph as their parent, which
+        // is why `subgraph` is compared along with the IDs
+        match &self.link {
+            ChildLink::Direct(attribute, false) => {
+                out.push_sql("(subgraph, data -> ");
+                out.push_bind_param::<Text, _>(attribute)?;
+                out.push_sql(" ->> 'data') in (select subgraph, id");
+            }
+            ChildLink::Direct(attribute, true) => {
+                out.push_sql("exists (select 1 from jsonb_array_elements(data -> ");
+                out.push_bind_param::<Text, _>(attribute)?;
+                out.push_sql(" -> 'data') as child_ref");
+                out.push_sql(" where (subgraph, child_ref ->> 'data') in (select subgraph, id");
+            }
+            ChildLink::Derived(attribute, false) => {
+                out.push_sql("(subgraph, id) in (select subgraph, data -> ");
+                out.push_bind_param::<Text, _>(attribute)?;
+                out.push_sql(" ->> 'data'");
+            }
+            ChildLink::Derived(attribute, true) => {
+                out.push_sql("(subgraph, id) in (select subgraph, jsonb_array_elements(data -> ");
+                out.push_bind_param::<Text, _>(attribute)?;
+                out.push_sql(" -> 'data') ->> 'data'");
+            }
+        }
+        out.push_sql(" from entities where entity = any(");
+        out.push_bind_param::<Array<Text>, _>(&self.entity_types)?;
+        out.push_sql(") and ");
+        self.filter.walk_ast(out.reborrow())?;
+        out.push_sql(")");
+        if let ChildLink::Direct(_, true) = self.link {
+            out.push_sql(")");
+        }
+        Ok(())
+    }
+}
+
 /// Adds `filter` to a SELECT statement.
 pub(crate) fn store_filter<T>(
     query: BoxedSelectStatement<T, entities::table, Pg>,
@@ -145,6 +234,12 @@ pub(crate) fn build_filter(filter: EntityFilter) -> Result<FilterExpression, Uns
             build_filter(filter).map(|filter_expr| Box::new(p.or(filter_expr)) as FilterExpression)
         }),
 
+        Child(link, entity_types, filter) => Ok(Box::new(ChildFilter {
+            link,
+            entity_types,
+            filter: build_filter(*filter)?,
+        }) as FilterExpression),
+
         Contains(..) | NotContains(..) => {
             let (attribute, contains, op, value) = match filter {
                 EntityFilter::Contains(attribute, value) => (attribute, true, " LIKE ", value),
@@ -351,5 +446,92 @@ pub(crate) fn build_filter(filter: EntityFilter) -> Result<FilterExpression, Uns
                 }
             }
         }
+
+        ContainsNoCase(..) | NotContainsNoCase(..) => {
+            let (attribute, op, value) = match filter {
+                ContainsNoCase(attribute, value) => (attribute, " ILIKE ", value),
+                NotContainsNoCase(attribute, value) => (attribute, " NOT ILIKE ", value),
+                _ => unreachable!(),
+            };
+
+            match value {
+                Value::String(s) => Ok(s.into_filter(attribute, op)),
+                Value::Bool(_)
+                | Value::BigInt(_)
+                | Value::Bytes(_)
+                | Value::BigDecimal(_)
+                | Value::Int(_)
+                | Value::List(_)
+                | Value::Null => {
+                    return Err(UnsupportedFilter {
+                        filter: if op == " ILIKE " {
+                            "contains_nocase"
+                        } else {
+                            "not_contains_nocase"
+                        }
+                        .to_owned(),
+                        value,
+                    });
+                }
+            }
+        }
+
+        StartsWithNoCase(..) | NotStartsWithNoCase(..) => {
+            let (attribute, op, value) = match filter {
+                StartsWithNoCase(attribute, value) => (attribute, " LIKE ", value),
+                NotStartsWithNoCase(attribute, value) => (attribute, " NOT LIKE ", value),
+                _ => unreachable!(),
+            };
+
+            match value {
+                Value::String(s) => Ok(lower_like_filter(attribute, op, format!("{}%", s))),
+                Value::Bool(_)
+                | Value::BigInt(_)
+                | Value::Bytes(_)
+                | Value::BigDecimal(_)
+                | Value::Int(_)
+                | Value::List(_)
+                | Value::Null => {
+                    return Err(UnsupportedFilter {
+                        filter: if op == " LIKE " {
+                            "starts_with_nocase"
+                        } else {
+                            "not_starts_with_nocase"
+                        }
+                        .to_owned(),
+                        value,
+                    });
+                }
+            }
+        }
+
+        EndsWithNoCase(..) | NotEndsWithNoCase(..) => {
+            let (attribute, op, value) = match filter {
+                EndsWithNoCase(attribute, value) => (attribute, " ILIKE ", value),
+                NotEndsWithNoCase(attribute, value) => (attribute, " NOT ILIKE ", value),
+                _ => unreachable!(),
+            };
+
+            match value {
+                Value::String(s) => Ok(format!("%{}", s).into_filter(attribute, op)),
+                Value::Bool(_)
+                | Value::BigInt(_)
+                | Value::Bytes(_)
+                | Value::BigDecimal(_)
+                | Value::Int(_)
+                | Value::List(_)
+                | Value::Null => {
+                    return Err(UnsupportedFilter {
+                        filter: if op == " ILIKE " {
+                            "ends_with_nocase"
+                        } else {
+                            "not_ends_with_nocase"
+                        }
+                        .to_owned(),
+                        value,
+                    });
+                }
+            }
+        }
     }
 }
@@ -41,6 +41,24 @@ sql_function! {
     )
 }
 
+sql_function! {
+    build_lower_attribute_index,
+    BuildLowerAttributeIndex,
+    (subgraph_id: Text, index_name: Text, attribute_name: Text, entity_name: Text)
+}
+
+sql_function! {
+    build_composite_attribute_index,
+    BuildCompositeAttributeIndex,
+    (
+        subgraph_id: Text,
+        index_name: Text,
+        attribute_names: Array<Text>,
+        attribute_casts: Array<Text>,
+        entity_name: Text
+    )
+}
+
 sql_function! {
     pg_notify,
     PGNotify,
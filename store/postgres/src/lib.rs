@@ -13,14 +13,19 @@ extern crate serde;
 extern crate uuid;
 
 mod chain_head_listener;
+mod copy;
 pub mod db_schema;
+mod entity_change_outbox;
 mod filter;
 pub mod functions;
 pub mod jsonb;
 pub mod models;
 mod notification_listener;
+mod prepared;
 pub mod store;
 mod store_events;
 
 pub use self::chain_head_listener::ChainHeadUpdateListener;
-pub use self::store::{Store, StoreConfig};
+pub use self::copy::copy_deployment;
+pub use self::entity_change_outbox::EntityChangeOutbox;
+pub use self::store::{postgres_version, Store, StoreConfig};
@@ -0,0 +1,138 @@
+use diesel::dsl::sql;
+use diesel::pg::{Pg, PgConnection, PgQueryBuilder};
+use diesel::query_builder::{AstPass, Query, QueryBuilder, QueryFragment, QueryId};
+use diesel::query_dsl::RunQueryDsl;
+use diesel::r2d2::{self, CustomizeConnection};
+use diesel::result::QueryResult;
+use diesel::select;
+use diesel::sql_types::Text;
+use graph::prelude::Error;
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+/// How many different query shapes connections keep prepared statements
+/// for. Diesel never drops prepared statements, so this bounds how many
+/// each connection accumulates.
+const MAX_PREPARED_SHAPES: usize = 1000;
+
+/// The first Postgres version, 12, that has `plan_cache_mode`.
+const PLAN_CACHE_MODE_VERSION: u32 = 120_000;
+
+/// Whether the server that `conn` is connected to can plan each execution
+/// of a prepared statement for the values bound to it. Otherwise, Postgres
+/// switches to a generic plan for all values after five executions, and
+/// generic plans can't use the attribute indexes: those are partial
+/// indexes for one subgraph and entity type, on expressions that contain
+/// the attribute name, all of which are bound values in entity queries.
+pub fn supports_custom_plans(conn: &PgConnection) -> Result<bool, Error> {
+    let version: String =
+        select(sql::<Text>("current_setting('server_version_num')")).get_result(conn)?;
+    Ok(version.parse::<u32>()? >= PLAN_CACHE_MODE_VERSION)
+}
+
+/// Makes the connections of a pool plan every execution of a prepared
+/// statement for its values, as if the statement wasn't prepared. That
+/// still saves parsing and analyzing the statement.
+#[derive(Debug)]
+pub struct ForceCustomPlans;
+
+impl CustomizeConnection<PgConnection, r2d2::Error> for ForceCustomPlans {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), r2d2::Error> {
+        diesel::sql_query("set plan_cache_mode = force_custom_plan")
+            .execute(conn)
+            .map(|_| ())
+            .map_err(r2d2::Error::QueryError)
+    }
+}
+
+/// The shapes of the queries that connections keep prepared statements
+/// for. The shape of a query is its SQL text, which doesn't contain the
+/// values that are bound to it.
+#[derive(Default)]
+pub struct PreparedShapes(RwLock<HashSet<String>>);
+
+impl PreparedShapes {
+    /// Whether statements for the query `sql` are kept; the first
+    /// `MAX_PREPARED_SHAPES` shapes that are asked about are.
+    fn admit(&self, sql: String) -> bool {
+        if self.0.read().unwrap().contains(&sql) {
+            return true;
+        }
+        let mut shapes = self.0.write().unwrap();
+        if shapes.len() < MAX_PREPARED_SHAPES {
+            shapes.insert(sql);
+            true
+        } else {
+            shapes.contains(&sql)
+        }
+    }
+}
+
+/// A query that Diesel keeps a prepared statement for, even though it is
+/// built from SQL literals. Diesel doesn't cache statements for those
+/// since it can't tell whether the literals change between queries, but
+/// the statement cache is keyed by the SQL text of the query, so queries
+/// that only bind values, like those built from an `EntityFilter`, reuse
+/// the statement of the first query with the same filter shape.
+pub struct Prepared<'a, Q> {
+    query: Q,
+    shapes: &'a PreparedShapes,
+}
+
+impl<'a, Q> Prepared<'a, Q> {
+    pub fn new(query: Q, shapes: &'a PreparedShapes) -> Self {
+        Prepared { query, shapes }
+    }
+}
+
+impl<'a, Q: Query> Query for Prepared<'a, Q> {
+    type SqlType = Q::SqlType;
+}
+
+impl<'a, Q> QueryId for Prepared<'a, Q> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Q: QueryFragment<Pg>> QueryFragment<Pg> for Prepared<'a, Q> {
+    fn walk_ast(&self, out: AstPass<Pg>) -> QueryResult<()> {
+        self.query.walk_ast(out)
+    }
+
+    fn is_safe_to_cache_prepared(&self) -> QueryResult<bool> {
+        let mut sql = PgQueryBuilder::default();
+        self.query.to_sql(&mut sql)?;
+        Ok(self.shapes.admit(sql.finish()))
+    }
+}
+
+impl<'a, Q, Conn> RunQueryDsl<Conn> for Prepared<'a, Q> {}
+
+/// A query that Diesel never keeps a prepared statement for, since the
+/// relations its SQL refers to are shadowed by temporary ones while it
+/// runs; a statement that was prepared for the same SQL text before would
+/// still read the original relations.
+pub struct Unprepared<Q>(pub Q);
+
+impl<Q: Query> Query for Unprepared<Q> {
+    type SqlType = Q::SqlType;
+}
+
+impl<Q> QueryId for Unprepared<Q> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<Q: QueryFragment<Pg>> QueryFragment<Pg> for Unprepared<Q> {
+    fn walk_ast(&self, out: AstPass<Pg>) -> QueryResult<()> {
+        self.0.walk_ast(out)
+    }
+
+    fn is_safe_to_cache_prepared(&self) -> QueryResult<bool> {
+        Ok(false)
+    }
+}
+
+impl<Q, Conn> RunQueryDsl<Conn> for Unprepared<Q> {}
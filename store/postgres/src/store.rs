@@ -4,32 +4,41 @@ use diesel::dsl::{any, sql};
 use diesel::pg::Pg;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
-use diesel::r2d2::{self, ConnectionManager, Pool};
-use diesel::sql_types::Text;
+use diesel::r2d2::{self, ConnectionManager, Pool, PooledConnection};
+use diesel::sql_types::{Array, BigInt, Bool, Integer, Jsonb, Nullable, Text};
 use diesel::{delete, insert_into, select, update};
 use futures::sync::mpsc::{channel, Sender};
 use lru_time_cache::LruCache;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::iter;
 use std::sync::{Mutex, RwLock};
 use std::time::{Duration, Instant};
 use uuid::Uuid;
 
 use crate::notification_listener::JsonNotification;
+use graph::components::entity_export::ENTITY_CHANGE_OUTBOX_SIZE;
 use graph::components::store::Store as StoreTrait;
+use graph::data::query::store_timed;
 use graph::data::subgraph::schema::*;
 use graph::prelude::*;
 use graph::serde_json;
-use graph::web3::types::H256;
+use graph::util::ethereum::contract_call_id;
+use graph::util::tracing;
+use graph::web3::types::{Address, Log, H256, U256};
 use graph::{tokio, tokio::timer::Interval};
 use graph_graphql::prelude::api_schema;
 
 use crate::chain_head_listener::ChainHeadUpdateListener;
+use crate::entity_change_outbox::{self, EntityChangeOutbox};
 use crate::functions::{
-    attempt_chain_head_update, build_attribute_index, lookup_ancestor_block, revert_block,
-    set_config,
+    attempt_chain_head_update, build_attribute_index, build_composite_attribute_index,
+    build_lower_attribute_index, lookup_ancestor_block, revert_block, set_config,
 };
 use crate::jsonb::PgJsonbExpressionMethods as _;
-use crate::store_events::{get_revert_event, StoreEventListener};
+use crate::prepared::{
+    supports_custom_plans, ForceCustomPlans, Prepared, PreparedShapes, Unprepared,
+};
+use crate::store_events::{get_revert_event, get_revert_event_for_source, StoreEventListener};
 
 embed_migrations!("./migrations");
 
@@ -74,6 +83,32 @@ pub struct Store {
     genesis_block_ptr: EthereumBlockPointer,
     conn: Pool<ConnectionManager<PgConnection>>,
     schema_cache: Mutex<LruCache<SubgraphDeploymentId, Arc<Schema>>>,
+    network_cache: Mutex<LruCache<SubgraphDeploymentId, String>>,
+    /// Only kept if prepared statements are planned for their values.
+    prepared_shapes: Option<PreparedShapes>,
+    change_exporter: Option<Arc<EntityChangeExporter>>,
+}
+
+/// Connects to the database at `postgres_url`, without changing it, and
+/// returns the version of the Postgres server.
+pub fn postgres_version(postgres_url: &str) -> Result<String, Error> {
+    let conn = PgConnection::establish(postgres_url)?;
+    Ok(select(sql::<Text>("version()")).get_result(&conn)?)
+}
+
+/// The cast that makes attribute values of `value_type` sort correctly,
+/// both when ordering query results and in composite indexes, which only
+/// help queries whose expressions match. Lists can't be ordered by.
+fn order_cast(value_type: &ValueType) -> Option<&'static str> {
+    match value_type {
+        ValueType::BigInt | ValueType::BigDecimal => Some("::numeric"),
+        ValueType::Boolean => Some("::boolean"),
+        ValueType::Bytes => Some(""),
+        ValueType::ID => Some(""),
+        ValueType::Int => Some("::bigint"),
+        ValueType::String => Some(""),
+        ValueType::List => None,
+    }
 }
 
 impl Store {
@@ -94,13 +129,25 @@ impl Store {
         }
         let error_handler = Box::new(ErrorHandler(logger.clone()));
 
+        // Entity queries only keep prepared statements if Postgres can plan
+        // every execution for its values
+        let custom_plans = PgConnection::establish(&config.postgres_url)
+            .map_err(Error::from)
+            .and_then(|conn| supports_custom_plans(&conn))
+            .unwrap_or(false);
+
         // Connect to Postgres
         let conn_manager = ConnectionManager::new(config.postgres_url.as_str());
-        let pool = Pool::builder()
-            .error_handler(error_handler)
-            .build(conn_manager)
-            .unwrap();
-        info!(logger, "Connected to Postgres"; "url" => &config.postgres_url);
+        let mut pool_builder = Pool::builder().error_handler(error_handler);
+        if custom_plans {
+            pool_builder = pool_builder.connection_customizer(Box::new(ForceCustomPlans));
+        }
+        let pool = pool_builder.build(conn_manager).unwrap();
+        info!(
+            logger, "Connected to Postgres";
+            "url" => &config.postgres_url,
+            "prepared_statements" => custom_plans,
+        );
 
         // Create the entities table (if necessary)
         initiate_schema(&logger, &pool.get().unwrap());
@@ -121,6 +168,13 @@ impl Store {
             genesis_block_ptr: (net_identifiers.genesis_block_hash, 0u64).into(),
             conn: pool,
             schema_cache: Mutex::new(LruCache::with_capacity(100)),
+            network_cache: Mutex::new(LruCache::with_capacity(100)),
+            prepared_shapes: if custom_plans {
+                Some(PreparedShapes::default())
+            } else {
+                None
+            },
+            change_exporter: None,
         };
 
         // Add network to store and check network identifiers
@@ -137,6 +191,20 @@ impl Store {
         store
     }
 
+    /// Records every entity change that is written to the store in the
+    /// entity change outbox, and tells `exporter` to publish it. Writes
+    /// wait while the outbox is full.
+    pub fn with_entity_change_exporter(mut self, exporter: Arc<EntityChangeExporter>) -> Self {
+        self.change_exporter = Some(exporter);
+        self
+    }
+
+    /// The outbox of the entity changes this store records, for an
+    /// `EntityChangeExporter` to publish them from.
+    pub fn entity_change_outbox(&self) -> EntityChangeOutbox {
+        EntityChangeOutbox::new(self.conn.clone())
+    }
+
     fn add_network_if_missing(
         &self,
         new_net_identifiers: EthereumNetworkIdentifier,
@@ -319,90 +387,12 @@ impl Store {
         conn: &PgConnection,
         query: EntityQuery,
     ) -> Result<Vec<Entity>, QueryExecutionError> {
-        use crate::db_schema::entities::dsl::*;
-
-        // Create base boxed query; this will be added to based on the
-        // query parameters provided
-        let mut diesel_query = entities
-            .filter(entity.eq(any(query.entity_types)))
-            .filter(subgraph.eq(query.subgraph_id.to_string()))
-            .into_boxed::<Pg>();
-
-        // Add specified filter to query
-        if let Some(filter) = query.filter {
-            diesel_query = store_filter(diesel_query, filter).map_err(|e| {
-                QueryExecutionError::FilterNotSupportedError(format!("{}", e.value), e.filter)
-            })?;
-        }
-
-        // Add order by filters to query
-        if let Some((order_attribute, value_type)) = query.order_by {
-            let direction = query
-                .order_direction
-                .map(|direction| match direction {
-                    EntityOrder::Ascending => "ASC",
-                    EntityOrder::Descending => "DESC",
-                })
-                .unwrap_or("ASC");
-            let cast_type = match value_type {
-                ValueType::BigInt | ValueType::BigDecimal => "::numeric",
-                ValueType::Boolean => "::boolean",
-                ValueType::Bytes => "",
-                ValueType::ID => "",
-                ValueType::Int => "::bigint",
-                ValueType::String => "",
-                ValueType::List => {
-                    return Err(QueryExecutionError::OrderByNotSupportedForType(
-                        "List".to_string(),
-                    ));
-                }
-            };
-            diesel_query = diesel_query.order(
-                sql::<Text>("(data ->")
-                    .bind::<Text, _>(order_attribute)
-                    .sql("->> 'data')")
-                    .sql(cast_type)
-                    .sql(" ")
-                    .sql(direction)
-                    .sql(" NULLS LAST"),
-            );
-        }
-
-        // Add range filter to query
-        if let Some(limit) = query.range.first {
-            diesel_query = diesel_query.limit(limit as i64);
-        }
-        if query.range.skip > 0 {
-            diesel_query = diesel_query.offset(query.range.skip as i64);
+        match query.block {
+            Some(block_number) => self
+                .snapshot_at_block(&query.subgraph_id, block_number)?
+                .find(query),
+            None => load_entities(conn, query, self.prepared_shapes.as_ref()),
         }
-
-        // Finally add the selected columns
-        let diesel_query = diesel_query.select((data, entity));
-
-        // Record debug info in case of error
-        let diesel_query_debug_info = debug_query(&diesel_query).to_string();
-
-        // Process results; deserialize JSON data
-        diesel_query
-            .load::<(serde_json::Value, String)>(conn)
-            .map(|values| {
-                values
-                    .into_iter()
-                    .map(|(value, entity_type)| {
-                        let parse_error_msg = format!("Error parsing entity JSON: {:?}", data);
-                        let mut value =
-                            serde_json::from_value::<Entity>(value).expect(&parse_error_msg);
-                        value.set("__typename", entity_type);
-                        value
-                    })
-                    .collect()
-            })
-            .map_err(|e| {
-                QueryExecutionError::ResolveEntitiesError(format!(
-                    "{}, query = {:?}",
-                    e, diesel_query_debug_info
-                ))
-            })
     }
 
     fn check_interface_entity_uniqueness(
@@ -454,6 +444,86 @@ impl Store {
         Ok(())
     }
 
+    /// Makes sure that the values of enum fields are values of their enums,
+    /// since the store keeps them as plain strings.
+    fn check_enum_values(&self, key: &EntityKey, entity: &Entity) -> Result<(), StoreError> {
+        let schema = self.subgraph_schema(&key.subgraph_id)?;
+        match schema.invalid_enum_value(&key.entity_type, entity) {
+            Some((field, value, enum_type)) => Err(StoreError::InvalidEnumValue(
+                field,
+                key.entity_type.clone(),
+                key.entity_id.clone(),
+                value,
+                enum_type,
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds the operations that roll the timeseries entities added in
+    /// `operations` up into the entities of their aggregations, for every
+    /// interval. Entities of aggregations that already have rolled up
+    /// timeseries entities of an earlier block are updated.
+    fn roll_up_timeseries(
+        &self,
+        conn: &PgConnection,
+        subgraph_id: &SubgraphDeploymentId,
+        mut operations: Vec<EntityOperation>,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        let schema = self.subgraph_schema(subgraph_id)?;
+        let mut rolled_up = BTreeMap::new();
+
+        for operation in operations.iter() {
+            let (key, data) = match operation {
+                EntityOperation::Set { key, data } => (key, Some(data)),
+                EntityOperation::Update { key, .. } | EntityOperation::Remove { key } => {
+                    (key, None)
+                }
+                EntityOperation::AbortUnless { .. } => continue,
+            };
+            if schema.is_aggregation_type(&key.entity_type) {
+                return Err(StoreError::AggregationWrite(key.entity_type.clone()));
+            }
+
+            // Timeseries entities are immutable, so they are rolled up exactly
+            // once; changing one fails when the operations are applied
+            let data = match data {
+                Some(data) if schema.is_timeseries_type(&key.entity_type) => data,
+                _ => continue,
+            };
+
+            for aggregation in schema.aggregations_for_source(&key.entity_type) {
+                for interval in aggregation.intervals.iter() {
+                    let aggregation_key = EntityKey {
+                        subgraph_id: subgraph_id.clone(),
+                        entity_type: aggregation.entity_type.clone(),
+                        entity_id: aggregation.entity_id(*interval, data)?,
+                    };
+                    let current = match rolled_up.remove(&aggregation_key) {
+                        Some(entity) => Some(entity),
+                        None => self
+                            .get_entity(
+                                conn,
+                                &aggregation_key.subgraph_id,
+                                &aggregation_key.entity_type,
+                                &aggregation_key.entity_id,
+                            )
+                            .map_err(Error::from)?,
+                    };
+                    let entity = aggregation.roll_up(*interval, current, data)?;
+                    rolled_up.insert(aggregation_key, entity);
+                }
+            }
+        }
+
+        operations.extend(
+            rolled_up
+                .into_iter()
+                .map(|(key, data)| EntityOperation::Set { key, data }),
+        );
+        Ok(operations)
+    }
+
     /// Applies a set operation in Postgres.
     fn apply_set_operation(
         &self,
@@ -466,6 +536,13 @@ impl Store {
 
         self.check_interface_entity_uniqueness(conn, &key)?;
 
+        if self
+            .subgraph_schema(&key.subgraph_id)?
+            .is_immutable_type(&key.entity_type)
+        {
+            return self.insert_immutable_entity(conn, key, data, event_source);
+        }
+
         // Load the entity if exists
         let existing_entity = self
             .get_entity(conn, &key.subgraph_id, &key.entity_type, &key.entity_id)
@@ -477,6 +554,9 @@ impl Store {
             data,
         };
         let updated_entity = operation.apply(existing_entity)?;
+        if let Some(entity) = &updated_entity {
+            self.check_enum_values(&key, entity)?;
+        }
         let updated_json: serde_json::Value =
             serde_json::to_value(&updated_entity).map_err(|e| {
                 format_err!(
@@ -520,6 +600,155 @@ impl Store {
             })
     }
 
+    /// Inserts an entity of an immutable type. Unlike other entities, there is
+    /// no existing entity to load and merge `data` into, and an entity with the
+    /// same ID is an error rather than something to update. Within the block,
+    /// or file, that inserted it, the entity can still be set again, since
+    /// handlers often create an entity and fill in its fields later; only
+    /// timeseries, which are rolled up each time they are set, can't.
+    fn insert_immutable_entity(
+        &self,
+        conn: &PgConnection,
+        key: EntityKey,
+        data: Entity,
+        event_source: EventSource,
+    ) -> Result<(), StoreError> {
+        let mut entity = Entity::new();
+        entity.merge(data);
+        self.check_enum_values(&key, &entity)?;
+        let json: serde_json::Value = serde_json::to_value(&entity).map_err(|e| {
+            format_err!(
+                "Failed to set entity ({}, {}, {}) as setting it would break it: {}",
+                key.subgraph_id,
+                key.entity_type,
+                key.entity_id,
+                e
+            )
+        })?;
+
+        let settable_again = match event_source {
+            EventSource::None => false,
+            EventSource::EthereumBlock(_) | EventSource::File(_) => !self
+                .subgraph_schema(&key.subgraph_id)?
+                .is_timeseries_type(&key.entity_type),
+        };
+        let on_conflict = if settable_again {
+            "do update set data = excluded.data
+              where entities.event_source = excluded.event_source"
+        } else {
+            "do nothing"
+        };
+        let insert = diesel::sql_query(format!(
+            "insert into entities (id, entity, subgraph, data, event_source)
+             values ($1, $2, $3, $4, $5)
+             on conflict (id, entity, subgraph) {}",
+            on_conflict
+        ))
+        .bind::<Text, _>(&key.entity_id)
+        .bind::<Text, _>(&key.entity_type)
+        .bind::<Text, _>(key.subgraph_id.to_string())
+        .bind::<Jsonb, _>(&json)
+        .bind::<Text, _>(event_source.to_string());
+
+        // Diesel keeps prepared statements for the other entity writes,
+        // which don't contain any SQL literals
+        let rows = match &self.prepared_shapes {
+            Some(prepared_shapes) => Prepared::new(insert, prepared_shapes).execute(conn),
+            None => insert.execute(conn),
+        }
+        .map_err(|e| {
+            format_err!(
+                "Failed to set entity ({}, {}, {}): {}",
+                key.subgraph_id,
+                key.entity_type,
+                key.entity_id,
+                e
+            )
+        })?;
+
+        if rows == 0 {
+            Err(StoreError::ImmutableEntity(key.entity_type, key.entity_id))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Tells the history triggers to skip the entities of the immutable types
+    /// of `subgraph_ids` for the rest of the transaction of `conn`. These
+    /// entities are reverted by the event source they were inserted with.
+    fn skip_immutable_entity_history<'a>(
+        &self,
+        conn: &PgConnection,
+        subgraph_ids: impl IntoIterator<Item = &'a SubgraphDeploymentId>,
+    ) -> Result<(), StoreError> {
+        let mut entity_types = vec![];
+        let subgraph_ids: HashSet<_> = subgraph_ids
+            .into_iter()
+            .filter(|subgraph_id| **subgraph_id != *SUBGRAPHS_ID)
+            .collect();
+        for subgraph_id in subgraph_ids {
+            for entity_type in self.subgraph_schema(subgraph_id)?.immutable_types() {
+                entity_types.push(format!("{}/{}", subgraph_id, entity_type));
+            }
+        }
+        if entity_types.is_empty() {
+            return Ok(());
+        }
+
+        select(set_config(
+            "vars.immutable_entity_types",
+            entity_types.join(","),
+            true,
+        ))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| format_err!("Failed to skip the history of immutable entities: {}", e).into())
+    }
+
+    /// Removes the entities of the immutable types of `subgraph_id` that
+    /// the event source `source` inserted. Unlike other entities, they have
+    /// no history for `revert_block` to revert.
+    fn revert_immutable_entities(
+        &self,
+        conn: &PgConnection,
+        subgraph_id: &SubgraphDeploymentId,
+        source: &str,
+    ) -> Result<(), StoreError> {
+        use crate::db_schema::entities;
+
+        let immutable_types = self.subgraph_schema(subgraph_id)?.immutable_types();
+        if immutable_types.is_empty() {
+            return Ok(());
+        }
+        self.skip_immutable_entity_history(conn, iter::once(subgraph_id))?;
+
+        delete(
+            entities::table
+                .filter(entities::subgraph.eq(subgraph_id.to_string()))
+                .filter(entities::entity.eq(any(immutable_types)))
+                .filter(entities::event_source.eq(source)),
+        )
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| format_err!("Error reverting immutable entities: {}", e).into())
+    }
+
+    /// Fails if `key` is an entity of an immutable type, which can't be
+    /// updated or removed.
+    fn check_mutable(&self, key: &EntityKey) -> Result<(), StoreError> {
+        if self
+            .subgraph_schema(&key.subgraph_id)?
+            .is_immutable_type(&key.entity_type)
+        {
+            Err(StoreError::ImmutableEntity(
+                key.entity_type.clone(),
+                key.entity_id.clone(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
     /// Applies an update operation to an existing entity
     fn apply_update_operation(
         &self,
@@ -531,7 +760,11 @@ impl Store {
     ) -> Result<(), StoreError> {
         use crate::db_schema::entities;
 
+        self.check_mutable(&key)?;
         self.check_interface_entity_uniqueness(conn, &key)?;
+        // The values of the existing entity were checked when they were
+        // written, so only the updated fields need to be checked
+        self.check_enum_values(&key, &data)?;
 
         let json: serde_json::Value = serde_json::to_value(&data).map_err(|e| {
             format_err!(
@@ -562,7 +795,15 @@ impl Store {
                         e.filter, e.value
                     ))
                 })?;
-                query.filter(filter).execute(conn)?
+
+                // Guards bind their values like query filters, so updates
+                // with the same guard shape can reuse one prepared statement
+                match &self.prepared_shapes {
+                    Some(prepared_shapes) => {
+                        Prepared::new(query.filter(filter), prepared_shapes).execute(conn)?
+                    }
+                    None => query.filter(filter).execute(conn)?,
+                }
             }
             None => query.execute(conn)?,
         };
@@ -593,6 +834,8 @@ impl Store {
     ) -> Result<(), StoreError> {
         use crate::db_schema::entities;
 
+        self.check_mutable(&key)?;
+
         select(set_config(
             "vars.current_event_source",
             event_source.to_string(),
@@ -704,8 +947,26 @@ impl Store {
         operations: Vec<EntityOperation>,
         event_source: EventSource,
     ) -> Result<(), StoreError> {
+        if self.change_exporter.is_some() {
+            let block_ptr = match event_source {
+                EventSource::EthereumBlock(block_ptr) => Some(block_ptr),
+                EventSource::None | EventSource::File(_) => None,
+            };
+            let changes = operations
+                .iter()
+                .filter_map(|op| EntityChange::from_entity_operation(op.clone()))
+                .collect();
+            self.record_changes(conn, changes, block_ptr, false)?;
+        }
+        self.skip_immutable_entity_history(
+            conn,
+            operations.iter().filter_map(|operation| match operation {
+                EntityOperation::AbortUnless { .. } => None,
+                operation => Some(&operation.entity_key().subgraph_id),
+            }),
+        )?;
         for operation in operations.into_iter() {
-            self.apply_entity_operation(conn, operation, event_source)?;
+            self.apply_entity_operation(conn, operation, event_source.clone())?;
         }
         Ok(())
     }
@@ -716,36 +977,64 @@ impl Store {
         conn: &PgConnection,
         index: AttributeIndexDefinition,
     ) -> Result<(), SubgraphAssignmentProviderError> {
-        let (index_type, index_operator, jsonb_index) = match index.field_value_type {
-            ValueType::Boolean
-            | ValueType::BigInt
-            | ValueType::Bytes
-            | ValueType::BigDecimal
-            | ValueType::ID
-            | ValueType::Int => (String::from("btree"), String::from(""), false),
-            ValueType::String => (String::from("gin"), String::from("gin_trgm_ops"), false),
-            ValueType::List => (String::from("gin"), String::from("jsonb_path_ops"), true),
-        };
-
-        select(build_attribute_index(
-            index.subgraph_id.to_string(),
-            index.index_name.clone(),
-            index_type,
-            index_operator,
-            jsonb_index,
-            index.attribute_name.clone(),
-            index.entity_name.clone(),
-        ))
-        .execute(conn)
-        .map_err(|e| SubgraphAssignmentProviderError::Unknown(e.into()))
-        .and_then(move |row_count| match row_count {
-            1 => Ok(()),
-            _ => Err(SubgraphAssignmentProviderError::BuildIndexesError(
+        let build = if !index.composite_attributes.is_empty() {
+            let (attribute_names, attribute_casts): (Vec<_>, Vec<_>) =
+                iter::once((index.attribute_name.clone(), index.field_value_type.clone()))
+                    .chain(index.composite_attributes.iter().cloned())
+                    .map(|(name, value_type)| {
+                        (name, order_cast(&value_type).unwrap_or("").to_owned())
+                    })
+                    .unzip();
+            select(build_composite_attribute_index(
                 index.subgraph_id.to_string(),
+                index.index_name.clone(),
+                attribute_names,
+                attribute_casts,
                 index.entity_name.clone(),
+            ))
+            .execute(conn)
+        } else if index.case_insensitive {
+            select(build_lower_attribute_index(
+                index.subgraph_id.to_string(),
+                index.index_name.clone(),
                 index.attribute_name.clone(),
-            )),
-        })
+                index.entity_name.clone(),
+            ))
+            .execute(conn)
+        } else {
+            let (index_type, index_operator, jsonb_index) = match index.field_value_type {
+                ValueType::Boolean
+                | ValueType::BigInt
+                | ValueType::Bytes
+                | ValueType::BigDecimal
+                | ValueType::ID
+                | ValueType::Int => (String::from("btree"), String::from(""), false),
+                ValueType::String => (String::from("gin"), String::from("gin_trgm_ops"), false),
+                ValueType::List => (String::from("gin"), String::from("jsonb_path_ops"), true),
+            };
+
+            select(build_attribute_index(
+                index.subgraph_id.to_string(),
+                index.index_name.clone(),
+                index_type,
+                index_operator,
+                jsonb_index,
+                index.attribute_name.clone(),
+                index.entity_name.clone(),
+            ))
+            .execute(conn)
+        };
+
+        build
+            .map_err(|e| SubgraphAssignmentProviderError::Unknown(e.into()))
+            .and_then(move |row_count| match row_count {
+                1 => Ok(()),
+                _ => Err(SubgraphAssignmentProviderError::BuildIndexesError(
+                    index.subgraph_id.to_string(),
+                    index.entity_name.clone(),
+                    index.attribute_name.clone(),
+                )),
+            })
     }
 
     /// Build a set of indexes on the entities table
@@ -779,92 +1068,1066 @@ impl Store {
         JsonNotification::send("store_events", &v, conn)
     }
 
+    /// Emits the store event for reverting `block_ptr_from` and returns
+    /// its changes.
     fn emit_revert_event(
         &self,
         conn: &PgConnection,
         subgraph_id: &SubgraphDeploymentId,
         block_ptr_from: &EthereumBlockPointer,
         block_ptr_to: EthereumBlockPointer,
-    ) -> Result<(), StoreError> {
-        let event = get_revert_event(conn, subgraph_id, block_ptr_from, block_ptr_to)?;
+    ) -> Result<Vec<EntityChange>, StoreError> {
+        let immutable_types = self.subgraph_schema(subgraph_id)?.immutable_types();
+        let event = get_revert_event(
+            conn,
+            subgraph_id,
+            &immutable_types,
+            block_ptr_from,
+            block_ptr_to,
+        )?;
 
         trace!(self.logger, "Emit store event for revert";
                 "tag" => event.tag,
                 "changes" => event.changes.len());
 
-        let v = serde_json::to_value(event)?;
-        JsonNotification::send("store_events", &v, conn)
+        let v = serde_json::to_value(&event)?;
+        JsonNotification::send("store_events", &v, conn)?;
+        Ok(event.changes.into_iter().collect())
     }
-}
 
-impl StoreTrait for Store {
-    fn block_ptr(&self, subgraph_id: SubgraphDeploymentId) -> Result<EthereumBlockPointer, Error> {
-        let subgraph_entity = self
-            .get(SubgraphDeploymentEntity::key(subgraph_id.clone()))
-            .map_err(|e| format_err!("error reading subgraph entity: {}", e))?
-            .ok_or_else(|| {
-                format_err!(
-                    "could not read block ptr for non-existent subgraph {}",
-                    subgraph_id
+    /// Records the changes of the entities of deployments in the entity
+    /// change outbox, if changes are exported, so that they are published
+    /// once the transaction of `conn` commits. Changes of metadata are not
+    /// exported.
+    fn record_changes(
+        &self,
+        conn: &PgConnection,
+        changes: Vec<EntityChange>,
+        block_ptr: Option<EthereumBlockPointer>,
+        revert: bool,
+    ) -> Result<(), Error> {
+        if self.change_exporter.is_none() {
+            return Ok(());
+        }
+        let changes: Vec<_> = changes
+            .into_iter()
+            .filter(|change| change.subgraph_id != *SUBGRAPHS_ID)
+            .map(|change| CommittedEntityChange::new(change, block_ptr, revert))
+            .collect();
+        entity_change_outbox::record(conn, &changes)
+    }
+
+    /// Reverts the blocks in `block_ptrs` except the last one, which is the
+    /// block the subgraph block pointer is moved to. Every pointer must be
+    /// the parent of the one before it.
+    fn revert_blocks_with_conn(
+        &self,
+        conn: &PgConnection,
+        subgraph_id: &SubgraphDeploymentId,
+        block_ptrs: &[EthereumBlockPointer],
+    ) -> Result<(), StoreError> {
+        let block_ptr_from = block_ptrs[0];
+        let block_ptr_to = block_ptrs[block_ptrs.len() - 1];
+        let mut ops = SubgraphDeploymentEntity::update_ethereum_block_pointer_operations(
+            subgraph_id,
+            block_ptr_from,
+            block_ptr_to,
+        );
+
+        // Metadata has no history, so the data sources that mappings
+        // created in the reverted blocks are removed explicitly
+        for block_ptr in &block_ptrs[..block_ptrs.len() - 1] {
+            let data_sources = self
+                .execute_query(
+                    conn,
+                    DynamicEthereumContractDataSourceEntity::query_for_block(
+                        subgraph_id,
+                        block_ptr.number,
+                    ),
                 )
-            })?;
+                .map_err(|e| format_err!("Error reverting data sources: {}", e))?;
+            for data_source in data_sources {
+                ops.push(EntityOperation::Remove {
+                    key: DynamicEthereumContractDataSourceEntity::key(data_source.id()?),
+                });
+            }
+        }
 
-        let hash = subgraph_entity
-            .get("latestEthereumBlockHash")
-            .ok_or_else(|| format_err!("SubgraphDeployment is missing latestEthereumBlockHash"))?
-            .to_owned()
-            .as_string()
-            .ok_or_else(|| {
-                format_err!("SubgraphDeployment has wrong type in latestEthereumBlockHash")
-            })?
-            .parse::<H256>()
-            .map_err(|e| format_err!("latestEthereumBlockHash: {}", e))?;
+        self.emit_store_events(conn, &ops)?;
+        self.apply_entity_operations_with_conn(conn, ops, EventSource::None)?;
 
-        let number = subgraph_entity
-            .get("latestEthereumBlockNumber")
-            .ok_or_else(|| format_err!("SubgraphDeployment is missing latestEthereumBlockNumber"))?
-            .to_owned()
-            .as_bigint()
-            .ok_or_else(|| {
-                format_err!("SubgraphDeployment has wrong type in latestEthereumBlockNumber")
-            })?
-            .to_u64();
+        for pair in block_ptrs.windows(2) {
+            let (block_ptr, parent_ptr) = (pair[0], pair[1]);
+            let changes = self.emit_revert_event(conn, subgraph_id, &block_ptr, parent_ptr)?;
+            self.record_changes(conn, changes, Some(parent_ptr), true)?;
 
-        Ok(EthereumBlockPointer { hash, number })
+            select(revert_block(&block_ptr.hash_hex(), subgraph_id.to_string()))
+                .execute(conn)
+                .map_err(|e| StoreError::from(format_err!("Error reverting block: {}", e)))?;
+            self.revert_immutable_entities(conn, subgraph_id, &block_ptr.hash_hex())?;
+        }
+        Ok(())
     }
 
-    fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
-        let conn = self
-            .conn
-            .get()
-            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        self.get_entity(&*conn, &key.subgraph_id, &key.entity_type, &key.entity_id)
+    /// Waits until the entity change outbox has room for more changes, if
+    /// changes are exported and `operations` change entities of
+    /// deployments.
+    fn wait_for_change_outbox(
+        &self,
+        conn: &PgConnection,
+        operations: &[EntityOperation],
+    ) -> Result<(), Error> {
+        if self.change_exporter.is_none()
+            || operations
+                .iter()
+                .all(|op| op.entity_key().subgraph_id == *SUBGRAPHS_ID)
+        {
+            return Ok(());
+        }
+        let mut warned = false;
+        while entity_change_outbox::len(conn)? >= *ENTITY_CHANGE_OUTBOX_SIZE {
+            if !warned {
+                warn!(
+                    self.logger,
+                    "Entity change outbox is full, waiting for changes to be published";
+                    "size" => *ENTITY_CHANGE_OUTBOX_SIZE,
+                );
+                warned = true;
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        }
+        Ok(())
     }
 
-    fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
-        let conn = self
-            .conn
-            .get()
-            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        self.execute_query(&conn, query)
+    /// Tells the exporter, if there is one, that changes were committed.
+    fn notify_change_exporter(&self) {
+        if let Some(exporter) = &self.change_exporter {
+            exporter.notify();
+        }
     }
 
-    fn find_one(&self, mut query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
-        query.range = EntityRange::first(1);
-
-        let conn = self
-            .conn
-            .get()
-            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+    /// Returns the Ethereum network that a deployment indexes, as declared
+    /// by the first data source of its manifest. The blocks of the entity
+    /// history of the deployment are looked up in that network. The
+    /// subgraph of subgraphs, and deployments that don't name a network,
+    /// belong to the network of this store.
+    fn deployment_network(&self, subgraph_id: &SubgraphDeploymentId) -> Result<String, Error> {
+        if let Some(network) = self.network_cache.lock().unwrap().get(subgraph_id) {
+            return Ok(network.clone());
+        }
 
-        let mut results = self.execute_query(&conn, query)?;
-        match results.len() {
-            0 | 1 => Ok(results.pop()),
-            n => panic!("find_one query found {} results", n),
+        let mut network = None;
+        if *subgraph_id != *SUBGRAPHS_ID {
+            let manifest = self.get(SubgraphManifestEntity::key(SubgraphManifestEntity::id(
+                subgraph_id,
+            )))?;
+            if let Some(Value::List(data_sources)) =
+                manifest.as_ref().and_then(|m| m.get("dataSources"))
+            {
+                if let Some(Value::String(data_source_id)) = data_sources.first() {
+                    let data_source = self.get(EntityKey {
+                        subgraph_id: SUBGRAPHS_ID.clone(),
+                        entity_type: "EthereumContractDataSource".to_owned(),
+                        entity_id: data_source_id.to_owned(),
+                    })?;
+                    if let Some(Value::String(name)) =
+                        data_source.as_ref().and_then(|ds| ds.get("network"))
+                    {
+                        network = Some(name.to_owned());
+                    }
+                }
+            }
         }
-    }
 
-    fn set_block_ptr_with_no_changes(
+        let network = network.unwrap_or_else(|| self.network_name.clone());
+        self.network_cache
+            .lock()
+            .unwrap()
+            .insert(subgraph_id.clone(), network.clone());
+        Ok(network)
+    }
+
+    /// Returns the data that entities of a deployment had at block
+    /// `block_number`, for every entity that changed after that block, or
+    /// `None` for entities that did not exist at that block. Applied to the
+    /// current entities, this gives the state of the deployment at that
+    /// block.
+    pub fn entities_changed_after_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_number: u64,
+    ) -> Result<BTreeMap<EntityKey, Option<Entity>>, Error> {
+        #[derive(QueryableByName)]
+        struct EntityBefore {
+            #[sql_type = "Text"]
+            entity: String,
+            #[sql_type = "Text"]
+            entity_id: String,
+            #[sql_type = "Nullable<Jsonb>"]
+            data_before: Option<serde_json::Value>,
+        }
+
+        let immutable_types = self.subgraph_schema(subgraph_id)?.immutable_types();
+        let rows = diesel::sql_query(ENTITIES_BEFORE_BLOCK)
+            .bind::<Text, _>(subgraph_id.to_string())
+            .bind::<Text, _>(self.deployment_network(subgraph_id)?)
+            .bind::<BigInt, _>(block_number as i64)
+            .bind::<Array<Text>, _>(&immutable_types)
+            .load::<EntityBefore>(&*self.conn.get()?)?;
+
+        let mut entities = BTreeMap::new();
+        for row in rows {
+            let key = EntityKey {
+                subgraph_id: subgraph_id.clone(),
+                entity_type: row.entity,
+                entity_id: row.entity_id,
+            };
+            if entities.contains_key(&key) {
+                continue;
+            }
+            let data = match row.data_before {
+                Some(json) => {
+                    let mut entity = serde_json::from_value::<Entity>(json)?;
+                    entity.set("__typename", key.entity_type.as_str());
+                    Some(entity)
+                }
+                None => None,
+            };
+            entities.insert(key, data);
+        }
+        Ok(entities)
+    }
+
+    /// Reverts the entity operations of the handler of the file data source
+    /// file `link` of a deployment and forgets that the file was handled, so
+    /// that the handler runs for it again when the deployment restarts.
+    pub fn revert_file_operations(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        link: &str,
+    ) -> Result<(), StoreError> {
+        let source = EventSource::File(link.to_owned()).to_string();
+        let conn = self.conn.get().map_err(Error::from)?;
+        conn.transaction(|| {
+            let ops = vec![EntityOperation::Remove {
+                key: SubgraphDeploymentFileEntity::key(SubgraphDeploymentFileEntity::id(
+                    subgraph_id,
+                    link,
+                )),
+            }];
+            self.emit_store_events(&conn, &ops)?;
+            self.apply_entity_operations_with_conn(&conn, ops, EventSource::None)?;
+
+            let immutable_types = self.subgraph_schema(subgraph_id)?.immutable_types();
+            let event = get_revert_event_for_source(&conn, subgraph_id, &immutable_types, &source)?;
+            JsonNotification::send("store_events", &serde_json::to_value(&event)?, &conn)?;
+            self.record_changes(&conn, event.changes.into_iter().collect(), None, true)?;
+
+            select(revert_block(&source, subgraph_id.to_string()))
+                .execute(&*conn)
+                .map_err(|e| StoreError::from(format_err!("Error reverting file: {}", e)))?;
+            self.revert_immutable_entities(&conn, subgraph_id, &source)
+        })?;
+        self.notify_change_exporter();
+        Ok(())
+    }
+
+    /// Returns the number of entities of each type of a deployment.
+    pub fn entity_counts(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<(String, u64)>, Error> {
+        #[derive(QueryableByName)]
+        struct EntityCount {
+            #[sql_type = "Text"]
+            entity: String,
+            #[sql_type = "BigInt"]
+            count: i64,
+        }
+
+        Ok(diesel::sql_query(
+            "select entity, count(*) as count
+               from entities
+              where subgraph = $1
+              group by entity
+              order by entity",
+        )
+        .bind::<Text, _>(subgraph_id.to_string())
+        .load::<EntityCount>(&*self.conn.get()?)?
+        .into_iter()
+        .map(|row| (row.entity, row.count as u64))
+        .collect())
+    }
+
+    /// Returns the number of entity changes that are kept for a deployment
+    /// to revert blocks.
+    pub fn entity_history_count(&self, subgraph_id: &SubgraphDeploymentId) -> Result<u64, Error> {
+        use crate::db_schema::entity_history::dsl::*;
+
+        let count: i64 = entity_history
+            .filter(subgraph.eq(subgraph_id.to_string()))
+            .count()
+            .get_result(&*self.conn.get()?)?;
+        Ok(count as u64)
+    }
+
+    /// Removes the entity changes that a deployment made in blocks before
+    /// `block_number` from its entity history. The history is only needed
+    /// to revert blocks, to export entities at a block and to rewind the
+    /// deployment, none of which can go back before `block_number`
+    /// afterwards. Changes from file data sources are kept. Returns the
+    /// number of entity changes that were removed.
+    pub fn prune_entity_history(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_number: u64,
+    ) -> Result<u64, Error> {
+        // The source of an event is the hash of its block, formatted like
+        // the hashes in `ethereum_blocks`
+        let rows = diesel::sql_query(
+            "delete from entity_history h
+              using event_meta_data m, ethereum_blocks b
+              where h.event_id = m.id
+                and m.source = b.hash
+                and h.subgraph = $1
+                and b.network_name = $2
+                and b.number < $3",
+        )
+        .bind::<Text, _>(subgraph_id.to_string())
+        .bind::<Text, _>(self.deployment_network(subgraph_id)?)
+        .bind::<BigInt, _>(block_number as i64)
+        .execute(&*self.conn.get()?)?;
+        Ok(rows as u64)
+    }
+
+    /// Records the deployments that are currently unused and forgets the
+    /// recorded deployments that are in use again.
+    pub fn record_unused_deployments(&self) -> Result<(), Error> {
+        let conn = self.conn.get()?;
+        conn.transaction(|| {
+            diesel::sql_query(format!(
+                "delete from unused_deployments where deployment not in ({})",
+                UNUSED_DEPLOYMENTS
+            ))
+            .execute(&*conn)?;
+            diesel::sql_query(format!(
+                "insert into unused_deployments (deployment, entity_count)
+                 select u.id, (select count(*) from entities e where e.subgraph = u.id)
+                   from ({}) u
+                 on conflict (deployment) do nothing",
+                UNUSED_DEPLOYMENTS
+            ))
+            .execute(&*conn)?;
+            Ok(())
+        })
+    }
+
+    /// Returns the deployments that were recorded as unused, with the
+    /// number of entities they had when they were recorded.
+    pub fn unused_deployments(&self) -> Result<Vec<(SubgraphDeploymentId, u64)>, Error> {
+        use crate::db_schema::unused_deployments::dsl::*;
+
+        unused_deployments
+            .select((deployment, entity_count))
+            .order(recorded_at.asc())
+            .load::<(String, i64)>(&*self.conn.get()?)?
+            .into_iter()
+            .map(|(id, count)| {
+                SubgraphDeploymentId::new(id.clone())
+                    .map(|id| (id, count as u64))
+                    .map_err(|()| {
+                        format_err!("invalid deployment ID in unused_deployments: {}", id)
+                    })
+            })
+            .collect()
+    }
+
+    /// Removes a deployment that was recorded as unused, with its entities,
+    /// their history, attribute indexes and metadata. Deployments that have
+    /// been used again since they were recorded are kept and no longer
+    /// recorded. Returns whether the deployment was removed.
+    pub fn remove_unused_deployment(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<bool, Error> {
+        use crate::db_schema::entities::dsl as e;
+        use crate::db_schema::entity_history::dsl as h;
+        use crate::db_schema::unused_deployments::dsl as u;
+
+        #[derive(QueryableByName)]
+        struct Unused {
+            #[sql_type = "Bool"]
+            unused: bool,
+        }
+        #[derive(QueryableByName)]
+        struct Index {
+            #[sql_type = "Text"]
+            indexname: String,
+        }
+
+        if *subgraph_id == *SUBGRAPHS_ID {
+            bail!("the subgraph of subgraphs cannot be removed");
+        }
+
+        let id = subgraph_id.to_string();
+        let conn = self.conn.get()?;
+        let removed = conn.transaction(|| {
+            let recorded: i64 = u::unused_deployments
+                .filter(u::deployment.eq(&id))
+                .count()
+                .get_result(&*conn)?;
+            if recorded == 0 {
+                bail!("deployment `{}` has not been recorded as unused", id);
+            }
+
+            let unused = diesel::sql_query(format!(
+                "select exists ({} and d.id = $1) as unused",
+                UNUSED_DEPLOYMENTS
+            ))
+            .bind::<Text, _>(&id)
+            .get_result::<Unused>(&*conn)?
+            .unused;
+            delete(u::unused_deployments.filter(u::deployment.eq(&id))).execute(&*conn)?;
+            if !unused {
+                return Ok(false);
+            }
+
+            // Attribute indexes are named after the deployment, see
+            // `attribute_index_definitions`
+            let indexes = diesel::sql_query(
+                "select indexname::text
+                   from pg_indexes
+                  where tablename = 'entities'
+                    and indexname like lower($1) || '\\_%\\_idx'",
+            )
+            .bind::<Text, _>(&id)
+            .load::<Index>(&*conn)?;
+            for index in indexes {
+                diesel::sql_query(format!("drop index if exists \"{}\"", index.indexname))
+                    .execute(&*conn)?;
+            }
+
+            // Mirrors of the deployment learn that its entities are gone
+            if self.change_exporter.is_some() {
+                diesel::sql_query(
+                    "insert into entity_change_outbox (change)
+                     select jsonb_build_object(
+                              'deployment', subgraph, 'blockHash', null,
+                              'blockNumber', null, 'entityType', entity,
+                              'entityId', id, 'operation', 'removed',
+                              'revert', false)
+                       from entities
+                      where subgraph = $1
+                      order by entity, id",
+                )
+                .bind::<Text, _>(&id)
+                .execute(&*conn)?;
+            }
+
+            // Removing the entities adds them to the history, so the history
+            // is removed afterwards
+            delete(e::entities.filter(e::subgraph.eq(&id))).execute(&*conn)?;
+            delete(h::entity_history.filter(h::subgraph.eq(&id))).execute(&*conn)?;
+
+            // The deployment entity and the manifest, with everything the
+            // manifest refers to, whose IDs all start with the manifest ID
+            let manifest_id = SubgraphManifestEntity::id(subgraph_id);
+            delete(
+                e::entities
+                    .filter(e::subgraph.eq(SUBGRAPHS_ID.to_string()))
+                    .filter(
+                        e::entity
+                            .eq(SubgraphDeploymentEntity::TYPENAME)
+                            .and(e::id.eq(&id))
+                            .or(e::id.eq(&manifest_id))
+                            .or(e::id.like(format!("{}-%", manifest_id))),
+                    ),
+            )
+            .execute(&*conn)?;
+            Ok(true)
+        })?;
+        self.notify_change_exporter();
+        Ok(removed)
+    }
+
+    /// Adds a job for `deployment` to the background job queue. Returns the
+    /// ID of the job.
+    pub fn enqueue_job(
+        &self,
+        kind: &str,
+        deployment: &SubgraphDeploymentId,
+        args: serde_json::Value,
+    ) -> Result<i32, Error> {
+        #[derive(QueryableByName)]
+        struct Id {
+            #[sql_type = "Integer"]
+            id: i32,
+        }
+
+        Ok(diesel::sql_query(
+            "insert into background_jobs (kind, deployment, args)
+             values ($1, $2, $3)
+             returning id",
+        )
+        .bind::<Text, _>(kind)
+        .bind::<Text, _>(deployment.to_string())
+        .bind::<Jsonb, _>(args)
+        .get_result::<Id>(&*self.conn.get()?)?
+        .id)
+    }
+
+    /// Takes the next job in the queue for `node_id` to run and leases it
+    /// to the node for `lease`. The jobs that the node was running when it
+    /// stopped, and the jobs whose lease has expired because the node that
+    /// ran them went away, come first, so that they are resumed before new
+    /// jobs are started.
+    pub fn claim_job(
+        &self,
+        node_id: &NodeId,
+        lease: Duration,
+    ) -> Result<Option<BackgroundJob>, Error> {
+        diesel::sql_query(format!(
+            "update background_jobs
+                set status = 'running', node_id = $1, updated_at = now(),
+                    lease_expires_at = now() + $2 * interval '1 second'
+              where id = (select id
+                            from background_jobs
+                           where status = 'queued'
+                              or (status = 'running'
+                                  and (node_id = $1
+                                       or lease_expires_at is null
+                                       or lease_expires_at < now()))
+                           order by status = 'running' desc, id
+                           limit 1
+                             for update skip locked)
+          returning {}",
+            BACKGROUND_JOB_COLUMNS
+        ))
+        .bind::<Text, _>(node_id.to_string())
+        .bind::<BigInt, _>(lease.as_secs() as i64)
+        .get_result::<BackgroundJobRow>(&*self.conn.get()?)
+        .optional()?
+        .map(BackgroundJobRow::into_job)
+        .transpose()
+    }
+
+    /// Extends the lease of a running job by `lease`. Returns `false` if
+    /// `node_id` no longer holds the job, because its lease expired and
+    /// another node took the job over.
+    pub fn renew_job_lease(
+        &self,
+        id: i32,
+        node_id: &NodeId,
+        lease: Duration,
+    ) -> Result<bool, Error> {
+        let rows = diesel::sql_query(
+            "update background_jobs
+                set lease_expires_at = now() + $3 * interval '1 second'
+              where id = $1 and node_id = $2 and status = 'running'",
+        )
+        .bind::<Integer, _>(id)
+        .bind::<Text, _>(node_id.to_string())
+        .bind::<BigInt, _>(lease.as_secs() as i64)
+        .execute(&*self.conn.get()?)?;
+        Ok(rows > 0)
+    }
+
+    /// Records what a running job has done so far, unless `node_id` no
+    /// longer holds the job.
+    pub fn update_job_progress(
+        &self,
+        id: i32,
+        node_id: &NodeId,
+        progress: serde_json::Value,
+    ) -> Result<(), Error> {
+        diesel::sql_query(
+            "update background_jobs
+                set progress = $3, updated_at = now()
+              where id = $1 and node_id = $2 and status = 'running'",
+        )
+        .bind::<Integer, _>(id)
+        .bind::<Text, _>(node_id.to_string())
+        .bind::<Jsonb, _>(progress)
+        .execute(&*self.conn.get()?)?;
+        Ok(())
+    }
+
+    /// Marks a job as finished, or as failed with `error`, unless `node_id`
+    /// no longer holds the job.
+    pub fn finish_job(
+        &self,
+        id: i32,
+        node_id: &NodeId,
+        error: Option<String>,
+    ) -> Result<(), Error> {
+        let status = match error {
+            Some(_) => BackgroundJobStatus::Failed,
+            None => BackgroundJobStatus::Finished,
+        };
+        diesel::sql_query(
+            "update background_jobs
+                set status = $3, error = $4, updated_at = now(), lease_expires_at = null
+              where id = $1 and node_id = $2 and status = 'running'",
+        )
+        .bind::<Integer, _>(id)
+        .bind::<Text, _>(node_id.to_string())
+        .bind::<Text, _>(status.as_str())
+        .bind::<Nullable<Text>, _>(error)
+        .execute(&*self.conn.get()?)?;
+        Ok(())
+    }
+}
+
+/// Loads the entities that match `query`. With `prepared_shapes`, a
+/// prepared statement is kept for queries of the same shape.
+fn load_entities(
+    conn: &PgConnection,
+    query: EntityQuery,
+    prepared_shapes: Option<&PreparedShapes>,
+) -> Result<Vec<Entity>, QueryExecutionError> {
+    use crate::db_schema::entities::dsl::*;
+
+    // Create base boxed query; this will be added to based on the
+    // query parameters provided
+    let mut diesel_query = entities
+        .filter(entity.eq(any(query.entity_types)))
+        .filter(subgraph.eq(query.subgraph_id.to_string()))
+        .into_boxed::<Pg>();
+
+    // Add specified filter to query
+    if let Some(filter) = query.filter {
+        diesel_query = store_filter(diesel_query, filter).map_err(|e| {
+            QueryExecutionError::FilterNotSupportedError(format!("{}", e.value), e.filter)
+        })?;
+    }
+
+    // Add order by filters to query
+    if let Some((order_attribute, value_type)) = query.order_by {
+        let direction = query
+            .order_direction
+            .map(|direction| match direction {
+                EntityOrder::Ascending => "ASC",
+                EntityOrder::Descending => "DESC",
+            })
+            .unwrap_or("ASC");
+        let cast_type = order_cast(&value_type)
+            .ok_or_else(|| QueryExecutionError::OrderByNotSupportedForType("List".to_string()))?;
+        diesel_query = match query.order_by_child {
+            // Order by the attribute of the entity that the queried
+            // entity refers to, looked up in a subquery
+            Some((child_attribute, child_types)) => diesel_query.order(
+                sql::<Text>("(select (c.data ->")
+                    .bind::<Text, _>(order_attribute)
+                    .sql("->> 'data')")
+                    .sql(cast_type)
+                    .sql(" from entities c where c.subgraph = entities.subgraph")
+                    .sql(" and c.entity = any(")
+                    .bind::<Array<Text>, _>(child_types)
+                    .sql(") and c.id = entities.data ->")
+                    .bind::<Text, _>(child_attribute)
+                    .sql("->> 'data') ")
+                    .sql(direction)
+                    .sql(" NULLS LAST"),
+            ),
+            None => diesel_query.order(
+                sql::<Text>("(data ->")
+                    .bind::<Text, _>(order_attribute)
+                    .sql("->> 'data')")
+                    .sql(cast_type)
+                    .sql(" ")
+                    .sql(direction)
+                    .sql(" NULLS LAST"),
+            ),
+        };
+    }
+
+    // Add range filter to query
+    if let Some(limit) = query.range.first {
+        diesel_query = diesel_query.limit(limit as i64);
+    }
+    if query.range.skip > 0 {
+        diesel_query = diesel_query.offset(query.range.skip as i64);
+    }
+
+    // Finally add the selected columns
+    let diesel_query = diesel_query.select((data, entity));
+
+    // Record debug info in case of error
+    let diesel_query_debug_info = debug_query(&diesel_query).to_string();
+
+    // All values are bound, so queries with the same filter shape can
+    // reuse one prepared statement
+    let values = match prepared_shapes {
+        Some(prepared_shapes) => {
+            Prepared::new(diesel_query, prepared_shapes).load::<(serde_json::Value, String)>(conn)
+        }
+        None => Unprepared(diesel_query).load::<(serde_json::Value, String)>(conn),
+    };
+    values
+        .map(|values| {
+            values
+                .into_iter()
+                .map(|(value, entity_type)| {
+                    let parse_error_msg = format!("Error parsing entity JSON: {:?}", data);
+                    let mut value =
+                        serde_json::from_value::<Entity>(value).expect(&parse_error_msg);
+                    value.set("__typename", entity_type);
+                    value
+                })
+                .collect()
+        })
+        .map_err(|e| {
+            QueryExecutionError::ResolveEntitiesError(format!(
+                "{}, query = {:?}",
+                e, diesel_query_debug_info
+            ))
+        })
+}
+
+/// The entities of a deployment as they were at a block, read in a
+/// repeatable read transaction on a connection of its own. When the
+/// snapshot is opened, the entities that changed after the block are copied
+/// from the entity history into a temporary table, and a temporary view
+/// that shadows the `entities` table replaces them with the data they had
+/// at the block; all queries of the snapshot read from that view. Dropping
+/// the snapshot rolls the transaction back, which drops the table and the
+/// view.
+struct BlockSnapshot {
+    conn: PooledConnection<ConnectionManager<PgConnection>>,
+    subgraph_id: SubgraphDeploymentId,
+    block_number: u64,
+}
+
+impl BlockSnapshot {
+    fn open(
+        conn: PooledConnection<ConnectionManager<PgConnection>>,
+        subgraph_id: SubgraphDeploymentId,
+        network_name: &str,
+        block_number: u64,
+        immutable_types: &[String],
+    ) -> Result<Self, QueryExecutionError> {
+        diesel::sql_query("begin isolation level repeatable read")
+            .execute(&*conn)
+            .map_err(|e| Self::error(block_number, e))?;
+        // From here on, dropping the snapshot ends the transaction
+        let snapshot = BlockSnapshot {
+            conn,
+            subgraph_id,
+            block_number,
+        };
+
+        diesel::sql_query(
+            "create temporary table entities_before_block (
+               subgraph varchar not null,
+               entity varchar not null,
+               entity_id varchar not null,
+               data_before jsonb
+             ) on commit drop",
+        )
+        .execute(&*snapshot.conn)
+        .and_then(|_| {
+            diesel::sql_query(format!(
+                "insert into entities_before_block {}",
+                ENTITIES_BEFORE_BLOCK
+            ))
+            .bind::<Text, _>(snapshot.subgraph_id.to_string())
+            .bind::<Text, _>(network_name)
+            .bind::<BigInt, _>(block_number as i64)
+            .bind::<Array<Text>, _>(immutable_types)
+            .execute(&*snapshot.conn)
+        })
+        .and_then(|_| {
+            // `entities` still refers to the table when the view is
+            // created; afterwards, the view shadows it
+            diesel::sql_query(
+                "create temporary view entities as
+                 select e.id, e.subgraph, e.entity, e.data, e.event_source
+                   from entities e
+                  where not exists (
+                          select 1 from entities_before_block b
+                           where b.subgraph = e.subgraph
+                             and b.entity = e.entity
+                             and b.entity_id = e.id)
+                 union all
+                 select b.entity_id, b.subgraph, b.entity, b.data_before, 'none'
+                   from entities_before_block b
+                  where b.data_before is not null",
+            )
+            .execute(&*snapshot.conn)
+        })
+        .map_err(|e| Self::error(block_number, e))?;
+        Ok(snapshot)
+    }
+
+    fn error(block_number: u64, e: diesel::result::Error) -> QueryExecutionError {
+        QueryExecutionError::ResolveEntitiesError(format!(
+            "failed to query entities at block {}: {}",
+            block_number, e
+        ))
+    }
+}
+
+impl EntitySnapshot for BlockSnapshot {
+    fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        if query.subgraph_id != self.subgraph_id {
+            return Err(QueryExecutionError::ResolveEntitiesError(format!(
+                "snapshot of subgraph {} can't query subgraph {}",
+                self.subgraph_id, query.subgraph_id
+            )));
+        }
+
+        tracing::traced("store.find", |span| {
+            tracing::tag(span, "entity", query.entity_types.join(","));
+            store_timed(|| {
+                // A failed query aborts the transaction; rolling back to the
+                // savepoint keeps the snapshot usable for the queries after it
+                diesel::sql_query("savepoint find")
+                    .execute(&*self.conn)
+                    .map_err(|e| Self::error(self.block_number, e))?;
+                let result = load_entities(&self.conn, query, None);
+                let end = match result {
+                    Ok(_) => "release savepoint find",
+                    Err(_) => "rollback to savepoint find",
+                };
+                diesel::sql_query(end)
+                    .execute(&*self.conn)
+                    .map_err(|e| Self::error(self.block_number, e))?;
+                result
+            })
+        })
+    }
+}
+
+impl Drop for BlockSnapshot {
+    fn drop(&mut self) {
+        // Nothing was written, so there's nothing to lose if this fails
+        let _ = diesel::sql_query("rollback").execute(&*self.conn);
+    }
+}
+
+/// The columns of `background_jobs` that make up a `BackgroundJobRow`.
+const BACKGROUND_JOB_COLUMNS: &str = "id, kind, deployment, args, status, progress, error, node_id,
+     extract(epoch from created_at)::bigint as created_at,
+     extract(epoch from updated_at)::bigint as updated_at";
+
+#[derive(QueryableByName)]
+struct BackgroundJobRow {
+    #[sql_type = "Integer"]
+    id: i32,
+    #[sql_type = "Text"]
+    kind: String,
+    #[sql_type = "Text"]
+    deployment: String,
+    #[sql_type = "Jsonb"]
+    args: serde_json::Value,
+    #[sql_type = "Text"]
+    status: String,
+    #[sql_type = "Jsonb"]
+    progress: serde_json::Value,
+    #[sql_type = "Nullable<Text>"]
+    error: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    node_id: Option<String>,
+    #[sql_type = "BigInt"]
+    created_at: i64,
+    #[sql_type = "BigInt"]
+    updated_at: i64,
+}
+
+impl BackgroundJobRow {
+    fn into_job(self) -> Result<BackgroundJob, Error> {
+        let id = self.id;
+        let invalid = |column: &str| format_err!("invalid {} in background job {}", column, id);
+        Ok(BackgroundJob {
+            id,
+            kind: self.kind,
+            deployment: SubgraphDeploymentId::new(self.deployment)
+                .map_err(|()| invalid("deployment"))?,
+            args: self.args,
+            status: BackgroundJobStatus::from_str(&self.status).ok_or_else(|| invalid("status"))?,
+            progress: self.progress,
+            error: self.error,
+            node_id: self
+                .node_id
+                .map(NodeId::new)
+                .transpose()
+                .map_err(|()| invalid("node ID"))?,
+            created_at: self.created_at as u64,
+            updated_at: self.updated_at as u64,
+        })
+    }
+}
+
+/// Selects the deployments that no subgraph version refers to and that are
+/// not assigned to a node.
+const UNUSED_DEPLOYMENTS: &str = "
+    select d.id
+      from entities d
+     where d.subgraph = 'subgraphs'
+       and d.entity = 'SubgraphDeployment'
+       and not exists (
+         select 1
+           from entities v
+          where v.subgraph = 'subgraphs'
+            and v.entity = 'SubgraphVersion'
+            and v.data -> 'deployment' ->> 'data' = d.id)
+       and not exists (
+         select 1
+           from entities a
+          where a.subgraph = 'subgraphs'
+            and a.entity = 'SubgraphDeploymentAssignment'
+            and a.id = d.id)";
+
+/// Selects the entities of the deployment `$1` that changed after block
+/// number `$3` of the network `$2`, with the data they had at that block.
+/// The history of an entity starts with its first change after the block;
+/// the data before that change is its data at the block. The source of an
+/// event is the hash of its block, formatted like the hashes in
+/// `ethereum_blocks`. Entities of the immutable types `$4` have no history;
+/// the ones inserted after the block did not exist at the block.
+const ENTITIES_BEFORE_BLOCK: &str = "
+    select c.subgraph, c.entity, c.entity_id, c.data_before
+      from ((select distinct on (h.entity, h.entity_id)
+                    h.subgraph, h.entity, h.entity_id, h.data_before
+               from entity_history h
+              where h.subgraph = $1
+                and h.event_id >= (
+                  select min(m.id)
+                    from entity_history hb
+                    join event_meta_data m on m.id = hb.event_id
+                    join ethereum_blocks b on m.source = b.hash
+                   where hb.subgraph = $1
+                     and b.network_name = $2
+                     and b.number > $3)
+              order by h.entity, h.entity_id, h.id)
+            union all
+            select e.subgraph, e.entity, e.id, null::jsonb
+              from entities e
+              join ethereum_blocks b on b.hash = e.event_source
+             where e.subgraph = $1
+               and e.entity = any($4)
+               and b.network_name = $2
+               and b.number > $3) c";
+
+impl StoreTrait for Store {
+    fn block_ptr(&self, subgraph_id: SubgraphDeploymentId) -> Result<EthereumBlockPointer, Error> {
+        let subgraph_entity = self
+            .get(SubgraphDeploymentEntity::key(subgraph_id.clone()))
+            .map_err(|e| format_err!("error reading subgraph entity: {}", e))?
+            .ok_or_else(|| {
+                format_err!(
+                    "could not read block ptr for non-existent subgraph {}",
+                    subgraph_id
+                )
+            })?;
+
+        let hash = subgraph_entity
+            .get("latestEthereumBlockHash")
+            .ok_or_else(|| format_err!("SubgraphDeployment is missing latestEthereumBlockHash"))?
+            .to_owned()
+            .as_string()
+            .ok_or_else(|| {
+                format_err!("SubgraphDeployment has wrong type in latestEthereumBlockHash")
+            })?
+            .parse::<H256>()
+            .map_err(|e| format_err!("latestEthereumBlockHash: {}", e))?;
+
+        let number = subgraph_entity
+            .get("latestEthereumBlockNumber")
+            .ok_or_else(|| format_err!("SubgraphDeployment is missing latestEthereumBlockNumber"))?
+            .to_owned()
+            .as_bigint()
+            .ok_or_else(|| {
+                format_err!("SubgraphDeployment has wrong type in latestEthereumBlockNumber")
+            })?
+            .to_u64();
+
+        Ok(EthereumBlockPointer { hash, number })
+    }
+
+    fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        tracing::traced("store.get", |span| {
+            tracing::tag(span, "entity", &key.entity_type);
+            // Waiting for a connection doesn't count as store time
+            let conn = self
+                .conn
+                .get()
+                .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+            store_timed(|| {
+                self.get_entity(&*conn, &key.subgraph_id, &key.entity_type, &key.entity_id)
+            })
+        })
+    }
+
+    fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        if let Some(block_number) = query.block {
+            return self
+                .snapshot_at_block(&query.subgraph_id, block_number)?
+                .find(query);
+        }
+
+        tracing::traced("store.find", |span| {
+            tracing::tag(span, "entity", query.entity_types.join(","));
+            let conn = self
+                .conn
+                .get()
+                .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+            store_timed(|| self.execute_query(&conn, query))
+        })
+    }
+
+    fn find_one(&self, mut query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
+        query.range = EntityRange::first(1);
+
+        let mut results = match query.block {
+            Some(block_number) => self
+                .snapshot_at_block(&query.subgraph_id, block_number)?
+                .find(query)?,
+            None => {
+                let conn = self
+                    .conn
+                    .get()
+                    .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+                store_timed(|| self.execute_query(&conn, query))?
+            }
+        };
+        match results.len() {
+            0 | 1 => Ok(results.pop()),
+            n => panic!("find_one query found {} results", n),
+        }
+    }
+
+    fn snapshot_at_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_number: u64,
+    ) -> Result<Box<EntitySnapshot>, QueryExecutionError> {
+        let immutable_types = self
+            .subgraph_schema(subgraph_id)
+            .map_err(|e| QueryExecutionError::ResolveEntitiesError(e.to_string()))?
+            .immutable_types();
+        let network_name = self
+            .deployment_network(subgraph_id)
+            .map_err(|e| QueryExecutionError::ResolveEntitiesError(e.to_string()))?;
+
+        // Waiting for a connection doesn't count as store time
+        let conn = self
+            .conn
+            .get()
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        let snapshot = store_timed(|| {
+            BlockSnapshot::open(
+                conn,
+                subgraph_id.clone(),
+                &network_name,
+                block_number,
+                &immutable_types,
+            )
+        })?;
+        Ok(Box::new(snapshot))
+    }
+
+    fn set_block_ptr_with_no_changes(
         &self,
         subgraph_id: SubgraphDeploymentId,
         block_ptr_from: EthereumBlockPointer,
@@ -890,9 +2153,13 @@ impl StoreTrait for Store {
             panic!("transact_block_operations must transact a single block only");
         }
 
-        // All operations should apply only to entities in this subgraph
+        // All operations should apply only to entities in this subgraph, or
+        // create the data sources of its mappings
         for op in &operations {
-            if op.entity_key().subgraph_id != subgraph_id {
+            let key = op.entity_key();
+            let is_data_source = key.subgraph_id == *SUBGRAPHS_ID
+                && key.entity_type == DynamicEthereumContractDataSourceEntity::TYPENAME;
+            if key.subgraph_id != subgraph_id && !is_data_source {
                 panic!("transact_block_operations must affect only entities in the subgraph");
             }
         }
@@ -907,7 +2174,15 @@ impl StoreTrait for Store {
         );
 
         let event_source = EventSource::EthereumBlock(block_ptr_to);
-        self.apply_entity_operations(operations, event_source)
+        let conn = self.conn.get().map_err(Error::from)?;
+        self.wait_for_change_outbox(&conn, &operations)?;
+        conn.transaction(|| {
+            let operations = self.roll_up_timeseries(&conn, &subgraph_id, operations)?;
+            self.emit_store_events(&conn, &operations)?;
+            self.apply_entity_operations_with_conn(&conn, operations, event_source)
+        })?;
+        self.notify_change_exporter();
+        Ok(())
     }
 
     fn apply_entity_operations(
@@ -916,10 +2191,13 @@ impl StoreTrait for Store {
         event_source: EventSource,
     ) -> Result<(), StoreError> {
         let conn = self.conn.get().map_err(Error::from)?;
+        self.wait_for_change_outbox(&conn, &operations)?;
         conn.transaction(|| {
             self.emit_store_events(&conn, &operations)?;
             self.apply_entity_operations_with_conn(&conn, operations, event_source)
-        })
+        })?;
+        self.notify_change_exporter();
+        Ok(())
     }
 
     fn build_entity_attribute_indexes(
@@ -943,24 +2221,36 @@ impl StoreTrait for Store {
 
         let conn = self.conn.get().map_err(Error::from)?;
         conn.transaction(|| {
-            let ops = SubgraphDeploymentEntity::update_ethereum_block_pointer_operations(
-                &subgraph_id,
-                block_ptr_from,
-                block_ptr_to,
-            );
-            self.emit_store_events(&conn, &ops)?;
-            self.apply_entity_operations_with_conn(&conn, ops, EventSource::None)?;
+            self.revert_blocks_with_conn(&conn, &subgraph_id, &[block_ptr_from, block_ptr_to])
+        })?;
+        self.notify_change_exporter();
+        Ok(())
+    }
 
-            self.emit_revert_event(&conn, &subgraph_id, &block_ptr_from, block_ptr_to)?;
+    fn revert_block_range(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        block_ptrs: Vec<EthereumBlockPointer>,
+        operations: Vec<EntityOperation>,
+    ) -> Result<(), StoreError> {
+        // Sanity check on block numbers
+        if block_ptrs.len() < 2
+            || block_ptrs
+                .windows(2)
+                .any(|pair| pair[0].number != pair[1].number + 1)
+        {
+            panic!("revert_block_range must revert a contiguous range of blocks");
+        }
 
-            select(revert_block(
-                &block_ptr_from.hash_hex(),
-                subgraph_id.to_string(),
-            ))
-            .execute(&*conn)
-            .map(|_| ())
-            .map_err(|e| format_err!("Error reverting block: {}", e).into())
-        })
+        let conn = self.conn.get().map_err(Error::from)?;
+        self.wait_for_change_outbox(&conn, &operations)?;
+        conn.transaction(|| {
+            self.revert_blocks_with_conn(&conn, &subgraph_id, &block_ptrs)?;
+            self.emit_store_events(&conn, &operations)?;
+            self.apply_entity_operations_with_conn(&conn, operations, EventSource::None)
+        })?;
+        self.notify_change_exporter();
+        Ok(())
     }
 
     fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
@@ -996,6 +2286,167 @@ impl StoreTrait for Store {
             .get_result(&*self.conn.get()?)?;
         Ok(count as u64)
     }
+
+    fn block_timestamp(&self, block_hash: H256) -> Result<Option<u64>, Error> {
+        use crate::db_schema::ethereum_blocks::dsl::*;
+
+        // Block hashes are unique across networks, and queries for the
+        // subgraphs of all networks go through the same store
+        let timestamp = ethereum_blocks
+            .select(sql::<Jsonb>("data -> 'block' -> 'timestamp'"))
+            .filter(hash.eq(format!("{:x}", block_hash)))
+            .first::<serde_json::Value>(&*self.conn.get()?)
+            .optional()?;
+        Ok(match timestamp {
+            Some(timestamp) => Some(serde_json::from_value::<U256>(timestamp)?.as_u64()),
+            None => None,
+        })
+    }
+
+    fn background_jobs(&self) -> Result<Vec<BackgroundJob>, Error> {
+        diesel::sql_query(format!(
+            "select {} from background_jobs order by id",
+            BACKGROUND_JOB_COLUMNS
+        ))
+        .load::<BackgroundJobRow>(&*self.conn.get()?)?
+        .into_iter()
+        .map(BackgroundJobRow::into_job)
+        .collect()
+    }
+
+    fn blocks_with_entity_changes(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: &[String],
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<EthereumBlockPointer>, Error> {
+        #[derive(QueryableByName)]
+        struct BlockRow {
+            #[sql_type = "Text"]
+            hash: String,
+            #[sql_type = "BigInt"]
+            number: i64,
+        }
+
+        // The source of an event is the hash of its block, formatted like
+        // the hashes in `ethereum_blocks`. Entities of immutable types have
+        // no history, but keep the source that inserted them
+        let schema = self.subgraph_schema(subgraph_id)?;
+        let immutable_types: Vec<_> = entity_types
+            .iter()
+            .filter(|entity_type| schema.is_immutable_type(entity_type))
+            .cloned()
+            .collect();
+        diesel::sql_query(
+            "select b.hash, b.number
+               from entity_history h
+               join event_meta_data m on m.id = h.event_id
+               join ethereum_blocks b on b.hash = m.source
+              where h.subgraph = $1
+                and h.entity = any($2)
+                and b.network_name = $3
+                and b.number >= $4
+                and b.number <= $5
+              union
+             select b.hash, b.number
+               from entities e
+               join ethereum_blocks b on b.hash = e.event_source
+              where e.subgraph = $1
+                and e.entity = any($6)
+                and b.network_name = $3
+                and b.number >= $4
+                and b.number <= $5
+              order by number, hash",
+        )
+        .bind::<Text, _>(subgraph_id.to_string())
+        .bind::<Array<Text>, _>(entity_types)
+        .bind::<Text, _>(self.deployment_network(subgraph_id)?)
+        .bind::<BigInt, _>(from as i64)
+        .bind::<BigInt, _>(to as i64)
+        .bind::<Array<Text>, _>(immutable_types)
+        .load::<BlockRow>(&*self.conn.get()?)?
+        .into_iter()
+        .map(|row| {
+            Ok(EthereumBlockPointer::from((
+                row.hash.parse::<H256>()?,
+                row.number,
+            )))
+        })
+        .collect()
+    }
+
+    fn entity_changes_in_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: &[String],
+        block_hash: H256,
+    ) -> Result<Vec<EntityChangeTrigger>, Error> {
+        #[derive(QueryableByName)]
+        struct EntityAfter {
+            #[sql_type = "Text"]
+            entity: String,
+            #[sql_type = "Text"]
+            entity_id: String,
+            #[sql_type = "Nullable<Jsonb>"]
+            data_after: Option<serde_json::Value>,
+        }
+
+        // A block that was reverted and processed again has several events;
+        // only the last one counts. Within it, the last change of an entity
+        // has the data the entity had at the end of the block. Entities of
+        // immutable types have no history, but keep the source that
+        // inserted them
+        let schema = self.subgraph_schema(subgraph_id)?;
+        let immutable_types: Vec<_> = entity_types
+            .iter()
+            .filter(|entity_type| schema.is_immutable_type(entity_type))
+            .cloned()
+            .collect();
+        diesel::sql_query(
+            "(select distinct on (h.entity, h.entity_id)
+                     h.entity, h.entity_id, h.data_after
+                from entity_history h
+               where h.subgraph = $1
+                 and h.entity = any($2)
+                 and h.event_id = (
+                   select max(m.id)
+                     from event_meta_data m
+                     join entity_history hm on hm.event_id = m.id
+                    where m.source = $3
+                      and hm.subgraph = $1)
+               order by h.entity, h.entity_id, h.id desc)
+             union all
+             select e.entity, e.id, e.data
+               from entities e
+              where e.subgraph = $1
+                and e.entity = any($4)
+                and e.event_source = $3",
+        )
+        .bind::<Text, _>(subgraph_id.to_string())
+        .bind::<Array<Text>, _>(entity_types)
+        .bind::<Text, _>(format!("{:x}", block_hash))
+        .bind::<Array<Text>, _>(immutable_types)
+        .load::<EntityAfter>(&*self.conn.get()?)?
+        .into_iter()
+        .map(|row| {
+            let entity = match row.data_after {
+                Some(json) => {
+                    let mut entity = serde_json::from_value::<Entity>(json)?;
+                    entity.set("__typename", row.entity.as_str());
+                    Some(entity)
+                }
+                None => None,
+            };
+            Ok(EntityChangeTrigger {
+                subgraph_id: subgraph_id.clone(),
+                entity_type: row.entity,
+                entity_id: row.entity_id,
+                entity,
+            })
+        })
+        .collect()
+    }
 }
 
 impl SubgraphDeploymentStore for Store {
@@ -1180,4 +2631,144 @@ impl ChainStore for Store {
             })
             .map_err(Error::from)
     }
+
+    fn block_pointers_in_range(
+        &self,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<(EthereumBlockPointer, H256)>, Error> {
+        use crate::db_schema::ethereum_blocks::dsl::*;
+
+        ethereum_blocks
+            .select((hash, number, parent_hash))
+            .filter(network_name.eq(&self.network_name))
+            .filter(number.ge(from as i64))
+            .filter(number.le(to as i64))
+            .order((number.asc(), hash.asc()))
+            .load::<(String, i64, Option<String>)>(&*self.conn.get()?)
+            .map_err(Error::from)?
+            .into_iter()
+            .map(|(block_hash, block_number, block_parent_hash)| {
+                let block_ptr =
+                    EthereumBlockPointer::from((block_hash.parse::<H256>().unwrap(), block_number));
+                block_parent_hash
+                    .map(|block_parent_hash| (block_ptr, block_parent_hash.parse().unwrap()))
+                    .ok_or_else(|| format_err!("block {} has no parent hash", block_hash))
+            })
+            .collect()
+    }
+
+    fn block_calls(&self, block_hash: H256) -> Result<Option<Vec<EthereumCall>>, Error> {
+        use crate::db_schema::ethereum_block_calls::dsl;
+
+        dsl::ethereum_block_calls
+            .select(dsl::calls)
+            .filter(dsl::network_name.eq(&self.network_name))
+            .filter(dsl::block_hash.eq(format!("{:x}", block_hash)))
+            .first::<serde_json::Value>(&*self.conn.get()?)
+            .optional()?
+            .map(|json_calls| serde_json::from_value(json_calls).map_err(Error::from))
+            .transpose()
+    }
+
+    fn upsert_block_calls(
+        &self,
+        block_ptr: EthereumBlockPointer,
+        calls: &[EthereumCall],
+    ) -> Result<(), Error> {
+        use crate::db_schema::ethereum_block_calls::dsl;
+
+        // The calls in a block never change, so calls cached concurrently
+        // for another subgraph can be left as they are
+        insert_into(dsl::ethereum_block_calls)
+            .values((
+                dsl::block_hash.eq(block_ptr.hash_hex()),
+                dsl::block_number.eq(block_ptr.number as i64),
+                dsl::network_name.eq(&self.network_name),
+                dsl::calls.eq(serde_json::to_value(calls)?),
+            ))
+            .on_conflict_do_nothing()
+            .execute(&*self.conn.get()?)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    fn block_logs(&self, block_hash: H256) -> Result<Option<Vec<Log>>, Error> {
+        use crate::db_schema::ethereum_block_logs::dsl;
+
+        dsl::ethereum_block_logs
+            .select(dsl::logs)
+            .filter(dsl::network_name.eq(&self.network_name))
+            .filter(dsl::block_hash.eq(format!("{:x}", block_hash)))
+            .first::<serde_json::Value>(&*self.conn.get()?)
+            .optional()?
+            .map(|json_logs| serde_json::from_value(json_logs).map_err(Error::from))
+            .transpose()
+    }
+
+    fn upsert_block_logs(
+        &self,
+        block_ptr: EthereumBlockPointer,
+        logs: &[Log],
+    ) -> Result<(), Error> {
+        use crate::db_schema::ethereum_block_logs::dsl;
+
+        // Like calls, the logs of a block never change
+        insert_into(dsl::ethereum_block_logs)
+            .values((
+                dsl::block_hash.eq(block_ptr.hash_hex()),
+                dsl::block_number.eq(block_ptr.number as i64),
+                dsl::network_name.eq(&self.network_name),
+                dsl::logs.eq(serde_json::to_value(logs)?),
+            ))
+            .on_conflict_do_nothing()
+            .execute(&*self.conn.get()?)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+impl EthereumCallCache for Store {
+    fn get_call(
+        &self,
+        address: Address,
+        call_data: &[u8],
+        block: EthereumBlockPointer,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        use crate::db_schema::eth_call_cache::dsl::*;
+
+        let call_id = contract_call_id(&address, call_data, &block.hash);
+        eth_call_cache
+            .select(return_value)
+            .filter(id.eq(format!("{:x}", call_id)))
+            .first::<Vec<u8>>(&*self.conn.get()?)
+            .optional()
+            .map_err(Error::from)
+    }
+
+    fn set_call(
+        &self,
+        address: Address,
+        call_data: &[u8],
+        block: EthereumBlockPointer,
+        output: &[u8],
+    ) -> Result<(), Error> {
+        use crate::db_schema::eth_call_cache::dsl::*;
+
+        let call_id = contract_call_id(&address, call_data, &block.hash);
+
+        // Results never change, so a result cached concurrently by another
+        // subgraph can be left as it is
+        insert_into(eth_call_cache)
+            .values((
+                id.eq(format!("{:x}", call_id)),
+                return_value.eq(output),
+                contract_address.eq(format!("{:x}", address)),
+                block_number.eq(block.number as i64),
+            ))
+            .on_conflict_do_nothing()
+            .execute(&*self.conn.get()?)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
 }
@@ -2,7 +2,7 @@ use crate::notification_listener::{NotificationListener, SafeChannelName};
 use diesel::deserialize::QueryableByName;
 use diesel::pg::Pg;
 use diesel::pg::PgConnection;
-use diesel::sql_types::Text;
+use diesel::sql_types::{Array, Text};
 use diesel::RunQueryDsl;
 use graph::prelude::*;
 use graph::serde_json;
@@ -87,6 +87,7 @@ impl QueryableByName<Pg> for EntityChangeQBN {
 pub fn get_revert_event(
     conn: &PgConnection,
     subgraph_id: &SubgraphDeploymentId,
+    immutable_types: &[String],
     block_ptr_from: &EthereumBlockPointer,
     block_ptr_to: EthereumBlockPointer,
 ) -> Result<StoreEvent, StoreError> {
@@ -97,13 +98,32 @@ pub fn get_revert_event(
         "get_revert_event must revert a single block only"
     );
 
+    get_revert_event_for_source(
+        conn,
+        subgraph_id,
+        immutable_types,
+        &block_ptr_from.hash_hex(),
+    )
+}
+
+/// Finds the changes to the entities of `subgraph_id` that reverting the
+/// entity operations recorded under the event source `source` causes.
+/// Entities of `immutable_types` have no history; reverting removes the
+/// ones that `source` inserted.
+pub fn get_revert_event_for_source(
+    conn: &PgConnection,
+    subgraph_id: &SubgraphDeploymentId,
+    immutable_types: &[String],
+    source: &str,
+) -> Result<StoreEvent, StoreError> {
     // The query to find the EntityChanges that need to be emitted for the
     // reversion follows the logic of the revert_block stored procedure closely.
     // If that logic ever changes, this query will need to change, too.
     //
-    // The query takes two parameters:
-    //   - block_ptr_from: the block hash that we want to revert
+    // The query takes three parameters:
+    //   - source: the event source, e.g. the block hash, that we want to revert
     //   - subgraph_id: the subgraph for which we are reverting
+    //   - immutable_types: the entity types of the subgraph without history
     //
     // The query is fairly straightforward: the events_for_block_and_subgraph
     // query finds all events that affect the given subgraph at the given block,
@@ -120,10 +140,21 @@ SELECT
    END) as change
 FROM entity_history_with_source h
 WHERE h.source = $1
-  AND h.subgraph = $2";
+  AND h.subgraph = $2
+UNION ALL
+SELECT
+  e.subgraph,
+  e.entity,
+  e.id as entity_id,
+  'removed' as change
+FROM entities e
+WHERE e.event_source = $1
+  AND e.subgraph = $2
+  AND e.entity = any($3)";
     let query = diesel::sql_query(query)
-        .bind::<Text, _>(block_ptr_from.hash_hex())
-        .bind::<Text, _>(subgraph_id.to_string());
+        .bind::<Text, _>(source)
+        .bind::<Text, _>(subgraph_id.to_string())
+        .bind::<Array<Text>, _>(immutable_types);
     let changes: Vec<EntityChangeQBN> = query.get_results(conn)?;
     let changes = changes.into_iter().map(|qbn| qbn.0).collect();
 
@@ -15,10 +15,10 @@ use test_store::*;
 
 use graph::components::store::{EntityFilter, EntityKey, EntityOrder, EntityQuery, EntityRange};
 use graph::data::store::scalar;
-use graph::data::subgraph::schema::SubgraphDeploymentEntity;
+use graph::data::subgraph::schema::{SubgraphDeploymentEntity, SubgraphDeploymentFileEntity};
 use graph::prelude::*;
-use graph::web3::types::H256;
-use graph_store_postgres::{db_schema, Store as DieselStore};
+use graph::web3::types::{Address, Log, H256, U256};
+use graph_store_postgres::{db_schema, Store as DieselStore, StoreConfig};
 
 lazy_static! {
     static ref TEST_SUBGRAPH_ID_STRING: String = String::from("testsubgraph");
@@ -107,8 +107,12 @@ fn insert_test_data(store: Arc<DieselStore>) {
         spec_version: "1".to_owned(),
         description: None,
         repository: None,
+        author: None,
         schema: Schema::parse("scalar Foo", TEST_SUBGRAPH_ID.clone()).unwrap(),
         data_sources: vec![],
+        templates: vec![],
+        external_hosts: vec![],
+        features: vec![],
     };
 
     // Create SubgraphDeploymentEntity
@@ -232,7 +236,7 @@ fn create_test_entity(
 
 /// Removes test data from the database behind the store.
 fn remove_test_data() {
-    use crate::db_schema::{entities, entity_history, event_meta_data};
+    use crate::db_schema::{entities, entity_change_outbox, entity_history, event_meta_data};
 
     let url = postgres_test_url();
     let conn = PgConnection::establish(url.as_str()).expect("Failed to connect to Postgres");
@@ -245,6 +249,9 @@ fn remove_test_data() {
     delete(event_meta_data::table)
         .execute(&conn)
         .expect("Failed to remove entity change event test data");
+    delete(entity_change_outbox::table)
+        .execute(&conn)
+        .expect("Failed to remove entity change outbox test data");
 }
 
 #[test]
@@ -523,8 +530,10 @@ fn find_string_contains() {
                 "%ind%".into(),
             )])),
             order_by: None,
+            order_by_child: None,
             order_direction: None,
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -541,8 +550,10 @@ fn find_string_equal() {
                 "Cindini".into(),
             )])),
             order_by: None,
+            order_by_child: None,
             order_direction: None,
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -559,8 +570,10 @@ fn find_string_not_equal() {
                 "Cindini".into(),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Ascending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -577,8 +590,10 @@ fn find_string_greater_than() {
                 "Kundi".into(),
             )])),
             order_by: None,
+            order_by_child: None,
             order_direction: None,
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -595,8 +610,10 @@ fn find_string_less_than_order_by_asc() {
                 "Kundi".into(),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Ascending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -613,8 +630,10 @@ fn find_string_less_than_order_by_desc() {
                 "Kundi".into(),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -631,11 +650,13 @@ fn find_string_less_than_range() {
                 "ZZZ".into(),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange {
                 first: Some(1),
                 skip: 1,
             },
+            block: None,
         },
     )
 }
@@ -652,8 +673,10 @@ fn find_string_multiple_and() {
                 EntityFilter::Equal("name".to_owned(), "Cindini".into()),
             ])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -670,8 +693,10 @@ fn find_string_ends_with() {
                 "ini".into(),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -688,8 +713,50 @@ fn find_string_not_ends_with() {
                 "ini".into(),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
+        },
+    )
+}
+
+#[test]
+fn find_string_contains_nocase() {
+    test_find(
+        vec!["2"],
+        EntityQuery {
+            subgraph_id: TEST_SUBGRAPH_ID.clone(),
+            entity_types: vec!["user".to_owned()],
+            filter: Some(EntityFilter::And(vec![EntityFilter::ContainsNoCase(
+                "name".into(),
+                "%IND%".into(),
+            )])),
+            order_by: None,
+            order_by_child: None,
+            order_direction: None,
+            range: EntityRange::first(100),
+            block: None,
+        },
+    )
+}
+
+#[test]
+fn find_string_not_starts_with_nocase() {
+    test_find(
+        vec!["3", "1"],
+        EntityQuery {
+            subgraph_id: TEST_SUBGRAPH_ID.clone(),
+            entity_types: vec!["user".to_owned()],
+            filter: Some(EntityFilter::And(vec![EntityFilter::NotStartsWithNoCase(
+                "name".to_owned(),
+                "cIN".into(),
+            )])),
+            order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
+            order_direction: Some(EntityOrder::Descending),
+            range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -706,8 +773,10 @@ fn find_string_in() {
                 vec!["Johnton".into()],
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -724,8 +793,10 @@ fn find_string_not_in() {
                 vec!["Shaqueeena".into()],
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -742,8 +813,10 @@ fn find_float_equal() {
                 Value::BigDecimal(184.4.into()),
             )])),
             order_by: None,
+            order_by_child: None,
             order_direction: None,
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -760,8 +833,10 @@ fn find_float_not_equal() {
                 Value::BigDecimal(184.4.into()),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -778,8 +853,10 @@ fn find_float_greater_than() {
                 Value::BigDecimal(160.0.into()),
             )])),
             order_by: None,
+            order_by_child: None,
             order_direction: None,
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -796,8 +873,10 @@ fn find_float_less_than() {
                 Value::BigDecimal(160.0.into()),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Ascending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -814,8 +893,10 @@ fn find_float_less_than_order_by_desc() {
                 Value::BigDecimal(160.0.into()),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -832,11 +913,13 @@ fn find_float_less_than_range() {
                 Value::BigDecimal(161.0.into()),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange {
                 first: Some(1),
                 skip: 1,
             },
+            block: None,
         },
     )
 }
@@ -856,8 +939,10 @@ fn find_float_in() {
                 ],
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(5),
+            block: None,
         },
     )
 }
@@ -877,8 +962,10 @@ fn find_float_not_in() {
                 ],
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(5),
+            block: None,
         },
     )
 }
@@ -895,8 +982,10 @@ fn find_int_equal() {
                 Value::Int(67 as i32),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -913,8 +1002,10 @@ fn find_int_not_equal() {
                 Value::Int(67 as i32),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -931,8 +1022,10 @@ fn find_int_greater_than() {
                 Value::Int(43 as i32),
             )])),
             order_by: None,
+            order_by_child: None,
             order_direction: None,
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -949,8 +1042,10 @@ fn find_int_greater_or_equal() {
                 Value::Int(43 as i32),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Ascending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -967,8 +1062,10 @@ fn find_int_less_than() {
                 Value::Int(50 as i32),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Ascending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -985,8 +1082,10 @@ fn find_int_less_or_equal() {
                 Value::Int(43 as i32),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Ascending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -1003,8 +1102,10 @@ fn find_int_less_than_order_by_desc() {
                 Value::Int(50 as i32),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -1021,11 +1122,13 @@ fn find_int_less_than_range() {
                 Value::Int(67 as i32),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange {
                 first: Some(1),
                 skip: 1,
             },
+            block: None,
         },
     )
 }
@@ -1042,8 +1145,10 @@ fn find_int_in() {
                 vec![Value::Int(67 as i32), Value::Int(43 as i32)],
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(5),
+            block: None,
         },
     )
 }
@@ -1060,8 +1165,10 @@ fn find_int_not_in() {
                 vec![Value::Int(67 as i32), Value::Int(43 as i32)],
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(5),
+            block: None,
         },
     )
 }
@@ -1078,8 +1185,10 @@ fn find_bool_equal() {
                 Value::Bool(true),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -1096,8 +1205,10 @@ fn find_bool_not_equal() {
                 Value::Bool(true),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Ascending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -1114,8 +1225,10 @@ fn find_bool_in() {
                 vec![Value::Bool(true)],
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(5),
+            block: None,
         },
     )
 }
@@ -1132,8 +1245,10 @@ fn find_bool_not_in() {
                 vec![Value::Bool(true)],
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(5),
+            block: None,
         },
     )
 }
@@ -1150,8 +1265,10 @@ fn find_bytes_equal() {
                 Value::Bytes("Johnton".as_bytes().into()),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -1168,8 +1285,10 @@ fn find_null_equal() {
                 Value::Null,
             )),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -1183,8 +1302,10 @@ fn find_null_not_equal() {
             entity_types: vec!["user".to_owned()],
             filter: Some(EntityFilter::Not("favorite_color".to_owned(), Value::Null)),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -1201,8 +1322,10 @@ fn find_null_not_in() {
                 vec![Value::Null],
             )),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -1216,8 +1339,10 @@ fn find_order_by_float() {
             entity_types: vec!["user".to_owned()],
             filter: None,
             order_by: Some(("weight".to_owned(), ValueType::BigDecimal)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Ascending),
             range: EntityRange::first(100),
+            block: None,
         },
     );
     test_find(
@@ -1227,8 +1352,10 @@ fn find_order_by_float() {
             entity_types: vec!["user".to_owned()],
             filter: None,
             order_by: Some(("weight".to_owned(), ValueType::BigDecimal)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     );
 }
@@ -1242,8 +1369,10 @@ fn find_order_by_id() {
             entity_types: vec!["user".to_owned()],
             filter: None,
             order_by: Some(("id".to_owned(), ValueType::ID)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Ascending),
             range: EntityRange::first(100),
+            block: None,
         },
     );
     test_find(
@@ -1253,8 +1382,10 @@ fn find_order_by_id() {
             entity_types: vec!["user".to_owned()],
             filter: None,
             order_by: Some(("id".to_owned(), ValueType::ID)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     );
 }
@@ -1268,8 +1399,10 @@ fn find_order_by_int() {
             entity_types: vec!["user".to_owned()],
             filter: None,
             order_by: Some(("age".to_owned(), ValueType::Int)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Ascending),
             range: EntityRange::first(100),
+            block: None,
         },
     );
     test_find(
@@ -1279,8 +1412,10 @@ fn find_order_by_int() {
             entity_types: vec!["user".to_owned()],
             filter: None,
             order_by: Some(("age".to_owned(), ValueType::Int)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     );
 }
@@ -1294,8 +1429,10 @@ fn find_order_by_string() {
             entity_types: vec!["user".to_owned()],
             filter: None,
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Ascending),
             range: EntityRange::first(100),
+            block: None,
         },
     );
     test_find(
@@ -1305,8 +1442,10 @@ fn find_order_by_string() {
             entity_types: vec!["user".to_owned()],
             filter: None,
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         },
     );
 }
@@ -1323,8 +1462,10 @@ fn find_where_nested_and_or() {
                 EntityFilter::Equal("id".to_owned(), Value::from("2")),
             ])])),
             order_by: Some(("id".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Ascending),
             range: EntityRange::first(100),
+            block: None,
         },
     )
 }
@@ -1436,8 +1577,10 @@ fn check_basic_revert(
             Value::String("Shaqueeena".to_owned()),
         )])),
         order_by: Some(("name".to_owned(), ValueType::String)),
+        order_by_child: None,
         order_direction: Some(EntityOrder::Descending),
         range: EntityRange::first(100),
+        block: None,
     };
 
     let subscription = subscribe_and_consume(store.clone(), subgraph_id, entity_type);
@@ -1493,6 +1636,46 @@ fn revert_block_basic_subgraphs() {
     })
 }
 
+#[test]
+fn revert_block_range() {
+    run_test(|store| -> Result<(), ()> {
+        let this_query = EntityQuery {
+            subgraph_id: TEST_SUBGRAPH_ID.clone(),
+            entity_types: vec!["user".to_owned()],
+            filter: None,
+            order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
+            order_direction: Some(EntityOrder::Ascending),
+            range: EntityRange::first(100),
+            block: None,
+        };
+
+        // Revert blocks 3 and 2 at once
+        store
+            .revert_block_range(
+                TEST_SUBGRAPH_ID.clone(),
+                vec![*TEST_BLOCK_3_PTR, *TEST_BLOCK_2_PTR, *TEST_BLOCK_1_PTR],
+                vec![],
+            )
+            .unwrap();
+
+        // Only the user from block 1 is left
+        let returned_entities = store.find(this_query).expect("store.find operation failed");
+        let ids: Vec<_> = returned_entities
+            .iter()
+            .map(|entity| entity.id().unwrap())
+            .collect();
+        assert_eq!(vec!["1".to_owned()], ids);
+
+        assert_eq!(
+            *TEST_BLOCK_1_PTR,
+            store.block_ptr(TEST_SUBGRAPH_ID.clone()).unwrap()
+        );
+
+        Ok(())
+    })
+}
+
 #[test]
 fn revert_block_with_delete() {
     run_test(|store| {
@@ -1504,8 +1687,10 @@ fn revert_block_with_delete() {
                 Value::String("Cindini".to_owned()),
             )])),
             order_by: Some(("name".to_owned(), ValueType::String)),
+            order_by_child: None,
             order_direction: Some(EntityOrder::Descending),
             range: EntityRange::first(100),
+            block: None,
         };
 
         // Delete entity with id=2
@@ -1626,6 +1811,397 @@ fn revert_block_with_partial_update() {
     })
 }
 
+/// Creates a deployment with the given schema, without any entities.
+fn create_subgraph(store: &DieselStore, id: &str, schema: &str) -> SubgraphDeploymentId {
+    let subgraph_id = SubgraphDeploymentId::new(id).unwrap();
+    let manifest = SubgraphManifest {
+        id: subgraph_id.clone(),
+        location: "/ipfs/test".to_owned(),
+        spec_version: "1".to_owned(),
+        description: None,
+        repository: None,
+        author: None,
+        schema: Schema::parse(schema, subgraph_id.clone()).unwrap(),
+        data_sources: vec![],
+        templates: vec![],
+        external_hosts: vec![],
+        features: vec![],
+    };
+    store
+        .apply_entity_operations(
+            SubgraphDeploymentEntity::new(&manifest, false, false, *TEST_BLOCK_0_PTR, 1)
+                .create_operations(&subgraph_id),
+            EventSource::None,
+        )
+        .unwrap();
+    subgraph_id
+}
+
+#[test]
+fn enum_values_are_checked_on_set_and_update() {
+    run_test(|store| -> Result<(), ()> {
+        let subgraph_id = create_subgraph(
+            &store,
+            "EnumTestSubgraph",
+            "enum Color { RED, GREEN } type Pet @entity { id: ID!, color: Color }",
+        );
+        let key = EntityKey {
+            subgraph_id: subgraph_id.clone(),
+            entity_type: "Pet".to_owned(),
+            entity_id: "1".to_owned(),
+        };
+        let pet = |color: &str| {
+            Entity::from(vec![
+                ("id", Value::from("1")),
+                ("color", Value::from(color)),
+            ])
+        };
+        let write = |operation| {
+            store.transact_block_operations(
+                subgraph_id.clone(),
+                *TEST_BLOCK_0_PTR,
+                *TEST_BLOCK_1_PTR,
+                vec![operation],
+            )
+        };
+
+        assert!(write(EntityOperation::Set {
+            key: key.clone(),
+            data: pet("BLUE"),
+        })
+        .is_err());
+        write(EntityOperation::Set {
+            key: key.clone(),
+            data: pet("RED"),
+        })
+        .unwrap();
+        store
+            .transact_block_operations(
+                subgraph_id.clone(),
+                *TEST_BLOCK_1_PTR,
+                *TEST_BLOCK_2_PTR,
+                vec![EntityOperation::Update {
+                    key: key.clone(),
+                    data: pet("BLUE"),
+                    guard: None,
+                }],
+            )
+            .expect_err("updates must not write values that are not in the enum");
+        assert_eq!(store.get(key).unwrap(), Some(pet("RED")));
+        Ok(())
+    })
+}
+
+#[test]
+fn entities_changed_after_block_have_their_older_values() {
+    run_test(|store| -> Result<(), ()> {
+        use crate::db_schema::ethereum_blocks;
+
+        let subgraph_id = create_subgraph(
+            &store,
+            "ExportTestSubgraph",
+            "type Token @entity { id: ID!, name: String! }",
+        );
+
+        // The blocks of the events must be known to find them by number
+        let conn = PgConnection::establish(postgres_test_url().as_str()).unwrap();
+        for block_ptr in &[*TEST_BLOCK_1_PTR, *TEST_BLOCK_2_PTR] {
+            insert_into(ethereum_blocks::table)
+                .values((
+                    ethereum_blocks::hash.eq(block_ptr.hash_hex()),
+                    ethereum_blocks::number.eq(block_ptr.number as i64),
+                    ethereum_blocks::network_name.eq("fake_network"),
+                    ethereum_blocks::data.eq(graph::serde_json::json!({})),
+                ))
+                .on_conflict_do_nothing()
+                .execute(&conn)
+                .unwrap();
+        }
+
+        let set = |id: &str, name: &str| EntityOperation::Set {
+            key: EntityKey {
+                subgraph_id: subgraph_id.clone(),
+                entity_type: "Token".to_owned(),
+                entity_id: id.to_owned(),
+            },
+            data: Entity::from(vec![("id", Value::from(id)), ("name", Value::from(name))]),
+        };
+        store
+            .transact_block_operations(
+                subgraph_id.clone(),
+                *TEST_BLOCK_0_PTR,
+                *TEST_BLOCK_1_PTR,
+                vec![set("1", "old"), set("2", "unchanged")],
+            )
+            .unwrap();
+        store
+            .transact_block_operations(
+                subgraph_id.clone(),
+                *TEST_BLOCK_1_PTR,
+                *TEST_BLOCK_2_PTR,
+                vec![set("1", "new"), set("3", "added")],
+            )
+            .unwrap();
+
+        let changed = store
+            .entities_changed_after_block(&subgraph_id, 1)
+            .unwrap()
+            .into_iter()
+            .map(|(key, entity)| {
+                let name = entity.and_then(|entity| entity.get("name").cloned());
+                (key.entity_id, name)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changed,
+            vec![
+                ("1".to_owned(), Some(Value::from("old"))),
+                ("3".to_owned(), None),
+            ]
+        );
+        assert!(store
+            .entities_changed_after_block(&subgraph_id, 2)
+            .unwrap()
+            .is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn entities_are_found_as_they_were_at_a_block() {
+    run_test(|store| -> Result<(), ()> {
+        use crate::db_schema::ethereum_blocks;
+
+        let subgraph_id = create_subgraph(
+            &store,
+            "BlockQueryTestSubgraph",
+            "type Token @entity { id: ID!, name: String! }",
+        );
+
+        // The blocks of the events must be known to find them by number
+        let conn = PgConnection::establish(postgres_test_url().as_str()).unwrap();
+        for block_ptr in &[*TEST_BLOCK_1_PTR, *TEST_BLOCK_2_PTR] {
+            insert_into(ethereum_blocks::table)
+                .values((
+                    ethereum_blocks::hash.eq(block_ptr.hash_hex()),
+                    ethereum_blocks::number.eq(block_ptr.number as i64),
+                    ethereum_blocks::network_name.eq("fake_network"),
+                    ethereum_blocks::data.eq(graph::serde_json::json!({})),
+                ))
+                .on_conflict_do_nothing()
+                .execute(&conn)
+                .unwrap();
+        }
+
+        let set = |id: &str, name: &str| EntityOperation::Set {
+            key: EntityKey {
+                subgraph_id: subgraph_id.clone(),
+                entity_type: "Token".to_owned(),
+                entity_id: id.to_owned(),
+            },
+            data: Entity::from(vec![("id", Value::from(id)), ("name", Value::from(name))]),
+        };
+        store
+            .transact_block_operations(
+                subgraph_id.clone(),
+                *TEST_BLOCK_0_PTR,
+                *TEST_BLOCK_1_PTR,
+                vec![set("1", "old"), set("2", "unchanged")],
+            )
+            .unwrap();
+        store
+            .transact_block_operations(
+                subgraph_id.clone(),
+                *TEST_BLOCK_1_PTR,
+                *TEST_BLOCK_2_PTR,
+                vec![set("1", "new"), set("3", "added")],
+            )
+            .unwrap();
+
+        let names = |query: EntityQuery| {
+            store
+                .find(query)
+                .unwrap()
+                .into_iter()
+                .map(|entity| {
+                    let id = entity.id().unwrap();
+                    (id, entity.get("name").cloned().unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+        let query = EntityQuery::new(
+            subgraph_id.clone(),
+            vec!["Token".to_owned()],
+            EntityRange::first(100),
+        )
+        .order_by(("id".to_owned(), ValueType::String), EntityOrder::Ascending);
+
+        assert_eq!(
+            names(query.clone().at_block(1)),
+            vec![
+                ("1".to_owned(), Value::from("old")),
+                ("2".to_owned(), Value::from("unchanged")),
+            ]
+        );
+        assert_eq!(
+            names(query.clone().at_block(2)),
+            vec![
+                ("1".to_owned(), Value::from("new")),
+                ("2".to_owned(), Value::from("unchanged")),
+                ("3".to_owned(), Value::from("added")),
+            ]
+        );
+        assert_eq!(names(query.clone().at_block(2)), names(query));
+
+        // A snapshot serves any number of queries, even after one failed
+        let snapshot = store.snapshot_at_block(&subgraph_id, 1).unwrap();
+        let first = snapshot.find(query.clone()).unwrap();
+        assert_eq!(first.len(), 2);
+        let invalid_order = query.clone().order_by(
+            ("name".to_owned(), ValueType::BigInt),
+            EntityOrder::Ascending,
+        );
+        assert!(snapshot.find(invalid_order).is_err());
+        assert_eq!(snapshot.find(query.clone()).unwrap(), first);
+        Ok(())
+    })
+}
+
+#[test]
+fn entity_changes_are_found_by_block() {
+    run_test(|store| -> Result<(), ()> {
+        use crate::db_schema::ethereum_blocks;
+
+        let subgraph_id = create_subgraph(
+            &store,
+            "EntityChangeBlockTestSubgraph",
+            "type Token @entity { id: ID!, name: String! } type Account @entity { id: ID! }",
+        );
+
+        // The blocks of the events must be known to find them by number
+        let conn = PgConnection::establish(postgres_test_url().as_str()).unwrap();
+        let block_ptrs = vec![
+            *TEST_BLOCK_1_PTR,
+            *TEST_BLOCK_2_PTR,
+            *TEST_BLOCK_3_PTR,
+            *TEST_BLOCK_3A_PTR,
+        ];
+        for block_ptr in block_ptrs.iter() {
+            insert_into(ethereum_blocks::table)
+                .values((
+                    ethereum_blocks::hash.eq(block_ptr.hash_hex()),
+                    ethereum_blocks::number.eq(block_ptr.number as i64),
+                    ethereum_blocks::network_name.eq("fake_network"),
+                    ethereum_blocks::data.eq(graph::serde_json::json!({})),
+                ))
+                .on_conflict_do_nothing()
+                .execute(&conn)
+                .unwrap();
+        }
+
+        let key = |entity_type: &str, id: &str| EntityKey {
+            subgraph_id: subgraph_id.clone(),
+            entity_type: entity_type.to_owned(),
+            entity_id: id.to_owned(),
+        };
+        let set = |id: &str, name: &str| EntityOperation::Set {
+            key: key("Token", id),
+            data: Entity::from(vec![("id", Value::from(id)), ("name", Value::from(name))]),
+        };
+        let transact = |from, to, operations| {
+            store
+                .transact_block_operations(subgraph_id.clone(), from, to, operations)
+                .unwrap()
+        };
+        transact(
+            *TEST_BLOCK_0_PTR,
+            *TEST_BLOCK_1_PTR,
+            vec![
+                set("1", "first"),
+                set("2", "second"),
+                EntityOperation::Set {
+                    key: key("Account", "1"),
+                    data: Entity::from(vec![("id", Value::from("1"))]),
+                },
+            ],
+        );
+        transact(
+            *TEST_BLOCK_1_PTR,
+            *TEST_BLOCK_2_PTR,
+            vec![
+                set("1", "changed"),
+                set("1", "changed twice"),
+                EntityOperation::Remove {
+                    key: key("Token", "2"),
+                },
+            ],
+        );
+        transact(
+            *TEST_BLOCK_2_PTR,
+            *TEST_BLOCK_3A_PTR,
+            vec![set("3", "uncled")],
+        );
+        store
+            .revert_block_operations(subgraph_id.clone(), *TEST_BLOCK_3A_PTR, *TEST_BLOCK_2_PTR)
+            .unwrap();
+        transact(
+            *TEST_BLOCK_2_PTR,
+            *TEST_BLOCK_3_PTR,
+            vec![set("3", "third")],
+        );
+
+        // Reverted blocks are still found; the block stream only keeps
+        // blocks on the main chain
+        let tokens = vec!["Token".to_owned()];
+        let mut expected = block_ptrs.clone();
+        expected.sort_by_key(|block_ptr| (block_ptr.number, block_ptr.hash_hex()));
+        assert_eq!(
+            store
+                .blocks_with_entity_changes(&subgraph_id, &tokens, 1, 3)
+                .unwrap(),
+            expected
+        );
+        assert_eq!(
+            store
+                .blocks_with_entity_changes(&subgraph_id, &tokens, 2, 2)
+                .unwrap(),
+            vec![*TEST_BLOCK_2_PTR]
+        );
+        assert_eq!(
+            store
+                .blocks_with_entity_changes(&subgraph_id, &["Account".to_owned()], 1, 3)
+                .unwrap(),
+            vec![*TEST_BLOCK_1_PTR]
+        );
+
+        // Each changed entity is reported once, as of the end of the block
+        let changes = |block_ptr: EthereumBlockPointer| {
+            store
+                .entity_changes_in_block(&subgraph_id, &tokens, block_ptr.hash)
+                .unwrap()
+                .into_iter()
+                .map(|change| {
+                    let name = change.entity.and_then(|entity| entity.get("name").cloned());
+                    (change.entity_type, change.entity_id, name)
+                })
+                .collect::<Vec<_>>()
+        };
+        let change = |id: &str, name: Option<&str>| {
+            ("Token".to_owned(), id.to_owned(), name.map(Value::from))
+        };
+        assert_eq!(
+            changes(*TEST_BLOCK_1_PTR),
+            vec![change("1", Some("first")), change("2", Some("second"))]
+        );
+        assert_eq!(
+            changes(*TEST_BLOCK_2_PTR),
+            vec![change("1", Some("changed twice")), change("2", None)]
+        );
+        assert_eq!(changes(*TEST_BLOCK_3_PTR), vec![change("3", Some("third"))]);
+        Ok(())
+    })
+}
+
 #[test]
 fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
     run_test(|store| {
@@ -1636,8 +2212,12 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
             spec_version: "1".to_owned(),
             description: None,
             repository: None,
+            author: None,
             schema: Schema::parse("scalar Foo", subgraph_id.clone()).unwrap(),
             data_sources: vec![],
+            templates: vec![],
+            external_hosts: vec![],
+            features: vec![],
         };
 
         // Create SubgraphDeploymentEntity
@@ -1899,3 +2479,698 @@ fn subgraph_schema_types_have_subgraph_id_directive() {
         Ok(())
     })
 }
+
+#[test]
+fn file_operations_are_reverted_as_a_whole() {
+    run_test(|store| -> Result<(), ()> {
+        let link = "/ipfs/QmFile";
+        let user_key = |id: &str| EntityKey {
+            subgraph_id: TEST_SUBGRAPH_ID.clone(),
+            entity_type: "user".to_owned(),
+            entity_id: id.to_owned(),
+        };
+        let user_name = |id: &str| {
+            store
+                .get(user_key(id))
+                .unwrap()
+                .and_then(|user| user.get("name").cloned())
+        };
+        let marker_key = SubgraphDeploymentFileEntity::key(SubgraphDeploymentFileEntity::id(
+            &*TEST_SUBGRAPH_ID,
+            link,
+        ));
+
+        let mut data = Entity::new();
+        data.set("name", "Fileton");
+        let mut operations = vec![
+            create_test_entity("7", "user", "Filey", "file@email.com", 1, 1.0, false, None),
+            EntityOperation::Update {
+                key: user_key("1"),
+                data,
+                guard: None,
+            },
+        ];
+        operations.extend(
+            SubgraphDeploymentFileEntity::new(TEST_SUBGRAPH_ID.clone(), link.to_owned())
+                .write_operations(),
+        );
+        store
+            .apply_entity_operations(operations, EventSource::File(link.to_owned()))
+            .unwrap();
+        assert!(store.get(marker_key.clone()).unwrap().is_some());
+        assert_eq!(user_name("1"), Some(Value::from("Fileton")));
+
+        store
+            .revert_file_operations(&*TEST_SUBGRAPH_ID, link)
+            .unwrap();
+        assert!(store.get(marker_key).unwrap().is_none());
+        assert!(store.get(user_key("7")).unwrap().is_none());
+        assert_eq!(user_name("1"), Some(Value::from("Johnton")));
+
+        // Entities written for blocks are not affected
+        assert!(store.get(user_key("2")).unwrap().is_some());
+        Ok(())
+    })
+}
+
+#[test]
+fn every_entity_change_is_recorded_in_the_outbox() {
+    run_test(|_| -> Result<(), ()> {
+        /// Never accepts changes, so that they stay in the outbox.
+        struct UnavailableSink;
+
+        impl EntityChangeSink for UnavailableSink {
+            fn publish(&self, _: &[CommittedEntityChange]) -> Result<(), Error> {
+                Err(format_err!("sink is unavailable"))
+            }
+        }
+
+        let store = DieselStore::new(
+            StoreConfig {
+                postgres_url: postgres_test_url(),
+                network_name: "fake_network".to_owned(),
+            },
+            &*LOGGER,
+            EthereumNetworkIdentifier {
+                net_version: "graph test suite".to_owned(),
+                genesis_block_hash: GENESIS_PTR.hash,
+            },
+        );
+        let outbox = Arc::new(store.entity_change_outbox());
+        let exporter =
+            EntityChangeExporter::new(&*LOGGER, outbox.clone(), Arc::new(UnavailableSink)).unwrap();
+        let store = store.with_entity_change_exporter(Arc::new(exporter));
+        let pending = || -> Vec<(Option<u64>, String, EntityChangeOperation, bool)> {
+            outbox
+                .pending(100)
+                .unwrap()
+                .into_iter()
+                .map(|(_, change)| {
+                    assert_eq!(change.deployment, *TEST_SUBGRAPH_ID);
+                    (
+                        change.block_number,
+                        change.entity_id,
+                        change.operation,
+                        change.revert,
+                    )
+                })
+                .collect()
+        };
+
+        // Writing a block; the deployment's metadata is not exported
+        store
+            .transact_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_3_PTR,
+                *TEST_BLOCK_4_PTR,
+                vec![create_test_entity(
+                    "7",
+                    "user",
+                    "Blocky",
+                    "block@email.com",
+                    1,
+                    1.0,
+                    false,
+                    None,
+                )],
+            )
+            .unwrap();
+        assert_eq!(
+            pending(),
+            vec![(Some(4), "7".to_owned(), EntityChangeOperation::Set, false)]
+        );
+
+        // Reverting it
+        store
+            .revert_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_4_PTR,
+                *TEST_BLOCK_3_PTR,
+            )
+            .unwrap();
+        assert_eq!(
+            pending()[1..].to_vec(),
+            vec![(
+                Some(3),
+                "7".to_owned(),
+                EntityChangeOperation::Removed,
+                true
+            )]
+        );
+
+        // Writes that are not tied to a block, and their revert
+        let link = "/ipfs/QmFile";
+        store
+            .apply_entity_operations(
+                vec![create_test_entity(
+                    "8",
+                    "user",
+                    "Filey",
+                    "file@email.com",
+                    1,
+                    1.0,
+                    false,
+                    None,
+                )],
+                EventSource::File(link.to_owned()),
+            )
+            .unwrap();
+        store
+            .revert_file_operations(&*TEST_SUBGRAPH_ID, link)
+            .unwrap();
+        assert_eq!(
+            pending()[2..].to_vec(),
+            vec![
+                (None, "8".to_owned(), EntityChangeOperation::Set, false),
+                (None, "8".to_owned(), EntityChangeOperation::Removed, true),
+            ]
+        );
+
+        // Changes stay in the outbox until they are acknowledged
+        let (last, _) = outbox.pending(100).unwrap().pop().unwrap();
+        outbox.acknowledge(last).unwrap();
+        assert!(pending().is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn entity_history_is_pruned_before_a_block() {
+    run_test(|store| -> Result<(), ()> {
+        use crate::db_schema::ethereum_blocks;
+
+        let subgraph_id = create_subgraph(
+            &store,
+            "PruneTestSubgraph",
+            "type Token @entity { id: ID!, name: String! }",
+        );
+
+        // The blocks of the events must be known to find them by number
+        let conn = PgConnection::establish(postgres_test_url().as_str()).unwrap();
+        for block_ptr in &[*TEST_BLOCK_1_PTR, *TEST_BLOCK_2_PTR] {
+            insert_into(ethereum_blocks::table)
+                .values((
+                    ethereum_blocks::hash.eq(block_ptr.hash_hex()),
+                    ethereum_blocks::number.eq(block_ptr.number as i64),
+                    ethereum_blocks::network_name.eq("fake_network"),
+                    ethereum_blocks::data.eq(graph::serde_json::json!({})),
+                ))
+                .on_conflict_do_nothing()
+                .execute(&conn)
+                .unwrap();
+        }
+
+        let set = |id: &str, name: &str| EntityOperation::Set {
+            key: EntityKey {
+                subgraph_id: subgraph_id.clone(),
+                entity_type: "Token".to_owned(),
+                entity_id: id.to_owned(),
+            },
+            data: Entity::from(vec![("id", Value::from(id)), ("name", Value::from(name))]),
+        };
+        store
+            .transact_block_operations(
+                subgraph_id.clone(),
+                *TEST_BLOCK_0_PTR,
+                *TEST_BLOCK_1_PTR,
+                vec![set("1", "old"), set("2", "unchanged")],
+            )
+            .unwrap();
+        store
+            .transact_block_operations(
+                subgraph_id.clone(),
+                *TEST_BLOCK_1_PTR,
+                *TEST_BLOCK_2_PTR,
+                vec![set("1", "new"), set("3", "added")],
+            )
+            .unwrap();
+        assert_eq!(store.entity_history_count(&subgraph_id).unwrap(), 4);
+
+        // Only the changes of block 1 are removed, and only for this
+        // deployment
+        let test_history = store.entity_history_count(&*TEST_SUBGRAPH_ID).unwrap();
+        assert_eq!(store.prune_entity_history(&subgraph_id, 2).unwrap(), 2);
+        assert_eq!(store.entity_history_count(&subgraph_id).unwrap(), 2);
+        assert_eq!(
+            store.entity_history_count(&*TEST_SUBGRAPH_ID).unwrap(),
+            test_history
+        );
+
+        // Block 2 can still be reverted
+        store
+            .revert_block_operations(subgraph_id.clone(), *TEST_BLOCK_2_PTR, *TEST_BLOCK_1_PTR)
+            .unwrap();
+        let name = store
+            .get(EntityKey {
+                subgraph_id: subgraph_id.clone(),
+                entity_type: "Token".to_owned(),
+                entity_id: "1".to_owned(),
+            })
+            .unwrap()
+            .and_then(|token| token.get("name").cloned());
+        assert_eq!(name, Some(Value::from("old")));
+        Ok(())
+    })
+}
+
+#[test]
+fn background_jobs_are_claimed_once_and_taken_over_when_their_lease_expires() {
+    run_test(|store| -> Result<(), ()> {
+        use crate::db_schema::background_jobs;
+
+        let conn = PgConnection::establish(postgres_test_url().as_str()).unwrap();
+        delete(background_jobs::table).execute(&conn).unwrap();
+
+        let node_a = NodeId::new("node_a").unwrap();
+        let node_b = NodeId::new("node_b").unwrap();
+        let lease = Duration::from_secs(60);
+        let claim = |node_id: &NodeId| {
+            store
+                .claim_job(node_id, lease)
+                .unwrap()
+                .map(|job| (job.id, job.node_id.unwrap()))
+        };
+
+        let args = graph::serde_json::json!({});
+        let first = store
+            .enqueue_job("export", &*TEST_SUBGRAPH_ID, args.clone())
+            .unwrap();
+        let second = store
+            .enqueue_job("export", &*TEST_SUBGRAPH_ID, args)
+            .unwrap();
+
+        // Every job is run by one node
+        assert_eq!(claim(&node_a), Some((first, node_a.clone())));
+        assert_eq!(claim(&node_b), Some((second, node_b.clone())));
+
+        // A node that restarts resumes the job it was running, but does not
+        // take the jobs of other nodes while their lease lasts
+        assert_eq!(claim(&node_b), Some((second, node_b.clone())));
+        assert!(store.renew_job_lease(first, &node_a, lease).unwrap());
+        store.finish_job(second, &node_b, None).unwrap();
+        assert_eq!(claim(&node_b), None);
+
+        // Once the lease of a job has expired, another node takes it over,
+        // and the node that lost it can no longer change it
+        diesel::sql_query(
+            "update background_jobs
+                set lease_expires_at = now() - interval '1 second'
+              where id = $1",
+        )
+        .bind::<sql_types::Integer, _>(first)
+        .execute(&conn)
+        .unwrap();
+        assert_eq!(claim(&node_b), Some((first, node_b.clone())));
+        assert!(!store.renew_job_lease(first, &node_a, lease).unwrap());
+        store
+            .finish_job(first, &node_a, Some("lost".to_owned()))
+            .unwrap();
+        store.finish_job(first, &node_b, None).unwrap();
+
+        let jobs = store
+            .background_jobs()
+            .unwrap()
+            .into_iter()
+            .map(|job| (job.id, job.status, job.error))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            jobs,
+            vec![
+                (first, BackgroundJobStatus::Finished, None),
+                (second, BackgroundJobStatus::Finished, None),
+            ]
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn chain_head_updates_include_the_reverted_blocks() {
+    run_test(|_| -> Result<(), ()> {
+        use crate::db_schema::{ethereum_blocks, ethereum_networks};
+
+        const NETWORK_NAME: &str = "reorg_network";
+
+        let genesis: EthereumBlockPointer = (H256::from(0x100), 0u64).into();
+        let block_1: EthereumBlockPointer = (H256::from(0x101), 1u64).into();
+        let block_2a: EthereumBlockPointer = (H256::from(0x102), 2u64).into();
+        let block_2b: EthereumBlockPointer = (H256::from(0x202), 2u64).into();
+        let block_3b: EthereumBlockPointer = (H256::from(0x203), 3u64).into();
+
+        // Use a network of our own so that other tests' blocks don't get in the way
+        let store = DieselStore::new(
+            StoreConfig {
+                postgres_url: postgres_test_url(),
+                network_name: NETWORK_NAME.to_owned(),
+            },
+            &*LOGGER,
+            EthereumNetworkIdentifier {
+                net_version: "graph test suite".to_owned(),
+                genesis_block_hash: genesis.hash,
+            },
+        );
+        let conn = PgConnection::establish(postgres_test_url().as_str()).unwrap();
+        delete(ethereum_blocks::table.filter(ethereum_blocks::network_name.eq(NETWORK_NAME)))
+            .execute(&conn)
+            .unwrap();
+        update(ethereum_networks::table.filter(ethereum_networks::name.eq(NETWORK_NAME)))
+            .set((
+                ethereum_networks::head_block_hash.eq(None::<String>),
+                ethereum_networks::head_block_number.eq(None::<i64>),
+            ))
+            .execute(&conn)
+            .unwrap();
+        let insert_block = |block_ptr: &EthereumBlockPointer, parent: Option<H256>| {
+            insert_into(ethereum_blocks::table)
+                .values((
+                    ethereum_blocks::hash.eq(block_ptr.hash_hex()),
+                    ethereum_blocks::number.eq(block_ptr.number as i64),
+                    ethereum_blocks::parent_hash.eq(parent.map(|hash| format!("{:x}", hash))),
+                    ethereum_blocks::network_name.eq(NETWORK_NAME),
+                    ethereum_blocks::data.eq(graph::serde_json::json!({})),
+                ))
+                .execute(&conn)
+                .unwrap();
+        };
+
+        let mut listener = store.chain_head_updates();
+        let mut updates = listener
+            .take_event_stream()
+            .expect("failed to listen to chain head updates")
+            .wait();
+        listener.start();
+
+        // Advance the chain head to block 2a
+        insert_block(&genesis, None);
+        insert_block(&block_1, Some(genesis.hash));
+        insert_block(&block_2a, Some(block_1.hash));
+        assert!(store.attempt_chain_head_update(10).unwrap().is_empty());
+        let update = updates.next().unwrap().unwrap();
+        assert_eq!(update.head_block_ptr(), block_2a);
+        assert_eq!(update.reverted_to(), None);
+        assert!(update.reverted_blocks.is_empty());
+
+        // Switch to the branch of block 3b, which reverts block 2a
+        insert_block(&block_2b, Some(block_1.hash));
+        insert_block(&block_3b, Some(block_2b.hash));
+        assert!(store.attempt_chain_head_update(10).unwrap().is_empty());
+        let update = updates.next().unwrap().unwrap();
+        assert_eq!(update.head_block_ptr(), block_3b);
+        assert_eq!(update.reverted_to(), Some(block_1));
+        assert_eq!(update.reverted_blocks, vec![block_2a]);
+        Ok(())
+    })
+}
+
+#[test]
+fn block_calls_and_logs_are_cached_per_network() {
+    run_test(|store| -> Result<(), ()> {
+        use crate::db_schema::{ethereum_block_calls, ethereum_block_logs};
+
+        let block_ptr = *TEST_BLOCK_1_PTR;
+        let call = EthereumCall {
+            from: Address::from(1),
+            to: Address::from(2),
+            value: U256::from(3),
+            gas_used: U256::from(4),
+            input: graph::web3::types::Bytes(vec![5]),
+            output: graph::web3::types::Bytes(vec![6]),
+            block_number: block_ptr.number,
+            block_hash: block_ptr.hash,
+            transaction_hash: Some(H256::from(7)),
+        };
+        let log = Log {
+            address: Address::from(2),
+            topics: vec![H256::from(8)],
+            data: graph::web3::types::Bytes(vec![9]),
+            block_hash: Some(block_ptr.hash),
+            block_number: Some(block_ptr.number.into()),
+            transaction_hash: Some(H256::from(7)),
+            transaction_index: Some(0.into()),
+            log_index: Some(0.into()),
+            transaction_log_index: Some(0.into()),
+            log_type: None,
+            removed: None,
+        };
+
+        let conn = PgConnection::establish(postgres_test_url().as_str()).unwrap();
+        delete(ethereum_block_calls::table).execute(&conn).unwrap();
+        delete(ethereum_block_logs::table).execute(&conn).unwrap();
+
+        assert_eq!(store.block_calls(block_ptr.hash).unwrap(), None);
+        assert_eq!(store.block_logs(block_ptr.hash).unwrap(), None);
+        store
+            .upsert_block_calls(block_ptr, &[call.clone()])
+            .unwrap();
+        store.upsert_block_logs(block_ptr, &[log.clone()]).unwrap();
+        assert_eq!(
+            store.block_calls(block_ptr.hash).unwrap(),
+            Some(vec![call.clone()])
+        );
+        assert_eq!(
+            store.block_logs(block_ptr.hash).unwrap(),
+            Some(vec![log.clone()])
+        );
+
+        // Blocks never change, so caching a block again keeps what is cached
+        store.upsert_block_calls(block_ptr, &[]).unwrap();
+        store.upsert_block_logs(block_ptr, &[]).unwrap();
+        assert_eq!(
+            store.block_calls(block_ptr.hash).unwrap(),
+            Some(vec![call.clone()])
+        );
+        assert_eq!(
+            store.block_logs(block_ptr.hash).unwrap(),
+            Some(vec![log.clone()])
+        );
+
+        // Another network has a cache of its own, even for the same block hash
+        let other_store = DieselStore::new(
+            StoreConfig {
+                postgres_url: postgres_test_url(),
+                network_name: "other_network".to_owned(),
+            },
+            &*LOGGER,
+            EthereumNetworkIdentifier {
+                net_version: "graph test suite".to_owned(),
+                genesis_block_hash: GENESIS_PTR.hash,
+            },
+        );
+        assert_eq!(other_store.block_calls(block_ptr.hash).unwrap(), None);
+        assert_eq!(other_store.block_logs(block_ptr.hash).unwrap(), None);
+        other_store.upsert_block_calls(block_ptr, &[]).unwrap();
+        other_store.upsert_block_logs(block_ptr, &[]).unwrap();
+        assert_eq!(
+            other_store.block_calls(block_ptr.hash).unwrap(),
+            Some(vec![])
+        );
+        assert_eq!(
+            other_store.block_logs(block_ptr.hash).unwrap(),
+            Some(vec![])
+        );
+        assert_eq!(store.block_calls(block_ptr.hash).unwrap(), Some(vec![call]));
+        assert_eq!(store.block_logs(block_ptr.hash).unwrap(), Some(vec![log]));
+        Ok(())
+    })
+}
+
+#[test]
+fn immutable_entities_are_settable_within_their_block_and_have_no_history() {
+    run_test(|store| -> Result<(), ()> {
+        let subgraph_id = create_subgraph(
+            &store,
+            "ImmutableTestSubgraph",
+            "type Transfer @entity(immutable: true) { id: ID!, value: Int! }",
+        );
+        let key = |id: &str| EntityKey {
+            subgraph_id: subgraph_id.clone(),
+            entity_type: "Transfer".to_owned(),
+            entity_id: id.to_owned(),
+        };
+        let set = |id: &str, value: i32| EntityOperation::Set {
+            key: key(id),
+            data: Entity::from(vec![("id", Value::from(id)), ("value", Value::from(value))]),
+        };
+        let value = |id: &str| {
+            store
+                .get(key(id))
+                .unwrap()
+                .and_then(|entity| entity.get("value").cloned())
+        };
+
+        // Setting an entity again in the block that inserted it is fine
+        store
+            .transact_block_operations(
+                subgraph_id.clone(),
+                *TEST_BLOCK_0_PTR,
+                *TEST_BLOCK_1_PTR,
+                vec![set("1", 1), set("1", 2)],
+            )
+            .unwrap();
+        assert_eq!(value("1"), Some(Value::from(2)));
+
+        // but not in a later block
+        match store.transact_block_operations(
+            subgraph_id.clone(),
+            *TEST_BLOCK_1_PTR,
+            *TEST_BLOCK_2_PTR,
+            vec![set("1", 3)],
+        ) {
+            Err(StoreError::ImmutableEntity(entity_type, entity_id)) => {
+                assert_eq!(
+                    (entity_type.as_str(), entity_id.as_str()),
+                    ("Transfer", "1")
+                )
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(value("1"), Some(Value::from(2)));
+
+        store
+            .transact_block_operations(
+                subgraph_id.clone(),
+                *TEST_BLOCK_1_PTR,
+                *TEST_BLOCK_2_PTR,
+                vec![set("2", 1)],
+            )
+            .unwrap();
+        assert_eq!(store.entity_history_count(&subgraph_id).unwrap(), 0);
+
+        // Reverting removes the entities the block inserted, without history
+        store
+            .revert_block_operations(subgraph_id.clone(), *TEST_BLOCK_2_PTR, *TEST_BLOCK_1_PTR)
+            .unwrap();
+        assert_eq!(value("1"), Some(Value::from(2)));
+        assert_eq!(value("2"), None);
+        Ok(())
+    })
+}
+
+#[test]
+fn child_filters_match_parents_through_all_kinds_of_links() {
+    run_test(|store| -> Result<(), ()> {
+        let subgraph_id = create_subgraph(
+            &store,
+            "ChildFilterTestSubgraph",
+            "type Parent @entity { id: ID!, child: Child, children: [Child!] }
+             type Child @entity { id: ID!, name: String!, parent: Parent, parents: [Parent!] }",
+        );
+        let set = |entity_type: &str, data: Vec<(&str, Value)>| {
+            let entity = Entity::from(data);
+            EntityOperation::Set {
+                key: EntityKey {
+                    subgraph_id: subgraph_id.clone(),
+                    entity_type: entity_type.to_owned(),
+                    entity_id: entity.id().unwrap(),
+                },
+                data: entity,
+            }
+        };
+        let ids = |ids: &[&str]| Value::List(ids.iter().map(|id| Value::from(*id)).collect());
+
+        store
+            .transact_block_operations(
+                subgraph_id.clone(),
+                *TEST_BLOCK_0_PTR,
+                *TEST_BLOCK_1_PTR,
+                vec![
+                    set(
+                        "Parent",
+                        vec![
+                            ("id", Value::from("p1")),
+                            ("child", Value::from("c1")),
+                            ("children", ids(&["c1", "c2"])),
+                        ],
+                    ),
+                    set(
+                        "Parent",
+                        vec![
+                            ("id", Value::from("p2")),
+                            ("child", Value::from("c2")),
+                            ("children", ids(&["c2"])),
+                        ],
+                    ),
+                    set(
+                        "Parent",
+                        vec![("id", Value::from("p3")), ("children", ids(&[]))],
+                    ),
+                    set(
+                        "Child",
+                        vec![
+                            ("id", Value::from("c1")),
+                            ("name", Value::from("a")),
+                            ("parent", Value::from("p1")),
+                            ("parents", ids(&["p1", "p2"])),
+                        ],
+                    ),
+                    set(
+                        "Child",
+                        vec![
+                            ("id", Value::from("c2")),
+                            ("name", Value::from("b")),
+                            ("parent", Value::from("p2")),
+                            ("parents", ids(&["p2"])),
+                        ],
+                    ),
+                ],
+            )
+            .unwrap();
+
+        // A child with a matching name in another subgraph must not match
+        let other_subgraph_id = create_subgraph(
+            &store,
+            "ChildFilterOtherSubgraph",
+            "type Child @entity { id: ID!, name: String! }",
+        );
+        store
+            .transact_block_operations(
+                other_subgraph_id.clone(),
+                *TEST_BLOCK_0_PTR,
+                *TEST_BLOCK_1_PTR,
+                vec![EntityOperation::Set {
+                    key: EntityKey {
+                        subgraph_id: other_subgraph_id.clone(),
+                        entity_type: "Child".to_owned(),
+                        entity_id: "c2".to_owned(),
+                    },
+                    data: Entity::from(vec![("id", Value::from("c2")), ("name", Value::from("a"))]),
+                }],
+            )
+            .unwrap();
+
+        let find = |link: ChildLink, name: &str| -> Vec<String> {
+            store
+                .find(EntityQuery {
+                    subgraph_id: subgraph_id.clone(),
+                    entity_types: vec!["Parent".to_owned()],
+                    filter: Some(EntityFilter::Child(
+                        link,
+                        vec!["Child".to_owned()],
+                        Box::new(EntityFilter::new_equal("name", name)),
+                    )),
+                    order_by: Some(("id".to_owned(), ValueType::ID)),
+                    order_by_child: None,
+                    order_direction: Some(EntityOrder::Ascending),
+                    range: EntityRange::first(100),
+                    block: None,
+                })
+                .expect("store.find failed to execute query")
+                .into_iter()
+                .map(|entity| entity.id().unwrap())
+                .collect()
+        };
+        let direct = |attr: &str, is_list| ChildLink::Direct(attr.to_owned(), is_list);
+        let derived = |attr: &str, is_list| ChildLink::Derived(attr.to_owned(), is_list);
+
+        assert_eq!(find(direct("child", false), "a"), vec!["p1"]);
+        assert_eq!(find(direct("child", false), "b"), vec!["p2"]);
+        assert_eq!(find(direct("children", true), "a"), vec!["p1"]);
+        assert_eq!(find(direct("children", true), "b"), vec!["p1", "p2"]);
+        assert_eq!(find(derived("parent", false), "a"), vec!["p1"]);
+        assert_eq!(find(derived("parent", false), "b"), vec!["p2"]);
+        assert_eq!(find(derived("parents", true), "a"), vec!["p1", "p2"]);
+        assert_eq!(find(derived("parents", true), "b"), vec!["p2"]);
+        assert!(find(direct("child", false), "c").is_empty());
+        Ok(())
+    })
+}
@@ -0,0 +1,392 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+use graph::components::ethereum::{EthereumContractCall, EthereumContractCallError};
+use graph::ethabi::Token;
+use graph::prelude::*;
+use graph::web3::types::{
+    Address, Block, Bytes, Log, Transaction, TransactionReceipt, H2048, H256, U128, U256,
+};
+use graph_runtime_test::MockEthereumAdapter;
+use tiny_keccak::keccak256;
+
+/// An Ethereum chain that lives in memory and only changes when a test adds
+/// blocks to it. It serves as the Ethereum adapter of a `TestNode`, so that
+/// the node ingests its blocks and indexes subgraphs against them like
+/// against a real Ethereum node.
+///
+/// Each log of a block is emitted by its own transaction. Block hashes are
+/// unique to each chain, so that chains of different tests don't get mixed
+/// up in the database they share, and to each branch of a chain.
+pub struct DevChain {
+    seed: u64,
+    /// How many reorgs the chain went through.
+    reorgs: AtomicUsize,
+    blocks: RwLock<Vec<EthereumBlock>>,
+    calls: MockEthereumAdapter,
+    /// How often the chain was asked whether a block is on the main chain.
+    main_chain_checks: AtomicUsize,
+}
+
+impl DevChain {
+    /// Creates a chain that only has a genesis block.
+    pub fn new() -> Self {
+        let chain = DevChain {
+            seed: unique_seed(),
+            reorgs: AtomicUsize::new(0),
+            blocks: RwLock::new(vec![]),
+            calls: MockEthereumAdapter::default(),
+            main_chain_checks: AtomicUsize::new(0),
+        };
+        chain.add_block(vec![]);
+        chain
+    }
+
+    /// The name of the network of this chain, which no other chain shares.
+    pub fn network_name(&self) -> String {
+        format!("devchain{:x}", self.seed)
+    }
+
+    /// Appends a block with `logs` to the chain and returns a pointer to
+    /// it. The block, transaction and index fields of the logs are filled
+    /// in.
+    pub fn add_block(&self, logs: Vec<Log>) -> EthereumBlockPointer {
+        let mut blocks = self.blocks.write().unwrap();
+        let number = blocks.len() as u64;
+        // Hashing three numbers keeps the hashes of blocks on later
+        // branches apart from those of transactions
+        let hash = match self.reorgs.load(Ordering::SeqCst) as u64 {
+            0 => self.hash(&[number]),
+            reorgs => self.hash(&[number, reorgs, 0]),
+        };
+        let parent_hash = blocks
+            .last()
+            .map_or(H256::zero(), |parent| parent.block.hash.unwrap());
+
+        let mut block = EthereumBlock::default();
+        block.block.hash = Some(hash);
+        block.block.parent_hash = parent_hash;
+        block.block.number = Some(U128::from(number));
+        block.block.timestamp = U256::from(number);
+        for (index, mut log) in logs.into_iter().enumerate() {
+            let transaction_hash = self.hash(&[number, index as u64]);
+            log.block_hash = Some(hash);
+            log.block_number = Some(number.into());
+            log.transaction_hash = Some(transaction_hash);
+            log.transaction_index = Some(index.into());
+            log.log_index = Some(index.into());
+            log.transaction_log_index = Some(U256::zero());
+
+            block.block.transactions.push(Transaction {
+                hash: transaction_hash,
+                nonce: U256::zero(),
+                block_hash: Some(hash),
+                block_number: Some(number.into()),
+                transaction_index: Some(index.into()),
+                from: Address::zero(),
+                to: Some(log.address),
+                value: U256::zero(),
+                gas_price: U256::zero(),
+                gas: U256::zero(),
+                input: Bytes::default(),
+            });
+            block.transaction_receipts.push(TransactionReceipt {
+                transaction_hash,
+                transaction_index: index.into(),
+                block_hash: Some(hash),
+                block_number: Some(number.into()),
+                cumulative_gas_used: U256::zero(),
+                gas_used: U256::zero(),
+                contract_address: None,
+                logs: vec![log],
+                status: Some(1.into()),
+                logs_bloom: H2048::default(),
+            });
+        }
+
+        blocks.push(block);
+        (hash, number).into()
+    }
+
+    /// Appends `count` blocks without logs to the chain and returns a
+    /// pointer to the last one.
+    pub fn add_empty_blocks(&self, count: u64) -> EthereumBlockPointer {
+        let mut head = self.head();
+        for _ in 0..count {
+            head = self.add_block(vec![]);
+        }
+        head
+    }
+
+    /// Removes the last `depth` blocks from the chain, like a reorg does.
+    /// The blocks that are added afterwards are on a new branch of the
+    /// chain and have hashes of their own.
+    pub fn reorg(&self, depth: u64) {
+        let mut blocks = self.blocks.write().unwrap();
+        let len = blocks.len().saturating_sub(depth as usize);
+        assert!(len > 0, "the genesis block can't be reorged");
+        blocks.truncate(len);
+        self.reorgs.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Returns a pointer to the latest block of the chain.
+    pub fn head(&self) -> EthereumBlockPointer {
+        self.blocks.read().unwrap().last().unwrap().into()
+    }
+
+    /// Returns how often the chain was asked whether a block is on the
+    /// main chain.
+    pub fn main_chain_checks(&self) -> usize {
+        self.main_chain_checks.load(Ordering::SeqCst)
+    }
+
+    /// Answers calls of `function` of the contract at `address` with
+    /// `args` with `result`. Calls that weren't mocked fail.
+    pub fn mock_call(
+        &self,
+        address: Address,
+        function: &str,
+        args: Vec<Token>,
+        result: Vec<Token>,
+    ) {
+        self.calls.mock_call(address, function, args, result)
+    }
+
+    /// The identifiers of the network of this chain.
+    pub fn net_identifiers(&self) -> EthereumNetworkIdentifier {
+        EthereumNetworkIdentifier {
+            net_version: self.network_name(),
+            genesis_block_hash: self.hash(&[0]),
+        }
+    }
+
+    fn hash(&self, parts: &[u64]) -> H256 {
+        let mut data = self.seed.to_be_bytes().to_vec();
+        for part in parts {
+            data.extend_from_slice(&part.to_be_bytes());
+        }
+        H256::from(keccak256(&data))
+    }
+
+    fn block_by_number(&self, number: u64) -> Option<EthereumBlock> {
+        self.blocks.read().unwrap().get(number as usize).cloned()
+    }
+
+    fn find_block(&self, hash: H256) -> Option<EthereumBlock> {
+        self.blocks
+            .read()
+            .unwrap()
+            .iter()
+            .find(|block| block.block.hash == Some(hash))
+            .cloned()
+    }
+
+    /// Pointers to the blocks from `from` to `to` that `matches` accepts.
+    fn matching_blocks(
+        &self,
+        from: u64,
+        to: u64,
+        matches: impl Fn(&EthereumBlock) -> bool,
+    ) -> Vec<EthereumBlockPointer> {
+        self.blocks
+            .read()
+            .unwrap()
+            .iter()
+            .skip(from as usize)
+            .take((to + 1).saturating_sub(from) as usize)
+            .filter(|block| matches(block))
+            .map(EthereumBlockPointer::from)
+            .collect()
+    }
+}
+
+/// A different number for every chain, even across test runs.
+fn unique_seed() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    static CHAINS: AtomicUsize = AtomicUsize::new(0);
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    (now.as_secs() << 20)
+        ^ u64::from(now.subsec_micros())
+        ^ (CHAINS.fetch_add(1, Ordering::SeqCst) as u64)
+}
+
+fn has_matching_log(block: &EthereumBlock, log_filter: &EthereumLogFilter) -> bool {
+    block
+        .transaction_receipts
+        .iter()
+        .flat_map(|receipt| receipt.logs.iter())
+        .any(|log| log_filter.matches(log))
+}
+
+impl EthereumAdapter for DevChain {
+    fn net_identifiers(
+        &self,
+        _: &Logger,
+    ) -> Box<Future<Item = EthereumNetworkIdentifier, Error = Error> + Send> {
+        Box::new(future::ok(self.net_identifiers()))
+    }
+
+    fn latest_block(
+        &self,
+        _: &Logger,
+    ) -> Box<Future<Item = Block<Transaction>, Error = EthereumAdapterError> + Send> {
+        let block = self.blocks.read().unwrap().last().unwrap().block.clone();
+        Box::new(future::ok(block))
+    }
+
+    fn block_by_hash(
+        &self,
+        _: &Logger,
+        block_hash: H256,
+    ) -> Box<Future<Item = Option<Block<Transaction>>, Error = Error> + Send> {
+        Box::new(future::ok(
+            self.find_block(block_hash).map(|block| block.block),
+        ))
+    }
+
+    fn block_header_by_hash(
+        &self,
+        _: &Logger,
+        block_hash: H256,
+    ) -> Box<Future<Item = Option<Block<Transaction>>, Error = Error> + Send> {
+        Box::new(future::ok(self.find_block(block_hash).map(|block| Block {
+            transactions: vec![],
+            ..block.block
+        })))
+    }
+
+    fn load_full_block(
+        &self,
+        _: &Logger,
+        block: Block<Transaction>,
+    ) -> Box<Future<Item = EthereumBlock, Error = EthereumAdapterError> + Send> {
+        let hash = block.hash.unwrap();
+        Box::new(future::result(
+            self.find_block(hash)
+                .ok_or_else(|| EthereumAdapterError::BlockUnavailable(hash)),
+        ))
+    }
+
+    fn block_parent_hash_by_block_hash(
+        &self,
+        _: &Logger,
+        block_hash: H256,
+    ) -> Box<Future<Item = Option<H256>, Error = Error> + Send> {
+        Box::new(future::ok(
+            self.find_block(block_hash)
+                .map(|block| block.block.parent_hash),
+        ))
+    }
+
+    fn block_hash_by_block_number(
+        &self,
+        _: &Logger,
+        block_number: u64,
+    ) -> Box<Future<Item = Option<H256>, Error = Error> + Send> {
+        Box::new(future::ok(
+            self.block_by_number(block_number)
+                .and_then(|block| block.block.hash),
+        ))
+    }
+
+    fn is_on_main_chain(
+        &self,
+        _: &Logger,
+        block_ptr: EthereumBlockPointer,
+    ) -> Box<Future<Item = bool, Error = Error> + Send> {
+        self.main_chain_checks.fetch_add(1, Ordering::SeqCst);
+        Box::new(future::ok(
+            self.block_by_number(block_ptr.number)
+                .map_or(false, |block| block.block.hash == Some(block_ptr.hash)),
+        ))
+    }
+
+    fn calls_in_block(
+        &self,
+        _: &Logger,
+        _: u64,
+        _: H256,
+    ) -> Box<Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
+        Box::new(future::ok(vec![]))
+    }
+
+    fn logs_in_block(
+        &self,
+        _: &Logger,
+        _: u64,
+        block_hash: H256,
+    ) -> Box<Future<Item = Vec<Log>, Error = Error> + Send> {
+        let logs = self.find_block(block_hash).map_or(vec![], |block| {
+            block
+                .transaction_receipts
+                .into_iter()
+                .flat_map(|receipt| receipt.logs)
+                .collect()
+        });
+        Box::new(future::ok(logs))
+    }
+
+    fn blocks_with_triggers(
+        &self,
+        _: &Logger,
+        from: u64,
+        to: u64,
+        log_filter: Option<EthereumLogFilter>,
+        _: Option<EthereumCallFilter>,
+        block_filter: Option<EthereumBlockFilter>,
+    ) -> Box<Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        // The chain has no calls, so only logs and block handlers that run
+        // without calls trigger anything
+        Box::new(future::ok(self.matching_blocks(from, to, |block| {
+            let number = block.block.number.unwrap().as_u64();
+            log_filter
+                .as_ref()
+                .map_or(false, |log_filter| has_matching_log(block, log_filter))
+                || block_filter.as_ref().map_or(false, |block_filter| {
+                    block_filter.trigger_every_block
+                        || !block_filter.polling_intervals_for_block(number).is_empty()
+                })
+        })))
+    }
+
+    fn blocks_with_logs(
+        &self,
+        _: &Logger,
+        from: u64,
+        to: u64,
+        log_filter: EthereumLogFilter,
+    ) -> Box<Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        Box::new(future::ok(self.matching_blocks(from, to, |block| {
+            has_matching_log(block, &log_filter)
+        })))
+    }
+
+    fn blocks_with_calls(
+        &self,
+        _: &Logger,
+        _: u64,
+        _: u64,
+        _: EthereumCallFilter,
+    ) -> Box<Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        Box::new(future::ok(vec![]))
+    }
+
+    fn blocks(
+        &self,
+        _: &Logger,
+        from: u64,
+        to: u64,
+    ) -> Box<Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        Box::new(future::ok(self.matching_blocks(from, to, |_| true)))
+    }
+
+    fn contract_call(
+        &self,
+        logger: &Logger,
+        call: EthereumContractCall,
+    ) -> Box<Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send> {
+        self.calls.contract_call(logger, call)
+    }
+}
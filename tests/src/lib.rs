@@ -0,0 +1,34 @@
+//! End-to-end tests of the indexing pipeline.
+//!
+//! A `TestNode` runs the components of Graph Node against a `DevChain`, an
+//! Ethereum chain in memory that tests add blocks and logs to. Tests deploy
+//! subgraphs to the node, wait for them to index the chain and check the
+//! results of queries:
+//!
+//! ```ignore
+//! let chain = Arc::new(DevChain::new());
+//! let mut node = TestNode::start(chain.clone())?;
+//! let id = node.deploy("example", "./subgraphs/example/example.yaml")?;
+//! chain.add_block(vec![log]);
+//! node.wait_for_head(&id)?;
+//! let result = node.query(&id, "{ tokens { id } }")?;
+//! ```
+//!
+//! The node stores its data in the database at
+//! `THEGRAPH_STORE_POSTGRES_DIESEL_URL`.
+
+extern crate graph;
+extern crate graph_core;
+extern crate graph_datasource_ethereum;
+extern crate graph_runtime_test;
+extern crate graph_runtime_wasm;
+extern crate graph_store_postgres;
+extern crate graphql_parser;
+extern crate test_store;
+extern crate tiny_keccak;
+
+mod dev_chain;
+mod test_node;
+
+pub use self::dev_chain::DevChain;
+pub use self::test_node::{TestBlockStream, TestNode};
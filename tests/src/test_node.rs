@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use graph::components::forward;
+use graph::components::link_resolver::StreamValue;
+use graph::data::subgraph::schema::SubgraphDeploymentEntity;
+use graph::prelude::BlockStreamBuilder as BlockStreamBuilderTrait;
+use graph::prelude::*;
+use graph::tokio::runtime::Runtime;
+use graph::util::log;
+use graph_core::{
+    SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
+    SubgraphRegistrar as IpfsSubgraphRegistrar,
+};
+use graph_datasource_ethereum::{BlockIngestor, BlockStreamBuilder};
+use graph_runtime_wasm::RuntimeHostBuilder as WASMRuntimeHostBuilder;
+use graph_store_postgres::{Store as DieselStore, StoreConfig};
+use graphql_parser::parse_query;
+
+use crate::dev_chain::DevChain;
+
+/// How long to wait for a subgraph to index a block before giving up.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the node polls the dev chain for new blocks.
+const POLLING_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Serves the manifests of the subgraphs deployed to a `TestNode`, and the
+/// `file:` links in them, from disk.
+#[derive(Default)]
+struct TestLinkResolver {
+    manifests: RwLock<HashMap<String, PathBuf>>,
+}
+
+impl TestLinkResolver {
+    fn path(&self, link: &Link) -> Result<PathBuf, Error> {
+        let manifests = self.manifests.read().unwrap();
+        if let Some(path) = manifests.get(link.link.trim_start_matches("/ipfs/")) {
+            return Ok(path.clone());
+        }
+        if link.link.starts_with("file:") {
+            let path = link
+                .link
+                .trim_start_matches("file://")
+                .trim_start_matches("file:");
+            return Ok(PathBuf::from(path));
+        }
+        Err(format_err!(
+            "only `file:` links can be resolved in integration tests: {}",
+            link.link
+        ))
+    }
+}
+
+impl LinkResolver for TestLinkResolver {
+    fn cat(&self, link: &Link) -> Box<Future<Item = Vec<u8>, Error = failure::Error> + Send> {
+        Box::new(future::result(self.path(link).and_then(|path| {
+            fs::read(&path).map_err(|e| format_err!("failed to read {}: {}", path.display(), e))
+        })))
+    }
+
+    fn json_stream(
+        &self,
+        link: &Link,
+    ) -> Box<
+        Future<
+                Item = Box<Stream<Item = StreamValue, Error = failure::Error> + Send + 'static>,
+                Error = failure::Error,
+            > + Send
+            + 'static,
+    > {
+        Box::new(future::err(format_err!(
+            "JSON streams are not supported in integration tests: {}",
+            link.link
+        )))
+    }
+}
+
+type TestRegistrar = IpfsSubgraphRegistrar<
+    TestLinkResolver,
+    IpfsSubgraphAssignmentProvider<TestLinkResolver, DieselStore>,
+    DieselStore,
+    DieselStore,
+>;
+
+/// A Graph Node that indexes subgraphs against a `DevChain`, with the same
+/// components as `graph-node` itself: blocks are ingested into the store,
+/// subgraphs are deployed through the subgraph registrar and assignment
+/// provider, and queries are run by the GraphQL runner.
+///
+/// The node needs the database at `THEGRAPH_STORE_POSTGRES_DIESEL_URL`.
+/// Each node gets its own network in the database and its subgraphs get
+/// their own names and deployment IDs, so that test runs don't see each
+/// other's data.
+pub struct TestNode {
+    runtime: Runtime,
+    logger: Logger,
+    store: Arc<DieselStore>,
+    chain: Arc<DevChain>,
+    link_resolver: Arc<TestLinkResolver>,
+    registrar: Arc<TestRegistrar>,
+    graphql_runner: Arc<graph_core::GraphQlRunner<DieselStore>>,
+    node_id: NodeId,
+    deployments: u64,
+}
+
+impl TestNode {
+    /// Starts a node that follows `chain`.
+    pub fn start(chain: Arc<DevChain>) -> Result<Self, Error> {
+        let mut runtime = Runtime::new()?;
+        let logger = match env::var_os("GRAPH_LOG") {
+            Some(_) => log::logger(false),
+            None => Logger::root(slog::Discard, o!()),
+        };
+        // Each node has its own ID, so that it doesn't start the subgraphs
+        // that are still assigned to the nodes of earlier test runs
+        let node_id = NodeId::new(chain.network_name()).unwrap();
+        let link_resolver = Arc::new(TestLinkResolver::default());
+
+        let start_logger = logger.clone();
+        let start_chain = chain.clone();
+        let start_node_id = node_id.clone();
+        let start_link_resolver = link_resolver.clone();
+        let (store, registrar, graphql_runner) =
+            runtime.block_on(future::lazy(move || -> Result<_, Error> {
+                let logger = start_logger;
+                let chain = start_chain;
+                let node_id = start_node_id;
+                let link_resolver = start_link_resolver;
+                let network_name = chain.network_name();
+
+                let store = Arc::new(DieselStore::new(
+                    StoreConfig {
+                        postgres_url: test_store::postgres_test_url(),
+                        network_name: network_name.clone(),
+                    },
+                    &logger,
+                    chain.net_identifiers(),
+                ));
+
+                let mut chain_stores = EthereumNetworks::new();
+                chain_stores.insert(network_name.clone(), store.clone());
+                let mut eth_adapters = EthereumNetworks::new();
+                eth_adapters.insert(network_name.clone(), chain.clone());
+                let mut network_capabilities = EthereumNetworks::new();
+                network_capabilities.insert(
+                    network_name.clone(),
+                    Arc::new(EthereumNetworkCapabilities {
+                        network: network_name.clone(),
+                        providers: vec![EthereumProviderCapabilities {
+                            provider: "devchain".to_owned(),
+                            archive: true,
+                            traces: true,
+                            max_log_range: None,
+                        }],
+                    }),
+                );
+
+                let block_ingestor = BlockIngestor::new(
+                    store.clone(),
+                    chain.clone(),
+                    50,
+                    logger.clone(),
+                    POLLING_INTERVAL,
+                )?;
+                tokio::spawn(block_ingestor.into_polling_stream());
+
+                // Blocks of the dev chain are final, so no reorg threshold
+                let block_stream_builder = BlockStreamBuilder::new(
+                    store.clone(),
+                    chain_stores.clone(),
+                    eth_adapters.clone(),
+                    node_id.clone(),
+                    0,
+                );
+                let runtime_host_builder =
+                    WASMRuntimeHostBuilder::new(eth_adapters, link_resolver.clone(), store.clone());
+                let subgraph_instance_manager = SubgraphInstanceManager::new(
+                    &logger,
+                    store.clone(),
+                    runtime_host_builder,
+                    block_stream_builder,
+                    None,
+                    Arc::new(ResourceQuotas::default()),
+                );
+                let mut subgraph_provider = IpfsSubgraphAssignmentProvider::new(
+                    logger.clone(),
+                    link_resolver.clone(),
+                    store.clone(),
+                );
+                tokio::spawn(forward(&mut subgraph_provider, &subgraph_instance_manager).unwrap());
+
+                let registrar = Arc::new(IpfsSubgraphRegistrar::new(
+                    logger.clone(),
+                    link_resolver,
+                    Arc::new(subgraph_provider),
+                    store.clone(),
+                    chain_stores,
+                    network_capabilities,
+                    node_id,
+                    SubgraphVersionSwitchingMode::Instant,
+                ));
+
+                let graphql_runner =
+                    Arc::new(graph_core::GraphQlRunner::new(&logger, store.clone()));
+                Ok((store, registrar, graphql_runner))
+            }))?;
+        runtime.block_on(registrar.start())?;
+
+        Ok(TestNode {
+            runtime,
+            logger,
+            store,
+            chain,
+            link_resolver,
+            registrar,
+            graphql_runner,
+            node_id,
+            deployments: 0,
+        })
+    }
+
+    /// Deploys the subgraph with the manifest at `manifest` as a new
+    /// subgraph called `name`. The manifest refers to its files with links
+    /// of the form `{ /: "file:./mapping.wasm" }`, where relative paths are
+    /// taken relative to the working directory.
+    pub fn deploy(
+        &mut self,
+        name: &str,
+        manifest: impl AsRef<Path>,
+    ) -> Result<SubgraphDeploymentId, Error> {
+        self.deployments += 1;
+        // Names and IDs are unique to the network of the chain
+        let network_name = self.chain.network_name();
+        let name = self.subgraph_name(name)?;
+        let id =
+            SubgraphDeploymentId::new(format!("Qm{}x{}", network_name, self.deployments)).unwrap();
+        self.link_resolver
+            .manifests
+            .write()
+            .unwrap()
+            .insert(id.to_string(), manifest.as_ref().to_owned());
+
+        let registrar = self.registrar.clone();
+        let node_id = self.node_id.clone();
+        let version_name = name.clone();
+        let version_id = id.clone();
+        self.runtime
+            .block_on(registrar.create_subgraph(name).and_then(move |_| {
+                registrar.create_subgraph_version(version_name, version_id, node_id)
+            }))?;
+        Ok(id)
+    }
+
+    /// The name that the subgraph `name` has on this node. Subgraph names
+    /// are unique to the network of the chain.
+    pub fn subgraph_name(&self, name: &str) -> Result<SubgraphName, Error> {
+        SubgraphName::new(format!("{}/{}", name, self.chain.network_name()))
+            .map_err(|()| format_err!("invalid subgraph name: {}", name))
+    }
+
+    /// Applies `operations` in one batch through the subgraph registrar,
+    /// like the `subgraph_batch` admin request does.
+    pub fn apply_operations(
+        &mut self,
+        operations: Vec<SubgraphRegistrarOperation>,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Result<Vec<Option<CreateSubgraphResult>>, Error> {
+        Ok(self
+            .runtime
+            .block_on(self.registrar.apply_operations(operations, idempotency_key))?)
+    }
+
+    /// The names of all subgraphs in the store.
+    pub fn subgraphs(&mut self) -> Result<Vec<SubgraphName>, Error> {
+        Ok(self.runtime.block_on(self.registrar.list_subgraphs())?)
+    }
+
+    /// The ID of the node.
+    pub fn node_id(&self) -> NodeId {
+        self.node_id.clone()
+    }
+
+    /// Creates the deployment of the subgraph with the manifest at
+    /// `manifest` without assigning it to the node, and runs a block stream
+    /// with `reorg_threshold` for it. The test consumes the stream in place
+    /// of a subgraph instance, which includes writing its blocks.
+    pub fn block_stream(
+        &mut self,
+        manifest: impl AsRef<Path>,
+        reorg_threshold: u64,
+    ) -> Result<TestBlockStream, Error> {
+        self.deployments += 1;
+        let network_name = self.chain.network_name();
+        let id =
+            SubgraphDeploymentId::new(format!("Qm{}x{}", network_name, self.deployments)).unwrap();
+        self.link_resolver
+            .manifests
+            .write()
+            .unwrap()
+            .insert(id.to_string(), manifest.as_ref().to_owned());
+        let link = Link {
+            link: format!("/ipfs/{}", id),
+        };
+        let manifest = self
+            .runtime
+            .block_on(SubgraphManifest::resolve(link, self.link_resolver.clone()))?;
+
+        let genesis_ptr = self.store.genesis_block_ptr()?;
+        self.store.apply_entity_operations(
+            SubgraphDeploymentEntity::new(&manifest, false, false, genesis_ptr, 0)
+                .create_operations(&id),
+            EventSource::None,
+        )?;
+
+        let mut chain_stores = EthereumNetworks::new();
+        chain_stores.insert(network_name.clone(), self.store.clone());
+        let mut eth_adapters = EthereumNetworks::new();
+        eth_adapters.insert(network_name, self.chain.clone());
+        let block_stream_builder = BlockStreamBuilder::new(
+            self.store.clone(),
+            chain_stores,
+            eth_adapters,
+            self.node_id.clone(),
+            reorg_threshold,
+        );
+        let logger = self.logger.clone();
+        let stream = self.runtime.block_on(future::lazy(move || {
+            block_stream_builder.from_subgraph(&manifest, logger)
+        }))?;
+
+        let mut written_blocks_sink = stream.written_blocks_sink();
+        let (sender, blocks) = mpsc::channel();
+        self.runtime.spawn(
+            stream
+                .then(move |result| sender.send(result).map_err(|_| ()))
+                .for_each(|()| Ok(())),
+        );
+        Ok(TestBlockStream {
+            id,
+            blocks,
+            written_blocks: Box::new(move |block| {
+                let _ = written_blocks_sink.try_send(block);
+            }),
+        })
+    }
+
+    /// Waits until the node has ingested the chain up to its current head.
+    pub fn wait_for_chain_head(&self) -> Result<(), Error> {
+        let start = Instant::now();
+        while self.store.chain_head_ptr()? != Some(self.chain.head()) {
+            if start.elapsed() > SYNC_TIMEOUT {
+                bail!("the chain head was not ingested in time");
+            }
+            thread::sleep(POLLING_INTERVAL);
+        }
+        Ok(())
+    }
+
+    /// The store of the node.
+    pub fn store(&self) -> Arc<DieselStore> {
+        self.store.clone()
+    }
+
+    /// Waits until the subgraph deployment `id` has indexed the chain up to
+    /// `block`. Fails if the deployment fails or doesn't get there in time.
+    pub fn wait_for_block(
+        &self,
+        id: &SubgraphDeploymentId,
+        block: EthereumBlockPointer,
+    ) -> Result<(), Error> {
+        let start = Instant::now();
+        loop {
+            if let Some(deployment) = self.store.get(SubgraphDeploymentEntity::key(id.clone()))? {
+                if deployment.get("failed") == Some(&Value::Bool(true)) {
+                    bail!("subgraph deployment `{}` failed", id);
+                }
+                let latest_block = deployment
+                    .get("latestEthereumBlockNumber")
+                    .and_then(|number| number.clone().as_bigint())
+                    .map(|number| number.to_u64());
+                if latest_block.map_or(false, |number| number >= block.number) {
+                    return Ok(());
+                }
+            }
+            if start.elapsed() > SYNC_TIMEOUT {
+                bail!(
+                    "subgraph deployment `{}` did not reach block #{} in time",
+                    id,
+                    block.number
+                );
+            }
+            thread::sleep(POLLING_INTERVAL);
+        }
+    }
+
+    /// The message of the error that made the subgraph deployment `id` fail,
+    /// if it failed.
+    pub fn fatal_error(&self, id: &SubgraphDeploymentId) -> Result<Option<String>, Error> {
+        Ok(self
+            .store
+            .get(SubgraphDeploymentEntity::key(id.clone()))?
+            .and_then(|deployment| deployment.get("lastErrorMessage").cloned())
+            .and_then(|message| message.as_string()))
+    }
+
+    /// Waits until the subgraph deployment `id` has indexed the chain up to
+    /// its current head.
+    pub fn wait_for_head(&self, id: &SubgraphDeploymentId) -> Result<(), Error> {
+        self.wait_for_block(id, self.chain.head())
+    }
+
+    /// Runs the GraphQL query `query` against the subgraph deployment `id`.
+    pub fn query(&mut self, id: &SubgraphDeploymentId, query: &str) -> Result<QueryResult, Error> {
+        let query = Query {
+            schema: self.store.subgraph_schema(id)?,
+            document: parse_query(query).map_err(|e| format_err!("invalid query: {}", e))?,
+            variables: None,
+            block: None,
+        };
+        let graphql_runner = self.graphql_runner.clone();
+        self.runtime
+            .block_on(future::lazy(move || graphql_runner.run_query(query)))
+            .map_err(|e| format_err!("query failed: {}", e))
+    }
+}
+
+/// A block stream that a `TestNode` runs for a deployment it doesn't index
+/// itself.
+pub struct TestBlockStream {
+    /// The deployment that the stream is for.
+    pub id: SubgraphDeploymentId,
+    blocks: mpsc::Receiver<Result<EthereumBlockWithTriggers, Error>>,
+    written_blocks: Box<FnMut(EthereumBlockPointer) + Send>,
+}
+
+impl TestBlockStream {
+    /// Waits for the next block of the stream. Fails if the stream fails
+    /// or doesn't yield a block within `timeout`.
+    pub fn next_block(&self, timeout: Duration) -> Result<EthereumBlockWithTriggers, Error> {
+        self.blocks
+            .recv_timeout(timeout)
+            .map_err(|_| format_err!("the block stream did not yield a block in time"))?
+    }
+
+    /// Tells the stream that the test has written `block` to the store, as
+    /// a subgraph instance does.
+    pub fn block_written(&mut self, block: EthereumBlockPointer) {
+        (self.written_blocks)(block)
+    }
+}
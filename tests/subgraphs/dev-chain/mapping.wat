@@ -0,0 +1,43 @@
+;; Mapping of the dev-chain test subgraph.
+;;
+;; `handleExampleEvent` saves `ExampleEntity` "1" with `exampleAttribute`
+;; "hello"; `handleFailingEvent` traps. The arguments of `store.set` are laid
+;; out in memory the way AssemblyScript lays out strings and typed maps.
+(module
+  (import "index" "store.set" (func $store.set (param i32 i32 i32)))
+  (memory (export "memory") 4)
+  (global $heap (mut i32) (i32.const 1024))
+
+  ;; Bump allocator for the objects the node passes to the handlers
+  (func (export "memory.allocate") (param $size i32) (result i32)
+    (global.get $heap)
+    (global.set $heap
+      (i32.and
+        (i32.add (i32.add (global.get $heap) (local.get $size)) (i32.const 7))
+        (i32.const -8))))
+
+  (func (export "handleExampleEvent") (param $event i32)
+    (call $store.set (i32.const 256) (i32.const 320) (i32.const 464)))
+
+  (func (export "handleFailingEvent") (param $event i32)
+    (unreachable))
+
+  ;; "ExampleEntity"
+  (data (i32.const 256) "\0d\00\00\00E\00x\00a\00m\00p\00l\00e\00E\00n\00t\00i\00t\00y\00")
+  ;; "1"
+  (data (i32.const 320) "\01\00\00\001\00")
+  ;; "exampleAttribute"
+  (data (i32.const 336)
+    "\10\00\00\00e\00x\00a\00m\00p\00l\00e\00A\00t\00t\00r\00i\00b\00u\00t\00e\00")
+  ;; "hello"
+  (data (i32.const 384) "\05\00\00\00h\00e\00l\00l\00o\00")
+  ;; Store value: kind String, padding, pointer to "hello"
+  (data (i32.const 400) "\00\00\00\00\00\00\00\00\80\01\00\00\00\00\00\00")
+  ;; Typed map entry: "exampleAttribute" -> store value
+  (data (i32.const 416) "\50\01\00\00\90\01\00\00")
+  ;; Array buffer with the pointer to the entry
+  (data (i32.const 432) "\04\00\00\00\00\00\00\00\a0\01\00\00")
+  ;; Array of entries
+  (data (i32.const 448) "\b0\01\00\00\01\00\00\00")
+  ;; Entity
+  (data (i32.const 464) "\c0\01\00\00"))
@@ -0,0 +1,139 @@
+extern crate graph;
+extern crate graph_tests;
+
+use graph::prelude::*;
+use graph::util::ethereum::string_to_h256;
+use graph::web3::types::{Address, Bytes, Log};
+use graph_tests::{DevChain, TestNode};
+use std::str::FromStr;
+use std::time::Duration;
+
+const MANIFEST: &str = "./subgraphs/dev-chain/dev-chain.yaml";
+
+/// How long to wait for a block that the stream should yield.
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for a block that the stream must not yield.
+const NO_BLOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn example_event(value: &str) -> Log {
+    Log {
+        address: Address::from_str("22843e74c59580b3eaf6c233fa67d8b7c561a835").unwrap(),
+        topics: vec![
+            string_to_h256("ExampleEvent(string)"),
+            string_to_h256(value),
+        ],
+        data: Bytes(vec![]),
+        block_hash: None,
+        block_number: None,
+        transaction_hash: None,
+        transaction_index: None,
+        log_index: None,
+        transaction_log_index: None,
+        log_type: None,
+        removed: None,
+    }
+}
+
+#[test]
+fn blocks_with_queued_writes_are_reverted_once_they_are_written() {
+    let chain = Arc::new(DevChain::new());
+    let genesis = chain.head();
+    let block1 = chain.add_block(vec![example_event("a")]);
+    chain.add_block(vec![example_event("b")]);
+    let mut node = TestNode::start(chain.clone()).unwrap();
+    node.wait_for_chain_head().unwrap();
+    let mut stream = node.block_stream(MANIFEST, 10).unwrap();
+    let store = node.store();
+
+    // The stream waits for the block it yielded to be written before it
+    // looks for more blocks
+    let block = stream.next_block(BLOCK_TIMEOUT).unwrap();
+    assert_eq!(EthereumBlockPointer::from(&block.ethereum_block), block1);
+    assert!(stream.next_block(NO_BLOCK_TIMEOUT).is_err());
+
+    // The block is replaced while its write is still queued
+    chain.reorg(2);
+    let new_block1 = chain.add_block(vec![example_event("c")]);
+    chain.add_block(vec![example_event("d")]);
+    chain.add_block(vec![example_event("e")]);
+    node.wait_for_chain_head().unwrap();
+    assert!(stream.next_block(NO_BLOCK_TIMEOUT).is_err());
+
+    let key = EntityKey {
+        subgraph_id: stream.id.clone(),
+        entity_type: "ExampleEntity".to_owned(),
+        entity_id: "1".to_owned(),
+    };
+    let entity = Entity::from(vec![
+        ("id", Value::from("1")),
+        ("exampleAttribute", Value::from("a")),
+    ]);
+    store
+        .transact_block_operations(
+            stream.id.clone(),
+            genesis,
+            block1,
+            vec![EntityOperation::Set {
+                key: key.clone(),
+                data: entity,
+            }],
+        )
+        .unwrap();
+    stream.block_written(block1);
+
+    // Once written, the block is reverted before the stream moves on to
+    // the new branch of the chain
+    let block = stream.next_block(BLOCK_TIMEOUT).unwrap();
+    assert_eq!(
+        EthereumBlockPointer::from(&block.ethereum_block),
+        new_block1
+    );
+    assert_eq!(store.block_ptr(stream.id.clone()).unwrap(), genesis);
+    assert_eq!(store.get(key).unwrap(), None);
+}
+
+#[test]
+fn scanned_blocks_are_not_checked_against_the_main_chain() {
+    let chain = Arc::new(DevChain::new());
+    let genesis = chain.head();
+    let block1 = chain.add_block(vec![example_event("a")]);
+    chain.add_empty_blocks(20);
+    let mut node = TestNode::start(chain.clone()).unwrap();
+    node.wait_for_chain_head().unwrap();
+    let mut stream = node.block_stream(MANIFEST, 5).unwrap();
+    let store = node.store();
+
+    let block = stream.next_block(BLOCK_TIMEOUT).unwrap();
+    assert_eq!(EthereumBlockPointer::from(&block.ethereum_block), block1);
+    let checks = chain.main_chain_checks();
+    store
+        .set_block_ptr_with_no_changes(stream.id.clone(), genesis, block1)
+        .unwrap();
+    stream.block_written(block1);
+
+    // Block #1 was found by scanning beyond the reorg threshold, so the
+    // stream scans on from it without asking the Ethereum node, skips the
+    // blocks without triggers and processes the blocks within the
+    // threshold one at a time
+    let block = stream.next_block(BLOCK_TIMEOUT).unwrap();
+    let block17 = EthereumBlockPointer::from(&block.ethereum_block);
+    assert_eq!(block17.number, 17);
+    assert_eq!(chain.main_chain_checks(), checks);
+
+    // Block #17 was processed within the threshold, so once it falls
+    // behind the threshold it is checked against the main chain
+    chain.add_empty_blocks(10);
+    node.wait_for_chain_head().unwrap();
+    store
+        .set_block_ptr_with_no_changes(
+            stream.id.clone(),
+            EthereumBlockPointer::to_parent(&block.ethereum_block),
+            block17,
+        )
+        .unwrap();
+    stream.block_written(block17);
+    let block = stream.next_block(BLOCK_TIMEOUT).unwrap();
+    assert_eq!(block.ethereum_block.block.number.unwrap().as_u64(), 27);
+    assert_eq!(chain.main_chain_checks(), checks + 1);
+}
@@ -0,0 +1,86 @@
+extern crate graph;
+extern crate graph_tests;
+extern crate graphql_parser;
+
+use graph::prelude::*;
+use graph::util::ethereum::string_to_h256;
+use graph::web3::types::{Address, Bytes, Log};
+use graph_tests::{DevChain, TestNode};
+use graphql_parser::query as q;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+const MANIFEST: &str = "./subgraphs/dev-chain/dev-chain.yaml";
+
+fn event(signature: &str, value: &str) -> Log {
+    Log {
+        address: Address::from_str("22843e74c59580b3eaf6c233fa67d8b7c561a835").unwrap(),
+        topics: vec![string_to_h256(signature), string_to_h256(value)],
+        data: Bytes(vec![]),
+        block_hash: None,
+        block_number: None,
+        transaction_hash: None,
+        transaction_index: None,
+        log_index: None,
+        transaction_log_index: None,
+        log_type: None,
+        removed: None,
+    }
+}
+
+#[test]
+fn subgraph_indexes_dev_chain() {
+    let chain = Arc::new(DevChain::new());
+    chain.add_empty_blocks(5);
+    let mut node = TestNode::start(chain.clone()).unwrap();
+
+    let id = node.deploy("dev-chain", MANIFEST).unwrap();
+    node.wait_for_head(&id).unwrap();
+
+    let query = "{ exampleEntities { id exampleAttribute } }";
+    let result = node.query(&id, query).unwrap();
+    assert!(result.errors.is_none());
+    let mut expected = BTreeMap::new();
+    expected.insert("exampleEntities".to_owned(), q::Value::List(vec![]));
+    assert_eq!(result.data, Some(q::Value::Object(expected)));
+
+    // Blocks added after the deployment are indexed, too; the mapping saves
+    // an entity for the event
+    chain.add_empty_blocks(3);
+    chain.add_block(vec![event("ExampleEvent(string)", "hello")]);
+    node.wait_for_head(&id).unwrap();
+
+    let result = node.query(&id, query).unwrap();
+    assert!(result.errors.is_none());
+    let mut entity = BTreeMap::new();
+    entity.insert("id".to_owned(), q::Value::String("1".to_owned()));
+    entity.insert(
+        "exampleAttribute".to_owned(),
+        q::Value::String("hello".to_owned()),
+    );
+    let mut expected = BTreeMap::new();
+    expected.insert(
+        "exampleEntities".to_owned(),
+        q::Value::List(vec![q::Value::Object(entity)]),
+    );
+    assert_eq!(result.data, Some(q::Value::Object(expected)));
+}
+
+#[test]
+fn failing_handlers_fail_the_deployment() {
+    let chain = Arc::new(DevChain::new());
+    let mut node = TestNode::start(chain.clone()).unwrap();
+    let id = node.deploy("dev-chain", MANIFEST).unwrap();
+
+    // The handler for the event traps
+    chain.add_block(vec![event("FailingEvent(string)", "hello")]);
+    let error = node.wait_for_head(&id).unwrap_err();
+    assert!(error.to_string().contains("failed"));
+
+    let message = node.fatal_error(&id).unwrap().unwrap();
+    assert!(
+        message.contains("handleFailingEvent"),
+        "unexpected error: {}",
+        message
+    );
+}
@@ -0,0 +1,104 @@
+extern crate graph;
+extern crate graph_tests;
+
+use graph::prelude::*;
+use graph_tests::{DevChain, TestNode};
+
+const MANIFEST: &str = "./subgraphs/dev-chain/dev-chain.yaml";
+
+#[test]
+fn batches_are_applied_atomically() {
+    let chain = Arc::new(DevChain::new());
+    let mut node = TestNode::start(chain).unwrap();
+    let id = node.deploy("deployed", MANIFEST).unwrap();
+
+    let batch = node.subgraph_name("batch").unwrap();
+    let node_id = node.node_id();
+    let operations = |last_operation| {
+        vec![
+            SubgraphRegistrarOperation::Create {
+                name: batch.clone(),
+            },
+            SubgraphRegistrarOperation::Deploy {
+                name: batch.clone(),
+                hash: id.clone(),
+                node_id: node_id.clone(),
+            },
+            last_operation,
+        ]
+    };
+
+    // Removing a subgraph that doesn't exist fails the batch, and the
+    // operations before it don't take effect either
+    let missing = node.subgraph_name("missing").unwrap();
+    let error = node
+        .apply_operations(
+            operations(SubgraphRegistrarOperation::Remove { name: missing }),
+            None,
+        )
+        .err()
+        .expect("the batch should fail");
+    assert!(error.to_string().contains("subgraph name not found"));
+    assert!(!node.subgraphs().unwrap().contains(&batch));
+
+    // Without the failing operation, the batch is applied
+    let other = node.subgraph_name("other").unwrap();
+    let results = node
+        .apply_operations(
+            operations(SubgraphRegistrarOperation::Create {
+                name: other.clone(),
+            }),
+            None,
+        )
+        .unwrap();
+    assert_eq!(results.len(), 3);
+    let subgraphs = node.subgraphs().unwrap();
+    assert!(subgraphs.contains(&batch));
+    assert!(subgraphs.contains(&other));
+}
+
+#[test]
+fn batches_with_an_idempotency_key_are_applied_once() {
+    let chain = Arc::new(DevChain::new());
+    let mut node = TestNode::start(chain).unwrap();
+
+    let name = node.subgraph_name("idempotent").unwrap();
+    let create = vec![SubgraphRegistrarOperation::Create { name: name.clone() }];
+    let key = |request: &str| {
+        Some(IdempotencyKey {
+            key: "key".to_owned(),
+            request: request.to_owned(),
+        })
+    };
+
+    // Creating the subgraph again would fail, but repeating the key
+    // returns the result of the first batch instead
+    let results = node
+        .apply_operations(create.clone(), key("create"))
+        .unwrap();
+    let replayed = node
+        .apply_operations(create.clone(), key("create"))
+        .unwrap();
+    assert_eq!(
+        results[0].as_ref().unwrap().id,
+        replayed[0].as_ref().unwrap().id
+    );
+
+    // The key can't be used for a different request, even one that
+    // would succeed
+    let other = node.subgraph_name("other").unwrap();
+    let error = node
+        .apply_operations(
+            vec![SubgraphRegistrarOperation::Create {
+                name: other.clone(),
+            }],
+            key("create other"),
+        )
+        .err()
+        .expect("the batch should fail");
+    assert!(error.to_string().contains("idempotency key was used"));
+    assert!(!node.subgraphs().unwrap().contains(&other));
+
+    // Without the key, the batch is applied again, and fails
+    assert!(node.apply_operations(create, None).is_err());
+}